@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    //the grpc messages and service are generated from the proto file
+    #[cfg(feature = "grpc")]
+    tonic_prost_build::compile_protos("proto/share_screen.proto")?;
+
+    Ok(())
+}
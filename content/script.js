@@ -5,14 +5,48 @@
 // ===========================
 const CONFIG = {
   MAX_BUFFER: 10 * 1024 * 1024, // 10MB
+  PROTOCOL_VERSION: 1,
+  HEADER_LEN: 20,
+  PACKET_TYPES: { frame: 0, dimensions: 1, audio: 2, heartbeat: 3 },
+  PACKET_FLAGS: { encrypted: 1 },
   IDLE_TIMEOUT: 2500,
   FPS_UPDATE_INTERVAL: 1000,
+  AUDIO_LATENCY: 0.1, // seconds of audio buffered before playback
+  MAX_QUEUED_FRAMES: 30, // frames held back for lip-sync
+  MAX_SYNC_WAIT: 1000000, // us a frame may wait for the audio clock
   ENDPOINTS: {
     dimensions: "/stream/dimensions",
-    stream: "/stream"
-  }
+    stream: "/stream?version=1",
+    audio: "/stream/audio?version=1",
+    events: "/events",
+    ping: "/api/ping",
+    chat: "/ws/chat"
+  },
+  CHAT_RECONNECT: 3000,
+  PING_INTERVAL: 5000
 };
 
+// settings injected by the server into the page (dimensions, enabled features)
+const SETTINGS = window.SHARE_SCREEN || null;
+
+// token of the page url (/?token=...), passed on to the stream routes
+const TOKEN = new URLSearchParams(location.search).get("token");
+
+// pacing and codec of the page url (/?preset=low-latency&max_kbps=2000&codec=qoi), passed on to the stream
+const PAGE_PARAMS = new URLSearchParams(location.search);
+const STREAM_PARAMS = ["preset", "max_kbps", "codec"]
+  .filter((name) => PAGE_PARAMS.has(name))
+  .map((name) => "&" + name + "=" + encodeURIComponent(PAGE_PARAMS.get(name)))
+  .join("");
+
+// lossless frames of ?codec=qoi or png, jpeg otherwise
+const CODEC = (PAGE_PARAMS.get("codec") || "").toLowerCase();
+
+function withToken(url) {
+  if (!TOKEN) return url;
+  return url + (url.includes("?") ? "&" : "?") + "token=" + encodeURIComponent(TOKEN);
+}
+
 // ===========================
 // DOM Cache
 // ===========================
@@ -26,6 +60,10 @@ const $ = {
   placeholder: document.getElementById("placeholder"),
   statusDot: document.getElementById("statusDot"),
   statusText: document.getElementById("statusText"),
+  chat: document.getElementById("chat"),
+  chatMessages: document.getElementById("chatMessages"),
+  chatForm: document.getElementById("chatForm"),
+  chatInput: document.getElementById("chatInput"),
 };
 
 // ===========================
@@ -34,16 +72,28 @@ const $ = {
 const state = {
   width: 0,
   height: 0,
+  startedOnce: false,
   isStreaming: false,
   abortController: null,
   buffer: new Uint8Array(CONFIG.MAX_BUFFER),
   writeOffset: 0,
   readOffset: 0,
-  pendingFrame: null,
+  frameQueue: [],
   frameCount: 0,
+  drift: null,
+  lastSequence: null,
+  dropped: 0,
   fpsInterval: null,
+  pingInterval: null,
+  clockOffset: null, // stream clock minus the local clock, in us
+  latency: null, // capture to display of the last frame, in ms
   rafId: null,
   idleTimer: null,
+  audioContext: null,
+  audioDecoder: null,
+  audioPlayhead: 0,
+  audioClock: null,
+  events: null,
 };
 
 // Canvas context with optimizations
@@ -58,6 +108,7 @@ const ctx = $.canvas.getContext("2d", {
 // ===========================
 const isIOS = /iPad|iPhone|iPod/.test(navigator.userAgent);
 const supportsOffscreenCanvas = typeof OffscreenCanvas !== 'undefined';
+const supportsAudioDecoder = typeof AudioDecoder !== 'undefined';
 
 // ===========================
 // Event Delegation
@@ -91,8 +142,12 @@ async function startStream() {
     
     // Start concurrent loops
     state.fpsInterval = setInterval(updateFPS, CONFIG.FPS_UPDATE_INTERVAL);
+    state.pingInterval = setInterval(ping, CONFIG.PING_INTERVAL);
+    ping();
     renderLoop();
     readStream(state.abortController.signal);
+    readAudio(state.abortController.signal);
+    listenEvents();
   } catch (err) {
     console.error("Failed to start stream:", err);
     cleanup();
@@ -107,7 +162,10 @@ function stopStream() {
 function cleanup() {
   state.isStreaming = false;
   state.abortController = null;
-  state.pendingFrame = null;
+  state.frameQueue = [];
+  state.drift = null;
+  state.lastSequence = null;
+  state.dropped = 0;
   state.writeOffset = 0;
   state.readOffset = 0;
   
@@ -120,6 +178,15 @@ function cleanup() {
     clearInterval(state.fpsInterval);
     state.fpsInterval = null;
   }
+
+  clearInterval(state.pingInterval);
+  state.pingInterval = null;
+  state.latency = null;
+  
+  stopAudio();
+  
+  state.events?.close();
+  state.events = null;
   
   updateUI(false);
 }
@@ -128,10 +195,21 @@ function cleanup() {
 // Dimension Fetching
 // ===========================
 async function fetchDimensions() {
-  const res = await fetch(CONFIG.ENDPOINTS.dimensions);
+  // the page already carries them on the first start, later starts pick up resolution changes
+  if (SETTINGS && !state.startedOnce) {
+    state.startedOnce = true;
+    applyDimensions(SETTINGS.width, SETTINGS.height);
+    return;
+  }
+
+  const res = await fetch(withToken(CONFIG.ENDPOINTS.dimensions));
   if (!res.ok) throw new Error(`HTTP ${res.status}`);
   
   const { width, height } = await res.json();
+  applyDimensions(width, height);
+}
+
+function applyDimensions(width, height) {
   state.width = width;
   state.height = height;
   
@@ -156,7 +234,7 @@ function initializeStream() {
 // ===========================
 async function readStream(signal) {
   try {
-    const res = await fetch(CONFIG.ENDPOINTS.stream, { 
+    const res = await fetch(withToken(CONFIG.ENDPOINTS.stream + STREAM_PARAMS), { 
       method: "POST", 
       signal 
     });
@@ -188,7 +266,7 @@ function appendToBuffer(chunk) {
   const needed = state.writeOffset + chunk.length;
   
   // Compact buffer if overflow imminent
-  if (needed > CONFIG.MAX_BUFFER) {
+  if (needed > state.buffer.length) {
     const validSize = state.writeOffset - state.readOffset;
     
     if (validSize > 0) {
@@ -201,54 +279,285 @@ function appendToBuffer(chunk) {
       state.readOffset = 0;
     }
   }
+
+  // Lossless frames of a large screen can outgrow the buffer on their own
+  if (state.writeOffset + chunk.length > state.buffer.length) {
+    const grown = new Uint8Array(Math.max(state.buffer.length * 2, state.writeOffset + chunk.length));
+    grown.set(state.buffer.subarray(0, state.writeOffset));
+    state.buffer = grown;
+  }
   
   state.buffer.set(chunk, state.writeOffset);
   state.writeOffset += chunk.length;
 }
 
+// [1 version][1 type][2 flags][4 sequence][8 timestamp us][4 length], little endian
+function readHeader(buf, offset) {
+  const view = new DataView(buf.buffer, buf.byteOffset + offset, CONFIG.HEADER_LEN);
+
+  return {
+    version: view.getUint8(0),
+    type: view.getUint8(1),
+    flags: view.getUint16(2, true),
+    sequence: view.getUint32(4, true),
+    timestamp: Number(view.getBigUint64(8, true)),
+    length: view.getUint32(16, true),
+  };
+}
+
 function parseFrames() {
   const buf = state.buffer;
   
-  while (state.writeOffset - state.readOffset >= 4) {
-    // Fast 32-bit little-endian read
-    const len = buf[state.readOffset] |
-                (buf[state.readOffset + 1] << 8) |
-                (buf[state.readOffset + 2] << 16) |
-                (buf[state.readOffset + 3] << 24);
+  while (state.writeOffset - state.readOffset >= CONFIG.HEADER_LEN) {
+    const header = readHeader(buf, state.readOffset);
+
+    if (header.version !== CONFIG.PROTOCOL_VERSION) {
+      throw new Error(`Unsupported protocol version ${header.version}`);
+    }
+
+    if (header.flags & CONFIG.PACKET_FLAGS.encrypted) {
+      throw new Error("The stream is encrypted, it can only be played by a client with the key");
+    }
     
-    const totalSize = 4 + len;
+    const totalSize = CONFIG.HEADER_LEN + header.length;
     
     if (state.writeOffset - state.readOffset < totalSize) {
-      break; // Incomplete frame
+      break; // Incomplete packet
+    }
+
+    // Gaps in the sequence are packets the server dropped for this client, heartbeats are not sequenced
+    if (header.type !== CONFIG.PACKET_TYPES.heartbeat) {
+      if (state.lastSequence !== null) {
+        state.dropped += (header.sequence - state.lastSequence - 1) >>> 0;
+      }
+      state.lastSequence = header.sequence;
     }
     
-    const start = state.readOffset + 4;
-    const end = start + len;
-    
-    // Extract frame (copy for safety)
-    state.pendingFrame = buf.slice(start, end);
+    const start = state.readOffset + CONFIG.HEADER_LEN;
+    const end = start + header.length;
+
+    if (header.type === CONFIG.PACKET_TYPES.dimensions) {
+      // In-band dimension update: [width][height]
+      const view = new DataView(buf.buffer, buf.byteOffset + start, 8);
+      applyDimensions(view.getUint32(0, true), view.getUint32(4, true));
+    } else if (header.type === CONFIG.PACKET_TYPES.frame) {
+      // Extract frame (copy for safety)
+      state.frameQueue.push({ timestamp: header.timestamp, data: buf.slice(start, end) });
+      if (state.frameQueue.length > CONFIG.MAX_QUEUED_FRAMES) {
+        state.frameQueue.shift();
+      }
+    }
+
     state.readOffset += totalSize;
   }
 }
 
+// ===========================
+// Audio Engine
+// ===========================
+async function readAudio(signal) {
+  if (!supportsAudioDecoder) return;
+  if (SETTINGS && !SETTINGS.features.audio) return;
+
+  try {
+    const res = await fetch(withToken(CONFIG.ENDPOINTS.audio), { signal });
+    if (!res.ok) return;
+
+    state.audioContext = new AudioContext({ sampleRate: 48000 });
+    state.audioPlayhead = 0;
+    state.audioDecoder = new AudioDecoder({
+      output: playAudio,
+      error: (err) => console.error("Audio decode error:", err),
+    });
+    state.audioDecoder.configure({ codec: "opus", sampleRate: 48000, numberOfChannels: 2 });
+
+    const reader = res.body.getReader();
+    let pending = new Uint8Array(0);
+
+    while (true) {
+      const { value, done } = await reader.read();
+      if (done) break;
+
+      const merged = new Uint8Array(pending.length + value.length);
+      merged.set(pending);
+      merged.set(value, pending.length);
+      pending = merged;
+
+      let offset = 0;
+      while (pending.length - offset >= CONFIG.HEADER_LEN) {
+        const header = readHeader(pending, offset);
+        const start = offset + CONFIG.HEADER_LEN;
+        if (pending.length < start + header.length) break;
+
+        if (header.type === CONFIG.PACKET_TYPES.audio && !(header.flags & CONFIG.PACKET_FLAGS.encrypted)) {
+          state.audioDecoder.decode(new EncodedAudioChunk({
+            type: "key",
+            timestamp: header.timestamp,
+            data: pending.subarray(start, start + header.length),
+          }));
+        }
+
+        offset = start + header.length;
+      }
+
+      pending = pending.slice(offset);
+    }
+  } catch (err) {
+    if (err.name !== "AbortError") {
+      console.error("Audio stream error:", err);
+    }
+  }
+}
+
+function playAudio(data) {
+  const ctx = state.audioContext;
+  if (!ctx) {
+    data.close();
+    return;
+  }
+
+  const buffer = ctx.createBuffer(data.numberOfChannels, data.numberOfFrames, data.sampleRate);
+  for (let c = 0; c < data.numberOfChannels; c++) {
+    data.copyTo(buffer.getChannelData(c), { planeIndex: c, format: "f32-planar" });
+  }
+  data.close();
+
+  // Reset the playhead when playback fell behind
+  if (state.audioPlayhead < ctx.currentTime) {
+    state.audioPlayhead = ctx.currentTime + CONFIG.AUDIO_LATENCY;
+  }
+
+  const source = ctx.createBufferSource();
+  source.buffer = buffer;
+  source.connect(ctx.destination);
+  source.start(state.audioPlayhead);
+
+  // Maps the stream clock to the audio context clock for lip-sync
+  state.audioClock = { timestamp: data.timestamp, time: state.audioPlayhead };
+  state.audioPlayhead += buffer.duration;
+}
+
+// Stream clock timestamp (us) of the audio being heard, null without audio
+function audioNow() {
+  const ctx = state.audioContext;
+  if (!ctx || !state.audioClock) return null;
+
+  return state.audioClock.timestamp + (ctx.currentTime - state.audioClock.time) * 1e6;
+}
+
+function stopAudio() {
+  if (state.audioDecoder && state.audioDecoder.state !== "closed") {
+    state.audioDecoder.close();
+  }
+  state.audioDecoder = null;
+
+  state.audioContext?.close();
+  state.audioContext = null;
+  state.audioClock = null;
+}
+
+// ===========================
+// Latency
+// ===========================
+// Aligns the local clock with the stream clock of the frame timestamps, and reports the last latency measured
+async function ping() {
+  const url = state.latency === null
+    ? CONFIG.ENDPOINTS.ping
+    : `${CONFIG.ENDPOINTS.ping}?latency_ms=${state.latency.toFixed(1)}`;
+
+  try {
+    const sent = performance.now() * 1000;
+    const res = await fetch(withToken(url), { cache: "no-store" });
+    if (!res.ok) return;
+
+    const { server_us } = await res.json();
+    const received = performance.now() * 1000;
+
+    // the server answered halfway through the round trip
+    state.clockOffset = server_us - (sent + received) / 2;
+  } catch (err) {
+    console.warn("Ping failed:", err);
+  }
+}
+
+// ===========================
+// Server Events
+// ===========================
+function listenEvents() {
+  const events = new EventSource(withToken(CONFIG.ENDPOINTS.events));
+  const on = (name, handler) =>
+    events.addEventListener(name, (e) => handler(JSON.parse(e.data)));
+
+  on("dimensions_changed", ({ width, height }) => applyDimensions(width, height));
+  on("paused", () => { $.statusText.textContent = "PAUSED"; });
+  on("resumed", () => { $.statusText.textContent = "LIVE"; });
+  on("source_changed", ({ source }) => console.info(`Source changed to ${source}`));
+  on("capture_error", ({ error }) => {
+    $.statusText.textContent = "CAPTURE DOWN";
+    $.statusText.title = error;
+  });
+  on("capture_restored", () => {
+    $.statusText.textContent = "LIVE";
+    $.statusText.title = "";
+  });
+  on("shutting_down", () => stopStream());
+
+  state.events = events;
+}
+
 // ===========================
 // Render Engine (Consumer)
 // ===========================
 function renderLoop() {
   if (!state.isStreaming) return;
   
-  if (state.pendingFrame) {
-    drawFrame(state.pendingFrame);
-    state.pendingFrame = null;
+  const frame = nextFrame();
+  if (frame) {
+    drawFrame(frame.data);
+
+    if (state.clockOffset !== null) {
+      state.latency = (performance.now() * 1000 + state.clockOffset - frame.timestamp) / 1000;
+    }
   }
   
   state.rafId = requestAnimationFrame(renderLoop);
 }
 
+// Newest frame due for display, frames wait for the audio clock when audio is playing
+function nextFrame() {
+  const queue = state.frameQueue;
+  if (queue.length === 0) return null;
+
+  const now = audioNow();
+  if (now === null) {
+    state.frameQueue = [];
+    return queue[queue.length - 1];
+  }
+
+  let due = -1;
+  while (due + 1 < queue.length && queue[due + 1].timestamp <= now) {
+    due++;
+  }
+
+  // Give up on syncing when the audio clock is far behind
+  if (due < 0 && queue[0].timestamp - now > CONFIG.MAX_SYNC_WAIT) {
+    due = 0;
+  }
+
+  if (due < 0) return null;
+
+  const frame = queue[due];
+  state.frameQueue = queue.slice(due + 1);
+  state.drift = frame.timestamp - now;
+  return frame;
+}
+
 async function drawFrame(data) {
   try {
-    const blob = new Blob([data], { type: "image/jpeg" });
-    const bitmap = await createImageBitmap(blob, {
+    const image = CODEC === "qoi"
+      ? decodeQoi(data)
+      : new Blob([data], { type: CODEC === "png" ? "image/png" : "image/jpeg" });
+    const bitmap = await createImageBitmap(image, {
       resizeQuality: "low", // Faster decoding
       premultiplyAlpha: "none",
     });
@@ -262,6 +571,53 @@ async function drawFrame(data) {
   }
 }
 
+// QOI (https://qoiformat.org) is not decoded by the browsers, a single pass over the bytes
+function decodeQoi(data) {
+  const view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+  const width = view.getUint32(4);
+  const height = view.getUint32(8);
+  const pixels = new Uint8ClampedArray(width * height * 4);
+  const index = new Uint8Array(64 * 4);
+  const end = data.length - 8; // end marker
+  let r = 0, g = 0, b = 0, a = 255, run = 0, p = 14;
+
+  for (let o = 0; o < pixels.length; o += 4) {
+    if (run > 0) {
+      run--;
+    } else if (p < end) {
+      const b1 = data[p++];
+
+      if (b1 === 0xfe) {
+        r = data[p++]; g = data[p++]; b = data[p++];
+      } else if (b1 === 0xff) {
+        r = data[p++]; g = data[p++]; b = data[p++]; a = data[p++];
+      } else if ((b1 & 0xc0) === 0x00) {
+        const i = b1 * 4;
+        r = index[i]; g = index[i + 1]; b = index[i + 2]; a = index[i + 3];
+      } else if ((b1 & 0xc0) === 0x40) {
+        r = (r + ((b1 >> 4) & 3) - 2) & 255;
+        g = (g + ((b1 >> 2) & 3) - 2) & 255;
+        b = (b + (b1 & 3) - 2) & 255;
+      } else if ((b1 & 0xc0) === 0x80) {
+        const b2 = data[p++];
+        const dg = (b1 & 0x3f) - 32;
+        r = (r + dg - 8 + ((b2 >> 4) & 0x0f)) & 255;
+        g = (g + dg) & 255;
+        b = (b + dg - 8 + (b2 & 0x0f)) & 255;
+      } else {
+        run = b1 & 0x3f;
+      }
+
+      const i = ((r * 3 + g * 5 + b * 7 + a * 11) % 64) * 4;
+      index[i] = r; index[i + 1] = g; index[i + 2] = b; index[i + 3] = a;
+    }
+
+    pixels[o] = r; pixels[o + 1] = g; pixels[o + 2] = b; pixels[o + 3] = a;
+  }
+
+  return new ImageData(pixels, width, height);
+}
+
 // ===========================
 // UI Updates
 // ===========================
@@ -278,7 +634,10 @@ function updateUI(active) {
 }
 
 function updateFPS() {
-  $.fpsCounter.textContent = `${state.frameCount} FPS`;
+  const drift = state.drift === null ? "" : ` · A/V ${Math.round(state.drift / 1000)}ms`;
+  const dropped = state.dropped === 0 ? "" : ` · ${state.dropped} dropped`;
+  const latency = state.latency === null ? "" : ` · ${Math.round(state.latency)}ms behind`;
+  $.fpsCounter.textContent = `${state.frameCount} FPS${latency}${drift}${dropped}`;
   state.frameCount = 0;
 }
 
@@ -325,6 +684,55 @@ function handleFullscreenChange() {
   }
 }
 
+// ===========================
+// Chat
+// ===========================
+function connectChat() {
+  const scheme = location.protocol === "https:" ? "wss://" : "ws://";
+  const socket = new WebSocket(scheme + location.host + withToken(CONFIG.ENDPOINTS.chat));
+
+  socket.addEventListener("message", (e) => {
+    const reply = JSON.parse(e.data);
+
+    if (reply.type === "history") {
+      $.chatMessages.replaceChildren();
+      reply.messages.forEach(appendChatMessage);
+    } else if (reply.type === "message") {
+      appendChatMessage(reply);
+    } else if (reply.type === "error") {
+      console.warn("Chat:", reply.error);
+    }
+  });
+
+  // the server restarted or the connection dropped
+  socket.addEventListener("close", () => setTimeout(connectChat, CONFIG.CHAT_RECONNECT));
+
+  $.chatForm.onsubmit = (e) => {
+    e.preventDefault();
+
+    const text = $.chatInput.value.trim();
+    if (!text || socket.readyState !== WebSocket.OPEN) return;
+
+    socket.send(JSON.stringify({ type: "message", text }));
+    $.chatInput.value = "";
+  };
+}
+
+function appendChatMessage(message) {
+  const line = document.createElement("div");
+  const name = document.createElement("strong");
+  name.textContent = message.name + ": ";
+  line.append(name, message.text);
+
+  $.chatMessages.append(line);
+  $.chatMessages.scrollTop = $.chatMessages.scrollHeight;
+}
+
+if (SETTINGS && SETTINGS.features.chat) {
+  $.chat.classList.remove("hidden");
+  connectChat();
+}
+
 // ===========================
 // Cleanup on Page Unload
 // ===========================
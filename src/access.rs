@@ -0,0 +1,136 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::Deserialize;
+
+/// `[access]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// networks (`192.168.1.0/24`, `fd00::/8`) or single ips allowed to connect, everyone when empty.
+    pub allow: Vec<String>,
+    /// networks or single ips refused even when they are allowed.
+    pub deny: Vec<String>,
+    /// keep the `X-Forwarded-For` and `X-Forwarded-Proto` headers of the clients, only set it when a reverse proxy is in front of the server.
+    pub trust_forwarded: bool,
+}
+
+impl AccessConfig {
+    /// Checks that every entry of the lists is a network or an ip.
+    pub fn validate(&self) -> Result<(), String> {
+        AccessList::new(self).map(|_| ())
+    }
+}
+
+/// # Access List
+///
+/// The parsed allow and deny lists, checked against the address of every connection before it reaches the web app.
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn new(config: &AccessConfig) -> Result<Self, String> {
+        let parse = |entries: &[String]| entries.iter().map(|entry| parse_net(entry)).collect();
+
+        Ok(Self {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+        })
+    }
+
+    /// Whether a client at the ip may connect, the deny list wins over the allow list.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        //ipv4 clients of a dual stack socket show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// A network like `10.0.0.0/8`, or a single ip.
+pub fn parse_net(entry: &str) -> Result<IpNet, String> {
+    let entry = entry.trim();
+
+    entry
+        .parse::<IpNet>()
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("Invalid [access] entry '{entry}', expected a network like 10.0.0.0/8 or an ip"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(allow: &[&str], deny: &[&str]) -> AccessList {
+        AccessList::new(&AccessConfig {
+            allow: allow.iter().map(|entry| entry.to_string()).collect(),
+            deny: deny.iter().map(|entry| entry.to_string()).collect(),
+            trust_forwarded: false,
+        })
+        .unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn allows_everyone_without_entries() {
+        let list = list(&[], &[]);
+
+        assert!(list.allows(ip("203.0.113.7")));
+        assert!(list.allows(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allows_only_the_allowed_networks() {
+        let list = list(&["192.168.1.0/24", "fd00::/8", "203.0.113.7"], &[]);
+
+        assert!(list.allows(ip("192.168.1.42")));
+        assert!(!list.allows(ip("192.168.2.1")));
+        assert!(list.allows(ip("fd12::1")));
+        assert!(!list.allows(ip("2001:db8::1")));
+        assert!(list.allows(ip("203.0.113.7")));
+        assert!(!list.allows(ip("203.0.113.8")));
+    }
+
+    #[test]
+    fn the_deny_list_wins() {
+        let nested = list(&["10.0.0.0/8"], &["10.0.5.0/24"]);
+
+        assert!(nested.allows(ip("10.1.2.3")));
+        assert!(!nested.allows(ip("10.0.5.9")));
+
+        let deny_only = list(&[], &["10.0.5.9"]);
+        assert!(!deny_only.allows(ip("10.0.5.9")));
+        assert!(deny_only.allows(ip("10.0.5.10")));
+    }
+
+    #[test]
+    fn checks_ipv4_clients_of_dual_stack_sockets() {
+        let list = list(&["192.168.1.0/24"], &["192.168.1.13"]);
+
+        assert!(list.allows(ip("::ffff:192.168.1.42")));
+        assert!(!list.allows(ip("::ffff:192.168.1.13")));
+        assert!(!list.allows(ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn refuses_invalid_entries() {
+        assert!(parse_net(" 10.0.0.0/8 ").is_ok());
+        assert!(parse_net("10.0.0.0/33").is_err());
+        assert!(parse_net("localhost").is_err());
+
+        let config = AccessConfig {
+            deny: vec!["10.0.0.300".to_string()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
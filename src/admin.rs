@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{ApiError, ApiValue, json},
+    auth::{Auth, Role, constant_time_eq},
+    bytes_resolution::BytesResolution,
+    events::ServerEvent,
+    request_params::{UNKNOWN_IP, bearer_token, host, query_param, scheme},
+    state::StreamState,
+};
+
+/// `[admin]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// token required by the admin routes, they are disabled without one.
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    /// Whether the request carries the admin token or one of the `[auth] admin_tokens`, as `Authorization: Bearer <token>` or `?token=`.
+    pub fn authorized(&self, auth: &Auth, req: &Parts) -> bool {
+        if let Some(token) = &self.token
+            && bearer_token(req).is_some_and(|given| constant_time_eq(token, &given))
+        {
+            return true;
+        }
+
+        auth.has_role(req, Role::Admin)
+    }
+}
+
+/// # Admin Routes
+///
+/// The authenticated admin routes:
+///
+/// - `POST /api/shutdown` - shuts the server down cleanly
+/// - `POST /api/restart-capture` - releases and reacquires the capture device
+/// - `POST /api/viewers/{id}/kick` - ends the stream of a viewer, `?ban=1` also bans its ip
+/// - `GET /api/viewers/bans` - the banned ips
+/// - `POST /api/viewers/unban?ip=` - lifts the ban of an ip
+/// - `POST /api/invites` - creates a one-time `/join/<code>` link
+/// - `POST /api/remote-input/allow` - lets a viewer control the mouse and keyboard through `/ws/input` until revoked
+/// - `POST /api/remote-input/revoke` - disconnects the viewer in control and refuses the next ones
+/// - `POST /api/files/offer?path=` - offers a file of the host at `/files/{id}`
+/// - `POST /api/files/{id}/withdraw` - stops offering a file
+pub fn admin_routes(config: AdminConfig) -> Router<Arc<StreamState>> {
+    let routes: [(&str, fn(&StreamState), &'static str); 2] = [
+        ("/api/shutdown", StreamState::request_quit, "shutting down"),
+        (
+            "/api/restart-capture",
+            StreamState::request_capture_restart,
+            "restarting capture",
+        ),
+    ];
+
+    let mut router = Router::new();
+
+    for (path, action, message) in routes {
+        let config = config.clone();
+
+        router = router.route(
+            path,
+            post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if !config.authorized(&state.auth, &req) {
+                    return unauthorized(&config, &state.auth);
+                }
+
+                action(&state);
+                json(ApiValue { value: message })
+            }),
+        );
+    }
+
+    let config_clone = config.clone();
+    router = router.route(
+        "/api/viewers/{id}/kick",
+        post(
+            move |State(state): State<Arc<StreamState>>, Path(id): Path<String>, req: Parts| async move {
+                if !config_clone.authorized(&state.auth, &req) {
+                    return unauthorized(&config_clone, &state.auth);
+                }
+
+                let Ok(id) = id.parse::<u64>() else {
+                    return BytesResolution::text(400, "Expected a viewer id").into_response();
+                };
+                let ban = query_param(&req, "ban").is_some_and(|ban| matches!(ban.as_str(), "1" | "true"));
+
+                let Some(session) = state.viewers.kick(id) else {
+                    return BytesResolution::text(404, format!("No viewer with id {id}")).into_response();
+                };
+
+                if ban {
+                    //without forwarding headers every viewer shares the unknown ip
+                    if session.client.ip == UNKNOWN_IP {
+                        return json(ApiError::new("Kicked, the ip of the viewer is unknown so it was not banned"));
+                    }
+
+                    state.viewers.ban(&session.client.ip);
+                    tracing::info!(id, ip = %session.client.ip, "Viewer banned");
+                } else {
+                    tracing::info!(id, ip = %session.client.ip, "Viewer kicked");
+                }
+
+                json(session.info())
+            },
+        ),
+    );
+
+    let config_clone = config.clone();
+    router = router.route(
+        "/api/viewers/bans",
+        get(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+            if !config_clone.authorized(&state.auth, &req) {
+                return unauthorized(&config_clone, &state.auth);
+            }
+
+            json(state.viewers.bans())
+        }),
+    );
+
+    let config_clone = config.clone();
+    router = router.route(
+        "/api/viewers/unban",
+        post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+            if !config_clone.authorized(&state.auth, &req) {
+                return unauthorized(&config_clone, &state.auth);
+            }
+
+            let Some(ip) = query_param(&req, "ip").filter(|ip| !ip.is_empty()) else {
+                return BytesResolution::text(400, "Expected ?ip=").into_response();
+            };
+
+            json(ApiValue {
+                value: state.viewers.unban(&ip),
+            })
+        }),
+    );
+
+    for (path, allowed) in [("/api/remote-input/allow", true), ("/api/remote-input/revoke", false)] {
+        let config = config.clone();
+
+        router = router.route(
+            path,
+            post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if !config.authorized(&state.auth, &req) {
+                    return unauthorized(&config, &state.auth);
+                }
+
+                if state.control.is_remote_input_allowed() != allowed {
+                    state.control.set_remote_input_allowed(allowed);
+                    state.emit(if allowed {
+                        ServerEvent::RemoteInputAllowed
+                    } else {
+                        ServerEvent::RemoteInputRevoked
+                    });
+                    tracing::info!(allowed, "Remote input changed");
+                }
+
+                json(state.status())
+            }),
+        );
+    }
+
+    let config_clone = config.clone();
+    router = router.route(
+        "/api/files/offer",
+        post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+            if !config_clone.authorized(&state.auth, &req) {
+                return unauthorized(&config_clone, &state.auth);
+            }
+
+            let Some(path) = query_param(&req, "path").filter(|path| !path.is_empty()) else {
+                return BytesResolution::text(400, "Expected ?path=").into_response();
+            };
+
+            match state.files.offer(&path) {
+                Ok(file) => {
+                    tracing::info!(id = file.id, %path, "File offered");
+                    json(file)
+                }
+                Err(e) => ApiError::new(format!("Failed to offer {path}: {e}")).response(400),
+            }
+        }),
+    );
+
+    let config_clone = config.clone();
+    router = router.route(
+        "/api/files/{id}/withdraw",
+        post(
+            move |State(state): State<Arc<StreamState>>, Path(id): Path<String>, req: Parts| async move {
+                if !config_clone.authorized(&state.auth, &req) {
+                    return unauthorized(&config_clone, &state.auth);
+                }
+
+                let Ok(id) = id.parse::<u64>() else {
+                    return BytesResolution::text(400, "Expected a file id").into_response();
+                };
+
+                json(ApiValue {
+                    value: state.files.withdraw(id),
+                })
+            },
+        ),
+    );
+
+    router.route(
+        "/api/invites",
+        post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+            if !config.authorized(&state.auth, &req) {
+                return unauthorized(&config, &state.auth);
+            }
+
+            let (code, expires_at) = state.auth.create_invite();
+            let path = format!("/join/{code}");
+            //the host the admin reached the server with is the one viewers can reach it with too
+            let url = host(&req).map(|host| format!("{}://{host}{path}", scheme(&req)));
+
+            json(Invite { path, url, expires_at })
+        }),
+    )
+}
+
+/// Rest API Json of a created invite.
+#[derive(Serialize)]
+struct Invite {
+    path: String,
+    /// full url of the invite, when the host of the request is known.
+    url: Option<String>,
+    /// unix timestamp (seconds) the invite expires at.
+    expires_at: u64,
+}
+
+/// The response of an admin route requested without the admin token.
+pub(crate) fn unauthorized(config: &AdminConfig, auth: &Auth) -> Response {
+    match config.token.is_some() || auth.admin_tokens_enabled() {
+        true => BytesResolution::text(401, "Invalid admin token").into_response(),
+        false => BytesResolution::text(403, "Admin routes are disabled, set [admin] token or [auth] admin_tokens").into_response(),
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, State},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use ipnet::IpNet;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use crate::{
+    access::parse_net,
+    api::json,
+    assets::asset,
+    auth::guard,
+    bytes_resolution::BytesResolution,
+    state::StreamState,
+};
+
+/// Service type the hosts are advertised and discovered as.
+pub const SERVICE_TYPE: &str = "_share-screen._tcp.local.";
+
+/// Time a host has to answer its status before it is shown offline.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `[aggregate]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AggregateConfig {
+    /// serve the dashboard of the hosts at `/hosts`.
+    pub enabled: bool,
+    /// hosts always shown on the dashboard.
+    pub hosts: Vec<AggregateHost>,
+    /// add the hosts advertised on the LAN (mDNS).
+    pub discover: bool,
+    /// token sent to the discovered hosts in the `trusted` networks, the `[auth] tokens` they share.
+    pub token: Option<String>,
+    /// networks (`192.168.1.0/24`) or single ips of the discovered hosts the `token` is sent to, anyone can advertise a host.
+    pub trusted: Vec<String>,
+    /// advertise this host on the LAN so dashboards discover it.
+    pub advertise: bool,
+    /// name advertised, the host name of the machine when missing.
+    pub name: Option<String>,
+}
+
+impl Default for AggregateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hosts: Vec::new(),
+            discover: false,
+            token: None,
+            trusted: Vec::new(),
+            advertise: false,
+            name: None,
+        }
+    }
+}
+
+impl AggregateConfig {
+    /// Checks that every entry of `trusted` is a network or an ip.
+    pub fn validate(&self) -> Result<(), String> {
+        self.trusted_networks().map(|_| ())
+    }
+
+    fn trusted_networks(&self) -> Result<Vec<IpNet>, String> {
+        self.trusted
+            .iter()
+            .map(|entry| {
+                parse_net(entry).map_err(|_| format!("Invalid [aggregate] trusted entry '{entry}', expected a network or an ip"))
+            })
+            .collect()
+    }
+}
+
+/// A host of `[[aggregate.hosts]]`.
+#[derive(Deserialize, Clone)]
+pub struct AggregateHost {
+    /// base url of the host, like `http://192.168.1.20:80`.
+    pub url: String,
+    pub name: Option<String>,
+    /// one of the `[auth] tokens` of the host.
+    pub token: Option<String>,
+}
+
+/// A host of the dashboard, static or discovered.
+#[derive(Clone)]
+struct KnownHost {
+    id: String,
+    name: String,
+    url: String,
+    token: Option<String>,
+    discovered: bool,
+}
+
+impl KnownHost {
+    fn new(name: String, url: String, token: Option<String>, discovered: bool) -> Self {
+        let url = url.trim_end_matches('/').to_string();
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+
+        Self {
+            id: format!("{:x}", hasher.finish()),
+            name,
+            url,
+            token,
+            discovered,
+        }
+    }
+}
+
+/// Rest API Json of a host of `/api/hosts`.
+#[derive(Serialize)]
+struct HostStatus {
+    id: String,
+    name: String,
+    url: String,
+    discovered: bool,
+    online: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// the `/api/status` of the host while it is online.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<serde_json::Value>,
+}
+
+/// # Host Directory
+///
+/// The hosts of the dashboard: the ones of the config and the ones discovered on the LAN, keyed by their full mDNS name.
+struct HostDirectory {
+    config: AggregateConfig,
+    discovered: Arc<Mutex<HashMap<String, KnownHost>>>,
+    client: reqwest::Client,
+}
+
+impl HostDirectory {
+    fn new(config: AggregateConfig) -> Self {
+        let discovered = Arc::new(Mutex::new(HashMap::new()));
+
+        if config.discover {
+            //validated with the config
+            let trusted = config.trusted_networks().unwrap_or_default();
+
+            browse(config.token.clone(), trusted, discovered.clone());
+        }
+
+        Self {
+            config,
+            discovered,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The static hosts then the discovered ones, a discovered host already in the config is only listed once.
+    fn hosts(&self) -> Vec<KnownHost> {
+        let mut hosts: Vec<KnownHost> = self
+            .config
+            .hosts
+            .iter()
+            .map(|host| {
+                let name = host.name.clone().unwrap_or_else(|| host.url.clone());
+                KnownHost::new(name, host.url.clone(), host.token.clone(), false)
+            })
+            .collect();
+
+        let mut discovered: Vec<KnownHost> = self.discovered.lock().unwrap().values().cloned().collect();
+        discovered.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for host in discovered {
+            if !hosts.iter().any(|known| known.id == host.id) {
+                hosts.push(host);
+            }
+        }
+
+        hosts
+    }
+
+    fn host(&self, id: &str) -> Option<KnownHost> {
+        self.hosts().into_iter().find(|host| host.id == id)
+    }
+
+    async fn status(&self, host: KnownHost) -> HostStatus {
+        let mut request = self.client.get(format!("{}/api/status", host.url)).timeout(STATUS_TIMEOUT);
+
+        if let Some(token) = &host.token {
+            request = request.bearer_auth(token);
+        }
+
+        let status = match request.send().await.and_then(|response| response.error_for_status()) {
+            Ok(response) => response.json::<serde_json::Value>().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        HostStatus {
+            id: host.id,
+            name: host.name,
+            url: host.url,
+            discovered: host.discovered,
+            online: status.is_ok(),
+            error: status.as_ref().err().cloned(),
+            status: status.ok(),
+        }
+    }
+}
+
+/// # Aggregate Routes
+///
+/// The dashboard of several hosts for a classroom or a lab, with `[aggregate] enabled`:
+///
+/// - `GET /hosts` - the dashboard, a live thumbnail per host with its status and links
+/// - `GET /api/hosts` - the hosts and their `/api/status`, offline ones with the error
+/// - `GET /hosts/{id}/thumbnail.jpg` - the thumbnail of a host, through this server
+/// - `GET /hosts/{id}/stream` - the stream of a host through this server, with the query of `/stream`
+///
+/// Note: `The hosts are the ones of [aggregate] hosts and, with discover, the ones advertised on the LAN (mDNS).`
+pub fn aggregate_routes(config: AggregateConfig) -> Router<Arc<StreamState>> {
+    if !config.enabled {
+        return Router::new();
+    }
+
+    let directory = Arc::new(HostDirectory::new(config));
+
+    Router::new()
+        .route(
+            "/hosts",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                let page = asset("hosts.html").expect("hosts.html is embedded");
+
+                BytesResolution::new(page.content.to_vec(), "text/html; charset=utf-8")
+                    .with_header("Cache-Control", "no-cache")
+                    .into_response()
+            }),
+        )
+        .route(
+            "/api/hosts",
+            get({
+                let directory = directory.clone();
+
+                move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+                    if let Err(denied) = guard(&state, &req) {
+                        return denied;
+                    }
+
+                    let statuses = directory.hosts().into_iter().map(|host| directory.status(host));
+
+                    json(futures::future::join_all(statuses).await)
+                }
+            }),
+        )
+        .route(
+            "/hosts/{id}/thumbnail.jpg",
+            get({
+                let directory = directory.clone();
+
+                move |State(state): State<Arc<StreamState>>, Path(id): Path<String>, req: Parts| async move {
+                    if let Err(denied) = guard(&state, &req) {
+                        return denied;
+                    }
+
+                    proxy(&directory, &id, "thumbnail.jpg", &req).await
+                }
+            }),
+        )
+        .route(
+            "/hosts/{id}/stream",
+            get(
+                move |State(state): State<Arc<StreamState>>, Path(id): Path<String>, req: Parts| async move {
+                    if let Err(denied) = guard(&state, &req) {
+                        return denied;
+                    }
+
+                    proxy(&directory, &id, "stream", &req).await
+                },
+            ),
+        )
+}
+
+/// Forwards a request to a host with its token, its response is streamed back as it arrives.
+async fn proxy(directory: &HostDirectory, id: &str, path: &str, req: &Parts) -> Response {
+    let Some(host) = directory.host(id) else {
+        return BytesResolution::text(404, "Unknown host").into_response();
+    };
+
+    //the token of this server is not the one of the host
+    let query: Vec<&str> = req
+        .uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("token="))
+        .collect();
+
+    let url = match query.is_empty() {
+        true => format!("{}/{path}", host.url),
+        false => format!("{}/{path}?{}", host.url, query.join("&")),
+    };
+
+    let mut request = directory.client.get(url);
+
+    if let Some(token) = &host.token {
+        request = request.bearer_auth(token);
+    }
+
+    match request.send().await {
+        Ok(response) => {
+            let status = response.status();
+            let content_type = response.headers().get("content-type").cloned();
+
+            let mut proxied = Response::builder().status(status);
+
+            if let Some(content_type) = content_type {
+                proxied = proxied.header("content-type", content_type);
+            }
+
+            proxied
+                .header("cache-control", "no-cache")
+                .body(Body::from_stream(response.bytes_stream()))
+                .unwrap_or_else(|_| BytesResolution::text(502, "Invalid response of the host").into_response())
+        }
+        Err(e) => {
+            debug!(host = %host.url, error = %e, "Failed to reach a host");
+            BytesResolution::text(502, format!("Failed to reach {}: {e}", host.name)).into_response()
+        }
+    }
+}
+
+/// Keeps the discovered hosts up to date from the mDNS announcements, on the thread of the mDNS daemon. The token only goes to
+/// the hosts of the trusted networks.
+fn browse(token: Option<String>, trusted: Vec<IpNet>, discovered: Arc<Mutex<HashMap<String, KnownHost>>>) {
+    let browsing = ServiceDaemon::new().and_then(|daemon| Ok((daemon.browse(SERVICE_TYPE)?, daemon)));
+
+    let (receiver, daemon) = match browsing {
+        Ok(browsing) => browsing,
+        Err(e) => {
+            error!(error = %e, "Failed to browse the LAN for hosts, only the [aggregate] hosts are shown");
+            return;
+        }
+    };
+
+    let spawned = std::thread::Builder::new().name("mdns-browse".to_string()).spawn(move || {
+        //browsing as long as the routes are served
+        let _daemon = daemon;
+
+        while let Ok(event) = receiver.recv() {
+            match event {
+                ServiceEvent::ServiceResolved(info) => {
+                    //ipv4 first, a link local ipv6 cannot be used as is in a url
+                    let mut addresses: Vec<&IpAddr> = info.get_addresses().iter().collect();
+                    addresses.sort_by_key(|address| address.is_ipv6());
+
+                    let Some(address) = addresses.first() else {
+                        continue;
+                    };
+
+                    let url = match address {
+                        IpAddr::V4(ip) => format!("http://{ip}:{}", info.get_port()),
+                        IpAddr::V6(ip) => format!("http://[{ip}]:{}", info.get_port()),
+                    };
+                    let name = info
+                        .get_property_val_str("name")
+                        .map(str::to_string)
+                        .unwrap_or_else(|| info.get_fullname().trim_end_matches(SERVICE_TYPE).trim_end_matches('.').to_string());
+
+                    debug!(%name, %url, "Discovered a host");
+
+                    let trusted = trusted.iter().any(|net| net.contains(&address.to_canonical()));
+                    let host = KnownHost::new(name, url, token.clone().filter(|_| trusted), true);
+                    discovered.lock().unwrap().insert(info.get_fullname().to_string(), host);
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    discovered.lock().unwrap().remove(&fullname);
+                }
+                _ => {}
+            }
+        }
+    });
+
+    if let Err(e) = spawned {
+        error!(error = %e, "Failed to spawn the mDNS browse thread");
+    }
+}
+
+/// # Spawn Advertiser
+///
+/// Advertises this host on the LAN with `[aggregate] advertise`, so the dashboards discover it. Withdrawn on shutdown.
+pub fn spawn_advertiser(config: &AggregateConfig, ip: IpAddr, port: u16, state: Arc<StreamState>) {
+    if !config.advertise {
+        return;
+    }
+
+    let name = config
+        .name
+        .clone()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| ip.to_string());
+    let host_name = format!("share-screen-{}.local.", ip.to_string().replace(['.', ':'], "-"));
+
+    let advertised = ServiceDaemon::new().and_then(|daemon| {
+        let info = ServiceInfo::new(SERVICE_TYPE, &name, &host_name, ip, port, &[("name", name.as_str())][..])?;
+        let fullname = info.get_fullname().to_string();
+
+        daemon.register(info)?;
+
+        Ok((daemon, fullname))
+    });
+
+    let (daemon, fullname) = match advertised {
+        Ok(advertised) => advertised,
+        Err(e) => {
+            state.stats.error();
+            error!(error = %e, "Failed to advertise the host on the LAN");
+            return;
+        }
+    };
+
+    info!(%name, "Advertising the host on the LAN");
+
+    tokio::spawn(async move {
+        state.shutting_down().await;
+
+        let _ = daemon.unregister(&fullname);
+        let _ = daemon.shutdown();
+    });
+}
@@ -0,0 +1,465 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Serialize;
+
+use crate::{
+    auth::{control_guard, guard},
+    devices::list_devices,
+    error::ShareScreenError,
+    events::ServerEvent,
+    openapi::{OPENAPI_PATH, openapi_document},
+    recorder::recording_path,
+    replay::ReplayBuffer,
+    request_params::query_param,
+    state::StreamState,
+};
+
+/// Rest API Json for a failed request.
+#[derive(Serialize)]
+pub struct ApiError {
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn new(error: impl ToString) -> Self {
+        Self {
+            error: error.to_string(),
+        }
+    }
+
+    /// The error as the json response with the status, `400` for a request that cannot be served as asked, `500` when the server failed.
+    pub fn response(self, status: u16) -> Response {
+        (StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR), Json(self)).into_response()
+    }
+}
+
+/// The status of a failed operation: `400` when it cannot be done in the current state (already recording, empty replay buffer...),
+/// `500` when the server failed at it (writing the file, starting the encoder).
+pub fn error_status(error: &ShareScreenError) -> u16 {
+    match error {
+        ShareScreenError::Server(_) => 400,
+        _ => 500,
+    }
+}
+
+/// Rest API Json of `/api/ping`.
+#[derive(Serialize)]
+struct Pong {
+    echo: Option<String>,
+    /// microseconds on the stream clock, the clock of the frame timestamps.
+    server_us: u64,
+}
+
+/// Rest API Json of `/api/time`.
+#[derive(Serialize)]
+struct ServerTime {
+    /// microseconds since the unix epoch.
+    unix_us: u64,
+    /// microseconds on the stream clock, the clock of the frame timestamps.
+    stream_us: u64,
+    /// unix time (microseconds) the stream clock started at.
+    epoch_unix_us: u64,
+}
+
+impl ServerTime {
+    fn now(state: &StreamState) -> Self {
+        //read together, the two clocks are a few nanoseconds apart
+        let stream_us = state.timestamp_us();
+        let unix_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        Self {
+            unix_us,
+            stream_us,
+            epoch_unix_us: unix_us.saturating_sub(stream_us),
+        }
+    }
+}
+
+/// Rest API Json for a single value.
+#[derive(Serialize)]
+pub struct ApiValue<T: Serialize> {
+    pub value: T,
+}
+
+/// # API Routes
+///
+/// The `/api/` routes of the web app, served under the versioned `/api/v1/` as well (see `router`) and described by `GET /api/v1/openapi.json`.
+///
+/// Settings are changed with a POST and a `?value=` query parameter, for example `POST /api/v1/quality?value=50`.
+pub fn api_routes() -> Router<Arc<StreamState>> {
+    let mut router = Router::new()
+        //the contract of the versioned api, open so clients can discover it before they have a token
+        .route(OPENAPI_PATH, get(|| async { json(openapi_document()) }))
+        //cameras and monitors available on the host
+        .route(
+            "/api/devices",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                let devices =
+                    tokio::task::spawn_blocking(|| list_devices().map_err(|e| e.to_string())).await;
+
+                match devices {
+                    Ok(Ok(devices)) => json(devices),
+                    Ok(Err(e)) => ApiError::new(e).response(500),
+                    Err(e) => ApiError::new(e).response(500),
+                }
+            }),
+        )
+        //echoes ?echo= with the stream clock, the clock of the frame timestamps, viewers report their latency with ?latency_ms=
+        .route(
+            "/api/ping",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                if let Some(latency) = query_param(&req, "latency_ms")
+                    .and_then(|latency| latency.parse::<f64>().ok())
+                    .filter(|latency| latency.is_finite() && (0.0..60_000.0).contains(latency))
+                {
+                    state.pipeline.viewer_latency.record(Duration::from_secs_f64(latency / 1000.0));
+                }
+
+                json(Pong {
+                    echo: query_param(&req, "echo"),
+                    server_us: state.timestamp_us(),
+                })
+            }),
+        )
+        //wall clock of the server along with the stream clock, frame timestamps are `epoch_unix_us + timestamp us` in wall clock
+        .route(
+            "/api/time",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ServerTime::now(&state))
+            }),
+        )
+        //everything at once
+        .route(
+            "/api/status",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(state.status())
+            }),
+        );
+
+    //pause and resume the broadcast of frames
+    for (path, paused) in [("/api/pause", true), ("/api/resume", false)] {
+        router = router.route(
+            path,
+            post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                if state.control.is_paused() != paused {
+                    state.control.set_paused(paused);
+                    state.emit(if paused { ServerEvent::Paused } else { ServerEvent::Resumed });
+                }
+
+                json(state.status())
+            }),
+        );
+    }
+
+    router
+        .route(
+            "/api/source",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.source().to_string(),
+                })
+            }),
+        )
+        .route(
+            "/api/viewers",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(state.viewers.list())
+            }),
+        )
+        .route(
+            "/api/viewers/count",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.stats.viewers(),
+                })
+            }),
+        )
+        .route(
+            "/api/quality",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.control.quality(),
+                })
+            })
+            .post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match parsed_value::<u8>(&req) {
+                    Some(quality) if (1..=100).contains(&quality) => {
+                        state.control.set_quality(quality);
+                        json(state.status())
+                    }
+                    _ => ApiError::new("Expected ?value= from 1 to 100").response(400),
+                }
+            }),
+        )
+        //luminance only frames, for text-heavy shares over slow links
+        .route(
+            "/api/grayscale",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.control.is_grayscale(),
+                })
+            })
+            .post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match parsed_value::<bool>(&req) {
+                    Some(grayscale) => {
+                        state.control.set_grayscale(grayscale);
+                        json(state.status())
+                    }
+                    None => ApiError::new("Expected ?value=true or ?value=false").response(400),
+                }
+            }),
+        )
+        //the stats of the pipeline burned into the frames, to tune the settings while watching the stream
+        .route(
+            "/api/debug-overlay",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.control.is_debug_overlay(),
+                })
+            })
+            .post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match parsed_value::<bool>(&req) {
+                    Some(shown) => {
+                        state.control.set_debug_overlay(shown);
+                        json(state.status())
+                    }
+                    None => ApiError::new("Expected ?value=true or ?value=false").response(400),
+                }
+            }),
+        )
+        //size of the streamed frames in percent of the captured ones
+        .route(
+            "/api/scale",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.scaler.scale(),
+                })
+            })
+            .post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match parsed_value::<u8>(&req) {
+                    Some(scale) if (1..=100).contains(&scale) => {
+                        state.scaler.set_scale(scale);
+                        json(state.status())
+                    }
+                    _ => ApiError::new("Expected ?value= from 1 to 100").response(400),
+                }
+            }),
+        )
+        //swaps left and right, for a camera filming the host
+        .route(
+            "/api/mirror",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.transform.is_mirrored(),
+                })
+            })
+            .post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match parsed_value::<bool>(&req) {
+                    Some(mirrored) => {
+                        state.transform.set_mirrored(mirrored);
+                        json(state.status())
+                    }
+                    None => ApiError::new("Expected ?value=true or ?value=false").response(400),
+                }
+            }),
+        )
+        .route(
+            "/api/fps",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(ApiValue {
+                    value: state.control.max_fps(),
+                })
+            })
+            .post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match parsed_value::<u32>(&req) {
+                    Some(fps) => {
+                        state.control.set_max_fps(fps);
+                        json(state.status())
+                    }
+                    None => ApiError::new("Expected ?value= with the max fps (0 is unlimited)").response(400),
+                }
+            }),
+        )
+        .route(
+            "/api/record",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(state.recorder.status().await)
+            }),
+        )
+        //starts a recording, ?name= is optional
+        .route(
+            "/api/record/start",
+            post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                let path = match named_recording(&req, || state.recorder.default_path()) {
+                    Ok(path) => path,
+                    Err(refused) => return refused,
+                };
+
+                match state.recorder.start(path, state.clone()).await {
+                    Ok(_) => json(state.recorder.status().await),
+                    Err(e) => ApiError::new(&e).response(error_status(&e)),
+                }
+            }),
+        )
+        .route(
+            "/api/record/stop",
+            post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                match state.recorder.stop().await {
+                    Some(path) => json(ApiValue { value: path }),
+                    None => ApiError::new("Not recording").response(400),
+                }
+            }),
+        )
+        //dumps the replay buffer to a file, ?name= is optional
+        .route(
+            "/api/replay/save",
+            post(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = control_guard(&state, &req) {
+                    return denied;
+                }
+
+                let path = match named_recording(&req, ReplayBuffer::default_path) {
+                    Ok(path) => path,
+                    Err(refused) => return refused,
+                };
+
+                let dimensions = state.dimensions();
+
+                match state
+                    .replay
+                    .save(&path, dimensions.width as u32, dimensions.height as u32)
+                    .await
+                {
+                    Ok(_) => {
+                        tracing::info!(%path, "Replay saved");
+                        json(ApiValue { value: path })
+                    }
+                    Err(e) => ApiError::new(&e).response(error_status(&e)),
+                }
+            }),
+        )
+}
+
+/// The file of the recordings folder `?name=` names, the default path without it, a `400` for a name that is not a bare file name.
+fn named_recording(req: &Parts, default_path: impl FnOnce() -> String) -> Result<String, Response> {
+    match query_param(req, "name").filter(|name| !name.is_empty()) {
+        Some(name) => recording_path(&name)
+            .ok_or_else(|| ApiError::new("Expected ?name= with a file name, written to the recordings folder").response(400)),
+        None => Ok(default_path()),
+    }
+}
+
+/// Parses the `?value=` query parameter of the request.
+pub fn parsed_value<T: std::str::FromStr>(req: &Parts) -> Option<T> {
+    query_param(req, "value")?.parse().ok()
+}
+
+/// Serializes the value as the json response.
+pub fn json<T: Serialize>(value: T) -> Response {
+    Json(value).into_response()
+}
@@ -0,0 +1,196 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Serialize;
+
+use crate::{
+    bytes_resolution::BytesResolution,
+    packets::PROTOCOL_VERSION,
+    request_params::host,
+    state::StreamState,
+    static_files::{cached_response, mime_type},
+};
+
+/// # Asset
+///
+/// A file of the bundled viewer, embedded into the binary so it is served no matter the working directory.
+pub struct Asset {
+    pub name: &'static str,
+    pub content: &'static [u8],
+}
+
+/// The files of `content/`, served at `/content/{name}`.
+pub const ASSETS: &[Asset] = &[
+    Asset {
+        name: "stream.html",
+        content: include_bytes!("../content/stream.html"),
+    },
+    Asset {
+        name: "hosts.html",
+        content: include_bytes!("../content/hosts.html"),
+    },
+    Asset {
+        name: "login.html",
+        content: include_bytes!("../content/login.html"),
+    },
+    Asset {
+        name: "script.js",
+        content: include_bytes!("../content/script.js"),
+    },
+    Asset {
+        name: "styles.css",
+        content: include_bytes!("../content/styles.css"),
+    },
+];
+
+/// The embedded asset with the name.
+pub fn asset(name: &str) -> Option<&'static Asset> {
+    ASSETS.iter().find(|asset| asset.name == name)
+}
+
+impl Asset {
+    pub fn text(&self) -> &'static str {
+        std::str::from_utf8(self.content).unwrap_or_default()
+    }
+
+    /// Changes with the content, so browsers fetch the asset again after an update.
+    pub fn etag(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.content.hash(&mut hasher);
+
+        format!("\"{:x}\"", hasher.finish())
+    }
+}
+
+/// # Render
+///
+/// Replaces every `{{key}}` of the template with its value.
+pub fn render(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |page, (key, value)| {
+            page.replace(&format!("{{{{{key}}}}}"), value)
+        })
+}
+
+/// Escapes text put into a page.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// # Viewer Settings
+///
+/// Injected into the viewer page as `window.SHARE_SCREEN`, so it can start without asking the server first.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewerSettings {
+    /// the host the page was requested from.
+    pub address: String,
+    pub width: usize,
+    pub height: usize,
+    pub protocol_version: u8,
+    pub features: ViewerFeatures,
+}
+
+/// What the server has enabled.
+#[derive(Serialize)]
+pub struct ViewerFeatures {
+    pub audio: bool,
+    pub login: bool,
+    pub encrypted: bool,
+    pub chat: bool,
+}
+
+impl ViewerSettings {
+    pub fn current(state: &StreamState, address: String) -> Self {
+        let dimensions = state.dimensions();
+
+        Self {
+            address,
+            width: dimensions.width,
+            height: dimensions.height,
+            protocol_version: PROTOCOL_VERSION,
+            features: ViewerFeatures {
+                audio: state.audio_enabled(),
+                login: state.auth.password_enabled() || state.auth.oidc_enabled(),
+                encrypted: state.cipher.is_some(),
+                chat: state.chat.enabled(),
+            },
+        }
+    }
+}
+
+/// # Viewer Routes
+///
+/// The routes of the bundled viewer:
+///
+/// - `GET /` - the viewer page with the current `ViewerSettings`, the login form when a password is set and the viewer has no session,
+///   a redirect to `/login/oidc` when only OpenID Connect is
+/// - `GET /content/{file}` - the embedded scripts and styles
+pub fn viewer_routes() -> Router<Arc<StreamState>> {
+    Router::new()
+        .route("/", get(viewer_page))
+        .route("/content/{file}", get(content))
+}
+
+async fn content(Path(name): Path<String>, req: Parts) -> Response {
+    match asset(&name) {
+        Some(asset) => cached_response(
+            &req,
+            asset.content.to_vec(),
+            mime_type(std::path::Path::new(&name)),
+            asset.etag(),
+            "no-cache".to_string(),
+        ),
+        None => BytesResolution::text(404, "Not found").into_response(),
+    }
+}
+
+async fn viewer_page(State(state): State<Arc<StreamState>>, req: Parts) -> Response {
+    let (password, oidc) = (state.auth.password_enabled(), state.auth.oidc_enabled());
+
+    if (password || oidc) && !state.auth.authorized(&req) {
+        if !password {
+            return BytesResolution::text(303, "Log in with the identity provider")
+                .with_header("Location", "/login/oidc")
+                .into_response();
+        }
+
+        //the password form links to the provider as well when both are set
+        let (hidden, provider) = match oidc {
+            true => ("", escape_html(&state.auth.oidc_config().name)),
+            false => ("hidden", String::new()),
+        };
+        let login = render(
+            asset("login.html").expect("login.html is embedded").text(),
+            &[("oidc", hidden.to_string()), ("provider", provider)],
+        );
+
+        return BytesResolution::new(login.into_bytes(), "text/html; charset=utf-8").into_response();
+    }
+
+    let address = host(&req).unwrap_or_default();
+    let settings = serde_json::to_string(&ViewerSettings::current(&state, address)).unwrap_or_default();
+
+    let page = render(
+        asset("stream.html").expect("stream.html is embedded").text(),
+        //a `</script>` in a value would end the script tag early
+        &[("settings", settings.replace("</", "<\\/"))],
+    );
+
+    BytesResolution::new(page.into_bytes(), "text/html; charset=utf-8")
+        .with_header("Cache-Control", "no-cache")
+        .into_response()
+}
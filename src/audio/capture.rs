@@ -0,0 +1,200 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use cpal::{
+    SampleFormat, StreamConfig,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+};
+
+use super::{CHANNELS, SAMPLE_RATE};
+use crate::error::{Result, ShareScreenError};
+
+/// Most samples buffered per source (500ms), older samples are dropped when the mixer falls behind.
+const MAX_BUFFERED: usize = SAMPLE_RATE as usize * CHANNELS / 2;
+
+/// The audio sources that can be captured.
+pub enum AudioSource {
+    /// What the host is playing on the default output device.
+    Loopback,
+    /// A microphone by (part of) its name, "default" for the default input device.
+    Microphone(String),
+}
+
+impl std::fmt::Display for AudioSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioSource::Loopback => write!(f, "system audio"),
+            AudioSource::Microphone(name) => write!(f, "microphone '{name}'"),
+        }
+    }
+}
+
+/// # Audio Input
+///
+/// A running audio source, samples are converted to `SAMPLE_RATE` stereo and buffered for the mixer.
+pub struct AudioInput {
+    pub name: String,
+    pub buffer: Arc<Mutex<VecDeque<f32>>>,
+}
+
+/// # Start Source
+///
+/// Opens the device of the source and starts capturing.
+///
+/// The cpal stream is not Send, it lives on its own thread for the rest of the program.
+pub fn start_source(source: &AudioSource) -> Result<AudioInput> {
+    let host = cpal::default_host();
+
+    let (device, supported) = match source {
+        AudioSource::Loopback => {
+            //WASAPI captures the output device in loopback mode when used as an input
+            let device = host
+                .default_output_device()
+                .ok_or_else(|| capture_error("No output device for loopback."))?;
+            let supported = device.default_output_config().map_err(capture_error)?;
+            (device, supported)
+        }
+        AudioSource::Microphone(name) => {
+            let device = if name.eq_ignore_ascii_case("default") {
+                host.default_input_device()
+            } else {
+                let lowered = name.to_lowercase();
+                host.input_devices().map_err(capture_error)?.find(|d| {
+                    d.name()
+                        .map(|n| n.to_lowercase().contains(&lowered))
+                        .unwrap_or(false)
+                })
+            }
+            .ok_or_else(|| capture_error(format!("No microphone matching '{name}'.")))?;
+
+            let supported = device.default_input_config().map_err(capture_error)?;
+            (device, supported)
+        }
+    };
+
+    let name = device.name().unwrap_or_else(|_| source.to_string());
+    let buffer = Arc::new(Mutex::new(VecDeque::new()));
+    let sample_format = supported.sample_format();
+    let config: StreamConfig = supported.config();
+
+    let thread_buffer = buffer.clone();
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel::<Result<(), String>>();
+
+    std::thread::spawn(move || {
+        let mut converter = Converter::new(config.channels as usize, config.sample_rate.0);
+        let buffer = thread_buffer;
+
+        let on_error = |e| tracing::error!(error = %e, "Audio stream error");
+
+        let stream = match sample_format {
+            SampleFormat::F32 => device.build_input_stream(
+                &config,
+                move |data: &[f32], _| converter.push(data.iter().copied(), &buffer),
+                on_error,
+                None,
+            ),
+            SampleFormat::I16 => device.build_input_stream(
+                &config,
+                move |data: &[i16], _| {
+                    converter.push(data.iter().map(|s| *s as f32 / i16::MAX as f32), &buffer)
+                },
+                on_error,
+                None,
+            ),
+            other => {
+                let _ = ready_tx.send(Err(format!("Unsupported sample format {other}")));
+                return;
+            }
+        };
+
+        let stream = match stream.map_err(|e| e.to_string()).and_then(|s| {
+            s.play().map_err(|e| e.to_string())?;
+            Ok(s)
+        }) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+
+        let _ = ready_tx.send(Ok(()));
+
+        //keep the stream alive
+        loop {
+            std::thread::park();
+            let _ = &stream;
+        }
+    });
+
+    ready_rx
+        .recv()
+        .map_err(capture_error)?
+        .map_err(capture_error)?;
+
+    Ok(AudioInput { name, buffer })
+}
+
+fn capture_error(e: impl std::fmt::Display) -> ShareScreenError {
+    ShareScreenError::Capture(e.to_string())
+}
+
+/// Converts device samples to interleaved stereo at `SAMPLE_RATE` with linear interpolation.
+struct Converter {
+    channels: usize,
+    step: f64,
+    position: f64,
+    previous: [f32; CHANNELS],
+}
+
+impl Converter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            channels: channels.max(1),
+            step: sample_rate as f64 / SAMPLE_RATE as f64,
+            position: 0.0,
+            previous: [0.0; CHANNELS],
+        }
+    }
+
+    fn push(&mut self, data: impl Iterator<Item = f32>, buffer: &Mutex<VecDeque<f32>>) {
+        let samples: Vec<f32> = data.collect();
+        let frames: Vec<[f32; CHANNELS]> = samples
+            .chunks(self.channels)
+            .map(|frame| {
+                let left = frame[0];
+                let right = if frame.len() > 1 { frame[1] } else { left };
+                [left, right]
+            })
+            .collect();
+
+        let mut out = Vec::with_capacity((frames.len() as f64 / self.step) as usize * CHANNELS + CHANNELS);
+
+        //position is relative to the previous frame (index -1)
+        while self.position < frames.len() as f64 {
+            let index = self.position.floor() as isize - 1;
+            let fraction = (self.position - self.position.floor()) as f32;
+
+            let a = if index < 0 { self.previous } else { frames[index as usize] };
+            let b = frames[(index + 1) as usize];
+
+            out.extend(a.iter().zip(b.iter()).map(|(a, b)| a + (b - a) * fraction));
+
+            self.position += self.step;
+        }
+
+        self.position -= frames.len() as f64;
+        if let Some(last) = frames.last() {
+            self.previous = *last;
+        }
+
+        let mut buffer = buffer.lock().unwrap();
+        buffer.extend(out);
+
+        while buffer.len() > MAX_BUFFERED {
+            buffer.pop_front();
+        }
+    }
+}
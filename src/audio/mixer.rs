@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use super::{AudioChunk, CHANNELS, CHUNK_DURATION, CHUNK_SAMPLES, capture::AudioInput};
+use crate::state::StreamState;
+
+/// # Spawn Mixer
+///
+/// Spawns a task that mixes a chunk of every input (with its gain) every `CHUNK_DURATION` and broadcasts it.
+///
+/// Inputs that have not delivered enough samples are padded with silence.
+pub fn spawn_mixer(inputs: Vec<(AudioInput, f32)>, state: Arc<StreamState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHUNK_DURATION);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+
+        loop {
+            ticker.tick().await;
+
+            let mut mixed = vec![0.0f32; CHUNK_SAMPLES * CHANNELS];
+
+            for (input, gain) in &inputs {
+                let mut buffer = input.buffer.lock().unwrap();
+                let available = buffer.len().min(mixed.len());
+
+                for (out, sample) in mixed.iter_mut().zip(buffer.drain(..available)) {
+                    *out += sample * gain;
+                }
+            }
+
+            for sample in mixed.iter_mut() {
+                *sample = sample.clamp(-1.0, 1.0);
+            }
+
+            let _ = state.audio.send(Arc::new(AudioChunk {
+                samples: mixed,
+                timestamp_us: state.timestamp_us(),
+            }));
+        }
+    });
+}
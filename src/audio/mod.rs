@@ -0,0 +1,104 @@
+pub mod capture;
+pub mod mixer;
+pub mod opus;
+
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::state::StreamState;
+
+/// Sample rate everything is converted to before mixing.
+pub const SAMPLE_RATE: u32 = 48_000;
+/// Channels everything is converted to before mixing (interleaved stereo).
+pub const CHANNELS: usize = 2;
+/// Length of a mixed chunk, 20ms is also an Opus frame.
+pub const CHUNK_DURATION: Duration = Duration::from_millis(20);
+/// Samples per channel of a mixed chunk.
+pub const CHUNK_SAMPLES: usize = (SAMPLE_RATE as usize * 20) / 1000;
+
+/// `[audio]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    /// capture what the host is playing (system audio).
+    pub loopback: bool,
+    pub loopback_gain: f32,
+    /// part of the name of the microphone to capture, "default" for the default input device.
+    pub microphone: Option<String>,
+    pub microphone_gain: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            loopback: true,
+            loopback_gain: 1.0,
+            microphone: None,
+            microphone_gain: 1.0,
+        }
+    }
+}
+
+/// A chunk of mixed interleaved stereo samples at `SAMPLE_RATE`.
+pub struct AudioChunk {
+    pub samples: Vec<f32>,
+    /// microseconds on the stream clock the chunk was mixed at.
+    pub timestamp_us: u64,
+}
+
+/// # Spawn Audio Capture
+///
+/// Opens the configured audio sources and spawns the mixer broadcasting mixed chunks to `state.audio`.
+pub fn spawn_audio_capture(config: AudioConfig, state: Arc<StreamState>) {
+    if !config.enabled {
+        return;
+    }
+
+    let mut sources = Vec::new();
+
+    if config.loopback {
+        sources.push((capture::AudioSource::Loopback, config.loopback_gain));
+    }
+
+    if let Some(microphone) = &config.microphone {
+        sources.push((
+            capture::AudioSource::Microphone(microphone.clone()),
+            config.microphone_gain,
+        ));
+    }
+
+    if sources.is_empty() {
+        tracing::warn!("Audio is enabled but no audio source is configured");
+        return;
+    }
+
+    let mut inputs = Vec::new();
+
+    for (source, gain) in sources {
+        match capture::start_source(&source) {
+            Ok(input) => {
+                tracing::info!(device = %input.name, "Capturing audio");
+                inputs.push((input, gain));
+            }
+            Err(e) => {
+                state.stats.error();
+                tracing::error!(%source, error = %e, "Failed to capture audio");
+            }
+        }
+    }
+
+    if inputs.is_empty() {
+        return;
+    }
+
+    state.audio_enabled.store(true, Ordering::Relaxed);
+
+    mixer::spawn_mixer(inputs, state.clone());
+    opus::spawn_opus_encoder(state);
+}
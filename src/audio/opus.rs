@@ -0,0 +1,121 @@
+use std::{convert::Infallible, sync::Arc};
+
+use async_stream::stream;
+use axum::{
+    body::Body,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+use super::{CHANNELS, SAMPLE_RATE};
+use crate::{
+    packets::{PROTOCOL_VERSION, audio_packet},
+    state::StreamState,
+};
+
+/// Largest Opus packet the encoder may produce.
+const MAX_PACKET: usize = 4000;
+
+/// # Spawn Opus Encoder
+///
+/// Spawns a task encoding every mixed chunk with Opus and broadcasting it to `state.audio_packets` (see `packets::audio_packet`).
+pub fn spawn_opus_encoder(state: Arc<StreamState>) {
+    let mut rx = state.audio.subscribe();
+
+    tokio::spawn(async move {
+        let mut encoder = match ::opus::Encoder::new(
+            SAMPLE_RATE,
+            ::opus::Channels::Stereo,
+            ::opus::Application::Audio,
+        ) {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                state.stats.error();
+                tracing::error!(error = %e, "Failed to create the Opus encoder");
+                return;
+            }
+        };
+
+        let mut encoded = vec![0u8; MAX_PACKET];
+        let mut sequence = 0u32;
+
+        loop {
+            let chunk = match rx.recv().await {
+                Ok(chunk) => chunk,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            debug_assert_eq!(chunk.samples.len() % CHANNELS, 0);
+
+            let len = match encoder.encode_float(&chunk.samples, &mut encoded) {
+                Ok(len) => len,
+                Err(e) => {
+                    state.stats.error();
+                    tracing::error!(error = %e, "Opus encoding error");
+                    continue;
+                }
+            };
+
+            let _ = state
+                .audio_packets
+                .send(audio_packet(sequence, chunk.timestamp_us, &encoded[..len]));
+            sequence = sequence.wrapping_add(1);
+        }
+    });
+}
+
+/// # Audio Resolution
+///
+/// Represents a streamed broadcast of the Opus audio packets.
+pub struct AudioResolution {
+    //broadcast channel
+    rx: Receiver<Vec<u8>>,
+    //bytes sent accounting
+    state: Arc<StreamState>,
+}
+
+impl AudioResolution {
+    /// create a new audio resolution from a receiver.
+    pub fn from_receiver(rx: Receiver<Vec<u8>>, state: Arc<StreamState>) -> Self {
+        Self {
+            rx,
+            state,
+        }
+    }
+}
+
+impl IntoResponse for AudioResolution {
+    //streams the packets with the protocol version of their framing
+    fn into_response(self) -> Response {
+        let Self { mut rx, state } = self;
+
+        let content = stream! {
+            loop {
+                let packet = tokio::select! {
+                    packet = rx.recv() => match packet {
+                        Ok(packet) => packet,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = state.shutting_down() => break,
+                };
+                let packet = state.seal_packet(packet);
+
+                state.stats.add_bytes_sent(packet.len());
+
+                yield packet;
+            }
+        };
+
+        (
+            [
+                ("content-type", "application/octet-stream".to_string()),
+                ("x-protocol-version", PROTOCOL_VERSION.to_string()),
+            ],
+            Body::from_stream(content.map(Ok::<_, Infallible>)),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    auth::identity,
+    request_params::client_ip,
+    state::StreamState,
+    viewers::ViewerSession,
+};
+
+/// `[audit]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// file the audit records are appended to, one json object per line. Nothing is audited without it.
+    pub path: Option<String>,
+    /// megabytes the file grows to before it is rotated to `<path>.1`, 0 never rotates it.
+    pub max_mb: u64,
+    /// rotated files kept (`<path>.1` the newest), older ones are deleted.
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_mb: 10,
+            keep: 5,
+        }
+    }
+}
+
+/// A line of the audit file.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    /// utc, rfc 3339 with milliseconds.
+    time: String,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// What is audited, the `event` of the line.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum AuditEvent<'a> {
+    ViewerConnected {
+        viewer: u64,
+        ip: &'a str,
+        identity: Option<&'a str>,
+        container: &'a str,
+        user_agent: Option<&'a str>,
+    },
+    ViewerDisconnected {
+        viewer: u64,
+        ip: &'a str,
+        identity: Option<&'a str>,
+        container: &'a str,
+        connected_secs: f64,
+        bytes_sent: u64,
+        frames_sent: u64,
+        average_kbps: f64,
+    },
+    /// a request changing something, anything but `GET`, `HEAD` and `OPTIONS` under `/api/`.
+    ApiRequest {
+        method: &'a str,
+        path: &'a str,
+        /// the query without the `token`.
+        query: Option<String>,
+        status: u16,
+        ip: &'a str,
+        identity: Option<&'a str>,
+    },
+}
+
+struct AuditFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+/// # Audit Log
+///
+/// An append-only record of who watched the stream and when, and of every request changing it, kept to prove who saw a shared screen.
+///
+/// Note: `Tokens are written as their identity (see auth::identity), never in the clear. A line that cannot be written is logged as an error,
+/// the viewer or the request it is about is not refused.`
+#[derive(Default)]
+pub struct AuditLog {
+    file: Option<Mutex<AuditFile>>,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl AuditLog {
+    /// # Open
+    ///
+    /// Opens the file of `[audit] path` for appending, creating it and its directory. Disabled without a path.
+    pub fn open(config: &AuditConfig) -> std::io::Result<Self> {
+        let Some(path) = config.path.as_deref() else {
+            return Ok(Self::default());
+        };
+
+        let path = PathBuf::from(path);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            file: Some(Mutex::new(AuditFile { path, file, size })),
+            max_bytes: config.max_mb * 1024 * 1024,
+            keep: config.keep,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Records a viewer that started watching.
+    pub fn viewer_connected(&self, session: &ViewerSession) {
+        let client = &session.client;
+
+        self.record(AuditEvent::ViewerConnected {
+            viewer: session.id,
+            ip: &client.ip,
+            identity: client.identity.as_deref(),
+            container: client.container,
+            user_agent: client.user_agent.as_deref(),
+        });
+    }
+
+    /// Records a viewer that stopped watching, with how long it watched and what it was sent.
+    pub fn viewer_disconnected(&self, session: &ViewerSession) {
+        let info = session.info();
+
+        self.record(AuditEvent::ViewerDisconnected {
+            viewer: info.id,
+            ip: &info.ip,
+            identity: info.identity.as_deref(),
+            container: info.container,
+            connected_secs: (info.connected_secs * 10.0).round() / 10.0,
+            bytes_sent: info.bytes_sent,
+            frames_sent: info.frames_sent,
+            average_kbps: (info.average_kbps * 10.0).round() / 10.0,
+        });
+    }
+
+    fn record(&self, event: AuditEvent) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let record = AuditRecord {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            event,
+        };
+        let Ok(mut line) = serde_json::to_vec(&record) else {
+            return;
+        };
+        line.push(b'\n');
+
+        let mut file = file.lock().unwrap();
+
+        if self.max_bytes > 0
+            && file.size > 0
+            && file.size + line.len() as u64 > self.max_bytes
+            && let Err(e) = file.rotate(self.keep)
+        {
+            error!(path = %file.path.display(), error = %e, "Failed to rotate the audit log");
+        }
+
+        match file.file.write_all(&line) {
+            Ok(()) => file.size += line.len() as u64,
+            Err(e) => error!(path = %file.path.display(), error = %e, "Failed to write the audit log"),
+        }
+    }
+}
+
+impl AuditFile {
+    /// Moves `<path>.n` to `<path>.n+1` (deleting the last one kept) and the file to `<path>.1`, then starts a new file.
+    fn rotate(&mut self, keep: usize) -> std::io::Result<()> {
+        let rotated = |n: usize| -> PathBuf {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            name.into()
+        };
+
+        if keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            remove_if_exists(&rotated(keep))?;
+
+            for n in (1..keep).rev() {
+                if rotated(n).exists() {
+                    std::fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// # Audit Requests
+///
+/// Middleware recording the requests under `/api/` that change something (any method but `GET`, `HEAD` and `OPTIONS`)
+/// along with the status they got, those refused included.
+pub async fn audit_requests(State(state): State<Arc<StreamState>>, req: Request, next: Next) -> Response {
+    if !state.audit.enabled()
+        || matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || !req.uri().path().contains("/api/")
+    {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let method = parts.method.to_string();
+    let path = parts.uri.path().to_string();
+    let query = parts.uri.query().map(|query| {
+        query
+            .split('&')
+            .filter(|pair| pair.split_once('=').map_or(*pair, |(key, _)| key) != "token")
+            .collect::<Vec<_>>()
+            .join("&")
+    });
+    let ip = client_ip(&parts);
+    let identity = identity(&parts);
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    state.audit.record(AuditEvent::ApiRequest {
+        method: &method,
+        path: &path,
+        query: query.filter(|query| !query.is_empty()),
+        status: response.status().as_u16(),
+        ip: &ip,
+        identity: identity.as_deref(),
+    });
+
+    response
+}
@@ -0,0 +1,613 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    bytes_resolution::BytesResolution,
+    oidc::{OidcConfig, OidcLogins},
+    request_params::{bearer_token, cookie, header},
+    session::unix_now,
+    state::StreamState,
+};
+
+/// Cookie holding the session of a viewer that logged in with the password, an invite or OpenID Connect.
+pub const SESSION_COOKIE: &str = "share_session";
+
+/// `[auth]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// tokens accepted by the stream and api routes, only to watch once `controller_tokens` or `admin_tokens` are set.
+    pub tokens: Vec<String>,
+    /// tokens that can also control the stream through `/api/*` (pause, quality, fps, scale, recording...).
+    pub controller_tokens: Vec<String>,
+    /// tokens that can also use the admin routes (shutdown, kick, bans...), like the `[admin] token`.
+    pub admin_tokens: Vec<String>,
+    /// argon2 hash of the viewer password (`share-screen --hash-password <password>`), `/` shows a login form when set.
+    pub password_hash: Option<String>,
+    /// hours a login stays valid.
+    pub session_hours: u64,
+    /// minutes an invite link can be used before it expires.
+    pub invite_minutes: u64,
+    /// `[auth.oidc]` login with an OpenID Connect provider, `/` redirects to it when set.
+    pub oidc: OidcConfig,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            tokens: Vec::new(),
+            controller_tokens: Vec::new(),
+            admin_tokens: Vec::new(),
+            password_hash: None,
+            session_hours: 24,
+            invite_minutes: 60,
+            oidc: OidcConfig::default(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Whether the tokens have roles, the `tokens` and the logins only watch the stream then.
+    fn roles_enabled(&self) -> bool {
+        !self.controller_tokens.is_empty() || !self.admin_tokens.is_empty()
+    }
+
+    fn token_role(&self, token: &str) -> Option<Role> {
+        let listed = |tokens: &[String]| tokens.iter().any(|valid| constant_time_eq(valid, token));
+
+        if listed(&self.admin_tokens) {
+            Some(Role::Admin)
+        } else if listed(&self.controller_tokens) {
+            Some(Role::Controller)
+        } else if listed(&self.tokens) {
+            Some(self.member_role())
+        } else {
+            None
+        }
+    }
+
+    //the viewers control the stream as well until roles are configured
+    fn member_role(&self) -> Role {
+        match self.roles_enabled() {
+            true => Role::Viewer,
+            false => Role::Controller,
+        }
+    }
+}
+
+/// # Role
+///
+/// What a request is allowed to do, each role can do everything the ones before it can.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Role {
+    /// watches the streams, the snapshots and the status.
+    Viewer,
+    /// controls the stream through `/api/*`: pause, quality, fps, scale, recording...
+    Controller,
+    /// uses the admin routes: shutdown, kick, bans, invites...
+    Admin,
+}
+
+/// # Auth
+///
+/// Access control of the stream routes (`/stream`, `/snapshot.jpg`, `/api/*`...), requests carry a token
+/// as `Authorization: Bearer <token>` or `?token=`, or the signed session cookie set by `/login` or `/login/oidc/callback`.
+///
+/// The routes are open to anyone when neither tokens, a password nor OpenID Connect are configured.
+///
+/// Note: `The admin routes check the [admin] token or the admin_tokens instead. The config is swapped when the config file is reloaded.`
+pub struct Auth {
+    config: RwLock<AuthConfig>,
+    /// signs the session cookies, generated per run so a restart logs everyone out.
+    secret: [u8; 32],
+    /// unused invite codes and the unix time they expire at.
+    invites: Mutex<HashMap<String, u64>>,
+    oidc: OidcLogins,
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            secret: rand::random(),
+            invites: Mutex::new(HashMap::new()),
+            oidc: OidcLogins::default(),
+        }
+    }
+
+    /// Replaces the tokens, the password and the durations, the sessions and the invites already handed out stay valid.
+    pub fn reload(&self, config: AuthConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Whether requests have to be authenticated.
+    pub fn enabled(&self) -> bool {
+        let config = self.config.read().unwrap();
+
+        !config.tokens.is_empty() || config.roles_enabled() || config.password_hash.is_some() || config.oidc.enabled()
+    }
+
+    /// Whether `[auth] admin_tokens` are set, the admin routes accept them along with the `[admin] token`.
+    pub fn admin_tokens_enabled(&self) -> bool {
+        !self.config.read().unwrap().admin_tokens.is_empty()
+    }
+
+    /// Whether viewers log in with a password.
+    pub fn password_enabled(&self) -> bool {
+        self.config.read().unwrap().password_hash.is_some()
+    }
+
+    /// Whether viewers log in with the OpenID Connect provider of `[auth.oidc]`.
+    pub fn oidc_enabled(&self) -> bool {
+        self.config.read().unwrap().oidc.enabled()
+    }
+
+    /// The current `[auth.oidc]`.
+    pub fn oidc_config(&self) -> OidcConfig {
+        self.config.read().unwrap().oidc.clone()
+    }
+
+    /// The pending OpenID Connect logins and the cached provider.
+    pub fn oidc(&self) -> &OidcLogins {
+        &self.oidc
+    }
+
+    /// Whether the request may access the stream routes.
+    pub fn authorized(&self, req: &Parts) -> bool {
+        self.role(req).is_some()
+    }
+
+    /// Whether the request has the role or one above it.
+    pub fn has_role(&self, req: &Parts, role: Role) -> bool {
+        self.role(req).is_some_and(|granted| granted >= role)
+    }
+
+    /// # Role
+    ///
+    /// The role of the token of the request, the logged in viewers have the one of the `[auth] tokens`.
+    /// None when the request is not authenticated.
+    ///
+    /// Note: `Without any token nor password everyone controls the stream, as without roles the tokens and the logins do.`
+    pub fn role(&self, req: &Parts) -> Option<Role> {
+        if !self.enabled() {
+            return Some(Role::Controller);
+        }
+
+        if let Some(role) = bearer_token(req).and_then(|token| self.token_role(&token)) {
+            return Some(role);
+        }
+
+        cookie(req, SESSION_COOKIE)
+            .is_some_and(|session| self.valid_session(&session))
+            .then(|| self.config.read().unwrap().member_role())
+    }
+
+    /// The role of the token, none when it is not one of the tokens of `[auth]`.
+    pub fn token_role(&self, token: &str) -> Option<Role> {
+        self.config.read().unwrap().token_role(token)
+    }
+
+    /// # Find Token
+    ///
+    /// The role of the first token of `[auth]` the check accepts, for the protocols that prove a token without sending it
+    /// (the Digest authentication of RTSP). None when no token is accepted.
+    pub fn find_token(&self, check: impl Fn(&str) -> bool) -> Option<Role> {
+        let config = self.config.read().unwrap();
+
+        [&config.admin_tokens, &config.controller_tokens, &config.tokens]
+            .into_iter()
+            .flatten()
+            .find(|token| check(token))
+            .and_then(|token| config.token_role(token))
+    }
+
+    /// Whether the token is one of the tokens of `[auth]`.
+    pub fn valid_token(&self, token: &str) -> bool {
+        self.token_role(token).is_some()
+    }
+
+    /// Checks the password against the configured hash.
+    pub fn verify_password(&self, password: &str) -> bool {
+        //not held while hashing
+        let Some(hash) = self.config.read().unwrap().password_hash.clone() else {
+            return false;
+        };
+
+        match PasswordHash::new(&hash) {
+            Ok(parsed) => Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            Err(e) => {
+                tracing::error!(error = %e, "Invalid [auth] password_hash");
+                false
+            }
+        }
+    }
+
+    /// # Create Session
+    ///
+    /// A new session cookie value, `<expiry>.<signature>` where the signature is the HMAC of the unix expiry.
+    pub fn create_session(&self) -> String {
+        let expires = unix_now() + self.config.read().unwrap().session_hours.max(1) * 3600;
+
+        self.signed(expires.to_string())
+    }
+
+    /// A new session cookie value naming who logged in, `<expiry>.<hex identity>.<signature>` where the signature covers both.
+    pub fn create_identified_session(&self, identity: &str) -> String {
+        let expires = unix_now() + self.config.read().unwrap().session_hours.max(1) * 3600;
+
+        self.signed(format!("{expires}.{}", hex(identity.as_bytes())))
+    }
+
+    /// Whether the session cookie value was signed by this run and has not expired.
+    pub fn valid_session(&self, session: &str) -> bool {
+        let Some((payload, signature)) = session.rsplit_once('.') else {
+            return false;
+        };
+        let expires = payload.split('.').next().unwrap_or_default();
+        let (Ok(expires), Some(signature)) = (expires.parse::<u64>(), unhex(signature)) else {
+            return false;
+        };
+
+        expires > unix_now() && self.mac(payload).verify_slice(&signature).is_ok()
+    }
+
+    /// `Set-Cookie` value of a session.
+    pub fn session_cookie(&self, session: &str) -> String {
+        format!(
+            "{SESSION_COOKIE}={session}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+            self.config.read().unwrap().session_hours.max(1) * 3600
+        )
+    }
+
+    /// # Create Invite
+    ///
+    /// A random code for `/join/<code>`, usable once within `invite_minutes`. Returns the code and the unix time it expires at.
+    pub fn create_invite(&self) -> (String, u64) {
+        let code = hex(&rand::random::<[u8; 16]>());
+        let expires = unix_now() + self.config.read().unwrap().invite_minutes.max(1) * 60;
+
+        let mut invites = self.invites.lock().unwrap();
+        //forget the invites nobody used
+        let now = unix_now();
+        invites.retain(|_, expires| *expires > now);
+        invites.insert(code.clone(), expires);
+
+        (code, expires)
+    }
+
+    /// Uses up the invite, whether it existed and had not expired.
+    pub fn redeem_invite(&self, code: &str) -> bool {
+        self.invites
+            .lock()
+            .unwrap()
+            .remove(code)
+            .is_some_and(|expires| expires > unix_now())
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("hmac takes keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    //the payload followed by its signature
+    fn signed(&self, payload: String) -> String {
+        let signature = hex(&self.mac(&payload).finalize().into_bytes());
+
+        format!("{payload}.{signature}")
+    }
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self::new(AuthConfig::default())
+    }
+}
+
+/// # Hash Password
+///
+/// Hashes a password for the `password_hash` of the config.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// # Identity
+///
+/// Who sent the request as the audit and the viewer list show it without the secret: `token:<fingerprint>` (the start of the sha256
+/// of the token, the same for every request of the token), `oidc:<email>` for an OpenID Connect session, `login` for a password
+/// or invite session, none for an anonymous request.
+///
+/// Note: `The signature of the session is checked by the guard of the route, not here.`
+pub fn identity(req: &Parts) -> Option<String> {
+    if let Some(token) = bearer_token(req) {
+        return Some(token_identity(&token));
+    }
+
+    let session = cookie(req, SESSION_COOKIE)?;
+    let named = session
+        .split('.')
+        .nth(1)
+        .filter(|_| session.matches('.').count() == 2)
+        .and_then(unhex)
+        .and_then(|identity| String::from_utf8(identity).ok());
+
+    match named {
+        Some(identity) => Some(format!("oidc:{identity}")),
+        None => Some("login".to_string()),
+    }
+}
+
+/// The `token:<fingerprint>` identity of a token.
+pub fn token_identity(token: &str) -> String {
+    format!("token:{}", hex(&Sha256::digest(token.as_bytes())[..4]))
+}
+
+/// # Guard
+///
+/// Checked first by every protected route, the err is the `401` response to return for requests that are not authenticated.
+pub fn guard(state: &StreamState, req: &Parts) -> Result<(), Response> {
+    if state.auth.authorized(req) {
+        Ok(())
+    } else {
+        Err(BytesResolution::text(401, "Log in or provide a valid token").into_response())
+    }
+}
+
+/// # Control Guard
+///
+/// Checked first by the routes changing the stream (pause, quality, recording...), a viewer token gets a `403`.
+pub fn control_guard(state: &StreamState, req: &Parts) -> Result<(), Response> {
+    guard(state, req)?;
+
+    if state.auth.has_role(req, Role::Controller) {
+        Ok(())
+    } else {
+        Err(BytesResolution::text(403, "This token can only watch the stream, controlling it needs a controller token").into_response())
+    }
+}
+
+/// # Login Routes
+///
+/// The login routes used by the password form of the viewer page:
+///
+/// - `POST /login` - the password is sent in the `X-Password` header, sets the session cookie when it matches
+/// - `POST /logout` - clears the session cookie
+/// - `GET /join/{code}` - redeems an invite, sets the session cookie and redirects to the viewer page
+pub fn login_routes() -> Router<Arc<StreamState>> {
+    Router::new()
+        .route("/login", post(login))
+        .route("/join/{code}", get(join))
+        .route("/logout", post(logout))
+}
+
+async fn login(State(state): State<Arc<StreamState>>, req: Parts) -> Response {
+    if !state.auth.password_enabled() {
+        return BytesResolution::text(404, "Password login is disabled").into_response();
+    }
+
+    let password = header(&req, "X-Password").unwrap_or_default();
+
+    if !state.auth.verify_password(&password) {
+        tracing::warn!("Failed login attempt");
+        return BytesResolution::text(401, "Wrong password").into_response();
+    }
+
+    let session = state.auth.create_session();
+
+    BytesResolution::text(200, "Logged in")
+        .with_header("Set-Cookie", state.auth.session_cookie(&session))
+        .into_response()
+}
+
+async fn join(State(state): State<Arc<StreamState>>, Path(code): Path<String>) -> Response {
+    if !state.auth.redeem_invite(&code) {
+        return BytesResolution::text(410, "This invite was already used or has expired").into_response();
+    }
+
+    tracing::info!("Invite redeemed");
+    let session = state.auth.create_session();
+
+    BytesResolution::text(303, "Joined")
+        .with_header("Set-Cookie", state.auth.session_cookie(&session))
+        .with_header("Location", "/")
+        .into_response()
+}
+
+async fn logout() -> Response {
+    BytesResolution::text(200, "Logged out")
+        .with_header("Set-Cookie", format!("{SESSION_COOKIE}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict"))
+        .into_response()
+}
+
+/// Compares the secrets in a time that depends on their length only, not on how much of them matches.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_sessions_it_signed() {
+        let auth = Auth::default();
+        let session = auth.create_session();
+
+        assert!(auth.valid_session(&session));
+    }
+
+    #[test]
+    fn refuses_the_sessions_of_another_run() {
+        let session = Auth::default().create_session();
+
+        assert!(!Auth::default().valid_session(&session));
+    }
+
+    #[test]
+    fn refuses_tampered_sessions() {
+        let auth = Auth::default();
+        let session = auth.create_session();
+        let (expires, signature) = session.split_once('.').unwrap();
+
+        let extended = format!("{}.{signature}", expires.parse::<u64>().unwrap() + 3600);
+        assert!(!auth.valid_session(&extended));
+
+        let mut flipped = signature.to_string();
+        flipped.replace_range(..1, if signature.starts_with('0') { "1" } else { "0" });
+        assert!(!auth.valid_session(&format!("{expires}.{flipped}")));
+    }
+
+    #[test]
+    fn refuses_expired_and_malformed_sessions() {
+        let auth = Auth::default();
+
+        assert!(!auth.valid_session(&auth.signed((unix_now() - 1).to_string())));
+        assert!(!auth.valid_session(""));
+        assert!(!auth.valid_session("no-signature"));
+        assert!(!auth.valid_session(&format!("{}.not-hex", unix_now() + 3600)));
+        assert!(!auth.valid_session(&format!("soon.{}", hex(&[0; 32]))));
+    }
+
+    #[test]
+    fn sets_the_session_for_its_hours() {
+        let auth = Auth::new(AuthConfig {
+            session_hours: 2,
+            ..Default::default()
+        });
+        let cookie = auth.session_cookie("value");
+
+        assert!(cookie.starts_with(&format!("{SESSION_COOKIE}=value;")));
+        assert!(cookie.contains("Max-Age=7200"));
+        assert!(cookie.contains("HttpOnly"));
+    }
+
+    fn request(token: Option<&str>, session: Option<&str>) -> Parts {
+        let mut builder = axum::http::Request::builder().uri("/api/status");
+        if let Some(token) = token {
+            builder = builder.header("Authorization", format!("Bearer {token}"));
+        }
+        if let Some(session) = session {
+            builder = builder.header("Cookie", format!("{SESSION_COOKIE}={session}"));
+        }
+
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    fn with_roles() -> Auth {
+        Auth::new(AuthConfig {
+            tokens: vec!["viewer-token".to_string()],
+            controller_tokens: vec!["controller-token".to_string()],
+            admin_tokens: vec!["admin-token".to_string()],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn everyone_controls_without_auth() {
+        let auth = Auth::default();
+
+        assert_eq!(auth.role(&request(None, None)), Some(Role::Controller));
+        assert!(!auth.has_role(&request(None, None), Role::Admin));
+    }
+
+    #[test]
+    fn the_tokens_control_until_roles_are_set() {
+        let auth = Auth::new(AuthConfig {
+            tokens: vec!["token".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(auth.role(&request(Some("token"), None)), Some(Role::Controller));
+        assert_eq!(auth.role(&request(Some("other"), None)), None);
+        assert_eq!(auth.role(&request(None, None)), None);
+    }
+
+    #[test]
+    fn gives_each_token_its_role() {
+        let auth = with_roles();
+
+        assert_eq!(auth.role(&request(Some("viewer-token"), None)), Some(Role::Viewer));
+        assert_eq!(auth.role(&request(Some("controller-token"), None)), Some(Role::Controller));
+        assert_eq!(auth.role(&request(Some("admin-token"), None)), Some(Role::Admin));
+        assert_eq!(auth.role(&request(Some("admin-token-2"), None)), None);
+
+        let query = axum::http::Request::builder().uri("/stream?token=controller-token").body(()).unwrap().into_parts().0;
+        assert_eq!(auth.role(&query), Some(Role::Controller));
+    }
+
+    #[test]
+    fn each_role_has_the_ones_below_it() {
+        let auth = with_roles();
+        let viewer = request(Some("viewer-token"), None);
+        let controller = request(Some("controller-token"), None);
+        let admin = request(Some("admin-token"), None);
+
+        assert!(auth.has_role(&viewer, Role::Viewer) && !auth.has_role(&viewer, Role::Controller));
+        assert!(auth.has_role(&controller, Role::Controller) && !auth.has_role(&controller, Role::Admin));
+        assert!(auth.has_role(&admin, Role::Viewer) && auth.has_role(&admin, Role::Admin));
+        assert!(!auth.has_role(&request(None, None), Role::Viewer));
+    }
+
+    #[test]
+    fn the_logins_watch_once_roles_are_set() {
+        let auth = with_roles();
+        let session = auth.create_session();
+
+        assert_eq!(auth.role(&request(None, Some(&session))), Some(Role::Viewer));
+        assert_eq!(auth.role(&request(None, Some("forged.00"))), None);
+    }
+
+    #[test]
+    fn signs_the_identity_of_oidc_sessions() {
+        let auth = Auth::default();
+        let session = auth.create_identified_session("viewer@example.com");
+
+        assert!(auth.valid_session(&session));
+        assert_eq!(identity(&request(None, Some(&session))).as_deref(), Some("oidc:viewer@example.com"));
+        assert_eq!(identity(&request(None, Some(&auth.create_session()))).as_deref(), Some("login"));
+
+        let mut parts = session.split('.');
+        let (expires, _, signature) = (parts.next().unwrap(), parts.next(), parts.next().unwrap());
+        let renamed = format!("{expires}.{}.{signature}", hex(b"admin@example.com"));
+        assert!(!auth.valid_session(&renamed));
+    }
+}
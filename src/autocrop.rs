@@ -0,0 +1,254 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Pixels a border may move by between frames and still be the same border, compression noise of videos moves the edges a little.
+const TOLERANCE: u32 = 2;
+/// Pixels sampled across a row or a column, to detect the borders without reading every pixel.
+const SAMPLES: u32 = 64;
+
+/// `[autocrop]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AutoCropConfig {
+    pub enabled: bool,
+    /// brightest channel value (0-255) still counted as black, the bars of compressed videos are rarely pure black.
+    pub threshold: u8,
+    /// time a new border has to stay before the frames are cropped to it, so dark scenes are not cropped.
+    pub stable_seconds: f64,
+    /// narrowest bar cropped, in pixels, thinner dark edges are kept.
+    pub min_bar: u32,
+}
+
+impl Default for AutoCropConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 24,
+            stable_seconds: 2.0,
+            min_bar: 16,
+        }
+    }
+}
+
+/// Area of a frame, in pixels.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn full(width: u32, height: u32) -> Self {
+        Self { x: 0, y: 0, width, height }
+    }
+
+    /// Whether the edges are within `TOLERANCE` of the other ones.
+    fn is_close(&self, other: &Rect) -> bool {
+        let close = |a: u32, b: u32| a.abs_diff(b) <= TOLERANCE;
+
+        close(self.x, other.x)
+            && close(self.y, other.y)
+            && close(self.x + self.width, other.x + other.width)
+            && close(self.y + self.height, other.y + other.height)
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && self.x + self.width >= other.x + other.width
+            && self.y + self.height >= other.y + other.height
+    }
+}
+
+struct CropState {
+    //dimensions of the frames the crop was detected on
+    frame: (u32, u32),
+    current: Rect,
+    //a different border seen since
+    candidate: Option<(Rect, Instant)>,
+}
+
+/// # Auto Crop
+///
+/// Detects the constant letterbox and pillarbox bars of the frames and crops them before encoding,
+/// a 21:9 video shared on a 16:9 monitor does not spend a third of the stream on black pixels.
+///
+/// Note: `Hysteresis: a larger border is only cropped once it stayed for stable_seconds (a dark scene does not crop the frame),
+/// a smaller one is followed at the next frame so content is never hidden. Fully dark frames keep the current crop.`
+pub struct AutoCrop {
+    enabled: bool,
+    threshold: u8,
+    stable: Duration,
+    min_bar: u32,
+    state: Mutex<Option<CropState>>,
+}
+
+impl AutoCrop {
+    pub fn new(config: &AutoCropConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            threshold: config.threshold,
+            stable: Duration::from_secs_f64(config.stable_seconds.max(0.0)),
+            min_bar: config.min_bar,
+            state: Mutex::new(None),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The area the frames are currently cropped to, `None` when they are not.
+    pub fn current(&self) -> Option<Rect> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .as_ref()
+            .filter(|state| state.current != Rect::full(state.frame.0, state.frame.1))
+            .map(|state| state.current)
+    }
+
+    /// # Apply
+    ///
+    /// The frame without its bars along with its dimensions, unchanged when disabled or when the frame does not match the dimensions.
+    pub fn apply(&self, frame: Vec<u8>, width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+        if !self.enabled || width == 0 || height == 0 || frame.len() != width as usize * height as usize * 4 {
+            return (frame, width, height);
+        }
+
+        let crop = self.update(&frame, width, height);
+
+        if crop == Rect::full(width, height) {
+            return (frame, width, height);
+        }
+
+        let mut cropped = Vec::with_capacity(crop.width as usize * crop.height as usize * 4);
+
+        for row in crop.y..crop.y + crop.height {
+            let start = (row as usize * width as usize + crop.x as usize) * 4;
+            cropped.extend_from_slice(&frame[start..start + crop.width as usize * 4]);
+        }
+
+        (cropped, crop.width, crop.height)
+    }
+
+    /// # To Uncropped
+    ///
+    /// A position over the cropped frame, from 0.0 (left, top) to 1.0 (right, bottom), as a position over the whole frame.
+    pub fn to_uncropped(&self, x: f64, y: f64) -> (f64, f64) {
+        let state = self.state.lock().unwrap();
+
+        let Some(state) = state.as_ref().filter(|_| self.enabled) else {
+            return (x, y);
+        };
+
+        let (width, height) = (state.frame.0.max(1) as f64, state.frame.1.max(1) as f64);
+        let crop = state.current;
+
+        (
+            (crop.x as f64 + x * crop.width as f64) / width,
+            (crop.y as f64 + y * crop.height as f64) / height,
+        )
+    }
+
+    /// Detects the border of the frame and returns the area to crop to, following the hysteresis.
+    fn update(&self, frame: &[u8], width: u32, height: u32) -> Rect {
+        let detected = self.detect(frame, width, height);
+        let mut state = self.state.lock().unwrap();
+
+        //a new resolution starts over uncropped
+        let state = match state.as_mut() {
+            Some(current) if current.frame == (width, height) => current,
+            _ => state.insert(CropState {
+                frame: (width, height),
+                current: Rect::full(width, height),
+                candidate: None,
+            }),
+        };
+
+        let Some(detected) = detected else {
+            return state.current;
+        };
+
+        if detected.is_close(&state.current) {
+            state.candidate = None;
+        } else if detected.contains(&state.current) {
+            state.candidate = None;
+            self.switch(state, detected);
+        } else {
+            match state.candidate {
+                Some((candidate, since)) if candidate.is_close(&detected) => {
+                    if since.elapsed() >= self.stable {
+                        state.candidate = None;
+                        self.switch(state, detected);
+                    }
+                }
+                _ => state.candidate = Some((detected, Instant::now())),
+            }
+        }
+
+        state.current
+    }
+
+    fn switch(&self, state: &mut CropState, crop: Rect) {
+        state.current = crop;
+
+        info!(
+            x = crop.x,
+            y = crop.y,
+            width = crop.width,
+            height = crop.height,
+            "Cropping the black bars of the frames"
+        );
+    }
+
+    /// The area inside the dark bars, `None` for a frame dark everywhere.
+    fn detect(&self, frame: &[u8], width: u32, height: u32) -> Option<Rect> {
+        let dark = |x: u32, y: u32| {
+            let offset = (y as usize * width as usize + x as usize) * 4;
+            frame[offset..offset + 3].iter().all(|channel| *channel <= self.threshold)
+        };
+
+        let row_step = (width / SAMPLES).max(1);
+        let column_step = (height / SAMPLES).max(1);
+        let row_dark = |y: u32| (0..width).step_by(row_step as usize).all(|x| dark(x, y));
+
+        let top = (0..height).find(|y| !row_dark(*y))?;
+        let bottom = (top..height).rev().find(|y| !row_dark(*y)).unwrap_or(top);
+
+        let column_dark = |x: u32| (top..=bottom).step_by(column_step as usize).all(|y| dark(x, y));
+
+        let left = (0..width).find(|x| !column_dark(*x)).unwrap_or(0);
+        let right = (left..width).rev().find(|x| !column_dark(*x)).unwrap_or(width - 1);
+
+        //thin dark edges (a taskbar, a window border) are not bars
+        let bar = |size: u32| if size < self.min_bar { 0 } else { size };
+        let (top, left) = (bar(top), bar(left));
+        let (bottom, right) = (bar(height - 1 - bottom), bar(width - 1 - right));
+
+        //even offsets and dimensions, the video containers expect them
+        let (x, y) = (left & !1, top & !1);
+        let crop_width = (width - x - right).max(2) & !1;
+        let crop_height = (height - y - bottom).max(2) & !1;
+
+        Some(Rect {
+            x,
+            y,
+            width: crop_width.min(width - x),
+            height: crop_height.min(height - y),
+        })
+    }
+}
+
+impl Default for AutoCrop {
+    fn default() -> Self {
+        Self::new(&AutoCropConfig::default())
+    }
+}
@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, time::Duration};
+
+use rav1e::prelude::{ChromaSampling, Config, Context, EncoderConfig, EncoderStatus, FrameType, Rational, SpeedSettings};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::{
+    error::{Result, ShareScreenError},
+    frame_compressor::packed_frame,
+    transcoder::{EncodedPacket, FrameQueue, Transcoder, pts_of},
+};
+
+/// Frame rate the rate control of the encoder assumes, the frames keep the timestamps they were captured at.
+const ASSUMED_FPS: u64 = 30;
+/// The temporal delimiter OBU starting every packet of the encoder, Matroska blocks leave it out.
+const TEMPORAL_DELIMITER: [u8; 2] = [0x12, 0x00];
+
+/// `[recording.av1]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Av1Config {
+    /// speed preset of rav1e, from 0 (smallest files, far too slow for a live capture) to 10 (fastest).
+    pub speed: u8,
+    /// quantizer of the frames, from 0 (lossless) to 255, lower keeps more detail for more bytes.
+    pub quantizer: u8,
+    /// most frames between two keyframes, a player seeks to the keyframe before the time asked for.
+    pub keyframe_interval: u64,
+    /// encoding threads, 0 uses every core.
+    pub threads: usize,
+}
+
+impl Default for Av1Config {
+    fn default() -> Self {
+        Self {
+            speed: 10,
+            quantizer: 100,
+            keyframe_interval: 300,
+            threads: 0,
+        }
+    }
+}
+
+/// # Spawn Av1
+///
+/// A transcoder encoding the raw frames to AV1 with rav1e, for the recordings trading cpu for bytes: a desktop recording comes out
+/// a fraction of the size of its JPEG frames. An err when rav1e refuses the settings or the size.
+pub fn spawn_av1(width: u32, height: u32, config: &Av1Config) -> Result<Transcoder> {
+    let mut encoder = EncoderConfig {
+        width: width as usize,
+        height: height as usize,
+        bit_depth: 8,
+        chroma_sampling: ChromaSampling::Cs420,
+        speed_settings: SpeedSettings::from_preset(config.speed.min(10)),
+        quantizer: config.quantizer as usize,
+        time_base: Rational::new(1, ASSUMED_FPS),
+        max_key_frame_interval: config.keyframe_interval.max(1),
+        //no frames reordered, a frame is written once the next few are in
+        low_latency: true,
+        ..Default::default()
+    };
+    encoder.min_key_frame_interval = encoder.min_key_frame_interval.min(encoder.max_key_frame_interval);
+
+    let context: Context<u8> = Config::new()
+        .with_encoder_config(encoder)
+        .with_threads(config.threads)
+        .new_context()
+        .map_err(|e| ShareScreenError::Encode(format!("AV1 encoder for {width}x{height}: {e}")))?;
+
+    let codec_config = context.container_sequence_header();
+
+    Transcoder::spawn("av1", width, height, codec_config, move |frames, packets| {
+        encode(context, frames, packets, width, height)
+    })
+}
+
+/// Encodes the frames received until the transcoder is finished or dropped, then flushes the encoder.
+fn encode(mut context: Context<u8>, frames: FrameQueue, packets: mpsc::UnboundedSender<EncodedPacket>, width: u32, height: u32) {
+    //the pts of the frames sent, by their number, until their packet comes out
+    let mut sent = VecDeque::new();
+    let mut frame_number = 0u64;
+
+    for (frame, pts) in frames {
+        let Some(raw_bgra) = packed_frame(&frame.data, width, height) else {
+            continue;
+        };
+
+        let (y, u, v) = bgra_to_i420(&raw_bgra, width as usize, height as usize);
+        let chroma_width = (width as usize).div_ceil(2);
+
+        let mut input = context.new_frame();
+        input.planes[0].copy_from_raw_u8(&y, width as usize, 1);
+        input.planes[1].copy_from_raw_u8(&u, chroma_width, 1);
+        input.planes[2].copy_from_raw_u8(&v, chroma_width, 1);
+
+        if let Err(e) = context.send_frame(input) {
+            error!(error = ?e, "The AV1 encoder refused a frame");
+            return;
+        }
+
+        sent.push_back((frame_number, pts));
+        frame_number += 1;
+
+        if !drain(&mut context, &mut sent, &packets) {
+            return;
+        }
+    }
+
+    context.flush();
+    drain(&mut context, &mut sent, &packets);
+}
+
+/// Sends the packets the encoder has ready, false when the encoder failed or the recording is gone.
+fn drain(
+    context: &mut Context<u8>,
+    sent: &mut VecDeque<(u64, Duration)>,
+    packets: &mpsc::UnboundedSender<EncodedPacket>,
+) -> bool {
+    loop {
+        match context.receive_packet() {
+            Ok(packet) => {
+                let Some(pts) = pts_of(sent, packet.input_frameno) else {
+                    continue;
+                };
+
+                let keyframe = packet.frame_type == FrameType::KEY;
+                let data = match packet.data.starts_with(&TEMPORAL_DELIMITER) {
+                    true => packet.data[TEMPORAL_DELIMITER.len()..].to_vec(),
+                    false => packet.data,
+                };
+
+                let packet = EncodedPacket { data, pts, keyframe };
+
+                if packets.send(packet).is_err() {
+                    return false;
+                }
+            }
+            Err(EncoderStatus::Encoded) => continue,
+            Err(EncoderStatus::NeedMoreData | EncoderStatus::LimitReached) => return true,
+            Err(e) => {
+                error!(error = ?e, "The AV1 encoder failed");
+                return false;
+            }
+        }
+    }
+}
+
+/// # BGRA to I420
+///
+/// Converts a packed BGRA frame to the planes of 4:2:0 YCbCr (BT.601, limited range), each chroma sample the average of its 2x2 block.
+fn bgra_to_i420(raw_bgra: &[u8], width: usize, height: usize) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+    let mut y = Vec::with_capacity(width * height);
+    let mut u = vec![0u8; chroma_width * chroma_height];
+    let mut v = vec![0u8; chroma_width * chroma_height];
+    let mut sums = vec![(0i32, 0i32, 0i32, 0i32); chroma_width];
+
+    for (row, pixels) in raw_bgra.chunks_exact(width * 4).enumerate() {
+        for (column, bgra) in pixels.chunks_exact(4).enumerate() {
+            let (b, g, r) = (bgra[0] as i32, bgra[1] as i32, bgra[2] as i32);
+            y.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+
+            let sum = &mut sums[column / 2];
+            *sum = (sum.0 + r, sum.1 + g, sum.2 + b, sum.3 + 1);
+        }
+
+        //the last row of an odd height closes its block alone
+        if row % 2 == 1 || row == height - 1 {
+            let target = row / 2 * chroma_width;
+
+            for (column, &(r, g, b, count)) in sums.iter().enumerate() {
+                let (r, g, b) = (r / count, g / count, b / count);
+                u[target + column] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+                v[target + column] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+            }
+
+            sums.fill((0, 0, 0, 0));
+        }
+    }
+
+    (y, u, v)
+}
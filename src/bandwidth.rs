@@ -0,0 +1,188 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::state::StreamState;
+
+/// How often the outbound bandwidth is measured.
+const GOVERNOR_INTERVAL: Duration = Duration::from_secs(2);
+/// Quality taken off (or given back) per step.
+const QUALITY_STEP: u8 = 10;
+/// Part of the fps taken off per step once the quality reached its minimum.
+const FPS_STEP: f64 = 0.25;
+/// Fps from which an unlimited fps is given back as unlimited.
+const UNLIMITED_FROM_FPS: u32 = 60;
+/// Part of the budget the egress has to get under before the settings are raised again, so they do not bounce around the budget.
+const RECOVER_BELOW: f64 = 0.7;
+
+/// `[bandwidth]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    /// total bytes sent to all the viewers, in megabits per second, 0 is unlimited.
+    pub max_mbps: f64,
+    /// lowest quality the stream is brought down to before the fps is.
+    pub min_quality: u8,
+    /// lowest fps the stream is brought down to.
+    pub min_fps: u32,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            max_mbps: 0.0,
+            min_quality: 30,
+            min_fps: 5,
+        }
+    }
+}
+
+/// Settings of the stream the governor brings down and back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Settings {
+    quality: u8,
+    //0 is unlimited
+    fps: u32,
+}
+
+impl Settings {
+    fn of(state: &StreamState) -> Self {
+        Self {
+            quality: state.control.quality(),
+            fps: state.control.max_fps(),
+        }
+    }
+
+    fn apply(self, state: &StreamState) {
+        state.control.set_quality(self.quality);
+        state.control.set_max_fps(self.fps);
+    }
+}
+
+/// # Spawn Bandwidth Governor
+///
+/// Spawns a task that keeps the total egress to the viewers under `[bandwidth] max_mbps`, so a share does not saturate
+/// the uplink of the host (and the call it is part of): over the budget the quality is lowered down to `min_quality`,
+/// then the fps down to `min_fps`. Once well under the budget they are raised back, the fps first, up to where they were.
+///
+/// Note: `Changing the quality or the fps while throttled (/api/quality, /api/fps) makes them the settings restored to.`
+pub fn spawn_bandwidth_governor(config: BandwidthConfig, state: Arc<StreamState>) {
+    if config.max_mbps <= 0.0 {
+        return;
+    }
+
+    let budget = config.max_mbps * 1_000_000.0 / 8.0;
+    let min_quality = config.min_quality.clamp(1, 100);
+    let min_fps = config.min_fps.max(1);
+
+    tokio::spawn(async move {
+        let mut previous_bytes = state.stats.bytes_sent();
+        let mut previous_frames = state.pipeline.snapshot();
+        let mut measured = Instant::now();
+        //the settings before throttling and the ones last set by the governor, while throttled
+        let mut throttled: Option<(Settings, Settings)> = None;
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(GOVERNOR_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+
+            let bytes = state.stats.bytes_sent();
+            let frames = state.pipeline.snapshot();
+            let rate = bytes.saturating_sub(previous_bytes) as f64 / measured.elapsed().as_secs_f64().max(f64::EPSILON);
+            let encode_fps = frames.rates_since(&previous_frames).encode_fps;
+
+            previous_bytes = bytes;
+            previous_frames = frames;
+            measured = Instant::now();
+
+            let current = Settings::of(&state);
+
+            //changed by hand since the last step, the new target to restore to
+            if let Some((original, set)) = &mut throttled
+                && current != *set
+            {
+                *original = current;
+                *set = current;
+            }
+
+            if rate > budget {
+                let lowered = lower(current, encode_fps, min_quality, min_fps);
+
+                if lowered != current {
+                    let original = throttled.map_or(current, |(original, _)| original);
+                    lowered.apply(&state);
+                    throttled = Some((original, lowered));
+
+                    warn!(
+                        mbps = format_args!("{:.1}", rate * 8.0 / 1_000_000.0),
+                        quality = lowered.quality,
+                        fps = lowered.fps,
+                        "Over the bandwidth budget, lowering the stream"
+                    );
+                }
+            } else if rate < budget * RECOVER_BELOW
+                && let Some((original, set)) = throttled
+            {
+                let raised = raise(set, original);
+                raised.apply(&state);
+
+                if raised == original {
+                    throttled = None;
+                    info!("Back under the bandwidth budget, the stream settings are restored");
+                } else {
+                    throttled = Some((original, raised));
+                }
+            }
+        }
+    });
+}
+
+/// One step down, the quality first then the fps (an unlimited fps is counted from the fps being encoded).
+fn lower(current: Settings, encode_fps: f64, min_quality: u8, min_fps: u32) -> Settings {
+    if current.quality > min_quality {
+        return Settings {
+            quality: current.quality.saturating_sub(QUALITY_STEP).max(min_quality),
+            ..current
+        };
+    }
+
+    let fps = match current.fps {
+        0 => encode_fps.round() as u32,
+        fps => fps,
+    };
+
+    if fps <= min_fps {
+        return current;
+    }
+
+    Settings {
+        fps: ((fps as f64 * (1.0 - FPS_STEP)) as u32).max(min_fps),
+        ..current
+    }
+}
+
+/// One step back up towards the original settings, the fps first since it was lowered last.
+fn raise(current: Settings, original: Settings) -> Settings {
+    if current.fps != original.fps {
+        let stepped = (current.fps as f64 / (1.0 - FPS_STEP)).ceil() as u32;
+        let fps = match original.fps {
+            //unlimited again once past the usual refresh rates
+            0 if stepped >= UNLIMITED_FROM_FPS => 0,
+            0 => stepped,
+            limit => stepped.min(limit),
+        };
+
+        return Settings { fps, ..current };
+    }
+
+    Settings {
+        quality: current.quality.saturating_add(QUALITY_STEP).min(original.quality),
+        ..current
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    encoder::{EncodeSettings, EncoderConfig, EncoderRegistry, FrameEncoder},
+    error::{Result, ShareScreenError},
+    frame_compressor::{ChromaSubsampling, bgra_to_rgb_into, encode_png, encode_thumbnail},
+    state::RawFrame,
+    test_pattern::TestPattern,
+    thumbnails::DEFAULT_THUMBNAIL_WIDTH,
+};
+
+/// Resolutions benchmarked when none is given.
+pub const DEFAULT_BENCH_RESOLUTIONS: [(u32, u32); 4] = [(1280, 720), (1920, 1080), (2560, 1440), (3840, 2160)];
+/// Distinct frames cycled through, so an encoder cannot benefit from encoding the same frame over and over.
+const BENCH_FRAMES: usize = 8;
+
+/// Settings of `share-screen bench`.
+pub struct BenchOptions {
+    pub resolutions: Vec<(u32, u32)>,
+    /// JPEG quality from 1 to 100, given to every encoder.
+    pub quality: u8,
+    /// chroma subsampling of the encoders.
+    pub chroma: ChromaSubsampling,
+    /// time spent on each path at each resolution.
+    pub duration: Duration,
+}
+
+/// Throughput of a path at a resolution.
+pub struct BenchResult {
+    /// the encoder or the conversion measured.
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u64,
+    pub fps: f64,
+    /// raw BGRA megabytes processed per second.
+    pub input_mb_per_sec: f64,
+    /// average size of a frame once encoded, 0 for the conversions.
+    pub average_output_kb: f64,
+}
+
+/// # Run Bench
+///
+/// Encodes synthetic frames (the test pattern) with every encoder of the `EncoderRegistry` and through the conversions of the pipeline
+/// (BGRA to RGB, the PNG screenshots and the thumbnails), at each resolution, and reports their throughput.
+pub fn run_bench(options: &BenchOptions) -> Result<Vec<BenchResult>> {
+    let registry = EncoderRegistry::default();
+    let encoders = registry
+        .names()
+        .into_iter()
+        .map(|name| {
+            registry.create(&EncoderConfig {
+                codec: name.to_string(),
+                chroma: options.chroma,
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<Arc<dyn FrameEncoder>>>>()?;
+
+    let mut results = Vec::new();
+    let quality = options.quality.clamp(1, 100);
+
+    for &(width, height) in &options.resolutions {
+        if width == 0 || height == 0 {
+            return Err(ShareScreenError::Server(format!("Invalid bench resolution {width}x{height}")));
+        }
+
+        let pattern = TestPattern::new(width, height, 30);
+        //the pattern rounds the dimensions to even numbers
+        let (width, height) = (width.max(16) & !1, height.max(16) & !1);
+
+        let frames: Vec<RawFrame> = (0..BENCH_FRAMES)
+            .map(|i| RawFrame {
+                data: pattern.draw(Duration::from_millis(i as u64 * 370)),
+                width,
+                height,
+                timestamp_us: 0,
+            })
+            .collect();
+
+        for encoder in &encoders {
+            for grayscale in [false, true] {
+                let path = match grayscale {
+                    true => format!("{} gray", encoder.name()),
+                    false => encoder.name().to_string(),
+                };
+                let settings = EncodeSettings { quality, grayscale };
+
+                results.push(measure(&path, &frames, options.duration, |frame| {
+                    encoder.encode(frame, settings).map(|encoded| encoded.len()).unwrap_or(0)
+                }));
+            }
+        }
+
+        let mut rgb = Vec::new();
+        results.push(measure("bgra->rgb", &frames, options.duration, |frame| {
+            bgra_to_rgb_into(&frame.data, &mut rgb);
+            std::hint::black_box(&rgb);
+            0
+        }));
+
+        results.push(measure("png snapshot", &frames, options.duration, |frame| {
+            encode_png(&frame.data, frame.width, frame.height).len()
+        }));
+
+        results.push(measure(
+            &format!("thumbnail {DEFAULT_THUMBNAIL_WIDTH}"),
+            &frames,
+            options.duration,
+            |frame| encode_thumbnail(&frame.data, frame.width, frame.height, DEFAULT_THUMBNAIL_WIDTH, quality).len(),
+        ));
+    }
+
+    Ok(results)
+}
+
+/// # Print Results
+///
+/// Prints the results as a table, one line per path and resolution.
+pub fn print_results(results: &[BenchResult]) {
+    println!(
+        "{:<16} {:>11} {:>8} {:>10} {:>12}",
+        "path", "resolution", "fps", "in MB/s", "out kB/frame"
+    );
+
+    for result in results {
+        let output = if result.average_output_kb > 0.0 {
+            format!("{:.1}", result.average_output_kb)
+        } else {
+            "-".to_string()
+        };
+
+        println!(
+            "{:<16} {:>11} {:>8.1} {:>10.1} {:>12}",
+            result.path,
+            format!("{}x{}", result.width, result.height),
+            result.fps,
+            result.input_mb_per_sec,
+            output
+        );
+    }
+}
+
+/// Runs the path over the frames for the duration (at least one frame), `run` returns the size of the output.
+fn measure(path: &str, frames: &[RawFrame], duration: Duration, mut run: impl FnMut(&RawFrame) -> usize) -> BenchResult {
+    let started = Instant::now();
+    let mut count = 0u64;
+    let mut output_bytes = 0u64;
+
+    while count == 0 || started.elapsed() < duration {
+        let frame = &frames[count as usize % frames.len()];
+        output_bytes += run(frame) as u64;
+        count += 1;
+    }
+
+    let secs = started.elapsed().as_secs_f64().max(f64::EPSILON);
+    let (width, height) = (frames[0].width, frames[0].height);
+
+    BenchResult {
+        path: path.to_string(),
+        width,
+        height,
+        frames: count,
+        fps: count as f64 / secs,
+        input_mb_per_sec: (count * frames[0].data.len() as u64) as f64 / secs / (1024.0 * 1024.0),
+        average_output_kb: output_bytes as f64 / count as f64 / 1024.0,
+    }
+}
+
+/// Parses a resolution like `1920x1080`.
+pub fn parse_resolution(resolution: &str) -> Option<(u32, u32)> {
+    let (width, height) = resolution.split_once(['x', 'X'])?;
+
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
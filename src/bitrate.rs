@@ -0,0 +1,230 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::state::StreamState;
+
+/// How often the encoded bitrate is measured and the settings adjusted.
+const CONTROLLER_INTERVAL: Duration = Duration::from_secs(2);
+/// Part of the target the bitrate may be off by before the settings are adjusted, so they do not bounce around it.
+const DEADBAND: f64 = 0.15;
+/// Largest quality step, taken when the bitrate is twice (or half) the target.
+const MAX_QUALITY_STEP: f64 = 15.0;
+/// Scale taken off (or given back) per step, in percent.
+const SCALE_STEP: u8 = 10;
+/// Part of the fps taken off (or given back) per step.
+const FPS_STEP: f64 = 0.25;
+/// Fps from which an unlimited fps is given back as unlimited.
+const UNLIMITED_FROM_FPS: u32 = 60;
+/// Frames an interval needs before its bitrate says anything, a still screen encodes next to nothing whatever the settings.
+const MIN_FRAMES: u64 = 4;
+
+/// `[bitrate]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BitrateConfig {
+    /// encoded bitrate the quality, scale and fps are tuned to, in megabits per second, 0 disables the tuning.
+    pub target_mbps: f64,
+    pub min_quality: u8,
+    pub max_quality: u8,
+    /// lowest scale in percent the frames are brought down to, once the quality reached its minimum.
+    pub min_scale: u8,
+    /// lowest fps the stream is brought down to, once the scale reached its minimum.
+    pub min_fps: u32,
+}
+
+impl Default for BitrateConfig {
+    fn default() -> Self {
+        Self {
+            target_mbps: 0.0,
+            min_quality: 25,
+            max_quality: 90,
+            min_scale: 50,
+            min_fps: 5,
+        }
+    }
+}
+
+/// Settings of the stream the controller tunes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Settings {
+    quality: u8,
+    scale: u8,
+    //0 is unlimited
+    fps: u32,
+}
+
+impl Settings {
+    fn of(state: &StreamState) -> Self {
+        Self {
+            quality: state.control.quality(),
+            scale: state.scaler.scale(),
+            fps: state.control.max_fps(),
+        }
+    }
+
+    fn apply(self, state: &StreamState) {
+        state.control.set_quality(self.quality);
+        state.scaler.set_scale(self.scale);
+        state.control.set_max_fps(self.fps);
+    }
+}
+
+/// The settings the controller works within.
+#[derive(Clone, Copy)]
+struct Limits {
+    min_quality: u8,
+    max_quality: u8,
+    min_scale: u8,
+    //the scale and fps of the stream when the tuning started, never gone over
+    max_scale: u8,
+    min_fps: u32,
+    max_fps: u32,
+}
+
+/// # Spawn Bitrate Controller
+///
+/// Spawns a task tuning the quality, the scale and the fps every few seconds so the encoded stream sits around
+/// `[bitrate] target_mbps`, instead of tuning the three by hand for every network: over the target the quality goes down
+/// first (by a step proportional to how far off it is), then the scale, then the fps. Under it they come back in the opposite order,
+/// up to `max_quality` and the scale and fps the stream started with.
+///
+/// Note: `The controller owns the three settings while it runs, changing them by hand (/api/quality, /api/scale, /api/fps) only lasts
+/// until its next step. It measures the bitrate of a single viewer, [bandwidth] caps the total of all of them.`
+pub fn spawn_bitrate_controller(config: BitrateConfig, state: Arc<StreamState>) {
+    if config.target_mbps <= 0.0 {
+        return;
+    }
+
+    let target = config.target_mbps * 1_000_000.0;
+    let start = Settings::of(&state);
+    let min_quality = config.min_quality.clamp(1, 100);
+    let limits = Limits {
+        min_quality,
+        max_quality: config.max_quality.clamp(min_quality, 100),
+        min_scale: config.min_scale.clamp(1, start.scale),
+        max_scale: start.scale,
+        min_fps: config.min_fps.max(1),
+        max_fps: start.fps,
+    };
+
+    info!(mbps = config.target_mbps, "Tuning the quality, scale and fps to the target bitrate");
+
+    tokio::spawn(async move {
+        let mut previous = state.pipeline.snapshot();
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(CONTROLLER_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+
+            let snapshot = state.pipeline.snapshot();
+            let frames = snapshot.frames_encoded - previous.frames_encoded;
+            let rates = snapshot.rates_since(&previous);
+            previous = snapshot;
+
+            if state.control.is_paused() || frames < MIN_FRAMES {
+                continue;
+            }
+
+            let current = Settings::of(&state);
+            let ratio = rates.bitrate_bps / target;
+
+            let tuned = if ratio > 1.0 + DEADBAND {
+                lower(current, ratio, rates.encode_fps, limits)
+            } else if ratio < 1.0 - DEADBAND {
+                raise(current, ratio, limits)
+            } else {
+                continue;
+            };
+
+            if tuned == current {
+                if ratio > 1.0 + DEADBAND {
+                    warn!(
+                        mbps = format_args!("{:.1}", rates.bitrate_bps / 1_000_000.0),
+                        "Over the target bitrate at the lowest settings"
+                    );
+                }
+
+                continue;
+            }
+
+            tuned.apply(&state);
+
+            debug!(
+                mbps = format_args!("{:.1}", rates.bitrate_bps / 1_000_000.0),
+                quality = tuned.quality,
+                scale = tuned.scale,
+                fps = tuned.fps,
+                "Tuned the stream to the target bitrate"
+            );
+        }
+    });
+}
+
+/// Quality step for a bitrate `ratio` times the target, bigger the further off it is.
+fn quality_step(ratio: f64) -> u8 {
+    let off = ratio.max(1.0 / ratio).log2().min(1.0);
+
+    (off * MAX_QUALITY_STEP).ceil().max(1.0) as u8
+}
+
+/// One step down, the quality first, then the scale, then the fps (an unlimited fps is counted from the fps being encoded).
+fn lower(current: Settings, ratio: f64, encode_fps: f64, limits: Limits) -> Settings {
+    if current.quality > limits.min_quality {
+        return Settings {
+            quality: current.quality.saturating_sub(quality_step(ratio)).max(limits.min_quality),
+            ..current
+        };
+    }
+
+    if current.scale > limits.min_scale {
+        return Settings {
+            scale: current.scale.saturating_sub(SCALE_STEP).max(limits.min_scale),
+            ..current
+        };
+    }
+
+    let fps = match current.fps {
+        0 => encode_fps.round() as u32,
+        fps => fps,
+    };
+
+    if fps <= limits.min_fps {
+        return current;
+    }
+
+    Settings {
+        fps: ((fps as f64 * (1.0 - FPS_STEP)) as u32).max(limits.min_fps),
+        ..current
+    }
+}
+
+/// One step up, the fps first, then the scale, then the quality, the opposite of `lower`.
+fn raise(current: Settings, ratio: f64, limits: Limits) -> Settings {
+    if current.fps != limits.max_fps {
+        let stepped = (current.fps as f64 / (1.0 - FPS_STEP)).ceil() as u32;
+        let fps = match limits.max_fps {
+            //unlimited again once past the usual refresh rates
+            0 if stepped >= UNLIMITED_FROM_FPS => 0,
+            0 => stepped,
+            limit => stepped.min(limit),
+        };
+
+        return Settings { fps, ..current };
+    }
+
+    if current.scale < limits.max_scale {
+        return Settings {
+            scale: current.scale.saturating_add(SCALE_STEP).min(limits.max_scale),
+            ..current
+        };
+    }
+
+    Settings {
+        quality: current.quality.saturating_add(quality_step(ratio)).min(limits.max_quality),
+        ..current
+    }
+}
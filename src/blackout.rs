@@ -0,0 +1,174 @@
+use std::sync::{Arc, RwLock};
+#[cfg(windows)]
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::state::StreamState;
+
+/// Time between two looks for the windows to black out, a window moved is followed within it.
+#[cfg(windows)]
+const TRACKING_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `[blackout]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct BlackoutConfig {
+    /// title patterns of the windows blacked out in the frames, `*` matches any text and `?` one character (ignoring case),
+    /// like `["*KeePass*", "*Outlook*"]`.
+    pub windows: Vec<String>,
+}
+
+/// A rectangle of the frames as fractions of their size, from 0.0 (left, top) to 1.0 (right, bottom).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlackoutArea {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+/// # Window Blackout
+///
+/// The areas of the captured monitor covered by the windows of `[blackout]`, filled with black in the frames before they are
+/// transformed and encoded, so the viewers, the recordings and the snapshots never see them.
+#[derive(Default)]
+pub struct WindowBlackout {
+    areas: RwLock<Vec<BlackoutArea>>,
+}
+
+impl WindowBlackout {
+    /// Replaces the areas blacked out, by the tracking of the windows.
+    pub fn set_areas(&self, areas: Vec<BlackoutArea>) {
+        *self.areas.write().unwrap() = areas;
+    }
+
+    /// Fills the areas of the BGRA frame with black, the pixels partly covered included.
+    pub fn draw(&self, frame: &mut [u8], width: u32, height: u32) {
+        let areas = self.areas.read().unwrap();
+        let (width, height) = (width as usize, height as usize);
+
+        if areas.is_empty() || frame.len() < width * height * 4 {
+            return;
+        }
+
+        for area in areas.iter() {
+            let left = ((area.left.clamp(0.0, 1.0) * width as f64).floor() as usize).min(width);
+            let right = ((area.right.clamp(0.0, 1.0) * width as f64).ceil() as usize).min(width);
+            let top = ((area.top.clamp(0.0, 1.0) * height as f64).floor() as usize).min(height);
+            let bottom = ((area.bottom.clamp(0.0, 1.0) * height as f64).ceil() as usize).min(height);
+
+            if left >= right {
+                continue;
+            }
+
+            for y in top..bottom {
+                let row = y * width * 4;
+
+                for pixel in frame[row + left * 4..row + right * 4].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+        }
+    }
+}
+
+/// # Title Matches
+///
+/// Whether the title of a window matches the pattern, `*` matching any text (none included) and `?` one character, ignoring case.
+/// A pattern without wildcards matches the whole title only.
+pub fn title_matches(pattern: &str, title: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let title: Vec<char> = title.to_lowercase().chars().collect();
+
+    //position after the last `*` in both, to backtrack to when the rest does not match
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < title.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == title[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after, from)) => {
+                    p = after;
+                    t = from + 1;
+                    star = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// # Spawn Window Blackout
+///
+/// Follows the windows whose title matches a pattern of `[blackout]` and blacks them out in the frames of the monitor being captured
+/// whenever they are on it and not minimized, the whole window even when another one covers part of it.
+///
+/// Note: `The windows are looked for four times a second, a window dragged quickly can show for a frame ahead of its blackout.
+/// Only windows has the tracking, the section is logged and ignored on the other platforms.`
+pub fn spawn_window_blackout(config: BlackoutConfig, state: Arc<StreamState>) {
+    if config.windows.is_empty() {
+        return;
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = state;
+        tracing::warn!("Windows are only blacked out on windows, ignoring [blackout]");
+    }
+
+    #[cfg(windows)]
+    tokio::spawn(async move {
+        use crate::platform::windows::{monitor_area, top_level_windows, window_area};
+
+        loop {
+            let monitor = state
+                .source()
+                .monitor()
+                .and_then(|index| monitor_area(index as usize).ok().flatten());
+
+            let areas = match monitor {
+                Some(monitor) => {
+                    let (width, height) = ((monitor.right - monitor.left) as f64, (monitor.bottom - monitor.top) as f64);
+
+                    top_level_windows()
+                        .into_iter()
+                        .filter(|(_, title)| config.windows.iter().any(|pattern| title_matches(pattern, title)))
+                        .filter_map(|(window, _)| window_area(window))
+                        .filter(|area| {
+                            area.left < monitor.right
+                                && area.right > monitor.left
+                                && area.top < monitor.bottom
+                                && area.bottom > monitor.top
+                        })
+                        .map(|area| BlackoutArea {
+                            left: (area.left - monitor.left) as f64 / width,
+                            top: (area.top - monitor.top) as f64 / height,
+                            right: (area.right - monitor.left) as f64 / width,
+                            bottom: (area.bottom - monitor.top) as f64 / height,
+                        })
+                        .collect()
+                }
+                //a camera or another source has no windows
+                None => Vec::new(),
+            };
+
+            state.blackout.set_areas(areas);
+
+            tokio::select! {
+                _ = tokio::time::sleep(TRACKING_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+        }
+    });
+}
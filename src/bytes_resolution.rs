@@ -0,0 +1,64 @@
+use axum::{
+    http::{HeaderName, HeaderValue, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+
+/// # Bytes Resolution
+///
+/// Represents a complete response body that is already in memory (images, generated files).
+pub struct BytesResolution {
+    content: Vec<u8>,
+    content_type: &'static str,
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl BytesResolution {
+    /// create a 200 response with the content and its type.
+    pub fn new(content: Vec<u8>, content_type: &'static str) -> Self {
+        Self {
+            content,
+            content_type,
+            status: 200,
+            headers: Vec::new(),
+        }
+    }
+
+    /// create a plain text response with a status code, used for errors (404, 503, etc.)
+    pub fn text(status: u16, text: impl Into<String>) -> Self {
+        Self {
+            content: text.into().into_bytes(),
+            content_type: "text/plain; charset=utf-8",
+            status,
+            headers: Vec::new(),
+        }
+    }
+
+    /// changes the status code of the response.
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// adds a header to the response (Set-Cookie, Location, etc.)
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+impl IntoResponse for BytesResolution {
+    //sets the status, content type and headers, the content length is set from the body
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = (status, [(CONTENT_TYPE, self.content_type)], self.content).into_response();
+
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+                response.headers_mut().append(name, value);
+            }
+        }
+
+        response
+    }
+}
@@ -0,0 +1,168 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{Router, extract::State, http::request::Parts, routing::get};
+use serde::Serialize;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{error, info, warn};
+
+use crate::{
+    api::json,
+    auth::{Auth, guard},
+    captures::{CaptureType, SerializedDimensions},
+    config::Config,
+    encoder::EncoderRegistry,
+    encryption::FrameCipher,
+    pipeline::spawn_frame_capture,
+    routes::stream_routes,
+    source::SourceDevice,
+    state::StreamState,
+};
+
+/// # Camera Stream
+///
+/// A camera of `[capture] cameras` captured at the same time as the source, with a pipeline and a state of its own.
+#[derive(Clone)]
+pub struct CameraStream {
+    /// number of the camera starting from 1, the one of the config and of its routes.
+    pub number: usize,
+    pub state: Arc<StreamState>,
+}
+
+/// Rest API Json for a camera stream.
+#[derive(Serialize)]
+pub struct CameraStreamInfo {
+    pub number: usize,
+    pub source: String,
+    /// where the stream is opened, the other stream routes are under the same prefix.
+    pub url: String,
+    pub viewers: usize,
+    /// why the capture of the camera is not running, none while it is.
+    pub capture_error: Option<String>,
+}
+
+impl From<&CameraStream> for CameraStreamInfo {
+    fn from(camera: &CameraStream) -> Self {
+        Self {
+            number: camera.number,
+            source: camera.state.source().to_string(),
+            url: format!("/cameras/{}/stream", camera.number),
+            viewers: camera.state.stats.viewers(),
+            capture_error: camera.state.capture_error(),
+        }
+    }
+}
+
+/// # Start Camera Streams
+///
+/// Activates the cameras of `[capture] cameras` and starts a pipeline for each, so a webcam and a document camera
+/// can be streamed next to the source instead of switching between them.
+///
+/// Note: `A camera that cannot be activated is logged and left out, the others and the source still start.
+/// The streams share the auth of the source and encode with the [encoder] of the config, the other sections only apply to the source.`
+pub fn start_camera_streams(
+    config: &Config,
+    source: &dyn SourceDevice,
+    auth: &Arc<Auth>,
+    replay: Duration,
+) -> Vec<(CameraStream, JoinHandle<()>)> {
+    let mut streams: Vec<(CameraStream, JoinHandle<()>)> = Vec::new();
+
+    for &number in &config.capture.cameras {
+        let device = CaptureType::Camera(number.max(1) - 1);
+
+        //the source is already streamed at the routes of the root
+        if device.to_string() == source.to_string() || streams.iter().any(|(camera, _)| camera.number == number) {
+            warn!(camera = number, "The camera is already streamed, ignoring it in [capture] cameras");
+            continue;
+        }
+
+        match start(number, device, config, auth, replay) {
+            Ok(stream) => {
+                info!(camera = number, url = %format!("/cameras/{number}/stream"), "Streaming the camera");
+                streams.push(stream);
+            }
+            Err(e) => error!(camera = number, error = %e, "Failed to start the camera stream"),
+        }
+    }
+
+    streams
+}
+
+fn start(
+    number: usize,
+    device: CaptureType,
+    config: &Config,
+    auth: &Arc<Auth>,
+    replay: Duration,
+) -> Result<(CameraStream, JoinHandle<()>), Box<dyn std::error::Error>> {
+    let capture = device.open()?;
+    let (width, height) = capture.dimensions()?;
+    let dimensions = SerializedDimensions {
+        width: width as usize,
+        height: height as usize,
+    };
+
+    let (compressed_sender, _) = broadcast::channel::<Vec<u8>>(100);
+
+    let state = Arc::new(
+        StreamState::new(Arc::new(device), dimensions, compressed_sender, replay)
+            .with_auth(auth.clone())
+            .with_cipher(FrameCipher::from_config(&config.encryption)?)
+            .with_encoder(EncoderRegistry::default().create(&config.encoder)?),
+    );
+
+    state.control.set_grayscale(config.encoder.grayscale);
+    if let Some(quality) = config.encoder.quality {
+        state.control.set_quality(quality);
+    }
+    if let Some(fps) = config.encoder.fps {
+        state.control.set_max_fps(fps);
+    }
+
+    let task = spawn_frame_capture(capture, state.clone());
+
+    Ok((CameraStream { number, state }, task))
+}
+
+/// # Stop Camera Streams
+///
+/// Ends the streams of the cameras and releases them once their pipelines finished the frames in flight.
+pub async fn stop_camera_streams(cameras: &[CameraStream], tasks: Vec<JoinHandle<()>>) {
+    for camera in cameras {
+        camera.state.shutdown();
+    }
+
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    for camera in cameras {
+        camera.state.source().release();
+    }
+}
+
+/// # Camera Routes
+///
+/// The stream routes of every camera stream nested under `/cameras/<number>` (`/cameras/2/stream`, `/cameras/2/snapshot.jpg`...),
+/// and `GET /api/cameras` listing them.
+pub fn camera_routes(state: &StreamState, config: &Config) -> Router<Arc<StreamState>> {
+    let mut router = Router::new().route(
+        "/api/cameras",
+        get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+            if let Err(denied) = guard(&state, &req) {
+                return denied;
+            }
+
+            json(state.cameras.iter().map(CameraStreamInfo::from).collect::<Vec<_>>())
+        }),
+    );
+
+    for camera in &state.cameras {
+        router = router.nest(
+            &format!("/cameras/{}", camera.number),
+            stream_routes(config).with_state(camera.state.clone()),
+        );
+    }
+
+    router
+}
@@ -0,0 +1,45 @@
+use serde::Serialize;
+
+use crate::{containers::StreamContainer, packets::SUPPORTED_VERSIONS, state::StreamState};
+
+/// # Capabilities
+///
+/// What this server build can stream, returned by `/stream/capabilities` so clients can pick the best options instead of assuming them.
+#[derive(Serialize)]
+pub struct Capabilities {
+    /// versions of the packet framing the raw container can be streamed in.
+    pub protocol_versions: Vec<u8>,
+    pub video_codecs: Vec<&'static str>,
+    /// values of the `codec` query parameter of `/stream` (raw container), the codec of the stream first.
+    pub frame_codecs: Vec<&'static str>,
+    /// empty when audio is not being captured.
+    pub audio_codecs: Vec<&'static str>,
+    /// values of the `container` query parameter of `/stream`.
+    pub containers: Vec<&'static str>,
+    pub transports: Vec<&'static str>,
+    /// maximum frames per second that are encoded, 0 being unlimited.
+    pub max_fps: u32,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Capabilities {
+    /// The capabilities of the running stream.
+    pub fn current(state: &StreamState) -> Self {
+        let dimensions = state.dimensions();
+
+        Self {
+            protocol_versions: SUPPORTED_VERSIONS.to_vec(),
+            video_codecs: vec!["mjpeg"],
+            frame_codecs: std::iter::once(state.encoder.name())
+                .chain(state.codecs.names().into_iter().filter(|codec| *codec != state.encoder.name()))
+                .collect(),
+            audio_codecs: if state.audio_enabled() { vec!["opus"] } else { Vec::new() },
+            containers: StreamContainer::ALL.iter().map(|container| container.name()).collect(),
+            transports: vec!["http"],
+            max_fps: state.control.max_fps(),
+            width: dimensions.width,
+            height: dimensions.height,
+        }
+    }
+}
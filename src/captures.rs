@@ -1,65 +1,251 @@
-use std::sync::Arc;
+use std::sync::{
+    Arc, RwLock,
+    atomic::{AtomicI32, Ordering},
+};
 
-use serde::Serialize;
-use win_video::{devices::{Cameras, Dimensions, Monitor}, i_capture::ICapture};
-use windows::Win32::System::Com::{COINIT_MULTITHREADED, CoInitializeEx};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    devices::MonitorInfo,
+    error::{Result, ShareScreenError},
+    link_codec::LinkCodec,
+    platform,
+    source::{CaptureSource, SourceDevice},
+    test_pattern::{DEFAULT_PATTERN_FPS, DEFAULT_PATTERN_HEIGHT, DEFAULT_PATTERN_WIDTH, TestPattern},
+};
+
+/// `[capture]` section of the config, the device is asked for at the console when `source` is not set.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// name of a source of the `SourceRegistry`, `monitor`, `camera`, `test_pattern`, `relay` or `file` unless more are registered.
+    pub source: Option<String>,
+    /// monitor captured with the `monitor` source, its number starting from 1 or its name.
+    pub monitor: MonitorSelector,
+    /// camera captured with the `camera` source, starting from 1 in the order of `/api/devices`.
+    pub camera: usize,
+    /// more cameras captured at the same time as the source, each streamed at its own `/cameras/<number>/` routes.
+    pub cameras: Vec<usize>,
+    /// width of the frames of the `test_pattern` source.
+    pub width: u32,
+    /// height of the frames of the `test_pattern` source.
+    pub height: u32,
+    /// frames per second of the `test_pattern` source.
+    pub fps: u32,
+    /// video played by the `file` source.
+    pub file: Option<String>,
+    /// start the `file` source over once it ends, instead of stopping the capture.
+    #[serde(rename = "loop")]
+    pub looped: bool,
+    /// instance re-broadcast by the `relay` source, like `http://192.168.1.20:5074`.
+    pub relay_url: Option<String>,
+    /// one of the `[auth] tokens` of the relayed instance.
+    pub relay_token: Option<String>,
+    /// frames requested from the relayed instance, `jpeg`, `qoi`, `zstd` or `auto` (`qoi` when it is on the local network).
+    pub relay_codec: LinkCodec,
+    /// shows the source as in a mirror (left and right swapped), usually for a camera filming the host, switched at runtime with `/api/mirror`.
+    pub mirror: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            source: None,
+            monitor: MonitorSelector::Number(1),
+            camera: 1,
+            cameras: Vec::new(),
+            width: DEFAULT_PATTERN_WIDTH,
+            height: DEFAULT_PATTERN_HEIGHT,
+            fps: DEFAULT_PATTERN_FPS,
+            file: None,
+            looped: false,
+            relay_url: None,
+            relay_token: None,
+            relay_codec: LinkCodec::Jpeg,
+            mirror: false,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// # Apply Source
+    ///
+    /// Sets the source from a `name[:argument]` spec of the command line, the argument is the file of `file`
+    /// (`file:demo.mp4`), the monitor of `monitor` (`monitor:2`, `monitor:DELL U2720Q`), the camera of `camera` (`camera:2`) and the url of `relay` (`relay:http://host:5074`).
+    pub fn apply_source(&mut self, spec: &str) -> Result<()> {
+        let (name, argument) = match spec.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (spec, None),
+        };
+
+        match (name.to_ascii_lowercase().as_str(), argument) {
+            ("file", Some(path)) => self.file = Some(path.to_string()),
+            ("relay", Some(url)) => self.relay_url = Some(url.to_string()),
+            ("monitor", Some(monitor)) => self.monitor = MonitorSelector::parse(monitor),
+            ("camera", Some(index)) => {
+                self.camera = index
+                    .parse()
+                    .map_err(|_| ShareScreenError::Server(format!("Invalid camera {index} in the source {spec}")))?
+            }
+            (_, Some(_)) => return Err(ShareScreenError::Server(format!("The source {name} takes no argument"))),
+            (_, None) => {}
+        }
+
+        self.source = Some(name.to_string());
+        Ok(())
+    }
+}
+
+/// A monitor of `[capture] monitor`, its number (`2`) or its name (`"DELL U2720Q"`).
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum MonitorSelector {
+    /// the number of the monitor starting from 1, as the prompt lists them.
+    Number(i32),
+    /// the model (`DELL U2720Q`) or the device name (`\\.\DISPLAY1`, `DP-1`) of the monitor in `/api/devices`.
+    Name(String),
+}
+
+impl MonitorSelector {
+    /// The number of the spec when it is one, the name of a monitor otherwise.
+    pub fn parse(spec: &str) -> Self {
+        let spec = spec.trim();
+
+        match spec.parse() {
+            Ok(number) => MonitorSelector::Number(number),
+            Err(_) => MonitorSelector::Name(spec.to_string()),
+        }
+    }
+
+    /// The device of the selected monitor.
+    pub fn device(&self) -> Arc<dyn SourceDevice> {
+        match self {
+            MonitorSelector::Number(number) => Arc::new(CaptureType::Monitor((*number).max(1) - 1)),
+            MonitorSelector::Name(name) => Arc::new(NamedMonitor::new(name.clone())),
+        }
+    }
+}
+
+/// # Named Monitor
+///
+/// A monitor picked by its model or device name, looked up in the monitors every time it is opened:
+/// it is still the one captured once the displays are enumerated in another order (a monitor plugged in, a dock reconnected).
+pub struct NamedMonitor {
+    name: String,
+    //index of the monitor when it was last opened, -1 before
+    index: AtomicI32,
+}
+
+impl NamedMonitor {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            index: AtomicI32::new(-1),
+        }
+    }
+
+    /// # Find
+    ///
+    /// Index of the monitor whose model or device name is the name (ignoring case), or the first one containing it.
+    pub fn find(&self) -> Result<i32> {
+        let monitors = platform::list_monitors()?;
+        let name = self.name.to_lowercase();
+        let matches = |monitor: &MonitorInfo, exact: bool| {
+            std::iter::once(&monitor.name).chain(&monitor.model).any(|candidate| {
+                let candidate = candidate.to_lowercase();
+
+                if exact { candidate == name } else { candidate.contains(&name) }
+            })
+        };
+
+        monitors
+            .iter()
+            .find(|monitor| matches(monitor, true))
+            .or_else(|| monitors.iter().find(|monitor| matches(monitor, false)))
+            .map(|monitor| monitor.index as i32)
+            .ok_or_else(|| ShareScreenError::MonitorNotFound { name: self.name.clone() })
+    }
+}
+
+impl std::fmt::Display for NamedMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "monitor {}", self.name)
+    }
+}
+
+impl SourceDevice for NamedMonitor {
+    fn open(&self) -> Result<Arc<dyn CaptureSource>> {
+        let index = self.find()?;
+        self.index.store(index, Ordering::Relaxed);
+
+        tracing::info!(monitor = %self.name, number = index + 1, "Found the monitor");
+        platform::open(CaptureType::Monitor(index))
+    }
+
+    fn can_reacquire(&self) -> bool {
+        true
+    }
+
+    fn monitor(&self) -> Option<i32> {
+        let index = self.index.load(Ordering::Relaxed);
+
+        (index >= 0).then_some(index)
+    }
+}
 
 /// The capture types available for the program.
+#[derive(Clone, Copy)]
 pub enum CaptureType {
-    /// Capture a camera (like your webcam)
-    Camera,
+    /// Capture the camera at an index starting from 0 (like your webcam)
+    Camera(usize),
     /// Capture the monitor at an index starting from 0
     Monitor(i32),
+    /// Synthetic color bars with a clock, for machines without a camera or a display
+    TestPattern { width: u32, height: u32, fps: u32 },
 }
 
-impl CaptureType {
-    /// # Activate Capture device
-    ///
-    /// Takes a capture device type and activates it using the win_video library.
-    ///
-    /// Returns the device activated.
-    /// 
-    /// The function also has the chance of returning an err for the following reasons:
-    /// CoInitializeEx failed,
-    /// No video devices 
-    /// No valid monitor devices
-    /// Monitor index out of range
-    /// And other window errors.
-    pub fn activate(self) -> Result<Arc<dyn ICapture<CaptureOutput = Vec<u8>>>, Box<dyn std::error::Error>> {
-        let capture;
-
+impl std::fmt::Display for CaptureType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CaptureType::Camera => unsafe {
-                if CoInitializeEx(None, COINIT_MULTITHREADED) != windows::Win32::Foundation::S_OK {
-                    return Err("Failed to CoIntialize for camera.".into());
-                }
+            CaptureType::Camera(c) => write!(f, "camera {}", c + 1),
+            CaptureType::Monitor(m) => write!(f, "monitor {}", m + 1),
+            CaptureType::TestPattern { width, height, fps } => write!(f, "test pattern {width}x{height}@{fps}"),
+        }
+    }
+}
 
-                let video_devices = Cameras::new()?;
+impl CaptureType {
+    /// Whether the capture can be reacquired after it has stopped.
+    ///
+    /// Only monitors are re-enumerated, a camera that stopped is considered gone until a restart is requested.
+    pub fn can_reacquire(&self) -> bool {
+        matches!(self, CaptureType::Monitor(_))
+    }
+}
 
-                if video_devices.devices.len() == 0 {
-                    return Err("No camera devices to capture.".into());
-                }
+impl SourceDevice for CaptureType {
+    fn open(&self) -> Result<Arc<dyn CaptureSource>> {
+        if let CaptureType::TestPattern { width, height, fps } = *self {
+            return Ok(Arc::new(TestPattern::new(width, height, fps)));
+        }
 
-                println!("Activating device (this may take a second)...");
+        platform::open(*self)
+    }
 
-                let device = video_devices.activate_device(
-                    video_devices.devices[0],
-                    Some(win_video::devices::camera::Output::RGB32),
-                )?;
+    fn can_reacquire(&self) -> bool {
+        CaptureType::can_reacquire(self)
+    }
 
-                capture = device as Arc<dyn ICapture<CaptureOutput = Vec<u8>>>;
-            },
-            CaptureType::Monitor(m) => unsafe {
-                capture = Monitor::from_monitor(m as u32)? as Arc<dyn ICapture<CaptureOutput = Vec<u8>>>;
-            },
+    fn monitor(&self) -> Option<i32> {
+        match *self {
+            CaptureType::Monitor(m) => Some(m),
+            _ => None,
         }
-
-        Ok(capture)
     }
 }
 
 /// Rest API Json for capture dimensions.
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
 pub struct SerializedDimensions {
     /// width of device.
     pub width: usize,
@@ -67,12 +253,5 @@ pub struct SerializedDimensions {
     pub height: usize,
 }
 
-impl SerializedDimensions {
-    /// Converts a dimensions reference toa serialized API dimension.
-    pub fn from_dimensions(size: Arc<Dimensions>) -> Self {
-        Self {
-            width: size.width as usize,
-            height: size.height as usize,
-        }
-    }
-}
+/// Dimensions of the capture shared between the compressor and the routes, updated when the device changes resolution.
+pub type SharedDimensions = Arc<RwLock<SerializedDimensions>>;
@@ -0,0 +1,338 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{
+    Router,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::request::Parts,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{Instrument, debug, info, info_span};
+
+use crate::{
+    admin::{AdminConfig, unauthorized},
+    api::{ApiError, json},
+    auth::guard,
+    request_params::{client_ip, query_param},
+    session::unix_now,
+    state::StreamState,
+};
+
+/// Messages kept for the viewers joining later when none is configured.
+pub const DEFAULT_CHAT_HISTORY: usize = 50;
+/// Longest name handle, longer ones are cut.
+const MAX_NAME_LENGTH: usize = 32;
+
+/// `[chat]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub enabled: bool,
+    /// messages sent to the viewers joining, the oldest are forgotten.
+    pub history: usize,
+    /// longest message accepted, in characters.
+    pub max_length: usize,
+    /// logs the messages, for the host to read them in the console.
+    pub console: bool,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            history: DEFAULT_CHAT_HISTORY,
+            max_length: 500,
+            console: true,
+        }
+    }
+}
+
+/// A message of the chat.
+#[derive(Serialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub name: String,
+    pub text: String,
+    /// unix timestamp (seconds) the message was sent at.
+    pub timestamp: u64,
+}
+
+/// # Chat Room
+///
+/// The messages of the viewers, broadcast to everyone connected to `/ws/chat` and kept in memory for the ones joining later.
+pub struct ChatRoom {
+    history: Mutex<VecDeque<ChatMessage>>,
+    capacity: usize,
+    enabled: bool,
+    messages: broadcast::Sender<ChatMessage>,
+    next_guest: AtomicU64,
+}
+
+impl ChatRoom {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            enabled: true,
+            messages: broadcast::channel(64).0,
+            next_guest: AtomicU64::new(1),
+        }
+    }
+
+    /// A room of the `[chat]` section, the viewer page hides the chat when it is disabled.
+    pub fn from_config(config: &ChatConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            ..Self::new(config.history)
+        }
+    }
+
+    /// Whether `/ws/chat` is served.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sends the message to everyone in the room and keeps it in the history.
+    pub fn post(&self, name: &str, text: &str) -> ChatMessage {
+        let message = ChatMessage {
+            name: name.to_string(),
+            text: text.to_string(),
+            timestamp: unix_now(),
+        };
+
+        {
+            let mut history = self.history.lock().unwrap();
+
+            if history.len() >= self.capacity {
+                history.pop_front();
+            }
+
+            if self.capacity > 0 {
+                history.push_back(message.clone());
+            }
+        }
+
+        let _ = self.messages.send(message.clone());
+        message
+    }
+
+    /// The messages kept, oldest first.
+    pub fn history(&self) -> Vec<ChatMessage> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChatMessage> {
+        self.messages.subscribe()
+    }
+
+    /// A name for a viewer that did not pick one.
+    fn guest_name(&self) -> String {
+        format!("viewer-{}", self.next_guest.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for ChatRoom {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHAT_HISTORY)
+    }
+}
+
+/// A message sent by a chat client as json, for example `{"type": "message", "text": "can you zoom in?"}`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatCommand {
+    Message { text: String },
+    /// changes the name handle of the client.
+    Rename { name: String },
+}
+
+/// A message sent to a chat client.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatReply {
+    /// the messages sent before the client joined, along with its name.
+    History { name: String, messages: Vec<ChatMessage> },
+    Message(ChatMessage),
+    Renamed { name: String },
+    Error { error: String },
+}
+
+/// # Chat Routes
+///
+/// The routes of the viewer chat:
+///
+/// - `GET /ws/chat?name=` - WebSocket of the chat, the name handle is optional
+/// - `GET /api/chat` - the messages kept in the history
+/// - `POST /api/chat?name=&text=` - posts a message as the host (admin), `name` defaults to `host`
+pub fn chat_routes(config: ChatConfig, admin: AdminConfig) -> Router<Arc<StreamState>> {
+    if !config.enabled {
+        return Router::new();
+    }
+
+    Router::new()
+        .route(
+            "/ws/chat",
+            get(
+                move |State(state): State<Arc<StreamState>>, req: Parts, upgrade: WebSocketUpgrade| async move {
+                    if let Err(denied) = guard(&state, &req) {
+                        return denied;
+                    }
+
+                    let name = query_param(&req, "name")
+                        .and_then(|name| sanitize_name(&name))
+                        .unwrap_or_else(|| state.chat.guest_name());
+                    let ip = client_ip(&req);
+
+                    upgrade
+                        .on_upgrade(move |socket| {
+                            handle_client(socket, name, config, state).instrument(info_span!("chat_client", %ip))
+                        })
+                        .into_response()
+                },
+            ),
+        )
+        .route(
+            "/api/chat",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(state.chat.history())
+            })
+            .post(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if !admin.authorized(&state.auth, &req) {
+                    return unauthorized(&admin, &state.auth);
+                }
+
+                let Some(text) = query_param(&req, "text").filter(|text| !text.trim().is_empty()) else {
+                    return ApiError::new("Expected ?text=").response(400);
+                };
+                let name = query_param(&req, "name")
+                    .and_then(|name| sanitize_name(&name))
+                    .unwrap_or_else(|| "host".to_string());
+
+                json(state.chat.post(&name, text.trim()))
+            }),
+        )
+}
+
+/// Sends the history and relays the messages until the client disconnects.
+async fn handle_client(socket: WebSocket, mut name: String, config: ChatConfig, state: Arc<StreamState>) {
+    let (mut sink, mut incoming) = socket.split();
+    let mut messages = state.chat.subscribe();
+
+    let history = ChatReply::History {
+        name: name.clone(),
+        messages: state.chat.history(),
+    };
+
+    if send(&mut sink, &history).await.is_err() {
+        return;
+    }
+
+    loop {
+        let reply = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => handle_command(text.as_str(), &mut name, &config, &state),
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!(error = %e, "Chat client error");
+                    break;
+                }
+            },
+            message = messages.recv() => match message {
+                Ok(message) => Some(ChatReply::Message(message)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = state.shutting_down() => break,
+        };
+
+        if let Some(reply) = reply
+            && send(&mut sink, &reply).await.is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Applies a command of the client, the reply is `None` for messages as they come back through the broadcast.
+fn handle_command(text: &str, name: &mut String, config: &ChatConfig, state: &StreamState) -> Option<ChatReply> {
+    let command = match serde_json::from_str::<ChatCommand>(text) {
+        Ok(command) => command,
+        Err(e) => {
+            return Some(ChatReply::Error {
+                error: format!("invalid message: {e}"),
+            });
+        }
+    };
+
+    match command {
+        ChatCommand::Message { text } => {
+            let text = text.trim();
+
+            if text.is_empty() {
+                return None;
+            }
+
+            if text.chars().count() > config.max_length {
+                return Some(ChatReply::Error {
+                    error: format!("messages are limited to {} characters", config.max_length),
+                });
+            }
+
+            let message = state.chat.post(name, text);
+
+            if config.console {
+                //escape sequences of a viewer would drive the terminal of the host
+                let text: String = message.text.chars().filter(|c| !c.is_control()).collect();
+
+                info!(name = %message.name, %text, "Chat message");
+            }
+
+            None
+        }
+        ChatCommand::Rename { name: requested } => match sanitize_name(&requested) {
+            Some(requested) => {
+                *name = requested;
+                Some(ChatReply::Renamed { name: name.clone() })
+            }
+            None => Some(ChatReply::Error {
+                error: "the name is empty".to_string(),
+            }),
+        },
+    }
+}
+
+async fn send<S>(sink: &mut S, reply: &ChatReply) -> Result<(), S::Error>
+where
+    S: futures::Sink<Message> + Unpin,
+{
+    let json = serde_json::to_string(reply).unwrap_or_default();
+    sink.send(Message::Text(json.into())).await
+}
+
+/// The name without control characters, cut to `MAX_NAME_LENGTH`, `None` when nothing is left.
+fn sanitize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LENGTH)
+        .collect();
+
+    (!name.is_empty()).then_some(name)
+}
@@ -0,0 +1,191 @@
+use std::net::SocketAddr;
+
+use clap::{Parser, Subcommand};
+
+use share_screen::{
+    control::DEFAULT_QUALITY, frame_compressor::ChromaSubsampling, link_codec::LinkCodec, logging::{DEFAULT_LOG_LEVEL, LogFormat},
+    presets::StreamPreset, replay::DEFAULT_REPLAY_SECONDS, timelapse::DEFAULT_TIMELAPSE_INTERVAL,
+};
+
+/// # Cli
+///
+/// Command line arguments of the program, anything not provided is asked for at the console prompt (unless `--headless`).
+#[derive(Parser)]
+#[command(version, about = "Share your screen or camera over the network.")]
+pub struct Cli {
+    /// Toml config file, defaults to share-screen.toml in the working directory if it exists.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Source to capture, overrides `[capture] source`: `monitor:2`, `camera`, `test_pattern`,
+    /// `relay:http://host:5074` or `file:demo.mp4` (windows).
+    #[arg(long, value_name = "SOURCE")]
+    pub source: Option<String>,
+
+    /// Monitor to capture, its number or its name as `/api/devices` lists it (`--monitor "DELL U2720Q"`),
+    /// found again by its name when the displays are re-detected. Captures a monitor unless `--source` says otherwise.
+    #[arg(long, value_name = "MONITOR")]
+    pub monitor: Option<String>,
+
+    /// Play the `file` source over and over instead of stopping at its end.
+    #[arg(long = "loop")]
+    pub looped: bool,
+
+    /// Preset of the stream: low-latency, balanced or quality (fps, scale, quality and buffer), viewers pick another with `?preset=`.
+    #[arg(long)]
+    pub preset: Option<StreamPreset>,
+
+    /// Record the stream to an mp4 file alongside live streaming.
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<String>,
+
+    /// Seconds of recent frames kept for `POST /api/replay/save` (0 disables the replay buffer).
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_REPLAY_SECONDS)]
+    pub replay_seconds: u64,
+
+    /// Write a timelapse of the capture to an mp4 file.
+    #[arg(long, value_name = "FILE")]
+    pub timelapse: Option<String>,
+
+    /// Seconds between the frames of the timelapse.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TIMELAPSE_INTERVAL)]
+    pub timelapse_interval: u64,
+
+    /// Only write the timelapse, without serving the live stream.
+    #[arg(long, requires = "timelapse")]
+    pub timelapse_only: bool,
+
+    /// Log level (error, warn, info, debug, trace) or a filter like `share_screen=debug,info`.
+    #[arg(long, value_name = "LEVEL", default_value = DEFAULT_LOG_LEVEL)]
+    pub log_level: String,
+
+    /// Layout of the logs: text, or json for one object per line (timestamp, level, target, fields) to ship to Loki or Elastic.
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Write the logs as json, same as `--log-format json`.
+    #[arg(long)]
+    pub log_json: bool,
+
+    /// Append the logs to a file instead of stderr.
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<String>,
+
+    /// Ask the router to forward the http/https ports (UPnP) and log the external url, so viewers outside the LAN can connect.
+    #[arg(long)]
+    pub upnp: bool,
+
+    /// Do not print the QR code of the viewer url once the server is listening.
+    #[arg(long)]
+    pub no_qr: bool,
+
+    /// Never read the console nor show an interactive UI, for supervisors and scripts: a setting that would be asked for
+    /// is an error (exit code 78) and the server runs until Ctrl+C.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Show a tray icon to pause/resume the share, copy the viewer url and quit.
+    #[cfg(all(windows, feature = "tray"))]
+    #[arg(long, conflicts_with = "headless")]
+    pub tray: bool,
+
+    /// Show a live dashboard in the console (fps, bitrate, viewers, logs) with keys to pause, switch the source and change the quality.
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "headless")]
+    pub tui: bool,
+
+    /// Print the hash of a viewer password for `[auth] password_hash` and exit.
+    #[arg(long, value_name = "PASSWORD")]
+    pub hash_password: Option<String>,
+
+    /// Print a random key for `[encryption] key` and exit.
+    #[arg(long)]
+    pub generate_key: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the server as a Windows service, started at boot (the device is read from `[capture]` of the config).
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+
+    /// Encode synthetic frames with every encoder and conversion at several resolutions and print their throughput.
+    Bench {
+        /// Resolutions to benchmark, like 1920x1080, defaults to 720p, 1080p, 1440p and 4k.
+        #[arg(long = "resolution", value_name = "WIDTHxHEIGHT", value_parser = parse_resolution)]
+        resolutions: Vec<(u32, u32)>,
+
+        /// JPEG quality from 1 to 100.
+        #[arg(long, default_value_t = DEFAULT_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Chroma subsampling of the jpeg encoder: 4:4:4, 4:2:2 or 4:2:0.
+        #[arg(long, default_value = "4:4:4")]
+        chroma: ChromaSubsampling,
+
+        /// Seconds spent on each encoder at each resolution.
+        #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+        seconds: f64,
+    },
+
+    /// Print the monitors (number, name, model, resolution, primary) and the cameras (number, name, formats) that can be captured and exit.
+    #[command(visible_alias = "list-devices")]
+    List {
+        /// Print the devices as the json of `/api/devices` instead.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run a tunnel relay that hosts behind a NAT connect out to (`[tunnel]` of their config), serving their viewers on its own port.
+    Tunnel {
+        /// Address the viewers connect to.
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        listen: SocketAddr,
+
+        /// Address the hosts open their tunnel at.
+        #[arg(long, default_value = "0.0.0.0:9000")]
+        hosts: SocketAddr,
+
+        /// Secret the hosts authenticate with, the `[tunnel] secret` of their config.
+        #[arg(long)]
+        secret: String,
+    },
+
+    /// Watch the stream of a server in a native window instead of the browser.
+    #[cfg(feature = "viewer")]
+    View {
+        /// Url of the server, like http://192.168.1.20:5074.
+        url: String,
+
+        /// One of the `[auth] tokens` of the server.
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Accept the self-signed certificate of a server with `[tls]` enabled.
+        #[arg(long)]
+        insecure: bool,
+
+        /// Frames to request, jpeg, qoi (lossless, for a gigabit LAN), zstd (raw deltas, the lowest latency) or auto (qoi when the server is on the local network).
+        #[arg(long, default_value = "jpeg")]
+        codec: LinkCodec,
+    },
+}
+
+#[derive(Subcommand, Clone, Copy)]
+pub enum ServiceAction {
+    /// Register the service with the config file and the log level of this command, requires an elevated console.
+    Install,
+    /// Stop and remove the service.
+    Uninstall,
+    /// Entry point used by the service control manager, not meant to be run from a console.
+    Run,
+}
+
+fn parse_resolution(resolution: &str) -> Result<(u32, u32), String> {
+    share_screen::bench::parse_resolution(resolution).ok_or_else(|| format!("expected WIDTHxHEIGHT, got {resolution}"))
+}
@@ -0,0 +1,115 @@
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::broadcast;
+
+use crate::{
+    delta_frames::ZstdDeltaEncoder,
+    encoder::{FrameEncoder, PngEncoder, QoiEncoder},
+    packets::{frame_packet, parse},
+    state::StreamState,
+};
+
+/// Packets buffered by the broadcast of a feed, lossless frames are large so fewer are kept than on the stream.
+const FEED_CAPACITY: usize = 16;
+
+/// # Codec Feed
+///
+/// The frames of the stream encoded with another codec, for the streams asking for it with `?codec=`.
+/// The packets carry the sequence of the same frame on the stream, and the dimension updates of the stream are sent along.
+pub struct CodecFeed {
+    pub encoder: Arc<dyn FrameEncoder>,
+    pub frames: broadcast::Sender<Vec<u8>>,
+    latest_packet: RwLock<Option<Arc<Vec<u8>>>>,
+}
+
+impl CodecFeed {
+    pub fn new(encoder: Arc<dyn FrameEncoder>) -> Self {
+        Self {
+            encoder,
+            frames: broadcast::channel(FEED_CAPACITY).0,
+            latest_packet: RwLock::new(None),
+        }
+    }
+
+    /// Whether a stream is subscribed, the frames are only encoded with the codec while one is.
+    pub fn is_watched(&self) -> bool {
+        self.frames.receiver_count() > 0
+    }
+
+    /// Broadcasts a frame packet and keeps it as the latest packet, like `StreamState::send_frame`.
+    pub fn send_frame(&self, packet: Vec<u8>) {
+        *self.latest_packet.write().unwrap() = Some(Arc::new(packet.clone()));
+        let _ = self.frames.send(packet);
+    }
+
+    /// # Primed
+    ///
+    /// The latest frame of the stream encoded with the codec, sent first to a stream subscribing to the feed. The frame is encoded
+    /// when the feed has not seen it (nobody watched the feed since) or as a keyframe for a delta codec, `None` while a newer frame
+    /// is being encoded, it is sent next.
+    pub async fn primed(&self, state: &StreamState) -> Option<Arc<Vec<u8>>> {
+        let latest = state.latest_packet()?;
+        let (header, _) = parse(&latest)?;
+
+        //the latest packet of a delta codec is likely a delta
+        let cached = self.latest_packet.read().unwrap().clone().filter(|_| self.encoder.intra_only());
+        if let Some(packet) = cached
+            && parse(&packet).is_some_and(|(latest, _)| latest.sequence == header.sequence)
+        {
+            return Some(packet);
+        }
+
+        let frame = state.latest_raw().filter(|frame| frame.timestamp_us == header.timestamp_us)?;
+        let (encoder, settings) = (self.encoder.clone(), state.control.encode_settings());
+        let encoded = tokio::task::spawn_blocking(move || encoder.encode_key(&frame, settings)).await.ok()?.ok()?;
+
+        Some(Arc::new(frame_packet(header.sequence, header.timestamp_us, &encoded)))
+    }
+}
+
+/// # Codec Feeds
+///
+/// The codecs a stream can ask for besides the one of `[encoder] codec`, the lossless `qoi` and `png` to share text and code over a LAN
+/// without the artifacts of the JPEGs around the characters, and the `zstd` deltas of the native viewer and the relays.
+///
+/// Note: `A feed costs nothing until a stream subscribes to it, then every frame is encoded once more. Relayed streams are not re-encoded,
+/// their feeds stay empty.`
+pub struct CodecFeeds {
+    feeds: Vec<CodecFeed>,
+}
+
+impl CodecFeeds {
+    pub fn new(encoders: Vec<Arc<dyn FrameEncoder>>) -> Self {
+        Self {
+            feeds: encoders.into_iter().map(CodecFeed::new).collect(),
+        }
+    }
+
+    /// The feed of the codec, ignoring case.
+    pub fn get(&self, codec: &str) -> Option<&CodecFeed> {
+        self.feeds.iter().find(|feed| feed.encoder.name().eq_ignore_ascii_case(codec))
+    }
+
+    /// Names of the codecs of the feeds.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.feeds.iter().map(|feed| feed.encoder.name()).collect()
+    }
+
+    /// The feeds a stream is subscribed to.
+    pub fn watched(&self) -> impl Iterator<Item = &CodecFeed> {
+        self.feeds.iter().filter(|feed| feed.is_watched())
+    }
+
+    /// Sends a packet of the stream other than a frame (a dimension update) to the subscribed streams of every feed.
+    pub fn send_control(&self, packet: &[u8]) {
+        for feed in self.watched() {
+            let _ = feed.frames.send(packet.to_vec());
+        }
+    }
+}
+
+impl Default for CodecFeeds {
+    fn default() -> Self {
+        Self::new(vec![Arc::new(QoiEncoder), Arc::new(PngEncoder), Arc::new(ZstdDeltaEncoder::default())])
+    }
+}
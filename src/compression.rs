@@ -0,0 +1,70 @@
+use axum::http::{Extensions, HeaderMap, StatusCode, Version, header::CONTENT_TYPE};
+use serde::Deserialize;
+use tower_http::compression::{
+    CompressionLayer,
+    predicate::{And, Predicate, SizeAbove},
+};
+
+/// Decides whether a response of the app is compressed.
+type CompressionPredicate = And<SizeAbove, fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool>;
+
+/// `[compression]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// responses smaller than this are sent as they are.
+    pub min_bytes: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_bytes: 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// # Layer
+    ///
+    /// Compresses the text and json responses with gzip or deflate, whichever the `Accept-Encoding` of the request prefers,
+    /// `None` when compression is disabled.
+    pub fn layer(&self) -> Option<CompressionLayer<CompressionPredicate>> {
+        if !self.enabled {
+            return None;
+        }
+
+        let min_bytes = u16::try_from(self.min_bytes).unwrap_or(u16::MAX);
+        let compressible_response: fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool =
+            |status, _, headers, _| {
+                status == StatusCode::OK
+                    && headers
+                        .get(CONTENT_TYPE)
+                        .and_then(|content_type| content_type.to_str().ok())
+                        .is_some_and(compressible)
+            };
+
+        Some(
+            CompressionLayer::new().compress_when(SizeAbove::new(min_bytes).and(compressible_response)),
+        )
+    }
+}
+
+/// Whether responses of the content type are worth compressing, text and json but not images or streams.
+pub fn compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    //event streams never end, they are sent as they are produced
+    (essence.starts_with("text/") && essence != "text/event-stream")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/javascript" | "image/svg+xml" | "application/wasm"
+        )
+}
@@ -0,0 +1,121 @@
+use serde::Deserialize;
+
+use crate::{
+    access::AccessConfig, admin::AdminConfig, aggregate::AggregateConfig, audio::AudioConfig, audit::AuditConfig, auth::AuthConfig,
+    autocrop::AutoCropConfig, av1::Av1Config, bandwidth::BandwidthConfig, bitrate::BitrateConfig, blackout::BlackoutConfig, captures::CaptureConfig, chat::ChatConfig, compression::CompressionConfig,
+    control_channel::ControlChannelConfig, cors::CorsConfig, encoder::EncoderConfig,
+    encryption::EncryptionConfig, exclusion::ExclusionConfig, files::FilesConfig, gateway::HttpConfig, hdr::HdrConfig,
+    hotkey::HotkeyConfig, motion::MotionConfig, notify::NotifyConfig, onvif::OnvifConfig, overlay::OverlayConfig, pipeline_stats::StatsConfig, placeholder::PauseConfig,
+    rate_limit::RateLimitConfig, recorder::{RecordingCodec, RecordingFormat}, remote_input::RemoteInputConfig, retention::DiskConfig, scaling::ScalingConfig,
+    schedule::RecordingSchedule, snapshots::SnapshotsConfig,
+    static_files::StaticConfig, tls::TlsConfig, transform::TransformConfig, tunnel::TunnelConfig, viewers::ViewersConfig,
+    virtual_camera::VirtualCameraConfig, vpx::VpxConfig, watchdog::WatchdogConfig, webhooks::Webhook,
+};
+
+/// Config file read when `--config` is not provided.
+pub const DEFAULT_CONFIG_PATH: &str = "share-screen.toml";
+
+/// # Config
+///
+/// Settings read from the toml config file, everything is optional.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub capture: CaptureConfig,
+    pub encoder: EncoderConfig,
+    pub hdr: HdrConfig,
+    pub transform: TransformConfig,
+    pub autocrop: AutoCropConfig,
+    pub scaling: ScalingConfig,
+    pub overlay: OverlayConfig,
+    pub exclusion: ExclusionConfig,
+    pub blackout: BlackoutConfig,
+    pub bandwidth: BandwidthConfig,
+    pub bitrate: BitrateConfig,
+    pub recording: RecordingConfig,
+    pub snapshots: SnapshotsConfig,
+    pub disk: DiskConfig,
+    pub motion: MotionConfig,
+    pub webhooks: Vec<Webhook>,
+    pub audio: AudioConfig,
+    pub control: ControlChannelConfig,
+    pub admin: AdminConfig,
+    pub auth: AuthConfig,
+    pub audit: AuditConfig,
+    pub watchdog: WatchdogConfig,
+    pub stats: StatsConfig,
+    pub viewers: ViewersConfig,
+    pub notify: NotifyConfig,
+    pub tls: TlsConfig,
+    pub access: AccessConfig,
+    pub rate_limit: RateLimitConfig,
+    pub encryption: EncryptionConfig,
+    pub cors: CorsConfig,
+    pub static_files: StaticConfig,
+    pub compression: CompressionConfig,
+    pub http: HttpConfig,
+    pub hotkey: HotkeyConfig,
+    pub pause: PauseConfig,
+    pub virtual_camera: VirtualCameraConfig,
+    pub remote_input: RemoteInputConfig,
+    pub files: FilesConfig,
+    pub chat: ChatConfig,
+    pub onvif: OnvifConfig,
+    pub aggregate: AggregateConfig,
+    pub tunnel: TunnelConfig,
+    #[cfg(feature = "grpc")]
+    pub grpc: crate::grpc::GrpcConfig,
+}
+
+/// `[recording]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// windows of time the recorder runs automatically.
+    pub schedules: Vec<RecordingSchedule>,
+    /// format of the recordings whose path has no `.mp4`, `.mkv` or `.webm` extension, and of the ones named automatically.
+    pub format: RecordingFormat,
+    /// video codec of the recordings, `mjpeg` (the frames of the stream) or `av1`, `vp8` and `vp9` (encoded again, Matroska or WebM).
+    pub codec: RecordingCodec,
+    /// settings of the `av1` codec.
+    pub av1: Av1Config,
+    /// settings of the `vp8` and `vp9` codecs.
+    pub vpx: VpxConfig,
+    /// days the recordings of the recordings folder are kept, older ones are deleted, 0 keeps them all.
+    pub max_days: u32,
+    /// most megabytes of recordings kept in the recordings folder, the oldest are deleted first, 0 is unlimited.
+    pub max_mb: u64,
+}
+
+impl Config {
+    /// # Load config
+    ///
+    /// Reads the config file at the path, a missing file at the default path results in the default config.
+    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = path.unwrap_or(DEFAULT_CONFIG_PATH);
+
+        let content = match std::fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) if path.is_none() && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(format!("Failed to read config {file}: {e}").into()),
+        };
+
+        Self::parse(&content).map_err(|e| format!("Invalid config {file}: {e}").into())
+    }
+
+    /// Parses and validates the content of a config file.
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config: Config = toml::from_str(content)?;
+
+        for schedule in &config.recording.schedules {
+            schedule.validate()?;
+        }
+
+        config.access.validate()?;
+        config.aggregate.validate()?;
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tracing::{info, warn};
+
+use crate::{config::Config, state::StreamState};
+
+/// How often the config file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keys of the config applied at runtime as `section.key`, `section.*` for a whole section.
+/// Everything else is only read when the pipeline is built.
+pub const RELOADABLE_KEYS: &[&str] = &[
+    "encoder.quality",
+    "encoder.fps",
+    "encoder.grayscale",
+    "capture.mirror",
+    "scaling.scale",
+    "auth.*",
+];
+
+/// # Spawn Config Reload
+///
+/// Spawns a task that watches the config file and applies the changes of the `RELOADABLE_KEYS` to the running stream:
+/// the quality, the fps, the grayscale mode, the mirror, the scale and the `[auth]` tokens and password.
+/// The other changes need a restart, they are logged and skipped.
+///
+/// Note: `An invalid file (half written by an editor, a typo) is reported and ignored until the next change, the stream keeps its settings.`
+pub fn spawn_config_reload(path: String, state: Arc<StreamState>) {
+    tokio::spawn(async move {
+        let mut modified = modified_at(&path).await;
+        //what the running settings were read from, changes are relative to it
+        let mut applied = read_table(&path).await.unwrap_or_default();
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(RELOAD_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+
+            let current = modified_at(&path).await;
+
+            if current == modified {
+                continue;
+            }
+            modified = current;
+
+            //a deleted file is a config of defaults
+            let content = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+
+            let parsed = Config::parse(&content).and_then(|config| Ok((config, content.parse::<toml::Table>()?)));
+
+            let (config, table) = match parsed {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    warn!(%path, error = %e, "Ignoring the invalid config file");
+                    continue;
+                }
+            };
+
+            let changed = changed_keys(&applied, &table);
+
+            if changed.is_empty() {
+                continue;
+            }
+
+            let (reloaded, skipped): (Vec<String>, Vec<String>) = changed.into_iter().partition(|key| is_reloadable(key));
+
+            apply(&config, &reloaded, &state);
+
+            if !reloaded.is_empty() {
+                info!(keys = ?reloaded, "Reloaded the config file");
+            }
+
+            if !skipped.is_empty() {
+                warn!(keys = ?skipped, "Changes of the config file that need a restart, skipped");
+            }
+
+            applied = table;
+        }
+    });
+}
+
+/// Whether a `section.key` is applied at runtime.
+fn is_reloadable(key: &str) -> bool {
+    RELOADABLE_KEYS.iter().any(|reloadable| match reloadable.strip_suffix(".*") {
+        Some(section) => key == section || key.strip_prefix(section).is_some_and(|rest| rest.starts_with('.')),
+        None => key == *reloadable,
+    })
+}
+
+/// Applies the reloaded keys of the config to the stream.
+fn apply(config: &Config, keys: &[String], state: &StreamState) {
+    let mut auth_reloaded = false;
+
+    for key in keys {
+        match key.as_str() {
+            //a removed quality or fps keeps the current one
+            "encoder.quality" => {
+                if let Some(quality) = config.encoder.quality {
+                    state.control.set_quality(quality);
+                }
+            }
+            "encoder.fps" => {
+                if let Some(fps) = config.encoder.fps {
+                    state.control.set_max_fps(fps);
+                }
+            }
+            "encoder.grayscale" => state.control.set_grayscale(config.encoder.grayscale),
+            "capture.mirror" => state.transform.set_mirrored(config.capture.mirror),
+            "scaling.scale" => state.scaler.set_scale(config.scaling.scale),
+            _ if !auth_reloaded && (key == "auth" || key.starts_with("auth.")) => {
+                state.auth.reload(config.auth.clone());
+                auth_reloaded = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// # Changed Keys
+///
+/// The keys that differ between two config files, as `section.key` for the keys of the sections (a missing section has no keys)
+/// and `key` for the rest (arrays of tables like `[[webhooks]]`, top level values).
+fn changed_keys(old: &toml::Table, new: &toml::Table) -> Vec<String> {
+    let empty = toml::Table::new();
+    let as_section = |value: Option<&toml::Value>| match value {
+        None => Some(&empty),
+        Some(toml::Value::Table(table)) => Some(table),
+        Some(_) => None,
+    };
+
+    let mut changed = Vec::new();
+    let mut sections: Vec<&String> = old.keys().chain(new.keys()).collect();
+    sections.sort();
+    sections.dedup();
+
+    for section in sections {
+        match (as_section(old.get(section)), as_section(new.get(section))) {
+            (Some(old), Some(new)) => {
+                let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+                keys.sort();
+                keys.dedup();
+
+                changed.extend(
+                    keys.into_iter()
+                        .filter(|key| old.get(*key) != new.get(*key))
+                        .map(|key| format!("{section}.{key}")),
+                );
+            }
+            _ if old.get(section) != new.get(section) => changed.push(section.clone()),
+            _ => {}
+        }
+    }
+
+    changed
+}
+
+async fn modified_at(path: &str) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
+
+async fn read_table(path: &str) -> Option<toml::Table> {
+    tokio::fs::read_to_string(path).await.ok()?.parse().ok()
+}
@@ -0,0 +1,489 @@
+use std::time::Duration;
+
+use super::Muxer;
+use crate::audio::{CHANNELS, CHUNK_SAMPLES, SAMPLE_RATE};
+
+/// Timescale of the video track, 90kHz like most video containers.
+const TIMESCALE: u32 = 90_000;
+
+const VIDEO_TRACK: u32 = 1;
+const AUDIO_TRACK: u32 = 2;
+
+/// Samples the Opus decoder discards at the start of the stream (encoder lookahead at 48kHz).
+const OPUS_PRE_SKIP: u16 = 312;
+
+/// MPEG-4 object type indication for JPEG (ISO/IEC 10918-1).
+const JPEG_OBJECT_TYPE: u8 = 0x6C;
+
+/// # Fragmented MP4 Muxer
+///
+/// Muxes JPEG frames into a fragmented MP4 (ISO BMFF) stream: an init segment (ftyp + moov) followed by one moof + mdat fragment per frame.
+///
+/// Frames are carried as an `mp4v` sample entry with the JPEG object type, which players like mpv/ffplay decode as MJPEG.
+/// With audio enabled an `Opus` track is added and audio packets are muxed as their own fragments, both tracks share the stream clock.
+pub struct Fmp4Muxer {
+    width: u16,
+    height: u16,
+    audio: bool,
+    sequence: u32,
+    last_pts: Option<u64>,
+}
+
+impl Fmp4Muxer {
+    /// Creates a muxer for frames of the given size.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width.min(u16::MAX as u32) as u16,
+            height: height.min(u16::MAX as u32) as u16,
+            audio: false,
+            sequence: 0,
+            last_pts: None,
+        }
+    }
+
+    /// Adds an Opus audio track (48kHz stereo) to the stream.
+    pub fn with_audio(mut self) -> Self {
+        self.audio = true;
+        self
+    }
+
+    fn moov(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+        children.extend(mvhd(if self.audio { 3 } else { 2 }));
+        children.extend(self.trak());
+
+        let mut mvex = trex(VIDEO_TRACK);
+
+        if self.audio {
+            children.extend(audio_trak());
+            mvex.extend(trex(AUDIO_TRACK));
+        }
+
+        children.extend(mp4_box(b"mvex", &mvex));
+
+        mp4_box(b"moov", &children)
+    }
+
+    fn trak(&self) -> Vec<u8> {
+        let mut tkhd = Vec::new();
+        tkhd.extend(0u32.to_be_bytes()); //creation time
+        tkhd.extend(0u32.to_be_bytes()); //modification time
+        tkhd.extend(VIDEO_TRACK.to_be_bytes());
+        tkhd.extend(0u32.to_be_bytes()); //reserved
+        tkhd.extend(0u32.to_be_bytes()); //duration
+        tkhd.extend([0u8; 8]); //reserved
+        tkhd.extend(0u16.to_be_bytes()); //layer
+        tkhd.extend(0u16.to_be_bytes()); //alternate group
+        tkhd.extend(0u16.to_be_bytes()); //volume
+        tkhd.extend(0u16.to_be_bytes()); //reserved
+        tkhd.extend(matrix());
+        tkhd.extend(((self.width as u32) << 16).to_be_bytes());
+        tkhd.extend(((self.height as u32) << 16).to_be_bytes());
+
+        let mut vmhd = Vec::new();
+        vmhd.extend(0u16.to_be_bytes()); //graphics mode
+        vmhd.extend([0u8; 6]); //opcolor
+
+        trak(
+            &tkhd,
+            TIMESCALE,
+            b"vide",
+            b"VideoHandler\0",
+            &full_box(b"vmhd", 0, 1, &vmhd),
+            &self.stbl(),
+        )
+    }
+
+    fn stbl(&self) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend([0u8; 6]); //reserved
+        entry.extend(1u16.to_be_bytes()); //data reference index
+        entry.extend([0u8; 16]); //pre defined + reserved
+        entry.extend(self.width.to_be_bytes());
+        entry.extend(self.height.to_be_bytes());
+        entry.extend(0x0048_0000u32.to_be_bytes()); //72 dpi
+        entry.extend(0x0048_0000u32.to_be_bytes());
+        entry.extend(0u32.to_be_bytes()); //reserved
+        entry.extend(1u16.to_be_bytes()); //frame count
+        entry.extend([0u8; 32]); //compressor name
+        entry.extend(0x0018u16.to_be_bytes()); //depth
+        entry.extend((-1i16).to_be_bytes());
+        entry.extend(esds());
+
+        stbl(&mp4_box(b"mp4v", &entry))
+    }
+
+    /// Wraps a single sample into a moof + mdat fragment of the track.
+    fn fragment(&mut self, track_id: u32, decode_time: u64, duration: u32, data: &[u8]) -> Vec<u8> {
+        self.sequence += 1;
+
+        let mut tfhd = Vec::new();
+        tfhd.extend(track_id.to_be_bytes());
+
+        let mut trun = Vec::new();
+        trun.extend(1u32.to_be_bytes()); //sample count
+        trun.extend(0u32.to_be_bytes()); //data offset, patched below
+        trun.extend(duration.to_be_bytes());
+        trun.extend((data.len() as u32).to_be_bytes());
+
+        let mut traf = Vec::new();
+        traf.extend(full_box(b"tfhd", 0, 0x02_0000, &tfhd)); //default base is moof
+        traf.extend(full_box(b"tfdt", 1, 0, &decode_time.to_be_bytes()));
+        let trun_offset = traf.len() + 8 + 8; //trun header + sample count
+        traf.extend(full_box(b"trun", 0, 0x301, &trun));
+
+        let mut moof = Vec::new();
+        moof.extend(full_box(b"mfhd", 0, 0, &self.sequence.to_be_bytes()));
+        let traf_offset = moof.len() + 8;
+        moof.extend(mp4_box(b"traf", &traf));
+
+        let mut fragment = mp4_box(b"moof", &moof);
+
+        //data starts right after the mdat header following the moof
+        let data_offset = (fragment.len() + 8) as u32;
+        let patch = 8 + traf_offset + trun_offset;
+        fragment[patch..patch + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+        fragment.extend(((data.len() + 8) as u32).to_be_bytes());
+        fragment.extend(b"mdat");
+        fragment.extend_from_slice(data);
+
+        fragment
+    }
+}
+
+impl Muxer for Fmp4Muxer {
+    const CONTENT_TYPE: &'static str = "video/mp4";
+
+    fn header(&mut self) -> Vec<u8> {
+        let mut ftyp = Vec::new();
+        ftyp.extend(b"isom");
+        ftyp.extend(0x200u32.to_be_bytes());
+        ftyp.extend(b"isomiso6mp41");
+
+        let mut header = mp4_box(b"ftyp", &ftyp);
+        header.extend(self.moov());
+
+        header
+    }
+
+    fn mux(&mut self, jpeg: &[u8], pts: Duration) -> Vec<u8> {
+        let pts = (pts.as_micros() as u64 * TIMESCALE as u64) / 1_000_000;
+
+        //the duration of a frame is unknown until the next arrives, use the previous frame delta instead.
+        let duration = match self.last_pts {
+            Some(last) => pts.saturating_sub(last).max(1),
+            None => (TIMESCALE / 30) as u64,
+        };
+        self.last_pts = Some(pts);
+
+        //decode time is the presentation time so the audio track lines up with it
+        self.fragment(VIDEO_TRACK, pts, duration as u32, jpeg)
+    }
+
+    fn mux_audio(&mut self, opus: &[u8], pts: Duration) -> Vec<u8> {
+        if !self.audio {
+            return Vec::new();
+        }
+
+        let pts = (pts.as_micros() as u64 * SAMPLE_RATE as u64) / 1_000_000;
+
+        self.fragment(AUDIO_TRACK, pts, CHUNK_SAMPLES as u32, opus)
+    }
+}
+
+fn audio_trak() -> Vec<u8> {
+    let mut tkhd = Vec::new();
+    tkhd.extend(0u32.to_be_bytes()); //creation time
+    tkhd.extend(0u32.to_be_bytes()); //modification time
+    tkhd.extend(AUDIO_TRACK.to_be_bytes());
+    tkhd.extend(0u32.to_be_bytes()); //reserved
+    tkhd.extend(0u32.to_be_bytes()); //duration
+    tkhd.extend([0u8; 8]); //reserved
+    tkhd.extend(0u16.to_be_bytes()); //layer
+    tkhd.extend(0u16.to_be_bytes()); //alternate group
+    tkhd.extend(0x0100u16.to_be_bytes()); //volume
+    tkhd.extend(0u16.to_be_bytes()); //reserved
+    tkhd.extend(matrix());
+    tkhd.extend(0u32.to_be_bytes()); //width
+    tkhd.extend(0u32.to_be_bytes()); //height
+
+    //Opus specific box (big endian, unlike the Ogg header)
+    let mut dops = vec![0u8, CHANNELS as u8]; //version, output channel count
+    dops.extend(OPUS_PRE_SKIP.to_be_bytes());
+    dops.extend(SAMPLE_RATE.to_be_bytes()); //input sample rate
+    dops.extend(0i16.to_be_bytes()); //output gain
+    dops.push(0); //channel mapping family
+
+    let mut entry = Vec::new();
+    entry.extend([0u8; 6]); //reserved
+    entry.extend(1u16.to_be_bytes()); //data reference index
+    entry.extend([0u8; 8]); //reserved
+    entry.extend((CHANNELS as u16).to_be_bytes());
+    entry.extend(16u16.to_be_bytes()); //sample size
+    entry.extend(0u16.to_be_bytes()); //pre defined
+    entry.extend(0u16.to_be_bytes()); //reserved
+    entry.extend((SAMPLE_RATE << 16).to_be_bytes());
+    entry.extend(mp4_box(b"dOps", &dops));
+
+    let mut smhd = Vec::new();
+    smhd.extend(0i16.to_be_bytes()); //balance
+    smhd.extend(0u16.to_be_bytes()); //reserved
+
+    trak(
+        &tkhd,
+        SAMPLE_RATE,
+        b"soun",
+        b"SoundHandler\0",
+        &full_box(b"smhd", 0, 0, &smhd),
+        &stbl(&mp4_box(b"Opus", &entry)),
+    )
+}
+
+/// Builds a track from its header, the media header box (vmhd/smhd) and sample table.
+fn trak(
+    tkhd: &[u8],
+    timescale: u32,
+    handler: &[u8; 4],
+    handler_name: &[u8],
+    media_header: &[u8],
+    stbl: &[u8],
+) -> Vec<u8> {
+    let mut mdhd = Vec::new();
+    mdhd.extend(0u32.to_be_bytes()); //creation time
+    mdhd.extend(0u32.to_be_bytes()); //modification time
+    mdhd.extend(timescale.to_be_bytes());
+    mdhd.extend(0u32.to_be_bytes()); //duration
+    mdhd.extend(0x55C4u16.to_be_bytes()); //language 'und'
+    mdhd.extend(0u16.to_be_bytes());
+
+    let mut hdlr = Vec::new();
+    hdlr.extend(0u32.to_be_bytes());
+    hdlr.extend(handler);
+    hdlr.extend([0u8; 12]);
+    hdlr.extend(handler_name);
+
+    let mut dref = Vec::new();
+    dref.extend(1u32.to_be_bytes());
+    dref.extend(full_box(b"url ", 0, 1, &[]));
+
+    let mut minf = Vec::new();
+    minf.extend_from_slice(media_header);
+    minf.extend(mp4_box(b"dinf", &full_box(b"dref", 0, 0, &dref)));
+    minf.extend_from_slice(stbl);
+
+    let mut mdia = Vec::new();
+    mdia.extend(full_box(b"mdhd", 0, 0, &mdhd));
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+    mdia.extend(mp4_box(b"minf", &minf));
+
+    let mut trak = Vec::new();
+    trak.extend(full_box(b"tkhd", 0, 0x3, tkhd));
+    trak.extend(mp4_box(b"mdia", &mdia));
+
+    mp4_box(b"trak", &trak)
+}
+
+/// Empty sample table (samples are in the fragments) with a single sample entry.
+fn stbl(sample_entry: &[u8]) -> Vec<u8> {
+    let mut stsd = Vec::new();
+    stsd.extend(1u32.to_be_bytes());
+    stsd.extend_from_slice(sample_entry);
+
+    let mut stbl = Vec::new();
+    stbl.extend(full_box(b"stsd", 0, 0, &stsd));
+    stbl.extend(full_box(b"stts", 0, 0, &0u32.to_be_bytes()));
+    stbl.extend(full_box(b"stsc", 0, 0, &0u32.to_be_bytes()));
+    stbl.extend(full_box(b"stsz", 0, 0, &[0u8; 8]));
+    stbl.extend(full_box(b"stco", 0, 0, &0u32.to_be_bytes()));
+
+    mp4_box(b"stbl", &stbl)
+}
+
+fn mvhd(next_track_id: u32) -> Vec<u8> {
+    let mut mvhd = Vec::new();
+    mvhd.extend(0u32.to_be_bytes()); //creation time
+    mvhd.extend(0u32.to_be_bytes()); //modification time
+    mvhd.extend(1000u32.to_be_bytes()); //timescale
+    mvhd.extend(0u32.to_be_bytes()); //duration
+    mvhd.extend(0x0001_0000u32.to_be_bytes()); //rate
+    mvhd.extend(0x0100u16.to_be_bytes()); //volume
+    mvhd.extend([0u8; 10]); //reserved
+    mvhd.extend(matrix());
+    mvhd.extend([0u8; 24]); //pre defined
+    mvhd.extend(next_track_id.to_be_bytes());
+
+    full_box(b"mvhd", 0, 0, &mvhd)
+}
+
+fn trex(track_id: u32) -> Vec<u8> {
+    let mut trex = Vec::new();
+    trex.extend(track_id.to_be_bytes());
+    trex.extend(1u32.to_be_bytes()); //default sample description index
+    trex.extend(0u32.to_be_bytes()); //default sample duration
+    trex.extend(0u32.to_be_bytes()); //default sample size
+    trex.extend(0u32.to_be_bytes()); //default sample flags (sync)
+
+    full_box(b"trex", 0, 0, &trex)
+}
+
+fn esds() -> Vec<u8> {
+    //decoder config: object type, stream type (visual), buffer size, max and avg bitrate
+    let mut decoder_config = vec![JPEG_OBJECT_TYPE, (0x04 << 2) | 1, 0, 0, 0];
+    decoder_config.extend(0u32.to_be_bytes());
+    decoder_config.extend(0u32.to_be_bytes());
+
+    let mut es = Vec::new();
+    es.extend(1u16.to_be_bytes()); //es id
+    es.push(0); //flags
+    es.extend(descriptor(0x04, &decoder_config));
+    es.extend(descriptor(0x06, &[0x02]));
+
+    full_box(b"esds", 0, 0, &descriptor(0x03, &es))
+}
+
+fn descriptor(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut descriptor = vec![tag, payload.len() as u8];
+    descriptor.extend_from_slice(payload);
+    descriptor
+}
+
+fn matrix() -> Vec<u8> {
+    let mut matrix = Vec::with_capacity(36);
+    for value in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        matrix.extend(value.to_be_bytes());
+    }
+    matrix
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut mp4_box = Vec::with_capacity(8 + payload.len());
+    mp4_box.extend(((8 + payload.len()) as u32).to_be_bytes());
+    mp4_box.extend(kind);
+    mp4_box.extend_from_slice(payload);
+    mp4_box
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut full = Vec::with_capacity(4 + payload.len());
+    full.push(version);
+    full.extend(&flags.to_be_bytes()[1..]);
+    full.extend_from_slice(payload);
+    mp4_box(kind, &full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The boxes of the data in order, their type and payload.
+    fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = Vec::new();
+
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            assert!(size >= 8 && size <= data.len(), "box of {size} bytes in {} bytes", data.len());
+
+            boxes.push((&data[4..8], &data[8..size]));
+            data = &data[size..];
+        }
+
+        assert!(data.is_empty(), "{} bytes left after the boxes", data.len());
+        boxes
+    }
+
+    /// The payload of the box at the path of box types, through the boxes of the types before it.
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        let (first, rest) = path.split_first().unwrap();
+        let (_, payload) = boxes(data)
+            .into_iter()
+            .find(|(kind, _)| kind == first)
+            .unwrap_or_else(|| panic!("no {} box", String::from_utf8_lossy(*first)));
+
+        match rest.is_empty() {
+            true => payload,
+            false => find(payload, rest),
+        }
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn starts_with_the_init_segment() {
+        let header = Fmp4Muxer::new(1920, 1080).header();
+        let kinds: Vec<&[u8]> = boxes(&header).into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"ftyp".as_slice(), b"moov"]);
+
+        let moov = find(&header, &[b"moov"]);
+        let traks = boxes(moov).into_iter().filter(|(kind, _)| *kind == b"trak").count();
+        assert_eq!(traks, 1);
+
+        //the width and height in 16.16 fixed point end the track header
+        let tkhd = find(moov, &[b"trak", b"tkhd"]);
+        assert_eq!(u32_at(tkhd, tkhd.len() - 8), 1920 << 16);
+        assert_eq!(u32_at(tkhd, tkhd.len() - 4), 1080 << 16);
+
+        let stsd = find(moov, &[b"trak", b"mdia", b"minf", b"stbl", b"stsd"]);
+        assert_eq!(&stsd[12..16], b"mp4v");
+    }
+
+    #[test]
+    fn adds_the_opus_track_with_audio() {
+        let header = Fmp4Muxer::new(1280, 720).with_audio().header();
+        let moov = find(&header, &[b"moov"]);
+
+        let handlers: Vec<&[u8]> = boxes(moov)
+            .into_iter()
+            .filter(|(kind, _)| *kind == b"trak")
+            .map(|(_, trak)| &find(trak, &[b"mdia", b"hdlr"])[8..12])
+            .collect();
+        assert_eq!(handlers, [b"vide".as_slice(), b"soun"]);
+
+        let trexs = boxes(find(moov, &[b"mvex"])).len();
+        assert_eq!(trexs, 2);
+    }
+
+    #[test]
+    fn carries_a_frame_per_fragment() {
+        let mut muxer = Fmp4Muxer::new(640, 480);
+        let jpeg = [0xFF, 0xD8, 1, 2, 3, 0xFF, 0xD9];
+
+        let first = muxer.mux(&jpeg, Duration::from_secs(1));
+        let second = muxer.mux(&jpeg, Duration::from_millis(1_040));
+
+        for (fragment, sequence, decode_time, duration) in [(&first, 1, 90_000, 3_000), (&second, 2, 93_600, 3_600)] {
+            let kinds: Vec<&[u8]> = boxes(fragment).into_iter().map(|(kind, _)| kind).collect();
+            assert_eq!(kinds, [b"moof".as_slice(), b"mdat"]);
+            assert_eq!(find(fragment, &[b"mdat"]), jpeg);
+
+            assert_eq!(u32_at(find(fragment, &[b"moof", b"mfhd"]), 4), sequence);
+            assert_eq!(u32_at(find(fragment, &[b"moof", b"traf", b"tfhd"]), 4), VIDEO_TRACK);
+
+            let tfdt = find(fragment, &[b"moof", b"traf", b"tfdt"]);
+            assert_eq!(u64::from_be_bytes(tfdt[4..12].try_into().unwrap()), decode_time);
+
+            //the data offset of the run points at the frame in the mdat
+            let trun = find(fragment, &[b"moof", b"traf", b"trun"]);
+            let offset = u32_at(trun, 8) as usize;
+            assert_eq!(&fragment[offset..], jpeg);
+            assert_eq!(u32_at(trun, 12), duration);
+            assert_eq!(u32_at(trun, 16), jpeg.len() as u32);
+        }
+    }
+
+    #[test]
+    fn muxes_the_audio_on_its_track() {
+        let opus = [0x7C, 1, 2, 3];
+
+        assert!(Fmp4Muxer::new(640, 480).mux_audio(&opus, Duration::ZERO).is_empty());
+
+        let fragment = Fmp4Muxer::new(640, 480).with_audio().mux_audio(&opus, Duration::from_millis(500));
+        assert_eq!(u32_at(find(&fragment, &[b"moof", b"traf", b"tfhd"]), 4), AUDIO_TRACK);
+
+        let tfdt = find(&fragment, &[b"moof", b"traf", b"tfdt"]);
+        assert_eq!(u64::from_be_bytes(tfdt[4..12].try_into().unwrap()), SAMPLE_RATE as u64 / 2);
+        assert_eq!(find(&fragment, &[b"mdat"]), opus);
+    }
+}
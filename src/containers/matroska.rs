@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use super::Muxer;
+use crate::audio::{CHANNELS, SAMPLE_RATE};
+
+/// Ticks of the block timestamps in nanoseconds, milliseconds like most Matroska files.
+const TIMESTAMP_SCALE: u64 = 1_000_000;
+
+const VIDEO_TRACK: u64 = 1;
+const AUDIO_TRACK: u64 = 2;
+
+/// Samples the Opus decoder discards at the start of the stream (encoder lookahead at 48kHz).
+const OPUS_PRE_SKIP: u16 = 312;
+/// Time the Opus decoder needs to converge after a seek, in nanoseconds, 80ms as the Matroska Opus mapping recommends.
+const OPUS_SEEK_PRE_ROLL: u64 = 80_000_000;
+
+/// Size of an element whose end is the end of the file, so the segment needs no size written once the recording stops.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE_ID: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CODEC_DELAY: u32 = 0x56AA;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const AUDIO_CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43_B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// # Matroska Muxer
+///
+/// Muxes JPEG frames into a Matroska stream: the EBML header, a segment of unknown size with the track infos,
+/// then one cluster per frame holding a single `V_MJPEG` keyframe.
+///
+/// Note: `Every cluster is complete once written and nothing is written back when the recording stops,
+/// so a file cut short by a crash plays up to its last cluster. There are no cues, players seek by scanning the clusters.`
+///
+/// With audio enabled an `A_OPUS` track is added and audio packets are muxed as their own clusters, both tracks share the stream clock.
+/// With `with_video_codec` the video track is of an inter-frame codec instead (`V_AV1`, `V_VP9`...), its blocks flagged as keyframes
+/// only when they are, and `as_webm` writes the `webm` doctype for the files of the WebM codecs.
+pub struct MatroskaMuxer {
+    width: u32,
+    height: u32,
+    audio: bool,
+    /// the codec id and the codec private data of the video track, `None` for MJPEG.
+    video_codec: Option<(&'static str, Vec<u8>)>,
+    doc_type: &'static str,
+}
+
+impl MatroskaMuxer {
+    /// Creates a muxer for frames of the given size.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            audio: false,
+            video_codec: None,
+            doc_type: "matroska",
+        }
+    }
+
+    /// Adds an Opus audio track (48kHz stereo) to the stream.
+    pub fn with_audio(mut self) -> Self {
+        self.audio = true;
+        self
+    }
+
+    /// Makes the video track of the codec, with the codec private data of the encoder (the `av1C` of AV1), empty for none.
+    pub fn with_video_codec(mut self, codec_id: &'static str, codec_private: Vec<u8>) -> Self {
+        self.video_codec = Some((codec_id, codec_private));
+        self
+    }
+
+    /// Writes the `webm` doctype, the tracks have to be VP8, VP9 or AV1 and Opus.
+    pub fn as_webm(mut self) -> Self {
+        self.doc_type = "webm";
+        self
+    }
+
+    fn tracks(&self) -> Vec<u8> {
+        let mut video = Vec::new();
+        video.extend(uint(PIXEL_WIDTH, self.width as u64));
+        video.extend(uint(PIXEL_HEIGHT, self.height as u64));
+
+        let mut entry = Vec::new();
+        entry.extend(uint(TRACK_NUMBER, VIDEO_TRACK));
+        entry.extend(uint(TRACK_UID, VIDEO_TRACK));
+        entry.extend(uint(TRACK_TYPE, 1));
+        entry.extend(uint(FLAG_LACING, 0));
+        match &self.video_codec {
+            Some((codec_id, codec_private)) => {
+                entry.extend(element(CODEC_ID, codec_id.as_bytes()));
+                if !codec_private.is_empty() {
+                    entry.extend(element(CODEC_PRIVATE, codec_private));
+                }
+            }
+            None => entry.extend(element(CODEC_ID, b"V_MJPEG")),
+        }
+        entry.extend(element(VIDEO, &video));
+
+        let mut tracks = element(TRACK_ENTRY, &entry);
+
+        if self.audio {
+            tracks.extend(audio_track());
+        }
+
+        element(TRACKS, &tracks)
+    }
+}
+
+impl Muxer for MatroskaMuxer {
+    const CONTENT_TYPE: &'static str = "video/x-matroska";
+
+    fn header(&mut self) -> Vec<u8> {
+        let mut ebml = Vec::new();
+        ebml.extend(uint(EBML_VERSION, 1));
+        ebml.extend(uint(EBML_READ_VERSION, 1));
+        ebml.extend(uint(EBML_MAX_ID_LENGTH, 4));
+        ebml.extend(uint(EBML_MAX_SIZE_LENGTH, 8));
+        ebml.extend(element(DOC_TYPE, self.doc_type.as_bytes()));
+        ebml.extend(uint(DOC_TYPE_VERSION, 4));
+        ebml.extend(uint(DOC_TYPE_READ_VERSION, 2));
+
+        let mut info = Vec::new();
+        info.extend(uint(TIMESTAMP_SCALE_ID, TIMESTAMP_SCALE));
+        info.extend(element(MUXING_APP, b"share-screen"));
+        info.extend(element(WRITING_APP, b"share-screen"));
+
+        let mut header = element(EBML, &ebml);
+        header.extend(id(SEGMENT));
+        header.extend(UNKNOWN_SIZE);
+        header.extend(element(INFO, &info));
+        header.extend(self.tracks());
+
+        header
+    }
+
+    fn mux(&mut self, jpeg: &[u8], pts: Duration) -> Vec<u8> {
+        cluster(VIDEO_TRACK, pts, jpeg, true)
+    }
+
+    fn mux_coded(&mut self, frame: &[u8], pts: Duration, keyframe: bool) -> Vec<u8> {
+        cluster(VIDEO_TRACK, pts, frame, keyframe)
+    }
+
+    fn mux_audio(&mut self, opus: &[u8], pts: Duration) -> Vec<u8> {
+        if !self.audio {
+            return Vec::new();
+        }
+
+        cluster(AUDIO_TRACK, pts, opus, true)
+    }
+}
+
+fn audio_track() -> Vec<u8> {
+    //OpusHead of the Ogg mapping (little endian, unlike the dOps box of mp4)
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); //version
+    head.push(CHANNELS as u8);
+    head.extend(OPUS_PRE_SKIP.to_le_bytes());
+    head.extend(SAMPLE_RATE.to_le_bytes()); //input sample rate
+    head.extend(0i16.to_le_bytes()); //output gain
+    head.push(0); //channel mapping family
+
+    let mut audio = Vec::new();
+    audio.extend(element(SAMPLING_FREQUENCY, &(SAMPLE_RATE as f64).to_be_bytes()));
+    audio.extend(uint(AUDIO_CHANNELS, CHANNELS as u64));
+
+    let mut entry = Vec::new();
+    entry.extend(uint(TRACK_NUMBER, AUDIO_TRACK));
+    entry.extend(uint(TRACK_UID, AUDIO_TRACK));
+    entry.extend(uint(TRACK_TYPE, 2));
+    entry.extend(uint(FLAG_LACING, 0));
+    entry.extend(element(CODEC_ID, b"A_OPUS"));
+    entry.extend(element(CODEC_PRIVATE, &head));
+    entry.extend(uint(CODEC_DELAY, OPUS_PRE_SKIP as u64 * 1_000_000_000 / SAMPLE_RATE as u64));
+    entry.extend(uint(SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL));
+    entry.extend(element(AUDIO, &audio));
+
+    element(TRACK_ENTRY, &entry)
+}
+
+/// A cluster at `pts` holding a single frame of the track.
+fn cluster(track: u64, pts: Duration, data: &[u8], keyframe: bool) -> Vec<u8> {
+    //track number, timestamp relative to the cluster, keyframe flag
+    let mut block = vint(track);
+    block.extend(0i16.to_be_bytes());
+    block.push(if keyframe { 0x80 } else { 0 });
+    block.extend_from_slice(data);
+
+    let mut cluster = uint(CLUSTER_TIMESTAMP, pts.as_nanos() as u64 / TIMESTAMP_SCALE);
+    cluster.extend(element(SIMPLE_BLOCK, &block));
+
+    element(CLUSTER, &cluster)
+}
+
+/// An element with its id, the size of the payload and the payload.
+fn element(element_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut element = id(element_id);
+    element.extend(vint(payload.len() as u64));
+    element.extend_from_slice(payload);
+    element
+}
+
+/// An unsigned integer element, in as few bytes as the value needs.
+fn uint(element_id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = (value.leading_zeros() / 8).min(7) as usize;
+
+    element(element_id, &bytes[skip..])
+}
+
+/// The bytes of an element id, the length marker is part of the id.
+fn id(element_id: u32) -> Vec<u8> {
+    let bytes = element_id.to_be_bytes();
+    let skip = (element_id.leading_zeros() / 8).min(3) as usize;
+
+    bytes[skip..].to_vec()
+}
+
+/// A variable size integer: the count of leading zero bits of the first byte tells the length, 1 to 8 bytes.
+fn vint(value: u64) -> Vec<u8> {
+    //all ones is reserved for the unknown size
+    let len = (1..=8).find(|&len| value < (1u64 << (7 * len)) - 1).unwrap_or(8);
+    let marked = value | (1u64 << (7 * len));
+
+    marked.to_be_bytes()[8 - len..].to_vec()
+}
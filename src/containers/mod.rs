@@ -0,0 +1,328 @@
+pub mod fmp4;
+pub mod matroska;
+
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use async_stream::stream;
+use axum::{
+    body::Body,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use crate::captures::SerializedDimensions;
+use crate::packets::{audio_payload, frame_payload, frame_sequence};
+use crate::presets::StreamPacing;
+use crate::state::StreamState;
+use crate::streamed_resolution::StreamedResolution;
+use crate::viewers::ViewerClient;
+
+use self::fmp4::Fmp4Muxer;
+
+/// # Muxer
+///
+/// Wraps encoded JPEG frames into a container format that standard players can consume.
+pub trait Muxer: Send + 'static {
+    /// The content type sent with the stream.
+    const CONTENT_TYPE: &'static str;
+
+    /// Bytes sent once before the first frame (init segment, etc.)
+    fn header(&mut self) -> Vec<u8>;
+
+    /// Mux a single JPEG frame presented at `pts` since the start of the stream.
+    fn mux(&mut self, jpeg: &[u8], pts: Duration) -> Vec<u8>;
+
+    /// Mux a frame of an inter-frame codec presented at `pts`, `keyframe` when it decodes on its own.
+    /// Containers of JPEG frames, where every frame is one, mux it as one.
+    fn mux_coded(&mut self, frame: &[u8], pts: Duration, _keyframe: bool) -> Vec<u8> {
+        self.mux(frame, pts)
+    }
+
+    /// Mux a single Opus packet presented at `pts` since the start of the stream, containers without an audio track ignore it.
+    fn mux_audio(&mut self, _opus: &[u8], _pts: Duration) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// The containers the `/stream` route can respond with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StreamContainer {
+    /// versioned packets (see `packets::packet`), used by the bundled viewer.
+    Raw,
+    /// Fragmented MP4 (MJPEG track).
+    FragmentedMp4,
+}
+
+impl StreamContainer {
+    /// Every container the route can respond with.
+    pub const ALL: [StreamContainer; 2] = [StreamContainer::Raw, StreamContainer::FragmentedMp4];
+
+    /// The value of the `container` query parameter for this container.
+    pub fn name(self) -> &'static str {
+        match self {
+            StreamContainer::Raw => "raw",
+            StreamContainer::FragmentedMp4 => "mp4",
+        }
+    }
+
+    /// # Negotiate container
+    ///
+    /// Picks the container from the `container` query parameter (`raw`, `mp4`) and falls back to the `Accept` header.
+    ///
+    /// Anything unknown results in the raw container.
+    ///
+    /// Errors for `ts`: MPEG-TS has no stream type for JPEG frames, the players do not decode them as video.
+    pub fn negotiate(query: Option<&str>, accept: Option<&str>) -> Result<Self, String> {
+        if let Some(query) = query {
+            return match query.to_lowercase().as_str() {
+                "mp4" | "fmp4" => Ok(StreamContainer::FragmentedMp4),
+                "ts" | "mpegts" => Err("MPEG-TS has no stream type for JPEG frames, use ?container=mp4".to_string()),
+                _ => Ok(StreamContainer::Raw),
+            };
+        }
+
+        match accept.unwrap_or_default().to_lowercase().contains("video/mp4") {
+            true => Ok(StreamContainer::FragmentedMp4),
+            false => Ok(StreamContainer::Raw),
+        }
+    }
+
+    /// Creates the resolution for this container over a subscriber of the encoded feed.
+    ///
+    /// With `audio` the mp4 container interleaves the Opus packets of the stream as a second track when audio is being captured,
+    /// the raw container always is video only (the viewer reads `/stream/audio`). The frames are skipped following the pacing of the viewer.
+    pub fn resolution(
+        self,
+        rx: Receiver<Vec<u8>>,
+        dimensions: SerializedDimensions,
+        audio: bool,
+        pacing: StreamPacing,
+        client: ViewerClient,
+        state: Arc<StreamState>,
+    ) -> Response {
+        let audio = (audio && state.audio_enabled()).then(|| state.audio_packets.subscribe());
+
+        match self {
+            StreamContainer::Raw => StreamedResolution::from_receiver(rx, state)
+                .for_client(client)
+                .with_pacing(pacing)
+                .into_response(),
+            StreamContainer::FragmentedMp4 => {
+                let mut muxer = Fmp4Muxer::new(dimensions.width as u32, dimensions.height as u32);
+
+                if audio.is_some() {
+                    muxer = muxer.with_audio();
+                }
+
+                ContainerResolution::new(rx, muxer, state)
+                    .with_audio(audio)
+                    .with_pacing(pacing)
+                    .for_client(client)
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// # Container Resolution
+///
+/// Represents a streamed broadcast from a subscriber of the broadcast channel, muxed into a container.
+pub struct ContainerResolution<M: Muxer> {
+    //broadcast channel
+    rx: Receiver<Vec<u8>>,
+    //opus packets interleaved with the frames
+    audio: Option<Receiver<Vec<u8>>>,
+    muxer: M,
+    //viewer and bytes sent accounting
+    state: Arc<StreamState>,
+    //the session the stream is registered as
+    client: ViewerClient,
+    //frames skipped for this viewer by its preset
+    pacing: StreamPacing,
+}
+
+impl<M: Muxer> ContainerResolution<M> {
+    /// create a new container resolution from a receiver and the muxer to use.
+    pub fn new(rx: Receiver<Vec<u8>>, muxer: M, state: Arc<StreamState>) -> Self {
+        Self {
+            rx,
+            audio: None,
+            muxer,
+            state,
+            client: ViewerClient::default(),
+            pacing: StreamPacing::default(),
+        }
+    }
+
+    /// registers the stream as a session of the client.
+    pub fn for_client(self, client: ViewerClient) -> Self {
+        Self { client, ..self }
+    }
+
+    /// skips the frames of the stream following the pacing of a preset.
+    pub fn with_pacing(self, pacing: StreamPacing) -> Self {
+        Self { pacing, ..self }
+    }
+
+    /// interleaves the audio packets of the receiver with the frames.
+    pub fn with_audio(self, audio: Option<Receiver<Vec<u8>>>) -> Self {
+        Self { audio, ..self }
+    }
+}
+
+/// A packet received by a container resolution.
+enum Received {
+    Frame(Vec<u8>),
+    Audio(Vec<u8>),
+    /// frames missed by falling behind, audio packets are not counted.
+    Lagged(u64),
+    Closed,
+    AudioClosed,
+}
+
+/// Receives from the audio receiver, pending forever when there is none.
+pub(crate) async fn recv_audio(audio: &mut Option<Receiver<Vec<u8>>>) -> Result<Vec<u8>, RecvError> {
+    match audio {
+        Some(audio) => audio.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+impl<M: Muxer> IntoResponse for ContainerResolution<M> {
+    //streams the muxed frames with the container content type
+    fn into_response(self) -> Response {
+        let Self {
+            mut rx,
+            mut audio,
+            mut muxer,
+            state,
+            client,
+            mut pacing,
+        } = self;
+
+        let content = stream! {
+            //counted as a viewer until the stream is dropped
+            let viewer = state.viewer(client);
+            //presentation times are the capture timestamps relative to the start of this stream, so both tracks line up
+            let start = state.timestamp_us();
+
+            let header = muxer.header();
+            if !header.is_empty() {
+                viewer.add_bytes_sent(header.len());
+                yield header;
+            }
+
+            //the latest frame right away, on a static desktop the next one may take seconds
+            let primed = state.latest_packet().filter(|packet| pacing.skip(packet, 0).is_none());
+            let primed_sequence = primed.as_deref().and_then(|packet| frame_sequence(packet));
+
+            if let Some((_, jpeg)) = primed.as_deref().and_then(|packet| frame_payload(packet)) {
+                let muxed = muxer.mux(jpeg, Duration::ZERO);
+
+                if !muxed.is_empty() {
+                    viewer.add_bytes_sent(muxed.len());
+                    viewer.add_frames_sent(1);
+                    yield muxed;
+                }
+            }
+
+            loop {
+                let received = tokio::select! {
+                    _ = state.shutting_down() => Received::Closed,
+                    _ = viewer.kicked() => Received::Closed,
+                    frame = rx.recv() => match frame {
+                        Ok(data) => Received::Frame(data),
+                        Err(RecvError::Lagged(missed)) => Received::Lagged(missed),
+                        Err(RecvError::Closed) => Received::Closed,
+                    },
+                    packet = recv_audio(&mut audio) => match packet {
+                        Ok(data) => Received::Audio(data),
+                        Err(RecvError::Lagged(_)) => Received::Lagged(0),
+                        Err(RecvError::Closed) => Received::AudioClosed,
+                    },
+                };
+
+                let (muxed, frames) = match received {
+                    Received::Frame(packet) => {
+                        //broadcast while the stream was subscribing, already sent
+                        if primed_sequence.is_some() && frame_sequence(&packet) == primed_sequence {
+                            continue;
+                        }
+
+                        if let Some(skipped) = pacing.skip(&packet, rx.len()) {
+                            viewer.add_skipped(skipped);
+                            continue;
+                        }
+
+                        //strip the raw framing, containers carry their own
+                        let Some((timestamp_us, jpeg)) = frame_payload(&packet) else {
+                            continue;
+                        };
+
+                        let pts = Duration::from_micros(timestamp_us.saturating_sub(start));
+                        (muxer.mux(jpeg, pts), 1)
+                    }
+                    Received::Audio(packet) => {
+                        let Some((timestamp_us, opus)) = audio_payload(&packet) else {
+                            continue;
+                        };
+                        pacing.sent(&packet);
+                        //skip packets mixed before the stream started
+                        let Some(pts) = timestamp_us.checked_sub(start) else {
+                            continue;
+                        };
+
+                        (muxer.mux_audio(opus, Duration::from_micros(pts)), 0)
+                    }
+                    Received::Lagged(missed) => {
+                        viewer.add_frames_lagged(missed);
+                        continue;
+                    }
+                    Received::Closed => break,
+                    //the encoder stopped, keep streaming the frames
+                    Received::AudioClosed => {
+                        audio = None;
+                        continue;
+                    }
+                };
+
+                if muxed.is_empty() {
+                    continue;
+                }
+
+                viewer.add_bytes_sent(muxed.len());
+                viewer.add_frames_sent(frames);
+
+                yield muxed;
+            }
+        };
+
+        (
+            [(CONTENT_TYPE, M::CONTENT_TYPE)],
+            Body::from_stream(content.map(Ok::<_, Infallible>)),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_the_container() {
+        assert!(StreamContainer::negotiate(Some("mp4"), None) == Ok(StreamContainer::FragmentedMp4));
+        assert!(StreamContainer::negotiate(Some("FMP4"), Some("video/mp2t")) == Ok(StreamContainer::FragmentedMp4));
+        assert!(StreamContainer::negotiate(Some("raw"), Some("video/mp4")) == Ok(StreamContainer::Raw));
+        assert!(StreamContainer::negotiate(Some("webm"), None) == Ok(StreamContainer::Raw));
+        assert!(StreamContainer::negotiate(None, Some("video/mp4, */*")) == Ok(StreamContainer::FragmentedMp4));
+        assert!(StreamContainer::negotiate(None, None) == Ok(StreamContainer::Raw));
+    }
+
+    #[test]
+    fn refuses_mpeg_ts() {
+        assert!(StreamContainer::negotiate(Some("ts"), None).is_err());
+        assert!(StreamContainer::negotiate(Some("mpegts"), None).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+use serde::Serialize;
+
+use crate::{autocrop::Rect, encoder::EncodeSettings};
+
+/// JPEG quality used when none is configured, 60-70 is usually a sweet spot for streaming speed vs quality.
+pub const DEFAULT_QUALITY: u8 = 70;
+
+/// # Stream Control
+///
+/// Runtime settings of the stream that can be changed while it is running (through the `/api/` routes).
+pub struct StreamControl {
+    paused: AtomicBool,
+    blanked: AtomicBool,
+    grayscale: AtomicBool,
+    remote_input: AtomicBool,
+    debug_overlay: AtomicBool,
+    quality: AtomicU8,
+    //0 is unlimited
+    max_fps: AtomicU32,
+}
+
+impl StreamControl {
+    pub fn new() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            blanked: AtomicBool::new(false),
+            grayscale: AtomicBool::new(false),
+            remote_input: AtomicBool::new(false),
+            debug_overlay: AtomicBool::new(false),
+            quality: AtomicU8::new(DEFAULT_QUALITY),
+            max_fps: AtomicU32::new(0),
+        }
+    }
+
+    /// Whether frames are currently held back from the broadcast.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Whether black frames are sent instead of the capture.
+    pub fn is_blanked(&self) -> bool {
+        self.blanked.load(Ordering::Relaxed)
+    }
+
+    pub fn set_blanked(&self, blanked: bool) {
+        self.blanked.store(blanked, Ordering::Relaxed);
+    }
+
+    /// Whether only the luminance of the frames is encoded.
+    pub fn is_grayscale(&self) -> bool {
+        self.grayscale.load(Ordering::Relaxed)
+    }
+
+    pub fn set_grayscale(&self, grayscale: bool) {
+        self.grayscale.store(grayscale, Ordering::Relaxed);
+    }
+
+    /// Whether the stats of the pipeline are burned into the frames.
+    pub fn is_debug_overlay(&self) -> bool {
+        self.debug_overlay.load(Ordering::Relaxed)
+    }
+
+    pub fn set_debug_overlay(&self, shown: bool) {
+        self.debug_overlay.store(shown, Ordering::Relaxed);
+    }
+
+    /// Whether the host allowed viewers to control the mouse and keyboard, never set when the server starts.
+    pub fn is_remote_input_allowed(&self) -> bool {
+        self.remote_input.load(Ordering::Relaxed)
+    }
+
+    pub fn set_remote_input_allowed(&self, allowed: bool) {
+        self.remote_input.store(allowed, Ordering::Relaxed);
+    }
+
+    /// JPEG quality from 1 to 100.
+    pub fn quality(&self) -> u8 {
+        self.quality.load(Ordering::Relaxed)
+    }
+
+    /// Sets the JPEG quality, clamped from 1 to 100.
+    pub fn set_quality(&self, quality: u8) {
+        self.quality.store(quality.clamp(1, 100), Ordering::Relaxed);
+    }
+
+    /// The settings the next frame is encoded with.
+    pub fn encode_settings(&self) -> EncodeSettings {
+        EncodeSettings {
+            quality: self.quality(),
+            grayscale: self.is_grayscale(),
+        }
+    }
+
+    /// Maximum frames per second that are encoded, 0 being unlimited.
+    pub fn max_fps(&self) -> u32 {
+        self.max_fps.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_fps(&self, fps: u32) {
+        self.max_fps.store(fps, Ordering::Relaxed);
+    }
+
+    /// Minimum time between encoded frames, `None` when unlimited.
+    pub fn frame_interval(&self) -> Option<std::time::Duration> {
+        match self.max_fps() {
+            0 => None,
+            fps => Some(std::time::Duration::from_secs_f64(1.0 / fps as f64)),
+        }
+    }
+}
+
+impl Default for StreamControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rest API Json for the status of the stream.
+#[derive(Serialize)]
+pub struct StreamStatus {
+    pub paused: bool,
+    /// black frames are sent instead of the capture.
+    pub blanked: bool,
+    /// viewers are allowed to control the mouse and keyboard through `/ws/input`.
+    pub remote_input: bool,
+    pub source: String,
+    pub quality: u8,
+    /// only the luminance of the frames is encoded.
+    pub grayscale: bool,
+    /// the source is shown as in a mirror.
+    pub mirrored: bool,
+    /// the stats of the pipeline are burned into the frames.
+    pub debug_overlay: bool,
+    /// area of the transformed frames the stream is cropped to by `[autocrop]`, only while bars are cropped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Rect>,
+    /// size of the streamed frames in percent of the captured ones.
+    pub scale: u8,
+    /// maximum fps, 0 being unlimited.
+    pub fps: u32,
+    pub viewers: usize,
+}
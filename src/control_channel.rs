@@ -0,0 +1,303 @@
+use std::{net::IpAddr, sync::Arc};
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast::error::RecvError,
+};
+use tokio_tungstenite::tungstenite::{
+    Message,
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+use crate::{
+    auth::{Role, constant_time_eq},
+    control::StreamStatus,
+    events::{Event, ServerEvent},
+    gateway::{ACCEPT_RETRY, Gateway},
+    pointer::parse_color,
+    request_params::query_string_param,
+    state::StreamState,
+};
+
+/// Path the control channel is served at.
+pub const CONTROL_PATH: &str = "/ws/control";
+
+/// `[control]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ControlChannelConfig {
+    pub enabled: bool,
+    pub port: u16,
+    /// clients connect with `?token=` set to it or to one of the `[auth]` tokens that control the stream, the channel is not
+    /// started without either.
+    pub token: Option<String>,
+}
+
+impl Default for ControlChannelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 8081,
+            token: None,
+        }
+    }
+}
+
+/// # Control Command
+///
+/// A command sent by a client over the control channel as json, for example `{"command": "set_quality", "value": 50}`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlCommand {
+    /// every JPEG frame is a keyframe, acknowledged so clients can use the same flow for future codecs.
+    RequestKeyframe,
+    /// sends the latest frame again to the stream of `viewer` (its `x-viewer-id`), for a client that detected a gap in the frame
+    /// sequences, `last_sequence` is the last one it got.
+    Resync { viewer: u64, last_sequence: Option<u32> },
+    SetQuality { value: u8 },
+    SetFps { value: u32 },
+    /// switches the luminance only mode.
+    SetGrayscale { value: bool },
+    /// shows the source as in a mirror.
+    SetMirror { value: bool },
+    /// burns the stats of the pipeline into the frames.
+    SetDebugOverlay { value: bool },
+    Pause,
+    Resume,
+    Status,
+    /// shows the laser pointer at a position of the frame, from 0.0 to 1.0, `color` is `#rrggbb`.
+    Pointer { x: f64, y: f64, color: Option<String> },
+}
+
+impl ControlCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ControlCommand::RequestKeyframe => "request_keyframe",
+            ControlCommand::Resync { .. } => "resync",
+            ControlCommand::SetQuality { .. } => "set_quality",
+            ControlCommand::SetFps { .. } => "set_fps",
+            ControlCommand::SetGrayscale { .. } => "set_grayscale",
+            ControlCommand::SetMirror { .. } => "set_mirror",
+            ControlCommand::SetDebugOverlay { .. } => "set_debug_overlay",
+            ControlCommand::Pause => "pause",
+            ControlCommand::Resume => "resume",
+            ControlCommand::Status => "status",
+            ControlCommand::Pointer { .. } => "pointer",
+        }
+    }
+
+    /// Applies the command to the stream.
+    fn apply(&self, state: &StreamState) -> Result<(), String> {
+        match self {
+            ControlCommand::RequestKeyframe | ControlCommand::Status => {}
+            ControlCommand::Resync { viewer, last_sequence } => state.resync(*viewer, *last_sequence)?,
+            ControlCommand::SetQuality { value } => {
+                if !(1..=100).contains(value) {
+                    return Err("quality must be from 1 to 100".to_string());
+                }
+
+                state.control.set_quality(*value);
+            }
+            ControlCommand::SetFps { value } => state.control.set_max_fps(*value),
+            ControlCommand::SetGrayscale { value } => state.control.set_grayscale(*value),
+            ControlCommand::SetMirror { value } => state.transform.set_mirrored(*value),
+            ControlCommand::SetDebugOverlay { value } => state.control.set_debug_overlay(*value),
+            ControlCommand::Pointer { x, y, color } => {
+                if !(0.0..=1.0).contains(x) || !(0.0..=1.0).contains(y) {
+                    return Err("the pointer position must be from 0 to 1".to_string());
+                }
+
+                let color = match color {
+                    Some(color) => Some(parse_color(color).ok_or("the color must be #rrggbb")?),
+                    None => None,
+                };
+
+                state.pointer.point(*x, *y, color);
+            }
+            ControlCommand::Pause | ControlCommand::Resume => {
+                let paused = matches!(self, ControlCommand::Pause);
+
+                if state.control.is_paused() != paused {
+                    state.control.set_paused(paused);
+                    state.emit(if paused { ServerEvent::Paused } else { ServerEvent::Resumed });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A message sent to a client of the control channel.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage {
+    /// the command was applied, along with the resulting status.
+    Ack {
+        command: &'static str,
+        status: StreamStatus,
+    },
+    Error {
+        error: String,
+    },
+    Event(Event),
+}
+
+/// # Spawn Control Channel
+///
+/// Listens for WebSocket connections at `ws://<ip>:<port>/ws/control`, clients send `ControlCommand`s and receive acknowledgements
+/// along with every server event.
+///
+/// Note: `The channel has its own listener and port, so it can be disabled or firewalled separately from the web app.`
+pub fn spawn_control_channel(
+    config: ControlChannelConfig,
+    ip: IpAddr,
+    gateway: Arc<Gateway>,
+    state: Arc<StreamState>,
+) {
+    if !config.enabled {
+        return;
+    }
+
+    if config.token.is_none() && !state.auth.enabled() {
+        error!("The control channel is not started without a [control] token or [auth] tokens");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind((ip, config.port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                state.stats.error();
+                error!(port = config.port, error = %e, "Failed to bind the control channel");
+                return;
+            }
+        };
+
+        info!("Control channel at ws://{ip}:{}{CONTROL_PATH}", config.port);
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "Control channel accept failed");
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                    continue;
+                }
+            };
+
+            if !gateway.allows(peer.ip()) {
+                debug!(%peer, "Refused a control client outside of the [access] lists");
+                continue;
+            }
+
+            let Some(permit) = gateway.admit(peer.ip()) else {
+                warn!(%peer, "Refused a control client, the ip has too many connections open");
+                continue;
+            };
+
+            let (token, state) = (config.token.clone(), state.clone());
+
+            tokio::spawn(
+                async move {
+                    //held until the connection closes
+                    let _permit = permit;
+                    handle_client(stream, token, state).await
+                }
+                .instrument(info_span!("control_client", %peer)),
+            );
+        }
+    });
+}
+
+/// Performs the handshake and serves a single client until it disconnects.
+async fn handle_client(stream: TcpStream, token: Option<String>, state: Arc<StreamState>) {
+    let authorize = |req: &Request, res: Response| -> Result<Response, ErrorResponse> {
+        if req.uri().path() != CONTROL_PATH {
+            return Err(error_response(StatusCode::NOT_FOUND, "not found"));
+        }
+
+        //the [control] token, or an [auth] token of a controller
+        let authorized = query_string_param(req.uri().query().unwrap_or_default(), "token").is_some_and(|given| {
+            token.as_deref().is_some_and(|token| constant_time_eq(token, &given))
+                || state.auth.token_role(&given).is_some_and(|role| role >= Role::Controller)
+        });
+
+        if !authorized {
+            return Err(error_response(StatusCode::UNAUTHORIZED, "invalid token"));
+        }
+
+        Ok(res)
+    };
+
+    let socket = match tokio_tungstenite::accept_hdr_async(stream, authorize).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(error = %e, "Control channel handshake failed");
+            return;
+        }
+    };
+
+    let (mut sink, mut incoming) = socket.split();
+    let mut events = state.events.subscribe();
+
+    loop {
+        let reply = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => handle_command(&text, &state),
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    warn!(error = %e, "Control channel error");
+                    break;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) => ControlMessage::Event(event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = state.shutting_down() => {
+                let _ = sink.send(Message::Close(None)).await;
+                break;
+            }
+        };
+
+        let Ok(json) = serde_json::to_string(&reply) else {
+            continue;
+        };
+
+        if sink.send(Message::text(json)).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn handle_command(text: &str, state: &StreamState) -> ControlMessage {
+    let command = match serde_json::from_str::<ControlCommand>(text) {
+        Ok(command) => command,
+        Err(e) => {
+            return ControlMessage::Error {
+                error: format!("invalid command: {e}"),
+            };
+        }
+    };
+
+    match command.apply(state) {
+        Ok(()) => ControlMessage::Ack {
+            command: command.name(),
+            status: state.status(),
+        },
+        Err(error) => ControlMessage::Error { error },
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(message.to_string()));
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use serde::Deserialize;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Response headers the viewer apps of other origins may read.
+const EXPOSED_HEADERS: [&str; 3] = ["x-protocol-version", "x-viewer-id", "retry-after"];
+
+/// `[cors]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// origins allowed to call the server (`https://viewer.example.com`), `"*"` for any, CORS is disabled when empty.
+    pub origins: Vec<String>,
+    /// methods allowed in preflight requests.
+    pub methods: Vec<String>,
+    /// request headers allowed in preflight requests.
+    pub headers: Vec<String>,
+    /// seconds browsers may cache a preflight answer.
+    pub max_age_seconds: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            headers: vec!["Authorization".to_string(), "Content-Type".to_string()],
+            max_age_seconds: 600,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// # Layer
+    ///
+    /// Cross-origin access to every route: preflight requests are answered before any route sees them
+    /// and the responses to allowed origins get the `Access-Control-*` headers, `None` when no origin is configured.
+    ///
+    /// Note: `Origins, methods and headers that are not valid header values are skipped.`
+    pub fn layer(&self) -> Option<CorsLayer> {
+        if self.origins.is_empty() {
+            return None;
+        }
+
+        let origin = if self.origins.iter().any(|allowed| allowed == "*") {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(self.origins.iter().filter_map(|origin| HeaderValue::from_str(origin).ok()))
+        };
+
+        let layer = CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods(
+                self.methods
+                    .iter()
+                    .filter_map(|method| Method::from_bytes(method.to_ascii_uppercase().as_bytes()).ok())
+                    .collect::<Vec<_>>(),
+            )
+            .allow_headers(
+                self.headers
+                    .iter()
+                    .filter_map(|header| HeaderName::from_bytes(header.as_bytes()).ok())
+                    .collect::<Vec<_>>(),
+            )
+            .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+            .max_age(Duration::from_secs(self.max_age_seconds));
+
+        Some(layer)
+    }
+}
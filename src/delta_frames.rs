@@ -0,0 +1,262 @@
+use std::sync::{Arc, Mutex};
+
+use tracing::debug;
+
+use crate::{
+    encoder::{EncodeSettings, FrameEncoder},
+    error::{Result, ShareScreenError},
+    frame_compressor::packed_frame,
+    state::RawFrame,
+};
+
+/// Start of a delta frame.
+const MAGIC: &[u8; 4] = b"ZBGR";
+/// Length of the header of a delta frame: the magic, the kind, the size, the timestamp and the reference.
+const HEADER_LEN: usize = 29;
+/// Most deltas in a row before a keyframe, a stream that skipped a frame waits for the next keyframe.
+const KEYFRAME_INTERVAL: u32 = 60;
+/// Microseconds since the reference after which a keyframe is sent instead of a delta, the feed was likely not watched in between
+/// so the streams that just subscribed do not have the reference.
+const KEYFRAME_GAP_US: u64 = 1_000_000;
+/// Level of the zstd compression, the fastest one: the deltas of a desktop are mostly zeros.
+const ZSTD_LEVEL: i32 = 1;
+
+const KIND_KEY: u8 = 0;
+const KIND_DELTA: u8 = 1;
+
+/// The last frame encoded, the reference of the next delta.
+struct Reference {
+    frame: Arc<RawFrame>,
+    deltas: u32,
+}
+
+/// # Zstd Delta Encoder
+///
+/// Skips the image codecs entirely: every frame is the zstd-compressed XOR of its BGRA pixels with the previous frame (a keyframe every
+/// sixty frames is the pixels themselves), so the frames are about as fast to encode as a copy and the unchanged parts of the screen
+/// cost next to nothing. Meant for the native viewer and the relays on a LAN, see `DeltaDecoder`.
+///
+/// Note: `A delta names the timestamp of the frame it applies to, a stream that missed that frame skips the deltas until the next keyframe.
+/// Only served as the zstd feed of ?codec=, the main codec primes the viewers with its latest frame which a delta cannot be.`
+#[derive(Default)]
+pub struct ZstdDeltaEncoder {
+    reference: Mutex<Option<Reference>>,
+}
+
+impl FrameEncoder for ZstdDeltaEncoder {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/x-zstd-bgra"
+    }
+
+    fn encode(&self, frame: &RawFrame, _settings: EncodeSettings) -> Result<Vec<u8>> {
+        let data = packed_frame(&frame.data, frame.width, frame.height).ok_or_else(|| invalid(frame))?;
+        let mut reference = self.reference.lock().unwrap();
+
+        let delta_of = reference.as_ref().filter(|reference| {
+            let previous = &reference.frame;
+
+            reference.deltas < KEYFRAME_INTERVAL
+                && (previous.width, previous.height) == (frame.width, frame.height)
+                && previous.timestamp_us < frame.timestamp_us
+                && frame.timestamp_us - previous.timestamp_us <= KEYFRAME_GAP_US
+        });
+
+        let encoded = match delta_of {
+            Some(delta_of) => {
+                let previous = packed_frame(&delta_of.frame.data, frame.width, frame.height).ok_or_else(|| invalid(frame))?;
+                let delta: Vec<u8> = data.iter().zip(previous.iter()).map(|(pixel, previous)| pixel ^ previous).collect();
+
+                encode_frame(KIND_DELTA, frame, delta_of.frame.timestamp_us, &delta)?
+            }
+            None => encode_frame(KIND_KEY, frame, 0, &data)?,
+        };
+
+        let deltas = match delta_of {
+            Some(delta_of) => delta_of.deltas + 1,
+            None => 0,
+        };
+        *reference = Some(Reference {
+            frame: Arc::new(RawFrame {
+                data: data.into_owned(),
+                width: frame.width,
+                height: frame.height,
+                timestamp_us: frame.timestamp_us,
+            }),
+            deltas,
+        });
+
+        Ok(encoded)
+    }
+
+    fn intra_only(&self) -> bool {
+        false
+    }
+
+    fn encode_key(&self, frame: &RawFrame, _settings: EncodeSettings) -> Result<Vec<u8>> {
+        let data = packed_frame(&frame.data, frame.width, frame.height).ok_or_else(|| invalid(frame))?;
+
+        encode_frame(KIND_KEY, frame, 0, &data)
+    }
+}
+
+fn encode_frame(kind: u8, frame: &RawFrame, reference_us: u64, pixels: &[u8]) -> Result<Vec<u8>> {
+    let compressed = zstd::bulk::compress(pixels, ZSTD_LEVEL).map_err(|e| ShareScreenError::Encode(format!("zstd: {e}")))?;
+
+    let mut encoded = Vec::with_capacity(HEADER_LEN + compressed.len());
+    encoded.extend_from_slice(MAGIC);
+    encoded.push(kind);
+    encoded.extend_from_slice(&frame.width.to_le_bytes());
+    encoded.extend_from_slice(&frame.height.to_le_bytes());
+    encoded.extend_from_slice(&frame.timestamp_us.to_le_bytes());
+    encoded.extend_from_slice(&reference_us.to_le_bytes());
+    encoded.extend_from_slice(&compressed);
+
+    Ok(encoded)
+}
+
+fn invalid(frame: &RawFrame) -> ShareScreenError {
+    ShareScreenError::Encode(format!("{}x{} frame of {} bytes", frame.width, frame.height, frame.data.len()))
+}
+
+/// # Delta Decoder
+///
+/// Decodes the frames of `ZstdDeltaEncoder` back to BGRA, keeping the last frame as the reference of the next delta.
+#[derive(Default)]
+pub struct DeltaDecoder {
+    previous: Option<RawFrame>,
+}
+
+impl DeltaDecoder {
+    /// # Decode
+    ///
+    /// The width, the height and the BGRA pixels of the frame, `None` for a malformed frame or a delta of a frame this decoder
+    /// did not see (a frame skipped), the frames decode again from the next keyframe.
+    pub fn decode(&mut self, encoded: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+        if encoded.len() < HEADER_LEN || &encoded[..4] != MAGIC {
+            return None;
+        }
+
+        let kind = encoded[4];
+        let width = u32::from_le_bytes(encoded[5..9].try_into().ok()?);
+        let height = u32::from_le_bytes(encoded[9..13].try_into().ok()?);
+        let timestamp_us = u64::from_le_bytes(encoded[13..21].try_into().ok()?);
+        let reference_us = u64::from_le_bytes(encoded[21..29].try_into().ok()?);
+        let size = (width as usize).checked_mul(height as usize)?.checked_mul(4)?;
+
+        let pixels = match zstd::bulk::decompress(&encoded[HEADER_LEN..], size) {
+            Ok(pixels) if pixels.len() == size => pixels,
+            _ => return None,
+        };
+
+        let data = match kind {
+            KIND_KEY => pixels,
+            KIND_DELTA => {
+                let Some(previous) = self
+                    .previous
+                    .as_mut()
+                    .filter(|previous| previous.timestamp_us == reference_us && (previous.width, previous.height) == (width, height))
+                else {
+                    debug!(reference_us, "Missing the reference of a delta frame, waiting for a keyframe");
+                    self.previous = None;
+                    return None;
+                };
+
+                //the reference is not needed anymore, it is xored in place
+                let mut data = std::mem::take(&mut previous.data);
+                for (pixel, delta) in data.iter_mut().zip(pixels.iter()) {
+                    *pixel ^= delta;
+                }
+                data
+            }
+            _ => return None,
+        };
+
+        let decoded = data.clone();
+        self.previous = Some(RawFrame {
+            data,
+            width,
+            height,
+            timestamp_us,
+        });
+
+        Some((width, height, decoded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: EncodeSettings = EncodeSettings {
+        quality: 80,
+        grayscale: false,
+    };
+
+    fn frame(timestamp_us: u64, fill: impl Fn(usize) -> u8) -> RawFrame {
+        RawFrame {
+            data: (0..16 * 8 * 4).map(fill).collect(),
+            width: 16,
+            height: 8,
+            timestamp_us,
+        }
+    }
+
+    #[test]
+    fn decodes_keyframes_and_deltas() {
+        let encoder = ZstdDeltaEncoder::default();
+        let mut decoder = DeltaDecoder::default();
+        let frames = [
+            frame(1_000, |i| i as u8),
+            frame(2_000, |i| if i < 64 { 255 } else { i as u8 }),
+            frame(3_000, |i| (i * 7) as u8),
+        ];
+
+        for (i, frame) in frames.iter().enumerate() {
+            let encoded = encoder.encode(frame, SETTINGS).unwrap();
+            assert_eq!(encoded[4], if i == 0 { KIND_KEY } else { KIND_DELTA });
+            assert_eq!(decoder.decode(&encoded), Some((16, 8, frame.data.clone())));
+        }
+    }
+
+    #[test]
+    fn skips_the_deltas_of_a_missed_frame() {
+        let encoder = ZstdDeltaEncoder::default();
+        let mut decoder = DeltaDecoder::default();
+
+        let key = encoder.encode(&frame(1_000, |i| i as u8), SETTINGS).unwrap();
+        assert!(decoder.decode(&key).is_some());
+
+        //the decoder never sees the frame at 2000
+        encoder.encode(&frame(2_000, |i| (i * 3) as u8), SETTINGS).unwrap();
+        let delta = encoder.encode(&frame(3_000, |i| (i * 5) as u8), SETTINGS).unwrap();
+        assert_eq!(decoder.decode(&delta), None);
+
+        let next = frame(4_000, |i| (i * 11) as u8);
+        let key = encoder.encode_key(&next, SETTINGS).unwrap();
+        assert_eq!(decoder.decode(&key), Some((16, 8, next.data)));
+    }
+
+    #[test]
+    fn sends_a_keyframe_after_a_gap() {
+        let encoder = ZstdDeltaEncoder::default();
+
+        encoder.encode(&frame(1_000, |i| i as u8), SETTINGS).unwrap();
+        let late = encoder.encode(&frame(1_000 + KEYFRAME_GAP_US + 1, |i| i as u8), SETTINGS).unwrap();
+
+        assert_eq!(late[4], KIND_KEY);
+    }
+
+    #[test]
+    fn refuses_malformed_frames() {
+        let mut decoder = DeltaDecoder::default();
+        let encoded = ZstdDeltaEncoder::default().encode(&frame(1_000, |i| i as u8), SETTINGS).unwrap();
+
+        assert_eq!(decoder.decode(&encoded[..HEADER_LEN - 1]), None);
+        assert_eq!(decoder.decode(&encoded[..encoded.len() - 1]), None);
+        assert_eq!(decoder.decode(&[b"ZRGB".as_slice(), &encoded[4..]].concat()), None);
+    }
+}
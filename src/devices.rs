@@ -0,0 +1,111 @@
+use serde::Serialize;
+
+use crate::{
+    error::Result,
+    platform::{list_cameras, list_monitors},
+};
+
+/// Rest API Json for the devices available to capture.
+#[derive(Serialize)]
+pub struct DeviceList {
+    pub cameras: Vec<CameraInfo>,
+    pub monitors: Vec<MonitorInfo>,
+}
+
+/// Rest API Json for a camera device.
+#[derive(Serialize)]
+pub struct CameraInfo {
+    /// index of the camera, in the order the cameras are enumerated.
+    pub index: usize,
+    /// friendly name of the camera.
+    pub name: String,
+    /// formats the camera can output.
+    pub formats: Vec<CameraFormat>,
+}
+
+/// Rest API Json for a single camera output format.
+#[derive(Serialize, PartialEq, Eq)]
+pub struct CameraFormat {
+    /// pixel format (NV12, YUY2, MJPG, RGB32...)
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Rest API Json for a monitor device.
+#[derive(Serialize)]
+pub struct MonitorInfo {
+    /// index of the monitor starting from 0, the prompt shows this index + 1.
+    pub index: usize,
+    /// device name of the display (\\.\DISPLAY1 on Windows, the RandR output like DP-1 on X11)
+    pub name: String,
+    /// name the monitor reports in its EDID (`DELL U2720Q`), it stays the same when the displays are enumerated in another order.
+    pub model: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// whether the monitor is the primary display.
+    pub primary: bool,
+}
+
+/// # List Devices
+///
+/// Enumerates all cameras and monitors that can be captured.
+///
+/// Should be called from a blocking thread, cameras are briefly activated to read their formats.
+pub fn list_devices() -> Result<DeviceList> {
+    Ok(DeviceList {
+        cameras: list_cameras()?,
+        monitors: list_monitors()?,
+    })
+}
+
+/// # Print Devices
+///
+/// Prints the devices as `share-screen list` shows them: the monitors by the number `--monitor` and `monitor:<number>` take,
+/// then the cameras with their formats by the number `[capture] camera` and `camera:<number>` take.
+pub fn print_devices(devices: &DeviceList) {
+    println!("Monitors:");
+    if devices.monitors.is_empty() {
+        println!("  none");
+    }
+    for monitor in &devices.monitors {
+        println!(
+            "  {:<3} {:<16} {:<20} {:>11} {}",
+            monitor.index + 1,
+            monitor.name,
+            monitor.model.as_deref().unwrap_or("-"),
+            format!("{}x{}", monitor.width, monitor.height),
+            if monitor.primary { "primary" } else { "" }
+        );
+    }
+
+    println!("Cameras:");
+    if devices.cameras.is_empty() {
+        println!("  none");
+    }
+    for camera in &devices.cameras {
+        println!("  {:<3} {}", camera.index + 1, camera.name);
+
+        for format in &camera.formats {
+            println!("        {:<6} {}x{}", format.format, format.width, format.height);
+        }
+    }
+}
+
+/// # EDID Monitor Name
+///
+/// The name a monitor reports in the display descriptors of its EDID (`DELL U2720Q`), none when it reports no name.
+pub fn edid_monitor_name(edid: &[u8]) -> Option<String> {
+    //the four 18 byte descriptors of the base block, the name one is tagged 0xFC and ends with a line feed
+    (54..126)
+        .step_by(18)
+        .filter_map(|offset| edid.get(offset..offset + 18))
+        .find(|descriptor| descriptor[..4] == [0, 0, 0, 0xFC])
+        .map(|descriptor| {
+            let text = &descriptor[5..];
+            let end = text.iter().position(|byte| *byte == b'\n').unwrap_or(text.len());
+
+            String::from_utf8_lossy(&text[..end]).trim().to_string()
+        })
+        .filter(|name| !name.is_empty())
+}
@@ -0,0 +1,265 @@
+use std::{
+    cell::RefCell,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Result, ShareScreenError},
+    frame_compressor::{
+        ChromaSubsampling, CompressBuffers, JpegSettings, compress_frame, convert_frame_into, encode_jpeg,
+        encode_png_fast, encode_qoi, packed_frame,
+    },
+    state::RawFrame,
+};
+
+/// `[encoder]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct EncoderConfig {
+    /// name of an encoder of the `EncoderRegistry`: `jpeg`, the lossless `qoi` and `png`, or one registered by the application.
+    pub codec: String,
+    /// threads of the parallel conversions, 0 uses every core.
+    pub threads: usize,
+    /// runs the conversion threads below the normal priority, so the application being shared keeps the cpu.
+    pub low_priority: bool,
+    /// starts the stream in the grayscale mode, it can be switched at runtime with `/api/grayscale`.
+    pub grayscale: bool,
+    /// chroma subsampling of the `jpeg` codec, `4:4:4`, `4:2:2` or `4:2:0`.
+    pub chroma: ChromaSubsampling,
+    /// quality the stream starts with (1-100), the one of the last run or 70 when not set.
+    pub quality: Option<u8>,
+    /// maximum fps the stream starts with, 0 being unlimited.
+    pub fps: Option<u32>,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            codec: "jpeg".to_string(),
+            threads: 0,
+            low_priority: false,
+            grayscale: false,
+            chroma: ChromaSubsampling::default(),
+            quality: None,
+            fps: None,
+        }
+    }
+}
+
+/// # Encode Settings
+///
+/// The runtime settings of the stream an encode follows, see `StreamControl::encode_settings`.
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeSettings {
+    /// from 1 to 100, codecs without a quality setting ignore it.
+    pub quality: u8,
+    /// only the luminance is encoded, about half the bytes and conversion time for text-heavy shares over slow links.
+    /// Codecs without a grayscale mode ignore it.
+    pub grayscale: bool,
+}
+
+/// # Frame Encoder
+///
+/// Encodes the raw BGRA frames of the capture into the payload of the frame packets and the snapshots.
+pub trait FrameEncoder: Send + Sync {
+    /// Name of the codec, as set in `[encoder] codec`.
+    fn name(&self) -> &'static str;
+
+    /// Content type of an encoded frame.
+    fn content_type(&self) -> &'static str;
+
+    /// Encodes the frame with the settings of the stream.
+    fn encode(&self, frame: &RawFrame, settings: EncodeSettings) -> Result<Vec<u8>>;
+
+    /// Encodes like `encode`, along with the time spent converting the frame before encoding it for the stage timings,
+    /// `None` for encoders that do not convert separately.
+    fn encode_staged(&self, frame: &RawFrame, settings: EncodeSettings) -> Result<(Vec<u8>, Option<Duration>)> {
+        self.encode(frame, settings).map(|encoded| (encoded, None))
+    }
+
+    /// Whether every encoded frame decodes on its own, so a stream can start on any of them.
+    /// Codecs sending deltas start the streams with `encode_key`.
+    fn intra_only(&self) -> bool {
+        true
+    }
+
+    /// Encodes the frame so it decodes on its own without changing the state of the encoder, the first frame of a stream.
+    fn encode_key(&self, frame: &RawFrame, settings: EncodeSettings) -> Result<Vec<u8>> {
+        self.encode(frame, settings)
+    }
+}
+
+/// # Jpeg Encoder
+///
+/// Every frame as its own JPEG, what the bundled viewer and the mjpeg container expect, single channel in grayscale.
+#[derive(Default)]
+pub struct JpegEncoder {
+    chroma: ChromaSubsampling,
+}
+
+impl JpegEncoder {
+    pub fn new(chroma: ChromaSubsampling) -> Self {
+        Self { chroma }
+    }
+
+    fn settings(&self, settings: EncodeSettings) -> JpegSettings {
+        JpegSettings {
+            quality: settings.quality,
+            grayscale: settings.grayscale,
+            chroma: self.chroma,
+        }
+    }
+}
+
+impl FrameEncoder for JpegEncoder {
+    fn name(&self) -> &'static str {
+        "jpeg"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn encode(&self, frame: &RawFrame, settings: EncodeSettings) -> Result<Vec<u8>> {
+        JPEG_BUFFERS.with_borrow_mut(|buffers| {
+            compress_frame(&frame.data, frame.width, frame.height, &self.settings(settings), buffers);
+
+            checked(&buffers.jpeg, frame)
+        })
+    }
+
+    fn encode_staged(&self, frame: &RawFrame, settings: EncodeSettings) -> Result<(Vec<u8>, Option<Duration>)> {
+        let Some(data) = packed_frame(&frame.data, frame.width, frame.height) else {
+            return checked(&[], frame).map(|jpeg| (jpeg, None));
+        };
+
+        JPEG_BUFFERS.with_borrow_mut(|buffers| {
+            let converting = Instant::now();
+            convert_frame_into(&data, settings.grayscale, &mut buffers.converted);
+            let converted = converting.elapsed();
+
+            encode_jpeg(&buffers.converted, frame.width, frame.height, &self.settings(settings), &mut buffers.jpeg);
+
+            checked(&buffers.jpeg, frame).map(|jpeg| (jpeg, Some(converted)))
+        })
+    }
+}
+
+/// # Qoi Encoder
+///
+/// Every frame as a lossless QOI image, text stays crisp at several times the bytes of a JPEG, a codec for the LAN.
+/// The bundled viewer decodes it itself, browsers do not know the format.
+pub struct QoiEncoder;
+
+impl FrameEncoder for QoiEncoder {
+    fn name(&self) -> &'static str {
+        "qoi"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/qoi"
+    }
+
+    fn encode(&self, frame: &RawFrame, _settings: EncodeSettings) -> Result<Vec<u8>> {
+        checked(&encode_qoi(&frame.data, frame.width, frame.height), frame)
+    }
+}
+
+/// # Png Encoder
+///
+/// Every frame as a lossless PNG compressed the fastest way, smaller than QOI but several times slower to encode.
+pub struct PngEncoder;
+
+impl FrameEncoder for PngEncoder {
+    fn name(&self) -> &'static str {
+        "png"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/png"
+    }
+
+    fn encode(&self, frame: &RawFrame, _settings: EncodeSettings) -> Result<Vec<u8>> {
+        checked(&encode_png_fast(&frame.data, frame.width, frame.height), frame)
+    }
+}
+
+thread_local! {
+    /// Buffers of the JPEG encodes of this thread, the encodes run on the few blocking threads of the runtime so they are reused frame after frame.
+    static JPEG_BUFFERS: RefCell<CompressBuffers> = RefCell::new(CompressBuffers::default());
+}
+
+/// The err of an empty encode, the encoding functions log the reason, otherwise a copy of the encoded frame sized to it.
+fn checked(encoded: &[u8], frame: &RawFrame) -> Result<Vec<u8>> {
+    if encoded.is_empty() {
+        return Err(ShareScreenError::Encode(format!(
+            "{}x{} frame of {} bytes",
+            frame.width,
+            frame.height,
+            frame.data.len()
+        )));
+    }
+
+    Ok(encoded.to_vec())
+}
+
+/// Creates the encoder of an `[encoder]` section.
+pub type EncoderConstructor = fn(&EncoderConfig) -> Result<Arc<dyn FrameEncoder>>;
+
+/// # Encoder Registry
+///
+/// The codecs `[encoder] codec` can name, `jpeg`, `qoi` and `png` are built in.
+/// Applications embedding the server register their own with `register`.
+pub struct EncoderRegistry {
+    encoders: Vec<(&'static str, EncoderConstructor)>,
+}
+
+impl EncoderRegistry {
+    /// A registry without any encoder.
+    pub fn empty() -> Self {
+        Self { encoders: Vec::new() }
+    }
+
+    /// Adds an encoder, replacing the one registered under the same name.
+    pub fn register(&mut self, name: &'static str, constructor: EncoderConstructor) -> &mut Self {
+        self.encoders.retain(|(registered, _)| *registered != name);
+        self.encoders.push((name, constructor));
+        self
+    }
+
+    /// Names of the registered encoders.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.encoders.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// The encoder of the `[encoder]` section.
+    pub fn create(&self, config: &EncoderConfig) -> Result<Arc<dyn FrameEncoder>> {
+        let (_, constructor) = self
+            .encoders
+            .iter()
+            .find(|(registered, _)| registered.eq_ignore_ascii_case(&config.codec))
+            .ok_or_else(|| {
+                ShareScreenError::Server(format!(
+                    "Unknown codec {}, expected one of: {}",
+                    config.codec,
+                    self.names().join(", ")
+                ))
+            })?;
+
+        constructor(config)
+    }
+}
+
+impl Default for EncoderRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("jpeg", |config| Ok(Arc::new(JpegEncoder::new(config.chroma))));
+        registry.register("qoi", |_| Ok(Arc::new(QoiEncoder)));
+        registry.register("png", |_| Ok(Arc::new(PngEncoder)));
+
+        registry
+    }
+}
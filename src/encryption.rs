@@ -0,0 +1,111 @@
+use axum::response::{IntoResponse, Response};
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::{hex, unhex},
+    bytes_resolution::BytesResolution,
+    packets::{FLAG_ENCRYPTED, HEADER_LEN, NONCE_LEN, PacketType, parse},
+    state::StreamState,
+};
+
+/// `[encryption]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// 32 byte key as 64 hex characters (`share-screen --generate-key`), packets are sent in the clear without one.
+    pub key: Option<String>,
+    /// keep serving the routes that cannot be encrypted (images, mp4 streams) while the key is set.
+    pub allow_plaintext: bool,
+}
+
+/// # Frame Cipher
+///
+/// Encrypts the payload of the stream packets with XChaCha20-Poly1305, for networks where TLS is not an option.
+///
+/// An encrypted packet has `FLAG_ENCRYPTED` set and its payload is `[24 bytes nonce] + [ciphertext and 16 bytes tag]`,
+/// the 20 byte header (with the final flags and length) is authenticated along with the payload.
+pub struct FrameCipher {
+    cipher: XChaCha20Poly1305,
+    allow_plaintext: bool,
+}
+
+impl FrameCipher {
+    /// The cipher of the config, `None` when no key is set.
+    pub fn from_config(config: &EncryptionConfig) -> Result<Option<Self>, String> {
+        let Some(key) = &config.key else {
+            return Ok(None);
+        };
+
+        let key = unhex(key.trim())
+            .filter(|key| key.len() == 32)
+            .ok_or("Invalid [encryption] key, expected 64 hex characters")?;
+
+        Ok(Some(Self {
+            cipher: XChaCha20Poly1305::new_from_slice(&key).expect("the key is 32 bytes"),
+            allow_plaintext: config.allow_plaintext,
+        }))
+    }
+
+    /// # Encrypt packet
+    ///
+    /// Encrypts the payload of a packet with a random nonce, heartbeats and malformed packets are returned as they are.
+    pub fn encrypt_packet(&self, packet: Vec<u8>) -> Vec<u8> {
+        let Some((header, payload)) = parse(&packet) else {
+            return packet;
+        };
+
+        if header.kind == PacketType::Heartbeat {
+            return packet;
+        }
+
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let length = (NONCE_LEN + payload.len() + 16) as u32;
+
+        let mut encrypted = Vec::with_capacity(HEADER_LEN + length as usize);
+        encrypted.extend_from_slice(&packet[..HEADER_LEN]);
+        encrypted[2..4].copy_from_slice(&(header.flags | FLAG_ENCRYPTED).to_le_bytes());
+        encrypted[16..20].copy_from_slice(&length.to_le_bytes());
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: &encrypted[..HEADER_LEN],
+                },
+            )
+            .expect("encrypting a packet cannot fail");
+
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+
+        encrypted
+    }
+}
+
+/// # Plaintext Guard
+///
+/// Checked by the routes serving frames outside of the packet stream, the err is the `403` response to return
+/// while the stream is encrypted and `allow_plaintext` is not set.
+pub fn plaintext_guard(state: &StreamState) -> Result<(), Response> {
+    match &state.cipher {
+        Some(cipher) if !cipher.allow_plaintext => Err(BytesResolution::text(
+            403,
+            "The stream is encrypted, only the raw /stream and /stream/audio are served",
+        )
+        .into_response()),
+        _ => Ok(()),
+    }
+}
+
+/// # Generate Key
+///
+/// A random key for the `key` of the config.
+pub fn generate_key() -> String {
+    hex(&rand::random::<[u8; 32]>())
+}
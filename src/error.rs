@@ -0,0 +1,57 @@
+use thiserror::Error;
+
+/// # Share Screen Error
+///
+/// Errors of the capture devices and the pipeline, lets callers tell apart why a device could not be captured
+/// (no camera found, monitor index out of range...) instead of matching on messages.
+#[derive(Debug, Error)]
+pub enum ShareScreenError {
+    /// CoInitializeEx failed on the thread activating or listing cameras.
+    #[cfg(windows)]
+    #[error("Failed to CoInitialize: {0}")]
+    ComInit(windows::core::Error),
+    /// Cameras or monitors could not be enumerated.
+    #[cfg(windows)]
+    #[error("Failed to enumerate devices: {0}")]
+    DeviceEnumeration(#[from] windows::core::Error),
+    /// The X server could not be reached or refused a request.
+    #[cfg(target_os = "linux")]
+    #[error("X11 error: {0}")]
+    X11(String),
+    /// No camera is connected.
+    #[error("No camera devices to capture.")]
+    NoCamera,
+    /// The camera index starting from 0 is not connected.
+    #[error("Camera {} is not connected ({count} cameras found).", index + 1)]
+    CameraOutOfRange { index: usize, count: usize },
+    /// The monitor index starting from 0 is not connected.
+    #[error("Monitor {} is not connected ({count} monitors found).", index + 1)]
+    MonitorOutOfRange { index: i32, count: i32 },
+    /// No connected monitor has the model or device name.
+    #[error("No monitor named {name} is connected, see the monitors of /api/devices.")]
+    MonitorNotFound { name: String },
+    /// The device was found but could not be activated.
+    #[error("Failed to activate {device}: {reason}")]
+    Activation { device: String, reason: String },
+    /// The capture stopped or an audio device could not be captured.
+    #[error("Capture failed: {0}")]
+    Capture(String),
+    /// A frame, recording or export could not be encoded.
+    #[error("Failed to encode: {0}")]
+    Encode(String),
+    /// The request could not be served in the current state (already recording, empty replay buffer...).
+    #[error("{0}")]
+    Server(String),
+    /// Writing a recording, replay or timelapse failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<image::ImageError> for ShareScreenError {
+    fn from(e: image::ImageError) -> Self {
+        ShareScreenError::Encode(e.to_string())
+    }
+}
+
+/// Result of the capture and pipeline functions.
+pub type Result<T> = std::result::Result<T, ShareScreenError>;
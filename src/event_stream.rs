@@ -0,0 +1,65 @@
+use std::convert::Infallible;
+
+use async_stream::stream;
+use axum::{
+    body::Body,
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+use crate::events::{Event, ServerEvent};
+
+/// # Event Stream Resolution
+///
+/// Represents a server-sent events stream of the server events, every event is sent as
+/// `event: <name>` with the json of the event as its data.
+pub struct EventStreamResolution {
+    //broadcast channel
+    rx: Receiver<Event>,
+}
+
+impl EventStreamResolution {
+    /// create a new event stream resolution from a receiver.
+    pub fn from_receiver(rx: Receiver<Event>) -> Self {
+        Self { rx }
+    }
+}
+
+impl IntoResponse for EventStreamResolution {
+    //streams the events with the event stream content type
+    fn into_response(self) -> Response {
+        let Self { mut rx } = self;
+
+        let content = stream! {
+            //lets the client know the stream is open before the first event
+            yield b": connected\n\n".to_vec();
+
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+
+                let Ok(data) = serde_json::to_string(&event) else {
+                    continue;
+                };
+
+                yield format!("event: {}\ndata: {data}\n\n", event.kind.name()).into_bytes();
+
+                //nothing follows the shutdown
+                if let ServerEvent::ShuttingDown = event.kind {
+                    break;
+                }
+            }
+        };
+
+        (
+            [(CONTENT_TYPE, "text/event-stream"), (CACHE_CONTROL, "no-cache")],
+            Body::from_stream(content.map(Ok::<_, Infallible>)),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+
+use crate::session::unix_now;
+
+/// # Server Event
+///
+/// Something that happened on the server, broadcast to webhooks and other listeners.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// a viewer started watching, `identity` is the one of its token (see auth::identity).
+    ViewerConnected { viewers: usize, ip: String, identity: Option<String> },
+    ViewerDisconnected { viewers: usize },
+    /// the capture stopped, `reacquiring` while the device is opened again on its own, it waits for a restart otherwise.
+    CaptureError { error: String, reacquiring: bool },
+    /// the capture runs again after it stopped or restarted.
+    CaptureRestored { source: String, down_secs: f64 },
+    RecordingFinished { path: String },
+    MotionDetected { score: f32 },
+    DimensionsChanged { width: u32, height: u32 },
+    Paused,
+    Resumed,
+    SourceChanged { source: String },
+    ShuttingDown,
+    PipelineRestarted { reason: String },
+    RemoteInputAllowed,
+    RemoteInputRevoked,
+    FileReceived { name: String, size: u64, ip: String },
+    /// the volume of `path` has less than `[disk] min_free_mb` left, the recordings and the snapshots are paused.
+    DiskSpaceLow { path: String, free_mb: u64 },
+    /// the volume of `path` has space again, the recordings and the snapshots resumed.
+    DiskSpaceRecovered { path: String, free_mb: u64 },
+}
+
+impl ServerEvent {
+    /// Name of the event as serialized in the `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerEvent::ViewerConnected { .. } => "viewer_connected",
+            ServerEvent::ViewerDisconnected { .. } => "viewer_disconnected",
+            ServerEvent::CaptureError { .. } => "capture_error",
+            ServerEvent::CaptureRestored { .. } => "capture_restored",
+            ServerEvent::RecordingFinished { .. } => "recording_finished",
+            ServerEvent::MotionDetected { .. } => "motion_detected",
+            ServerEvent::DimensionsChanged { .. } => "dimensions_changed",
+            ServerEvent::Paused => "paused",
+            ServerEvent::Resumed => "resumed",
+            ServerEvent::SourceChanged { .. } => "source_changed",
+            ServerEvent::ShuttingDown => "shutting_down",
+            ServerEvent::PipelineRestarted { .. } => "pipeline_restarted",
+            ServerEvent::RemoteInputAllowed => "remote_input_allowed",
+            ServerEvent::RemoteInputRevoked => "remote_input_revoked",
+            ServerEvent::FileReceived { .. } => "file_received",
+            ServerEvent::DiskSpaceLow { .. } => "disk_space_low",
+            ServerEvent::DiskSpaceRecovered { .. } => "disk_space_recovered",
+        }
+    }
+}
+
+/// An event along with the unix timestamp (seconds) it happened at.
+#[derive(Serialize, Clone, Debug)]
+pub struct Event {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: ServerEvent,
+}
+
+impl Event {
+    pub fn now(kind: ServerEvent) -> Self {
+        Self {
+            timestamp: unix_now(),
+            kind,
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+#[cfg(windows)]
+use std::{collections::HashSet, time::Duration};
+
+use serde::Deserialize;
+#[cfg(windows)]
+use tracing::{info, warn};
+
+use crate::state::StreamState;
+
+/// Time between two looks for the windows to hide, a window opened afterwards is hidden within it.
+#[cfg(windows)]
+const EXCLUSION_INTERVAL: Duration = Duration::from_secs(2);
+
+/// `[exclusion]` section of the config, windows left out of the monitor capture while they stay on the screen of the host.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ExclusionConfig {
+    /// titles of the windows to hide, a window whose title contains one (ignoring case) is hidden, like `["Speaker notes"]`.
+    pub windows: Vec<String>,
+    /// hides the console the server runs in, its status screen and logs.
+    pub console: bool,
+}
+
+impl ExclusionConfig {
+    fn is_enabled(&self) -> bool {
+        self.console || !self.windows.is_empty()
+    }
+
+    #[cfg(windows)]
+    fn matches(&self, title: &str) -> bool {
+        let title = title.to_lowercase();
+
+        self.windows
+            .iter()
+            .any(|window| !window.is_empty() && title.contains(&window.to_lowercase()))
+    }
+}
+
+/// # Spawn Window Exclusion
+///
+/// Hides the windows of `[exclusion]` from the capture with `SetWindowDisplayAffinity` (`WDA_EXCLUDEFROMCAPTURE`):
+/// the host keeps seeing them while the viewers, the recordings and the snapshots see what is behind them.
+/// The windows are looked for every two seconds so a window opened later is hidden too, they are shown again at shutdown.
+///
+/// Note: `Windows only hides the windows of the process asking (windows 10 2004 and later), a window of another application
+/// is logged once and left captured. The other platforms have no capture exclusion, the section is logged and ignored there.`
+pub fn spawn_window_exclusion(config: ExclusionConfig, state: Arc<StreamState>) {
+    if !config.is_enabled() {
+        return;
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = state;
+        tracing::warn!("Windows are only excluded from the capture on windows, ignoring [exclusion]");
+    }
+
+    #[cfg(windows)]
+    tokio::spawn(async move {
+        //windows hidden so far and the ones that could not be, by their handle
+        let mut excluded: HashSet<isize> = HashSet::new();
+        let mut failed: HashSet<isize> = HashSet::new();
+
+        loop {
+            let mut windows: Vec<(isize, String)> = crate::platform::windows::top_level_windows()
+                .into_iter()
+                .filter(|(_, title)| config.matches(title))
+                .collect();
+
+            if config.console
+                && let Some(console) = crate::platform::windows::console_window()
+                && windows.iter().all(|(window, _)| *window != console)
+            {
+                windows.push((console, "console".to_string()));
+            }
+
+            //closed windows are forgotten, a handle can be reused by another window
+            excluded.retain(|window| windows.iter().any(|(candidate, _)| candidate == window));
+            failed.retain(|window| windows.iter().any(|(candidate, _)| candidate == window));
+
+            for (window, title) in windows {
+                if excluded.contains(&window) || failed.contains(&window) {
+                    continue;
+                }
+
+                match crate::platform::windows::set_excluded_from_capture(window, true) {
+                    Ok(()) => {
+                        info!(window = %title, "Window hidden from the capture");
+                        excluded.insert(window);
+                    }
+                    Err(e) => {
+                        warn!(window = %title, error = %e, "Failed to hide the window from the capture, only the windows of the server can be hidden");
+                        failed.insert(window);
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(EXCLUSION_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+        }
+
+        //the console outlives the server
+        for window in excluded {
+            let _ = crate::platform::windows::set_excluded_from_capture(window, false);
+        }
+    });
+}
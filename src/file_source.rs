@@ -0,0 +1,265 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tracing::info;
+use windows::{
+    Win32::{
+        Foundation::E_POINTER,
+        Media::MediaFoundation::{
+            IMFSourceReader, MF_MT_DEFAULT_STRIDE, MF_MT_FRAME_SIZE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+            MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING, MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+            MF_SOURCE_READERF_ENDOFSTREAM, MF_VERSION, MFCreateAttributes, MFCreateMediaType,
+            MFCreateSourceReaderFromURL, MFMediaType_Video, MFSTARTUP_FULL, MFShutdown, MFStartup,
+            MFVideoFormat_RGB32,
+        },
+        System::Com::StructuredStorage::PROPVARIANT,
+    },
+    core::{GUID, HSTRING},
+};
+
+use crate::{
+    error::{Result, ShareScreenError},
+    platform::windows::ComGuard,
+    source::{CaptureSource, SourceDevice},
+};
+
+/// # File Source
+///
+/// Plays a local video file (mp4, mkv, avi, wmv... anything Media Foundation decodes, the recordings included)
+/// through the pipeline at its own pace, looping back to the start when `looped`.
+pub struct FileSource {
+    pub path: PathBuf,
+    pub looped: bool,
+}
+
+impl std::fmt::Display for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file {}", self.path.display())
+    }
+}
+
+impl SourceDevice for FileSource {
+    fn open(&self) -> Result<Arc<dyn CaptureSource>> {
+        Ok(Arc::new(FilePlayback::open(self.path.clone(), self.looped)?))
+    }
+}
+
+/// A file being decoded on its own thread, Media Foundation blocks while reading a sample.
+pub struct FilePlayback {
+    width: u32,
+    height: u32,
+    frames: Mutex<mpsc::Receiver<Vec<u8>>>,
+    finished: Mutex<Option<oneshot::Receiver<Result<()>>>>,
+}
+
+impl FilePlayback {
+    /// Opens the file and starts decoding it, returns once its resolution is known.
+    fn open(path: PathBuf, looped: bool) -> Result<Self> {
+        //a couple of frames ahead, decoding waits for the compressor
+        let (frames, receiver) = mpsc::channel(2);
+        let (opened, opened_receiver) = std::sync::mpsc::channel();
+        let (finished, finished_receiver) = oneshot::channel();
+
+        std::thread::Builder::new()
+            .name("file-source".to_string())
+            .spawn(move || {
+                let result = unsafe { decode(&path, looped, &frames, &opened) };
+                let _ = finished.send(result);
+            })?;
+
+        let (width, height) = opened_receiver
+            .recv()
+            .map_err(|_| ShareScreenError::Capture("the file could not be opened".to_string()))??;
+
+        Ok(Self {
+            width,
+            height,
+            frames: Mutex::new(receiver),
+            finished: Mutex::new(Some(finished_receiver)),
+        })
+    }
+}
+
+impl CaptureSource for FilePlayback {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        Ok((self.width, self.height))
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        async move { self.frames.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Some(finished) = self.finished.lock().await.take() else {
+                return Err(ShareScreenError::Capture("the file is already playing".to_string()));
+            };
+
+            finished
+                .await
+                .unwrap_or_else(|_| Err(ShareScreenError::Capture("the decoder stopped".to_string())))
+        }
+        .boxed()
+    }
+}
+
+/// Decodes the file until it ends (or forever when looped), or nothing receives the frames anymore.
+///
+/// The resolution (or the error opening the file) is sent on `opened` before the first frame.
+unsafe fn decode(
+    path: &Path,
+    looped: bool,
+    frames: &mpsc::Sender<Vec<u8>>,
+    opened: &std::sync::mpsc::Sender<Result<(u32, u32)>>,
+) -> Result<()> {
+    let _com = ComGuard::new()?;
+
+    unsafe {
+        MFStartup(MF_VERSION, MFSTARTUP_FULL)?;
+
+        let result = match open_reader(path) {
+            Ok((reader, width, height, stride)) => {
+                let _ = opened.send(Ok((width, height)));
+                info!(path = %path.display(), width, height, "Playing file");
+
+                read_frames(&reader, width, height, stride, looped, frames)
+            }
+            Err(e) => {
+                let message = e.to_string();
+                let _ = opened.send(Err(e));
+
+                Err(ShareScreenError::Capture(message))
+            }
+        };
+
+        let _ = MFShutdown();
+
+        result
+    }
+}
+
+/// A source reader converting the first video stream of the file to RGB32, along with its width, height and stride.
+unsafe fn open_reader(path: &Path) -> Result<(IMFSourceReader, u32, u32, i32)> {
+    let activation_error = |e: windows::core::Error| ShareScreenError::Activation {
+        device: format!("file {}", path.display()),
+        reason: e.message(),
+    };
+
+    unsafe {
+        let mut attributes = None;
+        MFCreateAttributes(&mut attributes, 1)?;
+        let attributes = attributes.ok_or_else(|| {
+            windows::core::Error::new(E_POINTER, "Failed to create source reader attributes.")
+        })?;
+
+        //lets the reader convert the decoded frames to RGB32
+        attributes.SetUINT32(&MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING, 1)?;
+
+        let url = HSTRING::from(std::path::absolute(path)?.as_os_str());
+        let reader = MFCreateSourceReaderFromURL(&url, &attributes).map_err(activation_error)?;
+
+        let output = MFCreateMediaType()?;
+        output.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
+        output.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_RGB32)?;
+
+        let stream = MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32;
+        reader.SetCurrentMediaType(stream, None, &output).map_err(activation_error)?;
+
+        let current = reader.GetCurrentMediaType(stream)?;
+        let size = current.GetUINT64(&MF_MT_FRAME_SIZE)?;
+        let (width, height) = ((size >> 32) as u32, size as u32);
+        //RGB frames are bottom-up unless the stride says otherwise
+        let stride = current
+            .GetUINT32(&MF_MT_DEFAULT_STRIDE)
+            .map(|stride| stride as i32)
+            .unwrap_or(-((width * 4) as i32));
+
+        Ok((reader, width, height, stride))
+    }
+}
+
+/// Sends the frames at the pace of their timestamps.
+unsafe fn read_frames(
+    reader: &IMFSourceReader,
+    width: u32,
+    height: u32,
+    stride: i32,
+    looped: bool,
+    frames: &mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
+    let stream = MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32;
+    let row = (width * 4) as usize;
+
+    //wall clock and timestamp (100ns units) of the first frame since the start or the last loop
+    let mut started: Option<(Instant, i64)> = None;
+
+    loop {
+        let mut flags = 0u32;
+        let mut timestamp = 0i64;
+        let mut sample = None;
+
+        unsafe {
+            reader.ReadSample(stream, 0, None, Some(&mut flags), Some(&mut timestamp), Some(&mut sample))?;
+        }
+
+        if flags & MF_SOURCE_READERF_ENDOFSTREAM.0 as u32 != 0 {
+            if !looped {
+                info!("Reached the end of the file");
+                return Ok(());
+            }
+
+            unsafe { reader.SetCurrentPosition(&GUID::zeroed(), &PROPVARIANT::from(0i64))? };
+            started = None;
+            continue;
+        }
+
+        let Some(sample) = sample else {
+            continue;
+        };
+
+        let frame = unsafe {
+            let buffer = sample.ConvertToContiguousBuffer()?;
+            let mut data = std::ptr::null_mut();
+            let mut length = 0u32;
+            buffer.Lock(&mut data, None, Some(&mut length))?;
+
+            let bytes = std::slice::from_raw_parts(data, length as usize);
+            let frame = top_down(bytes, row, height as usize, stride);
+
+            buffer.Unlock()?;
+            frame
+        };
+
+        let (wall, first) = *started.get_or_insert((Instant::now(), timestamp));
+        let due = wall + Duration::from_nanos((timestamp - first).max(0) as u64 * 100);
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+
+        //the capture was dropped
+        if frames.blocking_send(frame).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Copies the rows of the buffer top to bottom, dropping the padding of the stride.
+fn top_down(bytes: &[u8], row: usize, height: usize, stride: i32) -> Vec<u8> {
+    let pitch = (stride.unsigned_abs() as usize).max(row);
+    let mut frame = Vec::with_capacity(row * height);
+
+    for y in 0..height {
+        let source = if stride < 0 { height - 1 - y } else { y };
+        let start = source * pitch;
+
+        match bytes.get(start..start + row) {
+            Some(line) => frame.extend_from_slice(line),
+            None => frame.resize(frame.len() + row, 0),
+        }
+    }
+
+    frame
+}
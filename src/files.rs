@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{self, DefaultBodyLimit, State},
+    http::request::Parts,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    api::{ApiError, json},
+    auth::{control_guard, guard},
+    bytes_resolution::BytesResolution,
+    events::ServerEvent,
+    request_params::query_param,
+    session::unix_now,
+    state::StreamState,
+    viewers::ViewerClient,
+};
+
+/// `[files]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// whether viewers can upload files to the host, the offered files are served either way.
+    pub uploads: bool,
+    /// directory the uploaded files are saved to.
+    pub dir: String,
+    /// largest upload accepted, in megabytes.
+    pub max_upload_mb: u64,
+    /// files of the host offered to the viewers from the start.
+    pub offer: Vec<String>,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            uploads: false,
+            dir: "received".to_string(),
+            max_upload_mb: 50,
+            offer: Vec::new(),
+        }
+    }
+}
+
+impl FilesConfig {
+    fn max_upload_bytes(&self) -> usize {
+        (self.max_upload_mb.max(1) * 1024 * 1024) as usize
+    }
+}
+
+/// A file of the host viewers can download.
+#[derive(Serialize, Clone)]
+pub struct OfferedFile {
+    pub id: u64,
+    pub name: String,
+    pub size: u64,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// # File Shelf
+///
+/// The files the host offers to the viewers at `/files/{id}`, for the rest of the session.
+pub struct FileShelf {
+    next_id: AtomicU64,
+    files: RwLock<HashMap<u64, OfferedFile>>,
+}
+
+impl FileShelf {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            files: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// # Offer
+    ///
+    /// Lets viewers download the file, the file has to exist when offered and is read again on every download.
+    pub fn offer(&self, path: impl Into<PathBuf>) -> std::io::Result<OfferedFile> {
+        let path = path.into();
+        let metadata = std::fs::metadata(&path)?;
+
+        if !metadata.is_file() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file"));
+        }
+
+        let file = OfferedFile {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            name: file_name(&path),
+            size: metadata.len(),
+            path,
+        };
+
+        self.files.write().unwrap().insert(file.id, file.clone());
+
+        Ok(file)
+    }
+
+    /// Stops offering the file, returns whether it was offered.
+    pub fn withdraw(&self, id: u64) -> bool {
+        self.files.write().unwrap().remove(&id).is_some()
+    }
+
+    pub fn get(&self, id: u64) -> Option<OfferedFile> {
+        self.files.read().unwrap().get(&id).cloned()
+    }
+
+    /// The offered files, oldest first.
+    pub fn list(&self) -> Vec<OfferedFile> {
+        let mut files: Vec<OfferedFile> = self.files.read().unwrap().values().cloned().collect();
+        files.sort_by_key(|file| file.id);
+        files
+    }
+}
+
+impl Default for FileShelf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rest API Json of an uploaded file.
+#[derive(Serialize)]
+struct ReceivedFile {
+    name: String,
+    size: usize,
+    /// name the file was saved as in the `[files]` directory.
+    stored: String,
+}
+
+/// # File Routes
+///
+/// The routes exchanging files between the host and the viewers:
+///
+/// - `POST /api/files?name=` - uploads the body as a file of the `[files]` directory (controllers), refused when `uploads` is off
+/// - `GET /api/files` - the files offered by the host
+/// - `GET /files/{id}` - downloads an offered file
+///
+/// Note: `Uploads larger than max_upload_mb get 413, the name is reduced to its file name so an upload cannot leave the directory.`
+pub fn file_routes(config: FilesConfig) -> Router<Arc<StreamState>> {
+    let limit = config.max_upload_bytes();
+
+    Router::new()
+        .route(
+            "/api/files",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                json(state.files.list())
+            })
+            .post(
+                move |State(state): State<Arc<StreamState>>, req: Parts, body: Bytes| async move {
+                    if let Err(denied) = control_guard(&state, &req) {
+                        return denied;
+                    }
+
+                    if !config.uploads {
+                        return BytesResolution::text(403, "Uploads are disabled, set [files] uploads").into_response();
+                    }
+
+                    let Some(name) = query_param(&req, "name").as_deref().and_then(sanitize_name) else {
+                        return BytesResolution::text(400, "Expected ?name= with the file name").into_response();
+                    };
+
+                    match save_upload(&config.dir, &name, &body).await {
+                        Ok(stored) => {
+                            let client = ViewerClient::from_request(&req, "file");
+                            tracing::info!(%name, size = body.len(), %stored, ip = %client.ip, "File received");
+
+                            state.emit(ServerEvent::FileReceived {
+                                name: name.clone(),
+                                size: body.len() as u64,
+                                ip: client.ip,
+                            });
+
+                            json(ReceivedFile {
+                                name,
+                                size: body.len(),
+                                stored,
+                            })
+                        }
+                        Err(e) => {
+                            tracing::error!(%name, error = %e, "Failed to save a received file");
+                            ApiError::new(e).response(500)
+                        }
+                    }
+                },
+            )
+            .layer(DefaultBodyLimit::max(limit)),
+        )
+        .route(
+            "/files/{id}",
+            get(
+                |State(state): State<Arc<StreamState>>, extract::Path(id): extract::Path<String>, req: Parts| async move {
+                    if let Err(denied) = guard(&state, &req) {
+                        return denied;
+                    }
+
+                    let Some(file) = id.parse::<u64>().ok().and_then(|id| state.files.get(id)) else {
+                        return BytesResolution::text(404, "No file with this id").into_response();
+                    };
+
+                    match tokio::fs::read(&file.path).await {
+                        Ok(content) => BytesResolution::new(content, "application/octet-stream")
+                            .with_header("Content-Disposition", format!("attachment; filename=\"{}\"", file.name.replace('"', "'")))
+                            .into_response(),
+                        Err(e) => BytesResolution::text(410, format!("The file is not available anymore: {e}")).into_response(),
+                    }
+                },
+            ),
+        )
+}
+
+/// Saves the upload in the directory, prefixed with the time it was received (and a counter for the uploads of the same name
+/// within a second) so no upload replaces another. Returns the name it was stored as.
+async fn save_upload(dir: &str, name: &str, content: &[u8]) -> std::io::Result<String> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let received = unix_now();
+
+    for attempt in 0..1000u32 {
+        let stored = match attempt {
+            0 => format!("{received}-{name}"),
+            _ => format!("{received}-{attempt}-{name}"),
+        };
+
+        let created = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(Path::new(dir).join(&stored))
+            .await;
+
+        let mut file = match created {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+
+        file.write_all(content).await?;
+        file.flush().await?;
+
+        return Ok(stored);
+    }
+
+    Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "no free name left for the upload"))
+}
+
+/// The file name of the upload without any directory, `None` when nothing is left.
+fn sanitize_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+        .collect();
+
+    match name.trim_matches('.') {
+        "" => None,
+        _ => Some(name),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_the_file_name() {
+        assert_eq!(sanitize_name("report.pdf"), Some("report.pdf".to_string()));
+        assert_eq!(sanitize_name("../../etc/passwd"), Some("passwd".to_string()));
+        assert_eq!(sanitize_name("C:\\Users\\host\\notes.txt"), Some("notes.txt".to_string()));
+        assert_eq!(sanitize_name("/tmp/ archive.zip "), Some("archive.zip".to_string()));
+    }
+
+    #[test]
+    fn strips_the_reserved_characters() {
+        assert_eq!(sanitize_name("what?<now>|\"*.txt"), Some("whatnow.txt".to_string()));
+        assert_eq!(sanitize_name("line\nbreak\u{7}.txt"), Some("linebreak.txt".to_string()));
+        assert_eq!(sanitize_name("C:notes.txt"), Some("Cnotes.txt".to_string()));
+        assert_eq!(sanitize_name(".hidden"), Some(".hidden".to_string()));
+    }
+
+    #[test]
+    fn refuses_the_names_without_a_file() {
+        assert_eq!(sanitize_name(""), None);
+        assert_eq!(sanitize_name("uploads/"), None);
+        assert_eq!(sanitize_name(".."), None);
+        assert_eq!(sanitize_name("..."), None);
+        assert_eq!(sanitize_name("**??"), None);
+    }
+}
@@ -1,18 +1,122 @@
-use image::{ColorType, ImageEncoder, codecs::jpeg::JpegEncoder};
+use std::borrow::Cow;
+
+use image::{
+    ColorType, ImageEncoder, RgbImage,
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+    imageops,
+};
+use jpeg_encoder::SamplingFactor;
 use rayon::prelude::*; // Import Rayon traits
+use serde::Deserialize;
 
-pub fn compress_frame(raw_bgra: Vec<u8>, width: u32, height: u32) -> Vec<u8> {
-    let mut compressed = Vec::new();
+use crate::{encoder::EncoderConfig, platform};
 
-    let expected_len = (width * height * 4) as usize;
-    if raw_bgra.len() != expected_len {
-        return Vec::new();
+/// # Configure Thread Pool
+///
+/// Sizes the pool of the parallel conversions with `[encoder] threads` and lowers the priority of its threads with `low_priority`.
+///
+/// Note: `The pool is global and built once, a second call (a server started again in the same process) keeps the first settings.`
+pub fn configure_thread_pool(config: &EncoderConfig) {
+    let low_priority = config.low_priority;
+
+    let built = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .thread_name(|index| format!("convert-{index}"))
+        .start_handler(move |_| {
+            if low_priority && let Err(e) = platform::lower_thread_priority() {
+                tracing::warn!(error = %e, "Failed to lower the priority of a conversion thread");
+            }
+        })
+        .build_global();
+
+    match built {
+        Ok(()) => tracing::debug!(threads = rayon::current_num_threads(), low_priority, "Conversion pool ready"),
+        Err(e) => tracing::debug!(error = %e, "The conversion pool was already built"),
     }
+}
+
+/// # Chroma Subsampling
+///
+/// Resolution of the color channels of the JPEGs relative to the luminance, set with `[encoder] chroma`.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// full resolution color, sharp colored text and code.
+    #[default]
+    #[serde(rename = "4:4:4")]
+    Full,
+    /// half the horizontal resolution.
+    #[serde(rename = "4:2:2")]
+    Half,
+    /// a quarter of the resolution, smaller frames for video-like content.
+    #[serde(rename = "4:2:0")]
+    Quarter,
+}
+
+impl std::str::FromStr for ChromaSubsampling {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "4:4:4" | "444" => Ok(ChromaSubsampling::Full),
+            "4:2:2" | "422" => Ok(ChromaSubsampling::Half),
+            "4:2:0" | "420" => Ok(ChromaSubsampling::Quarter),
+            _ => Err(format!("expected 4:4:4, 4:2:2 or 4:2:0, got {value}")),
+        }
+    }
+}
+
+impl ChromaSubsampling {
+    fn sampling_factor(self) -> SamplingFactor {
+        match self {
+            ChromaSubsampling::Full => SamplingFactor::R_4_4_4,
+            ChromaSubsampling::Half => SamplingFactor::R_4_2_2,
+            ChromaSubsampling::Quarter => SamplingFactor::R_4_2_0,
+        }
+    }
+}
+
+/// Settings of a JPEG encode.
+#[derive(Clone, Copy, Debug)]
+pub struct JpegSettings {
+    /// from 1 to 100, see control::DEFAULT_QUALITY.
+    pub quality: u8,
+    /// only the luminance is encoded, the chroma subsampling does not apply.
+    pub grayscale: bool,
+    pub chroma: ChromaSubsampling,
+}
+
+/// # Compress Buffers
+///
+/// The scratch and output buffers of `compress_frame`, kept between frames so the conversion and the encoding reuse their allocations.
+#[derive(Default)]
+pub struct CompressBuffers {
+    /// the frame converted to RGB, or to luminance in grayscale.
+    pub converted: Vec<u8>,
+    /// the encoded JPEG, empty when the last frame failed.
+    pub jpeg: Vec<u8>,
+}
 
-    // 1. Pre-allocate exact size with 0s (Much faster than pushing)
-    let mut rgb_data = vec![0u8; (width * height * 3) as usize];
+/// # BGRA to RGB
+///
+/// Drops the alpha channel and swaps the red and blue channels, the conversion every JPEG encode starts with.
+pub fn bgra_to_rgb(raw_bgra: &[u8]) -> Vec<u8> {
+    let mut rgb_data = Vec::new();
+    bgra_to_rgb_into(raw_bgra, &mut rgb_data);
 
-    // 2. Parallel BGRA -> RGB Conversion (The FPS Fix)
+    rgb_data
+}
+
+/// # BGRA to RGB Into
+///
+/// `bgra_to_rgb` writing into a buffer of the caller, it only allocates when the frame grew.
+pub fn bgra_to_rgb_into(raw_bgra: &[u8], rgb_data: &mut Vec<u8>) {
+    //every byte is overwritten below, resizing only zeroes what the buffer did not already hold
+    rgb_data.resize(raw_bgra.len() / 4 * 3, 0);
+
+    // Parallel BGRA -> RGB Conversion (The FPS Fix)
     // We process 4-byte chunks of input (BGRA) and 3-byte chunks of output (RGB) in parallel
     rgb_data
         .par_chunks_exact_mut(3)
@@ -22,18 +126,382 @@ pub fn compress_frame(raw_bgra: Vec<u8>, width: u32, height: u32) -> Vec<u8> {
             rgb[1] = bgra[1]; // G
             rgb[2] = bgra[0]; // B
         });
+}
+
+/// # BGRA to Luma Into
+///
+/// Keeps only the luminance of every pixel (BT.601 weights), a third of the bytes of `bgra_to_rgb` for the grayscale mode.
+pub fn bgra_to_luma_into(raw_bgra: &[u8], luma_data: &mut Vec<u8>) {
+    luma_data.resize(raw_bgra.len() / 4, 0);
+
+    luma_data
+        .par_iter_mut()
+        .zip(raw_bgra.par_chunks_exact(4))
+        .for_each(|(luma, bgra)| {
+            //weights out of 256, rounded
+            *luma = ((bgra[2] as u32 * 77 + bgra[1] as u32 * 150 + bgra[0] as u32 * 29 + 128) >> 8) as u8;
+        });
+}
+
+/// # Convert Frame Into
+///
+/// The conversion a JPEG encode starts with, RGB or luminance only when `grayscale`.
+pub fn convert_frame_into(raw_bgra: &[u8], grayscale: bool, converted: &mut Vec<u8>) {
+    if grayscale {
+        bgra_to_luma_into(raw_bgra, converted);
+    } else {
+        bgra_to_rgb_into(raw_bgra, converted);
+    }
+}
+
+/// Part of a row its padding can be at most, more is a frame of another size rather than a padded one.
+const MAX_ROW_PADDING: usize = 4;
+
+/// # Detect Stride
+///
+/// The bytes per row of a BGRA frame of `len` bytes whose rows are padded past `width * 4` (the row pitch of a desktop duplication texture),
+/// with or without the padding of the last row. None when the frame is not `height` rows of the same padded size.
+pub fn detect_stride(len: usize, width: u32, height: u32) -> Option<usize> {
+    let (row, height) = (width as usize * 4, height as usize);
+
+    if row == 0 || height == 0 {
+        return None;
+    }
+
+    let stride = if len % height == 0 {
+        len / height
+    } else if height > 1 && len > row && (len - row) % (height - 1) == 0 {
+        (len - row) / (height - 1)
+    } else {
+        return None;
+    };
+
+    (stride > row && stride - row <= row / MAX_ROW_PADDING).then_some(stride)
+}
+
+/// # Strip Row Padding
+///
+/// Packs the rows of a frame of `stride` bytes per row in place, the frame is `width * height * 4` bytes afterwards.
+/// A frame too short for the stride is returned as it is.
+pub fn strip_row_padding(mut frame: Vec<u8>, width: u32, height: u32, stride: usize) -> Vec<u8> {
+    let (row, height) = (width as usize * 4, height as usize);
+
+    if stride <= row || height == 0 || frame.len() < stride * (height - 1) + row {
+        return frame;
+    }
+
+    //each row moves to a lower offset, the rows still to move are never overwritten
+    for y in 1..height {
+        frame.copy_within(y * stride..y * stride + row, y * row);
+    }
+
+    frame.truncate(row * height);
+    frame
+}
+
+/// # Packed Frame
+///
+/// The BGRA frame with rows of `width * 4` bytes: the frame itself when it has them, a copy without the padding when its rows are padded,
+/// none when it does not have the dimensions.
+pub fn packed_frame(raw_bgra: &[u8], width: u32, height: u32) -> Option<Cow<'_, [u8]>> {
+    let row = width as usize * 4;
+
+    if raw_bgra.len() == row * height as usize {
+        return Some(Cow::Borrowed(raw_bgra));
+    }
+
+    let stride = detect_stride(raw_bgra.len(), width, height)?;
+
+    Some(Cow::Owned(strip_row_padding(raw_bgra.to_vec(), width, height, stride)))
+}
+
+/// # Compress Frame
+///
+/// Encodes a raw BGRA frame as a JPEG into `buffers.jpeg`, returns false (and leaves it empty) if the frame does not match the dimensions
+/// or the encoding failed. Padded rows are packed first, see `packed_frame`.
+pub fn compress_frame(raw_bgra: &[u8], width: u32, height: u32, settings: &JpegSettings, buffers: &mut CompressBuffers) -> bool {
+    let Some(raw_bgra) = packed_frame(raw_bgra, width, height) else {
+        buffers.jpeg.clear();
+        return false;
+    };
+
+    convert_frame_into(&raw_bgra, settings.grayscale, &mut buffers.converted);
+
+    encode_jpeg(&buffers.converted, width, height, settings, &mut buffers.jpeg)
+}
+
+/// # Encode JPEG
+///
+/// Encodes a frame already converted with `convert_frame_into` into `compressed`, which is cleared first, returns false on failure.
+pub fn encode_jpeg(converted: &[u8], width: u32, height: u32, settings: &JpegSettings, compressed: &mut Vec<u8>) -> bool {
+    compressed.clear();
+
+    //the baseline format is limited to 65535 pixels on each side
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        tracing::error!(width, height, "Frame too large for a JPEG");
+        return false;
+    };
+
+    // Quality is controlled at runtime, see control::DEFAULT_QUALITY
+    let mut encoder = jpeg_encoder::Encoder::new(&mut *compressed, settings.quality.clamp(1, 100));
+    encoder.set_sampling_factor(settings.chroma.sampling_factor());
+
+    let color = match settings.grayscale {
+        true => jpeg_encoder::ColorType::Luma,
+        false => jpeg_encoder::ColorType::Rgb,
+    };
+
+    match encoder.encode(converted, width, height, color) {
+        Ok(_) => true,
+        Err(e) => {
+            tracing::error!(error = ?e, "JPEG encoding error");
+            compressed.clear();
+            false
+        }
+    }
+}
+
+/// # Encode PNG
+///
+/// Losslessly encodes a raw BGRA frame as a PNG, returns an empty Vec if the frame does not match the dimensions.
+pub fn encode_png(raw_bgra: &[u8], width: u32, height: u32) -> Vec<u8> {
+    png(raw_bgra, width, height, CompressionType::Default)
+}
+
+/// # Encode PNG Fast
+///
+/// Encodes like `encode_png` with the fastest compression, for the frames of a stream where the encode time matters more than a few bytes.
+pub fn encode_png_fast(raw_bgra: &[u8], width: u32, height: u32) -> Vec<u8> {
+    png(raw_bgra, width, height, CompressionType::Fast)
+}
+
+fn png(raw_bgra: &[u8], width: u32, height: u32, compression: CompressionType) -> Vec<u8> {
+    let mut encoded = Vec::new();
+
+    let Some(raw_bgra) = packed_frame(raw_bgra, width, height) else {
+        return Vec::new();
+    };
+
+    let mut rgba_data = vec![0u8; raw_bgra.len()];
+
+    rgba_data
+        .par_chunks_exact_mut(4)
+        .zip(raw_bgra.par_chunks_exact(4))
+        .for_each(|(rgba, bgra)| {
+            rgba[0] = bgra[2]; // R
+            rgba[1] = bgra[1]; // G
+            rgba[2] = bgra[0]; // B
+            rgba[3] = 255; // Captures carry no meaningful alpha
+        });
+
+    let encoder = PngEncoder::new_with_quality(&mut encoded, compression, FilterType::Adaptive);
+
+    match encoder.write_image(&rgba_data, width, height, ColorType::Rgba8.into()) {
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!(error = ?e, "PNG encoding error");
+            return Vec::new();
+        }
+    }
+
+    encoded
+}
+
+/// Length of the header of a QOI image.
+const QOI_HEADER_LEN: usize = 14;
+/// End of a QOI image, seven zero bytes and a one.
+const QOI_END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+/// Longest run of identical pixels a single `QOI_OP_RUN` holds.
+const QOI_MAX_RUN: u8 = 62;
+
+/// # Encode QOI
+///
+/// Losslessly encodes a raw BGRA frame as a QOI image (https://qoiformat.org), a pass over the pixels with no compression
+/// stage so it encodes about as fast as a copy: a desktop of text and flat colors comes out a few times the size of a JPEG without
+/// a single artifact around the characters. Returns an empty Vec if the frame does not match the dimensions.
+///
+/// Note: `Written with 3 channels, the captures carry no meaningful alpha.`
+pub fn encode_qoi(raw_bgra: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let Some(raw_bgra) = packed_frame(raw_bgra, width, height) else {
+        return Vec::new();
+    };
+
+    let mut encoded = Vec::with_capacity(QOI_HEADER_LEN + raw_bgra.len() / 4 + QOI_END.len());
+    encoded.extend_from_slice(b"qoif");
+    encoded.extend_from_slice(&width.to_be_bytes());
+    encoded.extend_from_slice(&height.to_be_bytes());
+    encoded.extend_from_slice(&[3, 0]); //rgb, srgb
+
+    let hash = |[r, g, b, a]: [u8; 4]| (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+
+    //the index starts transparent, so an opaque black pixel is not found in it before it was seen
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255];
+    let mut run = 0u8;
+
+    for bgra in raw_bgra.chunks_exact(4) {
+        let pixel = [bgra[2], bgra[1], bgra[0], 255];
+
+        if pixel == previous {
+            run += 1;
+
+            if run == QOI_MAX_RUN {
+                encoded.push(0xc0 | (run - 1));
+                run = 0;
+            }
+
+            continue;
+        }
+
+        if run > 0 {
+            encoded.push(0xc0 | (run - 1));
+            run = 0;
+        }
+
+        let slot = hash(pixel);
+
+        if index[slot] == pixel {
+            encoded.push(slot as u8);
+        } else {
+            index[slot] = pixel;
+
+            let dr = pixel[0].wrapping_sub(previous[0]) as i8;
+            let dg = pixel[1].wrapping_sub(previous[1]) as i8;
+            let db = pixel[2].wrapping_sub(previous[2]) as i8;
+            let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+
+            if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                encoded.push(0x40 | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+            } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                encoded.push(0x80 | (dg + 32) as u8);
+                encoded.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+            } else {
+                encoded.extend_from_slice(&[0xfe, pixel[0], pixel[1], pixel[2]]);
+            }
+        }
+
+        previous = pixel;
+    }
+
+    if run > 0 {
+        encoded.push(0xc0 | (run - 1));
+    }
+
+    encoded.extend_from_slice(&QOI_END);
+
+    encoded
+}
+
+/// # Decode QOI
+///
+/// Decodes a QOI image of `encode_qoi` (or of any QOI encoder) back to a raw BGRA frame, returns the width, the height and the pixels.
+/// `None` when the image is truncated or is not a QOI image.
+pub fn decode_qoi(data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+    if data.len() < QOI_HEADER_LEN + QOI_END.len() || &data[..4] != b"qoif" {
+        return None;
+    }
+
+    let width = u32::from_be_bytes(data[4..8].try_into().ok()?);
+    let height = u32::from_be_bytes(data[8..12].try_into().ok()?);
+    let pixels = (width as usize).checked_mul(height as usize)?;
+
+    //a byte holds a run of 62 pixels at most, a larger image is a malformed header rather than an allocation to make
+    if pixels > (data.len() - QOI_HEADER_LEN) * QOI_MAX_RUN as usize {
+        return None;
+    }
+
+    let hash = |[r, g, b, a]: [u8; 4]| (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+
+    let mut decoded = Vec::with_capacity(pixels * 4);
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0, 0, 0, 255];
+    let mut position = QOI_HEADER_LEN;
+    let body_end = data.len() - QOI_END.len();
+
+    while decoded.len() < pixels * 4 {
+        let &op = data.get(position).filter(|_| position < body_end)?;
+        position += 1;
+
+        let mut run = 1;
+
+        match op {
+            0xfe => {
+                let rgb = data.get(position..position + 3)?;
+                pixel = [rgb[0], rgb[1], rgb[2], pixel[3]];
+                position += 3;
+            }
+            0xff => {
+                let rgba = data.get(position..position + 4)?;
+                pixel = [rgba[0], rgba[1], rgba[2], rgba[3]];
+                position += 4;
+            }
+            _ => match op >> 6 {
+                0 => pixel = index[op as usize],
+                1 => {
+                    pixel[0] = pixel[0].wrapping_add((op >> 4 & 0x03).wrapping_sub(2));
+                    pixel[1] = pixel[1].wrapping_add((op >> 2 & 0x03).wrapping_sub(2));
+                    pixel[2] = pixel[2].wrapping_add((op & 0x03).wrapping_sub(2));
+                }
+                2 => {
+                    let &second = data.get(position)?;
+                    position += 1;
+
+                    let dg = (op & 0x3f).wrapping_sub(32);
+                    pixel[0] = pixel[0].wrapping_add(dg.wrapping_add((second >> 4).wrapping_sub(8)));
+                    pixel[1] = pixel[1].wrapping_add(dg);
+                    pixel[2] = pixel[2].wrapping_add(dg.wrapping_add((second & 0x0f).wrapping_sub(8)));
+                }
+                _ => run = (op & 0x3f) as usize + 1,
+            },
+        }
+
+        index[hash(pixel)] = pixel;
+
+        for _ in 0..run.min(pixels - decoded.len() / 4) {
+            decoded.extend_from_slice(&[pixel[2], pixel[1], pixel[0], 255]);
+        }
+    }
+
+    Some((width, height, decoded))
+}
+
+/// # Encode Thumbnail
+///
+/// Downscales a raw BGRA frame to the target width (keeping the aspect ratio) and encodes it as a JPEG.
+///
+/// Returns an empty Vec if the frame does not match the dimensions.
+pub fn encode_thumbnail(raw_bgra: &[u8], width: u32, height: u32, target_width: u32, quality: u8) -> Vec<u8> {
+    let mut compressed = Vec::new();
+
+    let Some(raw_bgra) = packed_frame(raw_bgra, width, height).filter(|_| width > 0) else {
+        return Vec::new();
+    };
+
+    let rgb_data = bgra_to_rgb(&raw_bgra);
+
+    let Some(image) = RgbImage::from_raw(width, height, rgb_data) else {
+        return Vec::new();
+    };
+
+    let target_width = target_width.clamp(1, width);
+    let target_height = ((height as u64 * target_width as u64) / width as u64).max(1) as u32;
+
+    let thumbnail = imageops::thumbnail(&image, target_width, target_height);
 
-    // 3. Encode
-    // Setting quality to 60-70 is usually a sweet spot for streaming speed vs quality
-    let encoder = JpegEncoder::new_with_quality(&mut compressed, 70);
+    let encoder = JpegEncoder::new_with_quality(&mut compressed, quality);
 
-    match encoder.write_image(&rgb_data, width, height, ColorType::Rgb8.into()) {
+    match encoder.write_image(
+        thumbnail.as_raw(),
+        target_width,
+        target_height,
+        ColorType::Rgb8.into(),
+    ) {
         Ok(_) => {}
         Err(e) => {
-            eprintln!("JPEG Encoding error: {:?}", e);
+            tracing::error!(error = ?e, "JPEG encoding error");
             return Vec::new();
         }
     }
 
     compressed
-}
\ No newline at end of file
+}
@@ -0,0 +1,250 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    http::{HeaderValue, Request},
+};
+use hyper::{body::Incoming, service::service_fn};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use serde::Deserialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::{
+    access::AccessList,
+    config::Config,
+    rate_limit::{ConnectionLimiter, ConnectionPermit},
+    state::StreamState,
+};
+
+/// Pause after a failed accept, it fails again at once while the process is out of file descriptors.
+pub(crate) const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// `[http]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// addresses the http listeners bind (`0.0.0.0:80`, `[::]:80`, the address of one interface...), the local ip at port 80 when empty.
+    pub listen: Vec<SocketAddr>,
+    /// keep connections open between requests, every request gets its own connection otherwise.
+    pub keep_alive: bool,
+    /// seconds a client has to send the headers of a request before the connection is closed.
+    pub header_timeout_seconds: u64,
+    /// serve HTTP/2 as well, one connection then carries the stream, the events and the api calls of a viewer.
+    pub http2: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: Vec::new(),
+            keep_alive: true,
+            header_timeout_seconds: 30,
+            http2: true,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// The addresses to bind, `fallback` when none are configured.
+    pub fn addresses(&self, fallback: SocketAddr) -> Vec<SocketAddr> {
+        if self.listen.is_empty() {
+            vec![fallback]
+        } else {
+            self.listen.clone()
+        }
+    }
+}
+
+/// # Gateway
+///
+/// The listeners clients connect to. Connections are checked against the `[access]` lists and the connection limit
+/// of `[rate_limit]` before any route sees them, then served by the router with the address of the client set
+/// as `X-Real-IP` on every request.
+pub struct Gateway {
+    access: AccessList,
+    trust_forwarded: bool,
+    connections: Arc<ConnectionLimiter>,
+    http: HttpConfig,
+}
+
+impl Gateway {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        Ok(Self {
+            access: AccessList::new(&config.access)?,
+            trust_forwarded: config.access.trust_forwarded,
+            connections: Arc::new(ConnectionLimiter::new(config.rate_limit.connections_per_ip)),
+            http: config.http.clone(),
+        })
+    }
+
+    /// Whether a client at the ip may connect.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.access.allows(ip)
+    }
+
+    /// Whether the listeners serve HTTP/2.
+    pub fn http2(&self) -> bool {
+        self.http.http2
+    }
+
+    /// Takes a connection slot of the ip, `None` when it already has as many connections open as the limit.
+    pub fn admit(&self, ip: IpAddr) -> Option<ConnectionPermit> {
+        self.connections.try_acquire(ip)
+    }
+}
+
+/// # Bind Gateway
+///
+/// Binds a listener at the address and spawns the task accepting its connections, terminating TLS first when an acceptor is given.
+pub async fn bind_gateway(
+    listen: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    gateway: Arc<Gateway>,
+    router: Router,
+    state: Arc<StreamState>,
+) -> std::io::Result<()> {
+    let listener = bind_listener(listen)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    info!("Now hosting on {scheme}://{listen}");
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!(error = %e, "Accept failed");
+                        tokio::time::sleep(ACCEPT_RETRY).await;
+                        continue;
+                    }
+                },
+                _ = state.shutting_down() => break,
+            };
+
+            if !gateway.allows(peer.ip()) {
+                debug!(%peer, "Refused a connection outside of the [access] lists");
+                continue;
+            }
+
+            let Some(permit) = gateway.admit(peer.ip()) else {
+                warn!(%peer, "Refused a connection, the ip has too many connections open");
+                continue;
+            };
+
+            let gateway = gateway.clone();
+            let router = router.clone();
+            let state = state.clone();
+            let tls = tls.clone();
+
+            tokio::spawn(
+                async move {
+                    //held until the connection closes
+                    let _permit = permit;
+                    let _ = stream.set_nodelay(true);
+
+                    match tls {
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(stream) => serve(stream, peer.ip(), scheme, &gateway, router, &state).await,
+                            //usually a browser refusing the self-signed certificate
+                            Err(e) => debug!(error = %e, "TLS handshake failed"),
+                        },
+                        None => serve(stream, peer.ip(), scheme, &gateway, router, &state).await,
+                    }
+                }
+                .instrument(info_span!("client", %peer, scheme)),
+            );
+        }
+    });
+
+    Ok(())
+}
+
+/// # Bind Listener
+///
+/// Binds a TCP listener at the address, IPv6 listeners only accept IPv6 so `[::]` and `0.0.0.0` can be bound on the same port.
+fn bind_listener(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    //rebinding right after a restart would fail while the old connections linger in TIME_WAIT
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves the requests of the connection until the client closes it, or the server shuts down. `scheme` is `https` for the
+/// connections TLS was terminated for, passed on as `X-Forwarded-Proto` (see `request_params::scheme`).
+pub(crate) async fn serve<S>(stream: S, peer: IpAddr, scheme: &'static str, gateway: &Gateway, router: Router, state: &StreamState)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let trust_forwarded = gateway.trust_forwarded;
+    let real_ip = HeaderValue::from_str(&peer.to_string()).expect("an ip is a valid header value");
+    let proto = HeaderValue::from_static(scheme);
+
+    let service = service_fn(move |mut req: Request<Incoming>| {
+        let headers = req.headers_mut();
+
+        //only the address of the connection is trusted, unless a proxy in front of the server sets the header
+        headers.remove("x-real-ip");
+        if !trust_forwarded {
+            headers.remove("x-forwarded-for");
+            headers.insert("x-forwarded-proto", proto.clone());
+        }
+        headers.insert("x-real-ip", real_ip.clone());
+
+        router.clone().oneshot(req)
+    });
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(gateway.http.keep_alive)
+        .header_read_timeout(Duration::from_secs(gateway.http.header_timeout_seconds.max(1)));
+    builder.http2().timer(TokioTimer::new());
+
+    //HTTP/2 is picked from the ALPN of TLS connections, or the connection preface of plain ones (prior knowledge)
+    if !gateway.http.http2 {
+        builder = builder.http1_only();
+    }
+
+    let connection = builder.serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = state.shutting_down() => {
+            //finishes the response in flight, the streams end on their own
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+
+    if let Err(e) = result {
+        debug!(error = %e, "Connection closed with an error");
+    }
+}
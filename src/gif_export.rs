@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+use image::{
+    Delay, Frame, ImageFormat, RgbaImage,
+    codecs::gif::{GifEncoder, Repeat},
+    imageops,
+};
+
+use crate::error::{Result, ShareScreenError};
+
+/// Seconds exported when the request does not provide them.
+pub const DEFAULT_GIF_SECONDS: u64 = 10;
+/// Width of the exported gif when the request does not provide one.
+pub const DEFAULT_GIF_WIDTH: u32 = 480;
+/// Frames per second of the exported gif, recent frames are downsampled to it.
+const GIF_FPS: u32 = 10;
+
+/// # Encode GIF
+///
+/// Transcodes encoded JPEG frames (oldest first, with the instant they were captured) into a looping animated GIF downscaled to the target width.
+///
+/// Frames are dropped to stay at `GIF_FPS`, the delay of each frame follows the time between captures.
+pub fn encode_gif(
+    frames: &[(Instant, std::sync::Arc<Vec<u8>>)],
+    target_width: u32,
+) -> Result<Vec<u8>> {
+    let interval = Duration::from_secs_f64(1.0 / GIF_FPS as f64);
+
+    //pick the frames to keep first so only those get decoded
+    let mut picked: Vec<&(Instant, std::sync::Arc<Vec<u8>>)> = Vec::new();
+    for frame in frames {
+        match picked.last() {
+            Some((last, _)) if frame.0.duration_since(*last) < interval => {}
+            _ => picked.push(frame),
+        }
+    }
+
+    if picked.is_empty() {
+        return Err(ShareScreenError::Server("No frames to export.".to_string()));
+    }
+
+    let mut gif = Vec::new();
+
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut gif, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        for (i, (captured, jpeg)) in picked.iter().enumerate() {
+            let decoded = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)?.to_rgba8();
+
+            let width = target_width.clamp(1, decoded.width());
+            let height =
+                ((decoded.height() as u64 * width as u64) / decoded.width() as u64).max(1) as u32;
+
+            let scaled: RgbaImage = imageops::thumbnail(&decoded, width, height);
+
+            let delay = match picked.get(i + 1) {
+                Some((next, _)) => next.duration_since(*captured),
+                None => interval,
+            };
+
+            encoder.encode_frame(Frame::from_parts(
+                scaled,
+                0,
+                0,
+                Delay::from_saturating_duration(delay),
+            ))?;
+        }
+    }
+
+    Ok(gif)
+}
@@ -0,0 +1,375 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+};
+
+use async_stream::stream;
+use futures::Stream;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Request, Response, Status, transport::Server};
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::{Role, token_identity},
+    devices::{DeviceList, list_devices},
+    events::ServerEvent,
+    gateway::Gateway,
+    packets::{FLAG_ENCRYPTED, PacketType, dimensions_packet, frame_sequence, parse},
+    presets::{StreamPacing, StreamPreset},
+    request_params::UNKNOWN_IP,
+    state::StreamState,
+    streamed_resolution::BandwidthCap,
+    viewers::{ViewerClient, ViewersConfig},
+};
+
+/// Messages and service generated from `proto/share_screen.proto`.
+pub mod proto {
+    tonic::include_proto!("share_screen.v1");
+}
+
+use proto::share_screen_server::{ShareScreen, ShareScreenServer};
+
+/// Times in a row a client may fall behind the broadcast before it is dropped.
+const MAX_CONSECUTIVE_LAGS: u32 = 5;
+
+/// `[grpc]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 50051,
+        }
+    }
+}
+
+/// # Spawn Grpc
+///
+/// Serves the `ShareScreen` gRPC service of `proto/share_screen.proto` at `<ip>:<port>`: the devices, the packets of the stream
+/// with their header as fields, the status and the pause, quality and fps controls, for programs that would rather use
+/// typed protobuf than parse the HTTP framing.
+///
+/// Note: `Requests carry one of the [auth] tokens as "authorization: Bearer <token>" metadata, and are refused
+/// outside of the [access] lists like the control channel.`
+pub fn spawn_grpc(
+    config: GrpcConfig,
+    ip: IpAddr,
+    viewers: ViewersConfig,
+    gateway: Arc<Gateway>,
+    state: Arc<StreamState>,
+) {
+    if !config.enabled {
+        return;
+    }
+
+    let address = SocketAddr::new(ip, config.port);
+    let service = GrpcService {
+        state: state.clone(),
+        viewers,
+    };
+
+    let authorized = state.clone();
+    let server = ShareScreenServer::with_interceptor(service, move |mut req: Request<()>| {
+        if req.remote_addr().is_some_and(|peer| !gateway.allows(peer.ip())) {
+            return Err(Status::permission_denied("outside of the [access] lists"));
+        }
+
+        let token = req
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        let role = match authorized.auth.enabled() {
+            true => token.and_then(|token| authorized.auth.token_role(token)),
+            false => Some(Role::Controller),
+        };
+
+        //the methods changing the stream check the role
+        match role {
+            Some(role) => {
+                req.extensions_mut().insert(role);
+                Ok(req)
+            }
+            None => Err(Status::unauthenticated("provide a valid token")),
+        }
+    });
+
+    tokio::spawn(async move {
+        info!("gRPC service at {address}");
+
+        let shutdown = state.clone();
+        let served = Server::builder()
+            .add_service(server)
+            .serve_with_shutdown(address, async move { shutdown.shutting_down().await })
+            .await;
+
+        if let Err(e) = served {
+            state.stats.error();
+            error!(%address, error = %e, "The gRPC service stopped");
+        }
+    });
+}
+
+struct GrpcService {
+    state: Arc<StreamState>,
+    viewers: ViewersConfig,
+}
+
+#[tonic::async_trait]
+impl ShareScreen for GrpcService {
+    type StreamFramesStream = Pin<Box<dyn Stream<Item = Result<proto::Packet, Status>> + Send>>;
+
+    async fn list_devices(&self, _: Request<proto::ListDevicesRequest>) -> Result<Response<proto::DeviceList>, Status> {
+        let devices = tokio::task::spawn_blocking(|| list_devices().map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::internal)?;
+
+        Ok(Response::new(devices.into()))
+    }
+
+    async fn stream_frames(
+        &self,
+        request: Request<proto::StreamFramesRequest>,
+    ) -> Result<Response<Self::StreamFramesStream>, Status> {
+        let ip = request.remote_addr().map(|peer| peer.ip().to_string());
+        let user_agent = request
+            .metadata()
+            .get("user-agent")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let identity = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(token_identity);
+        let request = request.into_inner();
+
+        let preset = match request.preset.as_str() {
+            "" => self.state.preset,
+            name => Some(name.parse::<StreamPreset>().map_err(Status::invalid_argument)?),
+        };
+
+        if ip.as_deref().is_some_and(|ip| self.state.viewers.is_banned(ip)) {
+            return Err(Status::permission_denied("Banned from the stream"));
+        }
+
+        //refuse new viewers instead of slowing the stream down for everyone
+        if !self.viewers.has_room(self.state.stats.viewers()) {
+            return Err(Status::resource_exhausted(format!(
+                "The stream is full ({} viewers), try again later",
+                self.viewers.max
+            )));
+        }
+
+        let state = self.state.clone();
+        let mut rx = state.frames.subscribe();
+        let mut pacing = StreamPacing::new(preset).with_cap(BandwidthCap::from_kbps(request.max_kbps));
+        let client = ViewerClient {
+            ip: ip.unwrap_or_else(|| UNKNOWN_IP.to_string()),
+            user_agent,
+            container: "grpc",
+            identity,
+        };
+
+        let packets = stream! {
+            //counted as a viewer until the client goes away
+            let viewer = state.viewer(client);
+
+            //sizes the client before the first frame, the framing only sends them on changes
+            let dimensions = state.dimensions();
+            let first = dimensions_packet(0, state.timestamp_us(), dimensions.width as u32, dimensions.height as u32);
+
+            if let Some(packet) = to_message(&state.seal_packet(first)) {
+                yield Ok(packet);
+            }
+
+            //then the latest frame, without waiting for the next one
+            let primed = state.latest_packet().filter(|packet| pacing.skip(packet, 0).is_none());
+            let primed_sequence = primed.as_deref().and_then(|packet| frame_sequence(packet));
+
+            if let Some(packet) = primed {
+                let packet = state.seal_packet(packet.to_vec());
+                viewer.add_bytes_sent(packet.len());
+
+                if let Some(packet) = to_message(&packet) {
+                    yield Ok(packet);
+                }
+            }
+
+            let mut lags = 0;
+
+            loop {
+                let received = tokio::select! {
+                    received = rx.recv() => received,
+                    _ = state.shutting_down() => break,
+                    _ = viewer.kicked() => break,
+                };
+
+                let data = match received {
+                    Ok(data) => {
+                        lags = 0;
+
+                        //broadcast while the stream was subscribing, already sent
+                        if primed_sequence.is_some() && frame_sequence(&data) == primed_sequence {
+                            continue;
+                        }
+
+                        if let Some(skipped) = pacing.skip(&data, rx.len()) {
+                            viewer.add_skipped(skipped);
+                            continue;
+                        }
+
+                        data
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        viewer.add_frames_lagged(missed);
+                        lags += 1;
+
+                        if lags >= MAX_CONSECUTIVE_LAGS {
+                            warn!("Dropping a gRPC viewer that cannot keep up with the stream");
+                            break;
+                        }
+
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let data = state.seal_packet(data);
+
+                viewer.add_bytes_sent(data.len());
+
+                if let Some(packet) = to_message(&data) {
+                    yield Ok(packet);
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(packets)))
+    }
+
+    async fn get_status(&self, _: Request<proto::GetStatusRequest>) -> Result<Response<proto::StreamStatus>, Status> {
+        Ok(Response::new(status(&self.state)))
+    }
+
+    async fn set_paused(&self, request: Request<proto::SetPausedRequest>) -> Result<Response<proto::StreamStatus>, Status> {
+        controller(&request)?;
+        let paused = request.into_inner().paused;
+
+        if self.state.control.is_paused() != paused {
+            self.state.control.set_paused(paused);
+            self.state.emit(if paused { ServerEvent::Paused } else { ServerEvent::Resumed });
+        }
+
+        Ok(Response::new(status(&self.state)))
+    }
+
+    async fn set_quality(&self, request: Request<proto::SetQualityRequest>) -> Result<Response<proto::StreamStatus>, Status> {
+        controller(&request)?;
+        let quality = request.into_inner().quality;
+
+        if !(1..=100).contains(&quality) {
+            return Err(Status::invalid_argument("quality must be from 1 to 100"));
+        }
+
+        self.state.control.set_quality(quality as u8);
+
+        Ok(Response::new(status(&self.state)))
+    }
+
+    async fn set_fps(&self, request: Request<proto::SetFpsRequest>) -> Result<Response<proto::StreamStatus>, Status> {
+        controller(&request)?;
+        self.state.control.set_max_fps(request.into_inner().fps);
+
+        Ok(Response::new(status(&self.state)))
+    }
+}
+
+/// Refuses the requests of viewer tokens, the role was set by the interceptor.
+fn controller<T>(request: &Request<T>) -> Result<(), Status> {
+    match request.extensions().get::<Role>() {
+        Some(role) if *role >= Role::Controller => Ok(()),
+        _ => Err(Status::permission_denied("this token can only watch the stream")),
+    }
+}
+
+/// The header of a packet as the fields of its message, `None` for a malformed packet.
+fn to_message(packet: &[u8]) -> Option<proto::Packet> {
+    let (header, payload) = parse(packet)?;
+
+    let kind = match header.kind {
+        PacketType::Frame => proto::PacketKind::Frame,
+        PacketType::Dimensions => proto::PacketKind::Dimensions,
+        PacketType::Audio => proto::PacketKind::Audio,
+        PacketType::Heartbeat => proto::PacketKind::Heartbeat,
+    };
+
+    Some(proto::Packet {
+        kind: kind as i32,
+        sequence: header.sequence,
+        timestamp_us: header.timestamp_us,
+        encrypted: header.flags & FLAG_ENCRYPTED != 0,
+        payload: payload.to_vec(),
+    })
+}
+
+fn status(state: &StreamState) -> proto::StreamStatus {
+    let status = state.status();
+
+    proto::StreamStatus {
+        paused: status.paused,
+        blanked: status.blanked,
+        source: status.source,
+        quality: status.quality as u32,
+        grayscale: status.grayscale,
+        mirrored: status.mirrored,
+        scale: status.scale as u32,
+        fps: status.fps,
+        viewers: status.viewers as u32,
+    }
+}
+
+impl From<DeviceList> for proto::DeviceList {
+    fn from(devices: DeviceList) -> Self {
+        Self {
+            cameras: devices
+                .cameras
+                .into_iter()
+                .map(|camera| proto::Camera {
+                    index: camera.index as u32,
+                    name: camera.name,
+                    formats: camera
+                        .formats
+                        .into_iter()
+                        .map(|format| proto::CameraFormat {
+                            format: format.format,
+                            width: format.width,
+                            height: format.height,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            monitors: devices
+                .monitors
+                .into_iter()
+                .map(|monitor| proto::Monitor {
+                    index: monitor.index as u32,
+                    name: monitor.name,
+                    width: monitor.width,
+                    height: monitor.height,
+                    primary: monitor.primary,
+                })
+                .collect(),
+        }
+    }
+}
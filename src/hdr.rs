@@ -0,0 +1,239 @@
+use rayon::prelude::*;
+use serde::Deserialize;
+
+/// Brightness of the SDR white in nits when none is configured, the reference white of BT.2408.
+pub const DEFAULT_SDR_WHITE_NITS: f32 = 203.0;
+/// Brightness scRGB 1.0 stands for.
+const SCRGB_WHITE_NITS: f32 = 80.0;
+/// Part of the SDR range kept as it is by the `reinhard` tone mapping, the highlights are rolled off above it.
+const KNEE: f32 = 0.8;
+/// Entries of the sRGB encoding table, the linear values in between share an entry.
+const SRGB_STEPS: usize = 4096;
+
+/// # Pixel Format
+///
+/// Layout of the raw frames of a capture, everything but `Bgra8` goes through the `ToneMapper` before the rest of the pipeline.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelFormat {
+    /// 8 bits per channel sRGB, what every encoder expects.
+    #[default]
+    Bgra8,
+    /// HDR10, 10 bits per channel packed in a little-endian u32 (R10G10B10A2), PQ encoded with the BT.2020 primaries.
+    Hdr10,
+    /// scRGB, a half float per channel (R16G16B16A16), linear with the BT.709 primaries where 1.0 is 80 nits.
+    ScRgb,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgra8 | PixelFormat::Hdr10 => 4,
+            PixelFormat::ScRgb => 8,
+        }
+    }
+}
+
+/// How the highlights above the SDR white are brought into range.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapping {
+    /// rolls the top of the range off up to `peak_nits`, keeps the detail of bright scenes.
+    #[default]
+    Reinhard,
+    /// cuts everything above the SDR white, the SDR content looks exactly as on the host.
+    Clip,
+}
+
+/// `[hdr]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct HdrConfig {
+    /// pixel format of the captured frames, detected from the source and the size of the frames when not set.
+    pub format: Option<PixelFormat>,
+    pub tone_mapping: ToneMapping,
+    /// brightness the SDR white is shown at on the host, the "SDR content brightness" of the windows display settings.
+    pub sdr_white_nits: f32,
+    /// brightest highlight kept by the `reinhard` tone mapping, brighter ones are white.
+    pub peak_nits: f32,
+}
+
+impl Default for HdrConfig {
+    fn default() -> Self {
+        Self {
+            format: None,
+            tone_mapping: ToneMapping::default(),
+            sdr_white_nits: DEFAULT_SDR_WHITE_NITS,
+            peak_nits: 1000.0,
+        }
+    }
+}
+
+/// # Tone Mapper
+///
+/// Converts the frames of HDR monitors (`Hdr10` and `ScRgb`) to 8 bit sRGB BGRA, the encoders and the rest of the pipeline only see SDR frames.
+///
+/// Note: `The PQ curve and the sRGB encoding are tables built once, a frame costs a matrix and the tone mapping per pixel.`
+pub struct ToneMapper {
+    format: Option<PixelFormat>,
+    tone_mapping: ToneMapping,
+    //peak above the knee, squared for the extended reinhard
+    white_squared: f32,
+    sdr_white_nits: f32,
+    //10 bit PQ code to linear light, 1.0 being the SDR white
+    pq: Vec<f32>,
+    srgb: Vec<u8>,
+}
+
+impl ToneMapper {
+    pub fn new(config: &HdrConfig) -> Self {
+        let sdr_white_nits = config.sdr_white_nits.clamp(1.0, 10_000.0);
+        let peak = (config.peak_nits / sdr_white_nits).max(1.0);
+        let white = (peak - KNEE) / (1.0 - KNEE);
+
+        Self {
+            format: config.format,
+            tone_mapping: config.tone_mapping,
+            white_squared: white * white,
+            sdr_white_nits,
+            pq: (0..1024).map(|code| pq_to_nits(code as f32 / 1023.0) / sdr_white_nits).collect(),
+            srgb: (0..SRGB_STEPS)
+                .map(|step| (srgb_encode(step as f32 / (SRGB_STEPS - 1) as f32) * 255.0).round() as u8)
+                .collect(),
+        }
+    }
+
+    /// # Detect
+    ///
+    /// The format of a frame of the source, the configured one first, then frames of 8 bytes per pixel are scRGB whatever the source reports.
+    pub fn detect(&self, source: PixelFormat, frame_len: usize, width: u32, height: u32) -> PixelFormat {
+        if let Some(format) = self.format {
+            return format;
+        }
+
+        if frame_len == width as usize * height as usize * PixelFormat::ScRgb.bytes_per_pixel() {
+            return PixelFormat::ScRgb;
+        }
+
+        source
+    }
+
+    /// # To SDR
+    ///
+    /// The frame as BGRA, unchanged in `Bgra8` and when it does not match the dimensions (the pipeline queries them again).
+    pub fn to_sdr(&self, frame: Vec<u8>, width: u32, height: u32, format: PixelFormat) -> Vec<u8> {
+        let pixels = width as usize * height as usize;
+
+        if frame.len() != pixels * format.bytes_per_pixel() {
+            return frame;
+        }
+
+        match format {
+            PixelFormat::Bgra8 => frame,
+            PixelFormat::Hdr10 => {
+                let mut frame = frame;
+
+                //same size as BGRA, converted in place
+                frame.par_chunks_exact_mut(4).for_each(|pixel| {
+                    let packed = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    let (r, g, b) = (
+                        self.pq[(packed & 0x3ff) as usize],
+                        self.pq[((packed >> 10) & 0x3ff) as usize],
+                        self.pq[((packed >> 20) & 0x3ff) as usize],
+                    );
+
+                    pixel.copy_from_slice(&self.bgra(bt2020_to_bt709(r, g, b)));
+                });
+
+                frame
+            }
+            PixelFormat::ScRgb => {
+                let scale = SCRGB_WHITE_NITS / self.sdr_white_nits;
+                let mut bgra = vec![0u8; pixels * 4];
+
+                bgra.par_chunks_exact_mut(4)
+                    .zip(frame.par_chunks_exact(8))
+                    .for_each(|(out, pixel)| {
+                        let channel = |i: usize| f16_to_f32(u16::from_le_bytes([pixel[i], pixel[i + 1]])) * scale;
+
+                        out.copy_from_slice(&self.bgra((channel(0), channel(2), channel(4))));
+                    });
+
+                bgra
+            }
+        }
+    }
+
+    /// Tone maps and encodes a linear BT.709 color, 1.0 being the SDR white.
+    fn bgra(&self, (r, g, b): (f32, f32, f32)) -> [u8; 4] {
+        let encode = |value: f32| {
+            //negative values are the colors outside of BT.709 (and NaN), clamped like any out of gamut color
+            let value = match self.tone_mapping {
+                ToneMapping::Reinhard if value > KNEE => {
+                    //extended reinhard from the knee, the peak ends up at 1.0
+                    let above = (value - KNEE) / (1.0 - KNEE);
+                    KNEE + (1.0 - KNEE) * above * (1.0 + above / self.white_squared) / (1.0 + above)
+                }
+                ToneMapping::Reinhard | ToneMapping::Clip => value,
+            };
+
+            self.srgb[(value.clamp(0.0, 1.0) * (SRGB_STEPS - 1) as f32) as usize]
+        };
+
+        [encode(b), encode(g), encode(r), 255]
+    }
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self::new(&HdrConfig::default())
+    }
+}
+
+/// The SMPTE ST 2084 (PQ) EOTF, a signal from 0.0 to 1.0 to nits.
+fn pq_to_nits(signal: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_562;
+    const C3: f32 = 18.6875;
+
+    let power = signal.clamp(0.0, 1.0).powf(1.0 / M2);
+
+    ((power - C1).max(0.0) / (C2 - C3 * power)).powf(1.0 / M1) * 10_000.0
+}
+
+/// The sRGB encoding of a linear value from 0.0 to 1.0.
+fn srgb_encode(linear: f32) -> f32 {
+    match linear <= 0.003_130_8 {
+        true => linear * 12.92,
+        false => 1.055 * linear.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+/// Linear BT.2020 to linear BT.709 primaries.
+fn bt2020_to_bt709(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    (
+        1.660_491 * r - 0.587_641 * g - 0.072_850 * b,
+        -0.124_550 * r + 1.132_900 * g - 0.008_349 * b,
+        -0.018_151 * r - 0.100_579 * g + 1.118_730 * b,
+    )
+}
+
+/// A half float to f32.
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half >> 15) as u32) << 31;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+
+    match exponent {
+        //zero and the subnormals, mantissa * 2^-24
+        0 => {
+            let value = mantissa as f32 / 16_777_216.0;
+            if sign != 0 { -value } else { value }
+        }
+        //infinities and NaN
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::state::StreamState;
+
+/// # Health
+///
+/// Rest API Json of `/healthz`, the stream is healthy while the device is capturing and frames are being encoded.
+#[derive(Serialize)]
+pub struct Health {
+    pub healthy: bool,
+    /// `ok`, `paused`, `capture_stopped` or `stalled`
+    pub status: &'static str,
+    /// description of the problem when unhealthy.
+    pub error: Option<String>,
+    /// seconds since the last frame was encoded.
+    pub last_frame_secs: f64,
+    /// seconds since the capture stopped, while it is down.
+    pub capture_down_secs: Option<f64>,
+}
+
+impl Health {
+    /// # Check health
+    ///
+    /// Unhealthy when the capture stopped (and is being reacquired or waits for a restart) or no frame was encoded within `stall_timeout`,
+    /// the stall is only checked with a timeout (the `[watchdog]` on): a still desktop encodes no frames.
+    ///
+    /// A paused stream is healthy, no frames are encoded on purpose.
+    pub fn check(state: &StreamState, stall_timeout: Option<Duration>) -> Self {
+        let since_last_frame = state.since_last_frame();
+
+        let (status, error) = if let Some(error) = state.capture_error() {
+            ("capture_stopped", Some(error))
+        } else if state.control.is_paused() {
+            ("paused", None)
+        } else if stall_timeout.is_some_and(|timeout| since_last_frame >= timeout) {
+            (
+                "stalled",
+                Some(format!("no frames for {}s", since_last_frame.as_secs())),
+            )
+        } else {
+            ("ok", None)
+        };
+
+        Self {
+            healthy: error.is_none(),
+            status,
+            error,
+            last_frame_secs: since_last_frame.as_secs_f64(),
+            capture_down_secs: state.capture_down_for().map(|down| down.as_secs_f64()),
+        }
+    }
+
+    /// 200 when healthy, 503 otherwise.
+    pub fn status_code(&self) -> u16 {
+        if self.healthy { 200 } else { 503 }
+    }
+}
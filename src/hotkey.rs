@@ -0,0 +1,219 @@
+use std::sync::Arc;
+#[cfg(windows)]
+use std::time::Duration;
+
+use serde::Deserialize;
+#[cfg(windows)]
+use tracing::{error, info};
+#[cfg(windows)]
+use windows::Win32::UI::{
+    Input::KeyboardAndMouse::{
+        HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN, RegisterHotKey, UnregisterHotKey,
+    },
+    WindowsAndMessaging::{MSG, PM_REMOVE, PeekMessageW, WM_HOTKEY},
+};
+
+use crate::state::StreamState;
+#[cfg(windows)]
+use crate::{events::ServerEvent, state::RawFrame};
+
+/// Time between two checks of the hotkey messages, keeps the switch well under a second.
+#[cfg(windows)]
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Id of the pause hotkey in the `WM_HOTKEY` messages.
+#[cfg(windows)]
+const PAUSE_ID: i32 = 1;
+/// Id of the blank hotkey in the `WM_HOTKEY` messages.
+#[cfg(windows)]
+const BLANK_ID: i32 = 2;
+
+/// `[hotkey]` section of the config, key combinations like `Ctrl+Alt+P` working system wide.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    /// toggles the broadcast of frames, viewers keep the last frame.
+    pub pause: Option<String>,
+    /// toggles sending black frames instead of the capture, viewers see the black frame right away.
+    pub blank: Option<String>,
+}
+
+/// # Hotkey
+///
+/// A key combination registered with `RegisterHotKey`.
+#[cfg(windows)]
+#[derive(Clone, Copy)]
+pub struct Hotkey {
+    modifiers: HOT_KEY_MODIFIERS,
+    key: u32,
+}
+
+#[cfg(windows)]
+impl Hotkey {
+    /// # Parse
+    ///
+    /// Parses a combination of `Ctrl`, `Alt`, `Shift`, `Win` and one key: a letter, a digit, `F1`-`F24`, `Space`, `Pause`,
+    /// `Escape`, `Insert`, `Delete`, `Home`, `End`, `PageUp` or `PageDown`, joined by `+` (case insensitive).
+    pub fn parse(combination: &str) -> Result<Self, String> {
+        let mut modifiers = MOD_NOREPEAT;
+        let mut key = None;
+
+        for part in combination.split('+').map(str::trim) {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= MOD_CONTROL,
+                "alt" => modifiers |= MOD_ALT,
+                "shift" => modifiers |= MOD_SHIFT,
+                "win" | "super" => modifiers |= MOD_WIN,
+                name if key.is_none() => {
+                    key = Some(virtual_key(name).ok_or_else(|| format!("Unknown key {part} in hotkey {combination}"))?)
+                }
+                _ => return Err(format!("Hotkey {combination} has more than one key")),
+            }
+        }
+
+        let key = key.ok_or_else(|| format!("Hotkey {combination} has no key"))?;
+
+        if modifiers == MOD_NOREPEAT {
+            return Err(format!("Hotkey {combination} needs a modifier (Ctrl, Alt, Shift or Win)"));
+        }
+
+        Ok(Self { modifiers, key })
+    }
+}
+
+/// The virtual key code of a key name.
+#[cfg(windows)]
+fn virtual_key(name: &str) -> Option<u32> {
+    let code = match name {
+        "space" => 0x20,
+        "pause" => 0x13,
+        "escape" | "esc" => 0x1B,
+        "pageup" => 0x21,
+        "pagedown" => 0x22,
+        "end" => 0x23,
+        "home" => 0x24,
+        "insert" => 0x2D,
+        "delete" => 0x2E,
+        _ => {
+            let mut chars = name.chars();
+
+            return match (chars.next(), chars.next()) {
+                //letters and digits are their uppercase ascii code
+                (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u32),
+                (Some('f'), Some(_)) => match name[1..].parse::<u32>() {
+                    Ok(n @ 1..=24) => Some(0x70 + n - 1),
+                    _ => None,
+                },
+                _ => None,
+            };
+        }
+    };
+
+    Some(code)
+}
+
+/// # Spawn Hotkeys
+///
+/// Registers the hotkeys of the config on their own thread, `pause` toggles the broadcast of frames
+/// and `blank` toggles sending black frames. Nothing is spawned without any hotkey configured.
+///
+/// Note: `A combination already taken by another program fails to register, it is logged and the others still work.`
+#[cfg(windows)]
+pub fn spawn_hotkeys(config: HotkeyConfig, state: Arc<StreamState>) -> Result<(), String> {
+    let hotkeys: Vec<(i32, Hotkey, String)> = [(PAUSE_ID, config.pause), (BLANK_ID, config.blank)]
+        .into_iter()
+        .filter_map(|(id, combination)| combination.map(|combination| (id, combination)))
+        .map(|(id, combination)| Hotkey::parse(&combination).map(|hotkey| (id, hotkey, combination)))
+        .collect::<Result<_, _>>()?;
+
+    if hotkeys.is_empty() {
+        return Ok(());
+    }
+
+    //hotkeys are delivered to the message queue of the thread that registered them
+    std::thread::Builder::new()
+        .name("hotkeys".to_string())
+        .spawn(move || run_hotkeys(&hotkeys, &state))
+        .map_err(|e| format!("Failed to spawn the hotkey thread: {e}"))?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn run_hotkeys(hotkeys: &[(i32, Hotkey, String)], state: &StreamState) {
+    for (id, hotkey, combination) in hotkeys {
+        match unsafe { RegisterHotKey(None, *id, hotkey.modifiers, hotkey.key) } {
+            Ok(()) => info!(hotkey = %combination, "Hotkey registered"),
+            Err(e) => {
+                state.stats.error();
+                error!(hotkey = %combination, error = %e, "Failed to register the hotkey, it may be used by another program");
+            }
+        }
+    }
+
+    let mut message = MSG::default();
+
+    while !state.is_shutting_down() {
+        while unsafe { PeekMessageW(&mut message, None, WM_HOTKEY, WM_HOTKEY, PM_REMOVE) }.as_bool() {
+            match message.wParam.0 as i32 {
+                PAUSE_ID => toggle_pause(state),
+                BLANK_ID => toggle_blank(state),
+                _ => {}
+            }
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    for (id, _, _) in hotkeys {
+        let _ = unsafe { UnregisterHotKey(None, *id) };
+    }
+}
+
+#[cfg(windows)]
+fn toggle_pause(state: &StreamState) {
+    let paused = !state.control.is_paused();
+    state.control.set_paused(paused);
+    state.emit(if paused { ServerEvent::Paused } else { ServerEvent::Resumed });
+
+    info!(paused, "Pause toggled by the hotkey");
+}
+
+#[cfg(windows)]
+fn toggle_blank(state: &StreamState) {
+    let blanked = !state.control.is_blanked();
+    state.control.set_blanked(blanked);
+
+    //a monitor that does not change sends no new frame, the black one is sent right away
+    if blanked {
+        send_black_frame(state);
+    }
+
+    info!(blanked, "Blank toggled by the hotkey");
+}
+
+/// Broadcasts a black frame at the current dimensions, it also becomes the latest frame of the snapshots.
+#[cfg(windows)]
+fn send_black_frame(state: &StreamState) {
+    let dimensions = state.dimensions();
+    let frame = RawFrame {
+        data: vec![0; dimensions.width * dimensions.height * 4],
+        width: dimensions.width as u32,
+        height: dimensions.height as u32,
+        timestamp_us: state.timestamp_us(),
+    };
+
+    if let Ok(encoded) = state.encoder.encode(&frame, state.control.encode_settings()) {
+        state.send_still(Arc::new(frame), Arc::new(encoded));
+    }
+}
+
+/// # Spawn Hotkeys
+///
+/// Global hotkeys are registered with the win32 message loop, configuring one fails the startup on the other platforms.
+#[cfg(not(windows))]
+pub fn spawn_hotkeys(config: HotkeyConfig, _state: Arc<StreamState>) -> Result<(), String> {
+    match config.pause.or(config.blank) {
+        Some(combination) => Err(format!("Hotkey {combination} cannot be registered, hotkeys are only supported on Windows")),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,107 @@
+//! Screen and camera sharing over HTTP, the capture pipeline and the web app of the `share-screen` binary.
+//!
+//! Applications embed it through [`ShareServer`], started with the config and the device to capture.
+
+pub mod access;
+pub mod admin;
+pub mod aggregate;
+pub mod assets;
+pub mod api;
+pub mod audio;
+pub mod audit;
+pub mod auth;
+pub mod autocrop;
+pub mod av1;
+pub mod bandwidth;
+pub mod bench;
+pub mod bitrate;
+pub mod blackout;
+pub mod bytes_resolution;
+pub mod cameras;
+pub mod capabilities;
+pub mod captures;
+pub mod chat;
+pub mod codec_feeds;
+pub mod compression;
+pub mod config;
+pub mod config_reload;
+pub mod containers;
+pub mod control;
+pub mod control_channel;
+pub mod cors;
+pub mod delta_frames;
+pub mod devices;
+pub mod encoder;
+pub mod encryption;
+pub mod error;
+pub mod event_stream;
+pub mod events;
+pub mod exclusion;
+#[cfg(windows)]
+pub mod file_source;
+pub mod files;
+pub mod frame_compressor;
+pub mod gateway;
+pub mod gif_export;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hdr;
+pub mod health;
+pub mod hotkey;
+pub mod link_codec;
+pub mod logging;
+pub mod motion;
+#[cfg(feature = "viewer")]
+pub mod native_viewer;
+pub mod notify;
+pub mod oidc;
+pub mod onvif;
+pub mod openapi;
+pub mod overlay;
+pub mod packets;
+pub mod pipeline;
+pub mod pipeline_stats;
+pub mod placeholder;
+pub mod platform;
+pub mod pointer;
+pub mod presets;
+pub mod qr;
+pub mod rate_limit;
+pub mod recorder;
+pub mod relay;
+pub mod remote_input;
+pub mod replay;
+pub mod request_params;
+pub mod retention;
+pub mod routes;
+pub mod rtsp;
+pub mod scaling;
+pub mod schedule;
+pub mod server;
+pub mod session;
+pub mod snapshots;
+pub mod source;
+pub mod state;
+pub mod static_files;
+pub mod streamed_resolution;
+pub mod tee;
+pub mod test_pattern;
+pub mod thumbnails;
+pub mod timelapse;
+pub mod tls;
+pub mod transcoder;
+pub mod transform;
+#[cfg(all(windows, feature = "tray"))]
+pub mod tray;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod tunnel;
+pub mod upnp;
+pub mod user_settings;
+pub mod viewers;
+pub mod virtual_camera;
+pub mod vpx;
+pub mod watchdog;
+pub mod webhooks;
+
+pub use server::{ShareServer, ShareServerBuilder};
@@ -0,0 +1,142 @@
+use std::{net::IpAddr, str::FromStr};
+
+use serde::Deserialize;
+use tracing::{debug, info};
+
+/// # Link Codec
+///
+/// Codec of the frames between two instances (a relay and its upstream) or between an instance and the native viewer, separate from the
+/// codec the viewers get. QOI spares the upstream the JPEG encodes, a pass over the pixels instead, at several times the bandwidth
+/// a gigabit LAN has to spare. The zstd deltas skip the image codecs entirely for the lowest encode latency.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkCodec {
+    /// the frames as the upstream encodes them for its viewers.
+    #[default]
+    Jpeg,
+    /// lossless QOI frames, when the upstream offers them.
+    Qoi,
+    /// the zstd-compressed BGRA deltas of `ZstdDeltaEncoder`, QOI when the upstream does not offer them.
+    Zstd,
+    /// QOI when the upstream is on the local network and offers it, JPEG otherwise.
+    Auto,
+}
+
+/// `/stream/capabilities` as far as the negotiation reads it, older instances have no `frame_codecs`.
+#[derive(Deserialize)]
+struct UpstreamCapabilities {
+    #[serde(default)]
+    frame_codecs: Vec<String>,
+}
+
+impl LinkCodec {
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkCodec::Jpeg => "jpeg",
+            LinkCodec::Qoi => "qoi",
+            LinkCodec::Zstd => "zstd",
+            LinkCodec::Auto => "auto",
+        }
+    }
+
+    /// # Negotiate
+    ///
+    /// The codec to request from the upstream of the stream url, `Jpeg`, `Qoi` or `Zstd`: a codec is only requested when the upstream lists it
+    /// in the `frame_codecs` of its `/stream/capabilities` (`Zstd` falls back to QOI), and `Auto` only requests QOI when every address
+    /// of its host is a private, link-local or loopback one. An upstream that cannot be asked gets JPEG.
+    ///
+    /// Note: `The speed of the link is not measured, a LAN address stands for the gigabit link.`
+    pub async fn negotiate(self, client: &reqwest::Client, stream: &reqwest::Url, token: Option<&str>) -> LinkCodec {
+        if self == LinkCodec::Jpeg {
+            return LinkCodec::Jpeg;
+        }
+
+        if self == LinkCodec::Auto && !is_local(stream).await {
+            debug!(%stream, "The upstream is not on the local network, streaming jpeg");
+            return LinkCodec::Jpeg;
+        }
+
+        let mut url = stream.clone();
+        url.set_path(&format!("{}/capabilities", url.path().trim_end_matches('/')));
+        url.set_query(None);
+
+        let mut request = client.get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let offered = match request.send().await.and_then(|response| response.error_for_status()) {
+            Ok(response) => response.json::<UpstreamCapabilities>().await.map(|capabilities| capabilities.frame_codecs).unwrap_or_default(),
+            Err(e) => {
+                debug!(error = %e, "Failed to read the capabilities of the upstream");
+                Vec::new()
+            }
+        };
+
+        let preferred: &[LinkCodec] = match self {
+            LinkCodec::Zstd => &[LinkCodec::Zstd, LinkCodec::Qoi],
+            _ => &[LinkCodec::Qoi],
+        };
+
+        match preferred.iter().find(|codec| offered.iter().any(|offered| offered.eq_ignore_ascii_case(codec.name()))) {
+            Some(codec) => {
+                info!(%stream, codec = codec.name(), "Streaming raw frames from the upstream");
+                *codec
+            }
+            None => {
+                info!(%stream, "The upstream does not offer {} frames, streaming jpeg", self.name());
+                LinkCodec::Jpeg
+            }
+        }
+    }
+
+    /// Whether the frames are decoded to BGRA, rather than the JPEGs of the upstream.
+    pub fn is_raw(self) -> bool {
+        matches!(self, LinkCodec::Qoi | LinkCodec::Zstd)
+    }
+}
+
+impl FromStr for LinkCodec {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "jpeg" => Ok(LinkCodec::Jpeg),
+            "qoi" => Ok(LinkCodec::Qoi),
+            "zstd" => Ok(LinkCodec::Zstd),
+            "auto" => Ok(LinkCodec::Auto),
+            _ => Err(format!("Unknown link codec {name}, expected jpeg, qoi, zstd or auto")),
+        }
+    }
+}
+
+/// Whether every address the host of the url resolves to is on the local network.
+async fn is_local(url: &reqwest::Url) -> bool {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return false;
+    };
+
+    //brackets of ipv6 literals
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(addresses) => {
+            let addresses: Vec<IpAddr> = addresses.map(|address| address.ip()).collect();
+
+            !addresses.is_empty() && addresses.iter().all(|address| is_local_address(*address))
+        }
+        Err(_) => false,
+    }
+}
+
+fn is_local_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_private() || address.is_link_local() || address.is_loopback(),
+        IpAddr::V6(address) => {
+            address.is_loopback()
+                || address.is_unique_local()
+                || address.is_unicast_link_local()
+                || address.to_ipv4_mapped().is_some_and(|address| is_local_address(IpAddr::V4(address)))
+        }
+    }
+}
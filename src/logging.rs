@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tracing_subscriber::{EnvFilter, fmt::writer::BoxMakeWriter};
+
+/// Log level used when `--log-level` is not provided.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+/// Lines kept by `RecentLogs`.
+const RECENT_LOG_LINES: usize = 500;
+
+/// # Log Format
+///
+/// Layout of the log lines, `--log-format`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    /// lines for a person reading the console.
+    #[default]
+    Text,
+    /// one json object per line (timestamp, level, target, fields and spans) for Loki, Elastic and the other log shippers.
+    Json,
+}
+
+impl LogFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format {name}, expected text or json")),
+        }
+    }
+}
+
+/// # Recent Logs
+///
+/// The last lines logged, kept in memory for the console dashboard instead of being written over it.
+#[derive(Clone, Default)]
+pub struct RecentLogs {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl RecentLogs {
+    /// The last `count` lines, the oldest first.
+    pub fn last(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+}
+
+impl std::io::Write for RecentLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut lines = self.lines.lock().unwrap();
+
+        for line in String::from_utf8_lossy(buf).lines().filter(|line| !line.trim().is_empty()) {
+            if lines.len() == RECENT_LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// # Init logging
+///
+/// Installs the global tracing subscriber writing to stderr, the level can be a single level (`debug`) or a filter (`share_screen=trace,info`).
+///
+/// With the `Json` format every event is written as a json object per line, along with the spans (capture, compressor, control_client...) it happened in.
+///
+/// With a `file` the logs are appended to it instead, for the service which has no console,
+/// with `recent` (and no file) they are only kept in memory for the console dashboard.
+pub fn init(level: &str, format: LogFormat, file: Option<&str>, recent: Option<RecentLogs>) {
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|e| {
+        eprintln!("Invalid log level '{level}' ({e}), using {DEFAULT_LOG_LEVEL}.");
+        EnvFilter::new(DEFAULT_LOG_LEVEL)
+    });
+
+    let file = file.and_then(|path| match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Failed to open the log file {path} ({e}), logging to stderr.");
+            None
+        }
+    });
+
+    let (writer, ansi) = match (file, recent) {
+        (Some(file), _) => (BoxMakeWriter::new(Mutex::new(file)), false),
+        (None, Some(recent)) => (BoxMakeWriter::new(move || recent.clone()), false),
+        (None, None) => (BoxMakeWriter::new(std::io::stderr), true),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(ansi)
+        .with_writer(writer);
+
+    if format == LogFormat::Json {
+        builder.json().with_current_span(true).with_span_list(true).init();
+    } else {
+        builder.init();
+    }
+}
+
+/// # Log Panics
+///
+/// Logs the panics of every thread as errors along with their backtrace, through the subscriber of `init` rather than stderr:
+/// they end up in the log file of the service, the json lines and the console dashboard like the other logs.
+///
+/// Note: `Replaces the panic hook of the process, an application embedding the server keeps its own by not calling it.`
+pub fn log_panics() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        let thread = std::thread::current();
+
+        tracing::error!(thread = thread.name().unwrap_or("unnamed"), panic = %info, %backtrace, "Panicked");
+    }));
+}
@@ -1,209 +1,288 @@
-pub mod captures;
-pub mod frame_compressor;
-pub mod streamed_resolution;
-
-use async_web::web::Resolution;
-use async_web::web::resolution::file_resolution::FileResolution;
-use std::sync::Arc;
-use tokio::sync::broadcast;
-
-use async_web::web::{App, resolution::json_resolution::JsonResolution};
-use win_video::i_capture::ICapture;
-
-use crate::captures::{CaptureType, SerializedDimensions};
-use crate::streamed_resolution::StreamedResolution;
-
-use crate::frame_compressor::compress_frame;
+mod cli;
+#[cfg(windows)]
+mod service;
+
+use std::{fmt, process::ExitCode, sync::Arc};
+
+use clap::Parser;
+use share_screen::{
+    ShareServer, auth,
+    bench::{BenchOptions, DEFAULT_BENCH_RESOLUTIONS, print_results, run_bench},
+    captures::{CaptureType, MonitorSelector},
+    config::{Config, DEFAULT_CONFIG_PATH},
+    devices::{list_devices, print_devices},
+    encryption,
+    logging::{self, LogFormat, RecentLogs},
+    platform, qr,
+    source::{SourceDevice, SourceRegistry},
+    test_pattern::{DEFAULT_PATTERN_FPS, DEFAULT_PATTERN_HEIGHT, DEFAULT_PATTERN_WIDTH},
+    tunnel::{TunnelServerOptions, run_tunnel_server},
+    user_settings::UserSettings,
+};
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use crate::cli::{Cli, Command};
+
+/// Exit code of a setting missing in `--headless` mode, `EX_CONFIG` of sysexits.
+const EXIT_MISSING_SETTING: u8 = 78;
+
+/// # Missing Setting
+///
+/// A setting that would have been asked for at the console prompt, an error in `--headless` mode.
+#[derive(Debug)]
+struct MissingSetting(&'static str);
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let capture_type = get_user_capture_type();
+impl fmt::Display for MissingSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is required with --headless, it cannot be asked for", self.0)
+    }
+}
 
-    println!("Initializing capture component now...");
+impl std::error::Error for MissingSetting {}
 
-    let capture = capture_type.activate()?;
+fn main() -> ExitCode {
+    match start() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
 
-    let dimensions = Arc::new(capture.get_dimensions()?);
+            match e.is::<MissingSetting>() {
+                true => ExitCode::from(EXIT_MISSING_SETTING),
+                false => ExitCode::FAILURE,
+            }
+        }
+    }
+}
 
-    //let (compressed_sender,  compressed_receiver) = mpsc::channel::<Vec<u8>>(buffer);
-    //let compressed_receiver_ref = Arc::new(Mutex::new(compressed_receiver));
+fn start() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
 
-    let (compressed_sender, _) = broadcast::channel::<Vec<u8>>(100);
+    //the dashboard shows the logs, they would be written over it
+    #[cfg(feature = "tui")]
+    let logs = cli.tui.then(RecentLogs::default);
+    #[cfg(not(feature = "tui"))]
+    let logs = None;
 
-    let compressed_sender_clone = Arc::new(compressed_sender);
+    let log_format = if cli.log_json { LogFormat::Json } else { cli.log_format };
+    logging::init(&cli.log_level, log_format, cli.log_file.as_deref(), logs.clone());
+    logging::log_panics();
 
-    //start receiving uncompressed data
-    spawn_frame_capture(capture.clone());
-    spawn_frame_compressor(capture.clone(), compressed_sender_clone.clone());
+    if let Some(password) = cli.hash_password {
+        println!("{}", auth::hash_password(&password)?);
+        return Ok(());
+    }
 
-    println!("Components initialized\nStarting web server...");
+    if cli.generate_key {
+        println!("{}", encryption::generate_key());
+        return Ok(());
+    }
 
-    let host_address = local_ip_address::local_ip()?;
-    let server_socket = format!("{host_address:?}:80");
+    if let Some(Command::List { json }) = cli.command {
+        let devices = list_devices()?;
 
-    //create the web app for sending data...
-    let mut app = App::bind(&server_socket).await?;
+        match json {
+            true => println!("{}", serde_json::to_string_pretty(&devices)?),
+            false => print_devices(&devices),
+        }
+        return Ok(());
+    }
 
-    route_app(
-        &mut app,
-        compressed_sender_clone.clone(),
-        dimensions.clone(),
-    )
-    .await;
+    if let Some(Command::Bench {
+        resolutions,
+        quality,
+        chroma,
+        seconds,
+    }) = cli.command
+    {
+        let options = BenchOptions {
+            resolutions: if resolutions.is_empty() {
+                DEFAULT_BENCH_RESOLUTIONS.to_vec()
+            } else {
+                resolutions
+            },
+            quality,
+            chroma,
+            duration: std::time::Duration::from_secs_f64(seconds.max(0.1)),
+        };
 
-    let _ = app.start();
+        println!("Benchmarking {} resolutions at quality {quality}...", options.resolutions.len());
+        print_results(&run_bench(&options)?);
+        return Ok(());
+    }
 
-    println!("Now hosting on http://{server_socket}");
+    if let Some(Command::Tunnel { listen, hosts, secret }) = cli.command {
+        let options = TunnelServerOptions { listen, hosts, secret };
 
-    loop {
-        let _ = prompt("Press enter to quit...");
-        break;
+        return Ok(tokio::runtime::Runtime::new()?.block_on(run_tunnel_server(options))?);
     }
 
-    let _ = app.close().await;
+    #[cfg(feature = "viewer")]
+    if let Some(Command::View {
+        url,
+        token,
+        insecure,
+        codec,
+    }) = cli.command
+    {
+        return share_screen::native_viewer::run_viewer(share_screen::native_viewer::ViewerOptions {
+            url,
+            token,
+            insecure,
+            codec,
+        });
+    }
 
-    Ok(())
-}
+    #[cfg(windows)]
+    if let Some(Command::Service { action }) = &cli.command {
+        let action = *action;
+        return service::handle(action, cli);
+    }
 
-/// # Route App
-///
-/// Adds routing to the web app, providing content from the content folder, changing the home page, and setting up the streamed resolutions.
-async fn route_app(
-    app: &mut App,
-    broad_tx: Arc<broadcast::Sender<Vec<u8>>>,
-    dimensions: Arc<win_video::devices::Dimensions>,
-) -> () {
-    //home page for serving the streamables
-    app.add_or_change_route("/", async_web::web::Method::GET, None, |_req, _res| async move {
-        FileResolution::new("content/stream.html").resolve()
-    })
-    .await
-    .expect("Failed to change home page.");
-
-    app.add_or_panic(
-        "/content/{file}",
-        async_web::web::Method::GET,
-        None,
-        |req, _res| async move {
-            let file = {
-                let req = req.lock().await;
-                let file: &String = req.variables.get("file").unwrap();
-
-                file.clone()
-            };
-
-            let path = format!("content/{file}");
-
-            FileResolution::new(&path).resolve()
-        },
-    )
-    .await;
-
-    let dimensions_clone = dimensions.clone();
-    app.add_or_change_route(
-        "/stream/dimensions",
-        async_web::web::Method::GET,
-        None,
-        move |_req, _res| {
-            let value = dimensions_clone.clone();
-            async move {
-                match JsonResolution::serialize(SerializedDimensions::from_dimensions(
-                    value.clone(),
-                )) {
-                    Ok(serialized) => serialized.resolve(),
-                    Err(err_r) => err_r.resolve(),
-                }
-            }
-        },
-    )
-    .await.expect("route not changed");
-
-    let broad_tx_clone = broad_tx.clone();
-    //streamed POST for the content of the device
-    app.add_or_change_route(
-        "/stream",
-        async_web::web::Method::POST,
-        None,
-        move |_req, _res| { 
-            
-            let broad_tx_clone = broad_tx_clone.clone();
-
-            async move {
-            let rx = broad_tx_clone.subscribe();
-
-            StreamedResolution::from_receiver(rx).resolve()
-        }},
-    )
-    .await.expect("route not changed");
-}
+    #[cfg(not(windows))]
+    if let Some(Command::Service { .. }) = &cli.command {
+        return Err("Services are only supported on Windows".into());
+    }
 
-/// # Spawn Frame Capture
-///
-/// Spawns a tokio task that starts and awaits the capture function of the device.
-fn spawn_frame_capture(capture: Arc<dyn ICapture<CaptureOutput = Vec<u8>>>) {
-    tokio::spawn(async move {
-        match capture.start_capturing().await {
-            Err(e) => eprintln!("{e}"),
-            _ => {}
-        };
-    });
+    tokio::runtime::Runtime::new()?.block_on(run(cli, None, logs))
 }
 
-/// # Spawn Compressor
+/// # Run
 ///
-/// Spawns a separate task that compresses incoming frames of the device and sends them to the broadcast channel
+/// Captures and serves until the user quits, or `stop` is notified when running as a service.
+/// A service cannot prompt, the device has to be set in `[capture]` of the config. Neither does `--headless`, the console is never read.
 ///
-/// Note: `This should be called with the spawn_frame_capture (does not matter the order)`
-fn spawn_frame_compressor(
-    capture: Arc<dyn ICapture<CaptureOutput = Vec<u8>>>,
-    compressed_frames: Arc<broadcast::Sender<Vec<u8>>>,
-) {
-    let rx = capture.clone_receiver();
-    let dimensions = capture.get_dimensions().expect("Could not get dimensions.");
-
-    tokio::spawn(async move {
-        loop {
-            let data = {
-                let mut guard = rx.lock().await;
-                guard.recv().await
-            };
-
-            if let None = data {
-                break; //done receiving data
-            }
+/// In a console the settings of the last run are the defaults of the prompts and of what the config leaves unset, see `UserSettings`.
+/// With the `logs` shown by the dashboard (`--tui`) the console is left to it once the server started.
+pub async fn run(cli: Cli, stop: Option<Arc<Notify>>, logs: Option<RecentLogs>) -> Result<(), Box<dyn std::error::Error>> {
+    let interactive = stop.is_none() && !cli.headless;
+    let logs = logs.filter(|_| interactive);
+    let mut config = Config::load(cli.config.as_deref())?;
+    let mut last = match interactive {
+        true => UserSettings::load(),
+        false => UserSettings::default(),
+    };
+
+    last.fill_config(&mut config);
+    last.listen = config.http.listen.clone();
+
+    if let Some(spec) = &cli.source {
+        config.capture.apply_source(spec)?;
+    }
+    if let Some(monitor) = &cli.monitor {
+        config.capture.monitor = MonitorSelector::parse(monitor);
+        config.capture.source.get_or_insert_with(|| "monitor".to_string());
+    }
+    config.capture.looped |= cli.looped;
 
-            let raw_data = data.unwrap();
+    let source: Arc<dyn SourceDevice> = match SourceRegistry::default().create(&config.capture)? {
+        Some(source) => source,
+        None if interactive => {
+            let capture = get_user_capture_type(last.capture.map(CaptureType::from));
+            last.capture = Some(capture.into());
 
-            let (width, height) = (dimensions.width, dimensions.height);
+            Arc::new(capture)
+        }
+        None if cli.headless => return Err(MissingSetting("[capture] source in the config or --source").into()),
+        None => return Err("Set [capture] source in the config, the service cannot ask for the device".into()),
+    };
+
+    let mut builder = ShareServer::builder(source)
+        .config(config)
+        .replay(std::time::Duration::from_secs(cli.replay_seconds))
+        .listen(!cli.timelapse_only)
+        .upnp(cli.upnp)
+        .preset(cli.preset)
+        .reload_config(cli.config.clone().unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string()));
+
+    if let Some(path) = cli.record {
+        builder = builder.record(path);
+    }
 
-            let compressed =
-                tokio::task::spawn_blocking(move || compress_frame(raw_data, width, height))
-                    .await
-                    .unwrap_or_default();
+    if let Some(path) = cli.timelapse {
+        builder = builder.timelapse(path, std::time::Duration::from_secs(cli.timelapse_interval.max(1)));
+    }
 
-            if !compressed.is_empty() {
-                let len = compressed.len() as u32;
+    let server = builder.start().await?;
+    let state = server.state().clone();
 
-                // Create a single packet: [4 bytes length] + [JPEG bytes]
-                let mut packet = Vec::with_capacity(4 + compressed.len());
-                packet.extend_from_slice(&len.to_le_bytes()); // Little Endian length
-                packet.extend_from_slice(&compressed);
+    if let Some(viewer_url) = server.viewer_url() {
+        if interactive && !cli.no_qr && logs.is_none() {
+            qr::print_qr_code(viewer_url);
+        }
 
-                //send the compressed data
-                let _ = compressed_frames.send(packet);
-            }
+        #[cfg(all(windows, feature = "tray"))]
+        if cli.tray {
+            share_screen::tray::spawn_tray(viewer_url.to_string(), state.clone());
         }
+    }
+
+    //the prompt blocks its thread, a blocking tokio task would hold the runtime open when quitting with Ctrl+C.
+    let (quit_sender, quit) = tokio::sync::oneshot::channel();
+    if interactive && logs.is_none() {
+        std::thread::spawn(move || {
+            let _ = prompt("Press enter to quit (or Ctrl+C)...");
+            let _ = quit_sender.send(());
+        });
+    }
+
+    #[cfg(feature = "tui")]
+    let dashboard = logs.and_then(|logs| {
+        share_screen::tui::spawn_dashboard(state.clone(), logs, server.viewer_url().map(str::to_string))
     });
+
+    let stopped = async {
+        match &stop {
+            Some(stop) => stop.notified().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        Ok(()) = quit => {}
+        _ = stopped => info!("Stop requested by the service control manager, shutting down..."),
+        _ = tokio::signal::ctrl_c() => info!("Ctrl+C received, shutting down..."),
+        _ = state.quit_requested() => info!("Shutdown requested, shutting down..."),
+    }
+
+    if interactive {
+        last.remember_stream(&state);
+
+        if let Err(e) = last.save() {
+            error!(error = %e, "Failed to save the settings of this run");
+        }
+    }
+
+    let summary = server.shutdown().await;
+
+    //the console is given back once the dashboard saw the shutdown
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        let _ = dashboard.join();
+    }
+
+    summary.print();
+
+    match summary.persist() {
+        Ok(path) => info!(%path, "Session summary saved"),
+        Err(e) => error!(error = %e, "Failed to save session summary"),
+    }
+
+    Ok(())
 }
 
 /// # get user capture type
 ///
-/// Retrieves the user's preferred capture type.
-fn get_user_capture_type() -> CaptureType {
+/// Retrieves the user's preferred capture type, an empty answer picks the capture of the last run if there was one.
+fn get_user_capture_type(last: Option<CaptureType>) -> CaptureType {
     let mut capture: Option<CaptureType> = None;
+    let question = match last {
+        Some(last) => format!("Choose capture type (enter for {last}): \r\n   - (1) Camera\r\n   - (2) Monitor\r\n   - (3) Test pattern"),
+        None => "Choose capture type: \r\n   - (1) Camera\r\n   - (2) Monitor\r\n   - (3) Test pattern".to_string(),
+    };
 
     while let None = capture {
-        let answer = prompt("Choose capture type: \r\n   - (1) Camera\r\n   - (2) Monitor");
+        let answer = prompt(&question);
 
         if let Err(e) = answer {
             println!("Invalid input: {e}");
@@ -212,6 +291,12 @@ fn get_user_capture_type() -> CaptureType {
 
         let answer = answer.unwrap().trim().to_lowercase();
 
+        if answer.is_empty()
+            && let Some(last) = last
+        {
+            return last;
+        }
+
         if answer.is_empty() || answer.len() <= 0 || answer.len() > 1 {
             println!("Invalid input! Please follow the prompt\n");
             continue;
@@ -228,10 +313,18 @@ fn get_user_capture_type() -> CaptureType {
 
         match answer {
             '1' => {
-                capture = Some(CaptureType::Camera);
+                capture = Some(CaptureType::Camera(0));
             }
             '2' => {
-                capture = Some(CaptureType::Monitor(user_request_monitor_index()));
+                let last_monitor = last.and_then(|last| last.monitor());
+                capture = Some(CaptureType::Monitor(user_request_monitor_index(last_monitor)));
+            }
+            '3' => {
+                capture = Some(CaptureType::TestPattern {
+                    width: DEFAULT_PATTERN_WIDTH,
+                    height: DEFAULT_PATTERN_HEIGHT,
+                    fps: DEFAULT_PATTERN_FPS,
+                });
             }
             _ => {
                 println!("Invalid choice, please choose again from the following\n");
@@ -245,21 +338,24 @@ fn get_user_capture_type() -> CaptureType {
 
 /// # User Request Monitor index
 ///
-/// Retrieves the user's preferred monitor index. This is called within the `get_user_capture_type` function if the answer proceeds with Monitor
-fn user_request_monitor_index() -> i32 {
+/// Retrieves the user's preferred monitor index. This is called within the `get_user_capture_type` function if the answer proceeds with Monitor,
+/// an empty answer picks the monitor of the last run if there was one.
+fn user_request_monitor_index(last: Option<i32>) -> i32 {
     let mut monitor_index = None;
 
     while let None = monitor_index {
-        let m_count = unsafe { win_video::devices::get_monitor_count() };
+        let m_count = platform::monitor_count();
+        let question = match last {
+            Some(last) => format!("Choose a monitor to share (from 1 to {}, enter for {}): ", m_count, last + 1),
+            None => format!("Choose a monitor to share (from 1 to {}): ", m_count),
+        };
 
-        let monitor = match prompt(&format!(
-            "Choose a monitor to share (from 1 to {}): ",
-            m_count
-        )) {
+        let monitor = match prompt(&question) {
             Err(m_e) => {
                 println!("Failed to choose monitor: {m_e}");
                 continue;
             }
+            Ok(m_choice) if m_choice.trim().is_empty() && last.is_some() => Ok(last.unwrap() + 1),
             Ok(m_choice) => m_choice.trim().to_lowercase().parse::<i32>(),
         };
 
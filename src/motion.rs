@@ -0,0 +1,176 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    events::ServerEvent,
+    recorder::RECORDINGS_FOLDER,
+    session::unix_now,
+    state::{RawFrame, StreamState},
+};
+
+/// Width and height of the grid frames are sampled at before comparing.
+const GRID_WIDTH: u32 = 64;
+const GRID_HEIGHT: u32 = 36;
+
+/// How often the latest frame is checked for motion.
+const MOTION_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// `[motion]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MotionConfig {
+    pub enabled: bool,
+    /// fraction of the frame (0.0 - 1.0) that has to change to count as motion.
+    pub threshold: f32,
+    /// brightness difference (0 - 255) for a sampled pixel to count as changed.
+    pub pixel_threshold: u8,
+    /// start a recording when motion is detected.
+    pub record: bool,
+    /// seconds the recording keeps going after the last motion.
+    pub record_seconds: u64,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 0.02,
+            pixel_threshold: 25,
+            record: true,
+            record_seconds: 10,
+        }
+    }
+}
+
+/// # Motion Detector
+///
+/// Compares consecutive frames sampled on a small grayscale grid.
+pub struct MotionDetector {
+    pixel_threshold: u8,
+    previous: Option<Vec<u8>>,
+}
+
+impl MotionDetector {
+    pub fn new(pixel_threshold: u8) -> Self {
+        Self {
+            pixel_threshold,
+            previous: None,
+        }
+    }
+
+    /// Fraction of the frame that changed since the previous frame, 0 for the first frame.
+    pub fn score(&mut self, frame: &RawFrame) -> f32 {
+        let grid = sample_luma(frame);
+
+        let score = match &self.previous {
+            Some(previous) if previous.len() == grid.len() => {
+                let changed = previous
+                    .iter()
+                    .zip(&grid)
+                    .filter(|(a, b)| a.abs_diff(**b) > self.pixel_threshold)
+                    .count();
+
+                changed as f32 / grid.len() as f32
+            }
+            _ => 0.0,
+        };
+
+        self.previous = Some(grid);
+        score
+    }
+}
+
+/// Samples the BGRA frame on the grid as luma values.
+fn sample_luma(frame: &RawFrame) -> Vec<u8> {
+    let mut grid = Vec::with_capacity((GRID_WIDTH * GRID_HEIGHT) as usize);
+
+    if frame.width == 0 || frame.height == 0 {
+        return grid;
+    }
+
+    for gy in 0..GRID_HEIGHT {
+        let y = (gy * frame.height / GRID_HEIGHT).min(frame.height - 1);
+
+        for gx in 0..GRID_WIDTH {
+            let x = (gx * frame.width / GRID_WIDTH).min(frame.width - 1);
+            let i = ((y * frame.width + x) * 4) as usize;
+
+            let Some(bgra) = frame.data.get(i..i + 4) else {
+                continue;
+            };
+
+            // Rec. 601 luma
+            let luma = (bgra[2] as u32 * 299 + bgra[1] as u32 * 587 + bgra[0] as u32 * 114) / 1000;
+            grid.push(luma as u8);
+        }
+    }
+
+    grid
+}
+
+/// # Spawn Motion Detector
+///
+/// Spawns a task checking the latest raw frame for motion, optionally recording while motion is detected.
+pub fn spawn_motion_detector(config: MotionConfig, state: Arc<StreamState>) {
+    if !config.enabled {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut detector = MotionDetector::new(config.pixel_threshold);
+        let mut last_frame: Option<Arc<RawFrame>> = None;
+        let mut last_motion: Option<Instant> = None;
+        let mut recording_path: Option<String> = None;
+
+        let record_for = Duration::from_secs(config.record_seconds);
+
+        loop {
+            tokio::time::sleep(MOTION_CHECK_INTERVAL).await;
+
+            let Some(frame) = state.latest_raw() else {
+                continue;
+            };
+
+            if last_frame.as_ref().is_some_and(|l| Arc::ptr_eq(l, &frame)) {
+                continue;
+            }
+            last_frame = Some(frame.clone());
+
+            let score = detector.score(&frame);
+
+            if score >= config.threshold {
+                if last_motion.is_none_or(|l| l.elapsed() > record_for) {
+                    tracing::info!(changed_percent = score * 100.0, "Motion detected");
+                    state.emit(ServerEvent::MotionDetected { score });
+                }
+
+                last_motion = Some(Instant::now());
+
+                //a recording started by someone else already covers the motion
+                if config.record && recording_path.is_none() && state.recorder.status().await.path.is_none() {
+                    let path = format!("{RECORDINGS_FOLDER}/motion-{}.{}", unix_now(), state.recorder.format().extension());
+
+                    match state.recorder.start(path.clone(), state.clone()).await {
+                        Ok(_) => recording_path = Some(path),
+                        Err(e) => tracing::error!(error = %e, "Failed to start motion recording"),
+                    }
+                }
+            }
+
+            let motion_ended = last_motion.is_some_and(|l| l.elapsed() > record_for);
+
+            if motion_ended && recording_path.is_some() {
+                //only stop the recording this task started
+                if state.recorder.status().await.path == recording_path {
+                    state.recorder.stop().await;
+                }
+
+                recording_path = None;
+            }
+        }
+    });
+}
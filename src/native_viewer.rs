@@ -0,0 +1,328 @@
+use std::{num::NonZeroU32, rc::Rc, time::Duration};
+
+use futures::StreamExt;
+use tracing::{error, info, warn};
+use winit::{
+    application::ApplicationHandler,
+    dpi::LogicalSize,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy},
+    window::{Window, WindowId},
+};
+
+use crate::{
+    delta_frames::DeltaDecoder,
+    frame_compressor::decode_qoi,
+    link_codec::LinkCodec,
+    packets::{FLAG_ENCRYPTED, PROTOCOL_VERSION, PacketType, packet_len, parse},
+};
+
+/// Time waited before connecting again after the stream ended.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+/// Size of the window until the first frame arrives.
+const INITIAL_SIZE: LogicalSize<u32> = LogicalSize::new(1280, 720);
+
+/// Options of `share-screen view`.
+pub struct ViewerOptions {
+    /// url of the server (`http://host:5074`) or of its stream.
+    pub url: String,
+    /// sent as `Authorization: Bearer`, one of the `[auth] tokens` of the server.
+    pub token: Option<String>,
+    /// accept the self-signed certificate of a server using `[tls]` without a certificate.
+    pub insecure: bool,
+    /// frames requested from the server, negotiated again on every connection.
+    pub codec: LinkCodec,
+}
+
+/// A decoded frame, `0RGB` pixels as softbuffer presents them.
+struct Frame {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+}
+
+enum ViewerEvent {
+    Frame(Frame),
+    /// the stream cannot be watched (wrong url, token, encrypted...), the window closes with the reason.
+    Failed(String),
+}
+
+/// # Run Viewer
+///
+/// Opens a native window showing the packet stream of a server, without the browser and the buffering of the viewer page:
+/// every frame is drawn as soon as it is decoded. Reconnects while the server restarts.
+///
+/// Note: `Blocks until the window is closed, has to be called on the main thread (winit).`
+pub fn run_viewer(options: ViewerOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let stream_url = stream_url(&options.url)?;
+
+    let event_loop = EventLoop::<ViewerEvent>::with_user_event().build()?;
+    let proxy = event_loop.create_proxy();
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(options.insecure)
+        .build()?;
+    let (token, codec) = (options.token, options.codec);
+
+    std::thread::Builder::new().name("viewer-stream".to_string()).spawn(move || {
+        match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime.block_on(receive(client, stream_url, token, codec, proxy)),
+            Err(e) => {
+                let _ = proxy.send_event(ViewerEvent::Failed(e.to_string()));
+            }
+        }
+    })?;
+
+    let mut viewer = Viewer {
+        title: format!("share-screen - {}", options.url),
+        window: None,
+        surface: None,
+        frame: None,
+    };
+    event_loop.run_app(&mut viewer)?;
+
+    Ok(())
+}
+
+/// The `/stream` url of the server url, requesting the protocol version of this build.
+fn stream_url(url: &str) -> Result<reqwest::Url, Box<dyn std::error::Error>> {
+    let mut url = reqwest::Url::parse(url)?;
+
+    if url.path().is_empty() || url.path() == "/" {
+        url.set_path("/stream");
+    }
+
+    url.query_pairs_mut().append_pair("version", &PROTOCOL_VERSION.to_string());
+
+    Ok(url)
+}
+
+/// Reads the stream and sends its decoded frames to the window, until the window is closed.
+async fn receive(
+    client: reqwest::Client,
+    stream_url: reqwest::Url,
+    token: Option<String>,
+    codec: LinkCodec,
+    proxy: EventLoopProxy<ViewerEvent>,
+) {
+    loop {
+        let negotiated = codec.negotiate(&client, &stream_url, token.as_deref()).await;
+
+        let mut url = stream_url.clone();
+        if negotiated.is_raw() {
+            url.query_pairs_mut().append_pair("codec", negotiated.name());
+        }
+
+        let mut request = client.get(url.clone());
+        if let Some(token) = &token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = match request.send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) if response.status().is_client_error() => {
+                let reason = format!("{} answered {}", url, response.status());
+                let _ = proxy.send_event(ViewerEvent::Failed(reason));
+                return;
+            }
+            Ok(response) => {
+                warn!(status = %response.status(), "The stream is not available, retrying");
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                continue;
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to connect, retrying");
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                continue;
+            }
+        };
+
+        info!(%url, codec = negotiated.name(), "Connected");
+
+        let mut body = response.bytes_stream();
+        let mut buffer = Vec::new();
+        let mut deltas = DeltaDecoder::default();
+
+        while let Some(chunk) = body.next().await {
+            let Ok(chunk) = chunk else {
+                break;
+            };
+            buffer.extend_from_slice(&chunk);
+
+            while let Some(length) = packet_len(&buffer) {
+                let Some((header, payload)) = parse(&buffer[..length]) else {
+                    let _ = proxy.send_event(ViewerEvent::Failed("the server sent a malformed packet".to_string()));
+                    return;
+                };
+
+                if header.flags & FLAG_ENCRYPTED != 0 {
+                    let reason = "the stream is encrypted, the native viewer cannot decrypt it".to_string();
+                    let _ = proxy.send_event(ViewerEvent::Failed(reason));
+                    return;
+                }
+
+                if header.kind == PacketType::Frame {
+                    let frame = match negotiated {
+                        LinkCodec::Qoi => decode_qoi(payload).map(|(width, height, raw_bgra)| bgra_frame(width, height, &raw_bgra)),
+                        LinkCodec::Zstd => deltas.decode(payload).map(|(width, height, raw_bgra)| bgra_frame(width, height, &raw_bgra)),
+                        _ => decode(payload),
+                    };
+
+                    match frame {
+                        Some(frame) => {
+                            //the window was closed
+                            if proxy.send_event(ViewerEvent::Frame(frame)).is_err() {
+                                return;
+                            }
+                        }
+                        //a delta of a frame that was skipped, the frames are back with the next keyframe
+                        None if negotiated == LinkCodec::Zstd => {}
+                        None => warn!("Failed to decode a frame"),
+                    }
+                }
+
+                buffer.drain(..length);
+            }
+        }
+
+        warn!("The stream ended, reconnecting");
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
+
+fn decode(jpeg: &[u8]) -> Option<Frame> {
+    let image = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg).ok()?.to_rgb8();
+
+    Some(Frame {
+        width: image.width(),
+        height: image.height(),
+        pixels: image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0;
+                (r as u32) << 16 | (g as u32) << 8 | b as u32
+            })
+            .collect(),
+    })
+}
+
+/// The frame of the pixels of a QOI or zstd frame.
+fn bgra_frame(width: u32, height: u32, raw_bgra: &[u8]) -> Frame {
+    Frame {
+        width,
+        height,
+        pixels: raw_bgra
+            .chunks_exact(4)
+            .map(|bgra| (bgra[2] as u32) << 16 | (bgra[1] as u32) << 8 | bgra[0] as u32)
+            .collect(),
+    }
+}
+
+struct Viewer {
+    title: String,
+    window: Option<Rc<Window>>,
+    surface: Option<softbuffer::Surface<Rc<Window>, Rc<Window>>>,
+    frame: Option<Frame>,
+}
+
+impl Viewer {
+    /// Draws the latest frame scaled to fit the window, black bars around it.
+    fn redraw(&mut self) -> Result<(), softbuffer::SoftBufferError> {
+        let (Some(window), Some(surface)) = (&self.window, &mut self.surface) else {
+            return Ok(());
+        };
+
+        let size = window.inner_size();
+        let (Some(width), Some(height)) = (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) else {
+            return Ok(());
+        };
+
+        surface.resize(width, height)?;
+        let mut buffer = surface.buffer_mut()?;
+        buffer.fill(0);
+
+        if let Some(frame) = &self.frame {
+            let (window_width, window_height) = (size.width as f32, size.height as f32);
+            let scale = (window_width / frame.width as f32).min(window_height / frame.height as f32);
+            let fitted_width = ((frame.width as f32 * scale) as u32).clamp(1, size.width);
+            let fitted_height = ((frame.height as f32 * scale) as u32).clamp(1, size.height);
+            let left = (size.width - fitted_width) / 2;
+            let top = (size.height - fitted_height) / 2;
+
+            //nearest neighbour, cheap enough to keep up with the stream
+            for y in 0..fitted_height {
+                let source_y = (y as u64 * frame.height as u64 / fitted_height as u64) as usize;
+                let source_row = &frame.pixels[source_y * frame.width as usize..][..frame.width as usize];
+                let target = ((top + y) * size.width + left) as usize;
+
+                for (x, pixel) in buffer[target..target + fitted_width as usize].iter_mut().enumerate() {
+                    *pixel = source_row[x * frame.width as usize / fitted_width as usize];
+                }
+            }
+        }
+
+        buffer.present()
+    }
+}
+
+impl ApplicationHandler<ViewerEvent> for Viewer {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+
+        let attributes = Window::default_attributes().with_title(&self.title).with_inner_size(INITIAL_SIZE);
+
+        let created = event_loop.create_window(attributes).map_err(|e| e.to_string()).and_then(|window| {
+            let window = Rc::new(window);
+            let context = softbuffer::Context::new(window.clone()).map_err(|e| e.to_string())?;
+            let surface = softbuffer::Surface::new(&context, window.clone()).map_err(|e| e.to_string())?;
+
+            Ok((window, surface))
+        });
+
+        match created {
+            Ok((window, surface)) => {
+                self.window = Some(window);
+                self.surface = Some(surface);
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to open the viewer window");
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: ViewerEvent) {
+        match event {
+            ViewerEvent::Frame(frame) => {
+                self.frame = Some(frame);
+
+                if let Some(window) = &self.window {
+                    window.request_redraw();
+                }
+            }
+            ViewerEvent::Failed(reason) => {
+                error!(%reason, "Cannot watch the stream");
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::RedrawRequested => {
+                if let Err(e) = self.redraw() {
+                    error!(error = %e, "Failed to draw the frame");
+                }
+            }
+            WindowEvent::Resized(_) => {
+                if let Some(window) = &self.window {
+                    window.request_redraw();
+                }
+            }
+            _ => {}
+        }
+    }
+}
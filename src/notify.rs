@@ -0,0 +1,281 @@
+use std::{io::IsTerminal, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error};
+
+use crate::{
+    events::{Event, ServerEvent},
+    state::StreamState,
+};
+
+/// Time the viewers joining after the first one are gathered into the same notification, a page reloaded by several viewers is one notification.
+const GATHER_INTERVAL: Duration = Duration::from_secs(1);
+/// Title of the notifications.
+const TITLE: &str = "share-screen";
+
+/// `[notify]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// notifies the host when a viewer starts watching, with its ip and the token it watches with.
+    pub viewer_joined: bool,
+    /// rings the bell of the console along with the notification, when the server runs in one.
+    pub bell: bool,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            viewer_joined: true,
+            bell: true,
+        }
+    }
+}
+
+/// # Spawn Join Notifications
+///
+/// Shows a desktop notification when a viewer starts watching, so the host knows the moment the screen is seen:
+/// a notification of the tray on windows (a toast on windows 10 and later), `notify-send` on linux and `osascript` on macOS.
+///
+/// Note: `A host without a desktop (a service, a headless machine) cannot show them, the failure is only logged at the debug level.`
+pub fn spawn_join_notifications(config: NotifyConfig, state: Arc<StreamState>) {
+    if !config.viewer_joined {
+        return;
+    }
+
+    let bell = config.bell && std::io::stderr().is_terminal();
+    let (notifications, received) = std::sync::mpsc::channel::<String>();
+
+    //the notification icon of windows belongs to a window, it lives on this thread
+    let spawned = std::thread::Builder::new().name("notifications".to_string()).spawn(move || {
+        let mut notifier = Notifier::default();
+
+        for text in received {
+            if bell {
+                eprint!("\x07");
+            }
+
+            if let Err(e) = notifier.show(&text) {
+                debug!(error = %e, "Failed to show the notification");
+            }
+        }
+    });
+
+    if let Err(e) = spawned {
+        error!(error = %e, "Failed to spawn the notification thread");
+        return;
+    }
+
+    let mut rx = state.events.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                event = rx.recv() => event,
+                _ = state.shutting_down() => break,
+            };
+
+            let first = match event {
+                Ok(event) => match joined(&event) {
+                    Some(viewer) => viewer,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            let mut viewers = vec![first];
+            let gathering = tokio::time::sleep(GATHER_INTERVAL);
+            tokio::pin!(gathering);
+
+            loop {
+                tokio::select! {
+                    _ = &mut gathering => break,
+                    event = rx.recv() => match event {
+                        Ok(event) => viewers.extend(joined(&event)),
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
+                }
+            }
+
+            let text = match viewers.as_slice() {
+                [viewer] => format!("{viewer} started watching your screen"),
+                viewers => format!("{} viewers started watching your screen: {}", viewers.len(), viewers.join(", ")),
+            };
+
+            if notifications.send(text).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// The viewer of a `viewer_connected` event as the notification shows it, its ip and the identity of its token.
+fn joined(event: &Event) -> Option<String> {
+    match &event.kind {
+        ServerEvent::ViewerConnected { ip, identity, .. } => Some(match identity {
+            Some(identity) => format!("{ip} ({identity})"),
+            None => ip.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// # Notifier
+///
+/// Shows the notifications of the platform, on windows through a notification icon added with the first one and removed on drop.
+#[derive(Default)]
+struct Notifier {
+    #[cfg(windows)]
+    icon: Option<windows_notifier::NotificationIcon>,
+}
+
+impl Notifier {
+    #[cfg(windows)]
+    fn show(&mut self, text: &str) -> std::io::Result<()> {
+        let icon = match &mut self.icon {
+            Some(icon) => icon,
+            None => self.icon.insert(windows_notifier::NotificationIcon::add()?),
+        };
+
+        icon.notify(TITLE, text)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn show(&mut self, text: &str) -> std::io::Result<()> {
+        run(std::process::Command::new("notify-send").args(["--app-name", TITLE, TITLE, text]))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn show(&mut self, text: &str) -> std::io::Result<()> {
+        //the text is passed as an argument of the script rather than quoted into it
+        run(std::process::Command::new("osascript").args([
+            "-e",
+            "on run argv",
+            "-e",
+            &format!("display notification (item 1 of argv) with title \"{TITLE}\""),
+            "-e",
+            "end run",
+            text,
+        ]))
+    }
+
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    fn show(&mut self, _text: &str) -> std::io::Result<()> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "notifications are not supported on this platform"))
+    }
+}
+
+/// Runs the notification command, an error when it is missing or fails.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run(command: &mut std::process::Command) -> std::io::Result<()> {
+    let status = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other(format!("the notification command exited with {status}"))),
+    }
+}
+
+#[cfg(windows)]
+mod windows_notifier {
+    use windows::{
+        Win32::{
+            Foundation::HWND,
+            UI::{
+                Shell::{
+                    NIF_ICON, NIF_INFO, NIF_TIP, NIIF_INFO, NIM_ADD, NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+                    Shell_NotifyIconW,
+                },
+                WindowsAndMessaging::{CreateWindowExW, DestroyWindow, HWND_MESSAGE, IDI_INFORMATION, LoadIconW, WINDOW_EX_STYLE, WINDOW_STYLE},
+            },
+        },
+        core::w,
+    };
+
+    /// Id of the icon among the icons of its window.
+    const ICON_ID: u32 = 1;
+
+    /// A notification icon of a message-only window, the notifications are the balloons of the icon.
+    pub struct NotificationIcon {
+        window: HWND,
+    }
+
+    impl NotificationIcon {
+        pub fn add() -> std::io::Result<Self> {
+            let window = unsafe {
+                CreateWindowExW(
+                    WINDOW_EX_STYLE::default(),
+                    w!("STATIC"),
+                    w!("share-screen notifications"),
+                    WINDOW_STYLE::default(),
+                    0,
+                    0,
+                    0,
+                    0,
+                    Some(HWND_MESSAGE),
+                    None,
+                    None,
+                    None,
+                )
+            }
+            .map_err(std::io::Error::other)?;
+
+            let mut data = data(window);
+            data.uFlags = NIF_ICON | NIF_TIP;
+            data.hIcon = unsafe { LoadIconW(None, IDI_INFORMATION) }.unwrap_or_default();
+            copy_wide(&mut data.szTip, super::TITLE);
+
+            if !unsafe { Shell_NotifyIconW(NIM_ADD, &data) }.as_bool() {
+                let _ = unsafe { DestroyWindow(window) };
+                return Err(std::io::Error::other("the notification icon could not be added"));
+            }
+
+            Ok(Self { window })
+        }
+
+        pub fn notify(&mut self, title: &str, text: &str) -> std::io::Result<()> {
+            let mut data = data(self.window);
+            data.uFlags = NIF_INFO;
+            data.dwInfoFlags = NIIF_INFO;
+            copy_wide(&mut data.szInfoTitle, title);
+            copy_wide(&mut data.szInfo, text);
+
+            match unsafe { Shell_NotifyIconW(NIM_MODIFY, &data) }.as_bool() {
+                true => Ok(()),
+                false => Err(std::io::Error::other("the notification could not be shown")),
+            }
+        }
+    }
+
+    impl Drop for NotificationIcon {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = Shell_NotifyIconW(NIM_DELETE, &data(self.window));
+                let _ = DestroyWindow(self.window);
+            }
+        }
+    }
+
+    fn data(window: HWND) -> NOTIFYICONDATAW {
+        NOTIFYICONDATAW {
+            cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+            hWnd: window,
+            uID: ICON_ID,
+            ..Default::default()
+        }
+    }
+
+    /// Copies the text into a fixed size field, truncated to keep the terminating nul.
+    fn copy_wide(field: &mut [u16], text: &str) {
+        for (slot, c) in field.iter_mut().take(field.len() - 1).zip(text.encode_utf16().chain(std::iter::repeat(0))) {
+            *slot = c;
+        }
+    }
+}
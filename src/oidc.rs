@@ -0,0 +1,481 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    extract::{Query, State},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, jwk::JwkSet};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::{constant_time_eq, hex},
+    bytes_resolution::BytesResolution,
+    request_params::cookie,
+    session::unix_now,
+    state::StreamState,
+};
+
+/// Cookie binding a login to the browser that started it, holds the `state` sent to the provider.
+const STATE_COOKIE: &str = "share_oidc";
+/// Seconds a viewer has to log in at the provider.
+const LOGIN_SECONDS: u64 = 600;
+/// Logins pending at once, the oldest one is dropped for a new one over this so repeated `/login/oidc` requests cannot fill the memory.
+const MAX_PENDING_LOGINS: usize = 1024;
+/// How long the discovery document and the keys of the provider are cached, the keys are fetched again sooner for an unknown key id.
+const PROVIDER_TTL: Duration = Duration::from_secs(3600);
+/// Timeout of the requests to the provider.
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `[auth.oidc]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct OidcConfig {
+    /// url of the identity provider, its `/.well-known/openid-configuration` is read. OpenID Connect login is on once
+    /// `issuer`, `client_id` and `redirect_url` are set.
+    pub issuer: Option<String>,
+    /// client id registered at the provider, the audience of the ID tokens.
+    pub client_id: Option<String>,
+    /// client secret of a confidential client, none for a public client (PKCE only).
+    pub client_secret: Option<String>,
+    /// `/login/oidc/callback` of this server as registered at the provider, like `https://share.example.com/login/oidc/callback`.
+    pub redirect_url: Option<String>,
+    /// scopes asked for, `openid` is always sent.
+    pub scopes: Vec<String>,
+    /// email domains allowed to log in, every account of the provider when empty.
+    pub allowed_domains: Vec<String>,
+    /// name of the provider on the button of the password form.
+    pub name: String,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            issuer: None,
+            client_id: None,
+            client_secret: None,
+            redirect_url: None,
+            scopes: vec!["openid".to_string(), "email".to_string(), "profile".to_string()],
+            allowed_domains: Vec::new(),
+            name: "single sign-on".to_string(),
+        }
+    }
+}
+
+impl OidcConfig {
+    /// Whether viewers can log in with the provider.
+    pub fn enabled(&self) -> bool {
+        self.issuer.is_some() && self.client_id.is_some() && self.redirect_url.is_some()
+    }
+
+    fn scope(&self) -> String {
+        let mut scopes = vec!["openid"];
+        scopes.extend(self.scopes.iter().map(String::as_str).filter(|scope| *scope != "openid"));
+
+        scopes.join(" ")
+    }
+
+    /// Whether the email is in one of the `allowed_domains`, every email is when none are set.
+    fn allows(&self, email: Option<&str>) -> bool {
+        if self.allowed_domains.is_empty() {
+            return true;
+        }
+
+        let Some((_, domain)) = email.and_then(|email| email.rsplit_once('@')) else {
+            return false;
+        };
+
+        self.allowed_domains.iter().any(|allowed| allowed.trim_start_matches('@').eq_ignore_ascii_case(domain))
+    }
+}
+
+/// The parts of `/.well-known/openid-configuration` the login uses.
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// The discovery document and the signing keys of the provider.
+struct Provider {
+    /// the `issuer` of the config it was fetched for.
+    configured: String,
+    discovery: Discovery,
+    keys: JwkSet,
+    fetched: Instant,
+}
+
+/// A login sent to the provider, by its `state`.
+struct PendingLogin {
+    nonce: String,
+    /// PKCE code verifier, its sha256 was sent as the challenge.
+    verifier: String,
+    expires: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims of the ID token the login reads, the expiry, the issuer and the audience are checked by `jsonwebtoken`.
+#[derive(Deserialize)]
+struct IdClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    preferred_username: Option<String>,
+}
+
+/// # Oidc Logins
+///
+/// The logins through the OpenID Connect provider of `[auth.oidc]`: the authorization code flow with PKCE, the ID token
+/// is checked against the keys of the provider and its nonce before a session cookie is issued.
+///
+/// Note: `The discovery document and the keys are cached for an hour, a token signed with a key id that is not cached
+/// fetches the keys again (the provider rotated them).`
+pub struct OidcLogins {
+    client: reqwest::Client,
+    provider: tokio::sync::Mutex<Option<Arc<Provider>>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl Default for OidcLogins {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder().timeout(PROVIDER_TIMEOUT).build().unwrap_or_default(),
+            provider: tokio::sync::Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl OidcLogins {
+    /// The provider of the issuer, fetched when it is not cached, is stale or `refresh` is set.
+    async fn provider(&self, issuer: &str, refresh: bool) -> Result<Arc<Provider>, String> {
+        let mut cached = self.provider.lock().await;
+
+        if let Some(provider) = cached.as_ref()
+            && !refresh
+            && provider.configured == issuer
+            && provider.fetched.elapsed() < PROVIDER_TTL
+        {
+            return Ok(provider.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+        let discovery: Discovery = self.get_json(&url).await?;
+
+        if discovery.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(format!("the provider calls itself {}, not {issuer}", discovery.issuer));
+        }
+
+        let keys: JwkSet = self.get_json(&discovery.jwks_uri).await?;
+        let provider = Arc::new(Provider {
+            configured: issuer.to_string(),
+            discovery,
+            keys,
+            fetched: Instant::now(),
+        });
+        *cached = Some(provider.clone());
+
+        Ok(provider)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("GET {url}: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("GET {url}: {e}"))
+    }
+
+    /// A new pending login, its state, nonce and PKCE challenge.
+    fn begin(&self) -> (String, String, String) {
+        let state = hex(&rand::random::<[u8; 16]>());
+        let nonce = hex(&rand::random::<[u8; 16]>());
+        let verifier = hex(&rand::random::<[u8; 32]>());
+        let challenge = base64_url(&Sha256::digest(verifier.as_bytes()));
+
+        let mut pending = self.pending.lock().unwrap();
+        //forget the logins nobody finished
+        let now = unix_now();
+        pending.retain(|_, login| login.expires > now);
+        if pending.len() >= MAX_PENDING_LOGINS
+            && let Some(oldest) = pending.iter().min_by_key(|(_, login)| login.expires).map(|(state, _)| state.clone())
+        {
+            pending.remove(&oldest);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce: nonce.clone(),
+                verifier,
+                expires: now + LOGIN_SECONDS,
+            },
+        );
+
+        (state, nonce, challenge)
+    }
+
+    /// Uses up the pending login of the state, none when it does not exist or has expired.
+    fn finish(&self, state: &str) -> Option<PendingLogin> {
+        self.pending.lock().unwrap().remove(state).filter(|login| login.expires > unix_now())
+    }
+
+    /// # Exchange
+    ///
+    /// Redeems the authorization code at the token endpoint and checks the ID token it returns: its signature with the keys
+    /// of the provider, the issuer, the audience, the expiry and the nonce of the login. Returns the identity of the viewer,
+    /// the email when the provider shares it.
+    async fn exchange(&self, config: &OidcConfig, code: &str, login: PendingLogin) -> Result<String, String> {
+        let (Some(issuer), Some(client_id), Some(redirect_url)) = (&config.issuer, &config.client_id, &config.redirect_url) else {
+            return Err("[auth.oidc] is not configured".to_string());
+        };
+
+        let provider = self.provider(issuer, false).await?;
+
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_url.as_str()),
+            ("client_id", client_id.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+
+        let mut request = self.client.post(&provider.discovery.token_endpoint).form(&form);
+        if let Some(secret) = &config.client_secret {
+            request = request.basic_auth(client_id, Some(secret));
+        }
+
+        let tokens: TokenResponse = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("token endpoint: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("token endpoint: {e}"))?;
+
+        let header = jsonwebtoken::decode_header(&tokens.id_token).map_err(|e| format!("ID token: {e}"))?;
+
+        //the HMAC algorithms would sign with the client secret, the provider keys are asymmetric
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(format!("ID token signed with {:?}", header.alg));
+        }
+
+        let provider = match find_key(&provider.keys, header.kid.as_deref()) {
+            Some(_) => provider,
+            None => self.provider(issuer, true).await?,
+        };
+        let key = find_key(&provider.keys, header.kid.as_deref()).ok_or_else(|| format!("no key {:?} at the provider", header.kid))?;
+        let key = DecodingKey::from_jwk(key).map_err(|e| format!("key of the provider: {e}"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[client_id]);
+        validation.set_issuer(&[&provider.discovery.issuer]);
+
+        let claims = jsonwebtoken::decode::<IdClaims>(&tokens.id_token, &key, &validation)
+            .map_err(|e| format!("ID token: {e}"))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err("the nonce of the ID token does not match the login".to_string());
+        }
+
+        //an unverified email would let anyone claim an allowed domain, the domains need the provider to vouch for the email
+        let verified = match config.allowed_domains.is_empty() {
+            true => claims.email_verified != Some(false),
+            false => claims.email_verified == Some(true),
+        };
+        let email = claims.email.filter(|_| verified);
+
+        if !config.allows(email.as_deref()) {
+            return Err(format!("{} is not in the allowed domains", email.as_deref().unwrap_or(&claims.sub)));
+        }
+
+        Ok(email.or(claims.preferred_username).unwrap_or(claims.sub))
+    }
+}
+
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        //a provider with a single key may leave out the key id
+        None => match keys.keys.as_slice() {
+            [key] => Some(key),
+            _ => None,
+        },
+    }
+}
+
+/// Unpadded base64url, the encoding of the PKCE challenge.
+fn base64_url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | ((*byte as u32) << (16 - i * 8)));
+
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[((bits >> (18 - i * 6)) & 0x3f) as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+/// # Oidc Routes
+///
+/// The OpenID Connect login of `[auth.oidc]`, 404 while it is not configured:
+///
+/// - `GET /login/oidc` - redirects to the provider to log in
+/// - `GET /login/oidc/callback` - where the provider sends the viewer back, sets the session cookie and returns to the viewer page
+pub fn oidc_routes() -> Router<Arc<StreamState>> {
+    Router::new()
+        .route("/login/oidc", get(start))
+        .route("/login/oidc/callback", get(callback))
+}
+
+async fn start(State(state): State<Arc<StreamState>>) -> Response {
+    let config = state.auth.oidc_config();
+    let (true, Some(issuer), Some(client_id), Some(redirect_url)) = (config.enabled(), &config.issuer, &config.client_id, &config.redirect_url)
+    else {
+        return BytesResolution::text(404, "OpenID Connect login is disabled").into_response();
+    };
+
+    let provider = match state.auth.oidc().provider(issuer, false).await {
+        Ok(provider) => provider,
+        Err(e) => {
+            error!(error = %e, "Failed to reach the OpenID Connect provider");
+            return BytesResolution::text(502, "The identity provider cannot be reached").into_response();
+        }
+    };
+
+    let Ok(mut url) = reqwest::Url::parse(&provider.discovery.authorization_endpoint) else {
+        error!(endpoint = %provider.discovery.authorization_endpoint, "Invalid authorization endpoint of the OpenID Connect provider");
+        return BytesResolution::text(502, "The identity provider is misconfigured").into_response();
+    };
+
+    let (login_state, nonce, challenge) = state.auth.oidc().begin();
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", client_id)
+        .append_pair("redirect_uri", redirect_url)
+        .append_pair("scope", &config.scope())
+        .append_pair("state", &login_state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    //lax, the provider sends the viewer back with a cross-site navigation
+    BytesResolution::text(303, "Redirecting to the identity provider")
+        .with_header("Location", url.to_string())
+        .with_header(
+            "Set-Cookie",
+            format!("{STATE_COOKIE}={login_state}; Path=/login/oidc; Max-Age={LOGIN_SECONDS}; HttpOnly; SameSite=Lax"),
+        )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+async fn callback(State(state): State<Arc<StreamState>>, Query(params): Query<CallbackParams>, req: Parts) -> Response {
+    let config = state.auth.oidc_config();
+    if !config.enabled() {
+        return BytesResolution::text(404, "OpenID Connect login is disabled").into_response();
+    }
+
+    if let Some(refused) = params.error {
+        warn!(error = %refused, "The OpenID Connect provider refused a login");
+        return BytesResolution::text(401, format!("The identity provider refused the login: {refused}")).into_response();
+    }
+
+    let (Some(code), Some(login_state)) = (params.code, params.state) else {
+        return BytesResolution::text(400, "Missing the code of the login").into_response();
+    };
+
+    if !cookie(&req, STATE_COOKIE).is_some_and(|cookie| constant_time_eq(&cookie, &login_state)) {
+        warn!("An OpenID Connect login came back to another browser");
+        return BytesResolution::text(400, "This login was started in another browser, log in again").into_response();
+    }
+
+    let Some(login) = state.auth.oidc().finish(&login_state) else {
+        return BytesResolution::text(400, "This login has expired, log in again").into_response();
+    };
+
+    let identity = match state.auth.oidc().exchange(&config, &code, login).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!(error = %e, "Failed OpenID Connect login");
+            return BytesResolution::text(401, "The login could not be verified").into_response();
+        }
+    };
+
+    info!(%identity, "Logged in with OpenID Connect");
+    let session = state.auth.create_identified_session(&identity);
+
+    //the session cookie is strict, it would not be sent on a redirect that came from the provider
+    let page = "<!DOCTYPE html><meta http-equiv=\"refresh\" content=\"0; url=/\"><a href=\"/\">Continue to the stream</a>";
+
+    BytesResolution::new(page.as_bytes().to_vec(), "text/html; charset=utf-8")
+        .with_header("Set-Cookie", state.auth.session_cookie(&session))
+        .with_header("Set-Cookie", format!("{STATE_COOKIE}=; Path=/login/oidc; Max-Age=0; HttpOnly; SameSite=Lax"))
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64_url_without_padding() {
+        assert_eq!(base64_url(b""), "");
+        assert_eq!(base64_url(b"f"), "Zg");
+        assert_eq!(base64_url(b"fo"), "Zm8");
+        assert_eq!(base64_url(b"foo"), "Zm9v");
+        assert_eq!(base64_url(b"foob"), "Zm9vYg");
+        assert_eq!(base64_url(b"fooba"), "Zm9vYmE");
+        assert_eq!(base64_url(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn caps_the_pending_logins() {
+        let logins = OidcLogins::default();
+        let states: Vec<String> = (0..MAX_PENDING_LOGINS + 10).map(|_| logins.begin().0).collect();
+
+        assert_eq!(logins.pending.lock().unwrap().len(), MAX_PENDING_LOGINS);
+
+        //the newest login is kept, and only finishes once
+        let newest = states.last().unwrap();
+        assert!(logins.finish(newest).is_some());
+        assert!(logins.finish(newest).is_none());
+    }
+
+    #[test]
+    fn uses_the_url_alphabet() {
+        assert_eq!(base64_url(&[0xFB, 0xFF]), "-_8");
+        assert_eq!(base64_url(&[0xFF; 3]), "____");
+        assert_eq!(base64_url(&[0x00; 3]), "AAAA");
+    }
+}
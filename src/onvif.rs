@@ -0,0 +1,459 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use chrono::{Datelike, Timelike, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info};
+
+use crate::{
+    gateway::{Gateway, bind_gateway},
+    rtsp::{RTSP_PATH, spawn_rtsp},
+    state::StreamState,
+    viewers::ViewersConfig,
+};
+
+/// Path of the device service.
+pub const DEVICE_PATH: &str = "/onvif/device_service";
+/// Path of the media service.
+pub const MEDIA_PATH: &str = "/onvif/media_service";
+
+/// Multicast group and port of WS-Discovery.
+const DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const DISCOVERY_PORT: u16 = 3702;
+/// Fps reported to NVRs for a stream without a limit, the field cannot be unlimited.
+const UNLIMITED_FPS: u32 = 30;
+
+const SOAP_CONTENT_TYPE: &str = "application/soap+xml; charset=utf-8";
+
+/// `[onvif]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct OnvifConfig {
+    pub enabled: bool,
+    /// port of the device and media services.
+    pub port: u16,
+    /// port of the RTSP stream the media service points to.
+    pub rtsp_port: u16,
+    /// name NVRs show for the camera.
+    pub name: String,
+    /// answer the WS-Discovery probes of the LAN, so the camera shows up without typing its address.
+    pub discovery: bool,
+}
+
+impl Default for OnvifConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 8000,
+            rtsp_port: 8554,
+            name: "share-screen".to_string(),
+            discovery: true,
+        }
+    }
+}
+
+/// Addresses and identity of the emulated camera.
+struct OnvifDevice {
+    name: String,
+    //stable across runs on the same host, NVRs key their cameras on it
+    uuid: String,
+    device_url: String,
+    media_url: String,
+    stream_url: String,
+    snapshot_url: String,
+}
+
+impl OnvifDevice {
+    fn new(config: &OnvifConfig, ip: IpAddr, http_port: u16) -> Self {
+        let hash = Sha256::digest(format!("{}/{ip}", config.name));
+
+        Self {
+            name: config.name.clone(),
+            uuid: format_uuid(&hash[..16]),
+            device_url: format!("http://{ip}:{}{DEVICE_PATH}", config.port),
+            media_url: format!("http://{ip}:{}{MEDIA_PATH}", config.port),
+            stream_url: format!("rtsp://{ip}:{}{RTSP_PATH}", config.rtsp_port),
+            snapshot_url: format!("http://{ip}:{http_port}/snapshot.jpg"),
+        }
+    }
+
+    fn scopes(&self) -> String {
+        format!(
+            "onvif://www.onvif.org/Profile/Streaming onvif://www.onvif.org/type/video_encoder onvif://www.onvif.org/name/{} onvif://www.onvif.org/hardware/share-screen",
+            escape(&self.name.replace(' ', "_"))
+        )
+    }
+}
+
+/// # Spawn Onvif
+///
+/// Emulates an ONVIF Profile S camera so NVRs and camera management software add the share like any other IP camera:
+/// the device and media services at `http://<ip>:<port>/onvif/...`, the RTSP stream they point to (see `spawn_rtsp`)
+/// and the answers to the WS-Discovery probes of the LAN.
+///
+/// Note: `With [auth] on the NVR is given one of the [auth] tokens as the password of the camera, the RTSP stream checks it with Digest.
+/// The services only describe the camera and its urls, they answer without credentials. Nothing is started while [encryption] is on
+/// without allow_plaintext.`
+pub fn spawn_onvif(
+    config: OnvifConfig,
+    ip: IpAddr,
+    http_port: u16,
+    viewers: ViewersConfig,
+    gateway: Arc<Gateway>,
+    state: Arc<StreamState>,
+) {
+    if !config.enabled {
+        return;
+    }
+
+    if state.cipher.as_ref().is_some_and(|cipher| !cipher.allow_plaintext) {
+        error!("The ONVIF camera streams the frames in the clear, it is not started while [encryption] is on without allow_plaintext");
+        return;
+    }
+
+    let device = Arc::new(OnvifDevice::new(&config, ip, http_port));
+
+    spawn_rtsp(config.rtsp_port, ip, viewers, gateway.clone(), state.clone());
+
+    if config.discovery {
+        tokio::spawn(answer_probes(device.clone(), state.clone()));
+    }
+
+    let router = Router::new()
+        .route(DEVICE_PATH, post(soap))
+        .route(MEDIA_PATH, post(soap))
+        .with_state((device, state.clone()));
+
+    tokio::spawn(async move {
+        let address = SocketAddr::new(ip, config.port);
+
+        if let Err(e) = bind_gateway(address, None, gateway, router, state.clone()).await {
+            state.stats.error();
+            error!(%address, error = %e, "Failed to bind the ONVIF services");
+        }
+    });
+}
+
+/// Answers the operations of the device and media services, every operation is unauthenticated.
+async fn soap(State((device, state)): State<(Arc<OnvifDevice>, Arc<StreamState>)>, envelope: String) -> Response {
+    let Some(operation) = operation(&envelope) else {
+        return fault(StatusCode::BAD_REQUEST, "s:Sender", "ter:WellFormed", "Not a SOAP envelope");
+    };
+
+    debug!(operation, "ONVIF request");
+
+    let body = match operation {
+        "GetSystemDateAndTime" => {
+            let now = Utc::now();
+
+            format!(
+                "<tds:GetSystemDateAndTimeResponse><tds:SystemDateAndTime><tt:DateTimeType>Manual</tt:DateTimeType>\
+                 <tt:DaylightSavings>false</tt:DaylightSavings><tt:UTCDateTime><tt:Time><tt:Hour>{}</tt:Hour><tt:Minute>{}</tt:Minute>\
+                 <tt:Second>{}</tt:Second></tt:Time><tt:Date><tt:Year>{}</tt:Year><tt:Month>{}</tt:Month><tt:Day>{}</tt:Day></tt:Date>\
+                 </tt:UTCDateTime></tds:SystemDateAndTime></tds:GetSystemDateAndTimeResponse>",
+                now.hour(),
+                now.minute(),
+                now.second(),
+                now.year(),
+                now.month(),
+                now.day()
+            )
+        }
+        "GetDeviceInformation" => format!(
+            "<tds:GetDeviceInformationResponse><tds:Manufacturer>share-screen</tds:Manufacturer><tds:Model>{}</tds:Model>\
+             <tds:FirmwareVersion>{}</tds:FirmwareVersion><tds:SerialNumber>{}</tds:SerialNumber><tds:HardwareId>share-screen</tds:HardwareId>\
+             </tds:GetDeviceInformationResponse>",
+            escape(&device.name),
+            env!("CARGO_PKG_VERSION"),
+            device.uuid
+        ),
+        "GetCapabilities" => format!(
+            "<tds:GetCapabilitiesResponse><tds:Capabilities><tt:Device><tt:XAddr>{}</tt:XAddr></tt:Device><tt:Media><tt:XAddr>{}</tt:XAddr>\
+             <tt:StreamingCapabilities><tt:RTPMulticast>false</tt:RTPMulticast><tt:RTP_TCP>true</tt:RTP_TCP><tt:RTP_RTSP_TCP>true</tt:RTP_RTSP_TCP>\
+             </tt:StreamingCapabilities></tt:Media></tds:Capabilities></tds:GetCapabilitiesResponse>",
+            device.device_url, device.media_url
+        ),
+        "GetServices" => format!(
+            "<tds:GetServicesResponse>\
+             <tds:Service><tds:Namespace>http://www.onvif.org/ver10/device/wsdl</tds:Namespace><tds:XAddr>{}</tds:XAddr>\
+             <tds:Version><tt:Major>2</tt:Major><tt:Minor>0</tt:Minor></tds:Version></tds:Service>\
+             <tds:Service><tds:Namespace>http://www.onvif.org/ver10/media/wsdl</tds:Namespace><tds:XAddr>{}</tds:XAddr>\
+             <tds:Version><tt:Major>2</tt:Major><tt:Minor>0</tt:Minor></tds:Version></tds:Service>\
+             </tds:GetServicesResponse>",
+            device.device_url, device.media_url
+        ),
+        "GetScopes" => {
+            let scopes: String = device
+                .scopes()
+                .split(' ')
+                .map(|scope| format!("<tds:Scopes><tt:ScopeDef>Fixed</tt:ScopeDef><tt:ScopeItem>{scope}</tt:ScopeItem></tds:Scopes>"))
+                .collect();
+
+            format!("<tds:GetScopesResponse>{scopes}</tds:GetScopesResponse>")
+        }
+        "GetProfiles" => format!("<trt:GetProfilesResponse>{}</trt:GetProfilesResponse>", profile("trt:Profiles", &device, &state)),
+        "GetProfile" => format!("<trt:GetProfileResponse>{}</trt:GetProfileResponse>", profile("trt:Profile", &device, &state)),
+        "GetVideoSources" => {
+            let (width, height, fps) = stream_format(&state);
+
+            format!(
+                "<trt:GetVideoSourcesResponse><trt:VideoSources token=\"video_source\"><tt:Framerate>{fps}</tt:Framerate>\
+                 <tt:Resolution><tt:Width>{width}</tt:Width><tt:Height>{height}</tt:Height></tt:Resolution></trt:VideoSources>\
+                 </trt:GetVideoSourcesResponse>"
+            )
+        }
+        "GetVideoSourceConfigurations" => format!(
+            "<trt:GetVideoSourceConfigurationsResponse>{}</trt:GetVideoSourceConfigurationsResponse>",
+            source_configuration("trt:Configurations", &state)
+        ),
+        "GetVideoEncoderConfigurations" => format!(
+            "<trt:GetVideoEncoderConfigurationsResponse>{}</trt:GetVideoEncoderConfigurationsResponse>",
+            encoder_configuration("trt:Configurations", &state)
+        ),
+        "GetStreamUri" => format!(
+            "<trt:GetStreamUriResponse><trt:MediaUri><tt:Uri>{}</tt:Uri><tt:InvalidAfterConnect>false</tt:InvalidAfterConnect>\
+             <tt:InvalidAfterReboot>false</tt:InvalidAfterReboot><tt:Timeout>PT0S</tt:Timeout></trt:MediaUri></trt:GetStreamUriResponse>",
+            device.stream_url
+        ),
+        "GetSnapshotUri" => format!(
+            "<trt:GetSnapshotUriResponse><trt:MediaUri><tt:Uri>{}</tt:Uri><tt:InvalidAfterConnect>false</tt:InvalidAfterConnect>\
+             <tt:InvalidAfterReboot>false</tt:InvalidAfterReboot><tt:Timeout>PT0S</tt:Timeout></trt:MediaUri></trt:GetSnapshotUriResponse>",
+            device.snapshot_url
+        ),
+        _ => {
+            return fault(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "s:Receiver",
+                "ter:ActionNotSupported",
+                &format!("{operation} is not supported"),
+            );
+        }
+    };
+
+    (StatusCode::OK, [("content-type", SOAP_CONTENT_TYPE)], envelope_of(&body)).into_response()
+}
+
+/// The name of the first element in the body of a SOAP envelope, without its namespace prefix.
+fn operation(envelope: &str) -> Option<&str> {
+    let body = envelope.find(":Body").or_else(|| envelope.find("<Body"))?;
+    let rest = &envelope[body..];
+    let rest = &rest[rest.find('>')? + 1..];
+    let rest = &rest[rest.find('<')? + 1..];
+    let name = rest.split(|c: char| c.is_whitespace() || c == '>' || c == '/').next()?;
+
+    Some(name.rsplit(':').next().unwrap_or(name)).filter(|name| !name.is_empty())
+}
+
+/// The width, the height and the fps of the frames streamed.
+fn stream_format(state: &StreamState) -> (usize, usize, u32) {
+    let dimensions = state.dimensions();
+    let status = state.status();
+    let scale = status.scale as usize;
+
+    let fps = match status.fps {
+        0 => UNLIMITED_FPS,
+        fps => fps,
+    };
+
+    (dimensions.width * scale / 100, dimensions.height * scale / 100, fps)
+}
+
+fn profile(tag: &str, device: &OnvifDevice, state: &StreamState) -> String {
+    format!(
+        "<{tag} token=\"profile_1\" fixed=\"true\"><tt:Name>{}</tt:Name>{}{}</{tag}>",
+        escape(&device.name),
+        source_configuration("tt:VideoSourceConfiguration", state),
+        encoder_configuration("tt:VideoEncoderConfiguration", state)
+    )
+}
+
+fn source_configuration(tag: &str, state: &StreamState) -> String {
+    let dimensions = state.dimensions();
+
+    format!(
+        "<{tag} token=\"video_source_config\"><tt:Name>video source</tt:Name><tt:UseCount>1</tt:UseCount>\
+         <tt:SourceToken>video_source</tt:SourceToken><tt:Bounds x=\"0\" y=\"0\" width=\"{}\" height=\"{}\"/></{tag}>",
+        dimensions.width, dimensions.height
+    )
+}
+
+fn encoder_configuration(tag: &str, state: &StreamState) -> String {
+    let (width, height, fps) = stream_format(state);
+
+    format!(
+        "<{tag} token=\"video_encoder\"><tt:Name>jpeg</tt:Name><tt:UseCount>1</tt:UseCount><tt:Encoding>JPEG</tt:Encoding>\
+         <tt:Resolution><tt:Width>{width}</tt:Width><tt:Height>{height}</tt:Height></tt:Resolution><tt:Quality>{}</tt:Quality>\
+         <tt:RateControl><tt:FrameRateLimit>{fps}</tt:FrameRateLimit><tt:EncodingInterval>1</tt:EncodingInterval><tt:BitrateLimit>0</tt:BitrateLimit></tt:RateControl>\
+         <tt:Multicast><tt:Address><tt:Type>IPv4</tt:Type><tt:IPv4Address>0.0.0.0</tt:IPv4Address></tt:Address><tt:Port>0</tt:Port><tt:TTL>1</tt:TTL>\
+         <tt:AutoStart>false</tt:AutoStart></tt:Multicast><tt:SessionTimeout>PT60S</tt:SessionTimeout></{tag}>",
+        state.control.quality()
+    )
+}
+
+fn envelope_of(body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" xmlns:tds=\"http://www.onvif.org/ver10/device/wsdl\" \
+         xmlns:trt=\"http://www.onvif.org/ver10/media/wsdl\" xmlns:tt=\"http://www.onvif.org/ver10/schema\" \
+         xmlns:ter=\"http://www.onvif.org/ver10/error\"><s:Body>{body}</s:Body></s:Envelope>"
+    )
+}
+
+fn fault(status: StatusCode, code: &str, subcode: &str, reason: &str) -> Response {
+    let body = format!(
+        "<s:Fault><s:Code><s:Value>{code}</s:Value><s:Subcode><s:Value>{subcode}</s:Value></s:Subcode></s:Code>\
+         <s:Reason><s:Text xml:lang=\"en\">{}</s:Text></s:Reason></s:Fault>",
+        escape(reason)
+    );
+
+    (status, [("content-type", SOAP_CONTENT_TYPE)], envelope_of(&body)).into_response()
+}
+
+/// # Answer Probes
+///
+/// Announces the camera on the WS-Discovery multicast group and answers the probes for network video transmitters.
+async fn answer_probes(device: Arc<OnvifDevice>, state: Arc<StreamState>) {
+    let socket = match discovery_socket() {
+        Ok(socket) => socket,
+        Err(e) => {
+            state.stats.error();
+            error!(error = %e, "Failed to join the WS-Discovery group, the camera has to be added by address");
+            return;
+        }
+    };
+
+    let hello = discovery_message("Hello", None, &device);
+    if let Err(e) = socket.send_to(hello.as_bytes(), (DISCOVERY_GROUP, DISCOVERY_PORT)).await {
+        debug!(error = %e, "Failed to announce the camera");
+    }
+
+    info!(uuid = %device.uuid, "Answering the WS-Discovery probes of the LAN");
+
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let (length, peer) = tokio::select! {
+            received = socket.recv_from(&mut buffer) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!(error = %e, "WS-Discovery receive failed");
+                    continue;
+                }
+            },
+            _ = state.shutting_down() => break,
+        };
+
+        let probe = String::from_utf8_lossy(&buffer[..length]);
+
+        if operation(&probe) != Some("Probe") || !probes_for_camera(&probe) {
+            continue;
+        }
+
+        let Some(message_id) = element_text(&probe, "MessageID") else {
+            continue;
+        };
+
+        let matches = discovery_message("ProbeMatches", Some(message_id), &device);
+
+        if let Err(e) = socket.send_to(matches.as_bytes(), peer).await {
+            debug!(%peer, error = %e, "Failed to answer a WS-Discovery probe");
+        }
+    }
+}
+
+fn discovery_socket() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    //other ONVIF software of the host may listen too
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).into())?;
+
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.join_multicast_v4(DISCOVERY_GROUP, Ipv4Addr::UNSPECIFIED)?;
+
+    Ok(socket)
+}
+
+/// Whether a probe looks for any device, a network video transmitter or an ONVIF device.
+fn probes_for_camera(probe: &str) -> bool {
+    match element_text(probe, "Types") {
+        None => true,
+        Some(types) => types.trim().is_empty() || types.contains("NetworkVideoTransmitter") || types.contains("Device"),
+    }
+}
+
+/// The text of the first element with the local name, whatever its namespace prefix.
+fn element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = xml;
+
+    loop {
+        let start = rest.find('<')? + 1;
+        rest = &rest[start..];
+
+        let tag = rest.split(|c: char| c.is_whitespace() || c == '>' || c == '/').next()?;
+
+        //closing tags start with a / and never match
+        if tag.rsplit(':').next() == Some(name) {
+            let text = &rest[rest.find('>')? + 1..];
+
+            return Some(&text[..text.find('<')?]);
+        }
+    }
+}
+
+/// A `Hello` or a `ProbeMatches` (answering `relates_to`) of the camera.
+fn discovery_message(action: &str, relates_to: Option<&str>, device: &OnvifDevice) -> String {
+    let relates_to = relates_to.map_or(String::new(), |id| format!("<a:RelatesTo>{}</a:RelatesTo>", escape(id)));
+    let (to, matches) = match action {
+        "ProbeMatches" => ("http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous", true),
+        _ => ("urn:schemas-xmlsoap-org:ws:2005:04:discovery", false),
+    };
+
+    let endpoint = format!(
+        "<a:EndpointReference><a:Address>urn:uuid:{}</a:Address></a:EndpointReference><d:Types>dn:NetworkVideoTransmitter</d:Types>\
+         <d:Scopes>{}</d:Scopes><d:XAddrs>{}</d:XAddrs><d:MetadataVersion>1</d:MetadataVersion>",
+        device.uuid,
+        device.scopes(),
+        device.device_url
+    );
+    let body = match matches {
+        true => format!("<d:ProbeMatches><d:ProbeMatch>{endpoint}</d:ProbeMatch></d:ProbeMatches>"),
+        false => format!("<d:Hello>{endpoint}</d:Hello>"),
+    };
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" xmlns:a=\"http://schemas.xmlsoap.org/ws/2004/08/addressing\" \
+         xmlns:d=\"http://schemas.xmlsoap.org/ws/2005/04/discovery\" xmlns:dn=\"http://www.onvif.org/ver10/network/wsdl\">\
+         <s:Header><a:MessageID>urn:uuid:{}</a:MessageID>{relates_to}<a:To>{to}</a:To>\
+         <a:Action>http://schemas.xmlsoap.org/ws/2005/04/discovery/{action}</a:Action></s:Header><s:Body>{body}</s:Body></s:Envelope>",
+        format_uuid(&rand::random::<[u8; 16]>())
+    )
+}
+
+/// Formats 16 bytes as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+fn format_uuid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+/// Escapes text put into the xml.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,289 @@
+use serde_json::{Map, Value, json};
+
+/// Prefix of the versioned api, the `/api/*` routes are served under it as well.
+pub const API_V1: &str = "/api/v1";
+/// Path of the OpenAPI description of the versioned api.
+pub const OPENAPI_PATH: &str = "/api/v1/openapi.json";
+
+/// Who can call an operation, see `Role`.
+#[derive(Clone, Copy)]
+enum Access {
+    Viewer,
+    Controller,
+    /// the `[admin] token` or the `[auth] admin_tokens`.
+    Admin,
+}
+
+/// A query parameter of an operation.
+struct Param {
+    name: &'static str,
+    /// json schema type: `string`, `integer`, `number` or `boolean`.
+    kind: &'static str,
+    description: &'static str,
+    required: bool,
+}
+
+/// # Operation
+///
+/// A route of the versioned api as its OpenAPI description shows it, `{id}` like segments of the path are path parameters.
+struct Operation {
+    method: &'static str,
+    /// under `/api/v1`.
+    path: &'static str,
+    summary: &'static str,
+    access: Access,
+    query: &'static [Param],
+    /// schema of `components` the response follows, a json object of its own otherwise.
+    response: Option<&'static str>,
+}
+
+const fn param(name: &'static str, kind: &'static str, required: bool, description: &'static str) -> Param {
+    Param {
+        name,
+        kind,
+        description,
+        required,
+    }
+}
+
+const fn operation(
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    access: Access,
+    query: &'static [Param],
+    response: Option<&'static str>,
+) -> Operation {
+    Operation {
+        method,
+        path,
+        summary,
+        access,
+        query,
+        response,
+    }
+}
+
+const VALUE_BOOL: &[Param] = &[param("value", "boolean", true, "true or false")];
+const NAME: &[Param] = &[param("name", "string", false, "name of the file to write in the recordings folder")];
+const STATUS: Option<&str> = Some("Status");
+const VALUE: Option<&str> = Some("Value");
+
+/// The operations of the versioned api, kept in step with the `/api/*` routes of `api`, `admin`, `cameras`, `chat`, `files`,
+/// `snapshots` and `aggregate`.
+const OPERATIONS: &[Operation] = &[
+    operation("get", "/devices", "Cameras and monitors available on the host", Access::Viewer, &[], None),
+    operation(
+        "get",
+        "/ping",
+        "Echoes ?echo= with the stream clock, ?latency_ms= reports the latency of a viewer",
+        Access::Viewer,
+        &[
+            param("echo", "string", false, "echoed back"),
+            param("latency_ms", "number", false, "latency of the viewer, from the capture of a frame to its display"),
+        ],
+        None,
+    ),
+    operation("get", "/time", "The wall clock and the clock of the frame timestamps", Access::Viewer, &[], None),
+    operation("get", "/status", "Paused, blanked, source, quality, grayscale, mirrored, crop, scale, fps and viewers at once", Access::Viewer, &[], STATUS),
+    operation("post", "/pause", "Pause the broadcast of frames", Access::Controller, &[], STATUS),
+    operation("post", "/resume", "Resume the broadcast of frames", Access::Controller, &[], STATUS),
+    operation("get", "/source", "The device being captured", Access::Viewer, &[], VALUE),
+    operation("get", "/viewers", "Sessions of the connected viewers", Access::Viewer, &[], None),
+    operation("get", "/viewers/count", "Connected viewers", Access::Viewer, &[], VALUE),
+    operation("get", "/quality", "JPEG quality", Access::Viewer, &[], VALUE),
+    operation("post", "/quality", "Set the JPEG quality", Access::Controller, &[param("value", "integer", true, "1 to 100")], STATUS),
+    operation("get", "/grayscale", "Whether only the luminance is encoded", Access::Viewer, &[], VALUE),
+    operation("post", "/grayscale", "Encode only the luminance of the frames", Access::Controller, VALUE_BOOL, STATUS),
+    operation("get", "/debug-overlay", "Whether the stats of the pipeline are burned into the frames", Access::Viewer, &[], VALUE),
+    operation("post", "/debug-overlay", "Burn the stats of the pipeline into the frames", Access::Controller, VALUE_BOOL, STATUS),
+    operation("get", "/scale", "Size of the streamed frames in percent of the captured ones", Access::Viewer, &[], VALUE),
+    operation("post", "/scale", "Set the size of the streamed frames", Access::Controller, &[param("value", "integer", true, "1 to 100 percent")], STATUS),
+    operation("get", "/mirror", "Whether the source is shown as in a mirror", Access::Viewer, &[], VALUE),
+    operation("post", "/mirror", "Show the source as in a mirror", Access::Controller, VALUE_BOOL, STATUS),
+    operation("get", "/fps", "Maximum fps, 0 is unlimited", Access::Viewer, &[], VALUE),
+    operation("post", "/fps", "Set the maximum fps", Access::Controller, &[param("value", "integer", true, "0 is unlimited")], STATUS),
+    operation("get", "/record", "Status of the recorder", Access::Viewer, &[], None),
+    operation("post", "/record/start", "Start recording", Access::Controller, NAME, None),
+    operation("post", "/record/stop", "Stop recording, the value is the path of the recording", Access::Controller, &[], VALUE),
+    operation("post", "/replay/save", "Save the replay buffer, the value is the path of the file", Access::Controller, NAME, VALUE),
+    operation("get", "/cameras", "The streams of the cameras", Access::Viewer, &[], None),
+    operation("get", "/chat", "History of the viewer chat", Access::Viewer, &[], None),
+    operation(
+        "post",
+        "/chat",
+        "Post to the chat as the host",
+        Access::Admin,
+        &[param("text", "string", true, "the message"), param("name", "string", false, "shown as the author")],
+        None,
+    ),
+    operation("get", "/files", "The files offered by the host", Access::Viewer, &[], None),
+    operation("post", "/files", "Send the body as a file to the host", Access::Controller, &[param("name", "string", true, "name of the file")], None),
+    operation("post", "/files/offer", "Offer a file of the host to the viewers", Access::Admin, &[param("path", "string", true, "file on the host")], None),
+    operation("post", "/files/{id}/withdraw", "Stop offering a file", Access::Admin, &[], None),
+    operation("post", "/shutdown", "Shut the server down cleanly", Access::Admin, &[], None),
+    operation("post", "/restart-capture", "Release and reacquire the capture device", Access::Admin, &[], None),
+    operation(
+        "post",
+        "/viewers/{id}/kick",
+        "End the stream of a viewer",
+        Access::Admin,
+        &[param("ban", "boolean", false, "also refuse the streams of its ip until the server restarts")],
+        None,
+    ),
+    operation("get", "/viewers/bans", "The banned ips", Access::Admin, &[], None),
+    operation("post", "/viewers/unban", "Lift a ban", Access::Admin, &[param("ip", "string", true, "the banned ip")], None),
+    operation("post", "/remote-input/allow", "Let a viewer control the mouse and keyboard", Access::Admin, &[], STATUS),
+    operation("post", "/remote-input/revoke", "Take the control of the mouse and keyboard back", Access::Admin, &[], STATUS),
+    operation("post", "/invites", "A one-time /join/<code> link", Access::Admin, &[], None),
+    operation(
+        "get",
+        "/snapshots",
+        "Days of the snapshot archive, or the snapshots of a day",
+        Access::Admin,
+        &[param("date", "string", false, "a day of the archive, like 2026-10-14")],
+        None,
+    ),
+    operation("get", "/snapshots/{date}/{file}", "A snapshot of the archive, as a JPEG", Access::Admin, &[], None),
+    operation("get", "/hosts", "The other hosts of the aggregation and their status", Access::Viewer, &[], None),
+];
+
+/// # OpenAPI Document
+///
+/// The OpenAPI 3.1 description of the `/api/v1/` routes, generated from `OPERATIONS`: the methods, the query and path parameters,
+/// the role each operation needs, the errors it answers and the schemas of the shared responses (the status, single values and errors).
+///
+/// Note: `Requests that are invalid or cannot be served in the current state get a 400, failures of the server a 500, both with an Error.`
+pub fn openapi_document() -> Value {
+    let mut paths = Map::new();
+
+    for operation in OPERATIONS {
+        let path = format!("{API_V1}{}", operation.path);
+
+        let mut parameters: Vec<Value> = operation
+            .path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "string"}}))
+            .collect();
+        parameters.extend(operation.query.iter().map(|param| {
+            json!({
+                "name": param.name,
+                "in": "query",
+                "required": param.required,
+                "description": param.description,
+                "schema": {"type": param.kind},
+            })
+        }));
+
+        let schema = match operation.response {
+            Some(name) => json!({"$ref": format!("#/components/schemas/{name}")}),
+            None => json!({"type": "object"}),
+        };
+        let (role, denied) = match operation.access {
+            Access::Viewer => ("viewer", None),
+            Access::Controller => ("controller", Some("403")),
+            Access::Admin => ("admin", Some("403")),
+        };
+
+        let error = |description: &str| {
+            json!({"description": description, "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}})
+        };
+
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            json!({"description": "OK", "content": {"application/json": {"schema": schema}}}),
+        );
+        //the changes and the parameters can be refused, the plain reads cannot
+        if operation.method == "post" || !parameters.is_empty() {
+            responses.insert("400".to_string(), error("Invalid parameters, or not possible in the current state"));
+        }
+        responses.insert("401".to_string(), json!({"description": "Not authenticated"}));
+        if let Some(denied) = denied {
+            responses.insert(denied.to_string(), json!({"description": format!("Needs the {role} role")}));
+        }
+        if operation.path.contains('{') {
+            responses.insert("404".to_string(), json!({"description": "Nothing has this id"}));
+        }
+        responses.insert("500".to_string(), error("The server failed at the operation"));
+
+        let entry = paths.entry(path).or_insert_with(|| json!({}));
+        entry[operation.method] = json!({
+            "summary": operation.summary,
+            "operationId": operation_id(operation),
+            "x-role": role,
+            "parameters": parameters,
+            "responses": responses,
+        });
+    }
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "share-screen",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Control api of a share-screen server. The streams (/stream, /stream/audio, /events) are described in the README.",
+        },
+        "paths": paths,
+        "security": [{"bearer": []}, {"session": []}],
+        "components": {
+            "securitySchemes": {
+                "bearer": {"type": "http", "scheme": "bearer", "description": "one of the [auth] tokens, ?token= works as well"},
+                "session": {"type": "apiKey", "in": "cookie", "name": "share_session"},
+            },
+            "schemas": {
+                "Status": {
+                    "type": "object",
+                    "properties": {
+                        "paused": {"type": "boolean"},
+                        "blanked": {"type": "boolean"},
+                        "remote_input": {"type": "boolean"},
+                        "source": {"type": "string"},
+                        "quality": {"type": "integer"},
+                        "grayscale": {"type": "boolean"},
+                        "mirrored": {"type": "boolean"},
+                        "debug_overlay": {"type": "boolean"},
+                        "crop": {
+                            "type": "object",
+                            "properties": {
+                                "x": {"type": "integer"},
+                                "y": {"type": "integer"},
+                                "width": {"type": "integer"},
+                                "height": {"type": "integer"},
+                            },
+                        },
+                        "scale": {"type": "integer"},
+                        "fps": {"type": "integer"},
+                        "viewers": {"type": "integer"},
+                    },
+                },
+                "Value": {
+                    "type": "object",
+                    "properties": {"value": {}},
+                    "required": ["value"],
+                },
+                "Error": {
+                    "type": "object",
+                    "properties": {"error": {"type": "string"}},
+                    "required": ["error"],
+                },
+            },
+        },
+    })
+}
+
+/// `getStatus`, `postRecordStart`, `getViewersIdKick`... from the method and the path.
+fn operation_id(operation: &Operation) -> String {
+    let mut id = operation.method.to_string();
+
+    for word in operation.path.split(['/', '-', '{', '}']).filter(|word| !word.is_empty()) {
+        let mut characters = word.chars();
+        if let Some(first) = characters.next() {
+            id.push(first.to_ascii_uppercase());
+            id.extend(characters);
+        }
+    }
+
+    id
+}
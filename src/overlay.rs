@@ -0,0 +1,256 @@
+use serde::Deserialize;
+
+use crate::state::StreamState;
+
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+/// 3x5 glyphs of the burned-in text, one row per byte with the 3 low bits left to right.
+///
+/// Letters are upper case only, other characters (spaces included) are left blank.
+const GLYPHS: [(char, [u8; 5]); 42] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+];
+
+/// Corner of the frame the text is drawn in.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    #[default]
+    BottomLeft,
+    BottomRight,
+}
+
+/// `[overlay]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct OverlayConfig {
+    pub enabled: bool,
+    /// `strftime` format of the local date and time, see the chrono documentation.
+    pub format: String,
+    /// also draws the name of the machine after the time.
+    pub hostname: bool,
+    pub corner: Corner,
+    /// starts with the debug overlay of the pipeline stats, switched at runtime with `/api/debug-overlay`.
+    pub debug: bool,
+    /// corner of the debug overlay.
+    pub debug_corner: Corner,
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: "%Y-%m-%d %H:%M:%S".to_string(),
+            hostname: false,
+            corner: Corner::default(),
+            debug: false,
+            debug_corner: Corner::TopLeft,
+        }
+    }
+}
+
+/// # Timestamp Overlay
+///
+/// Burns the local date and time (and the name of the machine) into a corner of every frame before it is encoded,
+/// so recordings, snapshots and the stream all carry when they were captured.
+#[derive(Default)]
+pub struct TimestampOverlay {
+    enabled: bool,
+    format: String,
+    hostname: Option<String>,
+    corner: Corner,
+    debug_corner: Corner,
+}
+
+impl TimestampOverlay {
+    pub fn new(config: &OverlayConfig) -> Self {
+        let hostname = config.hostname.then(|| {
+            std::env::var("COMPUTERNAME")
+                .or_else(|_| std::env::var("HOSTNAME"))
+                .unwrap_or_else(|_| "unknown host".to_string())
+        });
+
+        //an invalid format would panic on the first frame
+        let invalid = chrono::format::StrftimeItems::new(&config.format).any(|item| matches!(item, chrono::format::Item::Error));
+        let format = if invalid {
+            tracing::warn!(format = %config.format, "Invalid [overlay] format, using the default one");
+            OverlayConfig::default().format
+        } else {
+            config.format.clone()
+        };
+
+        Self {
+            enabled: config.enabled,
+            format,
+            hostname,
+            corner: config.corner,
+            debug_corner: config.debug_corner,
+        }
+    }
+
+    /// Draws the current time onto the BGRA frame, nothing when the overlay is disabled.
+    pub fn draw(&self, frame: &mut [u8], width: u32, height: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut text = chrono::Local::now().format(&self.format).to_string();
+
+        if let Some(hostname) = &self.hostname {
+            text.push_str("  ");
+            text.push_str(hostname);
+        }
+
+        draw_text(frame, width as usize, height as usize, &text, self.corner);
+    }
+
+    /// Draws the stats of the pipeline onto the BGRA frame, in the corner of the debug overlay.
+    pub fn draw_debug(&self, frame: &mut [u8], width: u32, height: u32, stats: &DebugStats) {
+        draw_text(frame, width as usize, height as usize, &stats.text(), self.debug_corner);
+    }
+}
+
+/// # Debug Stats
+///
+/// What the debug overlay shows while `StreamControl` has it on, to watch the health of the pipeline in the stream itself
+/// while tuning the settings. The rates are the ones of `/stats`, over the last seconds.
+pub struct DebugStats {
+    pub fps: f64,
+    pub encode_ms: f64,
+    pub frame_bytes: f64,
+    /// frames over the fps limit or that failed to encode, since the start.
+    pub dropped: u64,
+    pub viewers: usize,
+}
+
+impl DebugStats {
+    pub fn current(state: &StreamState) -> Self {
+        let rates = state.pipeline.recent_rates();
+
+        Self {
+            fps: rates.encode_fps,
+            encode_ms: rates.average_encode_ms,
+            frame_bytes: rates.average_frame_bytes,
+            dropped: state.pipeline.snapshot().frames_dropped,
+            viewers: state.stats.viewers(),
+        }
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "FPS {:.1}  ENCODE {:.1}MS  FRAME {:.0}KB  DROPPED {}  VIEWERS {}",
+            self.fps,
+            self.encode_ms,
+            self.frame_bytes / 1024.0,
+            self.dropped,
+            self.viewers
+        )
+    }
+}
+
+/// # Draw Text
+///
+/// Draws the text in white on a black box in a corner of the BGRA frame, with glyphs scaled to the height of the frame.
+///
+/// Note: `Lower case letters are drawn upper case, characters without a glyph are left blank.`
+pub fn draw_text(frame: &mut [u8], width: usize, height: usize, text: &str, corner: Corner) {
+    if frame.len() < width * height * 4 {
+        return;
+    }
+
+    let scale = (height / 60).max(2);
+    let advance = 4 * scale;
+    let margin = 2 * scale;
+
+    let box_width = (text.chars().count() * advance + margin * 2).min(width);
+    let box_height = (5 * scale + margin * 2).min(height);
+    let left = match corner {
+        Corner::TopLeft | Corner::BottomLeft => 0,
+        Corner::TopRight | Corner::BottomRight => width - box_width,
+    };
+    let top = match corner {
+        Corner::TopLeft | Corner::TopRight => 0,
+        Corner::BottomLeft | Corner::BottomRight => height - box_height,
+    };
+
+    let mut fill = |x: usize, y: usize, color: &[u8; 4]| {
+        if x < left + box_width && y < height {
+            let offset = (y * width + x) * 4;
+            frame[offset..offset + 4].copy_from_slice(color);
+        }
+    };
+
+    for y in top..top + box_height {
+        for x in left..left + box_width {
+            fill(x, y, &BLACK);
+        }
+    }
+
+    for (index, character) in text.chars().enumerate() {
+        let character = character.to_ascii_uppercase();
+
+        let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == character) else {
+            continue;
+        };
+
+        let glyph_left = left + margin + index * advance;
+
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        fill(glyph_left + column * scale + dx, top + margin + row * scale + dy, &WHITE);
+                    }
+                }
+            }
+        }
+    }
+}
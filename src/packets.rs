@@ -0,0 +1,240 @@
+/// Version of the packet framing, bumped on every incompatible change.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Protocol versions the server can stream.
+pub const SUPPORTED_VERSIONS: &[u8] = &[PROTOCOL_VERSION];
+
+/// # Negotiate version
+///
+/// Picks the protocol version for a client from the `version` query parameter, no version requested means the current version.
+///
+/// Errors when the client requested a version the server cannot stream.
+pub fn negotiate_version(requested: Option<&str>) -> Result<u8, String> {
+    let Some(requested) = requested else {
+        return Ok(PROTOCOL_VERSION);
+    };
+
+    match requested.parse::<u8>() {
+        Ok(version) if SUPPORTED_VERSIONS.contains(&version) => Ok(version),
+        _ => Err(format!(
+            "unsupported protocol version {requested}, supported versions: {SUPPORTED_VERSIONS:?}"
+        )),
+    }
+}
+
+/// Size of the header in front of every packet.
+pub const HEADER_LEN: usize = 20;
+
+/// Flag of packets whose payload is encrypted (see `FrameCipher`).
+pub const FLAG_ENCRYPTED: u16 = 1;
+/// Size of the nonce in front of the payload of an encrypted packet.
+pub const NONCE_LEN: usize = 24;
+
+/// The kind of payload a packet carries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum PacketType {
+    /// an encoded JPEG frame.
+    Frame = 0,
+    /// `[4 bytes width] + [4 bytes height]`, sent when the captured device changes resolution.
+    Dimensions = 1,
+    /// an Opus packet.
+    Audio = 2,
+    /// keepalive without a payload, sent while no other packet is, its sequence is always 0.
+    Heartbeat = 3,
+}
+
+impl PacketType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PacketType::Frame),
+            1 => Some(PacketType::Dimensions),
+            2 => Some(PacketType::Audio),
+            3 => Some(PacketType::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+/// The header in front of every packet.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PacketHeader {
+    pub version: u8,
+    pub kind: PacketType,
+    /// `FLAG_*` bits.
+    pub flags: u16,
+    /// increments by one per packet of a feed, gaps are dropped packets.
+    pub sequence: u32,
+    /// capture time in microseconds on the stream clock (see `StreamState::timestamp_us`).
+    pub timestamp_us: u64,
+    /// length of the payload following the header.
+    pub length: u32,
+}
+
+/// # Packet
+///
+/// Creates a single packet, all fields are little endian:
+///
+/// `[1 byte version] + [1 byte type] + [2 bytes flags] + [4 bytes sequence] + [8 bytes timestamp] + [4 bytes length] + [payload]`
+pub fn packet(kind: PacketType, sequence: u32, timestamp_us: u64, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.push(PROTOCOL_VERSION);
+    packet.push(kind as u8);
+    packet.extend_from_slice(&0u16.to_le_bytes()); //flags
+    packet.extend_from_slice(&sequence.to_le_bytes());
+    packet.extend_from_slice(&timestamp_us.to_le_bytes());
+    packet.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+/// Parses the header of a packet, `None` for malformed packets or packets of another protocol version.
+pub fn parse(packet: &[u8]) -> Option<(PacketHeader, &[u8])> {
+    let header = packet.get(..HEADER_LEN)?;
+
+    if header[0] != PROTOCOL_VERSION {
+        return None;
+    }
+
+    let header = PacketHeader {
+        version: header[0],
+        kind: PacketType::from_u8(header[1])?,
+        flags: u16::from_le_bytes(header[2..4].try_into().ok()?),
+        sequence: u32::from_le_bytes(header[4..8].try_into().ok()?),
+        timestamp_us: u64::from_le_bytes(header[8..16].try_into().ok()?),
+        length: u32::from_le_bytes(header[16..20].try_into().ok()?),
+    };
+
+    let payload = packet.get(HEADER_LEN..HEADER_LEN + header.length as usize)?;
+
+    Some((header, payload))
+}
+
+/// Length of the packet at the start of a buffer of received bytes, `None` until it was fully received.
+pub fn packet_len(buffer: &[u8]) -> Option<usize> {
+    let length = u32::from_le_bytes(buffer.get(16..HEADER_LEN)?.try_into().ok()?) as usize;
+
+    (buffer.len() >= HEADER_LEN + length).then_some(HEADER_LEN + length)
+}
+
+/// Creates a frame packet carrying an encoded JPEG.
+pub fn frame_packet(sequence: u32, timestamp_us: u64, jpeg: &[u8]) -> Vec<u8> {
+    packet(PacketType::Frame, sequence, timestamp_us, jpeg)
+}
+
+/// # Dimensions Packet
+///
+/// Creates an in-band dimension update with a `[4 bytes width] + [4 bytes height]` payload.
+///
+/// Sent when the captured device changes resolution so clients can resize before the next frame.
+pub fn dimensions_packet(sequence: u32, timestamp_us: u64, width: u32, height: u32) -> Vec<u8> {
+    let mut payload = [0u8; 8];
+    payload[..4].copy_from_slice(&width.to_le_bytes());
+    payload[4..].copy_from_slice(&height.to_le_bytes());
+
+    packet(PacketType::Dimensions, sequence, timestamp_us, &payload)
+}
+
+/// Creates an audio packet carrying an Opus packet.
+pub fn audio_packet(sequence: u32, timestamp_us: u64, opus: &[u8]) -> Vec<u8> {
+    packet(PacketType::Audio, sequence, timestamp_us, opus)
+}
+
+/// Creates a heartbeat packet, sent to idle clients so dead connections surface as write errors.
+pub fn heartbeat_packet(timestamp_us: u64) -> Vec<u8> {
+    packet(PacketType::Heartbeat, 0, timestamp_us, &[])
+}
+
+/// Gets the capture timestamp and JPEG bytes of a frame packet, `None` for control packets (dimension updates) or malformed packets.
+pub fn frame_payload(packet: &[u8]) -> Option<(u64, &[u8])> {
+    match parse(packet)? {
+        (header, jpeg) if header.kind == PacketType::Frame => Some((header.timestamp_us, jpeg)),
+        _ => None,
+    }
+}
+
+/// Gets the sequence of a frame packet, `None` for other packets or malformed packets.
+pub fn frame_sequence(packet: &[u8]) -> Option<u32> {
+    match parse(packet)? {
+        (header, _) if header.kind == PacketType::Frame => Some(header.sequence),
+        _ => None,
+    }
+}
+
+/// Gets the timestamp and Opus bytes of an audio packet, `None` for malformed packets.
+pub fn audio_payload(packet: &[u8]) -> Option<(u64, &[u8])> {
+    match parse(packet)? {
+        (header, opus) if header.kind == PacketType::Audio => Some((header.timestamp_us, opus)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_packets_it_creates() {
+        let created = packet(PacketType::Audio, 7, 123_456, b"opus");
+        let (header, payload) = parse(&created).unwrap();
+
+        assert_eq!(
+            header,
+            PacketHeader {
+                version: PROTOCOL_VERSION,
+                kind: PacketType::Audio,
+                flags: 0,
+                sequence: 7,
+                timestamp_us: 123_456,
+                length: 4,
+            }
+        );
+        assert_eq!(payload, b"opus");
+        assert_eq!(created.len(), HEADER_LEN + 4);
+    }
+
+    #[test]
+    fn refuses_malformed_packets() {
+        let mut created = frame_packet(1, 2, b"jpeg");
+
+        assert!(parse(&created[..HEADER_LEN - 1]).is_none());
+        assert!(parse(&created[..created.len() - 1]).is_none());
+
+        created[1] = 9;
+        assert!(parse(&created).is_none());
+
+        created[1] = PacketType::Frame as u8;
+        created[0] = PROTOCOL_VERSION + 1;
+        assert!(parse(&created).is_none());
+    }
+
+    #[test]
+    fn waits_for_the_whole_packet() {
+        let created = dimensions_packet(3, 4, 1920, 1080);
+
+        assert_eq!(packet_len(&created[..HEADER_LEN]), None);
+        assert_eq!(packet_len(&created[..created.len() - 1]), None);
+        assert_eq!(packet_len(&[created.as_slice(), b"next"].concat()), Some(HEADER_LEN + 8));
+    }
+
+    #[test]
+    fn reads_the_payloads_of_their_kind_only() {
+        let frame = frame_packet(5, 6, b"jpeg");
+        let audio = audio_packet(5, 6, b"opus");
+
+        assert_eq!(frame_payload(&frame), Some((6, b"jpeg".as_slice())));
+        assert_eq!(frame_sequence(&frame), Some(5));
+        assert_eq!(audio_payload(&audio), Some((6, b"opus".as_slice())));
+        assert_eq!(frame_payload(&audio), None);
+        assert_eq!(audio_payload(&heartbeat_packet(6)), None);
+    }
+
+    #[test]
+    fn negotiates_the_supported_versions() {
+        assert_eq!(negotiate_version(None), Ok(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(Some("1")), Ok(1));
+        assert!(negotiate_version(Some("2")).is_err());
+        assert!(negotiate_version(Some("one")).is_err());
+        assert!(negotiate_version(Some("")).is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::FutureExt;
+use tracing::{Instrument, error, info, info_span, warn};
+
+use crate::{
+    captures::SerializedDimensions,
+    events::ServerEvent,
+    frame_compressor::{detect_stride, strip_row_padding},
+    hdr::PixelFormat,
+    overlay::DebugStats,
+    packets::{dimensions_packet, frame_packet},
+    pipeline_stats::Stage,
+    source::CaptureSource,
+    state::{RawFrame, StreamState},
+};
+
+/// Time waited between attempts to reacquire a capture device that stopped.
+const REACQUIRE_INTERVAL: Duration = Duration::from_secs(2);
+/// Time the compressor has to encode the frames in flight when shutting down.
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// # Spawn Frame Capture
+///
+/// Spawns a tokio task that starts and awaits the capture function of the device, along with the frame compressor for it.
+///
+/// If the capture stops with an error and the device can be reacquired (monitor hotplug, display topology change) the devices are re-enumerated
+/// and the capture continues on the reacquired device once it is available again.
+///
+/// Other devices wait for a restart, requested by the watchdog or through `/api/restart-capture`,
+/// which releases and reacquires the device the same way.
+///
+/// A panic anywhere in the pipeline (the capture, the compressor, the conversions and the encoder on the blocking threads)
+/// restarts it on the reacquired device instead of leaving the server up without frames, the panic is logged with its backtrace
+/// (see `logging::log_panics`) and emitted as a `pipeline_restarted` event.
+///
+/// The task ends when the server shuts down, releasing the device.
+pub fn spawn_frame_capture(
+    capture: Arc<dyn CaptureSource>,
+    state: Arc<StreamState>,
+) -> tokio::task::JoinHandle<()> {
+    let state_source = state.source();
+
+    tokio::spawn(async move {
+        let mut capture = Some(capture);
+
+        loop {
+            let current = match capture.take() {
+                Some(current) => current,
+                None => match reacquire(&state).await {
+                    Some(current) => current,
+                    None => return,
+                },
+            };
+
+            //the loop holds the capture, a panic of it drops the capture before the device is opened again
+            match std::panic::AssertUnwindSafe(run_capture(current, state.clone())).catch_unwind().await {
+                Ok(()) => return,
+                Err(_) => {
+                    let reason = "capture pipeline panicked".to_string();
+
+                    state.stats.error();
+                    error!(%reason, "Restarting capture");
+                    state.set_capture_error(Some(reason.clone()));
+                    state.emit(ServerEvent::PipelineRestarted { reason });
+                }
+            }
+        }
+    }.instrument(info_span!("capture", source = %state_source)))
+}
+
+/// Captures and compresses until the server shuts down, restarting the capture whenever it stops.
+async fn run_capture(mut capture: Arc<dyn CaptureSource>, state: Arc<StreamState>) {
+    loop {
+        let mut compressor = spawn_frame_compressor(capture.clone(), state.clone());
+        let _compressor_guard = AbortOnDrop(compressor.abort_handle());
+
+        //a panic in the capture is handled like any other capture error
+        let capturing = std::panic::AssertUnwindSafe(capture.run()).catch_unwind();
+
+        let restart = tokio::select! {
+            result = capturing => {
+                //the compressor is bound to the receiver of this capture.
+                compressor.abort();
+
+                let error = match result {
+                    Ok(Err(e)) => e.to_string(),
+                    Ok(Ok(())) => "capture ended".to_string(),
+                    Err(_) => "capture panicked".to_string(),
+                };
+
+                state.stats.error();
+                error!(%error, "Capture stopped");
+                state.set_capture_error(Some(error.clone()));
+                state.emit(ServerEvent::CaptureError {
+                    error,
+                    reacquiring: state.source().can_reacquire(),
+                });
+
+                false
+            }
+            result = &mut compressor => {
+                let reason = match result {
+                    Err(e) if e.is_panic() => "frame compressor panicked",
+                    _ => "frame compressor stopped",
+                };
+
+                state.stats.error();
+                warn!(%reason, "Restarting capture");
+                state.set_capture_error(Some(reason.to_string()));
+                state.emit(ServerEvent::PipelineRestarted {
+                    reason: reason.to_string(),
+                });
+
+                true
+            }
+            _ = state.capture_restart_requested() => {
+                compressor.abort();
+                info!("Restarting capture...");
+                state.set_capture_error(Some("restarting capture".to_string()));
+
+                true
+            }
+            _ = state.shutting_down() => {
+                //let the compressor finish the frames in flight, the device is released when the task ends
+                let _ = tokio::time::timeout(FLUSH_TIMEOUT, &mut compressor).await;
+                compressor.abort();
+                break;
+            }
+        };
+
+        //the device has to be released before it can be activated again
+        drop(capture);
+
+        //devices that cannot be reacquired on their own wait for a restart (watchdog or /api/restart-capture)
+        if !restart && !state.source().can_reacquire() {
+            tokio::select! {
+                _ = state.capture_restart_requested() => {}
+                _ = state.shutting_down() => return,
+            }
+        }
+
+        capture = match reacquire(&state).await {
+            Some(capture) => capture,
+            None => return,
+        };
+    }
+}
+
+/// Aborts the task when dropped, the compressor of a capture loop that panicked does not hold the device open.
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// # Reacquire
+///
+/// Opens the device of the stream again once its capture was dropped, retrying every `REACQUIRE_INTERVAL` until it is available.
+/// None when the server shuts down first.
+async fn reacquire(state: &StreamState) -> Option<Arc<dyn CaptureSource>> {
+    let source = state.source();
+
+    info!("Re-enumerating devices...");
+
+    let capture = loop {
+        tokio::select! {
+            _ = tokio::time::sleep(REACQUIRE_INTERVAL) => {}
+            _ = state.shutting_down() => return None,
+        }
+
+        match source.open() {
+            Ok(c) => break c,
+            Err(e) => {
+                state.stats.error();
+                warn!(error = %e, "Failed to reacquire device");
+                state.set_capture_error(Some(e.to_string()));
+            }
+        }
+    };
+
+    let down_secs = state.capture_down_for().unwrap_or_default().as_secs_f64();
+
+    info!(down_secs = format_args!("{down_secs:.1}"), "Device reacquired, continuing capture");
+    state.set_capture_error(None);
+    state.emit(ServerEvent::CaptureRestored {
+        source: source.to_string(),
+        down_secs,
+    });
+    state.emit(ServerEvent::SourceChanged {
+        source: source.to_string(),
+    });
+
+    Some(capture)
+}
+
+/// # Spawn Compressor
+///
+/// Spawns a separate task that compresses incoming frames of the device and sends them to the broadcast channel
+///
+/// Frames are held back while the stream is paused and skipped to stay under the configured fps.
+///
+/// When a frame does not match the known dimensions the device is queried again, if it changed resolution the shared dimensions are updated
+/// and connected clients are notified with an in-band dimension update packet.
+///
+/// A panic of the work on the blocking threads (tone mapping, transform, encoding) or of the task ends it as panicked,
+/// which restarts the capture, see `resume_panic`. So does a device whose dimensions cannot be read.
+///
+/// Note: `This is called by spawn_frame_capture each time the capture (re)starts`
+fn spawn_frame_compressor(
+    capture: Arc<dyn CaptureSource>,
+    state: Arc<StreamState>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let (mut width, mut height) = match capture.dimensions() {
+            Ok(dimensions) => dimensions,
+            Err(e) => {
+                error!(error = %e, "Could not get the dimensions of the capture");
+                return;
+            }
+        };
+
+        //a reacquired device may come back with a different resolution, relayed frames cannot be transformed
+        match capture.pre_encoded() {
+            true => update_dimensions(&state, width, height),
+            false => {
+                let (width, height) = state.transform.dimensions(width, height);
+                let (width, height) = state.scaler.dimensions(width, height);
+                update_dimensions(&state, width, height);
+            }
+        }
+
+        let mut last_frame: Option<std::time::Instant> = None;
+        let mut last_format = PixelFormat::Bgra8;
+
+        loop {
+            let waiting = std::time::Instant::now();
+            let Some(mut raw_data) = capture.next_frame().await else {
+                break; //done receiving data
+            };
+            state.pipeline.stages.record(Stage::Capture, waiting.elapsed());
+
+            let timestamp_us = state.timestamp_us();
+            state.pipeline.frame_captured();
+
+            if state.control.is_paused() {
+                continue;
+            }
+
+            if let (Some(interval), Some(last)) = (state.control.frame_interval(), last_frame)
+                && last.elapsed() < interval
+            {
+                state.pipeline.frame_dropped();
+                continue;
+            }
+
+            last_frame = Some(std::time::Instant::now());
+
+            //relayed frames keep the encoding of the upstream, only its dimensions are followed
+            if capture.pre_encoded() {
+                if let Ok(current) = capture.dimensions()
+                    && current != (width, height)
+                {
+                    (width, height) = current;
+
+                    update_dimensions(&state, width, height);
+                }
+
+                if !state.control.is_blanked() {
+                    state.pipeline.frame_encoded(Duration::ZERO, raw_data.len());
+                    broadcast_frame(&state, timestamp_us, raw_data);
+                }
+
+                continue;
+            }
+
+            //HDR frames continue as SDR BGRA like the frames of any other monitor
+            let format = state.tone_mapper.detect(capture.pixel_format(), raw_data.len(), width, height);
+
+            if format != last_format {
+                info!(?format, "Capture pixel format changed");
+                last_format = format;
+            }
+
+            if format != PixelFormat::Bgra8 {
+                let tone_mapper_state = state.clone();
+
+                raw_data = tokio::task::spawn_blocking(move || {
+                    tone_mapper_state.tone_mapper.to_sdr(raw_data, width, height, format)
+                })
+                .await
+                .unwrap_or_else(resume_panic);
+            }
+
+            if raw_data.len() != (width * height * 4) as usize
+                && let Ok(current) = capture.dimensions()
+                && current != (width, height)
+            {
+                (width, height) = current;
+            }
+
+            //rows padded to the row pitch of the device are packed before anything reads the frame
+            if raw_data.len() != (width * height * 4) as usize
+                && let Some(stride) = capture.stride().or_else(|| detect_stride(raw_data.len(), width, height))
+            {
+                raw_data = strip_row_padding(raw_data, width, height, stride);
+            }
+
+            //a capture unaware of the DPI scaling reports the logical size of a scaled monitor while the frames have the physical one
+            if raw_data.len() != (width * height * 4) as usize
+                && let Some(scaled) = scaled_dimensions(raw_data.len(), width, height)
+            {
+                warn!(
+                    reported = %format_args!("{width}x{height}"),
+                    frames = %format_args!("{}x{}", scaled.0, scaled.1),
+                    "The frames do not have the dimensions of the capture, following the frames"
+                );
+                (width, height) = scaled;
+            }
+
+            //in the coordinates of the monitor, before the frame is transformed
+            state.blackout.draw(&mut raw_data, width, height);
+
+            //the stream has the dimensions of the transformed, cropped and scaled frames
+            let (mut raw_data, out_width, out_height) = if state.transform.is_identity()
+                && !state.autocrop.enabled()
+                && state.scaler.is_identity()
+            {
+                (raw_data, width, height)
+            } else {
+                let transform_state = state.clone();
+
+                tokio::task::spawn_blocking(move || {
+                    let (frame, width, height) = transform_state.transform.apply(raw_data, width, height);
+                    let (frame, width, height) = transform_state.autocrop.apply(frame, width, height);
+                    transform_state.scaler.apply(frame, width, height)
+                })
+                .await
+                .unwrap_or_else(resume_panic)
+            };
+
+            update_dimensions(&state, out_width, out_height);
+
+            //the capture keeps running, so unblanking is instant
+            if state.control.is_blanked() {
+                raw_data.fill(0);
+            } else {
+                state.pointer.draw(&mut raw_data, out_width, out_height);
+                state.overlay.draw(&mut raw_data, out_width, out_height);
+
+                if state.control.is_debug_overlay() {
+                    state.overlay.draw_debug(&mut raw_data, out_width, out_height, &DebugStats::current(&state));
+                }
+            }
+
+            let frame = Arc::new(RawFrame {
+                data: raw_data,
+                width: out_width,
+                height: out_height,
+                timestamp_us,
+            });
+            state.set_latest_raw(frame.clone());
+
+            let settings = state.control.encode_settings();
+            let encoder = state.encoder.clone();
+            let encode_started = std::time::Instant::now();
+
+            let staged = frame.clone();
+            let encoded = tokio::task::spawn_blocking(move || encoder.encode_staged(&staged, settings))
+                .await
+                .unwrap_or_else(resume_panic);
+
+            let Ok((compressed, converted)) = encoded else {
+                state.stats.error();
+                state.pipeline.frame_dropped();
+                continue;
+            };
+
+            let took = encode_started.elapsed();
+            let converted = converted.unwrap_or_default();
+            state.pipeline.stages.record(Stage::Convert, converted);
+            state.pipeline.stages.record(Stage::Encode, took.saturating_sub(converted));
+
+            state.pipeline.frame_encoded(took, compressed.len());
+            let sequence = broadcast_frame(&state, timestamp_us, compressed);
+
+            //once more for the streams of another codec, after the stream so it is not slowed down
+            for feed in state.codecs.watched() {
+                let (encoder, frame) = (feed.encoder.clone(), frame.clone());
+                let encoded = tokio::task::spawn_blocking(move || encoder.encode(&frame, settings))
+                    .await
+                    .unwrap_or_else(resume_panic);
+
+                match encoded {
+                    Ok(encoded) => feed.send_frame(frame_packet(sequence, timestamp_us, &encoded)),
+                    Err(_) => state.stats.error(),
+                }
+            }
+        }
+    }.instrument(info_span!("compressor")))
+}
+
+/// # Resume Panic
+///
+/// Panics the compressor with the panic of its work on a blocking thread, the capture restarts on a panicked compressor
+/// while a dropped frame would leave it failing on every frame. The panic was already logged where it happened.
+fn resume_panic<T>(error: tokio::task::JoinError) -> T {
+    match error.try_into_panic() {
+        Ok(panic) => std::panic::resume_unwind(panic),
+        //the blocking pool only cancels its tasks at the shutdown of the runtime
+        Err(error) => panic!("blocking work of the compressor cancelled: {error}"),
+    }
+}
+
+/// # Scaled Dimensions
+///
+/// The dimensions of a BGRA frame of `len` bytes with the aspect ratio of `width`x`height` (the same size scaled up or down), if there are any.
+fn scaled_dimensions(len: usize, width: u32, height: u32) -> Option<(u32, u32)> {
+    if width == 0 || height == 0 || len % 4 != 0 {
+        return None;
+    }
+
+    let scale = ((len / 4) as f64 / (width as f64 * height as f64)).sqrt();
+    let (scaled_width, scaled_height) = ((width as f64 * scale).round() as i64, (height as f64 * scale).round() as i64);
+
+    //the scaled size may be rounded a pixel off either way
+    (-1..=1)
+        .flat_map(|dw| (-1..=1).map(move |dh| (scaled_width + dw, scaled_height + dh)))
+        .find(|&(width, height)| width > 0 && height > 0 && (width * height * 4) as usize == len)
+        .map(|(width, height)| (width as u32, height as u32))
+}
+
+/// Sends an encoded frame to the viewers, the replay buffer and the snapshots, returns the sequence it was sent with.
+fn broadcast_frame(state: &StreamState, timestamp_us: u64, encoded: Vec<u8>) -> u32 {
+    let broadcasting = std::time::Instant::now();
+    state.stats.frame_encoded();
+    state.frame_produced(timestamp_us);
+    state
+        .pipeline
+        .pipeline_latency
+        .record(Duration::from_micros(state.timestamp_us().saturating_sub(timestamp_us)));
+
+    let sequence = state.next_frame_sequence();
+    state.send_frame(frame_packet(sequence, timestamp_us, &encoded));
+
+    let encoded = Arc::new(encoded);
+    state.replay.push(encoded.clone());
+    state.set_latest_frame(encoded);
+
+    state.pipeline.stages.record(Stage::Broadcast, broadcasting.elapsed());
+
+    sequence
+}
+
+/// # Update Dimensions
+///
+/// Updates the shared dimensions of the capture and broadcasts a dimension update packet if they changed.
+pub fn update_dimensions(state: &StreamState, width: u32, height: u32) {
+    let updated = SerializedDimensions {
+        width: width as usize,
+        height: height as usize,
+    };
+
+    //called for every frame, the write lock is only taken on a change
+    if *state.dimensions.read().unwrap() == updated {
+        return;
+    }
+
+    {
+        let mut current = state.dimensions.write().unwrap();
+
+        if *current == updated {
+            return;
+        }
+
+        *current = updated;
+    }
+
+    info!(width, height, "Capture resolution changed");
+    state.emit(ServerEvent::DimensionsChanged { width, height });
+
+    let sequence = state.next_frame_sequence();
+    let packet = dimensions_packet(sequence, state.timestamp_us(), width, height);
+    state.codecs.send_control(&packet);
+    state.send_control(packet);
+}
@@ -0,0 +1,451 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{state::StreamState, tee::SinkStats, viewers::ViewerInfo};
+
+/// Shortest window the current rates of `PipelineStats::recent_rates` are computed over.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+/// Weight of a new sample in the rolling latency estimates.
+const LATENCY_WEIGHT: f64 = 0.1;
+/// Latest samples of each stage the percentiles are computed over.
+const STAGE_SAMPLES: usize = 512;
+
+/// `[stats]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct StatsConfig {
+    /// seconds between the pipeline summary lines, 0 disables them.
+    pub log_interval_seconds: u64,
+    /// times every stage of every frame for the percentiles of `/stats`.
+    pub stage_timings: bool,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            log_interval_seconds: 10,
+            stage_timings: false,
+        }
+    }
+}
+
+/// # Pipeline Stats
+///
+/// Counters of the capture and encode stages, shared by the compressor (which receives the frames of the device) and the readers of the stats.
+///
+/// Counters only go up, rates are computed from the difference of two snapshots.
+pub struct PipelineStats {
+    frames_captured: AtomicU64,
+    frames_encoded: AtomicU64,
+    frames_dropped: AtomicU64,
+    encode_time_us: AtomicU64,
+    encoded_bytes: AtomicU64,
+    /// the two latest snapshots of the rate window, older first.
+    window: Mutex<(PipelineSnapshot, PipelineSnapshot)>,
+    /// from the capture of a frame to its broadcast.
+    pub pipeline_latency: RollingLatency,
+    /// from the capture of a frame to its display, as reported by the viewers with `/api/ping`.
+    pub viewer_latency: RollingLatency,
+    /// per-frame time of each stage, when `[stats] stage_timings` is on.
+    pub stages: StageTimings,
+}
+
+impl PipelineStats {
+    pub fn new() -> Self {
+        let empty = PipelineSnapshot {
+            taken: Instant::now(),
+            frames_captured: 0,
+            frames_encoded: 0,
+            frames_dropped: 0,
+            encode_time_us: 0,
+            encoded_bytes: 0,
+        };
+
+        Self {
+            frames_captured: AtomicU64::new(0),
+            frames_encoded: AtomicU64::new(0),
+            frames_dropped: AtomicU64::new(0),
+            encode_time_us: AtomicU64::new(0),
+            encoded_bytes: AtomicU64::new(0),
+            window: Mutex::new((empty, empty)),
+            pipeline_latency: RollingLatency::new(),
+            viewer_latency: RollingLatency::new(),
+            stages: StageTimings::new(),
+        }
+    }
+
+    /// A frame was received from the device.
+    pub fn frame_captured(&self) {
+        self.frames_captured.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A captured frame was not encoded (over the fps limit or failed to encode).
+    pub fn frame_dropped(&self) {
+        self.frames_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A frame was encoded to `bytes` in `took`.
+    pub fn frame_encoded(&self, took: Duration, bytes: usize) {
+        self.frames_encoded.fetch_add(1, Ordering::Relaxed);
+        self.encode_time_us
+            .fetch_add(took.as_micros() as u64, Ordering::Relaxed);
+        self.encoded_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> PipelineSnapshot {
+        PipelineSnapshot {
+            taken: Instant::now(),
+            frames_captured: self.frames_captured.load(Ordering::Relaxed),
+            frames_encoded: self.frames_encoded.load(Ordering::Relaxed),
+            frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+            encode_time_us: self.encode_time_us.load(Ordering::Relaxed),
+            encoded_bytes: self.encoded_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// # Recent Rates
+    ///
+    /// Rates over the last `RATE_WINDOW` to `2 * RATE_WINDOW`, the window moves forward as it is read
+    /// so the rates stay current without a task sampling them.
+    pub fn recent_rates(&self) -> PipelineRates {
+        //snapshot under the lock, the window never holds a snapshot newer than the current one
+        let mut window = self.window.lock().unwrap();
+        let current = self.snapshot();
+
+        if current.taken.duration_since(window.1.taken) >= RATE_WINDOW {
+            *window = (window.1, current);
+        }
+
+        current.rates_since(&window.0)
+    }
+}
+
+impl Default for PipelineStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// # Rolling Latency
+///
+/// An exponentially weighted average of latency samples, recent samples weigh the most.
+pub struct RollingLatency {
+    average_ms: Mutex<Option<f64>>,
+}
+
+impl RollingLatency {
+    pub fn new() -> Self {
+        Self {
+            average_ms: Mutex::new(None),
+        }
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let sample = latency.as_secs_f64() * 1000.0;
+        let mut average = self.average_ms.lock().unwrap();
+
+        *average = Some(match *average {
+            Some(current) => current + (sample - current) * LATENCY_WEIGHT,
+            None => sample,
+        });
+    }
+
+    /// The estimate in milliseconds, `None` before the first sample.
+    pub fn average_ms(&self) -> Option<f64> {
+        *self.average_ms.lock().unwrap()
+    }
+}
+
+impl Default for RollingLatency {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A stage every frame goes through.
+#[derive(Clone, Copy)]
+pub enum Stage {
+    /// waiting for the next frame of the device (the duplication api, the camera...).
+    Capture,
+    /// BGRA to the input of the encoder.
+    Convert,
+    Encode,
+    /// framing the packet and sending it to the viewers, the replay buffer and the snapshots.
+    Broadcast,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [Stage::Capture, Stage::Convert, Stage::Encode, Stage::Broadcast];
+}
+
+/// # Stage Timings
+///
+/// The latest `STAGE_SAMPLES` durations of each stage, nothing is recorded until enabled.
+pub struct StageTimings {
+    enabled: AtomicBool,
+    samples: [Mutex<VecDeque<u32>>; 4],
+}
+
+impl StageTimings {
+    pub fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            samples: std::array::from_fn(|_| Mutex::new(VecDeque::with_capacity(STAGE_SAMPLES))),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Records the time a frame spent in the stage.
+    pub fn record(&self, stage: Stage, took: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut samples = self.samples[stage as usize].lock().unwrap();
+
+        if samples.len() >= STAGE_SAMPLES {
+            samples.pop_front();
+        }
+
+        samples.push_back(took.as_micros().min(u32::MAX as u128) as u32);
+    }
+
+    /// Percentiles of every stage, `None` while disabled.
+    pub fn percentiles(&self) -> Option<StagePercentiles> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let [capture, convert, encode, broadcast] = Stage::ALL.map(|stage| {
+            let mut samples: Vec<u32> = self.samples[stage as usize].lock().unwrap().iter().copied().collect();
+            samples.sort_unstable();
+
+            Percentiles::of(&samples)
+        });
+
+        Some(StagePercentiles {
+            capture,
+            convert,
+            encode,
+            broadcast,
+        })
+    }
+}
+
+impl Default for StageTimings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rest API Json of the stage timings in `/stats`.
+#[derive(Serialize)]
+pub struct StagePercentiles {
+    pub capture: Percentiles,
+    pub convert: Percentiles,
+    pub encode: Percentiles,
+    pub broadcast: Percentiles,
+}
+
+/// Percentiles of the samples of a stage, in milliseconds.
+#[derive(Serialize)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Percentiles {
+    /// Percentiles of samples (microseconds) sorted in ascending order.
+    fn of(sorted: &[u32]) -> Self {
+        let at = |percentile: f64| match sorted.len() {
+            0 => 0.0,
+            len => sorted[((len - 1) as f64 * percentile).round() as usize] as f64 / 1000.0,
+        };
+
+        Self {
+            samples: sorted.len(),
+            p50_ms: at(0.5),
+            p90_ms: at(0.9),
+            p99_ms: at(0.99),
+            max_ms: at(1.0),
+        }
+    }
+}
+
+/// The counters of the pipeline at an instant.
+#[derive(Clone, Copy)]
+pub struct PipelineSnapshot {
+    pub taken: Instant,
+    pub frames_captured: u64,
+    pub frames_encoded: u64,
+    pub frames_dropped: u64,
+    pub encode_time_us: u64,
+    pub encoded_bytes: u64,
+}
+
+impl PipelineSnapshot {
+    /// Rates of the pipeline between an earlier snapshot and this one.
+    pub fn rates_since(&self, earlier: &PipelineSnapshot) -> PipelineRates {
+        let secs = self.taken.duration_since(earlier.taken).as_secs_f64();
+        let captured = self.frames_captured - earlier.frames_captured;
+        let encoded = self.frames_encoded - earlier.frames_encoded;
+        let bytes = self.encoded_bytes - earlier.encoded_bytes;
+
+        let per_second = |value: u64| if secs > 0.0 { value as f64 / secs } else { 0.0 };
+        let per_frame = |value: u64| if encoded > 0 { value as f64 / encoded as f64 } else { 0.0 };
+
+        PipelineRates {
+            capture_fps: per_second(captured),
+            encode_fps: per_second(encoded),
+            average_encode_ms: per_frame(self.encode_time_us - earlier.encode_time_us) / 1000.0,
+            average_frame_bytes: per_frame(bytes),
+            bitrate_bps: per_second(bytes) * 8.0,
+        }
+    }
+}
+
+/// Rates of the pipeline over a window of time.
+#[derive(Clone, Copy, Default)]
+pub struct PipelineRates {
+    pub capture_fps: f64,
+    pub encode_fps: f64,
+    pub average_encode_ms: f64,
+    pub average_frame_bytes: f64,
+    pub bitrate_bps: f64,
+}
+
+/// # Runtime Stats
+///
+/// Rest API Json of `/stats`, counters since the start of the server along with the current rates.
+#[derive(Serialize)]
+pub struct RuntimeStats {
+    pub uptime_secs: f64,
+    /// why the capture is not running, none while it is, the counters stop growing while it is down.
+    pub capture_error: Option<String>,
+    /// seconds since the capture stopped, while it is down.
+    pub capture_down_secs: Option<f64>,
+    pub frames_captured: u64,
+    pub frames_encoded: u64,
+    /// frames over the fps limit or that failed to encode.
+    pub frames_dropped: u64,
+    /// encoded frames per second over the last seconds.
+    pub fps: f64,
+    /// bits per second of encoded frames over the last seconds.
+    pub bitrate_bps: f64,
+    pub bytes_sent: u64,
+    pub viewers: usize,
+    /// rolling estimate of the time from the capture of a frame to its broadcast, in milliseconds.
+    pub pipeline_latency_ms: Option<f64>,
+    /// rolling estimate of the time from the capture of a frame to its display by the viewers, in milliseconds.
+    pub viewer_latency_ms: Option<f64>,
+    /// percentiles of the time spent in each stage, only with `[stats] stage_timings`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stages: Option<StagePercentiles>,
+    /// frames missed by each connected viewer, next to `frames_dropped` of the encoder.
+    pub viewer_drops: Vec<ViewerDrops>,
+    /// the queues of the sinks getting every frame (the recordings) and the frames they dropped.
+    pub sinks: Vec<SinkStats>,
+}
+
+/// # Viewer Drops
+///
+/// The frames a connected viewer did not get, `frames_lagged` grows with a connection that cannot keep up
+/// while `frames_skipped` only follows the preset and bandwidth cap it asked for.
+#[derive(Serialize)]
+pub struct ViewerDrops {
+    pub id: u64,
+    pub ip: String,
+    pub frames_lagged: u64,
+    pub frames_skipped: u64,
+}
+
+impl From<ViewerInfo> for ViewerDrops {
+    fn from(viewer: ViewerInfo) -> Self {
+        Self {
+            id: viewer.id,
+            ip: viewer.ip,
+            frames_lagged: viewer.frames_lagged,
+            frames_skipped: viewer.frames_skipped,
+        }
+    }
+}
+
+impl RuntimeStats {
+    pub fn current(state: &StreamState) -> Self {
+        let totals = state.pipeline.snapshot();
+        let rates = state.pipeline.recent_rates();
+
+        Self {
+            uptime_secs: state.stats.uptime().as_secs_f64(),
+            capture_error: state.capture_error(),
+            capture_down_secs: state.capture_down_for().map(|down| down.as_secs_f64()),
+            frames_captured: totals.frames_captured,
+            frames_encoded: totals.frames_encoded,
+            frames_dropped: totals.frames_dropped,
+            fps: rates.encode_fps,
+            bitrate_bps: rates.bitrate_bps,
+            bytes_sent: state.stats.bytes_sent(),
+            viewers: state.stats.viewers(),
+            pipeline_latency_ms: state.pipeline.pipeline_latency.average_ms(),
+            viewer_latency_ms: state.pipeline.viewer_latency.average_ms(),
+            stages: state.pipeline.stages.percentiles(),
+            viewer_drops: state.viewers.list().into_iter().map(ViewerDrops::from).collect(),
+            sinks: state.sinks.stats(),
+        }
+    }
+}
+
+/// # Spawn Stats Logger
+///
+/// Spawns a task logging a summary of the pipeline every interval: capture and encode fps, average encode time and frame size,
+/// the packets queued in the frame broadcast and its subscribers.
+pub fn spawn_stats_logger(config: StatsConfig, state: Arc<StreamState>) {
+    if config.log_interval_seconds == 0 {
+        return;
+    }
+
+    let interval = Duration::from_secs(config.log_interval_seconds);
+
+    tokio::spawn(async move {
+        let mut previous = state.pipeline.snapshot();
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = state.shutting_down() => break,
+            }
+
+            let current = state.pipeline.snapshot();
+            let rates = current.rates_since(&previous);
+            previous = current;
+
+            tracing::info!(
+                capture_fps = format_args!("{:.1}", rates.capture_fps),
+                encode_fps = format_args!("{:.1}", rates.encode_fps),
+                encode_ms = format_args!("{:.2}", rates.average_encode_ms),
+                frame_kb = format_args!("{:.1}", rates.average_frame_bytes / 1024.0),
+                queue_depth = state.frames.len(),
+                subscribers = state.frames.receiver_count(),
+                "Pipeline stats"
+            );
+        }
+    });
+}
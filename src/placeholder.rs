@@ -0,0 +1,170 @@
+use std::{sync::Arc, time::Duration};
+
+use image::{RgbaImage, imageops};
+use serde::Deserialize;
+use tracing::{Instrument, info_span};
+
+use crate::{
+    captures::SerializedDimensions,
+    encoder::{EncodeSettings, FrameEncoder},
+    state::{RawFrame, StreamState},
+};
+
+/// How often the pause state is checked while the stream is live.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Background of the placeholder, BGRA.
+const BACKGROUND: [u8; 4] = [32, 32, 32, 255];
+/// Color of the pause symbol drawn without an image, BGRA.
+const SYMBOL: [u8; 4] = [200, 200, 200, 255];
+
+/// `[pause]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct PauseConfig {
+    /// broadcast a placeholder frame while paused instead of leaving the viewers on the last frame.
+    pub placeholder: bool,
+    /// image shown as the placeholder (png, jpeg...), scaled to fit the stream, a pause symbol when not set.
+    pub image: Option<String>,
+    /// placeholder frames sent per second, new viewers get one within this time.
+    pub fps: f64,
+}
+
+impl Default for PauseConfig {
+    fn default() -> Self {
+        Self {
+            placeholder: true,
+            image: None,
+            fps: 1.0,
+        }
+    }
+}
+
+/// # Spawn Pause Placeholder
+///
+/// Spawns a task broadcasting the placeholder frame at `fps` while the stream is paused (api, control channel, hotkey or tray),
+/// it also replaces the latest frame so the snapshots stop showing the last captured one.
+///
+/// The placeholder is encoded once per resolution, a missing or broken image fails the startup.
+pub fn spawn_pause_placeholder(config: PauseConfig, state: Arc<StreamState>) -> Result<(), String> {
+    if !config.placeholder {
+        return Ok(());
+    }
+
+    let image = match &config.image {
+        Some(path) => Some(
+            image::open(path)
+                .map_err(|e| format!("Failed to read the pause image {path}: {e}"))?
+                .to_rgba8(),
+        ),
+        None => None,
+    };
+    let image = Arc::new(image);
+    let interval = Duration::from_secs_f64(1.0 / config.fps.clamp(0.1, 30.0));
+
+    tokio::spawn(
+        async move {
+            let mut rendered: Option<(SerializedDimensions, Arc<RawFrame>, Arc<Vec<u8>>)> = None;
+
+            loop {
+                let paused = state.control.is_paused();
+
+                if paused {
+                    let dimensions = state.dimensions();
+
+                    if rendered.as_ref().is_none_or(|(rendered_at, ..)| *rendered_at != dimensions) {
+                        let image = image.clone();
+                        let encoder = state.encoder.clone();
+                        let settings = state.control.encode_settings();
+                        let timestamp_us = state.timestamp_us();
+
+                        rendered = tokio::task::spawn_blocking(move || {
+                            render(image.as_ref().as_ref(), dimensions, encoder.as_ref(), settings, timestamp_us)
+                        })
+                        .await
+                        .ok()
+                        .flatten()
+                        .map(|(frame, encoded)| (dimensions, Arc::new(frame), Arc::new(encoded)));
+                    }
+
+                    if let Some((_, frame, encoded)) = &rendered {
+                        state.send_still(frame.clone(), encoded.clone());
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(if paused { interval } else { POLL_INTERVAL }) => {}
+                    _ = state.shutting_down() => break,
+                }
+            }
+        }
+        .instrument(info_span!("placeholder")),
+    );
+
+    Ok(())
+}
+
+/// Draws the placeholder at the dimensions and encodes it, `None` when the encoding failed.
+fn render(
+    image: Option<&RgbaImage>,
+    dimensions: SerializedDimensions,
+    encoder: &dyn FrameEncoder,
+    settings: EncodeSettings,
+    timestamp_us: u64,
+) -> Option<(RawFrame, Vec<u8>)> {
+    let (width, height) = (dimensions.width as u32, dimensions.height as u32);
+    let mut data = BACKGROUND.repeat(dimensions.width * dimensions.height);
+
+    match image {
+        Some(image) => draw_image(&mut data, width, height, image),
+        None => draw_pause_symbol(&mut data, width, height),
+    }
+
+    let frame = RawFrame {
+        data,
+        width,
+        height,
+        timestamp_us,
+    };
+    let encoded = encoder.encode(&frame, settings).ok()?;
+
+    Some((frame, encoded))
+}
+
+/// Scales the image to fit the frame, centered, transparent pixels show the background.
+fn draw_image(data: &mut [u8], width: u32, height: u32, image: &RgbaImage) {
+    let scale = (width as f32 / image.width() as f32).min(height as f32 / image.height() as f32);
+    let fitted_width = ((image.width() as f32 * scale) as u32).clamp(1, width);
+    let fitted_height = ((image.height() as f32 * scale) as u32).clamp(1, height);
+    let fitted = imageops::resize(image, fitted_width, fitted_height, imageops::FilterType::Triangle);
+
+    let left = (width - fitted_width) / 2;
+    let top = (height - fitted_height) / 2;
+
+    for (x, y, pixel) in fitted.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        let offset = (((top + y) * width + left + x) * 4) as usize;
+        let target = &mut data[offset..offset + 4];
+
+        //blends over the background, BGRA
+        for (channel, value) in [(0, b), (1, g), (2, r)] {
+            target[channel] = ((value as u32 * a as u32 + target[channel] as u32 * (255 - a as u32)) / 255) as u8;
+        }
+    }
+}
+
+/// Two bars in the middle of the frame.
+fn draw_pause_symbol(data: &mut [u8], width: u32, height: u32) {
+    let bar_height = height / 4;
+    let bar_width = (bar_height / 3).max(1);
+    let top = (height - bar_height) / 2;
+    let center = width / 2;
+
+    for left in [center.saturating_sub(bar_width * 3 / 2), center + bar_width / 2] {
+        for y in top..top + bar_height {
+            for x in left..(left + bar_width).min(width) {
+                let offset = ((y * width + x) * 4) as usize;
+                data[offset..offset + 4].copy_from_slice(&SYMBOL);
+            }
+        }
+    }
+}
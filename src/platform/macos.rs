@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use futures::{FutureExt, future::BoxFuture};
+use screencapturekit::{
+    output::{CMSampleBuffer, LockTrait},
+    shareable_content::SCShareableContent,
+    stream::{
+        SCStream,
+        configuration::{SCStreamConfiguration, pixel_format::PixelFormat},
+        content_filter::SCContentFilter,
+        delegate_trait::SCStreamDelegateTrait,
+        output_trait::SCStreamOutputTrait,
+        output_type::SCStreamOutputType,
+    },
+};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tracing::info;
+
+use crate::{
+    captures::CaptureType,
+    devices::{CameraInfo, MonitorInfo},
+    error::{Result, ShareScreenError},
+    source::CaptureSource,
+};
+
+/// # Open
+///
+/// Captures a display with ScreenCaptureKit, the first run asks for the Screen Recording permission
+/// (System Settings > Privacy & Security) and fails until it is granted.
+///
+/// Cameras are not supported on this backend yet (`NoCamera`).
+pub fn open(device: CaptureType) -> Result<Arc<dyn CaptureSource>> {
+    match device {
+        CaptureType::Monitor(m) => Ok(Arc::new(ScreenCaptureSource::open(m)?)),
+        CaptureType::Camera(_) => Err(ShareScreenError::NoCamera),
+        CaptureType::TestPattern { .. } => Err(ShareScreenError::Activation {
+            device: device.to_string(),
+            reason: "the test pattern is not a ScreenCaptureKit device".to_string(),
+        }),
+    }
+}
+
+/// Number of displays connected, 0 without the Screen Recording permission.
+pub fn monitor_count() -> i32 {
+    SCShareableContent::get().map_or(0, |content| content.displays().len() as i32)
+}
+
+/// # List Monitors
+///
+/// Enumerates the displays ScreenCaptureKit can capture, the first one is the main display.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>> {
+    let content = SCShareableContent::get().map_err(capture_error)?;
+
+    Ok(content
+        .displays()
+        .into_iter()
+        .enumerate()
+        .map(|(index, display)| MonitorInfo {
+            index,
+            name: format!("display {}", display.display_id()),
+            model: None,
+            width: display.width(),
+            height: display.height(),
+            primary: index == 0,
+        })
+        .collect())
+}
+
+/// Cameras are not enumerated on macOS.
+pub fn list_cameras() -> Result<Vec<CameraInfo>> {
+    Ok(Vec::new())
+}
+
+fn capture_error(e: impl std::fmt::Debug) -> ShareScreenError {
+    ShareScreenError::Capture(format!("{e:?}"))
+}
+
+/// # Screen Capture Source
+///
+/// A display streamed by ScreenCaptureKit, which delivers the frames on its own dispatch queue when the screen changes.
+pub struct ScreenCaptureSource {
+    width: u32,
+    height: u32,
+    stream: Mutex<SCStream>,
+    frames: Mutex<mpsc::Receiver<Vec<u8>>>,
+    stopped: Mutex<Option<oneshot::Receiver<String>>>,
+}
+
+impl ScreenCaptureSource {
+    fn open(index: i32) -> Result<Self> {
+        let content = SCShareableContent::get().map_err(|e| ShareScreenError::Activation {
+            device: format!("monitor {}", index + 1),
+            reason: format!("{e:?}, is the Screen Recording permission granted?"),
+        })?;
+        let displays = content.displays();
+        let count = displays.len() as i32;
+
+        let display = usize::try_from(index)
+            .ok()
+            .and_then(|index| displays.into_iter().nth(index))
+            .ok_or(ShareScreenError::MonitorOutOfRange { index, count })?;
+
+        let (width, height) = (display.width(), display.height());
+        let configuration = SCStreamConfiguration::new()
+            .set_width(width)
+            .and_then(|configuration| configuration.set_height(height))
+            .and_then(|configuration| configuration.set_pixel_format(PixelFormat::BGRA))
+            .and_then(|configuration| configuration.set_shows_cursor(true))
+            .map_err(capture_error)?;
+
+        let filter = SCContentFilter::new().with_display_excluding_windows(&display, &[]);
+
+        let (frames, receiver) = mpsc::channel(2);
+        let (stopped, stopped_receiver) = oneshot::channel();
+
+        let mut stream = SCStream::new_with_delegate(
+            &filter,
+            &configuration,
+            StopDelegate {
+                stopped: std::sync::Mutex::new(Some(stopped)),
+            },
+        );
+        stream.add_output_handler(FrameOutput { frames }, SCStreamOutputType::Screen);
+
+        info!(display = display.display_id(), width, height, "Capturing display");
+
+        Ok(Self {
+            width,
+            height,
+            stream: Mutex::new(stream),
+            frames: Mutex::new(receiver),
+            stopped: Mutex::new(Some(stopped_receiver)),
+        })
+    }
+}
+
+impl CaptureSource for ScreenCaptureSource {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        Ok((self.width, self.height))
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        async move { self.frames.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Some(stopped) = self.stopped.lock().await.take() else {
+                return Err(ShareScreenError::Capture("the display is already captured".to_string()));
+            };
+
+            self.stream.lock().await.start_capture().map_err(capture_error)?;
+
+            //frames arrive on the queue of ScreenCaptureKit until the stream stops (display unplugged, permission revoked)
+            let reason = stopped.await.unwrap_or_else(|_| "the stream was dropped".to_string());
+
+            Err(ShareScreenError::Capture(reason))
+        }
+        .boxed()
+    }
+}
+
+impl Drop for ScreenCaptureSource {
+    fn drop(&mut self) {
+        let _ = self.stream.get_mut().stop_capture();
+    }
+}
+
+/// Copies the frames of the stream into the channel of the capture.
+struct FrameOutput {
+    frames: mpsc::Sender<Vec<u8>>,
+}
+
+impl SCStreamOutputTrait for FrameOutput {
+    fn did_output_sample_buffer(&self, sample: CMSampleBuffer, _of_type: SCStreamOutputType) {
+        //idle and blank samples carry no image
+        let Ok(pixels) = sample.get_pixel_buffer() else {
+            return;
+        };
+
+        let (width, height, stride) = (
+            pixels.get_width() as usize,
+            pixels.get_height() as usize,
+            pixels.get_bytes_per_row() as usize,
+        );
+        let Ok(guard) = pixels.lock() else {
+            return;
+        };
+
+        let bytes = guard.as_slice();
+        let row = width * 4;
+        let mut frame = Vec::with_capacity(row * height);
+
+        for y in 0..height {
+            match bytes.get(y * stride..y * stride + row) {
+                Some(line) => frame.extend_from_slice(line),
+                None => frame.resize(frame.len() + row, 0),
+            }
+        }
+
+        //the compressor is behind, this frame is skipped
+        let _ = self.frames.try_send(frame);
+    }
+}
+
+/// Ends `run` with the reason the stream stopped.
+struct StopDelegate {
+    stopped: std::sync::Mutex<Option<oneshot::Sender<String>>>,
+}
+
+impl SCStreamDelegateTrait for StopDelegate {
+    fn did_stop_with_error(&self, error: screencapturekit::error::SCError) {
+        if let Some(stopped) = self.stopped.lock().unwrap().take() {
+            let _ = stopped.send(format!("{error:?}"));
+        }
+    }
+}
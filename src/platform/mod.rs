@@ -0,0 +1,122 @@
+//! Capture backends of the cameras and monitors, one per platform with the same functions:
+//! `open` a `CaptureType`, `monitor_count`, `list_monitors` and `list_cameras`, along with the thread priority of the platform.
+
+#[cfg(target_os = "macos")]
+pub mod macos;
+#[cfg(windows)]
+pub mod windows;
+#[cfg(target_os = "linux")]
+pub mod pipewire;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+use crate::{captures::CaptureType, error::Result, source::CaptureSource};
+
+#[cfg(windows)]
+pub use self::windows::{list_cameras, list_monitors, monitor_count, open};
+#[cfg(target_os = "macos")]
+pub use self::macos::{list_cameras, list_monitors, monitor_count, open};
+#[cfg(target_os = "linux")]
+pub use self::x11::{list_cameras, list_monitors, monitor_count};
+
+/// # Enable DPI Awareness
+///
+/// Makes the process aware of the scaling of every monitor on windows, so the monitors and their frames have their physical size
+/// instead of the logical size of a display scaled to 125% or 150%. The other platforms report physical sizes already.
+pub fn enable_dpi_awareness() {
+    #[cfg(windows)]
+    {
+        use ::windows::Win32::UI::HiDpi::{DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2, SetProcessDpiAwarenessContext};
+
+        //fails once the awareness was set, by the manifest of an application embedding the server for one
+        if let Err(e) = unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) } {
+            tracing::debug!(error = %e, "The DPI awareness of the process was left as it is");
+        }
+    }
+}
+
+/// # Lower Thread Priority
+///
+/// Runs the calling thread below the normal priority, the scheduler favors the other applications when the cpu is busy.
+pub fn lower_thread_priority() -> std::io::Result<()> {
+    #[cfg(windows)]
+    {
+        use ::windows::Win32::System::Threading::{GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL};
+
+        unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL) }.map_err(std::io::Error::other)
+    }
+
+    //the nice value of a linux thread only applies to the thread
+    #[cfg(target_os = "linux")]
+    {
+        match unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, 10) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        match unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_UTILITY, 0) } {
+            0 => Ok(()),
+            code => Err(std::io::Error::from_raw_os_error(code)),
+        }
+    }
+
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "thread priorities are not supported on this platform"))
+    }
+}
+
+/// # Free Space
+///
+/// Bytes left to the process on the volume of the path, which has to exist.
+pub fn free_space(path: &std::path::Path) -> std::io::Result<u64> {
+    #[cfg(windows)]
+    {
+        use ::windows::{Win32::Storage::FileSystem::GetDiskFreeSpaceExW, core::HSTRING};
+
+        let mut available = 0u64;
+
+        unsafe { GetDiskFreeSpaceExW(&HSTRING::from(path), Some(&mut available), None, None) }.map_err(std::io::Error::other)?;
+
+        Ok(available)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+        match unsafe { libc::statvfs(path.as_ptr(), &mut stats) } {
+            //the blocks left to unprivileged users, the root reserve excluded
+            0 => Ok(u64::from(stats.f_bavail) * u64::from(stats.f_frsize)),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the free space is not known on this platform"))
+    }
+}
+
+/// # Open
+///
+/// Monitors of a Wayland session are shared through the ScreenCast portal, the X server is captured directly otherwise.
+#[cfg(target_os = "linux")]
+pub fn open(device: CaptureType) -> Result<Arc<dyn CaptureSource>> {
+    if matches!(device, CaptureType::Monitor(_)) && pipewire::is_wayland_session() {
+        return pipewire::open(device);
+    }
+
+    x11::open(device)
+}
@@ -0,0 +1,377 @@
+use std::{
+    io::Cursor,
+    os::fd::OwnedFd,
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use ashpd::desktop::{
+    PersistMode,
+    screencast::{CursorMode, Screencast, SourceType},
+};
+use futures::{FutureExt, future::BoxFuture};
+use pipewire::{
+    self as pw,
+    spa::{
+        self,
+        param::{
+            ParamType,
+            format::{FormatProperties, MediaSubtype, MediaType},
+            format_utils::parse_format,
+            video::{VideoFormat, VideoInfoRaw},
+        },
+        pod::{Pod, Value, serialize::PodSerializer},
+        utils::{Direction, Fraction, Rectangle, SpaTypes},
+    },
+    stream::{Stream, StreamFlags, StreamState},
+};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::{
+    captures::CaptureType,
+    error::{Result, ShareScreenError},
+    source::CaptureSource,
+};
+
+/// File keeping the restore token of the portal, the monitor chosen once is shared again without the dialog.
+pub const RESTORE_TOKEN_FILE: &str = "share-screen-portal.token";
+
+/// Whether the desktop is a Wayland session, where only the ScreenCast portal can capture the screen.
+pub fn is_wayland_session() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+        || std::env::var("XDG_SESSION_TYPE").is_ok_and(|session| session.eq_ignore_ascii_case("wayland"))
+}
+
+/// # Open
+///
+/// Asks the ScreenCast portal (GNOME, KDE...) for a monitor and captures it through PipeWire,
+/// the monitor is picked in the dialog of the portal instead of by its index.
+///
+/// Note: `Blocks until the share is accepted in the dialog, the choice is kept for the next runs in RESTORE_TOKEN_FILE.`
+pub fn open(device: CaptureType) -> Result<Arc<dyn CaptureSource>> {
+    match device {
+        CaptureType::Monitor(_) => Ok(Arc::new(PipeWireSource::open(device)?)),
+        _ => Err(ShareScreenError::Activation {
+            device: device.to_string(),
+            reason: "only monitors are captured through the ScreenCast portal".to_string(),
+        }),
+    }
+}
+
+/// The stream of the portal to connect to.
+struct PortalStream {
+    /// remote of the PipeWire instance of the session, restricted to the stream.
+    fd: OwnedFd,
+    node_id: u32,
+}
+
+/// # PipeWire Source
+///
+/// A monitor shared through the ScreenCast portal, the PipeWire stream runs its main loop on its own thread.
+pub struct PipeWireSource {
+    dimensions: Arc<StdMutex<(u32, u32)>>,
+    frames: Mutex<mpsc::Receiver<Vec<u8>>>,
+    finished: Mutex<Option<oneshot::Receiver<Result<()>>>>,
+}
+
+impl PipeWireSource {
+    fn open(device: CaptureType) -> Result<Self> {
+        let (frames, receiver) = mpsc::channel(2);
+        let (opened, opened_receiver) = std::sync::mpsc::channel();
+        let (finished, finished_receiver) = oneshot::channel();
+        let dimensions = Arc::new(StdMutex::new((0, 0)));
+
+        let negotiated = dimensions.clone();
+        std::thread::Builder::new()
+            .name("pipewire".to_string())
+            .spawn(move || {
+                let _ = finished.send(share(frames, opened, negotiated));
+            })?;
+
+        opened_receiver
+            .recv()
+            .map_err(|_| ShareScreenError::Activation {
+                device: device.to_string(),
+                reason: "the screen cast ended before its first frame".to_string(),
+            })?
+            .map_err(|reason| ShareScreenError::Activation {
+                device: device.to_string(),
+                reason,
+            })?;
+
+        Ok(Self {
+            dimensions,
+            frames: Mutex::new(receiver),
+            finished: Mutex::new(Some(finished_receiver)),
+        })
+    }
+}
+
+impl CaptureSource for PipeWireSource {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        Ok(*self.dimensions.lock().unwrap())
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        async move { self.frames.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Some(finished) = self.finished.lock().await.take() else {
+                return Err(ShareScreenError::Capture("the screen cast is already running".to_string()));
+            };
+
+            finished
+                .await
+                .unwrap_or_else(|_| Err(ShareScreenError::Capture("the screen cast stopped".to_string())))
+        }
+        .boxed()
+    }
+}
+
+/// Goes through the portal and receives the frames of the stream until it ends or the capture is dropped.
+///
+/// `opened` gets the reason the share could not start, or `Ok` once the format of the stream is known.
+fn share(
+    frames: mpsc::Sender<Vec<u8>>,
+    opened: std::sync::mpsc::Sender<std::result::Result<(), String>>,
+    dimensions: Arc<StdMutex<(u32, u32)>>,
+) -> Result<()> {
+    //the portal is a dbus api, its session lives as long as this connection
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+
+    let (_screencast, session, stream) = match runtime.block_on(start_portal()) {
+        Ok(portal) => portal,
+        Err(e) => {
+            let reason = e.to_string();
+            let _ = opened.send(Err(reason.clone()));
+
+            return Err(ShareScreenError::Capture(reason));
+        }
+    };
+
+    info!(node = stream.node_id, "Screen cast started");
+
+    let result = receive(stream, frames, opened, dimensions).map_err(|e| ShareScreenError::Capture(e.to_string()));
+    let _ = runtime.block_on(session.close());
+
+    result
+}
+
+/// The portal dialog, skipped when a restore token of an earlier run is still valid.
+async fn start_portal() -> ashpd::Result<(
+    Screencast<'static>,
+    ashpd::desktop::Session<'static, Screencast<'static>>,
+    PortalStream,
+)> {
+    let screencast = Screencast::new().await?;
+    let session = screencast.create_session().await?;
+    let token = std::fs::read_to_string(RESTORE_TOKEN_FILE).ok();
+
+    screencast
+        .select_sources(
+            &session,
+            CursorMode::Embedded,
+            SourceType::Monitor.into(),
+            false,
+            token.as_deref().map(str::trim),
+            PersistMode::ExplicitlyRevoked,
+        )
+        .await?;
+
+    let streams = screencast.start(&session, None).await?.response()?;
+
+    if let Some(token) = streams.restore_token() {
+        if let Err(e) = std::fs::write(RESTORE_TOKEN_FILE, token) {
+            warn!(error = %e, file = %Path::new(RESTORE_TOKEN_FILE).display(), "Failed to save the portal restore token");
+        }
+    }
+
+    let node_id = streams
+        .streams()
+        .first()
+        .map(|stream| stream.pipe_wire_node_id())
+        .ok_or(ashpd::Error::NoResponse)?;
+    let fd = screencast.open_pipe_wire_remote(&session).await?;
+
+    Ok((screencast, session, PortalStream { fd, node_id }))
+}
+
+/// State of the stream callbacks.
+struct StreamData {
+    format: VideoInfoRaw,
+    frames: mpsc::Sender<Vec<u8>>,
+    opened: Option<std::sync::mpsc::Sender<std::result::Result<(), String>>>,
+    dimensions: Arc<StdMutex<(u32, u32)>>,
+}
+
+/// Runs the PipeWire main loop on the stream of the portal.
+fn receive(
+    portal: PortalStream,
+    frames: mpsc::Sender<Vec<u8>>,
+    opened: std::sync::mpsc::Sender<std::result::Result<(), String>>,
+    dimensions: Arc<StdMutex<(u32, u32)>>,
+) -> std::result::Result<(), pw::Error> {
+    pw::init();
+
+    let main_loop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&main_loop)?;
+    let core = context.connect_fd(portal.fd, None)?;
+
+    let stream = Stream::new(
+        &core,
+        "share-screen",
+        pw::properties::properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+
+    let state_loop = main_loop.clone();
+    let process_loop = main_loop.clone();
+
+    let _listener = stream
+        .add_local_listener_with_user_data(StreamData {
+            format: VideoInfoRaw::new(),
+            frames,
+            opened: Some(opened),
+            dimensions,
+        })
+        .state_changed(move |_, data, _, state| match state {
+            StreamState::Error(reason) => {
+                if let Some(opened) = data.opened.take() {
+                    let _ = opened.send(Err(reason));
+                }
+                state_loop.quit();
+            }
+            StreamState::Unconnected => state_loop.quit(),
+            _ => {}
+        })
+        .param_changed(|_, data, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+
+            if id != ParamType::Format.as_raw() {
+                return;
+            }
+
+            let Ok((MediaType::Video, MediaSubtype::Raw)) = parse_format(param) else {
+                return;
+            };
+
+            if data.format.parse(param).is_err() {
+                return;
+            }
+
+            let size = data.format.size();
+            *data.dimensions.lock().unwrap() = (size.width, size.height);
+
+            if let Some(opened) = data.opened.take() {
+                let _ = opened.send(Ok(()));
+            }
+        })
+        .process(move |stream, data| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+
+            let Rectangle { width, height } = data.format.size();
+            let Some(plane) = buffer.datas_mut().first_mut() else {
+                return;
+            };
+
+            let (offset, stride) = (plane.chunk().offset() as usize, plane.chunk().stride() as usize);
+            let Some(bytes) = plane.data() else {
+                return;
+            };
+
+            let frame = bgra(&bytes[offset.min(bytes.len())..], width as usize, height as usize, stride);
+
+            match data.frames.try_send(frame) {
+                //the compressor is behind, this frame is skipped
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => {}
+                Err(mpsc::error::TrySendError::Closed(_)) => process_loop.quit(),
+            }
+        })
+        .register()?;
+
+    let format = format_params();
+    let mut params = [Pod::from_bytes(&format).expect("the format pod is serialized above")];
+
+    stream.connect(
+        Direction::Input,
+        Some(portal.node_id),
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    main_loop.run();
+
+    Ok(())
+}
+
+/// The formats accepted from the compositor: BGRx or BGRA, any size and frame rate.
+fn format_params() -> Vec<u8> {
+    let object = spa::pod::object!(
+        SpaTypes::ObjectParamFormat,
+        ParamType::EnumFormat,
+        spa::pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+        spa::pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+        spa::pod::property!(
+            FormatProperties::VideoFormat,
+            Choice,
+            Enum,
+            Id,
+            VideoFormat::BGRx,
+            VideoFormat::BGRx,
+            VideoFormat::BGRA
+        ),
+        spa::pod::property!(
+            FormatProperties::VideoSize,
+            Choice,
+            Range,
+            Rectangle,
+            Rectangle { width: 1920, height: 1080 },
+            Rectangle { width: 1, height: 1 },
+            Rectangle { width: 8192, height: 8192 }
+        ),
+        spa::pod::property!(
+            FormatProperties::VideoFramerate,
+            Choice,
+            Range,
+            Fraction,
+            Fraction { num: 30, denom: 1 },
+            Fraction { num: 0, denom: 1 },
+            Fraction { num: 240, denom: 1 }
+        ),
+    );
+
+    PodSerializer::serialize(Cursor::new(Vec::new()), &Value::Object(object))
+        .expect("the format pod is valid")
+        .0
+        .into_inner()
+}
+
+/// Copies the rows of a BGRx buffer without the padding of the stride, the padding byte becomes an opaque alpha.
+fn bgra(bytes: &[u8], width: usize, height: usize, stride: usize) -> Vec<u8> {
+    let row = width * 4;
+    let stride = stride.max(row);
+    let mut frame = Vec::with_capacity(row * height);
+
+    for y in 0..height {
+        match bytes.get(y * stride..y * stride + row) {
+            Some(line) => frame.extend_from_slice(line),
+            None => frame.resize(frame.len() + row, 0),
+        }
+    }
+
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+
+    frame
+}
@@ -0,0 +1,543 @@
+use std::{cell::RefCell, collections::HashMap, marker::PhantomData, sync::Arc};
+
+use futures::{FutureExt, future::BoxFuture};
+use win_video::{
+    devices::{Cameras, Monitor},
+    i_capture::ICapture,
+};
+use windows::{
+    Win32::{
+        Devices::Display::{
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+            DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO,
+            DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_TARGET_DEVICE_NAME, DisplayConfigGetDeviceInfo,
+            GetDisplayConfigBufferSizes, QDC_ONLY_ACTIVE_PATHS, QueryDisplayConfig,
+        },
+        Foundation::{E_POINTER, HWND, LPARAM, RECT, RPC_E_CHANGED_MODE},
+        Graphics::{
+            Dxgi::{
+                Common::DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020, CreateDXGIFactory1, DXGI_OUTPUT_DESC,
+                IDXGIFactory1, IDXGIOutput, IDXGIOutput6,
+            },
+            Gdi::{GetMonitorInfoW, MONITORINFO, MONITORINFOF_PRIMARY},
+        },
+        Media::MediaFoundation::{
+            IMFActivate, IMFMediaSource, MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME,
+            MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE, MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+            MF_MT_FRAME_SIZE, MF_MT_SUBTYPE, MFCreateAttributes, MFEnumDeviceSources,
+        },
+        System::{
+            Com::{COINIT_MULTITHREADED, CoInitializeEx, CoTaskMemFree, CoUninitialize},
+            Console::GetConsoleWindow,
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetWindowRect, GetWindowTextW, IsIconic, IsWindowVisible, SetWindowDisplayAffinity, WDA_EXCLUDEFROMCAPTURE, WDA_NONE,
+        },
+    },
+    core::{BOOL, GUID, Interface, PWSTR},
+};
+
+use crate::{
+    captures::CaptureType,
+    devices::{CameraFormat, CameraInfo, MonitorInfo},
+    error::{Result, ShareScreenError},
+    hdr::PixelFormat,
+    source::CaptureSource,
+};
+
+/// # Com Guard
+///
+/// COM initialized on the current thread (multithreaded apartment) for as long as the guard lives, uninitialized on drop.
+///
+/// Note: `S_FALSE (already initialized on this thread) is a success and balanced the same. A thread already in a single threaded
+/// apartment (RPC_E_CHANGED_MODE, like the thread of a window) keeps it, COM is usable there and the guard leaves it initialized.`
+pub struct ComGuard {
+    //whether the guard has a CoInitializeEx to balance
+    initialized: bool,
+    //CoUninitialize has to run on the thread that initialized
+    _thread: PhantomData<*const ()>,
+}
+
+impl ComGuard {
+    pub fn new() -> Result<Self> {
+        let result = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) };
+
+        if result == RPC_E_CHANGED_MODE {
+            return Ok(Self {
+                initialized: false,
+                _thread: PhantomData,
+            });
+        }
+
+        result.ok().map_err(ShareScreenError::ComInit)?;
+
+        Ok(Self {
+            initialized: true,
+            _thread: PhantomData,
+        })
+    }
+
+    /// # Ensure
+    ///
+    /// Initializes COM once on the calling thread, the guard is kept until the thread exits.
+    /// Used by the code running on threads it does not own (tokio workers, blocking tasks).
+    pub fn ensure() -> Result<()> {
+        thread_local! {
+            static GUARD: RefCell<Option<ComGuard>> = const { RefCell::new(None) };
+        }
+
+        GUARD.with(|guard| {
+            let mut guard = guard.borrow_mut();
+
+            if guard.is_none() {
+                *guard = Some(ComGuard::new()?);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl Drop for ComGuard {
+    fn drop(&mut self) {
+        if self.initialized {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+/// # Open
+///
+/// Activates a camera or a monitor with the win_video library.
+///
+/// The function also has the chance of returning an err for the following reasons:
+/// CoInitializeEx failed (`ComInit`),
+/// No video devices (`NoCamera`)
+/// Camera index out of range (`CameraOutOfRange`)
+/// Monitor index out of range (`MonitorOutOfRange`)
+/// And other window errors while activating (`Activation`).
+///
+/// For monitors the displays are re-enumerated on every call, so it also reacquires a monitor that was unplugged.
+pub fn open(device: CaptureType) -> Result<Arc<dyn CaptureSource>> {
+    let capture: Arc<dyn ICapture<CaptureOutput = Vec<u8>>>;
+    let mut format = PixelFormat::Bgra8;
+    let activation_error = |e: &dyn std::fmt::Display| ShareScreenError::Activation {
+        device: device.to_string(),
+        reason: e.to_string(),
+    };
+
+    match device {
+        CaptureType::Camera(c) => unsafe {
+            ComGuard::ensure()?;
+
+            let video_devices = Cameras::new().map_err(|e| activation_error(&e))?;
+            let count = video_devices.devices.len();
+
+            if count == 0 {
+                return Err(ShareScreenError::NoCamera);
+            }
+
+            if c >= count {
+                return Err(ShareScreenError::CameraOutOfRange { index: c, count });
+            }
+
+            tracing::info!("Activating device (this may take a second)...");
+
+            capture = video_devices
+                .activate_device(
+                    video_devices.devices[c],
+                    Some(win_video::devices::camera::Output::RGB32),
+                )
+                .map_err(|e| activation_error(&e))?;
+        },
+        CaptureType::Monitor(m) => {
+            let count = monitor_count();
+
+            if m < 0 || m >= count {
+                return Err(ShareScreenError::MonitorOutOfRange { index: m, count });
+            }
+
+            capture = unsafe { Monitor::from_monitor(m as u32) }.map_err(|e| activation_error(&e))?;
+
+            //the desktop of a monitor in HDR mode is composed in HDR10
+            if monitor_is_hdr(m as usize).unwrap_or(false) {
+                tracing::info!(monitor = m + 1, "The monitor is in HDR mode, its frames are tone mapped to SDR");
+                format = PixelFormat::Hdr10;
+            }
+        }
+        CaptureType::TestPattern { .. } => {
+            return Err(activation_error(&"the test pattern is not a win_video device"));
+        }
+    }
+
+    Ok(Arc::new(WinVideoSource { capture, format }))
+}
+
+/// Number of monitors connected, enumerated again on every call.
+pub fn monitor_count() -> i32 {
+    unsafe { win_video::devices::get_monitor_count() as i32 }
+}
+
+/// # Win Video Source
+///
+/// A capture of the win_video library, the cameras and monitors of `CaptureType`.
+pub struct WinVideoSource {
+    capture: Arc<dyn ICapture<CaptureOutput = Vec<u8>>>,
+    format: PixelFormat,
+}
+
+impl CaptureSource for WinVideoSource {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        let dimensions = self
+            .capture
+            .get_dimensions()
+            .map_err(|e| ShareScreenError::Capture(e.to_string()))?;
+
+        Ok((dimensions.width, dimensions.height))
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        let rx = self.capture.clone_receiver();
+
+        async move { rx.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.capture
+                .start_capturing()
+                .await
+                .map(|_| ())
+                .map_err(|e| ShareScreenError::Capture(e.to_string()))
+        }
+        .boxed()
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+}
+
+/// # List Monitors
+///
+/// Enumerates the display outputs of every adapter.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>> {
+    let mut monitors = Vec::new();
+    let models = monitor_models().unwrap_or_default();
+
+    for desc in outputs()? {
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+            ..Default::default()
+        };
+        let primary = unsafe { GetMonitorInfoW(desc.Monitor, &mut info) }.as_bool()
+            && info.dwFlags & MONITORINFOF_PRIMARY != 0;
+
+        let rect = desc.DesktopCoordinates;
+        let name = wide_to_string(&desc.DeviceName);
+
+        monitors.push(MonitorInfo {
+            index: monitors.len(),
+            model: models.get(&name).cloned(),
+            name,
+            width: (rect.right - rect.left) as u32,
+            height: (rect.bottom - rect.top) as u32,
+            primary,
+        });
+    }
+
+    Ok(monitors)
+}
+
+/// The names the monitors report in their EDID, by the GDI name of the display they show (`\\.\DISPLAY1`).
+fn monitor_models() -> Result<HashMap<String, String>> {
+    let mut models = HashMap::new();
+    let (mut path_count, mut mode_count) = (0u32, 0u32);
+
+    unsafe {
+        GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count).ok()?;
+
+        let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+        let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+
+        QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut path_count,
+            paths.as_mut_ptr(),
+            &mut mode_count,
+            modes.as_mut_ptr(),
+            None,
+        )
+        .ok()?;
+
+        for path in &paths[..path_count as usize] {
+            let mut source = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+                header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                    r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+                    size: std::mem::size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
+                    adapterId: path.sourceInfo.adapterId,
+                    id: path.sourceInfo.id,
+                },
+                ..Default::default()
+            };
+            let mut target = DISPLAYCONFIG_TARGET_DEVICE_NAME {
+                header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                    r#type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+                    size: std::mem::size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+                    adapterId: path.targetInfo.adapterId,
+                    id: path.targetInfo.id,
+                },
+                ..Default::default()
+            };
+
+            if DisplayConfigGetDeviceInfo(&mut source.header) != 0 || DisplayConfigGetDeviceInfo(&mut target.header) != 0 {
+                continue;
+            }
+
+            //the friendly name is read from the EDID, empty for monitors without one
+            let model = wide_to_string(&target.monitorFriendlyDeviceName);
+
+            if !model.is_empty() {
+                models.insert(wide_to_string(&source.viewGdiDeviceName), model);
+            }
+        }
+    }
+
+    Ok(models)
+}
+
+/// Desktop coordinates of the monitor at the index, in the order of `list_monitors`.
+pub fn monitor_area(index: usize) -> Result<Option<RECT>> {
+    Ok(outputs()?.get(index).map(|desc| desc.DesktopCoordinates))
+}
+
+/// Whether the monitor at the index, in the order of `list_monitors`, is in HDR mode (the PQ color space).
+pub fn monitor_is_hdr(index: usize) -> Result<bool> {
+    let Some(output) = output_handles()?.into_iter().nth(index) else {
+        return Ok(false);
+    };
+
+    //IDXGIOutput6 needs windows 10 1803, older versions have no HDR desktop
+    let Ok(output) = output.cast::<IDXGIOutput6>() else {
+        return Ok(false);
+    };
+
+    let desc = unsafe { output.GetDesc1()? };
+
+    Ok(desc.ColorSpace == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020)
+}
+
+/// The display outputs of every adapter, adapter by adapter.
+fn outputs() -> Result<Vec<DXGI_OUTPUT_DESC>> {
+    output_handles()?
+        .iter()
+        .map(|output| unsafe { output.GetDesc() }.map_err(Into::into))
+        .collect()
+}
+
+fn output_handles() -> Result<Vec<IDXGIOutput>> {
+    let mut outputs = Vec::new();
+
+    unsafe {
+        let factory: IDXGIFactory1 = CreateDXGIFactory1()?;
+
+        let mut adapter_index = 0;
+        while let Ok(adapter) = factory.EnumAdapters1(adapter_index) {
+            let mut output_index = 0;
+
+            while let Ok(output) = adapter.EnumOutputs(output_index) {
+                outputs.push(output);
+                output_index += 1;
+            }
+
+            adapter_index += 1;
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// # List Cameras
+///
+/// Enumerates the video capture devices through Media Foundation, along with their supported formats.
+pub fn list_cameras() -> Result<Vec<CameraInfo>> {
+    let mut cameras = Vec::new();
+    ComGuard::ensure()?;
+
+    unsafe {
+        let mut attributes = None;
+        MFCreateAttributes(&mut attributes, 1)?;
+        let attributes = attributes.ok_or_else(|| {
+            windows::core::Error::new(E_POINTER, "Failed to create camera attributes.")
+        })?;
+
+        attributes.SetGUID(
+            &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+            &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+        )?;
+
+        let mut devices: *mut Option<IMFActivate> = std::ptr::null_mut();
+        let mut count = 0u32;
+        MFEnumDeviceSources(&attributes, &mut devices, &mut count)?;
+
+        if devices.is_null() {
+            return Ok(cameras);
+        }
+
+        for (index, device) in std::slice::from_raw_parts(devices, count as usize)
+            .iter()
+            .enumerate()
+        {
+            let Some(device) = device else {
+                continue;
+            };
+
+            cameras.push(CameraInfo {
+                index,
+                name: camera_name(device).unwrap_or_else(|| format!("Camera {}", index + 1)),
+                formats: camera_formats(device).unwrap_or_default(),
+            });
+        }
+
+        //release the activates before freeing the array
+        for i in 0..count as usize {
+            std::ptr::drop_in_place(devices.add(i));
+        }
+        CoTaskMemFree(Some(devices as *const _));
+    }
+
+    Ok(cameras)
+}
+
+unsafe fn camera_name(device: &IMFActivate) -> Option<String> {
+    let mut name = PWSTR::null();
+    let mut len = 0u32;
+
+    unsafe {
+        device
+            .GetAllocatedString(&MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME, &mut name, &mut len)
+            .ok()?;
+
+        let value = name.to_string().ok();
+        CoTaskMemFree(Some(name.0 as *const _));
+
+        value
+    }
+}
+
+unsafe fn camera_formats(device: &IMFActivate) -> windows::core::Result<Vec<CameraFormat>> {
+    let mut formats = Vec::new();
+
+    unsafe {
+        let source: IMFMediaSource = device.ActivateObject()?;
+        let presentation = source.CreatePresentationDescriptor()?;
+
+        let mut selected = Default::default();
+        let mut stream = None;
+        presentation.GetStreamDescriptorByIndex(0, &mut selected, &mut stream)?;
+
+        if let Some(stream) = stream {
+            let handler = stream.GetMediaTypeHandler()?;
+
+            for i in 0..handler.GetMediaTypeCount()? {
+                let media_type = handler.GetMediaTypeByIndex(i)?;
+
+                let (Ok(subtype), Ok(size)) = (
+                    media_type.GetGUID(&MF_MT_SUBTYPE),
+                    media_type.GetUINT64(&MF_MT_FRAME_SIZE),
+                ) else {
+                    continue;
+                };
+
+                let format = CameraFormat {
+                    format: subtype_name(&subtype),
+                    width: (size >> 32) as u32,
+                    height: size as u32,
+                };
+
+                //the same format is usually listed once per frame rate
+                if !formats.contains(&format) {
+                    formats.push(format);
+                }
+            }
+        }
+
+        let _ = source.Shutdown();
+        let _ = device.ShutdownObject();
+    }
+
+    Ok(formats)
+}
+
+/// Media Foundation video subtypes are FOURCC based, RGB formats use their D3DFORMAT value instead.
+fn subtype_name(subtype: &GUID) -> String {
+    match subtype.data1 {
+        20 => "RGB24".to_string(),
+        21 => "ARGB32".to_string(),
+        22 => "RGB32".to_string(),
+        fourcc => {
+            let bytes = fourcc.to_le_bytes();
+
+            if bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+                String::from_utf8_lossy(&bytes).trim().to_string()
+            } else {
+                format!("{fourcc:#x}")
+            }
+        }
+    }
+}
+
+/// # Top Level Windows
+///
+/// The visible top-level windows with a title, by their handle (`HWND` is not `Send`) along with the title.
+pub fn top_level_windows() -> Vec<(isize, String)> {
+    unsafe extern "system" fn collect(window: HWND, windows: LPARAM) -> BOOL {
+        let windows = unsafe { &mut *(windows.0 as *mut Vec<(isize, String)>) };
+        let mut title = [0u16; 256];
+        let len = unsafe { GetWindowTextW(window, &mut title) };
+
+        if len > 0 && unsafe { IsWindowVisible(window) }.as_bool() {
+            windows.push((window.0 as isize, wide_to_string(&title[..len as usize])));
+        }
+
+        true.into()
+    }
+
+    let mut windows: Vec<(isize, String)> = Vec::new();
+    let _ = unsafe { EnumWindows(Some(collect), LPARAM(&mut windows as *mut _ as isize)) };
+
+    windows
+}
+
+/// Handle of the console window of the process, none when it has no console.
+pub fn console_window() -> Option<isize> {
+    let window = unsafe { GetConsoleWindow() };
+
+    (!window.is_invalid()).then_some(window.0 as isize)
+}
+
+/// Desktop coordinates of the window of the handle, none once it is closed or while it is minimized.
+pub fn window_area(window: isize) -> Option<RECT> {
+    let window = HWND(window as *mut _);
+    let mut area = RECT::default();
+
+    if unsafe { IsIconic(window) }.as_bool() {
+        return None;
+    }
+
+    unsafe { GetWindowRect(window, &mut area) }.ok()?;
+    Some(area)
+}
+
+/// Hides the window of the handle from every capture (`WDA_EXCLUDEFROMCAPTURE`), or shows it again.
+pub fn set_excluded_from_capture(window: isize, excluded: bool) -> Result<()> {
+    let affinity = if excluded { WDA_EXCLUDEFROMCAPTURE } else { WDA_NONE };
+
+    unsafe { SetWindowDisplayAffinity(HWND(window as *mut _), affinity)? };
+    Ok(())
+}
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|c| *c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
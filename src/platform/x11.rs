@@ -0,0 +1,406 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tracing::{info, warn};
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{
+        randr::{ConnectionExt as _, Output},
+        shm::{self, ConnectionExt as _},
+        xproto::{Atom, AtomEnum, ConnectionExt as _, ImageFormat, ImageOrder, Window},
+    },
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    captures::CaptureType,
+    devices::{CameraInfo, MonitorInfo, edid_monitor_name},
+    error::{Result, ShareScreenError},
+    source::CaptureSource,
+};
+
+/// Frames grabbed per second, X11 has no damage-driven capture without the composite extension.
+const CAPTURE_FPS: u32 = 30;
+
+/// A rectangle of the root window, a RandR monitor or the whole screen.
+#[derive(Clone)]
+struct Area {
+    name: String,
+    /// name of the monitor in the EDID of its first output.
+    model: Option<String>,
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+    primary: bool,
+}
+
+/// # Open
+///
+/// Captures a monitor of the X server of `DISPLAY`, monitors are the RandR monitors (the whole screen without RandR).
+///
+/// Cameras are not supported on this backend yet (`NoCamera`).
+pub fn open(device: CaptureType) -> Result<Arc<dyn CaptureSource>> {
+    match device {
+        CaptureType::Monitor(m) => Ok(Arc::new(X11Source::open(m)?)),
+        CaptureType::Camera(_) => Err(ShareScreenError::NoCamera),
+        CaptureType::TestPattern { .. } => Err(ShareScreenError::Activation {
+            device: device.to_string(),
+            reason: "the test pattern is not an X11 device".to_string(),
+        }),
+    }
+}
+
+/// Number of monitors connected, 0 when the X server cannot be reached.
+pub fn monitor_count() -> i32 {
+    connect()
+        .and_then(|(connection, root)| monitors(&connection, root))
+        .map(|monitors| monitors.len() as i32)
+        .unwrap_or(0)
+}
+
+/// # List Monitors
+///
+/// Enumerates the RandR monitors of the screen.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>> {
+    let (connection, root) = connect()?;
+
+    Ok(monitors(&connection, root)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, area)| MonitorInfo {
+            index,
+            name: area.name,
+            model: area.model,
+            width: area.width as u32,
+            height: area.height as u32,
+            primary: area.primary,
+        })
+        .collect())
+}
+
+/// Cameras are not enumerated on X11.
+pub fn list_cameras() -> Result<Vec<CameraInfo>> {
+    Ok(Vec::new())
+}
+
+fn x11_error(e: impl std::fmt::Display) -> ShareScreenError {
+    ShareScreenError::X11(e.to_string())
+}
+
+/// Connects to the display of `DISPLAY`, along with the root window of its default screen.
+fn connect() -> Result<(RustConnection, Window)> {
+    let (connection, screen) = x11rb::connect(None).map_err(x11_error)?;
+    let root = connection.setup().roots[screen].root;
+
+    Ok((connection, root))
+}
+
+/// The RandR monitors, the whole root window when the server has no RandR 1.5.
+fn monitors(connection: &RustConnection, root: Window) -> Result<Vec<Area>> {
+    let reply = connection
+        .randr_get_monitors(root, true)
+        .map_err(x11_error)
+        .and_then(|cookie| cookie.reply().map_err(x11_error));
+
+    let monitors = match reply {
+        Ok(reply) if !reply.monitors.is_empty() => reply.monitors,
+        _ => {
+            let geometry = connection
+                .get_geometry(root)
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?;
+
+            return Ok(vec![Area {
+                name: "screen".to_string(),
+                model: None,
+                x: 0,
+                y: 0,
+                width: geometry.width,
+                height: geometry.height,
+                primary: true,
+            }]);
+        }
+    };
+
+    let edid = connection
+        .intern_atom(true, b"EDID")
+        .ok()
+        .and_then(|cookie| cookie.reply().ok())
+        .map(|reply| reply.atom)
+        .filter(|atom| *atom != x11rb::NONE);
+
+    Ok(monitors
+        .into_iter()
+        .map(|monitor| Area {
+            name: connection
+                .get_atom_name(monitor.name)
+                .ok()
+                .and_then(|cookie| cookie.reply().ok())
+                .map(|reply| String::from_utf8_lossy(&reply.name).into_owned())
+                .unwrap_or_default(),
+            model: edid.zip(monitor.outputs.first()).and_then(|(edid, output)| output_model(connection, *output, edid)),
+            x: monitor.x,
+            y: monitor.y,
+            width: monitor.width,
+            height: monitor.height,
+            primary: monitor.primary,
+        })
+        .collect())
+}
+
+/// The monitor name in the EDID property of the output.
+fn output_model(connection: &RustConnection, output: Output, edid: Atom) -> Option<String> {
+    let reply = connection
+        .randr_get_output_property(output, edid, AtomEnum::ANY, 0, 64, false, false)
+        .ok()?
+        .reply()
+        .ok()?;
+
+    edid_monitor_name(&reply.data)
+}
+
+/// # X11 Source
+///
+/// A monitor grabbed `CAPTURE_FPS` times per second on its own thread, through a shared memory segment (XShm)
+/// when the server supports it and with plain `GetImage` requests otherwise.
+pub struct X11Source {
+    width: u32,
+    height: u32,
+    frames: Mutex<mpsc::Receiver<Vec<u8>>>,
+    finished: Mutex<Option<oneshot::Receiver<Result<()>>>>,
+}
+
+impl X11Source {
+    fn open(index: i32) -> Result<Self> {
+        let (connection, root) = connect()?;
+        let monitors = monitors(&connection, root)?;
+        let count = monitors.len() as i32;
+
+        let area = usize::try_from(index)
+            .ok()
+            .and_then(|index| monitors.into_iter().nth(index))
+            .ok_or(ShareScreenError::MonitorOutOfRange { index, count })?;
+
+        let setup = connection.setup();
+        let depth = setup.roots.iter().find(|screen| screen.root == root).map_or(0, |screen| screen.root_depth);
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == depth)
+            .map_or(0, |format| format.bits_per_pixel);
+
+        if bits_per_pixel != 32 {
+            return Err(ShareScreenError::Activation {
+                device: format!("monitor {}", index + 1),
+                reason: format!("{depth} bit displays are not supported ({bits_per_pixel} bits per pixel)"),
+            });
+        }
+
+        let grabber = Grabber::new(connection, root, area.clone())?;
+        let (frames, receiver) = mpsc::channel(2);
+        let (finished, finished_receiver) = oneshot::channel();
+
+        std::thread::Builder::new()
+            .name("x11-capture".to_string())
+            .spawn(move || {
+                let _ = finished.send(grabber.capture(&frames));
+            })?;
+
+        info!(monitor = %area.name, width = area.width, height = area.height, "Capturing X11 monitor");
+
+        Ok(Self {
+            width: area.width as u32,
+            height: area.height as u32,
+            frames: Mutex::new(receiver),
+            finished: Mutex::new(Some(finished_receiver)),
+        })
+    }
+}
+
+impl CaptureSource for X11Source {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        Ok((self.width, self.height))
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        async move { self.frames.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Some(finished) = self.finished.lock().await.take() else {
+                return Err(ShareScreenError::Capture("the monitor is already captured".to_string()));
+            };
+
+            finished
+                .await
+                .unwrap_or_else(|_| Err(ShareScreenError::Capture("the X11 capture stopped".to_string())))
+        }
+        .boxed()
+    }
+}
+
+/// Grabs the pixels of an area of the root window.
+struct Grabber {
+    connection: RustConnection,
+    root: Window,
+    area: Area,
+    segment: Option<Segment>,
+}
+
+impl Grabber {
+    fn new(connection: RustConnection, root: Window, area: Area) -> Result<Self> {
+        let size = area.width as usize * area.height as usize * 4;
+
+        let segment = match Segment::attach(&connection, size) {
+            Ok(segment) => segment,
+            Err(e) => {
+                warn!(error = %e, "XShm is not available, capturing with GetImage");
+                None
+            }
+        };
+
+        Ok(Self {
+            connection,
+            root,
+            area,
+            segment,
+        })
+    }
+
+    /// Grabs frames until the capture is dropped, a frame the compressor is not ready for is skipped.
+    fn capture(&self, frames: &mpsc::Sender<Vec<u8>>) -> Result<()> {
+        let interval = Duration::from_secs(1) / CAPTURE_FPS;
+        let mut next = Instant::now();
+
+        loop {
+            next += interval;
+
+            match frames.try_reserve() {
+                Ok(permit) => permit.send(self.grab()?),
+                Err(mpsc::error::TrySendError::Full(())) => {}
+                Err(mpsc::error::TrySendError::Closed(())) => return Ok(()),
+            }
+
+            let now = Instant::now();
+            if next > now {
+                std::thread::sleep(next - now);
+            } else {
+                //too slow for the frame rate, no catching up
+                next = now;
+            }
+        }
+    }
+
+    /// One frame of the area, BGRA.
+    fn grab(&self) -> Result<Vec<u8>> {
+        let Area { x, y, width, height, .. } = self.area;
+
+        let mut frame = match &self.segment {
+            Some(segment) => {
+                self.connection
+                    .shm_get_image(self.root, x, y, width, height, !0, ImageFormat::Z_PIXMAP.into(), segment.id, 0)
+                    .map_err(x11_error)?
+                    .reply()
+                    .map_err(x11_error)?;
+
+                segment.bytes().to_vec()
+            }
+            None => {
+                self.connection
+                    .get_image(ImageFormat::Z_PIXMAP, self.root, x, y, width, height, !0)
+                    .map_err(x11_error)?
+                    .reply()
+                    .map_err(x11_error)?
+                    .data
+            }
+        };
+
+        let msb_first = self.connection.setup().image_byte_order == ImageOrder::MSB_FIRST;
+
+        //the padding byte of 24 bit visuals is undefined, pixels are XRGB on big endian servers
+        for pixel in frame.chunks_exact_mut(4) {
+            if msb_first {
+                pixel.reverse();
+            }
+            pixel[3] = 255;
+        }
+
+        Ok(frame)
+    }
+}
+
+/// A System V shared memory segment attached to the X server, the images are written straight into it.
+struct Segment {
+    id: shm::Seg,
+    address: *mut u8,
+    size: usize,
+}
+
+//the segment is only read by the thread grabbing, after the reply of the request writing it
+unsafe impl Send for Segment {}
+
+impl Segment {
+    /// `None` when the server has no XShm extension.
+    fn attach(connection: &RustConnection, size: usize) -> Result<Option<Self>> {
+        if connection
+            .extension_information(shm::X11_EXTENSION_NAME)
+            .map_err(x11_error)?
+            .is_none()
+        {
+            return Ok(None);
+        }
+
+        unsafe {
+            let shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if shmid < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+
+            let address = libc::shmat(shmid, std::ptr::null(), 0);
+
+            //removed once both the server and this process detached it
+            libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut());
+
+            if address as isize == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+
+            let id = connection.generate_id().map_err(x11_error)?;
+            let attached = connection
+                .shm_attach(id, shmid as u32, false)
+                .map_err(x11_error)
+                .and_then(|cookie| cookie.check().map_err(x11_error));
+
+            if let Err(e) = attached {
+                libc::shmdt(address);
+                return Err(e);
+            }
+
+            Ok(Some(Self {
+                id,
+                address: address as *mut u8,
+                size,
+            }))
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.address, self.size) }
+    }
+}
+
+impl Drop for Segment {
+    //the server detaches it once the connection of the grabber is closed
+    fn drop(&mut self) {
+        unsafe {
+            libc::shmdt(self.address as *const _);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Time the pointer stays on the frames after the last position was sent.
+pub const POINTER_DURATION: Duration = Duration::from_millis(500);
+/// Color of the pointer when the viewer does not pick one, BGRA.
+const DEFAULT_COLOR: [u8; 4] = [0, 0, 255, 255];
+
+struct Point {
+    x: f64,
+    y: f64,
+    color: [u8; 4],
+    shown: Instant,
+}
+
+/// # Laser Pointer
+///
+/// A dot composited onto the outgoing frames where a viewer points, so it can show the host where to click.
+pub struct LaserPointer {
+    point: Mutex<Option<Point>>,
+}
+
+impl LaserPointer {
+    pub fn new() -> Self {
+        Self { point: Mutex::new(None) }
+    }
+
+    /// # Point
+    ///
+    /// Shows the pointer at the position for `POINTER_DURATION`, from 0.0 (left, top) to 1.0 (right, bottom).
+    pub fn point(&self, x: f64, y: f64, color: Option<[u8; 4]>) {
+        *self.point.lock().unwrap() = Some(Point {
+            x: x.clamp(0.0, 1.0),
+            y: y.clamp(0.0, 1.0),
+            color: color.unwrap_or(DEFAULT_COLOR),
+            shown: Instant::now(),
+        });
+    }
+
+    /// Draws the pointer onto the BGRA frame, nothing is drawn once it expired.
+    pub fn draw(&self, frame: &mut [u8], width: u32, height: u32) {
+        let (x, y, color) = {
+            let mut point = self.point.lock().unwrap();
+
+            match point.as_ref() {
+                Some(current) if current.shown.elapsed() < POINTER_DURATION => (current.x, current.y, current.color),
+                Some(_) => {
+                    *point = None;
+                    return;
+                }
+                None => return,
+            }
+        };
+
+        let (width, height) = (width as i64, height as i64);
+
+        if frame.len() < (width * height * 4) as usize {
+            return;
+        }
+
+        let radius = (height / 90).max(4);
+        let center_x = (x * (width - 1) as f64).round() as i64;
+        let center_y = (y * (height - 1) as f64).round() as i64;
+
+        for py in (center_y - radius).max(0)..(center_y + radius + 1).min(height) {
+            for px in (center_x - radius).max(0)..(center_x + radius + 1).min(width) {
+                let (dx, dy) = (px - center_x, py - center_y);
+
+                if dx * dx + dy * dy <= radius * radius {
+                    let offset = ((py * width + px) * 4) as usize;
+                    frame[offset..offset + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
+
+impl Default for LaserPointer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses a `#rrggbb` color into BGRA.
+pub fn parse_color(color: &str) -> Option<[u8; 4]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let [_, r, g, b] = value.to_be_bytes();
+
+    Some([b, g, r, 255])
+}
@@ -0,0 +1,211 @@
+use std::{str::FromStr, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{
+    packets::{PacketType, parse},
+    state::StreamState,
+    streamed_resolution::BandwidthCap,
+};
+
+/// # Stream Preset
+///
+/// Named bundle of the fps, scale, quality and buffer of a stream, picked with `--preset` for the whole server
+/// or with `?preset=` for a single stream.
+///
+/// Note: `The frames are encoded once for every viewer, ?preset= only paces the stream of its viewer (fps and buffer),
+/// the scale and the quality are the ones of the server.`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamPreset {
+    /// small frames, as many as possible, a viewer that falls behind skips straight to the newest frame.
+    LowLatency,
+    Balanced,
+    /// full size sharp frames at a lower fps, a viewer on a slow link gets every frame a little later.
+    Quality,
+}
+
+/// Settings bundled by a preset.
+#[derive(Clone, Copy, Debug)]
+pub struct PresetSettings {
+    /// maximum fps, 0 being unlimited.
+    pub fps: u32,
+    /// size of the streamed frames in percent of the captured ones.
+    pub scale: u8,
+    pub quality: u8,
+    /// frames a viewer may be behind the broadcast before the oldest ones are skipped.
+    pub buffer: usize,
+}
+
+impl StreamPreset {
+    pub const ALL: [StreamPreset; 3] = [StreamPreset::LowLatency, StreamPreset::Balanced, StreamPreset::Quality];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StreamPreset::LowLatency => "low-latency",
+            StreamPreset::Balanced => "balanced",
+            StreamPreset::Quality => "quality",
+        }
+    }
+
+    pub fn settings(self) -> PresetSettings {
+        match self {
+            StreamPreset::LowLatency => PresetSettings {
+                fps: 60,
+                scale: 50,
+                quality: 50,
+                buffer: 1,
+            },
+            StreamPreset::Balanced => PresetSettings {
+                fps: 30,
+                scale: 75,
+                quality: 70,
+                buffer: 8,
+            },
+            StreamPreset::Quality => PresetSettings {
+                fps: 15,
+                scale: 100,
+                quality: 90,
+                buffer: 30,
+            },
+        }
+    }
+
+    /// # Apply
+    ///
+    /// Sets the fps, the scale and the quality of the server to the ones of the preset.
+    pub fn apply(self, state: &StreamState) {
+        let settings = self.settings();
+
+        state.control.set_max_fps(settings.fps);
+        state.control.set_quality(settings.quality);
+        state.scaler.set_scale(settings.scale);
+    }
+}
+
+impl FromStr for StreamPreset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.trim().to_ascii_lowercase().replace('_', "-");
+
+        StreamPreset::ALL
+            .into_iter()
+            .find(|preset| preset.name() == name)
+            .ok_or_else(|| format!("Unknown preset {name}, expected low-latency, balanced or quality"))
+    }
+}
+
+/// Why a frame was not sent to a viewer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Skipped {
+    /// the viewer is further behind the broadcast than the buffer of its preset, its connection does not keep up.
+    Backlog,
+    /// over the fps of the preset or the bandwidth cap of the viewer.
+    Throttled,
+    /// the container of the stream cannot carry the frame.
+    Unsupported,
+}
+
+/// # Stream Pacing
+///
+/// The part of a preset applied to the stream of a single viewer: frames closer than the fps of the preset are skipped,
+/// and so are the oldest frames of a viewer further behind the broadcast than the buffer and the frames over its bandwidth cap.
+///
+/// Note: `Only frames are skipped, every JPEG frame stands on its own. Dimension updates always go through.`
+#[derive(Default)]
+pub struct StreamPacing {
+    //minimum capture time between two frames sent, none when unlimited.
+    //a tenth below the interval of the fps, so a source capturing at exactly the fps is not halved by its jitter
+    interval_us: Option<u64>,
+    buffer: Option<usize>,
+    cap: Option<BandwidthCap>,
+    last_frame_us: Option<u64>,
+}
+
+impl StreamPacing {
+    /// Paces with the settings of the preset, does nothing without one.
+    pub fn new(preset: Option<StreamPreset>) -> Self {
+        let Some(settings) = preset.map(StreamPreset::settings) else {
+            return Self::default();
+        };
+
+        Self {
+            interval_us: (settings.fps > 0).then(|| Duration::from_secs(1).as_micros() as u64 * 9 / 10 / settings.fps as u64),
+            buffer: Some(settings.buffer.max(1)),
+            cap: None,
+            last_frame_us: None,
+        }
+    }
+
+    /// Also drops the frames over the bandwidth cap of the viewer.
+    pub fn with_cap(self, cap: Option<BandwidthCap>) -> Self {
+        Self { cap, ..self }
+    }
+
+    /// # Skip
+    ///
+    /// Why the packet is dropped for this viewer, `None` when it is sent, `queued` being the packets still waiting in its receiver.
+    pub fn skip(&mut self, packet: &[u8], queued: usize) -> Option<Skipped> {
+        if self.interval_us.is_none() && self.buffer.is_none() && self.cap.is_none() {
+            return None;
+        }
+
+        let Some((header, _)) = parse(packet).filter(|(header, _)| header.kind == PacketType::Frame) else {
+            self.sent(packet);
+            return None;
+        };
+
+        if self.buffer.is_some_and(|buffer| queued >= buffer) {
+            return Some(Skipped::Backlog);
+        }
+
+        if let (Some(interval), Some(last)) = (self.interval_us, self.last_frame_us)
+            && header.timestamp_us.saturating_sub(last) < interval
+        {
+            return Some(Skipped::Throttled);
+        }
+
+        if self.cap.as_mut().is_some_and(|cap| !cap.allows()) {
+            return Some(Skipped::Throttled);
+        }
+
+        self.last_frame_us = Some(header.timestamp_us);
+        self.sent(packet);
+
+        None
+    }
+
+    /// Takes a packet sent to the viewer out of its bandwidth, `skip` already does for the packets it lets through.
+    pub fn sent(&mut self, packet: &[u8]) {
+        if let Some(cap) = &mut self.cap {
+            cap.spend(packet.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::{dimensions_packet, frame_packet};
+
+    #[test]
+    fn skips_the_frames_over_the_bandwidth_cap() {
+        let mut pacing = StreamPacing::default().with_cap(BandwidthCap::from_kbps(8));
+        let frame = frame_packet(1, 1_000, &[0; 1_500]);
+
+        assert_eq!(pacing.skip(&frame, 0), None);
+        assert_eq!(pacing.skip(&frame_packet(2, 2_000, &[0; 100]), 0), Some(Skipped::Throttled));
+        //dimension updates always go through
+        assert_eq!(pacing.skip(&dimensions_packet(3, 3_000, 1920, 1080), 0), None);
+    }
+
+    #[test]
+    fn sends_everything_without_a_preset_nor_a_cap() {
+        let mut pacing = StreamPacing::new(None);
+
+        for sequence in 0..10 {
+            assert_eq!(pacing.skip(&frame_packet(sequence, sequence as u64, &[0; 100_000]), 100), None);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::net::{IpAddr, SocketAddr};
+
+use qrcode::{QrCode, render::unicode::Dense1x2};
+use tracing::warn;
+
+/// # Viewer Url
+///
+/// The url viewers open for a listener at the address, with the local ip for listeners bound to every interface.
+pub fn viewer_url(address: SocketAddr, local_ip: IpAddr) -> String {
+    let ip = if address.ip().is_unspecified() {
+        local_ip
+    } else {
+        address.ip()
+    };
+
+    match (ip, address.port()) {
+        (IpAddr::V4(ip), 80) => format!("http://{ip}"),
+        (IpAddr::V6(ip), 80) => format!("http://[{ip}]"),
+        (ip, port) => format!("http://{}", SocketAddr::new(ip, port)),
+    }
+}
+
+/// # Print QR Code
+///
+/// Prints the url as a QR code of half block characters, so phones and tablets can join by scanning the console.
+pub fn print_qr_code(url: &str) {
+    let code = match QrCode::new(url) {
+        Ok(code) => code,
+        Err(e) => {
+            warn!(error = %e, "Failed to encode the viewer url as a QR code");
+            return;
+        }
+    };
+
+    //drawn inverted, scanners expect dark modules on a light background and most consoles are dark
+    let rendered = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build();
+
+    println!("{rendered}");
+    println!("Scan to open {url}");
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+/// Window the stream subscriptions of `streams_per_minute` are counted over.
+const STREAM_WINDOW: Duration = Duration::from_secs(60);
+
+/// Clients tracked by the stream limiter before the ones without recent streams are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// `[rate_limit]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// connections a single ip can have open at once, 0 for no limit.
+    pub connections_per_ip: usize,
+    /// `/stream` requests a single ip can make per minute, 0 for no limit.
+    pub streams_per_minute: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            connections_per_ip: 64,
+            streams_per_minute: 30,
+        }
+    }
+}
+
+/// # Connection Limiter
+///
+/// Counts the open connections of every ip, the gateway refuses connections over the limit.
+pub struct ConnectionLimiter {
+    max: usize,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a connection slot of the ip, `None` when it has all of its connections open.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionPermit> {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(ip).or_insert(0);
+
+        if self.max != 0 && *count >= self.max {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// A connection slot of an ip, given back when dropped.
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut open = self.limiter.open.lock().unwrap();
+
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// # Stream Limiter
+///
+/// Remembers when every ip last started streams, over a sliding window of a minute.
+pub struct StreamLimiter {
+    per_minute: usize,
+    started: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl StreamLimiter {
+    pub fn new(per_minute: usize) -> Self {
+        Self {
+            per_minute,
+            started: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// # Check stream
+    ///
+    /// Counts a new stream of the ip, the err is the time until the ip may start another one.
+    pub fn check(&self, ip: &str) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut started = self.started.lock().unwrap();
+
+        if started.len() >= MAX_TRACKED_CLIENTS {
+            started.retain(|_, times| {
+                times.back().is_some_and(|last| now.duration_since(*last) < STREAM_WINDOW)
+            });
+        }
+
+        let times = started.entry(ip.to_string()).or_default();
+        while times.front().is_some_and(|first| now.duration_since(*first) >= STREAM_WINDOW) {
+            times.pop_front();
+        }
+
+        if times.len() >= self.per_minute {
+            let oldest = *times.front().expect("the limit is above 0");
+            return Err(STREAM_WINDOW - now.duration_since(oldest));
+        }
+
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_the_open_connections_of_an_ip() {
+        let limiter = Arc::new(ConnectionLimiter::new(2));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.try_acquire(ip).unwrap();
+        let _second = limiter.try_acquire(ip).unwrap();
+        assert!(limiter.try_acquire(ip).is_none());
+        assert!(limiter.try_acquire(other).is_some());
+
+        drop(first);
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[test]
+    fn forgets_the_ips_without_connections() {
+        let limiter = Arc::new(ConnectionLimiter::new(1));
+        let ip: IpAddr = "::1".parse().unwrap();
+
+        drop(limiter.try_acquire(ip).unwrap());
+        assert!(limiter.open.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unlimited_connections_at_zero() {
+        let limiter = Arc::new(ConnectionLimiter::new(0));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let permits: Vec<_> = (0..100).map(|_| limiter.try_acquire(ip)).collect();
+        assert!(permits.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn limits_the_streams_of_an_ip_per_minute() {
+        let limiter = StreamLimiter::new(2);
+
+        assert!(limiter.check("10.0.0.1").is_ok());
+        assert!(limiter.check("10.0.0.1").is_ok());
+
+        let wait = limiter.check("10.0.0.1").unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= STREAM_WINDOW);
+
+        assert!(limiter.check("10.0.0.2").is_ok());
+    }
+
+    #[test]
+    fn unlimited_streams_at_zero() {
+        let limiter = StreamLimiter::new(0);
+
+        assert!((0..100).all(|_| limiter.check("10.0.0.1").is_ok()));
+    }
+}
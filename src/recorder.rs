@@ -0,0 +1,451 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, error, info, info_span, warn};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{
+        Mutex,
+        broadcast::{Receiver, error::RecvError},
+        oneshot,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    av1::{Av1Config, spawn_av1},
+    containers::{Muxer, fmp4::Fmp4Muxer, matroska::MatroskaMuxer, recv_audio},
+    error::{Result, ShareScreenError},
+    events::ServerEvent,
+    packets::{audio_payload, frame_payload, frame_sequence},
+    session::unix_now,
+    state::StreamState,
+    static_files::valid_segment,
+    transcoder::{EncodedPacket, Transcoder},
+    vpx::{VpxCodec, VpxConfig, spawn_vpx},
+};
+
+/// Folder recordings and saved replays are written to.
+pub const RECORDINGS_FOLDER: &str = "recordings";
+/// # Recording Path
+///
+/// The path of the file a client named in the recordings folder, `None` unless the name is a bare file name (the `resolve_path`
+/// rules of the static files) or when it is a link, which would write wherever it points.
+pub fn recording_path(name: &str) -> Option<String> {
+    let path = format!("{RECORDINGS_FOLDER}/{name}");
+
+    (valid_segment(name) && !std::path::Path::new(&path).is_symlink()).then_some(path)
+}
+
+/// Packets a recording queues while its writes are slow (a busy disk), seconds of frames where the viewers keep a hundred packets.
+const RECORDING_QUEUE: usize = 512;
+
+/// The container a recording is written in.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// fragmented MP4, video only.
+    #[default]
+    Mp4,
+    /// Matroska, with the Opus track of the audio when it is captured.
+    Mkv,
+    /// Matroska limited to the codecs of WebM, what browsers play: the `av1`, `vp8` and `vp9` codecs.
+    Webm,
+}
+
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Mp4 => "mp4",
+            RecordingFormat::Mkv => "mkv",
+            RecordingFormat::Webm => "webm",
+        }
+    }
+
+    /// The format of a path from its extension, `None` for other extensions.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "mp4" => Some(RecordingFormat::Mp4),
+            "mkv" => Some(RecordingFormat::Mkv),
+            "webm" => Some(RecordingFormat::Webm),
+            _ => None,
+        }
+    }
+}
+
+/// The video codec of the recordings.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingCodec {
+    /// the JPEG frames of the stream as they are, no encoding.
+    #[default]
+    Mjpeg,
+    /// the raw frames encoded to AV1 with rav1e (see `spawn_av1`), Matroska or WebM.
+    Av1,
+    /// the raw frames encoded to VP8 with the libvpx of ffmpeg (see `spawn_vpx`), Matroska or WebM.
+    Vp8,
+    /// like `Vp8`, a third smaller for more cpu.
+    Vp9,
+}
+
+impl RecordingCodec {
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordingCodec::Mjpeg => "mjpeg",
+            RecordingCodec::Av1 => "av1",
+            RecordingCodec::Vp8 => "vp8",
+            RecordingCodec::Vp9 => "vp9",
+        }
+    }
+
+    /// Codec id of the Matroska video track.
+    fn codec_id(self) -> &'static str {
+        match self {
+            RecordingCodec::Mjpeg => "V_MJPEG",
+            RecordingCodec::Av1 => "V_AV1",
+            RecordingCodec::Vp8 => "V_VP8",
+            RecordingCodec::Vp9 => "V_VP9",
+        }
+    }
+}
+
+/// # Recorder
+///
+/// Records the encoded feed to an MP4, a Matroska or a WebM file alongside live streaming, one recording at a time.
+///
+/// Recordings are written as fragmented MP4 or as Matroska clusters so everything written up to a crash stays playable.
+pub struct Recorder {
+    active: Mutex<Option<ActiveRecording>>,
+    /// format of the recordings whose path does not end in `.mp4`, `.mkv` or `.webm`, and of the default paths.
+    format: RecordingFormat,
+    codec: RecordingCodec,
+    av1: Av1Config,
+    vpx: VpxConfig,
+}
+
+struct ActiveRecording {
+    path: String,
+    started: Instant,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+/// Rest API Json for the recorder state.
+#[derive(Serialize)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub path: Option<String>,
+    pub duration_secs: f64,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            active: Mutex::new(None),
+            format: RecordingFormat::default(),
+            codec: RecordingCodec::default(),
+            av1: Av1Config::default(),
+            vpx: VpxConfig::default(),
+        }
+    }
+
+    /// Writes the recordings in the format unless their path names another one.
+    pub fn with_format(self, format: RecordingFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Encodes the recordings with the codec, AV1 with the settings of `av1`, VP8 and VP9 with the ones of `vpx`.
+    pub fn with_codec(self, codec: RecordingCodec, av1: Av1Config, vpx: VpxConfig) -> Self {
+        Self { codec, av1, vpx, ..self }
+    }
+
+    /// The format of the recordings named automatically, for a codec MP4 cannot hold Matroska (AV1) or WebM (VP8, VP9) instead.
+    pub fn format(&self) -> RecordingFormat {
+        match (self.codec, self.format) {
+            (RecordingCodec::Av1, RecordingFormat::Mp4) => RecordingFormat::Mkv,
+            (RecordingCodec::Vp8 | RecordingCodec::Vp9, RecordingFormat::Mp4) => RecordingFormat::Webm,
+            (_, format) => format,
+        }
+    }
+
+    pub fn codec(&self) -> RecordingCodec {
+        self.codec
+    }
+
+    /// Default path of a new recording: `recordings/recording-<unix time>.<mp4|mkv|webm>`
+    pub fn default_path(&self) -> String {
+        format!("{RECORDINGS_FOLDER}/recording-{}.{}", unix_now(), self.format().extension())
+    }
+
+    /// # Start recording
+    ///
+    /// Subscribes to the broadcast of the stream and writes every frame to the path, in the format of its extension.
+    /// The AV1, VP8 and VP9 recordings encode the raw frame of every frame broadcast instead.
+    ///
+    /// Returns an err if a recording is already running, the format cannot hold the codec, the encoder cannot be started
+    /// or the file could not be created.
+    pub async fn start(&self, path: String, state: Arc<StreamState>) -> Result<()> {
+        let mut active = self.active.lock().await;
+
+        if let Some(recording) = active.as_ref() {
+            return Err(ShareScreenError::Server(format!("Already recording to {}", recording.path)));
+        }
+
+        let format = RecordingFormat::from_path(&path).unwrap_or(self.format());
+        match (self.codec, format) {
+            (RecordingCodec::Mjpeg, RecordingFormat::Webm) => {
+                return Err(ShareScreenError::Server(format!(
+                    "WebM recordings need the av1, vp8 or vp9 [recording] codec, not {path}"
+                )));
+            }
+            (codec, RecordingFormat::Mp4) if codec != RecordingCodec::Mjpeg => {
+                return Err(ShareScreenError::Server(format!(
+                    "{} recordings are written as .mkv or .webm, not {path}",
+                    codec.name()
+                )));
+            }
+            _ => {}
+        }
+
+        if let Some(parent) = std::path::Path::new(&path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let dimensions = state.dimensions();
+        let (width, height) = (dimensions.width as u32, dimensions.height as u32);
+
+        //the encoder is created first, it refuses some sizes
+        let transcoder = match self.codec {
+            RecordingCodec::Mjpeg => None,
+            RecordingCodec::Av1 => Some(spawn_av1(width, height, &self.av1)?),
+            RecordingCodec::Vp8 => Some(spawn_vpx(width, height, VpxCodec::Vp8, &self.vpx)?),
+            RecordingCodec::Vp9 => Some(spawn_vpx(width, height, VpxCodec::Vp9, &self.vpx)?),
+        };
+
+        let file = tokio::fs::File::create(&path).await?;
+        let (stop, stopped) = oneshot::channel();
+        let span = info_span!("recording", %path, codec = self.codec.name());
+
+        let task = match format {
+            RecordingFormat::Mp4 => {
+                let muxer = Fmp4Muxer::new(width, height);
+
+                tokio::spawn(record(file, muxer, None, None, path.clone(), state.clone(), stopped).instrument(span))
+            }
+            RecordingFormat::Mkv | RecordingFormat::Webm => {
+                let audio = state.audio_enabled().then(|| state.audio_packets.subscribe());
+                let mut muxer = MatroskaMuxer::new(width, height);
+
+                if audio.is_some() {
+                    muxer = muxer.with_audio();
+                }
+                if let Some(transcoder) = &transcoder {
+                    muxer = muxer.with_video_codec(self.codec.codec_id(), transcoder.codec_config().to_vec());
+                }
+                if format == RecordingFormat::Webm {
+                    muxer = muxer.as_webm();
+                }
+
+                tokio::spawn(record(file, muxer, audio, transcoder, path.clone(), state.clone(), stopped).instrument(span))
+            }
+        };
+
+        state.stats.recording_created();
+        info!(%path, "Recording started");
+
+        *active = Some(ActiveRecording {
+            path,
+            started: Instant::now(),
+            stop,
+            task,
+        });
+
+        Ok(())
+    }
+
+    /// # Stop recording
+    ///
+    /// Stops the running recording and waits for the file to be flushed, returns the path of the finished recording.
+    pub async fn stop(&self) -> Option<String> {
+        let recording = self.active.lock().await.take()?;
+
+        let _ = recording.stop.send(());
+        let _ = recording.task.await;
+
+        info!(path = %recording.path, "Recording saved");
+
+        Some(recording.path)
+    }
+
+    pub async fn status(&self) -> RecordingStatus {
+        let active = self.active.lock().await;
+
+        RecordingStatus {
+            recording: active.is_some(),
+            path: active.as_ref().map(|r| r.path.clone()),
+            duration_secs: active
+                .as_ref()
+                .map(|r| r.started.elapsed())
+                .unwrap_or(Duration::ZERO)
+                .as_secs_f64(),
+        }
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the frames of the stream (and the audio packets of the receiver) into the file until stopped or the stream closes,
+/// from a sink of the tee of the stream so a slow disk does not hold up the viewers.
+///
+/// With a transcoder the raw frame of every frame is pushed to it instead, and its packets are muxed as they come out.
+async fn record<M: Muxer>(
+    mut file: tokio::fs::File,
+    mut muxer: M,
+    mut audio: Option<Receiver<Vec<u8>>>,
+    mut transcoder: Option<Transcoder>,
+    path: String,
+    state: Arc<StreamState>,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let mut rx = state.sinks.attach("recording", RECORDING_QUEUE);
+    //timestamps are relative to the start of the recording, so both tracks line up
+    let start = state.timestamp_us();
+
+    //starts on the screen as it is, a still screen may not send a frame for a while
+    let primed = state.latest_packet();
+    let primed_sequence = primed.as_deref().and_then(|packet| frame_sequence(packet));
+
+    let mut header = muxer.header();
+
+    match &mut transcoder {
+        Some(transcoder) => {
+            if let Some(frame) = state.latest_raw() {
+                transcoder.push(frame, Duration::ZERO);
+            }
+        }
+        None => {
+            if let Some((_, jpeg)) = primed.as_deref().and_then(|packet| frame_payload(packet)) {
+                header.extend(muxer.mux(jpeg, Duration::ZERO));
+            }
+        }
+    }
+
+    if let Err(e) = file.write_all(&header).await {
+        state.stats.error();
+        error!(%path, error = %e, "Failed to write recording");
+        return;
+    }
+
+    loop {
+        let muxed = tokio::select! {
+            _ = &mut stopped => break,
+            packet = rx.recv() => match packet {
+                Some(packet) => {
+                    //paused until the disk has space again
+                    if state.disk.is_low() {
+                        continue;
+                    }
+
+                    //broadcast while the recording was subscribing, already written
+                    if primed_sequence.is_some() && frame_sequence(&packet) == primed_sequence {
+                        continue;
+                    }
+
+                    let Some((timestamp_us, jpeg)) = frame_payload(&packet) else {
+                        continue;
+                    };
+
+                    match &mut transcoder {
+                        //the raw frame of the packet, or a newer one when the pipeline is ahead
+                        Some(transcoder) => {
+                            if let Some(frame) = state.latest_raw() {
+                                let pts = Duration::from_micros(frame.timestamp_us.saturating_sub(start));
+                                transcoder.push(frame, pts);
+                            }
+                            continue;
+                        }
+                        None => muxer.mux(jpeg, Duration::from_micros(timestamp_us.saturating_sub(start))),
+                    }
+                }
+                None => break,
+            },
+            packet = recv_encoded(&mut transcoder) => match packet {
+                Some(packet) => muxer.mux_coded(&packet.data, packet.pts, packet.keyframe),
+                None => {
+                    state.stats.error();
+                    error!(%path, "The encoder of the recording stopped, the recording ends");
+                    transcoder = None;
+                    break;
+                }
+            },
+            packet = recv_audio(&mut audio) => match packet {
+                Ok(packet) => {
+                    if state.disk.is_low() {
+                        continue;
+                    }
+
+                    //skip packets mixed before the recording started
+                    let Some((pts, opus)) = audio_payload(&packet)
+                        .and_then(|(timestamp_us, opus)| Some((timestamp_us.checked_sub(start)?, opus)))
+                    else {
+                        continue;
+                    };
+
+                    muxer.mux_audio(opus, Duration::from_micros(pts))
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                //the encoder stopped, keep recording the frames
+                Err(RecvError::Closed) => {
+                    audio = None;
+                    continue;
+                }
+            },
+        };
+
+        if let Err(e) = file.write_all(&muxed).await {
+            state.stats.error();
+            error!(%path, error = %e, "Failed to write recording");
+            break;
+        }
+    }
+
+    //the frames the encoder still holds
+    if let Some(transcoder) = transcoder {
+        let mut muxed = Vec::new();
+        for packet in transcoder.finish().await {
+            muxed.extend(muxer.mux_coded(&packet.data, packet.pts, packet.keyframe));
+        }
+
+        if let Err(e) = file.write_all(&muxed).await {
+            state.stats.error();
+            error!(%path, error = %e, "Failed to write recording");
+        }
+    }
+
+    let _ = file.flush().await;
+
+    if rx.dropped() > 0 {
+        warn!(%path, dropped = rx.dropped(), "The recording could not keep up, frames are missing");
+    }
+
+    state.emit(ServerEvent::RecordingFinished { path });
+}
+
+/// The next packet of the transcoder, pending forever without one.
+async fn recv_encoded(transcoder: &mut Option<Transcoder>) -> Option<EncodedPacket> {
+    match transcoder {
+        Some(transcoder) => transcoder.recv().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,260 @@
+use std::sync::{Arc, Mutex as StdMutex};
+
+use futures::{FutureExt, StreamExt, future::BoxFuture};
+use serde::Deserialize;
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tracing::info;
+
+use crate::{
+    delta_frames::DeltaDecoder,
+    error::{Result, ShareScreenError},
+    frame_compressor::decode_qoi,
+    link_codec::LinkCodec,
+    packets::{FLAG_ENCRYPTED, PROTOCOL_VERSION, PacketType, packet_len, parse},
+    source::{CaptureSource, SourceDevice},
+};
+
+/// # Relay Source
+///
+/// Another share-screen instance to re-broadcast: its `/stream` is subscribed to and the frames are sent to the viewers
+/// of this instance as they were encoded upstream, so one capture host can fan out through relays on other networks.
+///
+/// The connection is opened again like an unplugged monitor when the upstream instance goes away.
+///
+/// Note: `With a codec negotiated to QOI or zstd the frames are decoded and encoded again by this instance, the upstream is spared the encodes
+/// and the LAN between them carries lossless frames.`
+pub struct RelaySource {
+    /// url of the upstream instance, like `http://192.168.1.20:5074`.
+    pub url: String,
+    /// one of the `[auth] tokens` of the upstream instance.
+    pub token: Option<String>,
+    /// codec requested from the upstream, see `LinkCodec::negotiate`.
+    pub codec: LinkCodec,
+}
+
+impl std::fmt::Display for RelaySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "relay {}", self.url)
+    }
+}
+
+impl SourceDevice for RelaySource {
+    fn open(&self) -> Result<Arc<dyn CaptureSource>> {
+        Ok(Arc::new(RelayStream::open(self)?))
+    }
+
+    fn can_reacquire(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct Dimensions {
+    width: u32,
+    height: u32,
+}
+
+/// The subscription to the upstream stream, read on its own thread.
+pub struct RelayStream {
+    dimensions: Arc<StdMutex<(u32, u32)>>,
+    /// the frames are the upstream's JPEGs, not QOI or zstd frames decoded to BGRA.
+    pre_encoded: bool,
+    frames: Mutex<mpsc::Receiver<Vec<u8>>>,
+    finished: Mutex<Option<oneshot::Receiver<Result<()>>>>,
+}
+
+impl RelayStream {
+    /// Connects to the upstream stream, returns once its dimensions are known.
+    fn open(source: &RelaySource) -> Result<Self> {
+        let base = reqwest::Url::parse(&source.url)
+            .map_err(|e| ShareScreenError::Server(format!("Invalid relay url {}: {e}", source.url)))?;
+
+        let (frames, receiver) = mpsc::channel(2);
+        let (opened, opened_receiver) = std::sync::mpsc::channel();
+        let (finished, finished_receiver) = oneshot::channel();
+        let dimensions = Arc::new(StdMutex::new((0, 0)));
+
+        let (token, codec) = (source.token.clone(), source.codec);
+        let shared = dimensions.clone();
+
+        //opening is synchronous, the subscription gets a runtime of its own
+        std::thread::Builder::new().name("relay".to_string()).spawn(move || {
+            let result = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(ShareScreenError::from)
+                .and_then(|runtime| runtime.block_on(subscribe(base, token, codec, shared, frames, opened)));
+
+            let _ = finished.send(result);
+        })?;
+
+        let codec = opened_receiver
+            .recv()
+            .map_err(|_| ShareScreenError::Capture("the relay could not connect".to_string()))?
+            .map_err(|reason| ShareScreenError::Activation {
+                device: format!("relay {}", source.url),
+                reason,
+            })?;
+
+        info!(url = %source.url, codec = codec.name(), "Relaying stream");
+
+        Ok(Self {
+            dimensions,
+            pre_encoded: !codec.is_raw(),
+            frames: Mutex::new(receiver),
+            finished: Mutex::new(Some(finished_receiver)),
+        })
+    }
+}
+
+impl CaptureSource for RelayStream {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        Ok(*self.dimensions.lock().unwrap())
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        async move { self.frames.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Some(finished) = self.finished.lock().await.take() else {
+                return Err(ShareScreenError::Capture("the relay is already running".to_string()));
+            };
+
+            finished
+                .await
+                .unwrap_or_else(|_| Err(ShareScreenError::Capture("the relay stopped".to_string())))
+        }
+        .boxed()
+    }
+
+    fn pre_encoded(&self) -> bool {
+        self.pre_encoded
+    }
+}
+
+/// Reads the upstream stream until it ends or the relay is dropped.
+///
+/// `opened` gets the reason the stream cannot be relayed, or the negotiated codec once it is connected.
+async fn subscribe(
+    base: reqwest::Url,
+    token: Option<String>,
+    codec: LinkCodec,
+    dimensions: Arc<StdMutex<(u32, u32)>>,
+    frames: mpsc::Sender<Vec<u8>>,
+    opened: std::sync::mpsc::Sender<std::result::Result<LinkCodec, String>>,
+) -> Result<()> {
+    let client = reqwest::Client::new();
+
+    let mut stream_url = base.clone();
+    stream_url.set_path("/stream");
+    let codec = codec.negotiate(&client, &stream_url, token.as_deref()).await;
+
+    let request = |path: &str| {
+        let mut url = base.clone();
+        url.set_path(path);
+
+        let request = client.get(url);
+        match &token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    };
+
+    let connected = async {
+        let initial: Dimensions = request("/stream/dimensions")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let mut stream = request("/stream").query(&[("version", PROTOCOL_VERSION.to_string())]);
+        if codec.is_raw() {
+            stream = stream.query(&[("codec", codec.name())]);
+        }
+
+        let stream = stream.send().await?.error_for_status()?;
+
+        Ok::<_, reqwest::Error>((initial, stream))
+    };
+
+    let response = match connected.await {
+        Ok((initial, response)) => {
+            *dimensions.lock().unwrap() = (initial.width, initial.height);
+            let _ = opened.send(Ok(codec));
+
+            response
+        }
+        Err(e) => {
+            let _ = opened.send(Err(e.to_string()));
+            return Err(ShareScreenError::Capture(e.to_string()));
+        }
+    };
+
+    let mut body = response.bytes_stream();
+    let mut buffer = Vec::new();
+    let mut deltas = DeltaDecoder::default();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| ShareScreenError::Capture(format!("the upstream stream failed: {e}")))?;
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(length) = packet_len(&buffer) {
+            let (header, payload) = parse(&buffer[..length])
+                .ok_or_else(|| ShareScreenError::Capture("the upstream sent a malformed packet".to_string()))?;
+
+            if header.flags & FLAG_ENCRYPTED != 0 {
+                return Err(ShareScreenError::Capture(
+                    "the upstream stream is encrypted, it cannot be relayed".to_string(),
+                ));
+            }
+
+            match header.kind {
+                //this instance is behind, the frame is skipped before it is decoded (a delta has to be decoded for the next one)
+                PacketType::Frame if codec == LinkCodec::Qoi && frames.capacity() == 0 => {}
+                PacketType::Frame => {
+                    let decoded = match codec {
+                        LinkCodec::Qoi => Some(
+                            decode_qoi(payload)
+                                .ok_or_else(|| ShareScreenError::Capture("the upstream sent a malformed qoi frame".to_string()))?,
+                        ),
+                        //none for a delta of a frame that was skipped, until the next keyframe
+                        LinkCodec::Zstd => deltas.decode(payload),
+                        _ => None,
+                    };
+
+                    let frame = match decoded {
+                        Some((width, height, raw_bgra)) => {
+                            //the raw frame has to match the dimensions the pipeline reads
+                            *dimensions.lock().unwrap() = (width, height);
+                            raw_bgra
+                        }
+                        None if codec.is_raw() => {
+                            buffer.drain(..length);
+                            continue;
+                        }
+                        None => payload.to_vec(),
+                    };
+
+                    match frames.try_send(frame) {
+                        //this instance is behind, the frame is skipped
+                        Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => {}
+                        Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+                    }
+                }
+                PacketType::Dimensions if payload.len() == 8 => {
+                    let width = u32::from_le_bytes(payload[..4].try_into().unwrap());
+                    let height = u32::from_le_bytes(payload[4..].try_into().unwrap());
+                    *dimensions.lock().unwrap() = (width, height);
+                }
+                _ => {}
+            }
+
+            buffer.drain(..length);
+        }
+    }
+
+    Err(ShareScreenError::Capture("the upstream stream ended".to_string()))
+}
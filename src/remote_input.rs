@@ -0,0 +1,424 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::broadcast::error::RecvError,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::{
+    Message,
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+use crate::{
+    auth::{Role, constant_time_eq},
+    events::ServerEvent,
+    gateway::{ACCEPT_RETRY, Gateway},
+    request_params::query_string_param,
+    state::StreamState,
+};
+
+/// Path the remote input channel is served at.
+pub const INPUT_PATH: &str = "/ws/input";
+
+/// `[remote_input]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RemoteInputConfig {
+    pub enabled: bool,
+    pub port: u16,
+    /// clients connect with `?token=` set to it or to one of the `[auth]` tokens that control the stream, the channel is not
+    /// served without either.
+    pub token: Option<String>,
+}
+
+impl Default for RemoteInputConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 8082,
+            token: None,
+        }
+    }
+}
+
+/// # Input Event
+///
+/// A mouse or keyboard event sent by the viewer in control as json, for example `{"type": "mouse_move", "x": 0.5, "y": 0.25}`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+    /// position over the shared frame, from 0.0 (left, top) to 1.0 (right, bottom).
+    MouseMove { x: f64, y: f64 },
+    MouseButton { button: MouseButton, down: bool },
+    /// wheel rotation, 120 per notch and positive away from the user.
+    Wheel { delta: i32 },
+    /// a windows virtual-key code.
+    Key { code: u16, down: bool },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// A message sent to the client in control, only errors are replied, not every event.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum InputMessage {
+    Error { error: String },
+}
+
+/// The keys and buttons the viewer in control holds down, released when it disconnects so none stays down on the host.
+#[derive(Default)]
+struct Pressed {
+    keys: HashSet<u16>,
+    buttons: HashSet<MouseButton>,
+}
+
+impl Pressed {
+    fn track(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::Key { code, down } => match down {
+                true => self.keys.insert(code),
+                false => self.keys.remove(&code),
+            },
+            InputEvent::MouseButton { button, down } => match down {
+                true => self.buttons.insert(button),
+                false => self.buttons.remove(&button),
+            },
+            _ => false,
+        };
+    }
+
+    fn release(&mut self) {
+        let keys = self.keys.drain().map(|code| InputEvent::Key { code, down: false });
+        let buttons = self.buttons.drain().map(|button| InputEvent::MouseButton { button, down: false });
+
+        for event in keys.chain(buttons) {
+            if let Err(error) = inject(&event, None) {
+                warn!(?event, %error, "Failed to release a key or a button of the viewer in control");
+            }
+        }
+    }
+}
+
+/// # Spawn Remote Input
+///
+/// Listens for WebSocket connections at `wss://<ip>:<port>/ws/input` with the certificate of `[tls]`, the viewer in control sends
+/// `InputEvent`s that are injected on the host as if they came from its own mouse and keyboard.
+///
+/// Note: `Strictly opt-in, the channel needs [remote_input] enabled, [tls] and a token, and refuses every client until the host allows
+/// remote input for the session (POST /api/remote-input/allow). A single viewer controls at a time.`
+pub fn spawn_remote_input(
+    config: RemoteInputConfig,
+    ip: IpAddr,
+    tls: Option<TlsAcceptor>,
+    gateway: Arc<Gateway>,
+    state: Arc<StreamState>,
+) {
+    if !config.enabled {
+        return;
+    }
+
+    if config.token.is_none() && !state.auth.enabled() {
+        state.stats.error();
+        error!("Remote input needs a [remote_input] token or [auth] tokens, the channel was not started");
+        return;
+    }
+
+    let Some(tls) = tls else {
+        state.stats.error();
+        error!("Remote input is only served over [tls], the channel was not started");
+        return;
+    };
+
+    if !cfg!(windows) {
+        state.stats.error();
+        error!("Remote input is only supported on Windows, the channel was not started");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind((ip, config.port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                state.stats.error();
+                error!(port = config.port, error = %e, "Failed to bind the remote input channel");
+                return;
+            }
+        };
+
+        info!("Remote input channel at wss://{ip}:{}{INPUT_PATH}", config.port);
+
+        let in_control = Arc::new(AtomicBool::new(false));
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "Remote input accept failed");
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                    continue;
+                }
+            };
+
+            if !gateway.allows(peer.ip()) {
+                debug!(%peer, "Refused a remote input client outside of the [access] lists");
+                continue;
+            }
+
+            let Some(permit) = gateway.admit(peer.ip()) else {
+                warn!(%peer, "Refused a remote input client, the ip has too many connections open");
+                continue;
+            };
+
+            let (tls, token, in_control, state) = (tls.clone(), config.token.clone(), in_control.clone(), state.clone());
+
+            tokio::spawn(
+                async move {
+                    //held until the connection closes
+                    let _permit = permit;
+
+                    match tls.accept(stream).await {
+                        Ok(stream) => handle_client(stream, token, in_control, state).await,
+                        Err(e) => debug!(error = %e, "Remote input TLS handshake failed"),
+                    }
+                }
+                .instrument(info_span!("input_client", %peer)),
+            );
+        }
+    });
+}
+
+/// Performs the handshake and injects the events of a single client until it disconnects or remote input is revoked.
+async fn handle_client<S>(stream: S, token: Option<String>, in_control: Arc<AtomicBool>, state: Arc<StreamState>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let authorize = |req: &Request, res: Response| -> Result<Response, ErrorResponse> {
+        if req.uri().path() != INPUT_PATH {
+            return Err(error_response(StatusCode::NOT_FOUND, "not found"));
+        }
+
+        //the [remote_input] token, or an [auth] token of a controller
+        let authorized = query_string_param(req.uri().query().unwrap_or_default(), "token").is_some_and(|given| {
+            token.as_deref().is_some_and(|token| constant_time_eq(token, &given))
+                || state.auth.token_role(&given).is_some_and(|role| role >= Role::Controller)
+        });
+
+        if !authorized {
+            return Err(error_response(StatusCode::UNAUTHORIZED, "invalid token"));
+        }
+
+        if !state.control.is_remote_input_allowed() {
+            return Err(error_response(StatusCode::FORBIDDEN, "the host did not allow remote input"));
+        }
+
+        Ok(res)
+    };
+
+    let socket = match tokio_tungstenite::accept_hdr_async(stream, authorize).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(error = %e, "Remote input handshake failed");
+            return;
+        }
+    };
+
+    let (mut sink, mut incoming) = socket.split();
+
+    if in_control.swap(true, Ordering::AcqRel) {
+        let _ = send(&mut sink, "another viewer is in control").await;
+        let _ = sink.send(Message::Close(None)).await;
+        return;
+    }
+
+    info!("Remote input started");
+    let mut events = state.events.subscribe();
+    let mut pressed = Pressed::default();
+
+    loop {
+        tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    //revoked between two events, the events are not read anymore
+                    if !state.control.is_remote_input_allowed() {
+                        break;
+                    }
+
+                    if let Err(error) = handle_event(&text, &state, &mut pressed)
+                        && send(&mut sink, error).await.is_err()
+                    {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    warn!(error = %e, "Remote input channel error");
+                    break;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) if matches!(event.kind, ServerEvent::RemoteInputRevoked) => break,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = state.shutting_down() => break,
+        }
+    }
+
+    //released before another viewer can take the control
+    pressed.release();
+
+    let _ = sink.send(Message::Close(None)).await;
+    in_control.store(false, Ordering::Release);
+    info!("Remote input stopped");
+}
+
+fn handle_event(text: &str, state: &StreamState, pressed: &mut Pressed) -> Result<(), String> {
+    let event = match serde_json::from_str::<InputEvent>(text).map_err(|e| format!("invalid event: {e}"))? {
+        //the viewers point at the rotated, flipped and cropped frames
+        InputEvent::MouseMove { x, y } => {
+            let (x, y) = state.autocrop.to_uncropped(x.clamp(0.0, 1.0), y.clamp(0.0, 1.0));
+            let (x, y) = state.transform.to_source(x, y);
+            InputEvent::MouseMove { x, y }
+        }
+        event => event,
+    };
+
+    inject(&event, state.source().monitor())?;
+    pressed.track(&event);
+
+    Ok(())
+}
+
+async fn send<S>(sink: &mut S, error: impl Into<String>) -> Result<(), S::Error>
+where
+    S: futures::Sink<Message> + Unpin,
+{
+    let message = InputMessage::Error { error: error.into() };
+    let json = serde_json::to_string(&message).unwrap_or_default();
+
+    sink.send(Message::text(json)).await
+}
+
+fn error_response(status: StatusCode, message: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(message.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// # Inject
+///
+/// Sends the event to the input queue of the host with `SendInput`, the positions are mapped onto the shared monitor.
+#[cfg(windows)]
+fn inject(event: &InputEvent, monitor: Option<i32>) -> Result<(), String> {
+    use windows::Win32::UI::{
+        Input::KeyboardAndMouse::{
+            INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBD_EVENT_FLAGS, KEYBDINPUT, KEYEVENTF_KEYUP,
+            MOUSE_EVENT_FLAGS, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
+            MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN,
+            MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_VIRTUALDESK, MOUSEEVENTF_WHEEL, MOUSEINPUT, SendInput,
+            VIRTUAL_KEY,
+        },
+        WindowsAndMessaging::{
+            GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN,
+        },
+    };
+
+    let mouse = |dx: i32, dy: i32, data: i32, flags: MOUSE_EVENT_FLAGS| INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx,
+                dy,
+                mouseData: data,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
+
+    let input = match *event {
+        InputEvent::MouseMove { x, y } => {
+            let monitor = monitor.ok_or("the shared source is not a monitor, the mouse cannot be moved")?;
+            let area = crate::platform::windows::monitor_area(monitor.max(0) as usize)
+                .map_err(|e| e.to_string())?
+                .ok_or("the shared monitor was disconnected")?;
+
+            //absolute positions go from 0 to 65535 over the whole virtual desktop
+            let (desktop_x, desktop_y, desktop_width, desktop_height) = unsafe {
+                (
+                    GetSystemMetrics(SM_XVIRTUALSCREEN),
+                    GetSystemMetrics(SM_YVIRTUALSCREEN),
+                    GetSystemMetrics(SM_CXVIRTUALSCREEN).max(2),
+                    GetSystemMetrics(SM_CYVIRTUALSCREEN).max(2),
+                )
+            };
+
+            let x = area.left as f64 + x.clamp(0.0, 1.0) * (area.right - area.left - 1) as f64;
+            let y = area.top as f64 + y.clamp(0.0, 1.0) * (area.bottom - area.top - 1) as f64;
+
+            mouse(
+                ((x - desktop_x as f64) * 65535.0 / (desktop_width - 1) as f64).round() as i32,
+                ((y - desktop_y as f64) * 65535.0 / (desktop_height - 1) as f64).round() as i32,
+                0,
+                MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK,
+            )
+        }
+        InputEvent::MouseButton { button, down } => {
+            let flags = match (button, down) {
+                (MouseButton::Left, true) => MOUSEEVENTF_LEFTDOWN,
+                (MouseButton::Left, false) => MOUSEEVENTF_LEFTUP,
+                (MouseButton::Right, true) => MOUSEEVENTF_RIGHTDOWN,
+                (MouseButton::Right, false) => MOUSEEVENTF_RIGHTUP,
+                (MouseButton::Middle, true) => MOUSEEVENTF_MIDDLEDOWN,
+                (MouseButton::Middle, false) => MOUSEEVENTF_MIDDLEUP,
+            };
+
+            mouse(0, 0, 0, flags)
+        }
+        InputEvent::Wheel { delta } => mouse(0, 0, delta, MOUSEEVENTF_WHEEL),
+        InputEvent::Key { code, down } => INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(code),
+                    wScan: 0,
+                    dwFlags: if down { KEYBD_EVENT_FLAGS(0) } else { KEYEVENTF_KEYUP },
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        },
+    };
+
+    //nothing is inserted when the input is blocked, by a window of a higher integrity level for example
+    match unsafe { SendInput(&[input], std::mem::size_of::<INPUT>() as i32) } {
+        0 => Err("the host blocked the input".to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(windows))]
+fn inject(_event: &InputEvent, _monitor: Option<i32>) -> Result<(), String> {
+    Err("remote input is only supported on Windows".to_string())
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    containers::{Muxer, fmp4::Fmp4Muxer},
+    error::{Result, ShareScreenError},
+    recorder::RECORDINGS_FOLDER,
+    session::unix_now,
+};
+
+/// Seconds of frames kept when none are configured.
+pub const DEFAULT_REPLAY_SECONDS: u64 = 30;
+
+/// # Replay Buffer
+///
+/// Ring buffer of the most recent encoded frames, so the last seconds of the stream can be saved after the fact.
+///
+/// Frames older than the window are dropped as new frames arrive.
+pub struct ReplayBuffer {
+    window: Duration,
+    frames: Mutex<VecDeque<(Instant, Arc<Vec<u8>>)>>,
+}
+
+impl ReplayBuffer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            frames: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// How far back the buffer reaches.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Adds an encoded JPEG frame to the buffer.
+    pub fn push(&self, jpeg: Arc<Vec<u8>>) {
+        if self.window.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut frames = self.frames.lock().unwrap();
+
+        frames.push_back((now, jpeg));
+
+        while let Some((captured, _)) = frames.front() {
+            if now.duration_since(*captured) <= self.window {
+                break;
+            }
+
+            frames.pop_front();
+        }
+    }
+
+    /// Frames of the last `duration` (clamped to the window), oldest first.
+    pub fn frames_since(&self, duration: Duration) -> Vec<(Instant, Arc<Vec<u8>>)> {
+        let frames = self.frames.lock().unwrap();
+        let now = Instant::now();
+
+        frames
+            .iter()
+            .filter(|(captured, _)| now.duration_since(*captured) <= duration)
+            .cloned()
+            .collect()
+    }
+
+    /// Default path of a saved replay: `recordings/replay-<unix time>.mp4`
+    pub fn default_path() -> String {
+        format!("{RECORDINGS_FOLDER}/replay-{}.mp4", unix_now())
+    }
+
+    /// # Save replay
+    ///
+    /// Writes the frames of the whole window to an mp4 file at the path, returns the amount of frames written.
+    pub async fn save(&self, path: &str, width: u32, height: u32) -> Result<usize> {
+        let frames = self.frames_since(self.window);
+
+        let Some((first, _)) = frames.first() else {
+            return Err(ShareScreenError::Server("The replay buffer is empty.".to_string()));
+        };
+        let first = *first;
+
+        let mut muxer = Fmp4Muxer::new(width, height);
+        let mut out = muxer.header();
+
+        for (captured, jpeg) in &frames {
+            out.extend(muxer.mux(jpeg, captured.duration_since(first)));
+        }
+
+        if let Some(parent) = std::path::Path::new(path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = tokio::fs::File::create(path).await?;
+        file.write_all(&out).await?;
+        file.flush().await?;
+
+        Ok(frames.len())
+    }
+}
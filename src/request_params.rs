@@ -0,0 +1,117 @@
+use axum::http::request::Parts;
+
+/// # Query Parameter
+///
+/// Gets the value of a query parameter from the request url, for example `container` in `/stream?container=mp4`.
+///
+/// A parameter without a value (`?debug`) results in an empty string.
+pub fn query_param(req: &Parts, name: &str) -> Option<String> {
+    query_string_param(req.uri.query()?, name)
+}
+
+/// Gets the value of a parameter from a query string (without the `?`), percent-decoded with `+` as a space like browsers encode forms.
+pub fn query_string_param(query: &str, name: &str) -> Option<String> {
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// # Header
+///
+/// Gets the value of a request header, header names are compared case insensitively.
+///
+/// Note: `Values that are not visible ASCII are skipped.`
+pub fn header(req: &Parts, name: &str) -> Option<String> {
+    req.headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// # Host
+///
+/// The host the client reached the server with, from the `Host` header or the authority of HTTP/2 requests (which have no `Host` header).
+pub fn host(req: &Parts) -> Option<String> {
+    header(req, "Host").or_else(|| req.uri.authority().map(|authority| authority.to_string()))
+}
+
+/// # Scheme
+///
+/// `https` or `http`, how the client reached the server: the gateway sets `X-Forwarded-Proto` from the connection, or keeps the one
+/// of the reverse proxy with `[access] trust_forwarded`.
+pub fn scheme(req: &Parts) -> &'static str {
+    match header(req, "X-Forwarded-Proto").is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https")) {
+        true => "https",
+        false => "http",
+    }
+}
+
+/// # Cookie
+///
+/// Gets the value of a cookie sent with the request, in any of its `Cookie` headers.
+pub fn cookie(req: &Parts, name: &str) -> Option<String> {
+    req.headers
+        .get_all("Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// # Bearer Token
+///
+/// The token of the request, sent as `Authorization: Bearer <token>` or with the `?token=` query parameter (for players and `<img>` tags that cannot set headers).
+pub fn bearer_token(req: &Parts) -> Option<String> {
+    header(req, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string))
+        .or_else(|| query_param(req, "token"))
+}
+
+/// Client ip of requests that did not come through the gateway.
+pub const UNKNOWN_IP: &str = "unknown";
+
+/// # Client IP
+///
+/// The address of the client that sent the request, taken from the `X-Forwarded-For` (last entry) or `X-Real-IP` header.
+/// The last entry is the one the reverse proxy appended, the ones before it come from the client and can be anything.
+///
+/// Note: `The gateway sets X-Real-IP to the address of the connection and drops the X-Forwarded-For of clients unless [access] trust_forwarded is set.`
+pub fn client_ip(req: &Parts) -> String {
+    header(req, "X-Forwarded-For")
+        .and_then(|forwarded| forwarded.rsplit(',').next().map(|ip| ip.trim().to_string()))
+        .or_else(|| header(req, "X-Real-IP").map(|ip| ip.trim().to_string()))
+        .filter(|ip| !ip.is_empty())
+        .unwrap_or_else(|| UNKNOWN_IP.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn request(headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::builder().uri("/stream");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn takes_the_client_ip_the_proxy_appended() {
+        let req = request(&[("X-Forwarded-For", "6.6.6.6, 203.0.113.7"), ("X-Real-IP", "10.0.0.1")]);
+        assert_eq!(client_ip(&req), "203.0.113.7");
+
+        let req = request(&[("X-Forwarded-For", " 203.0.113.7 ")]);
+        assert_eq!(client_ip(&req), "203.0.113.7");
+    }
+
+    #[test]
+    fn falls_back_to_the_address_of_the_connection() {
+        assert_eq!(client_ip(&request(&[("X-Real-IP", "10.0.0.1")])), "10.0.0.1");
+        assert_eq!(client_ip(&request(&[])), UNKNOWN_IP);
+    }
+}
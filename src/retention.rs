@@ -0,0 +1,226 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::RecordingConfig,
+    events::ServerEvent,
+    platform::free_space,
+    recorder::{RECORDINGS_FOLDER, RecordingFormat},
+    snapshots::SnapshotsConfig,
+    state::StreamState,
+};
+
+/// Time between two checks of the free space and of the recordings kept.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Megabytes free beyond `min_free_mb` before the writing resumes, a recording does not pause and resume at every check.
+const RESUME_MARGIN_MB: u64 = 256;
+
+/// `[disk]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DiskConfig {
+    /// megabytes left free on the volume of the recordings and the snapshots, the writing pauses under it, 0 never pauses it.
+    pub min_free_mb: u64,
+}
+
+impl Default for DiskConfig {
+    fn default() -> Self {
+        Self { min_free_mb: 1024 }
+    }
+}
+
+/// # Disk Guard
+///
+/// Whether the volume of the recordings or the snapshots ran low on space, the recordings skip the frames and the archive the snapshots
+/// while it is, so the drive never fills up mid session.
+#[derive(Default)]
+pub struct DiskGuard {
+    low: AtomicBool,
+}
+
+impl DiskGuard {
+    /// Whether the recordings and the snapshots are paused for the lack of free space.
+    pub fn is_low(&self) -> bool {
+        self.low.load(Ordering::Relaxed)
+    }
+}
+
+/// A recording of the recordings folder, for its retention.
+struct RecordingFile {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// # Spawn Storage Guard
+///
+/// Spawns a task that every thirty seconds deletes the recordings past `[recording] max_days` and the oldest ones over `max_mb`,
+/// then checks the free space of the volumes the recordings and the snapshots are written to. Once one has less than `[disk] min_free_mb`
+/// the recordings and the snapshots are paused and a `disk_space_low` event is sent, they resume with a `disk_space_recovered` event
+/// when the space is back.
+///
+/// Note: `Only the mp4, mkv and webm files of the recordings folder are deleted (the recordings named automatically and the replays),
+/// never the running recording. A recording paused keeps its file, the frames of the pause are missing from it.`
+pub fn spawn_storage_guard(disk: DiskConfig, recording: RecordingConfig, snapshots: SnapshotsConfig, state: Arc<StreamState>) {
+    let retention = recording.max_days > 0 || recording.max_mb > 0;
+
+    if !retention && disk.min_free_mb == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let active = state.recorder.status().await.path;
+
+            let (recording, snapshots, min_free_mb) = (recording.clone(), snapshots.clone(), disk.min_free_mb);
+            let checked = tokio::task::spawn_blocking(move || {
+                if retention && let Err(e) = prune_recordings(&recording, active.as_deref()) {
+                    error!(error = %e, "Failed to delete the recordings past the retention");
+                }
+
+                if min_free_mb == 0 {
+                    return None;
+                }
+
+                let recordings_dir = match active.as_deref().and_then(|path| Path::new(path).parent()) {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                    Some(_) => PathBuf::from("."),
+                    None => PathBuf::from(RECORDINGS_FOLDER),
+                };
+                let mut dirs = vec![recordings_dir];
+                if snapshots.enabled {
+                    dirs.push(PathBuf::from(&snapshots.dir));
+                }
+
+                //the lowest of the volumes written to, a directory not created yet is on the volume of its parent
+                dirs.into_iter()
+                    .filter_map(|dir| {
+                        let existing = dir
+                            .ancestors()
+                            .find(|dir| !dir.as_os_str().is_empty() && dir.exists())
+                            .unwrap_or(Path::new("."));
+
+                        match free_space(existing) {
+                            Ok(free) => Some((dir, free)),
+                            Err(e) => {
+                                warn!(dir = %dir.display(), error = %e, "Failed to read the free space");
+                                None
+                            }
+                        }
+                    })
+                    .min_by_key(|(_, free)| *free)
+            })
+            .await;
+
+            match checked {
+                Ok(Some((dir, free))) => check_free_space(&disk, &dir, free, &state),
+                Ok(None) => {}
+                Err(e) => warn!(error = %e, "The storage check failed"),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+        }
+    });
+}
+
+/// Pauses the writing when the free space fell under `min_free_mb`, resumes it once it is back over it by the margin.
+fn check_free_space(disk: &DiskConfig, dir: &Path, free: u64, state: &StreamState) {
+    let free_mb = free / 1024 / 1024;
+    let path = dir.display().to_string();
+
+    if !state.disk.is_low() && free_mb < disk.min_free_mb {
+        state.disk.low.store(true, Ordering::Relaxed);
+        warn!(%path, free_mb, min_free_mb = disk.min_free_mb, "The disk is low on space, pausing the recordings and the snapshots");
+        state.emit(ServerEvent::DiskSpaceLow { path, free_mb });
+    } else if state.disk.is_low() && free_mb >= disk.min_free_mb + RESUME_MARGIN_MB {
+        state.disk.low.store(false, Ordering::Relaxed);
+        info!(%path, free_mb, "The disk has space again, resuming the recordings and the snapshots");
+        state.emit(ServerEvent::DiskSpaceRecovered { path, free_mb });
+    }
+}
+
+/// Deletes the recordings older than `max_days`, then the oldest ones until the folder fits in `max_mb`, the active one excepted.
+/// A recording that cannot be deleted (open in a player) is logged and skipped, it still takes its space.
+fn prune_recordings(config: &RecordingConfig, active: Option<&str>) -> std::io::Result<()> {
+    let mut recordings = recordings(active)?;
+    //bytes of the recordings past max_days left on the disk
+    let mut undeleted = 0;
+
+    if config.max_days > 0 {
+        let oldest = SystemTime::now() - Duration::from_secs(u64::from(config.max_days) * 24 * 60 * 60);
+
+        for recording in recordings.extract_if(.., |recording| recording.modified < oldest) {
+            info!(path = %recording.path.display(), "Deleting a recording past [recording] max_days");
+
+            if let Err(e) = std::fs::remove_file(&recording.path) {
+                warn!(path = %recording.path.display(), error = %e, "Failed to delete a recording");
+                undeleted += recording.size;
+            }
+        }
+    }
+
+    if config.max_mb == 0 {
+        return Ok(());
+    }
+
+    let max_bytes = config.max_mb * 1024 * 1024;
+    let mut total: u64 = undeleted + recordings.iter().map(|recording| recording.size).sum::<u64>();
+
+    //oldest first
+    for recording in recordings {
+        if total <= max_bytes {
+            break;
+        }
+
+        info!(path = %recording.path.display(), "Deleting a recording over [recording] max_mb");
+
+        match std::fs::remove_file(&recording.path) {
+            Ok(()) => total -= recording.size,
+            Err(e) => warn!(path = %recording.path.display(), error = %e, "Failed to delete a recording"),
+        }
+    }
+
+    Ok(())
+}
+
+/// The mp4, mkv and webm files of the recordings folder but the active recording, oldest first.
+fn recordings(active: Option<&str>) -> std::io::Result<Vec<RecordingFile>> {
+    let entries = match std::fs::read_dir(RECORDINGS_FOLDER) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let active = active.and_then(|path| std::fs::canonicalize(path).ok());
+
+    let mut recordings: Vec<RecordingFile> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().to_str().and_then(RecordingFormat::from_path).is_some())
+        .filter(|entry| active.is_none() || std::fs::canonicalize(entry.path()).ok() != active)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+
+            Some(RecordingFile {
+                path: entry.path(),
+                modified: metadata.modified().ok()?,
+                size: metadata.len(),
+            })
+        })
+        .collect();
+
+    recordings.sort_by_key(|recording| recording.modified);
+
+    Ok(recordings)
+}
@@ -0,0 +1,408 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Request, State},
+    http::{Uri, request::Parts},
+    middleware,
+    response::IntoResponse,
+    routing::get,
+};
+use tower::ServiceExt;
+use tracing::warn;
+
+use crate::{
+    admin, aggregate, api, assets,
+    audio::opus::AudioResolution,
+    audit,
+    auth::{self, guard},
+    bytes_resolution::BytesResolution,
+    cameras,
+    capabilities::Capabilities,
+    chat,
+    config::Config,
+    containers::StreamContainer,
+    encryption::plaintext_guard,
+    files,
+    event_stream::EventStreamResolution,
+    frame_compressor::{encode_png, encode_thumbnail},
+    gif_export::{DEFAULT_GIF_SECONDS, DEFAULT_GIF_WIDTH, encode_gif},
+    health::Health,
+    oidc,
+    openapi::{API_V1, OPENAPI_PATH},
+    packets::negotiate_version,
+    pipeline_stats::RuntimeStats,
+    presets::{StreamPacing, StreamPreset},
+    rate_limit::StreamLimiter,
+    request_params::{header, query_param},
+    snapshots,
+    state::StreamState,
+    streamed_resolution::{BandwidthCap, StreamedResolution},
+    static_files,
+    thumbnails::DEFAULT_THUMBNAIL_WIDTH,
+    viewers::ViewerClient,
+};
+
+/// # Router
+///
+/// The routes of the web app, serving the embedded viewer, the api and the streamed resolutions,
+/// with the `[cors]` and `[compression]` layers around them.
+///
+/// Note: `The /api/v1/* paths are rewritten to the /api/* routes before they are routed, the unversioned paths stay for the clients
+/// that already use them.`
+pub fn router(state: Arc<StreamState>, config: &Config) -> Router {
+    let mut router = Router::new()
+        .merge(assets::viewer_routes())
+        .merge(auth::login_routes())
+        .merge(oidc::oidc_routes())
+        .merge(static_files::static_routes(config.static_files.clone()))
+        .merge(api::api_routes())
+        .merge(files::file_routes(config.files.clone()))
+        .merge(chat::chat_routes(config.chat.clone(), config.admin.clone()))
+        .merge(admin::admin_routes(config.admin.clone()))
+        .merge(snapshots::snapshot_routes(config.snapshots.clone(), config.admin.clone()))
+        .merge(aggregate::aggregate_routes(config.aggregate.clone()))
+        .merge(stream_routes(config))
+        .merge(cameras::camera_routes(&state, config))
+        .layer(middleware::from_fn_with_state(state.clone(), audit::audit_requests))
+        .with_state(state);
+
+    if let Some(cors) = config.cors.layer() {
+        router = router.layer(cors);
+    }
+
+    if let Some(compression) = config.compression.layer() {
+        router = router.layer(compression);
+    }
+
+    //the layers of a router run after its routing, the path is rewritten by a router around it
+    Router::new().fallback_service(router.map_request(unversioned))
+}
+
+/// `/api/v1/<route>` as the `/api/<route>` it is served by, the other paths as they are.
+fn unversioned(mut req: Request) -> Request {
+    let path = req.uri().path();
+
+    let Some(route) = path.strip_prefix(API_V1).filter(|route| route.starts_with('/') && path != OPENAPI_PATH) else {
+        return req;
+    };
+
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("/api{route}?{query}"),
+        None => format!("/api{route}"),
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+
+    req
+}
+
+/// # Stream Routes
+///
+/// The still images, the streamed resolutions and the stream metadata.
+pub fn stream_routes(config: &Config) -> Router<Arc<StreamState>> {
+    //frames are only expected with the watchdog on
+    let stall_timeout = config
+        .watchdog
+        .enabled
+        .then(|| std::time::Duration::from_secs(config.watchdog.stall_seconds.max(1)));
+
+    //streamed content of the device, the container is picked from ?container= or the Accept header, ?audio=1 muxes the audio into mp4.
+    //?preset= paces the frames of this stream (low-latency, balanced or quality), the one of --preset otherwise.
+    //?max_kbps= caps the bandwidth of this stream, whole frames are dropped to stay under it.
+    //?codec=qoi or png streams lossless frames (raw container only), encoded once more while a stream asks for them.
+    //POST is used by the bundled viewer, GET lets players like mpv/ffplay open the url directly.
+    //shared by both methods, a client alternating between them still gets limited
+    let stream_limiter = Arc::new(StreamLimiter::new(config.rate_limit.streams_per_minute));
+    let viewers_config = config.viewers.clone();
+
+    let stream = move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+        if let Err(denied) = guard(&state, &req) {
+            return denied;
+        }
+
+        let container = match StreamContainer::negotiate(query_param(&req, "container").as_deref(), header(&req, "Accept").as_deref()) {
+            Ok(container) => container,
+            Err(e) => return BytesResolution::text(400, e).into_response(),
+        };
+        let audio = query_param(&req, "audio").is_some_and(|audio| matches!(audio.as_str(), "1" | "true"));
+        let version = query_param(&req, "version");
+        let preset = match query_param(&req, "preset").map(|preset| preset.parse::<StreamPreset>()) {
+            Some(Ok(preset)) => Some(preset),
+            Some(Err(e)) => return BytesResolution::text(400, e).into_response(),
+            None => state.preset,
+        };
+        let cap = match query_param(&req, "max_kbps").map(|kbps| kbps.parse::<u32>()) {
+            Some(Ok(kbps)) => BandwidthCap::from_kbps(kbps),
+            Some(Err(_)) => return BytesResolution::text(400, "Expected ?max_kbps= in kilobits per second").into_response(),
+            None => None,
+        };
+        //the codec of the stream is the default, the others are streamed from their feed
+        let feed = match query_param(&req, "codec").filter(|codec| !codec.eq_ignore_ascii_case(state.encoder.name())) {
+            Some(codec) => match state.codecs.get(&codec) {
+                Some(feed) if container == StreamContainer::Raw => Some(feed),
+                Some(_) => return BytesResolution::text(400, "?codec= is only streamed in the raw container").into_response(),
+                None => {
+                    return BytesResolution::text(
+                        400,
+                        format!("Unknown codec {codec}, expected one of: {}, {}", state.encoder.name(), state.codecs.names().join(", ")),
+                    )
+                    .into_response();
+                }
+            },
+            None => None,
+        };
+        let client = ViewerClient::from_request(&req, container.name());
+
+        //the packet framing only applies to the raw container, the rest carry their own
+        if container == StreamContainer::Raw
+            && let Err(e) = negotiate_version(version.as_deref())
+        {
+            return BytesResolution::text(400, e).into_response();
+        }
+
+        //mp4 carries the frames in the clear
+        if container != StreamContainer::Raw
+            && let Err(denied) = plaintext_guard(&state)
+        {
+            return denied;
+        }
+
+        if state.viewers.is_banned(&client.ip) {
+            return BytesResolution::text(403, "You were banned from this stream").into_response();
+        }
+
+        if let Err(retry_after) = stream_limiter.check(&client.ip) {
+            warn!(ip = %client.ip, "Refused a stream, too many requests");
+
+            return BytesResolution::text(429, "Too many streams requested, try again later")
+                .with_header("Retry-After", (retry_after.as_secs() + 1).to_string())
+                .into_response();
+        }
+
+        //refuse new viewers instead of slowing the stream down for everyone
+        if !viewers_config.has_room(state.stats.viewers()) {
+            return BytesResolution::text(
+                503,
+                format!("The stream is full ({} viewers), try again later", viewers_config.max),
+            )
+            .into_response();
+        }
+
+        let pacing = StreamPacing::new(preset).with_cap(cap);
+
+        //the frames of another codec come from its feed, in the packet framing only
+        if let Some(feed) = feed {
+            return StreamedResolution::from_receiver(feed.frames.subscribe(), state.clone())
+                .for_client(client)
+                .with_pacing(pacing)
+                .for_codec(feed.encoder.name())
+                .into_response();
+        }
+
+        let rx = state.frames.subscribe();
+
+        container.resolution(rx, state.dimensions(), audio, pacing, client, state.clone())
+    };
+
+    Router::new()
+        //most recent frame as a still image
+        .route(
+            "/snapshot.jpg",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                if let Err(denied) = plaintext_guard(&state) {
+                    return denied;
+                }
+
+                match state.latest_frame() {
+                    Some(jpeg) => BytesResolution::new(jpeg.to_vec(), "image/jpeg").into_response(),
+                    None => BytesResolution::text(503, "No frame has been captured yet.").into_response(),
+                }
+            }),
+        )
+        //most recent raw frame, encoded losslessly
+        .route(
+            "/screenshot.png",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                if let Err(denied) = plaintext_guard(&state) {
+                    return denied;
+                }
+
+                let Some(frame) = state.latest_raw() else {
+                    return BytesResolution::text(503, "No frame has been captured yet.").into_response();
+                };
+
+                let png = tokio::task::spawn_blocking(move || {
+                    encode_png(&frame.data, frame.width, frame.height)
+                })
+                .await
+                .unwrap_or_default();
+
+                if png.is_empty() {
+                    return BytesResolution::text(500, "Failed to encode the screenshot.").into_response();
+                }
+
+                BytesResolution::new(png, "image/png").into_response()
+            }),
+        )
+        //small downscaled preview of the most recent frame, cached per width
+        .route(
+            "/thumbnail.jpg",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                if let Err(denied) = plaintext_guard(&state) {
+                    return denied;
+                }
+
+                let width = query_param(&req, "width")
+                    .and_then(|w| w.parse::<u32>().ok())
+                    .unwrap_or(DEFAULT_THUMBNAIL_WIDTH);
+
+                if let Some(jpeg) = state.thumbnails.get(width) {
+                    return BytesResolution::new(jpeg.to_vec(), "image/jpeg").into_response();
+                }
+
+                let Some(frame) = state.latest_raw() else {
+                    return BytesResolution::text(503, "No frame has been captured yet.").into_response();
+                };
+
+                let quality = state.control.quality();
+                let jpeg = tokio::task::spawn_blocking(move || {
+                    encode_thumbnail(&frame.data, frame.width, frame.height, width, quality)
+                })
+                .await
+                .unwrap_or_default();
+
+                if jpeg.is_empty() {
+                    return BytesResolution::text(500, "Failed to encode the thumbnail.").into_response();
+                }
+
+                let jpeg = Arc::new(jpeg);
+                state.thumbnails.insert(width, jpeg.clone());
+
+                BytesResolution::new(jpeg.to_vec(), "image/jpeg").into_response()
+            }),
+        )
+        //the last seconds of the replay buffer as an animated gif
+        .route(
+            "/replay.gif",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                if let Err(denied) = plaintext_guard(&state) {
+                    return denied;
+                }
+
+                let seconds = query_param(&req, "seconds")
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .unwrap_or(DEFAULT_GIF_SECONDS);
+                let width = query_param(&req, "width")
+                    .and_then(|w| w.parse::<u32>().ok())
+                    .unwrap_or(DEFAULT_GIF_WIDTH);
+
+                let frames = state.replay.frames_since(std::time::Duration::from_secs(seconds));
+
+                let gif = tokio::task::spawn_blocking(move || {
+                    encode_gif(&frames, width).map_err(|e| e.to_string())
+                })
+                .await;
+
+                match gif {
+                    Ok(Ok(gif)) => BytesResolution::new(gif, "image/gif").into_response(),
+                    Ok(Err(e)) => BytesResolution::text(503, e).into_response(),
+                    Err(e) => BytesResolution::text(500, e.to_string()).into_response(),
+                }
+            }),
+        )
+        .route(
+            "/stream/dimensions",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                Json(state.dimensions()).into_response()
+            }),
+        )
+        //server-sent events: dimension changes, pause/resume, source changes, shutdown...
+        .route(
+            "/events",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                EventStreamResolution::from_receiver(state.events.subscribe()).into_response()
+            }),
+        )
+        //codecs, containers and protocol versions the stream can be requested in
+        .route(
+            "/stream/capabilities",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                Json(Capabilities::current(&state)).into_response()
+            }),
+        )
+        //runtime statistics of the pipeline
+        .route(
+            "/stats",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                Json(RuntimeStats::current(&state)).into_response()
+            }),
+        )
+        //readiness for load balancers and supervisors, 503 while the capture is down or stalled
+        .route(
+            "/healthz",
+            get(move |State(state): State<Arc<StreamState>>| async move {
+                let health = Health::check(&state, stall_timeout);
+                let body = serde_json::to_vec(&health).unwrap_or_default();
+
+                BytesResolution::new(body, "application/json")
+                    .with_status(health.status_code())
+                    .into_response()
+            }),
+        )
+        //opus packets of the captured audio, nothing is streamed when audio is disabled
+        .route(
+            "/stream/audio",
+            get(|State(state): State<Arc<StreamState>>, req: Parts| async move {
+                if let Err(denied) = guard(&state, &req) {
+                    return denied;
+                }
+
+                let version = query_param(&req, "version");
+
+                if let Err(e) = negotiate_version(version.as_deref()) {
+                    return BytesResolution::text(400, e).into_response();
+                }
+
+                let rx = state.audio_packets.subscribe();
+                AudioResolution::from_receiver(rx, state).into_response()
+            }),
+        )
+        .route("/stream", get(stream.clone()).post(stream))
+}
@@ -0,0 +1,754 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use md5::{Digest, Md5};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{Receiver, error::RecvError},
+};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+use crate::{
+    auth::{Auth, constant_time_eq, hex},
+    gateway::{ACCEPT_RETRY, Gateway},
+    packets::frame_payload,
+    presets::Skipped,
+    state::StreamState,
+    viewers::{ViewerClient, ViewersConfig},
+};
+
+/// Path of the stream on the RTSP port.
+pub const RTSP_PATH: &str = "/stream";
+
+/// Payload type of JPEG in the RTP/AVP profile.
+const JPEG_PAYLOAD_TYPE: u8 = 26;
+/// Clock rate of the timestamps of video RTP packets.
+const RTP_CLOCK_HZ: u64 = 90_000;
+/// Largest RTP packet sent, so a packet fits in a single TCP segment of a usual MTU.
+const MAX_RTP_PACKET: usize = 1400;
+/// Seconds a session stays alive without a request, told to the client in `Session`.
+const SESSION_TIMEOUT_SECS: u32 = 60;
+/// Largest request read before the client is considered broken.
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+/// Realm of the Digest authentication, part of the hash of the credentials.
+const REALM: &str = "share-screen";
+
+/// # Spawn Rtsp
+///
+/// Serves the stream at `rtsp://<ip>:<port>/stream` as RTP/JPEG (RFC 2435) over the RTSP connection (interleaved TCP),
+/// for NVRs and players that only speak RTSP. Clients asking for UDP are answered `461 Unsupported Transport` and retry over TCP.
+///
+/// With `[auth]` on the clients authenticate with RTSP Digest, one of the `[auth] tokens` as the password (any user name).
+/// The banned ips are refused and the viewers count towards `[viewers] max` like the ones of `/stream`. Not started while
+/// `[encryption]` is on without `allow_plaintext`, RTP/JPEG carries the frames in the clear.
+///
+/// Note: `RTP/JPEG only carries 4:2:0 and 4:2:2 baseline JPEGs of up to 2040x2040 pixels, set [encoder] chroma and [scaling] to match,
+/// the other frames are skipped.`
+pub fn spawn_rtsp(port: u16, ip: IpAddr, viewers: ViewersConfig, gateway: Arc<Gateway>, state: Arc<StreamState>) {
+    if state.cipher.as_ref().is_some_and(|cipher| !cipher.allow_plaintext) {
+        error!("The RTSP stream carries the frames in the clear, it is not started while [encryption] is on without allow_plaintext");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind((ip, port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                state.stats.error();
+                error!(port, error = %e, "Failed to bind the RTSP listener");
+                return;
+            }
+        };
+
+        info!("RTSP stream at rtsp://{ip}:{port}{RTSP_PATH}");
+
+        //warned once, not per frame
+        let unsupported = Arc::new(AtomicBool::new(false));
+
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!(error = %e, "RTSP accept failed");
+                        tokio::time::sleep(ACCEPT_RETRY).await;
+                        continue;
+                    }
+                },
+                _ = state.shutting_down() => break,
+            };
+
+            if !gateway.allows(peer.ip()) {
+                debug!(%peer, "Refused an RTSP client outside of the [access] lists");
+                continue;
+            }
+
+            if state.viewers.is_banned(&peer.ip().to_string()) {
+                debug!(%peer, "Refused a banned RTSP client");
+                continue;
+            }
+
+            let Some(permit) = gateway.admit(peer.ip()) else {
+                warn!(%peer, "Refused an RTSP client, the ip has too many connections open");
+                continue;
+            };
+
+            let client = ViewerClient {
+                ip: peer.ip().to_string(),
+                user_agent: None,
+                container: "rtsp",
+                identity: None,
+            };
+
+            let (viewers, state, unsupported) = (viewers.clone(), state.clone(), unsupported.clone());
+
+            tokio::spawn(
+                async move {
+                    //held until the connection closes
+                    let _permit = permit;
+                    handle_client(stream, client, viewers, state, unsupported).await
+                }
+                .instrument(info_span!("rtsp_client", %peer)),
+            );
+        }
+    });
+}
+
+/// A request of an RTSP client.
+struct RtspRequest {
+    method: String,
+    url: String,
+    //lowercase names
+    headers: HashMap<String, String>,
+}
+
+impl RtspRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// Serves a single client until it tears the session down or disconnects.
+async fn handle_client(
+    mut stream: TcpStream,
+    client: ViewerClient,
+    viewers: ViewersConfig,
+    state: Arc<StreamState>,
+    unsupported: Arc<AtomicBool>,
+) {
+    let session = format!("{:016x}", rand::random::<u64>());
+    //the Digest challenge of this connection
+    let nonce = hex(&rand::random::<[u8; 16]>());
+    let ssrc = rand::random::<u32>();
+    let mut sequence = rand::random::<u16>();
+    //interleaved channel of the RTP packets, set by SETUP
+    let mut channel: Option<u8> = None;
+    let mut rx: Option<Receiver<Vec<u8>>> = None;
+    let mut viewer = None;
+    let mut buffer = Vec::new();
+
+    loop {
+        tokio::select! {
+            read = stream.read_buf(&mut buffer) => {
+                match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+
+                if buffer.len() > MAX_REQUEST_LEN {
+                    debug!("Dropping an RTSP client sending oversized requests");
+                    break;
+                }
+
+                let mut teardown = false;
+
+                while let Some(request) = next_request(&mut buffer) {
+                    let local_ip = stream.local_addr().ok().map(|address| address.ip());
+                    let (response, play) = match refusal(&request, &state, &viewers, &nonce, rx.is_some()) {
+                        Some(refused) => (refused, false),
+                        None => respond(&request, &session, &mut channel, local_ip),
+                    };
+
+                    if stream.write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+
+                    if play && rx.is_none() {
+                        rx = Some(state.frames.subscribe());
+                        viewer = Some(state.viewer(client.clone()));
+                    }
+
+                    teardown |= request.method == "TEARDOWN";
+                }
+
+                if teardown {
+                    break;
+                }
+            }
+            //nothing is sent before PLAY
+            received = async {
+                match &mut rx {
+                    Some(rx) => rx.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let data = match received {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(missed)) => {
+                        if let Some(viewer) = &viewer {
+                            viewer.add_frames_lagged(missed);
+                        }
+
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let (Some((timestamp_us, jpeg)), Some(channel)) = (frame_payload(&data), channel) else {
+                    continue;
+                };
+
+                let timestamp = (timestamp_us * RTP_CLOCK_HZ / 1_000_000) as u32;
+
+                let packets = match rtp_jpeg_packets(jpeg, &mut sequence, timestamp, ssrc) {
+                    Ok(packets) => packets,
+                    Err(e) => {
+                        if !unsupported.swap(true, Ordering::Relaxed) {
+                            warn!(error = e, "Frames the RTSP stream cannot carry are skipped");
+                        }
+
+                        if let Some(viewer) = &viewer {
+                            viewer.add_skipped(Skipped::Unsupported);
+                        }
+
+                        continue;
+                    }
+                };
+
+                let mut interleaved = Vec::new();
+
+                for packet in packets {
+                    interleaved.push(b'$');
+                    interleaved.push(channel);
+                    interleaved.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+                    interleaved.extend_from_slice(&packet);
+                }
+
+                if stream.write_all(&interleaved).await.is_err() {
+                    break;
+                }
+
+                if let Some(viewer) = &viewer {
+                    viewer.add_bytes_sent(interleaved.len());
+                }
+            }
+            _ = async {
+                match &viewer {
+                    Some(viewer) => viewer.kicked().await,
+                    None => std::future::pending().await,
+                }
+            } => break,
+            _ = state.shutting_down() => break,
+        }
+    }
+}
+
+/// Takes the next complete request out of the received bytes, skipping the interleaved packets of the client (RTCP reports).
+fn next_request(buffer: &mut Vec<u8>) -> Option<RtspRequest> {
+    loop {
+        if buffer.first() == Some(&b'$') {
+            let length = u16::from_be_bytes([*buffer.get(2)?, *buffer.get(3)?]) as usize;
+
+            if buffer.len() < 4 + length {
+                return None;
+            }
+
+            buffer.drain(..4 + length);
+            continue;
+        }
+
+        let end = buffer.windows(4).position(|window| window == b"\r\n\r\n")?;
+        let head = String::from_utf8_lossy(&buffer[..end]).to_string();
+        let mut lines = head.lines();
+        let mut start = lines.next().unwrap_or_default().split_whitespace();
+
+        let request = RtspRequest {
+            method: start.next().unwrap_or_default().to_string(),
+            url: start.next().unwrap_or_default().to_string(),
+            headers: lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+                .collect(),
+        };
+
+        let body = request.header("content-length").and_then(|length| length.parse::<usize>().ok()).unwrap_or(0);
+
+        if buffer.len() < end + 4 + body {
+            return None;
+        }
+
+        buffer.drain(..end + 4 + body);
+
+        return Some(request);
+    }
+}
+
+/// # Refusal
+///
+/// The response refusing the request, if it is: a `401` with the Digest challenge for the requests describing or playing the stream
+/// without valid credentials while `[auth]` is on, a `503` for a `PLAY` over `[viewers] max`.
+fn refusal(request: &RtspRequest, state: &StreamState, viewers: &ViewersConfig, nonce: &str, playing: bool) -> Option<String> {
+    let cseq = request.header("cseq").unwrap_or("0");
+    let protected = matches!(request.method.as_str(), "DESCRIBE" | "SETUP" | "PLAY");
+
+    if protected && state.auth.enabled() && !digest_authorized(request, &state.auth, nonce) {
+        if request.header("authorization").is_some() {
+            warn!("Refused RTSP credentials");
+        }
+
+        return Some(reply(
+            cseq,
+            "401 Unauthorized",
+            &format!("WWW-Authenticate: Digest realm=\"{REALM}\", nonce=\"{nonce}\"\r\n"),
+            "",
+        ));
+    }
+
+    if request.method == "PLAY" && !playing && !viewers.has_room(state.stats.viewers()) {
+        return Some(reply(cseq, "503 Service Unavailable", "", ""));
+    }
+
+    None
+}
+
+/// # Digest Authorized
+///
+/// Whether the `Authorization: Digest` of the request (RFC 2617 without qop, as RTSP clients send it) answers the nonce of the
+/// connection with one of the `[auth]` tokens as the password.
+fn digest_authorized(request: &RtspRequest, auth: &Auth, nonce: &str) -> bool {
+    let Some(params) = request.header("authorization").and_then(|value| value.strip_prefix("Digest ")) else {
+        return false;
+    };
+    let params = digest_params(params);
+
+    let (Some(username), Some(uri), Some(response)) = (params.get("username"), params.get("uri"), params.get("response")) else {
+        return false;
+    };
+
+    if params.get("realm").map(String::as_str) != Some(REALM) || params.get("nonce").map(String::as_str) != Some(nonce) {
+        return false;
+    }
+
+    let ha2 = md5_hex(&format!("{}:{uri}", request.method));
+
+    auth.find_token(|token| {
+        let ha1 = md5_hex(&format!("{username}:{REALM}:{token}"));
+
+        constant_time_eq(&md5_hex(&format!("{ha1}:{nonce}:{ha2}")), &response.to_ascii_lowercase())
+    })
+    .is_some()
+}
+
+/// The `name=value` and `name="value"` parameters of a Digest header, by their lowercase names.
+fn digest_params(value: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = value.trim();
+
+    while let Some((name, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+
+        params.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        rest = next.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    params
+}
+
+fn md5_hex(text: &str) -> String {
+    hex(&Md5::digest(text.as_bytes()))
+}
+
+/// An RTSP response with its status line, headers (each ending with `\r\n`) and body.
+fn reply(cseq: &str, status: &str, headers: &str, body: &str) -> String {
+    let content_length = match body.is_empty() {
+        true => String::new(),
+        false => format!("Content-Length: {}\r\n", body.len()),
+    };
+
+    format!("RTSP/1.0 {status}\r\nCSeq: {cseq}\r\nServer: share-screen\r\n{headers}{content_length}\r\n{body}")
+}
+
+/// The response to a request, and whether the client asked to play the stream.
+fn respond(request: &RtspRequest, session: &str, channel: &mut Option<u8>, ip: Option<IpAddr>) -> (String, bool) {
+    let cseq = request.header("cseq").unwrap_or("0");
+    let on_stream = url_path(&request.url).starts_with(RTSP_PATH);
+
+    let (status, headers, body, play) = match request.method.as_str() {
+        "OPTIONS" => (
+            "200 OK",
+            "Public: OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER\r\n".to_string(),
+            String::new(),
+            false,
+        ),
+        "DESCRIBE" if on_stream => {
+            let ip = ip.map_or("0.0.0.0".to_string(), |ip| ip.to_string());
+            let sdp = format!(
+                "v=0\r\no=- {session} 1 IN IP4 {ip}\r\ns=share-screen\r\nc=IN IP4 0.0.0.0\r\nt=0 0\r\n\
+                 m=video 0 RTP/AVP {JPEG_PAYLOAD_TYPE}\r\na=rtpmap:{JPEG_PAYLOAD_TYPE} JPEG/{RTP_CLOCK_HZ}\r\na=control:track1\r\n"
+            );
+
+            (
+                "200 OK",
+                format!(
+                    "Content-Base: {}/\r\nContent-Type: application/sdp\r\n",
+                    request.url.trim_end_matches('/')
+                ),
+                sdp,
+                false,
+            )
+        }
+        "SETUP" if on_stream => match request.header("transport") {
+            Some(transport) if transport.contains("TCP") => {
+                let channels = interleaved_channels(transport).unwrap_or((0, 1));
+                *channel = Some(channels.0);
+
+                (
+                    "200 OK",
+                    format!(
+                        "Transport: RTP/AVP/TCP;unicast;interleaved={}-{}\r\nSession: {session};timeout={SESSION_TIMEOUT_SECS}\r\n",
+                        channels.0, channels.1
+                    ),
+                    String::new(),
+                    false,
+                )
+            }
+            _ => ("461 Unsupported Transport", String::new(), String::new(), false),
+        },
+        "PLAY" if channel.is_some() => (
+            "200 OK",
+            format!("Session: {session};timeout={SESSION_TIMEOUT_SECS}\r\nRange: npt=0.000-\r\n"),
+            String::new(),
+            true,
+        ),
+        "PLAY" => ("455 Method Not Valid in This State", String::new(), String::new(), false),
+        "GET_PARAMETER" | "TEARDOWN" => (
+            "200 OK",
+            format!("Session: {session};timeout={SESSION_TIMEOUT_SECS}\r\n"),
+            String::new(),
+            false,
+        ),
+        "DESCRIBE" | "SETUP" => ("404 Not Found", String::new(), String::new(), false),
+        _ => ("501 Not Implemented", String::new(), String::new(), false),
+    };
+
+    (reply(cseq, status, &headers, &body), play)
+}
+
+/// The path of an `rtsp://host:port/path` url.
+fn url_path(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+
+    rest.find('/').map_or("/", |start| &rest[start..])
+}
+
+/// The channels of `interleaved=0-1` in a transport header.
+fn interleaved_channels(transport: &str) -> Option<(u8, u8)> {
+    let value = transport
+        .split(';')
+        .find_map(|part| part.trim().strip_prefix("interleaved="))?;
+    let (rtp, rtcp) = value.split_once('-').unwrap_or((value, value));
+    let rtp = rtp.trim().parse::<u8>().ok()?;
+
+    Some((rtp, rtcp.trim().parse::<u8>().unwrap_or(rtp.saturating_add(1))))
+}
+
+/// The parts of a baseline JPEG carried by RTP/JPEG.
+struct JpegScan<'a> {
+    //0 for 4:2:2, 1 for 4:2:0
+    kind: u8,
+    width: u16,
+    height: u16,
+    //luma then chroma, 64 bytes each in zigzag order
+    tables: Vec<u8>,
+    restart_interval: u16,
+    //entropy coded data, without the EOI marker
+    scan: &'a [u8],
+}
+
+/// Reads the markers of a JPEG up to its scan.
+fn parse_jpeg(jpeg: &[u8]) -> Result<JpegScan<'_>, &'static str> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return Err("not a JPEG");
+    }
+
+    let mut tables: [Option<&[u8]>; 4] = [None; 4];
+    //(sampling of the luma, table of the luma, table of the chroma)
+    let mut frame = None;
+    let mut size = (0, 0);
+    let mut restart_interval = 0;
+    let mut position = 2;
+
+    loop {
+        let marker = match jpeg.get(position..position + 4) {
+            Some([0xFF, marker, ..]) => *marker,
+            _ => return Err("malformed JPEG"),
+        };
+        let length = u16::from_be_bytes([jpeg[position + 2], jpeg[position + 3]]) as usize;
+        let segment = jpeg.get(position + 4..position + 2 + length).ok_or("malformed JPEG")?;
+
+        match marker {
+            //quantization tables
+            0xDB => {
+                let mut offset = 0;
+
+                while let Some(&precision_id) = segment.get(offset) {
+                    if precision_id >> 4 != 0 {
+                        return Err("16 bits quantization tables");
+                    }
+
+                    tables[(precision_id & 0x03) as usize] = Some(segment.get(offset + 1..offset + 65).ok_or("malformed JPEG")?);
+                    offset += 65;
+                }
+            }
+            //baseline frame
+            0xC0 => {
+                if segment.len() < 15 || segment[5] != 3 {
+                    return Err("only color JPEGs, not grayscale");
+                }
+
+                size = (
+                    u16::from_be_bytes([segment[3], segment[4]]),
+                    u16::from_be_bytes([segment[1], segment[2]]),
+                );
+                frame = Some((segment[7], segment[8], segment[11]));
+            }
+            0xC1..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => return Err("only baseline JPEGs"),
+            0xDD => restart_interval = u16::from_be_bytes([*segment.first().unwrap_or(&0), *segment.get(1).unwrap_or(&0)]),
+            //start of scan, the entropy coded data follows its header
+            0xDA => {
+                let (sampling, luma, chroma) = frame.ok_or("scan before the frame header")?;
+                let kind = match sampling {
+                    0x21 => 0,
+                    0x22 => 1,
+                    _ => return Err("only 4:2:2 and 4:2:0 chroma subsampling"),
+                };
+
+                if size.0 > 2040 || size.1 > 2040 {
+                    return Err("frames larger than 2040 pixels");
+                }
+
+                let luma = tables[(luma & 0x03) as usize].ok_or("missing quantization table")?;
+                let chroma = tables[(chroma & 0x03) as usize].ok_or("missing quantization table")?;
+
+                let start = position + 2 + length;
+                let end = match jpeg.ends_with(&[0xFF, 0xD9]) {
+                    true => jpeg.len() - 2,
+                    false => jpeg.len(),
+                };
+
+                return Ok(JpegScan {
+                    kind,
+                    width: size.0,
+                    height: size.1,
+                    tables: [luma, chroma].concat(),
+                    restart_interval,
+                    scan: jpeg.get(start..end).ok_or("malformed JPEG")?,
+                });
+            }
+            _ => {}
+        }
+
+        position += 2 + length;
+    }
+}
+
+/// # Rtp Jpeg Packets
+///
+/// Splits a JPEG into RTP/JPEG packets (RFC 2435), the quantization tables in the first one and the marker bit on the last one.
+fn rtp_jpeg_packets(jpeg: &[u8], sequence: &mut u16, timestamp: u32, ssrc: u32) -> Result<Vec<Vec<u8>>, &'static str> {
+    let jpeg = parse_jpeg(jpeg)?;
+    let mut packets = Vec::new();
+    let mut offset = 0;
+
+    while offset < jpeg.scan.len() {
+        let mut packet = Vec::with_capacity(MAX_RTP_PACKET);
+
+        packet.extend_from_slice(&[0x80, JPEG_PAYLOAD_TYPE]);
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+
+        //main JPEG header, types 64 and up carry restart markers, q 255 carries its tables
+        packet.push(0);
+        packet.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+        packet.push(jpeg.kind + if jpeg.restart_interval > 0 { 64 } else { 0 });
+        packet.push(255);
+        packet.push(jpeg.width.div_ceil(8) as u8);
+        packet.push(jpeg.height.div_ceil(8) as u8);
+
+        if jpeg.restart_interval > 0 {
+            packet.extend_from_slice(&jpeg.restart_interval.to_be_bytes());
+            packet.extend_from_slice(&[0xFF, 0xFF]);
+        }
+
+        if offset == 0 {
+            packet.extend_from_slice(&[0, 0]);
+            packet.extend_from_slice(&(jpeg.tables.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&jpeg.tables);
+        }
+
+        let length = (MAX_RTP_PACKET - packet.len()).min(jpeg.scan.len() - offset);
+        packet.extend_from_slice(&jpeg.scan[offset..offset + length]);
+        offset += length;
+
+        if offset == jpeg.scan.len() {
+            packet[1] |= 0x80;
+        }
+
+        *sequence = sequence.wrapping_add(1);
+        packets.push(packet);
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A baseline 4:2:0 JPEG of the size with the entropy coded data and its quantization tables filled with 1 and 2.
+    fn jpeg(width: u16, height: u16, scan: &[u8], restart_interval: Option<u16>) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+
+        jpeg.extend_from_slice(&[0xFF, 0xDB, 0x00, 132, 0x00]);
+        jpeg.extend_from_slice(&[1; 64]);
+        jpeg.push(0x01);
+        jpeg.extend_from_slice(&[2; 64]);
+
+        jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 17, 8]);
+        jpeg.extend_from_slice(&height.to_be_bytes());
+        jpeg.extend_from_slice(&width.to_be_bytes());
+        jpeg.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+
+        if let Some(interval) = restart_interval {
+            jpeg.extend_from_slice(&[0xFF, 0xDD, 0x00, 4]);
+            jpeg.extend_from_slice(&interval.to_be_bytes());
+        }
+
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 12, 3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+        jpeg.extend_from_slice(scan);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+
+        jpeg
+    }
+
+    #[test]
+    fn reads_the_scan_of_a_jpeg() {
+        let scan = parse_jpeg(&jpeg(1280, 720, b"entropy", None)).unwrap();
+
+        assert_eq!((scan.kind, scan.width, scan.height, scan.restart_interval), (1, 1280, 720, 0));
+        assert_eq!(scan.tables, [[1; 64], [2; 64]].concat());
+        assert_eq!(scan.scan, b"entropy");
+    }
+
+    #[test]
+    fn refuses_what_rtp_jpeg_cannot_carry() {
+        assert_eq!(parse_jpeg(b"\x89PNG").err(), Some("not a JPEG"));
+        assert_eq!(parse_jpeg(&jpeg(4096, 720, b"entropy", None)).err(), Some("frames larger than 2040 pixels"));
+
+        let mut progressive = jpeg(1280, 720, b"entropy", None);
+        let frame = progressive.windows(2).position(|marker| marker == [0xFF, 0xC0]).unwrap();
+        progressive[frame + 1] = 0xC2;
+        assert_eq!(parse_jpeg(&progressive).err(), Some("only baseline JPEGs"));
+
+        let whole = jpeg(1280, 720, b"entropy", None);
+        assert_eq!(parse_jpeg(&whole[..40]).err(), Some("malformed JPEG"));
+    }
+
+    #[test]
+    fn splits_a_jpeg_into_rtp_packets() {
+        let scan: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+        let mut sequence = u16::MAX;
+        let packets = rtp_jpeg_packets(&jpeg(1280, 720, &scan, None), &mut sequence, 90_000, 0xDEADBEEF).unwrap();
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(sequence, 2);
+
+        let mut fragments = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            assert!(packet.len() <= MAX_RTP_PACKET);
+            assert_eq!(packet[0], 0x80);
+            //the marker bit ends the frame
+            assert_eq!(packet[1], JPEG_PAYLOAD_TYPE | if i == packets.len() - 1 { 0x80 } else { 0 });
+            assert_eq!(u16::from_be_bytes([packet[2], packet[3]]), u16::MAX.wrapping_add(i as u16));
+            assert_eq!(&packet[4..12], &[0x00, 0x01, 0x5F, 0x90, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+            let offset = u32::from_be_bytes([0, packet[13], packet[14], packet[15]]) as usize;
+            assert_eq!(offset, fragments.len());
+            assert_eq!(&packet[16..20], &[1, 255, 160, 90]);
+
+            let payload = match i {
+                0 => {
+                    assert_eq!(&packet[20..24], &[0, 0, 0, 128]);
+                    assert_eq!(&packet[24..152], [[1; 64], [2; 64]].concat().as_slice());
+                    &packet[152..]
+                }
+                _ => &packet[20..],
+            };
+            fragments.extend_from_slice(payload);
+        }
+
+        assert_eq!(fragments, scan);
+    }
+
+    #[test]
+    fn carries_the_restart_interval() {
+        let mut sequence = 0;
+        let packets = rtp_jpeg_packets(&jpeg(64, 64, b"entropy", Some(4)), &mut sequence, 0, 0).unwrap();
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0][16], 1 + 64);
+        assert_eq!(&packets[0][20..24], &[0x00, 0x04, 0xFF, 0xFF]);
+        assert!(packets[0].ends_with(b"entropy"));
+    }
+
+    #[test]
+    fn reads_the_requests_urls_and_transports() {
+        assert_eq!(url_path("rtsp://192.168.1.2:8554/stream"), "/stream");
+        assert_eq!(url_path("rtsp://192.168.1.2:8554"), "/");
+        assert_eq!(url_path("/stream/trackID=0"), "/stream/trackID=0");
+
+        assert_eq!(interleaved_channels("RTP/AVP/TCP;unicast;interleaved=2-3"), Some((2, 3)));
+        assert_eq!(interleaved_channels("RTP/AVP/TCP;interleaved=4"), Some((4, 4)));
+        assert_eq!(interleaved_channels("RTP/AVP;unicast;client_port=5000-5001"), None);
+    }
+
+    #[test]
+    fn reads_the_digest_parameters() {
+        let params = digest_params(r#"username="viewer", realm="share-screen", nonce="abc", uri="rtsp://host/stream", response="0f1e", algorithm=MD5"#);
+
+        assert_eq!(params["username"], "viewer");
+        assert_eq!(params["realm"], REALM);
+        assert_eq!(params["nonce"], "abc");
+        assert_eq!(params["uri"], "rtsp://host/stream");
+        assert_eq!(params["response"], "0f1e");
+        assert_eq!(params["algorithm"], "MD5");
+    }
+
+    #[test]
+    fn hashes_the_digest_as_md5() {
+        assert_eq!(md5_hex(""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex("Mufasa:testrealm@host.com:Circle Of Life"), "939e7578ed9e3c518a452acee763bce9");
+    }
+}
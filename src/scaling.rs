@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use image::{ImageBuffer, Rgba, imageops};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+/// Filter the frames are downscaled with, from the fastest to the sharpest.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleFilter {
+    /// one pixel out of every few, the most throughput, text gets jagged.
+    Nearest,
+    /// averages the neighbouring pixels, a good default for video-like content.
+    #[default]
+    Bilinear,
+    /// the sharpest, for text-heavy shares, also the most cpu.
+    Lanczos3,
+}
+
+/// `[scaling]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ScalingConfig {
+    /// size of the streamed frames in percent of the captured ones, 100 streams them as they are.
+    pub scale: u8,
+    /// frames wider than this are downscaled to it (keeping the aspect ratio), 0 is unlimited.
+    pub max_width: u32,
+    /// frames taller than this are downscaled to it, 0 is unlimited.
+    pub max_height: u32,
+    pub filter: ScaleFilter,
+}
+
+impl Default for ScalingConfig {
+    fn default() -> Self {
+        Self {
+            scale: 100,
+            max_width: 0,
+            max_height: 0,
+            filter: ScaleFilter::default(),
+        }
+    }
+}
+
+/// # Frame Scaler
+///
+/// Downscales the frames before they are encoded, never upscales. The scale can be changed at runtime (`/api/scale`).
+pub struct FrameScaler {
+    scale: AtomicU8,
+    max_width: u32,
+    max_height: u32,
+    filter: ScaleFilter,
+}
+
+impl FrameScaler {
+    pub fn new(config: &ScalingConfig) -> Self {
+        Self {
+            scale: AtomicU8::new(config.scale.clamp(1, 100)),
+            max_width: config.max_width,
+            max_height: config.max_height,
+            filter: config.filter,
+        }
+    }
+
+    /// Size of the streamed frames in percent of the captured ones.
+    pub fn scale(&self) -> u8 {
+        self.scale.load(Ordering::Relaxed)
+    }
+
+    /// Sets the scale, clamped from 1 to 100.
+    pub fn set_scale(&self, scale: u8) {
+        self.scale.store(scale.clamp(1, 100), Ordering::Relaxed);
+    }
+
+    /// Whether the frames can go through unchanged whatever their size.
+    pub fn is_identity(&self) -> bool {
+        self.scale() == 100 && self.max_width == 0 && self.max_height == 0
+    }
+
+    /// # Dimensions
+    ///
+    /// Dimensions of the downscaled frames, the scale then the maximums keeping the aspect ratio, rounded to even numbers.
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let mut factor = self.scale() as f64 / 100.0;
+
+        if self.max_width > 0 {
+            factor = factor.min(self.max_width as f64 / width.max(1) as f64);
+        }
+
+        if self.max_height > 0 {
+            factor = factor.min(self.max_height as f64 / height.max(1) as f64);
+        }
+
+        if factor >= 1.0 {
+            return (width, height);
+        }
+
+        let scaled = |size: u32| ((size as f64 * factor).round() as u32).max(2) & !1;
+
+        (scaled(width).min(width), scaled(height).min(height))
+    }
+
+    /// # Apply
+    ///
+    /// The downscaled frame along with its dimensions, a frame that does not match the dimensions is returned as it is
+    /// (along with the scaled dimensions, the encoder rejects it).
+    pub fn apply(&self, frame: Vec<u8>, width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+        let (scaled_width, scaled_height) = self.dimensions(width, height);
+
+        if (scaled_width, scaled_height) == (width, height) || frame.len() != width as usize * height as usize * 4 {
+            return (frame, scaled_width, scaled_height);
+        }
+
+        let scaled = match self.filter {
+            ScaleFilter::Nearest => nearest(&frame, width, height, scaled_width, scaled_height),
+            ScaleFilter::Bilinear => filtered(frame, width, height, scaled_width, scaled_height, imageops::FilterType::Triangle),
+            ScaleFilter::Lanczos3 => filtered(frame, width, height, scaled_width, scaled_height, imageops::FilterType::Lanczos3),
+        };
+
+        (scaled, scaled_width, scaled_height)
+    }
+}
+
+impl Default for FrameScaler {
+    fn default() -> Self {
+        Self::new(&ScalingConfig::default())
+    }
+}
+
+/// Downscale with a filter of the image crate, the filters do not care about the order of the channels so BGRA goes through as it is.
+fn filtered(
+    frame: Vec<u8>,
+    width: u32,
+    height: u32,
+    scaled_width: u32,
+    scaled_height: u32,
+    filter: imageops::FilterType,
+) -> Vec<u8> {
+    match ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(width, height, frame) {
+        Some(image) => imageops::resize(&image, scaled_width, scaled_height, filter).into_raw(),
+        None => Vec::new(),
+    }
+}
+
+/// Nearest neighbour downscale, rows in parallel.
+fn nearest(frame: &[u8], width: u32, height: u32, scaled_width: u32, scaled_height: u32) -> Vec<u8> {
+    let mut scaled = vec![0u8; scaled_width as usize * scaled_height as usize * 4];
+    let columns: Vec<usize> = (0..scaled_width)
+        .map(|x| (x as u64 * width as u64 / scaled_width as u64) as usize)
+        .collect();
+
+    scaled
+        .par_chunks_exact_mut(scaled_width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let source_y = (y as u64 * height as u64 / scaled_height as u64) as usize;
+            let source_row = &frame[source_y * width as usize * 4..(source_y + 1) * width as usize * 4];
+
+            for (pixel, source_x) in row.chunks_exact_mut(4).zip(&columns) {
+                pixel.copy_from_slice(&source_row[source_x * 4..source_x * 4 + 4]);
+            }
+        });
+
+    scaled
+}
@@ -0,0 +1,129 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{Datelike, Local, NaiveTime, Weekday};
+use serde::Deserialize;
+
+use crate::state::StreamState;
+
+/// How often the schedules are checked.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// # Recording Schedule
+///
+/// A window of time on some days of the week the recorder runs automatically.
+///
+/// ```toml
+/// [[recording.schedules]]
+/// days = ["mon", "tue", "wed", "thu", "fri"] # or "weekdays", "weekends", "daily"
+/// start = "09:00"
+/// end = "17:00"
+/// ```
+///
+/// A window ending before it starts (`22:00` to `06:00`) runs past midnight.
+#[derive(Deserialize, Clone)]
+pub struct RecordingSchedule {
+    #[serde(default = "all_days")]
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+fn all_days() -> Vec<String> {
+    vec!["daily".to_string()]
+}
+
+impl RecordingSchedule {
+    /// Checks the days and times of the schedule can be parsed.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.times()?;
+
+        for day in &self.days {
+            if parse_days(day).is_empty() {
+                return Err(format!("Unknown schedule day '{day}'").into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn times(&self) -> Result<(NaiveTime, NaiveTime), Box<dyn std::error::Error>> {
+        let start = NaiveTime::parse_from_str(&self.start, "%H:%M")
+            .map_err(|e| format!("Invalid schedule start '{}': {e}", self.start))?;
+        let end = NaiveTime::parse_from_str(&self.end, "%H:%M")
+            .map_err(|e| format!("Invalid schedule end '{}': {e}", self.end))?;
+
+        Ok((start, end))
+    }
+
+    fn runs_on(&self, day: Weekday) -> bool {
+        self.days.iter().any(|d| parse_days(d).contains(&day))
+    }
+
+    /// Whether the schedule is active at the day and time.
+    pub fn is_active(&self, day: Weekday, time: NaiveTime) -> bool {
+        let Ok((start, end)) = self.times() else {
+            return false;
+        };
+
+        if start <= end {
+            self.runs_on(day) && time >= start && time < end
+        } else {
+            //past midnight, the part after midnight belongs to the previous day
+            (self.runs_on(day) && time >= start) || (self.runs_on(day.pred()) && time < end)
+        }
+    }
+}
+
+fn parse_days(day: &str) -> Vec<Weekday> {
+    use Weekday::*;
+
+    match day.to_lowercase().as_str() {
+        "daily" | "all" => vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun],
+        "weekdays" => vec![Mon, Tue, Wed, Thu, Fri],
+        "weekends" => vec![Sat, Sun],
+        other => other.parse::<Weekday>().map(|d| vec![d]).unwrap_or_default(),
+    }
+}
+
+/// # Spawn Recording Scheduler
+///
+/// Spawns a task that starts the recorder when a schedule becomes active and stops it when none are.
+///
+/// Recordings started by hand (`--record`, `/api/record/start`) are left alone.
+pub fn spawn_recording_scheduler(schedules: Vec<RecordingSchedule>, state: Arc<StreamState>) {
+    if schedules.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut scheduled_path: Option<String> = None;
+
+        loop {
+            let now = Local::now();
+            let active = schedules
+                .iter()
+                .any(|s| s.is_active(now.weekday(), now.time()));
+
+            let status = state.recorder.status().await;
+
+            //the scheduled recording was stopped by hand
+            if scheduled_path.is_some() && status.path != scheduled_path {
+                scheduled_path = None;
+            }
+
+            if active && !status.recording {
+                let path = state.recorder.default_path();
+
+                match state.recorder.start(path.clone(), state.clone()).await {
+                    Ok(_) => scheduled_path = Some(path),
+                    Err(e) => tracing::error!(error = %e, "Failed to start scheduled recording"),
+                }
+            } else if !active && scheduled_path.is_some() {
+                state.recorder.stop().await;
+                scheduled_path = None;
+            }
+
+            tokio::time::sleep(SCHEDULE_CHECK_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,465 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::Router;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{
+    aggregate::spawn_advertiser,
+    audio::spawn_audio_capture,
+    audit::AuditLog,
+    auth::Auth,
+    autocrop::AutoCrop,
+    bandwidth::spawn_bandwidth_governor,
+    bitrate::spawn_bitrate_controller,
+    blackout::spawn_window_blackout,
+    cameras::{start_camera_streams, stop_camera_streams},
+    captures::SerializedDimensions,
+    chat::ChatRoom,
+    config::Config,
+    config_reload::spawn_config_reload,
+    control_channel::spawn_control_channel,
+    encoder::{EncoderRegistry, FrameEncoder},
+    encryption::FrameCipher,
+    events::ServerEvent,
+    exclusion::spawn_window_exclusion,
+    frame_compressor::configure_thread_pool,
+    gateway::{Gateway, bind_gateway},
+    hdr::ToneMapper,
+    hotkey::spawn_hotkeys,
+    motion::spawn_motion_detector,
+    notify::spawn_join_notifications,
+    onvif::spawn_onvif,
+    overlay::TimestampOverlay,
+    pipeline::spawn_frame_capture,
+    platform,
+    presets::StreamPreset,
+    pipeline_stats::spawn_stats_logger,
+    placeholder::spawn_pause_placeholder,
+    qr,
+    recorder::Recorder,
+    remote_input::spawn_remote_input,
+    replay::DEFAULT_REPLAY_SECONDS,
+    retention::spawn_storage_guard,
+    routes::router,
+    scaling::FrameScaler,
+    schedule::spawn_recording_scheduler,
+    session::SessionSummary,
+    snapshots::spawn_snapshot_archive,
+    source::SourceDevice,
+    state::StreamState,
+    timelapse::Timelapse,
+    tls,
+    transform::FrameTransform,
+    tunnel::spawn_tunnel,
+    upnp::spawn_port_mapping,
+    virtual_camera::spawn_virtual_camera,
+    watchdog::spawn_watchdog,
+    webhooks::spawn_webhooks,
+};
+
+/// Time the router has to remove the port mappings when shutting down.
+const PORT_MAPPING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time the event streams get to deliver `shutting_down` before the client streams end.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(250);
+
+/// # Share Server Builder
+///
+/// Settings of a `ShareServer`, created with `ShareServer::builder`.
+pub struct ShareServerBuilder {
+    source: Arc<dyn SourceDevice>,
+    encoder: Option<Arc<dyn FrameEncoder>>,
+    config: Config,
+    replay: Duration,
+    record: Option<String>,
+    timelapse: Option<(String, Duration)>,
+    listen: bool,
+    upnp: bool,
+    preset: Option<StreamPreset>,
+    reload_config: Option<String>,
+}
+
+impl ShareServerBuilder {
+    /// The settings of the config file, the defaults otherwise.
+    pub fn config(self, config: Config) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Encodes the frames with the encoder instead of the `[encoder]` codec of the config.
+    pub fn encoder(self, encoder: Arc<dyn FrameEncoder>) -> Self {
+        Self {
+            encoder: Some(encoder),
+            ..self
+        }
+    }
+
+    /// How much of the stream the replay buffer keeps.
+    pub fn replay(self, replay: Duration) -> Self {
+        Self { replay, ..self }
+    }
+
+    /// Records the stream to the mp4 file from the start.
+    pub fn record(self, path: impl Into<String>) -> Self {
+        Self {
+            record: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Writes a timelapse grabbing one frame every `interval`.
+    pub fn timelapse(self, path: impl Into<String>, interval: Duration) -> Self {
+        Self {
+            timelapse: Some((path.into(), interval)),
+            ..self
+        }
+    }
+
+    /// Whether the server binds the listeners of `[http]`, `[tls]` and `[control]`, true by default.
+    ///
+    /// Without them the routes are served by the application through `ShareServer::router`.
+    pub fn listen(self, listen: bool) -> Self {
+        Self { listen, ..self }
+    }
+
+    /// Asks the router to forward the ports of the listeners (UPnP).
+    pub fn upnp(self, upnp: bool) -> Self {
+        Self { upnp, ..self }
+    }
+
+    /// Starts with the fps, scale and quality of the preset, also pacing the streams opened without `?preset=`.
+    pub fn preset(self, preset: Option<StreamPreset>) -> Self {
+        Self { preset, ..self }
+    }
+
+    /// Watches the config file at the path and applies the changes that do not need a restart, see `spawn_config_reload`.
+    pub fn reload_config(self, path: impl Into<String>) -> Self {
+        Self {
+            reload_config: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// # Start
+    ///
+    /// Activates the capture device (and the `[capture] cameras`), starts the pipelines and the tasks of the config, then binds the listeners.
+    ///
+    /// Note: `Must be called within a tokio runtime, the server runs until ShareServer::shutdown.`
+    pub async fn start(self) -> Result<ShareServer, Box<dyn std::error::Error>> {
+        let config = self.config;
+
+        info!("Initializing capture component now...");
+
+        //before the device is opened, its dimensions depend on it
+        platform::enable_dpi_awareness();
+
+        let encoder = match self.encoder {
+            Some(encoder) => encoder,
+            None => EncoderRegistry::default().create(&config.encoder)?,
+        };
+
+        let capture = self.source.open()?;
+        let (width, height) = capture.dimensions()?;
+        let dimensions = SerializedDimensions {
+            width: width as usize,
+            height: height as usize,
+        };
+
+        let (compressed_sender, _) = broadcast::channel::<Vec<u8>>(100);
+
+        let auth = Arc::new(Auth::new(config.auth.clone()));
+        let (cameras, camera_tasks): (Vec<_>, Vec<_>) =
+            start_camera_streams(&config, self.source.as_ref(), &auth, self.replay)
+                .into_iter()
+                .unzip();
+
+        let state = Arc::new(
+            StreamState::new(self.source, dimensions, compressed_sender, self.replay)
+                .with_auth(auth)
+                .with_cipher(FrameCipher::from_config(&config.encryption)?)
+                .with_encoder(encoder)
+                .with_chat(ChatRoom::from_config(&config.chat))
+                .with_audit(AuditLog::open(&config.audit).map_err(|e| format!("Failed to open the audit log: {e}"))?)
+                .with_tone_mapper(ToneMapper::new(&config.hdr))
+                .with_transform(FrameTransform::new(&config.transform))
+                .with_autocrop(AutoCrop::new(&config.autocrop))
+                .with_scaler(FrameScaler::new(&config.scaling))
+                .with_overlay(TimestampOverlay::new(&config.overlay))
+                .with_recorder(
+                    Recorder::new()
+                        .with_format(config.recording.format)
+                        .with_codec(config.recording.codec, config.recording.av1.clone(), config.recording.vpx.clone()),
+                )
+                .with_preset(self.preset)
+                .with_cameras(cameras),
+        );
+
+        state.pipeline.stages.set_enabled(config.stats.stage_timings);
+        configure_thread_pool(&config.encoder);
+        state.control.set_grayscale(config.encoder.grayscale);
+        state.control.set_debug_overlay(config.overlay.debug);
+        if let Some(quality) = config.encoder.quality {
+            state.control.set_quality(quality);
+        }
+        if let Some(fps) = config.encoder.fps {
+            state.control.set_max_fps(fps);
+        }
+        state.transform.set_mirrored(config.capture.mirror);
+
+        if let Some(preset) = self.preset {
+            preset.apply(&state);
+            info!(preset = preset.name(), "Streaming with a preset");
+        }
+
+        //start receiving uncompressed data, the compressor is restarted along with the capture.
+        let capture_task = spawn_frame_capture(capture, state.clone());
+
+        if let Some(path) = self.record
+            && let Err(e) = state.recorder.start(path, state.clone()).await
+        {
+            error!(error = %e, "Failed to start recording");
+        }
+
+        for path in &config.files.offer {
+            match state.files.offer(path) {
+                Ok(file) => info!(id = file.id, %path, "File offered"),
+                Err(e) => error!(%path, error = %e, "Failed to offer file"),
+            }
+        }
+
+        spawn_recording_scheduler(config.recording.schedules.clone(), state.clone());
+        spawn_motion_detector(config.motion.clone(), state.clone());
+        spawn_snapshot_archive(config.snapshots.clone(), state.clone());
+        spawn_storage_guard(config.disk.clone(), config.recording.clone(), config.snapshots.clone(), state.clone());
+        spawn_webhooks(config.webhooks.clone(), state.clone());
+        spawn_audio_capture(config.audio.clone(), state.clone());
+        spawn_watchdog(config.watchdog.clone(), state.clone());
+        spawn_window_exclusion(config.exclusion.clone(), state.clone());
+        spawn_window_blackout(config.blackout.clone(), state.clone());
+        spawn_join_notifications(config.notify.clone(), state.clone());
+        spawn_stats_logger(config.stats.clone(), state.clone());
+        spawn_bandwidth_governor(config.bandwidth.clone(), state.clone());
+        spawn_bitrate_controller(config.bitrate.clone(), state.clone());
+
+        if let Some(path) = self.reload_config {
+            spawn_config_reload(path, state.clone());
+        }
+        spawn_hotkeys(config.hotkey.clone(), state.clone())?;
+        spawn_pause_placeholder(config.pause.clone(), state.clone())?;
+        spawn_virtual_camera(config.virtual_camera.clone(), state.clone())?;
+
+        let timelapse = match self.timelapse {
+            Some((path, interval)) => match Timelapse::start(path, interval, state.clone()).await {
+                Ok(timelapse) => Some(timelapse),
+                Err(e) => {
+                    error!(error = %e, "Failed to start timelapse");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut server = ShareServer {
+            state,
+            config,
+            capture_task,
+            camera_tasks,
+            timelapse,
+            port_mapping: None,
+            viewer_url: None,
+        };
+
+        if self.listen {
+            server.bind(self.upnp).await?;
+        } else {
+            info!("Components initialized, serving disabled");
+        }
+
+        Ok(server)
+    }
+}
+
+/// # Share Server
+///
+/// The capture pipeline along with the web app streaming it, for applications embedding the share:
+///
+/// ```no_run
+/// # async fn embed() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::Arc;
+///
+/// use share_screen::{captures::CaptureType, config::Config, server::ShareServer};
+///
+/// let server = ShareServer::builder(Arc::new(CaptureType::Monitor(0)))
+///     .config(Config::load(Some("share-screen.toml"))?)
+///     .start()
+///     .await?;
+///
+/// server.state().quit_requested().await;
+/// server.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ShareServer {
+    state: Arc<StreamState>,
+    config: Config,
+    capture_task: JoinHandle<()>,
+    camera_tasks: Vec<JoinHandle<()>>,
+    timelapse: Option<Timelapse>,
+    port_mapping: Option<JoinHandle<()>>,
+    viewer_url: Option<String>,
+}
+
+impl ShareServer {
+    /// A server capturing the device, with the default config.
+    ///
+    /// `SourceRegistry` creates the device named in `[capture]` of a config.
+    pub fn builder(source: Arc<dyn SourceDevice>) -> ShareServerBuilder {
+        ShareServerBuilder {
+            source,
+            encoder: None,
+            config: Config::default(),
+            replay: Duration::from_secs(DEFAULT_REPLAY_SECONDS),
+            record: None,
+            timelapse: None,
+            listen: true,
+            upnp: false,
+            preset: None,
+            reload_config: None,
+        }
+    }
+
+    /// The shared state of the stream: controls, stats, events, viewers...
+    pub fn state(&self) -> &Arc<StreamState> {
+        &self.state
+    }
+
+    /// The url viewers open, `None` when the server does not listen.
+    pub fn viewer_url(&self) -> Option<&str> {
+        self.viewer_url.as_deref()
+    }
+
+    /// # Router
+    ///
+    /// The routes of the web app, to be merged into or nested in the router of the application.
+    pub fn router(&self) -> Router {
+        router(self.state.clone(), &self.config)
+    }
+
+    /// Binds the http and https listeners of the gateway and the control channel.
+    async fn bind(&mut self, upnp: bool) -> Result<(), Box<dyn std::error::Error>> {
+        info!("Components initialized, starting web server...");
+
+        let config = &self.config;
+        let state = &self.state;
+        let host_address = local_ip_address::local_ip()?;
+
+        //the web app for sending data, served on every listener of the gateway.
+        let app = self.router();
+
+        let gateway = Arc::new(Gateway::new(config)?);
+        let addresses = config.http.addresses(SocketAddr::new(host_address, 80));
+
+        for address in &addresses {
+            bind_gateway(*address, None, gateway.clone(), app.clone(), state.clone())
+                .await
+                .map_err(|e| format!("Failed to bind {address}: {e}"))?;
+        }
+
+        //https is served on the same addresses, at the port of [tls]
+        if let Some(acceptor) = tls::acceptor(&config.tls, host_address, gateway.http2(), state) {
+            for address in &addresses {
+                let address = SocketAddr::new(address.ip(), config.tls.port);
+
+                if let Err(e) = bind_gateway(
+                    address,
+                    Some(acceptor.clone()),
+                    gateway.clone(),
+                    app.clone(),
+                    state.clone(),
+                )
+                .await
+                {
+                    state.stats.error();
+                    error!(%address, error = %e, "Failed to bind the HTTPS listener");
+                }
+            }
+        }
+
+        spawn_control_channel(config.control.clone(), host_address, gateway.clone(), state.clone());
+        #[cfg(feature = "grpc")]
+        crate::grpc::spawn_grpc(
+            config.grpc.clone(),
+            host_address,
+            config.viewers.clone(),
+            gateway.clone(),
+            state.clone(),
+        );
+        spawn_onvif(
+            config.onvif.clone(),
+            host_address,
+            addresses[0].port(),
+            config.viewers.clone(),
+            gateway.clone(),
+            state.clone(),
+        );
+        spawn_advertiser(&config.aggregate, host_address, addresses[0].port(), state.clone());
+        spawn_tunnel(config.tunnel.clone(), gateway.clone(), app.clone(), state.clone());
+        //the acceptor of the HTTPS gateway offers h2, the input channel only speaks HTTP/1.1
+        let input_tls = config
+            .remote_input
+            .enabled
+            .then(|| tls::acceptor(&config.tls, host_address, false, state))
+            .flatten();
+        spawn_remote_input(config.remote_input.clone(), host_address, input_tls, gateway, state.clone());
+
+        self.viewer_url = Some(qr::viewer_url(addresses[0], host_address));
+
+        self.port_mapping = upnp.then(|| {
+            let mut ports: Vec<(u16, &'static str)> = Vec::new();
+
+            for address in &addresses {
+                if !ports.iter().any(|(port, _)| *port == address.port()) {
+                    ports.push((address.port(), "http"));
+                }
+            }
+
+            if config.tls.enabled {
+                ports.push((config.tls.port, "https"));
+            }
+
+            spawn_port_mapping(host_address, ports, state.clone())
+        });
+
+        Ok(())
+    }
+
+    /// # Shutdown
+    ///
+    /// Tells the clients, ends every stream, releases the device and finishes the recordings,
+    /// returns the summary of the session.
+    pub async fn shutdown(self) -> SessionSummary {
+        let state = self.state;
+
+        state.emit(ServerEvent::ShuttingDown);
+        //give the event streams a moment to deliver it, then end every client stream
+        tokio::time::sleep(SHUTDOWN_GRACE).await;
+        state.shutdown();
+
+        //the router keeps forwarding the ports until the mappings are removed
+        if let Some(port_mapping) = self.port_mapping {
+            let _ = tokio::time::timeout(PORT_MAPPING_TIMEOUT, port_mapping).await;
+        }
+
+        //stops the capture and releases the device once the in-flight frames are encoded
+        let _ = self.capture_task.await;
+        state.source().release();
+        stop_camera_streams(&state.cameras, self.camera_tasks).await;
+
+        if let Some(timelapse) = self.timelapse {
+            timelapse.stop().await;
+        }
+
+        state.recorder.stop().await;
+
+        state.stats.summary()
+    }
+}
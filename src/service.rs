@@ -0,0 +1,187 @@
+use std::{
+    ffi::OsString,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use share_screen::config::DEFAULT_CONFIG_PATH;
+use tokio::sync::Notify;
+use tracing::{error, info};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+        ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+use crate::cli::{Cli, ServiceAction};
+
+/// Name the service is registered as.
+pub const SERVICE_NAME: &str = "share-screen";
+/// Log file written next to the config when the service is installed without `--log-file`.
+const SERVICE_LOG_FILE: &str = "share-screen.log";
+/// Time the control manager waits for the server to stop before it considers it hung.
+const STOP_WAIT_HINT: Duration = Duration::from_secs(10);
+
+/// The arguments of `service run`, handed from `main` to the service thread the dispatcher starts.
+static SERVICE_CLI: Mutex<Option<Cli>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// # Handle service
+///
+/// Runs the `service` subcommand.
+pub fn handle(action: ServiceAction, cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        ServiceAction::Install => install(&cli),
+        ServiceAction::Uninstall => uninstall(),
+        ServiceAction::Run => {
+            *SERVICE_CLI.lock().unwrap() = Some(cli);
+            //blocks until the service stopped
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+            Ok(())
+        }
+    }
+}
+
+/// # Install
+///
+/// Registers the service to start at boot, running `service run` with the absolute path of the config
+/// (services start in the system directory) and logging next to it.
+fn install(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let config = std::path::absolute(cli.config.as_deref().unwrap_or(DEFAULT_CONFIG_PATH))?;
+
+    if !config.exists() {
+        return Err(format!(
+            "No config at {}, the service reads the device from its [capture] section",
+            config.display()
+        )
+        .into());
+    }
+
+    let log_file = match &cli.log_file {
+        Some(log_file) => std::path::absolute(log_file)?,
+        None => config.with_file_name(SERVICE_LOG_FILE),
+    };
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Share Screen"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            config.clone().into_os_string(),
+            OsString::from("--log-file"),
+            log_file.clone().into_os_string(),
+            OsString::from("--log-level"),
+            OsString::from(&cli.log_level),
+            OsString::from("--log-format"),
+            OsString::from(if cli.log_json { "json" } else { cli.log_format.name() }),
+            OsString::from("service"),
+            OsString::from("run"),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Shares the screen or a camera over the network")?;
+
+    info!(config = %config.display(), log_file = %log_file.display(), "Service installed, it starts at boot or with `sc start {SERVICE_NAME}`");
+
+    Ok(())
+}
+
+/// Stops the service when it is running and removes it.
+fn uninstall() -> Result<(), Box<dyn std::error::Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+
+    //removed once the last handle is closed and the service stopped
+    service.delete()?;
+
+    info!("Service uninstalled");
+
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some(cli) = SERVICE_CLI.lock().unwrap().take() else {
+        return;
+    };
+
+    if let Err(e) = run_service(cli) {
+        error!(error = %e, "Service failed");
+    }
+}
+
+/// Runs the server until the control manager stops it, reporting its state along the way.
+fn run_service(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let stop = Arc::new(Notify::new());
+
+    let stop_requested = stop.clone();
+    let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            stop_requested.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    let report = |state: ServiceState, exit_code: u32| {
+        let controls_accepted = match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        };
+
+        status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::ServiceSpecific(exit_code),
+            checkpoint: 0,
+            wait_hint: STOP_WAIT_HINT,
+            process_id: None,
+        })
+    };
+
+    report(ServiceState::Running, 0)?;
+
+    //relative paths of the config (certificates, recordings, static files) are relative to it
+    if let Some(directory) = cli.config.as_deref().and_then(|config| Path::new(config).parent()) {
+        std::env::set_current_dir(directory)?;
+    }
+
+    let result = tokio::runtime::Runtime::new()?.block_on(crate::run(cli, Some(stop), None));
+
+    if let Err(e) = &result {
+        error!(error = %e, "The server stopped with an error");
+    }
+
+    report(ServiceState::StopPending, 0)?;
+    report(ServiceState::Stopped, result.is_err() as u32)?;
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+/// Folder the session summaries are persisted to.
+const SESSIONS_FOLDER: &str = "sessions";
+
+/// # Session Stats
+///
+/// Counters for the running session, shared between the pipeline and the streamed resolutions.
+pub struct SessionStats {
+    started: Instant,
+    started_at: u64,
+    viewers: AtomicUsize,
+    peak_viewers: AtomicUsize,
+    bytes_sent: AtomicU64,
+    frames_encoded: AtomicU64,
+    recordings: AtomicUsize,
+    errors: AtomicU64,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: unix_now(),
+            viewers: AtomicUsize::new(0),
+            peak_viewers: AtomicUsize::new(0),
+            bytes_sent: AtomicU64::new(0),
+            frames_encoded: AtomicU64::new(0),
+            recordings: AtomicUsize::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers a connected viewer, returns the amount of viewers now connected.
+    pub fn viewer_connected(&self) -> usize {
+        let viewers = self.viewers.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_viewers.fetch_max(viewers, Ordering::Relaxed);
+
+        viewers
+    }
+
+    /// Removes a connected viewer, returns the amount of viewers still connected.
+    pub fn viewer_disconnected(&self) -> usize {
+        self.viewers.fetch_sub(1, Ordering::Relaxed) - 1
+    }
+
+    /// Current amount of connected viewers.
+    pub fn viewers(&self) -> usize {
+        self.viewers.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes sent to every viewer so far.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Time since the session started.
+    pub fn uptime(&self) -> std::time::Duration {
+        self.started.elapsed()
+    }
+
+    pub fn frame_encoded(&self) {
+        self.frames_encoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn recording_created(&self) {
+        self.recordings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Creates the summary of the session so far.
+    pub fn summary(&self) -> SessionSummary {
+        let duration = self.started.elapsed().as_secs_f64();
+        let frames_encoded = self.frames_encoded.load(Ordering::Relaxed);
+
+        SessionSummary {
+            started_at: self.started_at,
+            duration_secs: duration,
+            peak_viewers: self.peak_viewers.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            frames_encoded,
+            average_fps: if duration > 0.0 {
+                frames_encoded as f64 / duration
+            } else {
+                0.0
+            },
+            recordings: self.recordings.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Summary of a finished session.
+#[derive(Serialize)]
+pub struct SessionSummary {
+    /// unix timestamp (seconds) of when the session started.
+    pub started_at: u64,
+    pub duration_secs: f64,
+    pub peak_viewers: usize,
+    pub bytes_sent: u64,
+    pub frames_encoded: u64,
+    pub average_fps: f64,
+    pub recordings: usize,
+    pub errors: u64,
+}
+
+impl SessionSummary {
+    /// Prints the summary to the console.
+    pub fn print(&self) {
+        println!("Session summary:");
+        println!("   - Duration: {:.0}s", self.duration_secs);
+        println!("   - Peak viewers: {}", self.peak_viewers);
+        println!("   - Data sent: {:.2} MB", self.bytes_sent as f64 / (1024.0 * 1024.0));
+        println!("   - Average fps: {:.1}", self.average_fps);
+        println!("   - Recordings created: {}", self.recordings);
+        println!("   - Errors encountered: {}", self.errors);
+    }
+
+    /// # Persist summary
+    ///
+    /// Writes the summary as json to `sessions/session-<started_at>.json`, returns the path written.
+    pub fn persist(&self) -> Result<String, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(SESSIONS_FOLDER)?;
+
+        let path = format!("{SESSIONS_FOLDER}/session-{}.json", self.started_at);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+
+        Ok(path)
+    }
+}
+
+/// Seconds since the unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
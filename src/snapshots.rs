@@ -0,0 +1,312 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    extract::{self, State},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{Local, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    admin::{AdminConfig, unauthorized},
+    api::json,
+    bytes_resolution::BytesResolution,
+    request_params::query_param,
+    state::StreamState,
+};
+
+/// Format of the directory of a day.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+/// Format of the file name of a snapshot, without the extension, colons are not allowed in windows file names.
+const TIME_FORMAT: &str = "%H-%M-%S";
+
+/// `[snapshots]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SnapshotsConfig {
+    pub enabled: bool,
+    /// seconds between two snapshots.
+    pub interval_seconds: u64,
+    /// directory the snapshots are saved to, one directory per day.
+    pub dir: String,
+    /// days of snapshots kept, older days are deleted, 0 keeps them all.
+    pub max_days: u32,
+    /// most megabytes of snapshots kept, the oldest are deleted first, 0 is unlimited.
+    pub max_mb: u64,
+}
+
+impl Default for SnapshotsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: 60,
+            dir: "snapshots".to_string(),
+            max_days: 30,
+            max_mb: 0,
+        }
+    }
+}
+
+/// A day of the archive.
+#[derive(Serialize)]
+pub struct SnapshotDay {
+    pub date: String,
+    pub snapshots: usize,
+}
+
+/// A snapshot of the archive.
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub date: String,
+    /// local time it was taken, `HH:MM:SS`.
+    pub time: String,
+    pub size: u64,
+    /// where it is fetched from.
+    pub url: String,
+}
+
+/// # Spawn Snapshot Archive
+///
+/// Spawns a task saving the latest frame as `<dir>/<YYYY-MM-DD>/<HH-MM-SS>.jpg` every `interval_seconds` (local time),
+/// then deleting the days older than `max_days` and the oldest snapshots over `max_mb`.
+///
+/// Note: `A snapshot is saved every interval even when the screen did not change, so the archive answers what was on screen at any time.`
+pub fn spawn_snapshot_archive(config: SnapshotsConfig, state: Arc<StreamState>) {
+    if !config.enabled {
+        return;
+    }
+
+    let interval = Duration::from_secs(config.interval_seconds.max(1));
+
+    info!(dir = %config.dir, interval_secs = interval.as_secs(), "Archiving snapshots");
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = state.shutting_down() => break,
+            }
+
+            let Some(jpeg) = state.latest_frame() else {
+                continue;
+            };
+
+            //paused until the disk has space again, the archive is still pruned
+            let jpeg = (!state.disk.is_low()).then_some(jpeg);
+
+            let config = config.clone();
+            let saved = tokio::task::spawn_blocking(move || {
+                if let Some(jpeg) = jpeg {
+                    save(&config, &jpeg)?;
+                }
+                prune(&config)
+            })
+            .await;
+
+            match saved {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    state.stats.error();
+                    error!(error = %e, "Failed to archive a snapshot");
+                }
+                Err(e) => warn!(error = %e, "The snapshot archive task failed"),
+            }
+        }
+    });
+}
+
+/// Saves the frame under the current local date and time.
+fn save(config: &SnapshotsConfig, jpeg: &[u8]) -> std::io::Result<()> {
+    let now = Local::now();
+    let day = Path::new(&config.dir).join(now.format(DATE_FORMAT).to_string());
+
+    std::fs::create_dir_all(&day)?;
+    std::fs::write(day.join(format!("{}.jpg", now.format(TIME_FORMAT))), jpeg)
+}
+
+/// Deletes the days past `max_days`, then the oldest snapshots until the archive fits in `max_mb`.
+fn prune(config: &SnapshotsConfig) -> std::io::Result<()> {
+    let mut days = days(&config.dir)?;
+
+    if config.max_days > 0 {
+        let oldest = Local::now().date_naive() - chrono::Days::new(config.max_days as u64 - 1);
+
+        for (date, path) in days.extract_if(.., |(date, _)| *date < oldest) {
+            info!(date = %date.format(DATE_FORMAT), "Deleting archived snapshots past [snapshots] max_days");
+            std::fs::remove_dir_all(path)?;
+        }
+    }
+
+    if config.max_mb == 0 {
+        return Ok(());
+    }
+
+    let mut files = Vec::new();
+
+    for (_, path) in &days {
+        files.extend(snapshots(path)?.into_iter().map(|(_, path, size)| (path, size)));
+    }
+
+    let max_bytes = config.max_mb * 1024 * 1024;
+    let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+
+    //oldest first, the days and their snapshots are sorted
+    for (path, size) in files {
+        if total <= max_bytes {
+            break;
+        }
+
+        std::fs::remove_file(path)?;
+        total -= size;
+    }
+
+    Ok(())
+}
+
+/// The day directories of the archive, oldest first, other entries are ignored.
+fn days(dir: &str) -> std::io::Result<Vec<(NaiveDate, PathBuf)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut days: Vec<(NaiveDate, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let date = NaiveDate::parse_from_str(entry.file_name().to_str()?, DATE_FORMAT).ok()?;
+            Some((date, entry.path()))
+        })
+        .collect();
+
+    days.sort_by_key(|(date, _)| *date);
+    Ok(days)
+}
+
+/// The snapshots of a day directory with their size, oldest first, other files are ignored.
+fn snapshots(day: &Path) -> std::io::Result<Vec<(NaiveTime, PathBuf, u64)>> {
+    let mut snapshots: Vec<(NaiveTime, PathBuf, u64)> = std::fs::read_dir(day)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let time = parse_name(entry.file_name().to_str()?)?;
+            let size = entry.metadata().ok()?.len();
+            Some((time, entry.path(), size))
+        })
+        .collect();
+
+    snapshots.sort_by_key(|(time, _, _)| *time);
+    Ok(snapshots)
+}
+
+/// The time of a snapshot file name, `None` for anything the archive did not write.
+fn parse_name(name: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(name.strip_suffix(".jpg")?, TIME_FORMAT).ok()
+}
+
+/// # Snapshot Routes
+///
+/// The routes of the snapshot archive, behind the admin token since they show the past of the screen:
+///
+/// - `GET /api/snapshots` - the days of the archive with their count of snapshots, `?date=YYYY-MM-DD` lists the snapshots of a day
+/// - `GET /api/snapshots/{date}/{time}.jpg` - a snapshot of the archive
+///
+/// Note: `Only names the archive writes are served, so a request cannot leave the [snapshots] directory.`
+pub fn snapshot_routes(config: SnapshotsConfig, admin: AdminConfig) -> Router<Arc<StreamState>> {
+    let listing = (config.clone(), admin.clone());
+
+    Router::new()
+        .route(
+            "/api/snapshots",
+            get(move |State(state): State<Arc<StreamState>>, req: Parts| async move {
+                let (config, admin) = listing;
+
+                if !admin.authorized(&state.auth, &req) {
+                    return unauthorized(&admin, &state.auth);
+                }
+
+                let date = query_param(&req, "date");
+
+                match tokio::task::spawn_blocking(move || list(&config, date.as_deref())).await {
+                    Ok(Ok(listing)) => listing,
+                    Ok(Err(e)) => BytesResolution::text(500, format!("Failed to read the snapshots: {e}")).into_response(),
+                    Err(e) => BytesResolution::text(500, e.to_string()).into_response(),
+                }
+            }),
+        )
+        .route(
+            "/api/snapshots/{date}/{file}",
+            get(
+                move |State(state): State<Arc<StreamState>>,
+                      extract::Path((date, file)): extract::Path<(String, String)>,
+                      req: Parts| async move {
+                    if !admin.authorized(&state.auth, &req) {
+                        return unauthorized(&admin, &state.auth);
+                    }
+
+                    let (Ok(date), Some(time)) = (NaiveDate::parse_from_str(&date, DATE_FORMAT), parse_name(&file)) else {
+                        return BytesResolution::text(404, "No such snapshot").into_response();
+                    };
+
+                    let path = Path::new(&config.dir)
+                        .join(date.format(DATE_FORMAT).to_string())
+                        .join(format!("{}.jpg", time.format(TIME_FORMAT)));
+
+                    match tokio::fs::read(&path).await {
+                        Ok(jpeg) => BytesResolution::new(jpeg, "image/jpeg").into_response(),
+                        Err(_) => BytesResolution::text(404, "No such snapshot").into_response(),
+                    }
+                },
+            ),
+        )
+}
+
+/// The days of the archive, or the snapshots of one of them.
+fn list(config: &SnapshotsConfig, date: Option<&str>) -> std::io::Result<Response> {
+    let days = days(&config.dir)?;
+
+    let Some(date) = date else {
+        let days = days
+            .iter()
+            .map(|(date, path)| {
+                Ok(SnapshotDay {
+                    date: date.format(DATE_FORMAT).to_string(),
+                    snapshots: snapshots(path)?.len(),
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        return Ok(json(days));
+    };
+
+    let Ok(date) = NaiveDate::parse_from_str(date, DATE_FORMAT) else {
+        return Ok(BytesResolution::text(400, "Expected ?date=YYYY-MM-DD").into_response());
+    };
+
+    let Some((_, path)) = days.iter().find(|(day, _)| *day == date) else {
+        return Ok(json(Vec::<Snapshot>::new()));
+    };
+
+    let date = date.format(DATE_FORMAT).to_string();
+    let listed: Vec<Snapshot> = snapshots(path)?
+        .into_iter()
+        .map(|(time, _, size)| Snapshot {
+            date: date.clone(),
+            time: time.format("%H:%M:%S").to_string(),
+            size,
+            url: format!("/api/snapshots/{date}/{}.jpg", time.format(TIME_FORMAT)),
+        })
+        .collect();
+
+    Ok(json(listed))
+}
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use crate::{
+    captures::{CaptureConfig, CaptureType},
+    error::{Result, ShareScreenError},
+    hdr::PixelFormat,
+    relay::RelaySource,
+};
+
+/// # Capture Source
+///
+/// An active capture producing raw BGRA frames (or HDR frames, see `pixel_format`), opened from a `SourceDevice`.
+///
+/// The pipeline runs `run` and reads `next_frame` on separate tasks, the frames are encoded as long as both are going.
+pub trait CaptureSource: Send + Sync {
+    /// Width and height of the frames currently produced, queried again when a frame does not match them.
+    fn dimensions(&self) -> Result<(u32, u32)>;
+
+    /// Waits for the next BGRA frame, `None` once the source stopped producing frames.
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>>;
+
+    /// Captures until the source stops, an error is reported as a capture error of the pipeline.
+    fn run(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Whether `next_frame` yields frames already encoded for the stream (a relay) instead of raw BGRA,
+    /// they are broadcast as they are without the encoder.
+    fn pre_encoded(&self) -> bool {
+        false
+    }
+
+    /// Bytes per row of the frames of `next_frame` when their rows are padded past `width * 4`,
+    /// detected from the length of the frames when it is not known. The rows are packed before the frames go through the pipeline.
+    fn stride(&self) -> Option<usize> {
+        None
+    }
+
+    /// Layout of the frames of `next_frame`, the frames of HDR monitors are tone mapped to BGRA before the encoder.
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgra8
+    }
+}
+
+/// # Source Device
+///
+/// Something that can be captured (a monitor, a camera...), opened for every (re)start of the pipeline.
+///
+/// Displayed as it is shown in the status and the `source` of the events (`monitor 1`, `camera 1`).
+pub trait SourceDevice: std::fmt::Display + Send + Sync {
+    /// # Open
+    ///
+    /// Activates the device, the previous capture of it was dropped before.
+    fn open(&self) -> Result<Arc<dyn CaptureSource>>;
+
+    /// Undoes what `open` set up on the thread, called once the capture was dropped and the server shuts down.
+    fn release(&self) {}
+
+    /// Whether the device is opened again on its own after its capture stopped,
+    /// the others wait for a restart (watchdog or `/api/restart-capture`).
+    fn can_reacquire(&self) -> bool {
+        false
+    }
+
+    /// Index of the monitor the device shows, remote input maps the positions of the viewers onto it.
+    fn monitor(&self) -> Option<i32> {
+        None
+    }
+}
+
+/// Creates the device of a `[capture]` section.
+pub type SourceConstructor = fn(&CaptureConfig) -> Result<Arc<dyn SourceDevice>>;
+
+/// # Source Registry
+///
+/// The sources `[capture] source` can name, `camera`, `monitor`, `test_pattern`, `relay` and `file` (windows only) are built in.
+/// Applications embedding the server register their own with `register`.
+pub struct SourceRegistry {
+    sources: Vec<(&'static str, SourceConstructor)>,
+}
+
+impl SourceRegistry {
+    /// A registry without any source.
+    pub fn empty() -> Self {
+        Self { sources: Vec::new() }
+    }
+
+    /// Adds a source, replacing the one registered under the same name.
+    pub fn register(&mut self, name: &'static str, constructor: SourceConstructor) -> &mut Self {
+        self.sources.retain(|(registered, _)| *registered != name);
+        self.sources.push((name, constructor));
+        self
+    }
+
+    /// Names of the registered sources.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.sources.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// # Create
+    ///
+    /// The device of the `[capture]` section, `None` when no source is set and the device has to be asked for.
+    pub fn create(&self, config: &CaptureConfig) -> Result<Option<Arc<dyn SourceDevice>>> {
+        let Some(name) = config.source.as_deref() else {
+            return Ok(None);
+        };
+
+        let (_, constructor) = self
+            .sources
+            .iter()
+            .find(|(registered, _)| registered.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                ShareScreenError::Server(format!(
+                    "Unknown capture source {name}, expected one of: {}",
+                    self.names().join(", ")
+                ))
+            })?;
+
+        constructor(config).map(Some)
+    }
+}
+
+impl Default for SourceRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+
+        registry
+            .register("camera", |config| {
+                Ok(Arc::new(CaptureType::Camera(config.camera.max(1) - 1)))
+            })
+            .register("monitor", |config| Ok(config.monitor.device()))
+            .register("test_pattern", |config| {
+                Ok(Arc::new(CaptureType::TestPattern {
+                    width: config.width,
+                    height: config.height,
+                    fps: config.fps,
+                }))
+            })
+            .register("relay", |config| {
+                let url = config
+                    .relay_url
+                    .clone()
+                    .ok_or_else(|| ShareScreenError::Server("Set [capture] relay_url to the instance to relay".to_string()))?;
+
+                Ok(Arc::new(RelaySource {
+                    url,
+                    token: config.relay_token.clone(),
+                    codec: config.relay_codec,
+                }))
+            });
+
+        #[cfg(windows)]
+        registry.register("file", |config| {
+            let path = config
+                .file
+                .as_deref()
+                .ok_or_else(|| ShareScreenError::Server("Set [capture] file to the video to play".to_string()))?;
+
+            Ok(Arc::new(crate::file_source::FileSource {
+                path: path.into(),
+                looped: config.looped,
+            }))
+        });
+
+        registry
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Notify, broadcast, watch};
+
+use crate::{
+    audio::AudioChunk,
+    audit::AuditLog,
+    auth::Auth,
+    autocrop::AutoCrop,
+    blackout::WindowBlackout,
+    cameras::CameraStream,
+    captures::{SerializedDimensions, SharedDimensions},
+    chat::ChatRoom,
+    codec_feeds::CodecFeeds,
+    control::{StreamControl, StreamStatus},
+    encoder::{FrameEncoder, JpegEncoder},
+    encryption::FrameCipher,
+    events::{Event, ServerEvent},
+    files::FileShelf,
+    hdr::ToneMapper,
+    overlay::TimestampOverlay,
+    packets::{frame_packet, frame_sequence},
+    pipeline_stats::PipelineStats,
+    pointer::LaserPointer,
+    presets::{Skipped, StreamPreset},
+    recorder::Recorder,
+    replay::ReplayBuffer,
+    retention::DiskGuard,
+    scaling::FrameScaler,
+    session::SessionStats,
+    source::SourceDevice,
+    tee::FrameTee,
+    thumbnails::ThumbnailCache,
+    transform::FrameTransform,
+    viewers::{ViewerClient, ViewerRegistry, ViewerSession},
+};
+
+/// A raw BGRA frame of the capture.
+pub struct RawFrame {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// microseconds on the stream clock the frame was captured at.
+    pub timestamp_us: u64,
+}
+
+/// # Stream State
+///
+/// State of the running stream shared between the capture pipeline and the routes.
+pub struct StreamState {
+    /// broadcast channel of the encoded packets.
+    pub frames: Arc<broadcast::Sender<Vec<u8>>>,
+    /// the sinks getting every encoded packet with a queue of their own (the recordings), next to the broadcast.
+    pub sinks: FrameTee,
+    /// dimensions of the captured device.
+    pub dimensions: SharedDimensions,
+    pub stats: Arc<SessionStats>,
+    /// sessions of the connected viewers.
+    pub viewers: ViewerRegistry,
+    /// access control of the stream routes.
+    pub auth: Arc<Auth>,
+    /// encrypts the packets sent to viewers, none when the stream is sent in the clear.
+    pub cipher: Option<FrameCipher>,
+    /// counters of the capture and encode stages.
+    pub pipeline: PipelineStats,
+    pub control: StreamControl,
+    /// the pointer of the viewers drawn onto the frames.
+    pub pointer: LaserPointer,
+    /// blacks out the windows of `[blackout]` in the captured frames.
+    pub blackout: WindowBlackout,
+    /// converts the frames of HDR monitors to SDR.
+    pub tone_mapper: ToneMapper,
+    /// rotates and flips the frames before they are encoded.
+    pub transform: FrameTransform,
+    /// crops the black bars of the transformed frames.
+    pub autocrop: AutoCrop,
+    /// downscales the cropped frames.
+    pub scaler: FrameScaler,
+    /// burns the date and time into the scaled frames.
+    pub overlay: TimestampOverlay,
+    /// preset of the streams opened without `?preset=`, the one of `--preset`.
+    pub preset: Option<StreamPreset>,
+    /// the cameras of `[capture] cameras` streamed next to the source.
+    pub cameras: Vec<CameraStream>,
+    /// the device being captured, switched with `set_source`.
+    source: RwLock<Arc<dyn SourceDevice>>,
+    /// encodes the raw frames of the capture.
+    pub encoder: Arc<dyn FrameEncoder>,
+    /// the frames encoded with the other codecs a stream can ask for, `?codec=qoi` or `png`.
+    pub codecs: CodecFeeds,
+    /// the most recent encoded frame.
+    pub latest_frame: RwLock<Option<Arc<Vec<u8>>>>,
+    /// the packet the most recent frame was broadcast as, sent first to the streams opened next.
+    latest_packet: RwLock<Option<Arc<Vec<u8>>>>,
+    /// the most recent raw frame that was encoded.
+    pub latest_raw: RwLock<Option<Arc<RawFrame>>>,
+    /// downscaled previews of the latest frame.
+    pub thumbnails: ThumbnailCache,
+    /// mp4 recording of the stream.
+    pub recorder: Recorder,
+    /// pauses the recordings and the snapshots while the disk is low on space, see `[disk]`.
+    pub disk: DiskGuard,
+    /// the last seconds of encoded frames.
+    pub replay: ReplayBuffer,
+    /// messages of the viewer chat.
+    pub chat: ChatRoom,
+    /// files offered to the viewers.
+    pub files: FileShelf,
+    /// records the viewers and the requests changing the stream, see `[audit]`.
+    pub audit: AuditLog,
+    /// server events for webhooks and other listeners.
+    pub events: broadcast::Sender<Event>,
+    /// mixed audio chunks, nothing is sent when audio is disabled.
+    pub audio: broadcast::Sender<Arc<AudioChunk>>,
+    /// Opus encoded audio packets.
+    pub audio_packets: broadcast::Sender<Vec<u8>>,
+    /// set once the audio pipeline is running.
+    pub audio_enabled: AtomicBool,
+    /// sequence number of the next packet sent on `frames`.
+    pub frame_sequence: AtomicU32,
+    /// stream clock timestamp of the last frame that was encoded.
+    last_frame_us: AtomicU64,
+    /// why the capture is not running and since when, none while it is.
+    capture_error: RwLock<Option<(String, Instant)>>,
+    /// start of the stream clock.
+    pub epoch: Instant,
+    /// set once the server is shutting down.
+    shutdown: watch::Sender<bool>,
+    /// notified when a shutdown is requested remotely.
+    quit: Notify,
+    /// notified when a restart of the capture is requested.
+    restart_capture: Notify,
+}
+
+impl StreamState {
+    pub fn new(
+        source: Arc<dyn SourceDevice>,
+        dimensions: SerializedDimensions,
+        frames: broadcast::Sender<Vec<u8>>,
+        replay_window: std::time::Duration,
+    ) -> Self {
+        Self {
+            frames: Arc::new(frames),
+            sinks: FrameTee::default(),
+            dimensions: Arc::new(RwLock::new(dimensions)),
+            stats: Arc::new(SessionStats::new()),
+            viewers: ViewerRegistry::new(),
+            auth: Arc::new(Auth::default()),
+            cipher: None,
+            pipeline: PipelineStats::new(),
+            control: StreamControl::new(),
+            pointer: LaserPointer::new(),
+            blackout: WindowBlackout::default(),
+            tone_mapper: ToneMapper::default(),
+            transform: FrameTransform::default(),
+            autocrop: AutoCrop::default(),
+            scaler: FrameScaler::default(),
+            overlay: TimestampOverlay::default(),
+            preset: None,
+            cameras: Vec::new(),
+            source: RwLock::new(source),
+            encoder: Arc::new(JpegEncoder::default()),
+            codecs: CodecFeeds::default(),
+            latest_frame: RwLock::new(None),
+            latest_packet: RwLock::new(None),
+            latest_raw: RwLock::new(None),
+            thumbnails: ThumbnailCache::new(),
+            recorder: Recorder::new(),
+            disk: DiskGuard::default(),
+            replay: ReplayBuffer::new(replay_window),
+            chat: ChatRoom::default(),
+            files: FileShelf::new(),
+            audit: AuditLog::default(),
+            events: broadcast::channel(64).0,
+            audio: broadcast::channel(50).0,
+            audio_packets: broadcast::channel(50).0,
+            audio_enabled: AtomicBool::new(false),
+            frame_sequence: AtomicU32::new(0),
+            last_frame_us: AtomicU64::new(0),
+            capture_error: RwLock::new(None),
+            epoch: Instant::now(),
+            shutdown: watch::channel(false).0,
+            quit: Notify::new(),
+            restart_capture: Notify::new(),
+        }
+    }
+
+    /// Protects the stream routes with the auth of the config, shared by the streams of the extra cameras.
+    pub fn with_auth(self, auth: Arc<Auth>) -> Self {
+        Self { auth, ..self }
+    }
+
+    /// Encodes the frames with the encoder instead of JPEG.
+    pub fn with_encoder(self, encoder: Arc<dyn FrameEncoder>) -> Self {
+        Self { encoder, ..self }
+    }
+
+    /// Encrypts the packets sent to viewers with the cipher.
+    pub fn with_cipher(self, cipher: Option<FrameCipher>) -> Self {
+        Self { cipher, ..self }
+    }
+
+    /// Tone maps the HDR frames with the settings of the mapper instead of the default ones.
+    pub fn with_tone_mapper(self, tone_mapper: ToneMapper) -> Self {
+        Self { tone_mapper, ..self }
+    }
+
+    /// Rotates and flips the frames with the transform.
+    pub fn with_transform(self, transform: FrameTransform) -> Self {
+        Self { transform, ..self }
+    }
+
+    /// Crops the black bars of the frames with the settings of the auto crop.
+    pub fn with_autocrop(self, autocrop: AutoCrop) -> Self {
+        Self { autocrop, ..self }
+    }
+
+    /// Downscales the frames with the scaler.
+    pub fn with_scaler(self, scaler: FrameScaler) -> Self {
+        Self { scaler, ..self }
+    }
+
+    /// Burns the date and time into the frames with the overlay.
+    pub fn with_overlay(self, overlay: TimestampOverlay) -> Self {
+        Self { overlay, ..self }
+    }
+
+    /// Records with the recorder, to write the recordings in another format.
+    pub fn with_recorder(self, recorder: Recorder) -> Self {
+        Self { recorder, ..self }
+    }
+
+    /// Serves the streams of the cameras next to the source.
+    pub fn with_cameras(self, cameras: Vec<CameraStream>) -> Self {
+        Self { cameras, ..self }
+    }
+
+    /// Paces the streams opened without `?preset=` with the preset.
+    pub fn with_preset(self, preset: Option<StreamPreset>) -> Self {
+        Self { preset, ..self }
+    }
+
+    /// Keeps the messages of the chat in the room instead of the default one.
+    pub fn with_chat(self, chat: ChatRoom) -> Self {
+        Self { chat, ..self }
+    }
+
+    /// Records the viewers and the requests changing the stream to the audit log.
+    pub fn with_audit(self, audit: AuditLog) -> Self {
+        Self { audit, ..self }
+    }
+
+    /// Encrypts the packet for a viewer when a cipher is set.
+    pub fn seal_packet(&self, packet: Vec<u8>) -> Vec<u8> {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt_packet(packet),
+            None => packet,
+        }
+    }
+
+    /// Asks the server to shut down as if it was quit from the console.
+    pub fn request_quit(&self) {
+        self.quit.notify_one();
+    }
+
+    /// Completes once a shutdown was requested with `request_quit`.
+    pub async fn quit_requested(&self) {
+        self.quit.notified().await;
+    }
+
+    /// Asks the capture task to release and reacquire the device.
+    pub fn request_capture_restart(&self) {
+        self.restart_capture.notify_one();
+    }
+
+    /// Completes once a restart of the capture was requested.
+    pub async fn capture_restart_requested(&self) {
+        self.restart_capture.notified().await;
+    }
+
+    /// Signals every task and client stream to stop.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Whether the server is shutting down, for threads outside of the runtime.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Completes once the server is shutting down.
+    pub async fn shutting_down(&self) {
+        let mut rx = self.shutdown.subscribe();
+        let _ = rx.wait_for(|shutdown| *shutdown).await;
+    }
+
+    /// Microseconds since the stream started, the clock every timestamp of the stream is on.
+    pub fn timestamp_us(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Takes the sequence number of the next packet sent on `frames`.
+    pub fn next_frame_sequence(&self) -> u32 {
+        self.frame_sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Marks that a frame captured at `timestamp_us` was encoded and broadcast.
+    pub fn frame_produced(&self, timestamp_us: u64) {
+        self.last_frame_us.store(timestamp_us, Ordering::Relaxed);
+    }
+
+    /// Time since the last frame was encoded (or since the stream started).
+    pub fn since_last_frame(&self) -> std::time::Duration {
+        let last = self.last_frame_us.load(Ordering::Relaxed);
+        std::time::Duration::from_micros(self.timestamp_us().saturating_sub(last))
+    }
+
+    /// Records why the capture stopped, none once it is capturing again.
+    pub fn set_capture_error(&self, error: Option<String>) {
+        let mut capture_error = self.capture_error.write().unwrap();
+        //the capture stays down from its first error, through the failed attempts to reacquire it
+        let since = capture_error.as_ref().map_or_else(Instant::now, |(_, since)| *since);
+
+        *capture_error = error.map(|error| (error, since));
+    }
+
+    /// Why the capture is not running, none while it is.
+    pub fn capture_error(&self) -> Option<String> {
+        self.capture_error.read().unwrap().as_ref().map(|(error, _)| error.clone())
+    }
+
+    /// Time since the capture stopped, none while it is running.
+    pub fn capture_down_for(&self) -> Option<Duration> {
+        self.capture_error.read().unwrap().as_ref().map(|(_, since)| since.elapsed())
+    }
+
+    /// Whether audio is being captured and encoded.
+    pub fn audio_enabled(&self) -> bool {
+        self.audio_enabled.load(Ordering::Relaxed)
+    }
+
+    /// Broadcasts a server event to the listeners.
+    pub fn emit(&self, event: ServerEvent) {
+        let _ = self.events.send(Event::now(event));
+    }
+
+    /// Registers a connected viewer and its session, the viewer is removed when the returned guard is dropped.
+    pub fn viewer(self: &Arc<Self>, client: ViewerClient) -> ViewerGuard {
+        let session = self.viewers.register(client);
+        self.audit.viewer_connected(&session);
+        let viewers = self.stats.viewer_connected();
+        self.emit(ServerEvent::ViewerConnected {
+            viewers,
+            ip: session.client.ip.clone(),
+            identity: session.client.identity.clone(),
+        });
+
+        ViewerGuard {
+            state: self.clone(),
+            session,
+        }
+    }
+
+    /// The most recent encoded JPEG frame, `None` until the first frame is encoded.
+    pub fn latest_frame(&self) -> Option<Arc<Vec<u8>>> {
+        self.latest_frame.read().unwrap().clone()
+    }
+
+    /// Sets the most recent encoded JPEG frame.
+    pub fn set_latest_frame(&self, jpeg: Arc<Vec<u8>>) {
+        *self.latest_frame.write().unwrap() = Some(jpeg);
+    }
+
+    /// # Send Frame
+    ///
+    /// Broadcasts a frame packet and keeps it as the latest packet.
+    ///
+    /// Note: `Set before the broadcast, a stream subscribing in between gets the frame twice and skips the copy by its sequence.`
+    pub fn send_frame(&self, packet: Vec<u8>) {
+        let latest = Arc::new(packet.clone());
+        *self.latest_packet.write().unwrap() = Some(latest.clone());
+        self.sinks.send(&latest);
+        let _ = self.frames.send(packet);
+    }
+
+    /// Sends a packet other than a frame (a dimension update) to the viewers and the sinks.
+    pub fn send_control(&self, packet: Vec<u8>) {
+        if self.sinks.is_attached() {
+            self.sinks.send(&Arc::new(packet.clone()));
+        }
+        let _ = self.frames.send(packet);
+    }
+
+    /// The packet of the most recent frame, `None` until the first frame is broadcast.
+    pub fn latest_packet(&self) -> Option<Arc<Vec<u8>>> {
+        self.latest_packet.read().unwrap().clone()
+    }
+
+    /// # Resync
+    ///
+    /// Sends the latest frame again to the stream of the viewer (the `x-viewer-id` of its `/stream`), for a client that noticed a gap
+    /// in the sequences and would otherwise show a stale frame until the capture changes. `last_sequence` is the last frame the client
+    /// got, nothing is sent when it is already the latest one.
+    ///
+    /// Note: `The packet goes to that stream only, as it was broadcast: its sequence and timestamp are kept and the sinks do not get it.`
+    pub fn resync(&self, viewer: u64, last_sequence: Option<u32>) -> Result<(), String> {
+        let latest = self.latest_packet().and_then(|packet| frame_sequence(&packet));
+
+        if latest.is_none() {
+            return Err("there is no frame to resync with yet".to_string());
+        }
+
+        if last_sequence.is_some() && last_sequence == latest {
+            return Ok(());
+        }
+
+        match self.viewers.resync(viewer) {
+            true => Ok(()),
+            false => Err(format!("no stream of viewer {viewer}")),
+        }
+    }
+
+    /// The most recent raw frame, `None` until the first frame is captured.
+    pub fn latest_raw(&self) -> Option<Arc<RawFrame>> {
+        self.latest_raw.read().unwrap().clone()
+    }
+
+    pub fn set_latest_raw(&self, frame: Arc<RawFrame>) {
+        *self.latest_raw.write().unwrap() = Some(frame);
+    }
+
+    /// # Send Still
+    ///
+    /// Broadcasts a frame that does not come from the capture (black frame, pause placeholder),
+    /// it also replaces the latest frame so the snapshots show it instead of the last captured one.
+    pub fn send_still(&self, frame: Arc<RawFrame>, jpeg: Arc<Vec<u8>>) {
+        let sequence = self.next_frame_sequence();
+        self.send_frame(frame_packet(sequence, self.timestamp_us(), &jpeg));
+
+        self.set_latest_raw(frame);
+        self.set_latest_frame(jpeg);
+    }
+
+    /// Current dimensions of the captured device.
+    pub fn dimensions(&self) -> SerializedDimensions {
+        *self.dimensions.read().unwrap()
+    }
+
+    /// Current capture source.
+    pub fn source(&self) -> Arc<dyn SourceDevice> {
+        self.source.read().unwrap().clone()
+    }
+
+    /// Switches the capture to another device, the pipeline restarts on it.
+    pub fn set_source(&self, source: Arc<dyn SourceDevice>) {
+        tracing::info!(%source, "Switching the capture source");
+        *self.source.write().unwrap() = source;
+        self.request_capture_restart();
+    }
+
+    /// Status of the stream for the rest api.
+    pub fn status(&self) -> StreamStatus {
+        StreamStatus {
+            paused: self.control.is_paused(),
+            blanked: self.control.is_blanked(),
+            remote_input: self.control.is_remote_input_allowed(),
+            source: self.source().to_string(),
+            quality: self.control.quality(),
+            grayscale: self.control.is_grayscale(),
+            mirrored: self.transform.is_mirrored(),
+            debug_overlay: self.control.is_debug_overlay(),
+            crop: self.autocrop.current(),
+            scale: self.scaler.scale(),
+            fps: self.control.max_fps(),
+            viewers: self.stats.viewers(),
+        }
+    }
+}
+
+/// Keeps a viewer counted while alive.
+pub struct ViewerGuard {
+    state: Arc<StreamState>,
+    session: Arc<ViewerSession>,
+}
+
+impl ViewerGuard {
+    /// Counts bytes sent to the viewer, in its session and the session stats.
+    pub fn add_bytes_sent(&self, bytes: usize) {
+        self.session.add_bytes_sent(bytes);
+        self.state.stats.add_bytes_sent(bytes);
+    }
+
+    /// Counts frames the connection of the viewer accepted.
+    pub fn add_frames_sent(&self, frames: u64) {
+        self.session.add_frames_sent(frames);
+    }
+
+    /// Counts packets the viewer missed by falling behind the broadcast.
+    pub fn add_frames_lagged(&self, frames: u64) {
+        self.session.add_frames_lagged(frames);
+    }
+
+    /// Counts a frame the pacing or the container of the viewer left out.
+    pub fn add_skipped(&self, skipped: Skipped) {
+        self.session.add_skipped(skipped);
+    }
+
+    /// Completes once the viewer was kicked (or its ip banned).
+    pub async fn kicked(&self) {
+        self.session.kicked().await;
+    }
+
+    /// The id of the session, the viewer asks for resyncs with it.
+    pub fn id(&self) -> u64 {
+        self.session.id
+    }
+
+    /// Completes once the client asked for the latest frame again, see `StreamState::resync`.
+    pub async fn resync_requested(&self) {
+        self.session.resync_requested().await;
+    }
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        self.state.viewers.remove(self.session.id);
+        self.state.audit.viewer_disconnected(&self.session);
+        let viewers = self.state.stats.viewer_disconnected();
+        self.state.emit(ServerEvent::ViewerDisconnected { viewers });
+    }
+}
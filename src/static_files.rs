@@ -0,0 +1,251 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use axum::{
+    Router,
+    extract,
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+
+use crate::{bytes_resolution::BytesResolution, request_params::header};
+
+/// Url prefix the `[static_files]` directory is served at.
+pub const STATIC_PREFIX: &str = "/static";
+
+/// `[static_files]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct StaticConfig {
+    /// directory served at `/static/`, nothing is served when it does not exist.
+    pub dir: String,
+    /// seconds browsers may use a file before checking it again.
+    pub cache_seconds: u64,
+}
+
+impl Default for StaticConfig {
+    fn default() -> Self {
+        Self {
+            dir: "www".to_string(),
+            cache_seconds: 3600,
+        }
+    }
+}
+
+/// # Static Routes
+///
+/// The routes serving files of the `[static_files]` directory:
+///
+/// - `GET /static/{file}`, `GET /static/{dir}/{file}` - files of the directory, one level of subdirectories
+///
+/// Files are sent with their MIME type, `Cache-Control` and an `ETag`, requests with a matching `If-None-Match` get `304`.
+pub fn static_routes<S: Clone + Send + Sync + 'static>(config: StaticConfig) -> Router<S> {
+    let root = config.dir.clone();
+    let nested_root = config.dir;
+    let cache_seconds = config.cache_seconds;
+
+    Router::new()
+        .route(
+            &format!("{STATIC_PREFIX}/{{file}}"),
+            get(move |extract::Path(file): extract::Path<String>, req: Parts| async move {
+                serve_segments(&req, &root, &[file.as_str()], cache_seconds).await
+            }),
+        )
+        .route(
+            &format!("{STATIC_PREFIX}/{{dir}}/{{file}}"),
+            get(move |extract::Path((dir, file)): extract::Path<(String, String)>, req: Parts| async move {
+                serve_segments(&req, &nested_root, &[dir.as_str(), file.as_str()], cache_seconds).await
+            }),
+        )
+}
+
+async fn serve_segments(req: &Parts, root: &str, segments: &[&str], cache_seconds: u64) -> Response {
+    match resolve_path(Path::new(root), segments) {
+        Some(path) => serve_file(req, &path, cache_seconds).await,
+        None => BytesResolution::text(404, "Not found").into_response(),
+    }
+}
+
+/// The file at the segments below the root, `None` for anything that would leave it (`..`, absolute paths, links outside).
+pub fn resolve_path(root: &Path, segments: &[&str]) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+
+    for segment in segments {
+        if !valid_segment(segment) {
+            return None;
+        }
+
+        path.push(segment);
+    }
+
+    let root = root.canonicalize().ok()?;
+    let path = path.canonicalize().ok()?;
+
+    (path.starts_with(&root) && path.is_file()).then_some(path)
+}
+
+/// Whether the segment names an entry of its directory, not the directory itself, its parent or a path of its own.
+pub fn valid_segment(segment: &str) -> bool {
+    !segment.is_empty() && segment != "." && segment != ".." && !segment.contains(['/', '\\', ':'])
+}
+
+/// Reads the file, answering `304` when the client already has this version of it.
+async fn serve_file(req: &Parts, path: &Path, cache_seconds: u64) -> Response {
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return BytesResolution::text(404, "Not found").into_response(),
+    };
+
+    //changes whenever the file is rewritten
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_secs());
+    let etag = format!("\"{modified:x}-{:x}\"", metadata.len());
+
+    match tokio::fs::read(path).await {
+        Ok(content) => cached_response(
+            req,
+            content,
+            mime_type(path),
+            etag,
+            format!("public, max-age={cache_seconds}"),
+        ),
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to read a static file");
+            BytesResolution::text(500, "Failed to read the file").into_response()
+        }
+    }
+}
+
+/// # Cached Response
+///
+/// The content with its `ETag` and `Cache-Control`, or an empty `304` when the `If-None-Match` of the request has the etag.
+pub fn cached_response(
+    req: &Parts,
+    content: Vec<u8>,
+    content_type: &'static str,
+    etag: String,
+    cache_control: String,
+) -> Response {
+    let cached = header(req, "If-None-Match")
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag));
+
+    let (content, status) = if cached { (Vec::new(), 304) } else { (content, 200) };
+
+    BytesResolution::new(content, content_type)
+        .with_status(status)
+        .with_header("ETag", etag)
+        .with_header("Cache-Control", cache_control)
+        .into_response()
+}
+
+/// The MIME type of a file from its extension, `application/octet-stream` for unknown ones.
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory with `root/app.js`, `root/css/site.css` and `outside.txt` next to the root.
+    fn fixture(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("share-screen-static-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+
+        std::fs::create_dir_all(base.join("root/css")).unwrap();
+        std::fs::write(base.join("root/app.js"), "app").unwrap();
+        std::fs::write(base.join("root/css/site.css"), "site").unwrap();
+        std::fs::write(base.join("outside.txt"), "outside").unwrap();
+
+        base
+    }
+
+    #[test]
+    fn resolves_the_files_below_the_root() {
+        let base = fixture("inside");
+        let root = base.join("root");
+
+        assert_eq!(resolve_path(&root, &["app.js"]), Some(root.join("app.js").canonicalize().unwrap()));
+        assert_eq!(resolve_path(&root, &["css", "site.css"]), Some(root.join("css/site.css").canonicalize().unwrap()));
+        assert_eq!(resolve_path(&root, &["missing.js"]), None);
+        //directories are not served
+        assert_eq!(resolve_path(&root, &["css"]), None);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn refuses_the_paths_leaving_the_root() {
+        let base = fixture("outside");
+        let root = base.join("root");
+
+        assert_eq!(resolve_path(&root, &["..", "outside.txt"]), None);
+        assert_eq!(resolve_path(&root, &["css", "..", "..", "outside.txt"]), None);
+        assert_eq!(resolve_path(&root, &["../outside.txt"]), None);
+        assert_eq!(resolve_path(&root, &["css\\..\\app.js"]), None);
+        assert_eq!(resolve_path(&root, &[base.join("outside.txt").to_str().unwrap()]), None);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_the_links_leaving_the_root() {
+        let base = fixture("link");
+        let root = base.join("root");
+        std::os::unix::fs::symlink(base.join("outside.txt"), root.join("link.txt")).unwrap();
+
+        assert_eq!(resolve_path(&root, &["link.txt"]), None);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn validates_the_segments() {
+        assert!(valid_segment("app.js"));
+        assert!(valid_segment(".well-known"));
+        assert!(!valid_segment(""));
+        assert!(!valid_segment("."));
+        assert!(!valid_segment(".."));
+        assert!(!valid_segment("a/b"));
+        assert!(!valid_segment("a\\b"));
+        assert!(!valid_segment("C:"));
+    }
+
+    #[test]
+    fn types_the_files_by_their_extension() {
+        assert_eq!(mime_type(Path::new("index.HTML")), "text/html; charset=utf-8");
+        assert_eq!(mime_type(Path::new("icons/logo.svg")), "image/svg+xml");
+    }
+}
@@ -1,50 +1,294 @@
-use std::sync::Arc;
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_stream::stream;
-use async_web::web::Resolution;
-use tokio::sync::{Mutex, broadcast::Receiver};
+use axum::{
+    body::Body,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::{
+    broadcast::{Receiver, error::RecvError},
+    mpsc::{self, error::SendTimeoutError},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::Instrument;
+
+use crate::{
+    packets::{PROTOCOL_VERSION, frame_sequence, heartbeat_packet},
+    presets::StreamPacing,
+    state::StreamState,
+    viewers::ViewerClient,
+};
+
+/// Time without a packet before a heartbeat is sent.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// Time the connection of a client may take to take a single packet before the client is considered stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(15);
+/// Times in a row a client may fall behind the broadcast before it is dropped.
+const MAX_CONSECUTIVE_LAGS: u32 = 5;
+
+/// # Bandwidth Cap
+///
+/// Budget of bytes per second of a single viewer (`?max_kbps=`), the frames that would go over it are dropped whole.
+///
+/// Note: `A token bucket holding up to a second of budget, a frame is sent while the bucket is not empty and may overdraw it,
+/// so a frame larger than the budget still goes through once in a while instead of never.`
+pub struct BandwidthCap {
+    bytes_per_sec: f64,
+    //below zero after a frame larger than what was left
+    available: f64,
+    refilled: Instant,
+}
+
+impl BandwidthCap {
+    /// A cap of `kbps` kilobits per second, `None` for 0.
+    pub fn from_kbps(kbps: u32) -> Option<Self> {
+        let bytes_per_sec = kbps as f64 * 1000.0 / 8.0;
+
+        (kbps > 0).then(|| Self {
+            bytes_per_sec,
+            available: bytes_per_sec,
+            refilled: Instant::now(),
+        })
+    }
+
+    /// Whether there is budget left for a frame, it is taken out of the budget by `spend` once sent.
+    pub fn allows(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+
+        self.available = (self.available + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        self.refilled = now;
+
+        self.available > 0.0
+    }
+
+    /// Takes bytes sent out of the budget.
+    pub fn spend(&mut self, len: usize) {
+        self.available -= len as f64;
+    }
+}
 
 /// # Streamed Resolution
 ///
 /// Represents a streamed broadcast from a subscriber of the broadcast channel.
+///
+/// Heartbeats are sent while the broadcast is idle (paused stream, static camera...) so connections of clients that went away surface as
+/// write errors, clients that stall or cannot keep up with the broadcast are dropped to free their subscription.
+///
+/// Note: `The packets are produced on a task of their own and handed to the body through a channel of one packet, a client whose
+/// connection does not take the next one within STALL_TIMEOUT is dropped even while the write itself hangs. The bytes and the frames
+/// of a packet are counted in the session of the viewer once the connection took it, the totals go to the audit log when the session ends.`
 pub struct StreamedResolution {
     //broadcast channel
-    rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+    rx: Receiver<Vec<u8>>,
+    //viewer and bytes sent accounting
+    state: Arc<StreamState>,
+    //the session the stream is registered as
+    client: ViewerClient,
+    //frames skipped for this viewer by its preset
+    pacing: StreamPacing,
+    //the codec of the feed subscribed to, `None` for the stream
+    codec: Option<&'static str>,
 }
 
 impl StreamedResolution {
     /// create a new streamed resolution from a receiver.
-    pub fn from_receiver(rx: Receiver<Vec<u8>>) -> Self {
+    pub fn from_receiver(rx: Receiver<Vec<u8>>, state: Arc<StreamState>) -> Self {
+        Self {
+            rx,
+            state,
+            client: ViewerClient::default(),
+            pacing: StreamPacing::default(),
+            codec: None,
+        }
+    }
+
+    /// registers the stream as a session of the client.
+    pub fn for_client(self, client: ViewerClient) -> Self {
+        Self { client, ..self }
+    }
+
+    /// skips the frames of the stream following the pacing of a preset.
+    pub fn with_pacing(self, pacing: StreamPacing) -> Self {
+        Self { pacing, ..self }
+    }
+
+    /// streams the feed of a codec of `StreamState::codecs`, the receiver is one of its feed.
+    pub fn for_codec(self, codec: &'static str) -> Self {
         Self {
-            rx: Arc::new(Mutex::new(rx)),
+            codec: Some(codec),
+            ..self
         }
     }
 }
 
-impl Resolution for StreamedResolution {
-    //get content stream
-    fn get_content(&self) -> std::pin::Pin<Box<dyn futures::Stream<Item = Vec<u8>> + Send>> {
-        let rx = self.rx.clone();
+impl IntoResponse for StreamedResolution {
+    //streams the packets with the protocol version of their framing
+    fn into_response(self) -> Response {
+        let Self {
+            mut rx,
+            state,
+            client,
+            mut pacing,
+            codec,
+        } = self;
+
+        //counted as a viewer until the stream is dropped
+        let viewer = state.viewer(client);
+        let viewer_id = viewer.id();
+
+        let content = stream! {
+            //the latest frame right away, on a static desktop the next one may take seconds
+            let primed = match codec.and_then(|codec| state.codecs.get(codec)) {
+                Some(feed) => feed.primed(&state).await,
+                None => state.latest_packet(),
+            };
+            let primed = primed.filter(|packet| pacing.skip(packet, 0).is_none());
+            let primed_sequence = primed.as_deref().and_then(|packet| frame_sequence(packet));
+
+            if let Some(packet) = primed {
+                let packet = state.seal_packet(packet.to_vec());
+                let len = packet.len();
+                yield packet;
+
+                viewer.add_bytes_sent(len);
+                viewer.add_frames_sent(1);
+            }
+
+            let mut lags = 0;
 
-        Box::pin(stream! {
             loop {
+                let received = tokio::select! {
+                    received = tokio::time::timeout(HEARTBEAT_INTERVAL, rx.recv()) => Some(received),
+                    _ = viewer.resync_requested() => None,
+                    //ends the response cleanly instead of leaving the client hanging
+                    _ = state.shutting_down() => break,
+                    _ = viewer.kicked() => break,
+                };
 
-                yield match rx.lock().await.recv().await {
-                    Ok(data) => data,
-                    _ => continue
+                let data = match received {
+                    //the latest frame again as it was broadcast, to this stream only
+                    None => {
+                        let latest = match codec.and_then(|codec| state.codecs.get(codec)) {
+                            Some(feed) => feed.primed(&state).await,
+                            None => state.latest_packet(),
+                        };
+
+                        match latest {
+                            Some(packet) => packet.to_vec(),
+                            None => continue,
+                        }
+                    }
+                    Some(Ok(Ok(data))) => {
+                        lags = 0;
+
+                        //broadcast while the stream was subscribing, already sent
+                        if primed_sequence.is_some() && frame_sequence(&data) == primed_sequence {
+                            continue;
+                        }
+
+                        if let Some(skipped) = pacing.skip(&data, rx.len()) {
+                            viewer.add_skipped(skipped);
+                            continue;
+                        }
+
+                        data
+                    }
+                    Some(Ok(Err(RecvError::Lagged(missed)))) => {
+                        viewer.add_frames_lagged(missed);
+                        lags += 1;
+
+                        if lags >= MAX_CONSECUTIVE_LAGS {
+                            tracing::warn!("Dropping a viewer that cannot keep up with the stream");
+                            break;
+                        }
+
+                        continue;
+                    }
+                    Some(Ok(Err(RecvError::Closed))) => break,
+                    Some(Err(_)) => heartbeat_packet(state.timestamp_us()),
                 };
+                //heartbeats and dimension updates are bytes but not frames
+                let frames = frame_sequence(&data).is_some() as u64;
+                let data = state.seal_packet(data);
+                let len = data.len();
+                yield data;
+
+                //resumed once the connection took the packet
+                viewer.add_bytes_sent(len);
+                viewer.add_frames_sent(frames);
             }
-        })
+        };
+
+        //the stall timeout covers the write, the stream is not polled while the connection hangs on a packet
+        let (sender, body) = mpsc::channel(1);
+
+        tokio::spawn(
+            async move {
+                let mut content = std::pin::pin!(content);
+
+                while let Some(packet) = content.next().await {
+                    match sender.send_timeout(packet, STALL_TIMEOUT).await {
+                        Ok(()) => {}
+                        Err(SendTimeoutError::Timeout(_)) => {
+                            tracing::warn!("Dropping a stalled viewer");
+                            break;
+                        }
+                        //the client went away
+                        Err(SendTimeoutError::Closed(_)) => break,
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+
+        (
+            [
+                ("content-type", "application/octet-stream".to_string()),
+                ("x-protocol-version", PROTOCOL_VERSION.to_string()),
+                ("x-viewer-id", viewer_id.to_string()),
+            ],
+            Body::from_stream(ReceiverStream::new(body).map(Ok::<_, Infallible>)),
+        )
+            .into_response()
     }
+}
 
-    fn resolve(self) -> Box<dyn Resolution + Send + 'static> {
-        Box::new(self)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_cap_at_zero() {
+        assert!(BandwidthCap::from_kbps(0).is_none());
+    }
+
+    #[test]
+    fn drops_frames_over_the_budget() {
+        //1000 bytes a second
+        let mut cap = BandwidthCap::from_kbps(8).unwrap();
+
+        assert!(cap.allows());
+        cap.spend(1_500);
+        assert!(!cap.allows());
     }
 
-    //sets 200
-    fn set_headers<'a>(
-        &self,
-        _resolution: &mut tokio::sync::MutexGuard<'a, async_web::web::resolution::Resolve>,
-    ) {
+    #[test]
+    fn refills_up_to_a_second_of_budget() {
+        let mut cap = BandwidthCap::from_kbps(8).unwrap();
+        cap.spend(1_500);
+
+        cap.refilled -= Duration::from_secs(1);
+        assert!(cap.allows());
+        assert!(cap.available < 1_000.0);
+
+        cap.refilled -= Duration::from_secs(10);
+        assert!(cap.allows());
+        assert_eq!(cap.available, 1_000.0);
     }
 }
@@ -0,0 +1,119 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use serde::Serialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::debug;
+
+/// An attached sink, the sending half of its queue.
+struct Sink {
+    name: &'static str,
+    queue: mpsc::Sender<Arc<Vec<u8>>>,
+    capacity: usize,
+    dropped: Arc<AtomicU64>,
+}
+
+/// # Frame Tee
+///
+/// Fans the encoded packets of the stream out to the sinks that consume all of them (the recordings) next to the broadcast of the viewers,
+/// each sink reading its own bounded queue: a sink that falls behind (a slow disk) drops its own packets, counted in its stats, without
+/// the pipeline, the viewers or the other sinks waiting on it.
+///
+/// Note: `The queue of a sink is sized for what it can afford to catch up on, deeper than the broadcast of the viewers which only
+/// cares about the latest frames. A sink is detached when its receiver is dropped.`
+#[derive(Default)]
+pub struct FrameTee {
+    sinks: Mutex<Vec<Sink>>,
+}
+
+impl FrameTee {
+    /// # Attach
+    ///
+    /// A new sink named `name` (shown in `/stats`) getting every packet sent from now on, `capacity` packets are queued
+    /// before the next ones are dropped for it.
+    pub fn attach(&self, name: &'static str, capacity: usize) -> TeeSink {
+        let capacity = capacity.max(1);
+        let (queue, packets) = mpsc::channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        self.sinks.lock().unwrap().push(Sink {
+            name,
+            queue,
+            capacity,
+            dropped: dropped.clone(),
+        });
+
+        TeeSink { packets, dropped }
+    }
+
+    /// Queues the packet for every sink, dropped for the sinks whose queue is full and detaching the sinks that are gone.
+    pub fn send(&self, packet: &Arc<Vec<u8>>) {
+        let mut sinks = self.sinks.lock().unwrap();
+
+        sinks.retain(|sink| match sink.queue.try_send(packet.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                sink.dropped.fetch_add(1, Ordering::Relaxed);
+                debug!(sink = sink.name, "A sink of the stream is behind, a packet is dropped");
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        });
+    }
+
+    /// Whether a sink is attached, the packets are only shared with the tee while one is.
+    pub fn is_attached(&self) -> bool {
+        !self.sinks.lock().unwrap().is_empty()
+    }
+
+    /// The queues of the attached sinks.
+    pub fn stats(&self) -> Vec<SinkStats> {
+        self.sinks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|sink| !sink.queue.is_closed())
+            .map(|sink| SinkStats {
+                name: sink.name,
+                queued: sink.capacity - sink.queue.capacity(),
+                capacity: sink.capacity,
+                dropped: sink.dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// # Tee Sink
+///
+/// The receiving half of a sink of `FrameTee`, detached when dropped.
+pub struct TeeSink {
+    packets: mpsc::Receiver<Arc<Vec<u8>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl TeeSink {
+    /// The next packet, `None` once the tee is gone.
+    pub async fn recv(&mut self) -> Option<Arc<Vec<u8>>> {
+        self.packets.recv().await
+    }
+
+    /// Packets dropped for this sink so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// # Sink Stats
+///
+/// Rest API Json of a sink of the tee in `/stats`.
+#[derive(Serialize)]
+pub struct SinkStats {
+    pub name: &'static str,
+    /// packets waiting for the sink.
+    pub queued: usize,
+    pub capacity: usize,
+    /// packets dropped because the queue was full.
+    pub dropped: u64,
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use futures::{FutureExt, future::BoxFuture};
+use tokio::sync::{Mutex, mpsc};
+
+use crate::{
+    error::Result,
+    overlay::{Corner, draw_text},
+    source::CaptureSource,
+};
+
+/// Width of the test pattern when none is configured.
+pub const DEFAULT_PATTERN_WIDTH: u32 = 1280;
+/// Height of the test pattern when none is configured.
+pub const DEFAULT_PATTERN_HEIGHT: u32 = 720;
+/// Frames per second of the test pattern when none is configured.
+pub const DEFAULT_PATTERN_FPS: u32 = 30;
+
+/// The classic bars, BGRA: white, yellow, cyan, green, magenta, red, blue.
+const BARS: [[u8; 4]; 7] = [
+    [192, 192, 192, 255],
+    [0, 192, 192, 255],
+    [192, 192, 0, 255],
+    [0, 192, 0, 255],
+    [192, 0, 192, 255],
+    [0, 0, 192, 255],
+    [192, 0, 0, 255],
+];
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+/// # Test Pattern
+///
+/// A synthetic source for machines without a camera or a display: color bars scrolling one bar width every two seconds,
+/// a white line sweeping down and the wall clock (`HH:MM:SS.mmm`) burned in, so freezes and latency can be seen at a glance.
+pub struct TestPattern {
+    width: u32,
+    height: u32,
+    interval: Duration,
+    sender: mpsc::Sender<Vec<u8>>,
+    receiver: Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl TestPattern {
+    pub fn new(width: u32, height: u32, fps: u32) -> Self {
+        //a slow consumer gets the latest frames, not a backlog
+        let (sender, receiver) = mpsc::channel(2);
+
+        Self {
+            //even dimensions, the encoders of the containers need them
+            width: width.max(16) & !1,
+            height: height.max(16) & !1,
+            interval: Duration::from_secs_f64(1.0 / fps.clamp(1, 240) as f64),
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Draws the frame `elapsed` after the pattern started.
+    pub fn draw(&self, elapsed: Duration) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let bar_width = width.div_ceil(BARS.len());
+        let shift = (elapsed.as_secs_f64() / 2.0 * bar_width as f64) as usize;
+
+        let mut row = Vec::with_capacity(width * 4);
+        for x in 0..width {
+            row.extend_from_slice(&BARS[((x + shift) / bar_width) % BARS.len()]);
+        }
+
+        let mut frame = row.repeat(height);
+
+        //one sweep every four seconds
+        let sweep = ((elapsed.as_millis() % 4000) as usize * height / 4000).min(height - 1);
+        let line = (height / 180).max(2);
+        for y in sweep..(sweep + line).min(height) {
+            frame[y * width * 4..(y + 1) * width * 4]
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.copy_from_slice(&WHITE));
+        }
+
+        let clock = chrono::Local::now().format("%H:%M:%S%.3f").to_string();
+        draw_text(&mut frame, width, height, &clock, Corner::BottomLeft);
+
+        frame
+    }
+}
+
+impl CaptureSource for TestPattern {
+    fn dimensions(&self) -> Result<(u32, u32)> {
+        Ok((self.width, self.height))
+    }
+
+    fn next_frame(&self) -> BoxFuture<'_, Option<Vec<u8>>> {
+        async move { self.receiver.lock().await.recv().await }.boxed()
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let started = Instant::now();
+            let mut ticks = tokio::time::interval(self.interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+            while !self.sender.is_closed() {
+                ticks.tick().await;
+
+                //the compressor is behind, this frame is skipped
+                if let Ok(permit) = self.sender.try_reserve() {
+                    permit.send(self.draw(started.elapsed()));
+                }
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long a generated thumbnail is served before a new one is created.
+pub const THUMBNAIL_TTL: Duration = Duration::from_secs(1);
+
+/// Width used when the request does not provide one.
+pub const DEFAULT_THUMBNAIL_WIDTH: u32 = 320;
+
+/// # Thumbnail Cache
+///
+/// Caches encoded thumbnails per requested width so a wall of previews polling the host does not encode a frame per request.
+pub struct ThumbnailCache {
+    entries: Mutex<HashMap<u32, (Instant, Arc<Vec<u8>>)>>,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Gets a cached thumbnail of the width if it is younger than the ttl.
+    pub fn get(&self, width: u32) -> Option<Arc<Vec<u8>>> {
+        let entries = self.entries.lock().unwrap();
+
+        entries
+            .get(&width)
+            .filter(|(created, _)| created.elapsed() < THUMBNAIL_TTL)
+            .map(|(_, jpeg)| jpeg.clone())
+    }
+
+    /// Caches a thumbnail of the width, expired entries are removed.
+    pub fn insert(&self, width: u32, jpeg: Arc<Vec<u8>>) {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.retain(|_, (created, _)| created.elapsed() < THUMBNAIL_TTL);
+        entries.insert(width, (Instant::now(), jpeg));
+    }
+}
+
+impl Default for ThumbnailCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
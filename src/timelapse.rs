@@ -0,0 +1,112 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{io::AsyncWriteExt, sync::oneshot, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{
+    containers::{Muxer, fmp4::Fmp4Muxer},
+    error::Result,
+    events::ServerEvent,
+    state::StreamState,
+};
+
+/// Seconds between timelapse frames when none are configured.
+pub const DEFAULT_TIMELAPSE_INTERVAL: u64 = 5;
+/// Playback rate of the timelapse video.
+const TIMELAPSE_FPS: u32 = 30;
+
+/// # Timelapse
+///
+/// Grabs one encoded frame of the pipeline every interval and writes them to an mp4 played back at `TIMELAPSE_FPS`.
+pub struct Timelapse {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Timelapse {
+    /// # Start timelapse
+    ///
+    /// Creates the file at the path and spawns the task grabbing frames every interval.
+    pub async fn start(
+        path: String,
+        interval: Duration,
+        state: Arc<StreamState>,
+    ) -> Result<Self> {
+        if let Some(parent) = std::path::Path::new(&path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let file = tokio::fs::File::create(&path).await?;
+        let (stop, stopped) = oneshot::channel();
+
+        info!(%path, interval_secs = interval.as_secs_f64(), "Writing timelapse");
+
+        state.stats.recording_created();
+
+        let task = tokio::spawn(timelapse(file, path, interval, state, stopped));
+
+        Ok(Self { stop, task })
+    }
+
+    /// Stops the timelapse and waits for the file to be flushed.
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+async fn timelapse(
+    mut file: tokio::fs::File,
+    path: String,
+    interval: Duration,
+    state: Arc<StreamState>,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let dimensions = state.dimensions();
+    let mut muxer = Fmp4Muxer::new(dimensions.width as u32, dimensions.height as u32);
+
+    if let Err(e) = file.write_all(&muxer.header()).await {
+        state.stats.error();
+        error!(%path, error = %e, "Failed to write timelapse");
+        return;
+    }
+
+    let mut ticker = tokio::time::interval(interval);
+    let mut last: Option<Arc<Vec<u8>>> = None;
+    let mut written = 0u32;
+
+    loop {
+        tokio::select! {
+            _ = &mut stopped => break,
+            _ = ticker.tick() => {}
+        }
+
+        let Some(jpeg) = state.latest_frame() else {
+            continue;
+        };
+
+        //nothing new was encoded since the last tick (paused, static camera)
+        if last.as_ref().is_some_and(|l| Arc::ptr_eq(l, &jpeg)) {
+            continue;
+        }
+
+        let pts = Duration::from_secs_f64(written as f64 / TIMELAPSE_FPS as f64);
+
+        if let Err(e) = file.write_all(&muxer.mux(&jpeg, pts)).await {
+            state.stats.error();
+            error!(%path, error = %e, "Failed to write timelapse");
+            break;
+        }
+
+        written += 1;
+        last = Some(jpeg);
+    }
+
+    let _ = file.flush().await;
+
+    info!(%path, frames = written, "Timelapse saved");
+
+    state.emit(ServerEvent::RecordingFinished { path });
+}
@@ -0,0 +1,106 @@
+use std::{net::IpAddr, path::Path, sync::Arc};
+
+use serde::Deserialize;
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+use tracing::{error, info};
+
+use crate::state::StreamState;
+
+/// `[tls]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub port: u16,
+    /// PEM certificate chain.
+    pub cert: String,
+    /// PEM private key of the certificate.
+    pub key: String,
+    /// generate a self-signed certificate at `cert`/`key` when they do not exist.
+    pub self_signed: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 443,
+            cert: "tls/cert.pem".to_string(),
+            key: "tls/key.pem".to_string(),
+            self_signed: true,
+        }
+    }
+}
+
+/// # Load certificate
+///
+/// Reads the certificate and key of the config, generating a self-signed certificate for `localhost` and the ip first
+/// when they do not exist and `self_signed` is set.
+pub fn load_server_config(config: &TlsConfig, ip: IpAddr) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    if config.self_signed && !Path::new(&config.cert).exists() && !Path::new(&config.key).exists() {
+        generate_self_signed(config, ip)?;
+    }
+
+    let certs = CertificateDer::pem_file_iter(&config.cert)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(&config.key)?;
+
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(server_config)
+}
+
+/// Writes a self-signed certificate valid for `localhost` and the ip to the paths of the config.
+fn generate_self_signed(config: &TlsConfig, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string(), ip.to_string()])?;
+
+    for path in [&config.cert, &config.key] {
+        if let Some(parent) = Path::new(path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    std::fs::write(&config.cert, certified.cert.pem())?;
+    std::fs::write(&config.key, certified.key_pair.serialize_pem())?;
+
+    info!(cert = %config.cert, key = %config.key, "Generated a self-signed certificate, browsers will ask to trust it");
+
+    Ok(())
+}
+
+/// # TLS Acceptor
+///
+/// The acceptor of the HTTPS gateway, `None` when TLS is disabled or the certificate could not be loaded.
+///
+/// With `http2` the handshake offers `h2` first, browsers only speak HTTP/2 over TLS.
+pub fn acceptor(config: &TlsConfig, ip: IpAddr, http2: bool, state: &StreamState) -> Option<TlsAcceptor> {
+    if !config.enabled {
+        return None;
+    }
+
+    match load_server_config(config, ip) {
+        Ok(mut server_config) => {
+            server_config.alpn_protocols = if http2 {
+                vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            } else {
+                vec![b"http/1.1".to_vec()]
+            };
+
+            Some(TlsAcceptor::from(Arc::new(server_config)))
+        }
+        Err(e) => {
+            state.stats.error();
+            error!(error = %e, "Failed to load the TLS certificate, HTTPS is disabled");
+            None
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    sync::{
+        Arc,
+        mpsc::{Receiver, SyncSender, TrySendError},
+    },
+    time::Duration,
+};
+
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::{error::Result, state::RawFrame};
+
+/// The raw frames pushed to a transcoder and their time since the start of the recording.
+pub type FrameQueue = Receiver<(Arc<RawFrame>, Duration)>;
+
+/// A frame of an inter-frame codec as it leaves the encoder.
+pub struct EncodedPacket {
+    pub data: Vec<u8>,
+    /// the time of the frame since the start of the recording.
+    pub pts: Duration,
+    pub keyframe: bool,
+}
+
+/// # Transcoder
+///
+/// Encodes the raw frames of the capture on a thread of its own for the recordings trading cpu for bytes (AV1, VP8, VP9),
+/// see `av1::spawn_av1` and `vpx::spawn_vpx`.
+///
+/// Note: `A frame pushed while the encoder is still busy with the previous one is dropped, so a machine too slow for the fps of the capture
+/// records fewer frames rather than falling behind. Encoders looking ahead only write the last frames once more frames come or the recording stops.`
+pub struct Transcoder {
+    frames: Option<SyncSender<(Arc<RawFrame>, Duration)>>,
+    packets: mpsc::UnboundedReceiver<EncodedPacket>,
+    codec_config: Vec<u8>,
+    width: u32,
+    height: u32,
+    last_pts: Option<Duration>,
+    resized: bool,
+}
+
+impl Transcoder {
+    /// # Spawn
+    ///
+    /// Starts the thread running `encode`, it gets the frames pushed until the transcoder is finished or dropped and sends the packets
+    /// it encodes. `codec_config` is the codec private data of the track, empty for codecs without one.
+    pub fn spawn<F>(name: &str, width: u32, height: u32, codec_config: Vec<u8>, encode: F) -> Result<Self>
+    where
+        F: FnOnce(FrameQueue, mpsc::UnboundedSender<EncodedPacket>) + Send + 'static,
+    {
+        //a frame waits while the previous one is encoded, the ones after it are dropped
+        let (frames, received) = std::sync::mpsc::sync_channel(1);
+        let (encoded, packets) = mpsc::unbounded_channel();
+
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || encode(received, encoded))?;
+
+        Ok(Self {
+            frames: Some(frames),
+            packets,
+            codec_config,
+            width,
+            height,
+            last_pts: None,
+            resized: false,
+        })
+    }
+
+    /// The codec private data of the track (the `av1C` of AV1), empty when the codec has none.
+    pub fn codec_config(&self) -> &[u8] {
+        &self.codec_config
+    }
+
+    /// # Push
+    ///
+    /// Queues a frame presented at `pts`, dropped when the encoder is busy, when it is not newer than the last one pushed
+    /// or when its size is not the one of the encoder (the recording keeps the size it started with).
+    pub fn push(&mut self, frame: Arc<RawFrame>, pts: Duration) {
+        if self.last_pts.is_some_and(|last| pts <= last) {
+            return;
+        }
+
+        if (frame.width, frame.height) != (self.width, self.height) {
+            if !self.resized {
+                self.resized = true;
+                warn!(
+                    width = frame.width,
+                    height = frame.height,
+                    "The capture was resized, the recording skips the frames until it is {}x{} again",
+                    self.width,
+                    self.height
+                );
+            }
+            return;
+        }
+
+        let Some(frames) = &self.frames else {
+            return;
+        };
+
+        match frames.try_send((frame, pts)) {
+            Ok(()) => self.last_pts = Some(pts),
+            Err(TrySendError::Full(_)) => debug!("The encoder of the recording is behind, a frame is dropped"),
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// The next encoded packet, `None` once the encoder stopped.
+    pub async fn recv(&mut self) -> Option<EncodedPacket> {
+        self.packets.recv().await
+    }
+
+    /// # Finish
+    ///
+    /// Encodes the frames the encoder still holds and returns their packets, the end of the recording.
+    pub async fn finish(mut self) -> Vec<EncodedPacket> {
+        self.frames = None;
+
+        let mut packets = Vec::new();
+        while let Some(packet) = self.packets.recv().await {
+            packets.push(packet);
+        }
+
+        packets
+    }
+}
+
+/// # Pts Of
+///
+/// The pts of the frame numbered `number` among the frames sent to an encoder, forgetting the frames before it (the encoder dropped them).
+pub fn pts_of(sent: &mut std::collections::VecDeque<(u64, Duration)>, number: u64) -> Option<Duration> {
+    while sent.front().is_some_and(|(sent_number, _)| *sent_number < number) {
+        sent.pop_front();
+    }
+
+    match sent.front() {
+        Some((sent_number, pts)) if *sent_number == number => Some(*pts),
+        _ => None,
+    }
+}
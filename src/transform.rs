@@ -0,0 +1,181 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rayon::prelude::*;
+use serde::Deserialize;
+
+/// Clockwise rotation of the frames, in degrees.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "u16")]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Rotation {
+    pub fn degrees(self) -> u16 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Quarter => 90,
+            Rotation::Half => 180,
+            Rotation::ThreeQuarters => 270,
+        }
+    }
+
+    /// Whether the width and the height of the frames are swapped.
+    fn is_sideways(self) -> bool {
+        matches!(self, Rotation::Quarter | Rotation::ThreeQuarters)
+    }
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::None),
+            90 => Ok(Rotation::Quarter),
+            180 => Ok(Rotation::Half),
+            270 => Ok(Rotation::ThreeQuarters),
+            _ => Err(format!("the rotation must be 0, 90, 180 or 270 degrees, got {degrees}")),
+        }
+    }
+}
+
+/// `[transform]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TransformConfig {
+    /// clockwise, 90 and 270 for portrait monitors, 180 for cameras mounted upside down.
+    pub rotation: Rotation,
+    /// flips left and right, after the rotation (as the viewers see the frames).
+    pub flip_horizontal: bool,
+    /// flips top and bottom, after the rotation.
+    pub flip_vertical: bool,
+}
+
+/// # Frame Transform
+///
+/// Rotates and flips the raw BGRA frames before they are encoded, the dimensions of the stream are the ones of the transformed frames.
+///
+/// The mirror of the source (`[capture] mirror`) is one more horizontal flip, the only part switched at runtime (`/api/mirror`).
+pub struct FrameTransform {
+    rotation: Rotation,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+    mirrored: AtomicBool,
+}
+
+impl FrameTransform {
+    pub fn new(config: &TransformConfig) -> Self {
+        Self {
+            rotation: config.rotation,
+            flip_horizontal: config.flip_horizontal,
+            flip_vertical: config.flip_vertical,
+            mirrored: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the source is shown as in a mirror, left and right swapped.
+    pub fn is_mirrored(&self) -> bool {
+        self.mirrored.load(Ordering::Relaxed)
+    }
+
+    pub fn set_mirrored(&self, mirrored: bool) {
+        self.mirrored.store(mirrored, Ordering::Relaxed);
+    }
+
+    /// Whether the frames go through unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.rotation == Rotation::None && !self.flips_horizontally() && !self.flip_vertical
+    }
+
+    //mirroring a flipped source cancels the flip
+    fn flips_horizontally(&self) -> bool {
+        self.flip_horizontal != self.is_mirrored()
+    }
+
+    /// Dimensions of the transformed frames of a capture of these dimensions.
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.rotation.is_sideways() {
+            true => (height, width),
+            false => (width, height),
+        }
+    }
+
+    /// # Apply
+    ///
+    /// The transformed frame along with its dimensions, a frame that does not match the dimensions is returned as it is
+    /// (along with the transformed dimensions, the encoder rejects it).
+    pub fn apply(&self, frame: Vec<u8>, width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+        let source_width = width as usize;
+        let (out_width, out_height) = self.dimensions(width, height);
+        //read once, so a frame is not half mirrored when it is switched
+        let flip_horizontal = self.flips_horizontally();
+
+        if self.rotation == Rotation::None && !flip_horizontal && !self.flip_vertical || frame.len() != width as usize * height as usize * 4 {
+            return (frame, out_width, out_height);
+        }
+
+        let mut transformed = vec![0u8; frame.len()];
+
+        transformed
+            .par_chunks_exact_mut(out_width as usize * 4)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                    let (source_x, source_y) =
+                        self.source_pixel(x, y, out_width as usize, out_height as usize, flip_horizontal);
+                    let offset = (source_y * source_width + source_x) * 4;
+
+                    pixel.copy_from_slice(&frame[offset..offset + 4]);
+                }
+            });
+
+        (transformed, out_width, out_height)
+    }
+
+    /// # To Source
+    ///
+    /// A position over the transformed frame, from 0.0 (left, top) to 1.0 (right, bottom), as a position over the captured frame.
+    pub fn to_source(&self, x: f64, y: f64) -> (f64, f64) {
+        let x = if self.flips_horizontally() { 1.0 - x } else { x };
+        let y = if self.flip_vertical { 1.0 - y } else { y };
+
+        match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Quarter => (y, 1.0 - x),
+            Rotation::Half => (1.0 - x, 1.0 - y),
+            Rotation::ThreeQuarters => (1.0 - y, x),
+        }
+    }
+
+    /// The pixel of the captured frame shown at a pixel of the transformed frame.
+    fn source_pixel(
+        &self,
+        x: usize,
+        y: usize,
+        out_width: usize,
+        out_height: usize,
+        flip_horizontal: bool,
+    ) -> (usize, usize) {
+        let x = if flip_horizontal { out_width - 1 - x } else { x };
+        let y = if self.flip_vertical { out_height - 1 - y } else { y };
+
+        //the captured frame is out_height wide when sideways
+        match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Quarter => (y, out_width - 1 - x),
+            Rotation::Half => (out_width - 1 - x, out_height - 1 - y),
+            Rotation::ThreeQuarters => (out_height - 1 - y, x),
+        }
+    }
+}
+
+impl Default for FrameTransform {
+    fn default() -> Self {
+        Self::new(&TransformConfig::default())
+    }
+}
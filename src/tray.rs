@@ -0,0 +1,148 @@
+use std::{sync::Arc, time::Duration};
+
+use tracing::{error, info, warn};
+use tray_icon::{
+    Icon, TrayIcon, TrayIconBuilder,
+    menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+};
+use windows::Win32::UI::WindowsAndMessaging::{DispatchMessageW, MSG, PM_REMOVE, PeekMessageW, TranslateMessage};
+
+use crate::{events::ServerEvent, state::StreamState};
+
+/// Time between two checks of the menu events and the viewer count.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+/// Width and height of the generated icon.
+const ICON_SIZE: u32 = 32;
+
+/// # Spawn Tray
+///
+/// Shows a tray icon on its own thread with quick controls of the share: pause/resume, copy the viewer url and quit,
+/// the tooltip and the menu show the viewer count. The icon is removed once the server shuts down.
+///
+/// Note: `Only compiled with the tray feature, the icon needs a window message loop so it cannot live on a tokio thread.`
+pub fn spawn_tray(viewer_url: String, state: Arc<StreamState>) {
+    let spawned = std::thread::Builder::new().name("tray".to_string()).spawn(move || {
+        if let Err(e) = run_tray(&viewer_url, &state) {
+            state.stats.error();
+            error!(error = %e, "Failed to show the tray icon");
+        }
+    });
+
+    if let Err(e) = spawned {
+        error!(error = %e, "Failed to spawn the tray thread");
+    }
+}
+
+fn run_tray(viewer_url: &str, state: &StreamState) -> Result<(), Box<dyn std::error::Error>> {
+    let viewers = MenuItem::new(viewers_text(0), false, None);
+    let pause = MenuItem::new("Pause sharing", true, None);
+    let copy_url = MenuItem::new("Copy viewer url", true, None);
+    let quit = MenuItem::new("Quit", true, None);
+
+    let menu = Menu::new();
+    menu.append(&viewers)?;
+    menu.append(&PredefinedMenuItem::separator())?;
+    menu.append(&pause)?;
+    menu.append(&copy_url)?;
+    menu.append(&PredefinedMenuItem::separator())?;
+    menu.append(&quit)?;
+
+    let tray = TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip(tooltip(0, false))
+        .with_icon(icon(false)?)
+        .build()?;
+
+    //shown as they were last set, only updated when they change
+    let mut shown = (0, false);
+
+    while !state.is_shutting_down() {
+        pump_messages();
+
+        while let Ok(event) = MenuEvent::receiver().try_recv() {
+            if event.id == *pause.id() {
+                let paused = !state.control.is_paused();
+                state.control.set_paused(paused);
+                state.emit(if paused { ServerEvent::Paused } else { ServerEvent::Resumed });
+            } else if event.id == *copy_url.id() {
+                match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(viewer_url)) {
+                    Ok(()) => info!(url = %viewer_url, "Viewer url copied"),
+                    Err(e) => warn!(error = %e, "Failed to copy the viewer url"),
+                }
+            } else if event.id == *quit.id() {
+                state.request_quit();
+            }
+        }
+
+        let current = (state.stats.viewers(), state.control.is_paused());
+
+        if current != shown {
+            refresh(&tray, &viewers, &pause, current)?;
+            shown = current;
+        }
+
+        std::thread::sleep(REFRESH_INTERVAL);
+    }
+
+    Ok(())
+}
+
+/// Updates the icon, tooltip and menu to the viewer count and pause state.
+fn refresh(
+    tray: &TrayIcon,
+    viewers: &MenuItem,
+    pause: &MenuItem,
+    (count, paused): (usize, bool),
+) -> Result<(), Box<dyn std::error::Error>> {
+    viewers.set_text(viewers_text(count));
+    pause.set_text(if paused { "Resume sharing" } else { "Pause sharing" });
+    tray.set_tooltip(Some(tooltip(count, paused)))?;
+    tray.set_icon(Some(icon(paused)?))?;
+
+    Ok(())
+}
+
+/// Dispatches the messages of the tray window, clicks on the icon arrive as window messages.
+fn pump_messages() {
+    let mut message = MSG::default();
+
+    unsafe {
+        while PeekMessageW(&mut message, None, 0, 0, PM_REMOVE).as_bool() {
+            let _ = TranslateMessage(&message);
+            DispatchMessageW(&message);
+        }
+    }
+}
+
+fn viewers_text(count: usize) -> String {
+    match count {
+        1 => "1 viewer".to_string(),
+        count => format!("{count} viewers"),
+    }
+}
+
+fn tooltip(count: usize, paused: bool) -> String {
+    let status = if paused { "paused" } else { "sharing" };
+
+    format!("share-screen - {status}, {}", viewers_text(count))
+}
+
+/// A filled circle, red while sharing and grey while paused.
+fn icon(paused: bool) -> Result<Icon, tray_icon::BadIcon> {
+    let color = if paused { [128, 128, 128] } else { [220, 40, 40] };
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0 - 1.0;
+
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            let alpha = if distance <= radius { 255 } else { 0 };
+
+            rgba.extend_from_slice(&[color[0], color[1], color[2], alpha]);
+        }
+    }
+
+    Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)
+}
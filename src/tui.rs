@@ -0,0 +1,270 @@
+use std::{
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Row, Table},
+};
+use tracing::{error, info};
+
+use crate::{
+    captures::CaptureType, events::ServerEvent, logging::RecentLogs, pipeline_stats::PipelineRates, platform,
+    source::SourceDevice, state::StreamState,
+};
+
+/// Time between two redraws, the keys are read in between.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// Time the rates are measured over.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Quality added or taken by `+` and `-`.
+const QUALITY_STEP: u8 = 5;
+
+/// # Spawn Dashboard
+///
+/// Takes over the console with a live dashboard of the share on its own thread: the capture and encode fps, the bitrate,
+/// the encode time, the connected viewers and the recent log lines, with keys to pause (`p`), switch the source (`s`, the next
+/// monitor then the camera), change the quality (`+`/`-`) and quit (`q` or Ctrl+C).
+///
+/// The console is given back once the server shuts down, join the handle before printing to it again.
+///
+/// Note: `Only compiled with the tui feature, the logs have to go to the RecentLogs shown (see logging::init).`
+pub fn spawn_dashboard(state: Arc<StreamState>, logs: RecentLogs, viewer_url: Option<String>) -> Option<JoinHandle<()>> {
+    let spawned = std::thread::Builder::new().name("dashboard".to_string()).spawn(move || {
+        let mut terminal = ratatui::init();
+        let result = run_dashboard(&mut terminal, &state, &logs, viewer_url.as_deref());
+        ratatui::restore();
+
+        if let Err(e) = result {
+            state.stats.error();
+            error!(error = %e, "The dashboard stopped");
+        }
+    });
+
+    match spawned {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            error!(error = %e, "Failed to spawn the dashboard thread");
+            None
+        }
+    }
+}
+
+fn run_dashboard(
+    terminal: &mut DefaultTerminal,
+    state: &StreamState,
+    logs: &RecentLogs,
+    viewer_url: Option<&str>,
+) -> std::io::Result<()> {
+    let mut previous = state.pipeline.snapshot();
+    let mut previous_bytes = state.stats.bytes_sent();
+    let mut rates = PipelineRates::default();
+    let mut sent_bps = 0.0;
+
+    while !state.is_shutting_down() {
+        let now = state.pipeline.snapshot();
+
+        if now.taken.duration_since(previous.taken) >= RATE_WINDOW {
+            let bytes = state.stats.bytes_sent();
+            let secs = now.taken.duration_since(previous.taken).as_secs_f64();
+
+            rates = now.rates_since(&previous);
+            sent_bps = bytes.saturating_sub(previous_bytes) as f64 * 8.0 / secs;
+            previous = now;
+            previous_bytes = bytes;
+        }
+
+        terminal.draw(|frame| draw(frame, state, logs, viewer_url, &rates, sent_bps))?;
+
+        let deadline = Instant::now() + REFRESH_INTERVAL;
+
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    state.request_quit();
+                    return Ok(());
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    state.request_quit();
+                    return Ok(());
+                }
+                KeyCode::Char('p') | KeyCode::Char(' ') => toggle_pause(state),
+                KeyCode::Char('s') => switch_source(state),
+                KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Up => {
+                    state.control.set_quality(state.control.quality().saturating_add(QUALITY_STEP))
+                }
+                KeyCode::Char('-') | KeyCode::Down => {
+                    state.control.set_quality(state.control.quality().saturating_sub(QUALITY_STEP))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn draw(
+    frame: &mut Frame,
+    state: &StreamState,
+    logs: &RecentLogs,
+    viewer_url: Option<&str>,
+    rates: &PipelineRates,
+    sent_bps: f64,
+) {
+    let status = state.status();
+    let viewers = state.viewers.list();
+
+    let [header, stats, viewers_area, logs_area, help] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(4),
+        Constraint::Length(viewers.len().clamp(1, 8) as u16 + 3),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let state_text = match (status.paused, state.capture_error()) {
+        (true, _) => "paused".to_string(),
+        (false, Some(error)) => format!("capture error: {error}"),
+        (false, None) => "sharing".to_string(),
+    };
+    let fps_limit = match status.fps {
+        0 => "unlimited".to_string(),
+        fps => fps.to_string(),
+    };
+
+    frame.render_widget(
+        Paragraph::new(Line::from(format!(
+            "{} | {state_text} | quality {} | max fps {fps_limit} | scale {}% | {}",
+            status.source,
+            status.quality,
+            status.scale,
+            viewer_url.unwrap_or("not listening"),
+        )))
+        .block(Block::default().borders(Borders::ALL).title(" share-screen ")),
+        header,
+    );
+
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!(
+                "capture {:.1} fps | encode {:.1} fps | encode {:.2} ms | frame {:.1} kB",
+                rates.capture_fps,
+                rates.encode_fps,
+                rates.average_encode_ms,
+                rates.average_frame_bytes / 1024.0,
+            )),
+            Line::from(format!(
+                "encoded {} | sent {} to {} viewers | {} sent in total",
+                format_bitrate(rates.bitrate_bps),
+                format_bitrate(sent_bps),
+                status.viewers,
+                format_bytes(state.stats.bytes_sent()),
+            )),
+        ])
+        .block(Block::default().borders(Borders::ALL).title(" pipeline ")),
+        stats,
+    );
+
+    let rows = viewers.iter().map(|viewer| {
+        Row::new(vec![
+            viewer.id.to_string(),
+            viewer.ip.clone(),
+            viewer.container.to_string(),
+            format!("{:.0}s", viewer.connected_secs),
+            format_bytes(viewer.bytes_sent),
+            viewer.frames_dropped.to_string(),
+        ])
+    });
+
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(4),
+                Constraint::Length(40),
+                Constraint::Length(6),
+                Constraint::Length(8),
+                Constraint::Length(10),
+                Constraint::Length(8),
+            ],
+        )
+        .header(
+            Row::new(vec!["id", "ip", "format", "for", "sent", "dropped"]).style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL).title(" viewers ")),
+        viewers_area,
+    );
+
+    let lines = logs
+        .last(logs_area.height.saturating_sub(2) as usize)
+        .into_iter()
+        .map(Line::from)
+        .collect::<Vec<_>>();
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" logs ")),
+        logs_area,
+    );
+
+    frame.render_widget(
+        Paragraph::new("p pause/resume | s switch source | +/- quality | q quit"),
+        help,
+    );
+}
+
+fn toggle_pause(state: &StreamState) {
+    let paused = !state.control.is_paused();
+    state.control.set_paused(paused);
+    state.emit(if paused { ServerEvent::Paused } else { ServerEvent::Resumed });
+
+    info!(paused, "Pause toggled from the dashboard");
+}
+
+/// Switches to the next monitor, from the last one to the camera and from the camera (or any other source) to the first monitor.
+fn switch_source(state: &StreamState) {
+    let monitors = platform::monitor_count();
+
+    let next = match state.source().monitor() {
+        Some(monitor) if monitor + 1 < monitors => CaptureType::Monitor(monitor + 1),
+        Some(_) => CaptureType::Camera(0),
+        None if monitors > 0 => CaptureType::Monitor(0),
+        None => return,
+    };
+
+    state.set_source(Arc::new(next));
+}
+
+fn format_bitrate(bps: f64) -> String {
+    match bps {
+        bps if bps >= 1_000_000.0 => format!("{:.1} Mbps", bps / 1_000_000.0),
+        bps => format!("{:.0} kbps", bps / 1000.0),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        bytes if bytes >= 1 << 30 => format!("{:.2} GB", bytes as f64 / (1u64 << 30) as f64),
+        bytes if bytes >= 1 << 20 => format!("{:.1} MB", bytes as f64 / (1u64 << 20) as f64),
+        bytes => format!("{:.0} kB", bytes as f64 / 1024.0),
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::Router;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+use tokio_tungstenite::{
+    WebSocketStream, accept_hdr_async, connect_async,
+    tungstenite::{
+        Message,
+        client::IntoClientRequest,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{HeaderValue, StatusCode, header::AUTHORIZATION},
+    },
+};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+use crate::{
+    auth::constant_time_eq,
+    error::{Result, ShareScreenError},
+    gateway::{ACCEPT_RETRY, Gateway, serve},
+    request_params::query_string_param,
+    state::StreamState,
+};
+
+/// Path of the relay the host keeps its tunnel open at.
+const CONTROL_PATH: &str = "/tunnel";
+/// Path of the relay a host opens the connection of a viewer at, with `?id=`.
+const ACCEPT_PATH: &str = "/tunnel/accept";
+/// Time the host has to open the connection of a viewer before the relay closes it.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time between the pings of the relay, a host that went away without closing is noticed by the failed write.
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+/// Largest binary message a connection is copied in.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// `[tunnel]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct TunnelConfig {
+    pub enabled: bool,
+    /// websocket url of the relay started with `share-screen tunnel`, like `wss://relay.example.com:9000`.
+    pub url: Option<String>,
+    /// the `--secret` of the relay.
+    pub secret: Option<String>,
+    /// seconds between the attempts to connect to the relay.
+    pub reconnect_seconds: u64,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: None,
+            secret: None,
+            reconnect_seconds: 5,
+        }
+    }
+}
+
+/// Message of the relay to the host on the tunnel, as json.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TunnelMessage {
+    /// a viewer connected to the relay from `peer`, the host opens `ACCEPT_PATH?id=` to serve it.
+    Open { id: u64, peer: IpAddr },
+}
+
+/// # Spawn Tunnel
+///
+/// Connects the host out to the relay of `[tunnel]` and serves the viewers connecting to the relay as if they connected to the
+/// http listeners, for viewers outside the NAT without port forwarding or UPnP. Every viewer connection is a websocket of its own
+/// opened by the host, the connection to the relay is opened again every `reconnect_seconds` when it drops.
+///
+/// Note: `The viewers keep their ip for the [access] lists and the connection limits, the relay reports it. The connections are
+/// plain http between the viewers and the relay, put the relay behind a proxy with TLS (and use wss:// for the url) on the internet.`
+pub fn spawn_tunnel(config: TunnelConfig, gateway: Arc<Gateway>, router: Router, state: Arc<StreamState>) {
+    if !config.enabled {
+        return;
+    }
+
+    let (Some(url), Some(secret)) = (config.url.clone(), config.secret.clone()) else {
+        state.stats.error();
+        error!("[tunnel] needs the url and the secret of the relay, no tunnel opened");
+        return;
+    };
+
+    let Ok(url) = reqwest::Url::parse(&url) else {
+        state.stats.error();
+        error!(%url, "[tunnel] url is not a valid ws:// or wss:// url, no tunnel opened");
+        return;
+    };
+
+    let reconnect = Duration::from_secs(config.reconnect_seconds.max(1));
+    let relay = Relay { url, secret };
+
+    tokio::spawn(
+        async move {
+            loop {
+                match relay.connect(CONTROL_PATH, None).await {
+                    Ok(socket) => {
+                        info!(url = %relay.url, "Tunnel open, viewers outside the network can connect through the relay");
+
+                        if serve_tunnel(socket, &relay, &gateway, &router, &state).await {
+                            break;
+                        }
+                        warn!(url = %relay.url, "Lost the tunnel relay, reconnecting");
+                    }
+                    Err(e) => {
+                        state.stats.error();
+                        warn!(url = %relay.url, error = %e, "Failed to connect to the tunnel relay");
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(reconnect) => {}
+                    _ = state.shutting_down() => break,
+                }
+            }
+        }
+        .instrument(info_span!("tunnel")),
+    );
+}
+
+/// The relay a host tunnels to.
+#[derive(Clone)]
+struct Relay {
+    url: reqwest::Url,
+    secret: String,
+}
+
+impl Relay {
+    /// Opens a websocket at the path of the relay, authenticated with the secret.
+    async fn connect(
+        &self,
+        path: &str,
+        id: Option<u64>,
+    ) -> std::result::Result<WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>, Box<dyn std::error::Error + Send + Sync>>
+    {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}{path}", url.path().trim_end_matches('/')));
+        if let Some(id) = id {
+            url.set_query(Some(&format!("id={id}")));
+        }
+
+        let mut request = url.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", self.secret))?);
+
+        let (socket, _) = connect_async(request).await?;
+        Ok(socket)
+    }
+
+    /// The scheme the viewers reach the relay with, `https` when the relay is behind TLS (a `wss://` url).
+    fn viewer_scheme(&self) -> &'static str {
+        match self.url.scheme() {
+            "wss" | "https" => "https",
+            _ => "http",
+        }
+    }
+}
+
+/// Serves the viewers the relay announces until the tunnel drops, true when it was closed for the shutdown.
+async fn serve_tunnel<S>(socket: WebSocketStream<S>, relay: &Relay, gateway: &Arc<Gateway>, router: &Router, state: &Arc<StreamState>) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, mut incoming) = socket.split();
+
+    loop {
+        let message = tokio::select! {
+            message = incoming.next() => message,
+            _ = state.shutting_down() => {
+                let _ = sink.send(Message::Close(None)).await;
+                return true;
+            }
+        };
+
+        match message {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str::<TunnelMessage>(&text) {
+                Ok(TunnelMessage::Open { id, peer }) => {
+                    tokio::spawn(
+                        serve_viewer(id, peer, relay.clone(), gateway.clone(), router.clone(), state.clone())
+                            .instrument(info_span!("client", %peer, scheme = "tunnel")),
+                    );
+                }
+                Err(e) => debug!(error = %e, "Ignored a message of the tunnel relay"),
+            },
+            Some(Ok(Message::Close(_))) | None => return false,
+            //the pings of the relay are answered by tungstenite
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                debug!(error = %e, "Tunnel relay error");
+                return false;
+            }
+        }
+    }
+}
+
+/// Opens the connection of a viewer at the relay and serves the web app on it, like a connection accepted by `bind_gateway`.
+async fn serve_viewer(id: u64, peer: IpAddr, relay: Relay, gateway: Arc<Gateway>, router: Router, state: Arc<StreamState>) {
+    //the relay closes the connection once it is not opened in time
+    if !gateway.allows(peer) {
+        debug!("Refused a tunneled connection outside of the [access] lists");
+        return;
+    }
+
+    let Some(permit) = gateway.admit(peer) else {
+        warn!("Refused a tunneled connection, the ip has too many connections open");
+        return;
+    };
+
+    let socket = match relay.connect(ACCEPT_PATH, Some(id)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(error = %e, "Failed to open a tunneled connection");
+            return;
+        }
+    };
+
+    //held until the connection closes
+    let _permit = permit;
+    let (local, remote) = tokio::io::duplex(CHUNK_LEN);
+
+    //the links of the web app (invites...) follow the scheme of the relay, not of the pipe to it
+    tokio::join!(serve(local, peer, relay.viewer_scheme(), &gateway, router, &state), pipe(remote, socket));
+}
+
+/// # Tunnel Server Options
+///
+/// Options of `share-screen tunnel`, the relay the hosts behind a NAT connect out to.
+pub struct TunnelServerOptions {
+    /// address the viewers connect to.
+    pub listen: SocketAddr,
+    /// address the hosts open their tunnel at.
+    pub hosts: SocketAddr,
+    /// what the hosts authenticate with, their `[tunnel] secret`.
+    pub secret: String,
+}
+
+/// The tunnel of the connected host and the viewers waiting for it to open their connection.
+struct TunnelRelay {
+    secret: String,
+    /// the number of the connected host and where its messages go.
+    host: Mutex<Option<(u64, mpsc::UnboundedSender<TunnelMessage>)>>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<WebSocketStream<TcpStream>>>>,
+    next_id: AtomicU64,
+}
+
+/// Websocket a host opened at the relay.
+enum Opened {
+    Tunnel,
+    Connection(u64),
+}
+
+/// # Run Tunnel Server
+///
+/// Runs the relay until ctrl-c: a host connects its tunnel (`[tunnel]` of its config) with the secret, and every connection of a viewer
+/// is handed to the host over a websocket the host opens for it, the bytes copied as they are. A host connecting replaces the previous one.
+/// An err when the addresses cannot be bound.
+pub async fn run_tunnel_server(options: TunnelServerOptions) -> Result<()> {
+    let viewers = TcpListener::bind(options.listen)
+        .await
+        .map_err(|e| ShareScreenError::Server(format!("Failed to bind {}: {e}", options.listen)))?;
+    let hosts = TcpListener::bind(options.hosts)
+        .await
+        .map_err(|e| ShareScreenError::Server(format!("Failed to bind {}: {e}", options.hosts)))?;
+
+    let relay = Arc::new(TunnelRelay {
+        secret: options.secret,
+        host: Mutex::new(None),
+        pending: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+    });
+
+    info!("Viewers connect to http://{}, hosts to ws://{}{CONTROL_PATH}", options.listen, options.hosts);
+
+    loop {
+        tokio::select! {
+            accepted = viewers.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    tokio::spawn(relay.clone().relay_viewer(stream, peer.ip()).instrument(info_span!("viewer", %peer)));
+                }
+                Err(e) => {
+                    warn!(error = %e, "Accept failed");
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                }
+            },
+            accepted = hosts.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    tokio::spawn(relay.clone().accept_host(stream).instrument(info_span!("host", %peer)));
+                }
+                Err(e) => {
+                    warn!(error = %e, "Accept failed");
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    info!("Tunnel relay stopped");
+
+    Ok(())
+}
+
+impl TunnelRelay {
+    /// Hands the connection of a viewer to the host, closed when no host is connected or it does not open the connection in time.
+    async fn relay_viewer(self: Arc<Self>, stream: TcpStream, peer: IpAddr) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (opened, connection) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, opened);
+
+        let announced = self
+            .host
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|(_, host)| host.send(TunnelMessage::Open { id, peer }).is_ok());
+
+        let socket = match announced {
+            true => tokio::time::timeout(OPEN_TIMEOUT, connection).await,
+            false => {
+                debug!("No host is connected, the viewer is closed");
+                self.pending.lock().unwrap().remove(&id);
+                return;
+            }
+        };
+
+        self.pending.lock().unwrap().remove(&id);
+
+        match socket {
+            Ok(Ok(socket)) => {
+                let _ = stream.set_nodelay(true);
+                pipe(stream, socket).await;
+            }
+            _ => debug!("The host did not open the connection of the viewer"),
+        }
+    }
+
+    /// Performs the handshake of a websocket of a host, its tunnel or the connection of a viewer.
+    async fn accept_host(self: Arc<Self>, stream: TcpStream) {
+        let mut opened = None;
+
+        let authorize = |req: &Request, res: Response| -> std::result::Result<Response, ErrorResponse> {
+            let bearer = req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+
+            if !bearer.is_some_and(|bearer| constant_time_eq(&self.secret, bearer)) {
+                return Err(error_response(StatusCode::UNAUTHORIZED, "invalid secret"));
+            }
+
+            opened = match req.uri().path() {
+                CONTROL_PATH => Some(Opened::Tunnel),
+                ACCEPT_PATH => {
+                    let id = query_string_param(req.uri().query().unwrap_or_default(), "id").and_then(|id| id.parse().ok());
+                    Some(Opened::Connection(id.ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "missing id"))?))
+                }
+                _ => return Err(error_response(StatusCode::NOT_FOUND, "not found")),
+            };
+
+            Ok(res)
+        };
+
+        let socket = match accept_hdr_async(stream, authorize).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                debug!(error = %e, "Tunnel handshake failed");
+                return;
+            }
+        };
+
+        match opened {
+            Some(Opened::Tunnel) => self.serve_host(socket).await,
+            Some(Opened::Connection(id)) => match self.pending.lock().unwrap().remove(&id) {
+                Some(waiting) => {
+                    let _ = waiting.send(socket);
+                }
+                None => debug!(id, "The viewer of the connection is gone"),
+            },
+            None => {}
+        }
+    }
+
+    /// Announces the viewers to the host until its tunnel drops or another host replaces it.
+    async fn serve_host(&self, socket: WebSocketStream<TcpStream>) {
+        let (sender, mut announced) = mpsc::unbounded_channel();
+        let number = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        //the sender of the previous host is dropped, its loop ends
+        match self.host.lock().unwrap().replace((number, sender)) {
+            Some(_) => info!("A host connected, it replaces the previous one"),
+            None => info!("A host connected"),
+        }
+
+        let (mut sink, mut incoming) = socket.split();
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+
+        loop {
+            let message = tokio::select! {
+                message = announced.recv() => match message {
+                    Some(message) => match serde_json::to_string(&message) {
+                        Ok(json) => Message::text(json),
+                        Err(_) => continue,
+                    },
+                    //another host replaced this one
+                    None => break,
+                },
+                _ = ping.tick() => Message::Ping(Default::default()),
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            };
+
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+
+        let mut host = self.host.lock().unwrap();
+        if host.as_ref().is_some_and(|(connected, _)| *connected == number) {
+            *host = None;
+            info!("The host disconnected");
+        }
+    }
+}
+
+/// Copies the bytes of the stream into binary messages of the websocket and the messages back, until either side closes.
+async fn pipe<S, T>(stream: S, socket: WebSocketStream<T>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (mut sink, mut incoming) = socket.split();
+
+    let sending = async {
+        let mut buffer = vec![0u8; CHUNK_LEN];
+
+        loop {
+            match reader.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    if sink.send(Message::binary(buffer[..read].to_vec())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = sink.send(Message::Close(None)).await;
+    };
+
+    let receiving = async {
+        while let Some(Ok(message)) = incoming.next().await {
+            match message {
+                Message::Binary(data) => {
+                    if writer.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+
+        let _ = writer.shutdown().await;
+    };
+
+    tokio::select! {
+        _ = sending => {}
+        _ = receiving => {}
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(message.to_string()));
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,109 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use igd_next::{PortMappingProtocol, SearchOptions, aio::tokio::search_gateway};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, error, info, info_span, warn};
+
+use crate::state::StreamState;
+
+/// Seconds a mapping is leased for, renewed halfway through so a crash does not leave the ports open for long.
+const LEASE_SECONDS: u32 = 3600;
+/// Name of the mappings in the router's list.
+const DESCRIPTION: &str = "share-screen";
+
+/// # Spawn Port Mapping
+///
+/// Asks the router (UPnP IGD) to forward every port to the same port of the local ip, logs the external url of each
+/// and keeps the leases renewed. The mappings are removed once the server shuts down, await the handle to let that finish.
+///
+/// `ports` are the port and the scheme served on it (`http`, `https`).
+///
+/// Note: `UPnP only maps IPv4, routers without UPnP (or with it disabled) are logged and skipped.`
+pub fn spawn_port_mapping(local_ip: IpAddr, ports: Vec<(u16, &'static str)>, state: Arc<StreamState>) -> JoinHandle<()> {
+    tokio::spawn(
+        async move {
+            if !local_ip.is_ipv4() {
+                warn!(%local_ip, "UPnP only maps IPv4 addresses, no port mapping requested");
+                return;
+            }
+
+            let gateway = match search_gateway(SearchOptions::default()).await {
+                Ok(gateway) => gateway,
+                Err(e) => {
+                    state.stats.error();
+                    error!(error = %e, "No UPnP router found, forward the ports manually");
+                    return;
+                }
+            };
+
+            let mut mapped = Vec::new();
+
+            for (port, scheme) in ports {
+                match gateway
+                    .add_port(
+                        PortMappingProtocol::TCP,
+                        port,
+                        SocketAddr::new(local_ip, port),
+                        LEASE_SECONDS,
+                        DESCRIPTION,
+                    )
+                    .await
+                {
+                    Ok(()) => mapped.push((port, scheme)),
+                    Err(e) => {
+                        state.stats.error();
+                        error!(port, error = %e, "The router refused the port mapping");
+                    }
+                }
+            }
+
+            if mapped.is_empty() {
+                return;
+            }
+
+            match gateway.get_external_ip().await {
+                Ok(external_ip) => {
+                    for (port, scheme) in &mapped {
+                        info!("Reachable from the internet at {scheme}://{}", SocketAddr::new(external_ip, *port));
+                    }
+                }
+                Err(e) => warn!(error = %e, "Ports mapped, but the router did not tell its external ip"),
+            }
+
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(LEASE_SECONDS as u64 / 2)) => {}
+                    _ = state.shutting_down() => break,
+                }
+
+                for (port, _) in &mapped {
+                    if let Err(e) = gateway
+                        .add_port(
+                            PortMappingProtocol::TCP,
+                            *port,
+                            SocketAddr::new(local_ip, *port),
+                            LEASE_SECONDS,
+                            DESCRIPTION,
+                        )
+                        .await
+                    {
+                        warn!(port, error = %e, "Failed to renew the port mapping");
+                    }
+                }
+            }
+
+            for (port, _) in mapped {
+                if let Err(e) = gateway.remove_port(PortMappingProtocol::TCP, port).await {
+                    warn!(port, error = %e, "Failed to remove the port mapping");
+                }
+            }
+
+            info!("Port mappings removed");
+        }
+        .instrument(info_span!("upnp")),
+    )
+}
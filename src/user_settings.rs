@@ -0,0 +1,128 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{captures::CaptureType, config::Config, state::StreamState};
+
+/// Folder of the settings file, inside the config folder of the user.
+const SETTINGS_FOLDER: &str = "share-screen";
+/// File the last used settings are kept in.
+const SETTINGS_FILE: &str = "last-settings.json";
+
+/// The capture chosen at the console prompt.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum LastCapture {
+    /// the camera at an index starting from 0, the first one for the settings of older versions.
+    Camera {
+        #[serde(default)]
+        index: usize,
+    },
+    /// the monitor at an index starting from 0.
+    Monitor { index: i32 },
+    TestPattern { width: u32, height: u32, fps: u32 },
+}
+
+impl From<CaptureType> for LastCapture {
+    fn from(capture: CaptureType) -> Self {
+        match capture {
+            CaptureType::Camera(index) => LastCapture::Camera { index },
+            CaptureType::Monitor(index) => LastCapture::Monitor { index },
+            CaptureType::TestPattern { width, height, fps } => LastCapture::TestPattern { width, height, fps },
+        }
+    }
+}
+
+impl From<LastCapture> for CaptureType {
+    fn from(capture: LastCapture) -> Self {
+        match capture {
+            LastCapture::Camera { index } => CaptureType::Camera(index),
+            LastCapture::Monitor { index } => CaptureType::Monitor(index),
+            LastCapture::TestPattern { width, height, fps } => CaptureType::TestPattern { width, height, fps },
+        }
+    }
+}
+
+/// # User Settings
+///
+/// The settings of the last run, kept in a per-user file so a repeat user can press enter at the prompts instead of answering them again:
+/// the capture picked at the prompt, the quality and the fps the stream ended with, and the `[http] listen` addresses.
+///
+/// Note: `Kept in %APPDATA%\share-screen on windows and $XDG_CONFIG_HOME/share-screen (~/.config/share-screen) elsewhere,
+/// the config file and the command line always win over them.`
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct UserSettings {
+    pub capture: Option<LastCapture>,
+    pub quality: Option<u8>,
+    /// maximum fps, 0 being unlimited.
+    pub fps: Option<u32>,
+    pub listen: Vec<SocketAddr>,
+}
+
+impl UserSettings {
+    /// # Load
+    ///
+    /// The settings of the last run, the defaults when there was none or the file cannot be read.
+    pub fn load() -> Self {
+        let Some(path) = settings_path() else {
+            return Self::default();
+        };
+
+        match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "Ignoring the invalid last used settings");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Remembers the quality and the fps the stream is running with.
+    pub fn remember_stream(&mut self, state: &StreamState) {
+        self.quality = Some(state.control.quality());
+        self.fps = Some(state.control.max_fps());
+    }
+
+    /// # Fill Config
+    ///
+    /// Sets what the config leaves unset to the settings of the last run: the quality, the fps and the `[http] listen` addresses.
+    pub fn fill_config(&self, config: &mut Config) {
+        config.encoder.quality = config.encoder.quality.or(self.quality);
+        config.encoder.fps = config.encoder.fps.or(self.fps);
+
+        if config.http.listen.is_empty() && !self.listen.is_empty() {
+            tracing::info!(addresses = ?self.listen, "Listening on the addresses of the last run");
+            config.http.listen = self.listen.clone();
+        }
+    }
+
+    /// # Save
+    ///
+    /// Writes the settings to the per-user file, returns the path written.
+    pub fn save(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let path = settings_path().ok_or("No config folder for the user")?;
+
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+
+        Ok(path)
+    }
+}
+
+/// The settings file in the config folder of the user, `None` when the environment has none.
+fn settings_path() -> Option<PathBuf> {
+    let folder = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|folder| !folder.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+
+    Some(folder.join(SETTINGS_FOLDER).join(SETTINGS_FILE))
+}
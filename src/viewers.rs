@@ -0,0 +1,298 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use axum::http::request::Parts;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    auth::identity,
+    presets::Skipped,
+    request_params::{UNKNOWN_IP, client_ip, header},
+    session::unix_now,
+};
+
+/// `[viewers]` section of the config.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ViewersConfig {
+    /// most viewers streaming at once, 0 for no limit.
+    pub max: usize,
+}
+
+impl ViewersConfig {
+    /// Whether another viewer can connect while `viewers` are connected.
+    pub fn has_room(&self, viewers: usize) -> bool {
+        self.max == 0 || viewers < self.max
+    }
+}
+
+/// # Viewer Client
+///
+/// Who opened a stream, read from the request before the stream starts.
+#[derive(Clone)]
+pub struct ViewerClient {
+    pub ip: String,
+    pub user_agent: Option<String>,
+    /// container the stream is requested in (`raw`, `mp4`).
+    pub container: &'static str,
+    /// the token or login the stream was opened with, see `auth::identity`.
+    pub identity: Option<String>,
+}
+
+impl ViewerClient {
+    pub fn from_request(req: &Parts, container: &'static str) -> Self {
+        Self {
+            ip: client_ip(req),
+            user_agent: header(req, "User-Agent"),
+            container,
+            identity: identity(req),
+        }
+    }
+}
+
+impl Default for ViewerClient {
+    fn default() -> Self {
+        Self {
+            ip: UNKNOWN_IP.to_string(),
+            user_agent: None,
+            container: "raw",
+            identity: None,
+        }
+    }
+}
+
+/// # Viewer Session
+///
+/// A single subscription to `/stream`, alive for as long as the response is streamed.
+pub struct ViewerSession {
+    pub id: u64,
+    pub client: ViewerClient,
+    /// unix timestamp (seconds) of when the viewer connected.
+    pub connected_at: u64,
+    connected: Instant,
+    bytes_sent: AtomicU64,
+    frames_sent: AtomicU64,
+    frames_lagged: AtomicU64,
+    frames_skipped: AtomicU64,
+    kick: Notify,
+    resync: Notify,
+}
+
+impl ViewerSession {
+    /// Ends the stream of the viewer.
+    pub fn kick(&self) {
+        //stores a permit, the stream picks it up even when it is busy sending a packet
+        self.kick.notify_one();
+    }
+
+    /// Completes once the viewer was kicked.
+    pub async fn kicked(&self) {
+        self.kick.notified().await;
+    }
+
+    /// Asks the stream of the viewer to send the latest frame again, requests made before it sent it are covered by it.
+    pub fn request_resync(&self) {
+        self.resync.notify_one();
+    }
+
+    /// Completes once a resync of the viewer was requested.
+    pub async fn resync_requested(&self) {
+        self.resync.notified().await;
+    }
+
+    pub fn add_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Frames the connection of the viewer accepted.
+    pub fn add_frames_sent(&self, frames: u64) {
+        self.frames_sent.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// Packets of the broadcast the viewer missed by falling behind.
+    pub fn add_frames_lagged(&self, frames: u64) {
+        self.frames_lagged.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// A frame not sent to the viewer, a backlog is its connection falling behind like a lag.
+    pub fn add_skipped(&self, skipped: Skipped) {
+        match skipped {
+            Skipped::Backlog => self.add_frames_lagged(1),
+            Skipped::Throttled | Skipped::Unsupported => {
+                self.frames_skipped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Rest API Json of the session.
+    pub fn info(&self) -> ViewerInfo {
+        let frames_lagged = self.frames_lagged.load(Ordering::Relaxed);
+        let frames_skipped = self.frames_skipped.load(Ordering::Relaxed);
+        let bytes_sent = self.bytes_sent.load(Ordering::Relaxed);
+        let connected_secs = self.connected.elapsed().as_secs_f64();
+
+        ViewerInfo {
+            id: self.id,
+            ip: self.client.ip.clone(),
+            user_agent: self.client.user_agent.clone(),
+            container: self.client.container,
+            identity: self.client.identity.clone(),
+            connected_at: self.connected_at,
+            connected_secs,
+            bytes_sent,
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            average_kbps: match connected_secs > 0.0 {
+                true => bytes_sent as f64 * 8.0 / 1000.0 / connected_secs,
+                false => 0.0,
+            },
+            frames_dropped: frames_lagged + frames_skipped,
+            frames_lagged,
+            frames_skipped,
+        }
+    }
+}
+
+/// Rest API Json for a connected viewer.
+#[derive(Serialize)]
+pub struct ViewerInfo {
+    pub id: u64,
+    pub ip: String,
+    pub user_agent: Option<String>,
+    pub container: &'static str,
+    pub identity: Option<String>,
+    pub connected_at: u64,
+    pub connected_secs: f64,
+    pub bytes_sent: u64,
+    /// frames the connection of the viewer accepted.
+    pub frames_sent: u64,
+    /// kilobits per second sent to the viewer since it connected.
+    pub average_kbps: f64,
+    /// every frame the viewer did not get, `frames_lagged` and `frames_skipped`.
+    pub frames_dropped: u64,
+    /// frames missed because the connection of the viewer did not keep up with the broadcast.
+    pub frames_lagged: u64,
+    /// frames left out on purpose: over the fps of its preset or its bandwidth cap, or that its container cannot carry.
+    pub frames_skipped: u64,
+}
+
+/// # Viewer Registry
+///
+/// The sessions of the viewers currently connected, by id, and the ips banned for the rest of the session.
+pub struct ViewerRegistry {
+    next_id: AtomicU64,
+    sessions: RwLock<HashMap<u64, Arc<ViewerSession>>>,
+    banned: RwLock<HashSet<String>>,
+}
+
+impl ViewerRegistry {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            sessions: RwLock::new(HashMap::new()),
+            banned: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Adds the session of a connected client.
+    pub fn register(&self, client: ViewerClient) -> Arc<ViewerSession> {
+        let session = Arc::new(ViewerSession {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            client,
+            connected_at: unix_now(),
+            connected: Instant::now(),
+            bytes_sent: AtomicU64::new(0),
+            frames_sent: AtomicU64::new(0),
+            frames_lagged: AtomicU64::new(0),
+            frames_skipped: AtomicU64::new(0),
+            kick: Notify::new(),
+            resync: Notify::new(),
+        });
+
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session.id, session.clone());
+
+        session
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.sessions.write().unwrap().remove(&id);
+    }
+
+    /// # Kick viewer
+    ///
+    /// Ends the stream of the viewer, returns the session that was kicked or `None` when no viewer has the id.
+    pub fn kick(&self, id: u64) -> Option<Arc<ViewerSession>> {
+        let session = self.sessions.read().unwrap().get(&id).cloned()?;
+        session.kick();
+
+        Some(session)
+    }
+
+    /// Asks the stream of the viewer to send the latest frame again, `false` when no viewer has the id.
+    pub fn resync(&self, id: u64) -> bool {
+        let Some(session) = self.sessions.read().unwrap().get(&id).cloned() else {
+            return false;
+        };
+        session.request_resync();
+
+        true
+    }
+
+    /// # Ban ip
+    ///
+    /// Kicks every viewer connected from the ip and refuses new streams from it until the server restarts.
+    pub fn ban(&self, ip: &str) {
+        self.banned.write().unwrap().insert(ip.to_string());
+
+        for session in self.sessions.read().unwrap().values() {
+            if session.client.ip == ip {
+                session.kick();
+            }
+        }
+    }
+
+    /// Lifts the ban of the ip, returns whether it was banned.
+    pub fn unban(&self, ip: &str) -> bool {
+        self.banned.write().unwrap().remove(ip)
+    }
+
+    pub fn is_banned(&self, ip: &str) -> bool {
+        self.banned.read().unwrap().contains(ip)
+    }
+
+    /// The banned ips, sorted.
+    pub fn bans(&self) -> Vec<String> {
+        let mut bans: Vec<String> = self.banned.read().unwrap().iter().cloned().collect();
+        bans.sort();
+        bans
+    }
+
+    /// The connected viewers, oldest first.
+    pub fn list(&self) -> Vec<ViewerInfo> {
+        let mut viewers: Vec<ViewerInfo> = self
+            .sessions
+            .read()
+            .unwrap()
+            .values()
+            .map(|session| session.info())
+            .collect();
+
+        viewers.sort_by_key(|viewer| viewer.id);
+        viewers
+    }
+}
+
+impl Default for ViewerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::state::StreamState;
+
+/// `[virtual_camera]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct VirtualCameraConfig {
+    /// feed the captured frames into a virtual camera that conferencing apps can select.
+    pub enabled: bool,
+    /// v4l2loopback device written to (linux).
+    pub device: String,
+    /// frames written per second, the latest frame is repeated while the capture does not change.
+    pub fps: u32,
+}
+
+impl Default for VirtualCameraConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: "/dev/video10".to_string(),
+            fps: 30,
+        }
+    }
+}
+
+/// # Spawn Virtual Camera
+///
+/// Writes the latest raw frame of the capture to a v4l2loopback device on its own thread, `fps` times per second,
+/// so Zoom, Teams or OBS can select the share as a camera. The device switches format when the capture changes resolution.
+///
+/// Note: `Only linux has a virtual camera output, enabling it fails the startup elsewhere.`
+pub fn spawn_virtual_camera(config: VirtualCameraConfig, state: Arc<StreamState>) -> Result<(), String> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let interval = std::time::Duration::from_secs(1) / config.fps.clamp(1, 60);
+
+        std::thread::Builder::new()
+            .name("virtual-camera".to_string())
+            .spawn(move || v4l2::run(&config.device, interval, &state))
+            .map_err(|e| format!("Failed to spawn the virtual camera thread: {e}"))?;
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = state;
+        Err("The virtual camera output is only supported on linux (v4l2loopback)".to_string())
+    }
+}
+
+/// Converts BGRA to YUYV (BT.601, limited range), the format every v4l2 consumer reads. The width has to be even.
+fn bgra_to_yuyv(bgra: &[u8], yuyv: &mut Vec<u8>) {
+    yuyv.clear();
+
+    for pair in bgra.chunks_exact(8) {
+        let (y0, u0, v0) = yuv(pair[2], pair[1], pair[0]);
+        let (y1, u1, v1) = yuv(pair[6], pair[5], pair[4]);
+
+        yuyv.extend_from_slice(&[y0, ((u0 as u16 + u1 as u16) / 2) as u8, y1, ((v0 as u16 + v1 as u16) / 2) as u8]);
+    }
+}
+
+fn yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+    (y as u8, u.clamp(0, 255) as u8, v.clamp(0, 255) as u8)
+}
+
+#[cfg(target_os = "linux")]
+mod v4l2 {
+    use std::{
+        fs::{File, OpenOptions},
+        io::Write,
+        os::fd::AsRawFd,
+        time::{Duration, Instant},
+    };
+
+    use tracing::{error, info, warn};
+
+    use super::bgra_to_yuyv;
+    use crate::state::StreamState;
+
+    const V4L2_BUF_TYPE_VIDEO_OUTPUT: u32 = 2;
+    const V4L2_FIELD_NONE: u32 = 1;
+    const V4L2_COLORSPACE_SRGB: u32 = 8;
+    const V4L2_PIX_FMT_YUYV: u32 = u32::from_le_bytes(*b"YUYV");
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct PixFormat {
+        width: u32,
+        height: u32,
+        pixelformat: u32,
+        field: u32,
+        bytesperline: u32,
+        sizeimage: u32,
+        colorspace: u32,
+        private: u32,
+        flags: u32,
+        ycbcr_enc: u32,
+        quantization: u32,
+        xfer_func: u32,
+    }
+
+    #[repr(C)]
+    union FormatUnion {
+        pix: PixFormat,
+        //the kernel union also holds pointers, it is 8 byte aligned
+        _raw: [u64; 25],
+    }
+
+    /// `struct v4l2_format`
+    #[repr(C)]
+    struct Format {
+        kind: u32,
+        fmt: FormatUnion,
+    }
+
+    /// `_IOWR('V', 5, struct v4l2_format)`
+    const VIDIOC_S_FMT: u64 = (3 << 30) | ((size_of::<Format>() as u64) << 16) | ((b'V' as u64) << 8) | 5;
+
+    /// The device opened with the format of the frames written to it.
+    struct Output {
+        file: File,
+        width: u32,
+        height: u32,
+    }
+
+    impl Output {
+        fn open(device: &str, width: u32, height: u32) -> std::io::Result<Self> {
+            let file = OpenOptions::new().write(true).open(device)?;
+
+            let mut format = Format {
+                kind: V4L2_BUF_TYPE_VIDEO_OUTPUT,
+                fmt: FormatUnion {
+                    pix: PixFormat {
+                        width,
+                        height,
+                        pixelformat: V4L2_PIX_FMT_YUYV,
+                        field: V4L2_FIELD_NONE,
+                        bytesperline: width * 2,
+                        sizeimage: width * height * 2,
+                        colorspace: V4L2_COLORSPACE_SRGB,
+                        private: 0,
+                        flags: 0,
+                        ycbcr_enc: 0,
+                        quantization: 0,
+                        xfer_func: 0,
+                    },
+                },
+            };
+
+            if unsafe { libc::ioctl(file.as_raw_fd(), VIDIOC_S_FMT as _, &mut format) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(Self { file, width, height })
+        }
+    }
+
+    pub(super) fn run(device: &str, interval: Duration, state: &StreamState) {
+        let mut output: Option<Output> = None;
+        let mut yuyv = Vec::new();
+        let mut next = Instant::now();
+
+        while !state.is_shutting_down() {
+            next += interval;
+
+            if let Some(frame) = state.latest_raw() {
+                //yuyv pairs the pixels, an odd last column is left out
+                let width = frame.width & !1;
+
+                if output.as_ref().is_none_or(|output| (output.width, output.height) != (width, frame.height)) {
+                    output = match Output::open(device, width, frame.height) {
+                        Ok(output) => {
+                            info!(device, width, height = frame.height, "Writing the virtual camera");
+                            Some(output)
+                        }
+                        Err(e) => {
+                            state.stats.error();
+                            error!(device, error = %e, "Failed to open the virtual camera, is v4l2loopback loaded?");
+                            return;
+                        }
+                    };
+                }
+
+                if let Some(output) = &mut output {
+                    let row = frame.width as usize * 4;
+                    let bgra: Vec<u8> = frame
+                        .data
+                        .chunks_exact(row)
+                        .flat_map(|line| &line[..width as usize * 4])
+                        .copied()
+                        .collect();
+
+                    bgra_to_yuyv(&bgra, &mut yuyv);
+
+                    if let Err(e) = output.file.write_all(&yuyv) {
+                        warn!(device, error = %e, "Failed to write the virtual camera frame");
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            if next > now {
+                std::thread::sleep(next - now);
+            } else {
+                next = now;
+            }
+        }
+    }
+}
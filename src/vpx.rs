@@ -0,0 +1,210 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{debug, error};
+
+use crate::{
+    error::{Result, ShareScreenError},
+    frame_compressor::packed_frame,
+    transcoder::{EncodedPacket, FrameQueue, Transcoder, pts_of},
+};
+
+/// Frame rate the encoder is told the frames come at, the frames keep the timestamps they were captured at.
+const ASSUMED_FPS: u32 = 30;
+/// Length of the file header of an IVF stream.
+const IVF_HEADER_LEN: usize = 32;
+/// Length of the header of a frame of an IVF stream, its size and its pts.
+const IVF_FRAME_HEADER_LEN: usize = 12;
+
+/// The libvpx codecs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VpxCodec {
+    Vp8,
+    Vp9,
+}
+
+impl VpxCodec {
+    /// Name of the encoder of ffmpeg.
+    fn encoder(self) -> &'static str {
+        match self {
+            VpxCodec::Vp8 => "libvpx",
+            VpxCodec::Vp9 => "libvpx-vp9",
+        }
+    }
+
+    /// Whether the frame decodes on its own, from the first bits of its uncompressed header.
+    fn is_keyframe(self, frame: &[u8]) -> bool {
+        let Some(&first) = frame.first() else {
+            return false;
+        };
+
+        match self {
+            VpxCodec::Vp8 => first & 0x01 == 0,
+            VpxCodec::Vp9 => {
+                //frame marker, profile (low bit then high bit, a reserved bit for profile 3), show existing frame, frame type
+                if first >> 6 != 0b10 {
+                    return false;
+                }
+
+                let profile = (first >> 5 & 1) | (first >> 4 & 1) << 1;
+                let show_existing = if profile == 3 { 2 } else { 3 };
+
+                first >> show_existing & 1 == 0 && first >> (show_existing - 1) & 1 == 0
+            }
+        }
+    }
+}
+
+/// `[recording.vpx]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct VpxConfig {
+    /// ffmpeg built with libvpx, the encoder of the `vp8` and `vp9` codecs.
+    pub ffmpeg: String,
+    /// target bitrate of the video in kilobits per second.
+    pub bitrate_kbps: u32,
+    /// `-cpu-used` of libvpx in the realtime mode, from 0 (best) to 8 for vp9 and 16 for vp8 (fastest).
+    pub cpu_used: u8,
+    /// most frames between two keyframes, a player seeks to the keyframe before the time asked for.
+    pub keyframe_interval: u32,
+    /// encoding threads, 0 lets ffmpeg pick.
+    pub threads: usize,
+}
+
+impl Default for VpxConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: "ffmpeg".to_string(),
+            bitrate_kbps: 2000,
+            cpu_used: 8,
+            keyframe_interval: 300,
+            threads: 0,
+        }
+    }
+}
+
+/// # Spawn Vpx
+///
+/// A transcoder encoding the raw frames to VP8 or VP9 with the libvpx of ffmpeg, the royalty free codecs of WebM: the raw frames
+/// are written to the stdin of ffmpeg and the frames of the IVF stream it writes to its stdout are muxed by the recording.
+/// An err when ffmpeg cannot be started.
+///
+/// Note: `Encoded in the realtime mode of libvpx without alt-ref frames, every frame that goes in comes out as a single packet.`
+pub fn spawn_vpx(width: u32, height: u32, codec: VpxCodec, config: &VpxConfig) -> Result<Transcoder> {
+    let mut command = Command::new(&config.ffmpeg);
+    command
+        .args(["-hide_banner", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "bgra"])
+        .args(["-video_size", &format!("{width}x{height}"), "-framerate", &ASSUMED_FPS.to_string()])
+        .args(["-i", "pipe:0", "-c:v", codec.encoder(), "-pix_fmt", "yuv420p"])
+        .args(["-deadline", "realtime", "-cpu-used", &config.cpu_used.to_string()])
+        .args(["-lag-in-frames", "0", "-auto-alt-ref", "0"])
+        .args(["-b:v", &format!("{}k", config.bitrate_kbps.max(1)), "-g", &config.keyframe_interval.max(1).to_string()])
+        .args(["-threads", &config.threads.to_string(), "-f", "ivf", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| ShareScreenError::Encode(format!("Failed to start {} for the {codec:?} recording: {e}", config.ffmpeg)))?;
+
+    let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        let _ = child.kill();
+        return Err(ShareScreenError::Encode("ffmpeg has no pipes".to_string()));
+    };
+
+    //the pts of the frames written, by their number, until their frame comes out
+    let sent = Arc::new(Mutex::new(VecDeque::new()));
+
+    Transcoder::spawn("vpx", width, height, Vec::new(), move |frames: FrameQueue, packets| {
+        let written = sent.clone();
+
+        //ffmpeg blocks writing its stdout until it is read, the frames are read on a thread of their own
+        let reading = std::thread::Builder::new()
+            .name("vpx-reader".to_string())
+            .spawn(move || read_frames(stdout, child, codec, sent, packets));
+
+        if let Err(e) = reading {
+            error!(error = %e, "Failed to spawn the reader of ffmpeg");
+            return;
+        }
+
+        let mut frame_number = 0u64;
+        for (frame, pts) in frames {
+            let Some(raw_bgra) = packed_frame(&frame.data, width, height) else {
+                continue;
+            };
+
+            written.lock().unwrap().push_back((frame_number, pts));
+            frame_number += 1;
+
+            if let Err(e) = stdin.write_all(&raw_bgra) {
+                debug!(error = %e, "ffmpeg stopped reading the frames");
+                break;
+            }
+        }
+
+        //closing stdin makes ffmpeg write the last frames and exit
+        drop(stdin);
+    })
+}
+
+/// Reads the IVF stream of ffmpeg and sends its frames until it exits, then logs what it printed when it failed.
+fn read_frames(
+    mut stdout: std::process::ChildStdout,
+    mut child: std::process::Child,
+    codec: VpxCodec,
+    sent: Arc<Mutex<VecDeque<(u64, Duration)>>>,
+    packets: mpsc::UnboundedSender<EncodedPacket>,
+) {
+    let mut header = [0u8; IVF_HEADER_LEN];
+
+    if stdout.read_exact(&mut header).is_ok() && &header[..4] == b"DKIF" {
+        //the header may be longer than the 32 bytes of version 0
+        let header_len = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let mut skipped = vec![0u8; header_len.saturating_sub(IVF_HEADER_LEN)];
+
+        if stdout.read_exact(&mut skipped).is_ok() {
+            let mut frame_header = [0u8; IVF_FRAME_HEADER_LEN];
+
+            while stdout.read_exact(&mut frame_header).is_ok() {
+                let size = u32::from_le_bytes(frame_header[..4].try_into().unwrap()) as usize;
+                //the input frame rate is the time base, the pts is the number of the frame
+                let number = u64::from_le_bytes(frame_header[4..].try_into().unwrap());
+
+                let mut data = vec![0u8; size];
+                if stdout.read_exact(&mut data).is_err() {
+                    break;
+                }
+
+                let Some(pts) = pts_of(&mut sent.lock().unwrap(), number) else {
+                    continue;
+                };
+
+                let keyframe = codec.is_keyframe(&data);
+                if packets.send(EncodedPacket { data, pts, keyframe }).is_err() {
+                    let _ = child.kill();
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut printed = String::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        let _ = stderr.read_to_string(&mut printed);
+    }
+
+    match child.wait() {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!(%status, output = %printed.trim(), "ffmpeg failed to encode the recording"),
+        Err(e) => error!(error = %e, "Failed to wait for ffmpeg"),
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{events::ServerEvent, state::StreamState};
+
+/// How often the pipeline is checked.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(2);
+
+/// `[watchdog]` section of the config.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// off by default, a still desktop encodes no frames and cannot be told apart from a stalled capture.
+    pub enabled: bool,
+    /// seconds without an encoded frame before the pipeline is restarted.
+    ///
+    /// monitors only produce frames when the screen changes, keep this well above the time the screen may sit still.
+    pub stall_seconds: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stall_seconds: 60,
+        }
+    }
+}
+
+/// # Spawn Watchdog
+///
+/// Spawns a supervisor that restarts the capture pipeline when it stopped producing frames (stalled device, stopped camera, panicked compressor...),
+/// the incident is logged and emitted as a `pipeline_restarted` event.
+///
+/// A paused stream is never considered stalled.
+pub fn spawn_watchdog(config: WatchdogConfig, state: Arc<StreamState>) {
+    if !config.enabled {
+        return;
+    }
+
+    let stall_timeout = Duration::from_secs(config.stall_seconds.max(1));
+
+    tokio::spawn(async move {
+        //the last restart or pause, a device that takes long to come back is not restarted over and over
+        //and a resumed stream gets time to produce frames again.
+        let mut grace_from: Option<Instant> = None;
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(WATCHDOG_INTERVAL) => {}
+                _ = state.shutting_down() => break,
+            }
+
+            if state.control.is_paused() {
+                grace_from = Some(Instant::now());
+                continue;
+            }
+
+            let stalled = state.since_last_frame();
+
+            if stalled < stall_timeout {
+                grace_from = None;
+                continue;
+            }
+
+            if let Some(at) = grace_from
+                && at.elapsed() < stall_timeout
+            {
+                continue;
+            }
+
+            let reason = format!("no frames for {}s", stalled.as_secs());
+
+            state.stats.error();
+            tracing::warn!(%reason, "Watchdog restarting the capture pipeline");
+            state.emit(ServerEvent::PipelineRestarted { reason });
+
+            state.request_capture_restart();
+            grace_from = Some(Instant::now());
+        }
+    });
+}
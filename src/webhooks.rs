@@ -0,0 +1,81 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+
+use crate::state::StreamState;
+
+/// Time a webhook has to respond before the request is dropped.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// # Webhook
+///
+/// A url events are POSTed to as json.
+///
+/// ```toml
+/// [[webhooks]]
+/// url = "https://example.com/hook"
+/// events = ["viewer_connected", "motion_detected"] # empty or missing for every event
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl Webhook {
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+/// # Spawn Webhooks
+///
+/// Spawns a task that POSTs every server event to the webhooks subscribed to it.
+///
+/// Requests are sent concurrently so a slow webhook does not hold back the others.
+pub fn spawn_webhooks(webhooks: Vec<Webhook>, state: Arc<StreamState>) {
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = %e, "Failed to create webhook client");
+            return;
+        }
+    };
+
+    let mut rx = state.events.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "Webhooks missed events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            for webhook in webhooks.iter().filter(|w| w.wants(event.kind.name())) {
+                let request = client.post(&webhook.url).json(&event);
+                let url = webhook.url.clone();
+
+                tokio::spawn(async move {
+                    match request.send().await {
+                        Ok(res) if !res.status().is_success() => {
+                            warn!(%url, status = %res.status(), "Webhook responded with an error")
+                        }
+                        Err(e) => warn!(%url, error = %e, "Webhook failed"),
+                        _ => {}
+                    }
+                });
+            }
+        }
+    });
+}